@@ -1,145 +1,126 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
 use pixel_change_check_client::{
+    benchmark::SyntheticCapture,
+    capture,
     encoder::{self, FrameEncoder},
-    pcc::{PCCDetector, Frame, QualityConfig, PixelChangeDetector},
+    network::{EncodedFrame, FrameProtocol},
+    pcc::{delta, wire::FrameMessage, Frame, FrameCapture, FrameUpdate, PCCDetector, PixelChangeDetector, QualityConfig, Rect},
 };
+use std::hint::black_box;
 use tokio::runtime::Runtime;
 
 // Use a smaller resolution for faster benchmarks.
 // For real-world performance testing, increase to 1920x1080.
 const BENCH_WIDTH: u32 = 640;
 const BENCH_HEIGHT: u32 = 480;
+const FRAME_BYTES: u64 = (BENCH_WIDTH * BENCH_HEIGHT * 3) as u64;
 
-// Helper function to create test frame
-fn create_test_frame(id: u64) -> Frame {
-    Frame {
-        id,
-        timestamp: std::time::SystemTime::now(),
-        width: BENCH_WIDTH,
-        height: BENCH_HEIGHT,
-        data: vec![0; (BENCH_WIDTH * BENCH_HEIGHT * 3) as usize],
-    }
+// Two consecutive synthetic frames, a scrolling bar apart
+fn frame_pair() -> (Frame, Frame) {
+    let capture = SyntheticCapture::new(BENCH_WIDTH, BENCH_HEIGHT);
+    (capture.capture_frame().unwrap(), capture.capture_frame().unwrap())
 }
 
-// Helper function to create modified frame
-fn create_modified_frame(original: &Frame, change_percentage: f32) -> Frame {
-    let mut new_frame = original.clone();
-    let change_pixels = ((BENCH_WIDTH * BENCH_HEIGHT) as f32 * change_percentage) as usize;
-
-    for i in 0..(change_pixels * 3).min(new_frame.data.len()) {
-        new_frame.data[i] = 255;
+// What the host sends to get from one synthetic frame to the next
+fn frame_update() -> FrameUpdate {
+    let (previous, next) = frame_pair();
+    FrameUpdate {
+        frame_id: next.id,
+        timestamp: next.timestamp,
+        changes: PCCDetector::default().detect_changes(&previous, &next).unwrap(),
     }
-
-    new_frame
 }
 
-/// Run PCC detection benchmark
-fn bench_pcc_detection() {
+fn bench_detection(c: &mut Criterion) {
     let detector = PCCDetector::default();
-    let frame1 = create_test_frame(1);
-    let frame2 = create_modified_frame(&frame1, 0.1);
-
-    let start = std::time::Instant::now();
-    let iterations = 100;
-
-    for _ in 0..iterations {
-        let _changes = detector.detect_changes(&frame1, &frame2).unwrap();
-    }
+    let (previous, next) = frame_pair();
+    let mut group = c.benchmark_group("detection");
+    group.throughput(Throughput::Bytes(FRAME_BYTES));
+    group.bench_function("scrolling_bar", |b| b.iter(|| detector.detect_changes(&previous, &next).unwrap()));
+    group.bench_function("unchanged", |b| b.iter(|| detector.detect_changes(&previous, &previous).unwrap()));
+    group.finish();
+}
 
-    let elapsed = start.elapsed();
-    println!(
-        "PCC detection: {:.2}ms avg ({} iterations in {:.2}ms)",
-        elapsed.as_millis() as f64 / iterations as f64,
-        iterations,
-        elapsed.as_millis()
-    );
+fn bench_conversion(c: &mut Criterion) {
+    let rgba = vec![128; (BENCH_WIDTH * BENCH_HEIGHT * 4) as usize];
+    let mut rgb = vec![0; FRAME_BYTES as usize];
+    let mut group = c.benchmark_group("conversion");
+    group.throughput(Throughput::Bytes(FRAME_BYTES));
+    group.bench_function("rgba_to_rgb", |b| b.iter(|| capture::rgba_to_rgb(black_box(&rgba), &mut rgb)));
+    group.finish();
 }
 
-/// Run frame encoding benchmark
-fn bench_frame_encoding() {
+fn bench_encoding(c: &mut Criterion) {
     let rt = Runtime::new().unwrap();
     let encoder = FrameEncoder::new(BENCH_WIDTH, BENCH_HEIGHT, QualityConfig::default()).unwrap();
-    let frame = create_test_frame(1);
-
-    let start = std::time::Instant::now();
-    let iterations = 50;
-
-    for _ in 0..iterations {
-        rt.block_on(async {
-            encoder.encode_frame(&frame.data).await.unwrap();
-        });
-    }
-
-    let elapsed = start.elapsed();
-    println!(
-        "Frame encoding: {:.2}ms avg ({} iterations in {:.2}ms)",
-        elapsed.as_millis() as f64 / iterations as f64,
-        iterations,
-        elapsed.as_millis()
-    );
+    let (frame, _) = frame_pair();
+    let mut group = c.benchmark_group("encoding");
+    group.throughput(Throughput::Bytes(FRAME_BYTES));
+    group.bench_function("jpeg", |b| b.iter(|| rt.block_on(encoder.encode_frame(&frame.data)).unwrap()));
+    group.bench_function("lz4_roundtrip", |b| {
+        b.iter(|| {
+            let compressed = encoder::compression::compress_frame(&frame.data, 0.8).unwrap();
+            encoder::compression::decompress_frame(&compressed).unwrap()
+        })
+    });
+    group.finish();
 }
 
-/// Run frame compression benchmark
-fn bench_frame_compression() {
-    let frame = create_test_frame(1);
-
-    let start = std::time::Instant::now();
-    let iterations = 100;
-
-    for _ in 0..iterations {
-        let compressed = encoder::compression::compress_frame(&frame.data, 0.8).unwrap();
-        let _decompressed = encoder::compression::decompress_frame(&compressed).unwrap();
-    }
-
-    let elapsed = start.elapsed();
-    println!(
-        "Frame compress+decompress: {:.2}ms avg ({} iterations in {:.2}ms)",
-        elapsed.as_millis() as f64 / iterations as f64,
-        iterations,
-        elapsed.as_millis()
-    );
+fn bench_protocol(c: &mut Criterion) {
+    let (frame, _) = frame_pair();
+    let keyframe = frame.encode().unwrap();
+    let update = frame_update();
+    let messages = FrameProtocol::encode_update(&update).unwrap();
+    let mut group = c.benchmark_group("protocol");
+    group.bench_function("keyframe_encode", |b| b.iter(|| EncodedFrame::keyframe(&frame).unwrap()));
+    group.bench_function("keyframe_decode", |b| b.iter(|| Frame::decode(black_box(&keyframe)).unwrap()));
+    group.bench_function("update_serialize", |b| b.iter(|| FrameProtocol::encode_update(&update).unwrap()));
+    group.bench_function("update_parse", |b| {
+        b.iter(|| messages.iter().map(|message| FrameMessage::parse(message).unwrap()).collect::<Vec<_>>())
+    });
+    group.finish();
+
+    // Chunking a frame into FrameData messages slices its pixels, against
+    // joining each chunk into one buffer as serializing it whole would
+    let chunks = FrameProtocol::encode_frame(&frame).unwrap();
+    let pixels = frame.data.as_ptr_range();
+    assert!(chunks.iter().all(|chunk| pixels.contains(&chunk.payload.as_ptr())), "Chunks copied the frame");
+    let mut group = c.benchmark_group("frame_chunks");
+    group.throughput(Throughput::Bytes(FRAME_BYTES));
+    group.bench_function("shared", |b| b.iter(|| FrameProtocol::encode_frame(black_box(&frame)).unwrap()));
+    group.bench_function("copied", |b| {
+        b.iter(|| {
+            let chunks = FrameProtocol::encode_frame(black_box(&frame)).unwrap();
+            chunks.iter().map(|chunk| chunk.to_vec()).collect::<Vec<_>>()
+        })
+    });
+    group.finish();
 }
 
-/// Run full pipeline benchmark
-fn bench_full_pipeline() {
-    let rt = Runtime::new().unwrap();
-    let encoder = FrameEncoder::new(BENCH_WIDTH, BENCH_HEIGHT, QualityConfig::default()).unwrap();
-    let detector = PCCDetector::default();
-
-    let frame1 = create_test_frame(1);
-    let frame2 = create_modified_frame(&frame1, 0.1);
-
-    let start = std::time::Instant::now();
-    let iterations = 50;
-
-    for _ in 0..iterations {
-        // Detect changes
-        let _changes = detector.detect_changes(&frame1, &frame2).unwrap();
-
-        // Encode
-        rt.block_on(async {
-            encoder.encode_frame(&frame2.data).await.unwrap();
-        });
-    }
-
-    let elapsed = start.elapsed();
-    println!(
-        "Full pipeline (detect+encode): {:.2}ms avg ({} iterations in {:.2}ms)",
-        elapsed.as_millis() as f64 / iterations as f64,
-        iterations,
-        elapsed.as_millis()
-    );
+fn bench_delta(c: &mut Criterion) {
+    let (mut frame, _) = frame_pair();
+    let update = frame_update();
+    let scroll = Rect::new(0, 8, BENCH_WIDTH, BENCH_HEIGHT - 8);
+    let mut group = c.benchmark_group("delta");
+    group.bench_function("apply_update", |b| b.iter(|| frame.apply_update(&update).unwrap()));
+    group.bench_function("copy_rect", |b| b.iter(|| frame.copy_rect(0, 0, scroll).unwrap()));
+    // Copying shared pixels before writing them, as a viewer does while
+    // the frame is still being presented
+    group.bench_function("apply_update_shared", |b| {
+        b.iter_batched(
+            || frame.clone(),
+            |mut shared| {
+                delta::modify(&mut shared.data, |data| {
+                    delta::apply_changes(data, BENCH_WIDTH, BENCH_HEIGHT, &update.changes)
+                })
+                .unwrap();
+                shared
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
 }
 
-fn main() {
-    println!("=== PixelChangeCheck Benchmarks ===");
-    println!("Resolution: {}x{}", BENCH_WIDTH, BENCH_HEIGHT);
-    println!();
-
-    bench_pcc_detection();
-    bench_frame_encoding();
-    bench_frame_compression();
-    bench_full_pipeline();
-
-    println!();
-    println!("=== Benchmarks complete ===");
-} 
\ No newline at end of file
+criterion_group!(benches, bench_detection, bench_conversion, bench_encoding, bench_protocol, bench_delta);
+criterion_main!(benches);
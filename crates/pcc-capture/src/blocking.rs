@@ -0,0 +1,64 @@
+use pcc_core::{AsyncFrameCapture, AsyncPixelChangeDetector, ColorSpace, Frame, FrameCapture, PixelChange, PixelChangeDetector, QualityConfig};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use std::sync::{Arc, Mutex};
+
+/// Runs a synchronous `FrameCapture` as an `AsyncFrameCapture`, each
+/// capture on Tokio's blocking threads so the async ones keep running
+pub struct BlockingCapture<C> {
+    // Shared with the capture in flight, which may outlive a cancelled call
+    inner: Arc<Mutex<C>>,
+}
+
+impl<C: FrameCapture + Send + 'static> BlockingCapture<C> {
+    pub fn new(capture: C) -> Self {
+        Self { inner: Arc::new(Mutex::new(capture)) }
+    }
+}
+
+#[async_trait]
+impl<C: FrameCapture + Send + 'static> AsyncFrameCapture for BlockingCapture<C> {
+    async fn capture_frame(&self) -> Result<Frame> {
+        let inner = self.inner.clone();
+        tokio::task::spawn_blocking(move || inner.lock().unwrap().capture_frame())
+            .await
+            .context("Capture thread failed")?
+    }
+
+    fn supported_configs(&self) -> Vec<QualityConfig> {
+        self.inner.lock().unwrap().supported_configs()
+    }
+
+    fn configure(&mut self, config: QualityConfig) -> Result<()> {
+        self.inner.lock().unwrap().configure(config)
+    }
+
+    fn color_space(&self) -> ColorSpace {
+        self.inner.lock().unwrap().color_space()
+    }
+}
+
+/// Runs a synchronous `PixelChangeDetector` as an
+/// `AsyncPixelChangeDetector`, in place on the calling task. Fine for
+/// detectors as quick as `PCCDetector`; slower ones would hold up other
+/// tasks on the thread.
+pub struct BlockingDetector<D> {
+    inner: D,
+}
+
+impl<D: PixelChangeDetector + Send + Sync> BlockingDetector<D> {
+    pub fn new(detector: D) -> Self {
+        Self { inner: detector }
+    }
+}
+
+#[async_trait]
+impl<D: PixelChangeDetector + Send + Sync> AsyncPixelChangeDetector for BlockingDetector<D> {
+    async fn detect_changes(&self, previous: &Frame, current: &Frame) -> Result<Vec<PixelChange>> {
+        self.inner.detect_changes(previous, current)
+    }
+
+    fn configure(&mut self, config: QualityConfig) -> Result<()> {
+        self.inner.configure(config)
+    }
+}
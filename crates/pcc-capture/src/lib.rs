@@ -0,0 +1,12 @@
+//! Screen capture, the placeholder hosts send while sharing is paused, and
+//! the adapters that run synchronous captures and detectors on Tokio and
+//! turn captures into streams.
+
+pub mod privacy;
+
+mod blocking;
+mod screen;
+mod stream;
+pub use blocking::*;
+pub use screen::*;
+pub use stream::*;
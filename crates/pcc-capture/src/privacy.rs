@@ -0,0 +1,44 @@
+use pcc_core::{Frame, PixelFormat};
+use pcc_core::font::{glyph, GLYPH_HEIGHT, GLYPH_WIDTH};
+use std::time::SystemTime;
+
+// What the placeholder says
+const PAUSED_TEXT: &str = "SHARING PAUSED";
+const BACKGROUND: u8 = 0x20;
+const FOREGROUND: u8 = 0xc0;
+// Largest screen pixels per glyph pixel, so the text stays a caption
+const MAX_SCALE: u32 = 8;
+
+/// What viewers see in place of the screen while the host has paused
+/// sharing: a dark frame of the screen's size saying so, numbered and
+/// stamped like a captured one
+pub fn placeholder(id: u64, timestamp: SystemTime, width: u32, height: u32) -> Frame {
+    let mut data = vec![BACKGROUND; (width * height * 3) as usize];
+    let glyphs: Vec<[u8; 5]> = PAUSED_TEXT.chars().map(glyph).collect();
+    let columns = glyphs.len() as u32 * (GLYPH_WIDTH + 1);
+    // Half the screen wide at most
+    let scale = (width / 2 / columns).clamp(1, MAX_SCALE);
+    let x0 = width.saturating_sub(columns * scale) / 2;
+    let y0 = height.saturating_sub(GLYPH_HEIGHT * scale) / 2;
+
+    for (i, rows) in glyphs.iter().enumerate() {
+        for (gy, bits) in rows.iter().enumerate() {
+            for gx in 0..GLYPH_WIDTH {
+                if bits & (0b100 >> gx) == 0 {
+                    continue;
+                }
+                for sy in 0..scale {
+                    for sx in 0..scale {
+                        let px = x0 + (i as u32 * (GLYPH_WIDTH + 1) + gx) * scale + sx;
+                        let py = y0 + gy as u32 * scale + sy;
+                        if px < width && py < height {
+                            let p = ((py as u64 * width as u64 + px as u64) * 3) as usize;
+                            data[p..p + 3].fill(FOREGROUND);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Frame { id, timestamp, width, height, format: PixelFormat::Rgb24, data: data.into() }
+}
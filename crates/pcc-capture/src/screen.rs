@@ -0,0 +1,209 @@
+use anyhow::{Context, Result};
+use crate::privacy;
+use crate::stream::{capture_stream, FrameStream};
+use pcc_core::pool::FramePool;
+use pcc_core::types::{ColorSpace, Frame, FrameCapture, QualityConfig};
+use pcc_core::{PixelFormat, SessionClock};
+use screenshots::Screen;
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing::{debug, info};
+
+/// A display that can be captured
+#[derive(Debug, Clone, PartialEq)]
+pub struct Display {
+    /// Position in `list_displays`, as passed to `ScreenCapture::with_display`
+    pub index: usize,
+    pub width: u32,
+    pub height: u32,
+    pub scale_factor: f32,
+    pub is_primary: bool,
+}
+
+/// Every display this machine can capture
+pub fn list_displays() -> Result<Vec<Display>> {
+    let screens = Screen::all().context("Failed to enumerate screens")?;
+    Ok(screens
+        .iter()
+        .enumerate()
+        .map(|(index, screen)| Display {
+            index,
+            width: screen.display_info.width,
+            height: screen.display_info.height,
+            scale_factor: screen.display_info.scale_factor,
+            is_primary: screen.display_info.is_primary,
+        })
+        .collect())
+}
+
+/// Convert RGBA pixels, as the OS captures them, to the RGB24 frames
+/// carry, dropping alpha. `rgb` holds three bytes for each pixel.
+pub fn rgba_to_rgb(rgba: &[u8], rgb: &mut [u8]) {
+    for (rgb, rgba) in rgb.chunks_exact_mut(3).zip(rgba.chunks_exact(4)) {
+        rgb.copy_from_slice(&rgba[..3]);
+    }
+}
+
+pub struct ScreenCapture {
+    config: QualityConfig,
+    screen: Screen,
+    frame_counter: AtomicU64,
+    clock: SessionClock,
+    pool: FramePool,
+}
+
+impl ScreenCapture {
+    pub fn new() -> Result<Self> {
+        Self::with_display(0)
+    }
+
+    /// Capture the display at `index` in `list_displays`
+    pub fn with_display(index: usize) -> Result<Self> {
+        let screens = Screen::all()
+            .context("Failed to enumerate screens")?;
+
+        let count = screens.len();
+        let screen = screens
+            .into_iter()
+            .nth(index)
+            .with_context(|| match count {
+                0 => "No screens found".to_string(),
+                _ => format!("No display {} ({} found)", index, count),
+            })?;
+
+        info!(
+            "Screen capture initialized: {}x{} (scale: {})",
+            screen.display_info.width,
+            screen.display_info.height,
+            screen.display_info.scale_factor,
+        );
+
+        Ok(Self {
+            config: QualityConfig::default(),
+            screen,
+            frame_counter: AtomicU64::new(0),
+            clock: SessionClock::new(),
+            pool: FramePool::default(),
+        })
+    }
+
+    /// Open the display again, e.g. after capture stalls, keeping frame ids
+    /// and the clock
+    pub fn reopen(&mut self) -> Result<()> {
+        let id = self.screen.display_info.id;
+        self.screen = Screen::all()
+            .context("Failed to enumerate screens")?
+            .into_iter()
+            .find(|screen| screen.display_info.id == id)
+            .with_context(|| format!("Display {} is gone", id))?;
+        info!("Screen capture reopened: {}x{}", self.width(), self.height());
+        Ok(())
+    }
+
+    /// Stamp frames with `clock`, the one audio capture uses too
+    pub fn set_clock(&mut self, clock: SessionClock) {
+        self.clock = clock;
+    }
+
+    /// Convert captures into buffers from `pool`, e.g. the one the frame
+    /// pipeline recycles frames into
+    pub fn set_pool(&mut self, pool: FramePool) {
+        self.pool = pool;
+    }
+
+    /// A frame numbered and stamped like the next capture, showing the
+    /// paused placeholder rather than the screen
+    pub fn placeholder_frame(&self) -> Frame {
+        let id = self.frame_counter.fetch_add(1, Ordering::Relaxed);
+        privacy::placeholder(id, self.clock.now(), self.width(), self.height())
+    }
+
+    /// Captures as a `Stream`, taken as fast as the stream is polled; see
+    /// `capture_stream`
+    pub fn into_stream(self) -> FrameStream {
+        capture_stream(self)
+    }
+
+    /// Get the width of the captured screen
+    pub fn width(&self) -> u32 {
+        self.screen.display_info.width
+    }
+
+    /// Get the height of the captured screen
+    pub fn height(&self) -> u32 {
+        self.screen.display_info.height
+    }
+}
+
+impl FrameCapture for ScreenCapture {
+    fn capture_frame(&self) -> Result<Frame> {
+        let image = self
+            .screen
+            .capture()
+            .context("Failed to capture screen")?;
+
+        let width = image.width();
+        let height = image.height();
+
+        let rgba_data = image.into_raw();
+        let mut rgb_data = self.pool.take((width * height * 3) as usize);
+        rgba_to_rgb(&rgba_data, &mut rgb_data);
+
+        let id = self.frame_counter.fetch_add(1, Ordering::Relaxed);
+
+        debug!("Captured frame {}: {}x{}", id, width, height);
+
+        Ok(Frame {
+            id,
+            timestamp: self.clock.now(),
+            width,
+            height,
+            format: PixelFormat::Rgb24,
+            data: rgb_data.freeze(),
+        })
+    }
+
+    fn supported_configs(&self) -> Vec<QualityConfig> {
+        vec![
+            QualityConfig {
+                target_fps: 30,
+                max_fps: 60,
+                quality: 0.8,
+                compression_level: 6,
+                ..QualityConfig::default()
+            },
+            QualityConfig {
+                target_fps: 60,
+                max_fps: 60,
+                quality: 1.0,
+                compression_level: 4,
+                ..QualityConfig::default()
+            },
+        ]
+    }
+
+    fn configure(&mut self, config: QualityConfig) -> Result<()> {
+        self.config = config;
+        Ok(())
+    }
+
+    fn color_space(&self) -> ColorSpace {
+        // macOS hands back pixels in the display's own space rather than
+        // converting to sRGB, and built-in Mac displays are Display P3
+        if cfg!(target_os = "macos") {
+            ColorSpace::DisplayP3
+        } else {
+            ColorSpace::Srgb
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_screen_capture_creation() {
+        // This may fail in headless CI environments, which is expected
+        let _capture = ScreenCapture::new();
+    }
+}
\ No newline at end of file
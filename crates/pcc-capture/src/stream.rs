@@ -0,0 +1,25 @@
+use crate::BlockingCapture;
+use pcc_core::{AsyncFrameCapture, Frame, FrameCapture};
+use anyhow::Result;
+use futures::stream::{self, BoxStream, StreamExt};
+
+/// Captured frames as a `Stream`, for composing with stream combinators
+/// (`take_while`, `buffered`, tokio-stream's `throttle` and the like)
+/// rather than looping by hand
+pub type FrameStream = BoxStream<'static, Result<Frame>>;
+
+/// Frames from `capture`, each captured on a blocking thread when the
+/// stream is polled, so as fast as they're taken. The stream doesn't end:
+/// a failed capture yields its error and the next poll tries again.
+pub fn capture_stream(capture: impl FrameCapture + Send + 'static) -> FrameStream {
+    async_capture_stream(BlockingCapture::new(capture))
+}
+
+/// Like `capture_stream`, for captures that wait on the OS asynchronously
+pub fn async_capture_stream(capture: impl AsyncFrameCapture + 'static) -> FrameStream {
+    stream::unfold(capture, |capture| async move {
+        let frame = capture.capture_frame().await;
+        Some((frame, capture))
+    })
+    .boxed()
+}
@@ -0,0 +1,938 @@
+use anyhow::{bail, Context, Result};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use pixel_change_check_client::{
+    benchmark::{self, SyntheticCapture},
+    capture::{self, ScreenCapture},
+    client::{self, ClientConfig, SessionCommand, SessionMonitor, SessionRemote},
+    config::{ConfigWatcher, PccConfig},
+    daemon::{self, PidFile, RestartPolicy},
+    metrics::{self, MetricsSink},
+    network::{CloseReason, Fingerprint, Identity, SessionResume, ViewerApproval, DEFAULT_IDENTITY_DIR},
+    encoder::VideoCodec,
+    health::Health,
+    otel::{self, OtlpExporter, OtlpMetrics},
+    pcc::{FrameCapture, PCCDetector, PixelChangeDetector, QualityConfig},
+    quality::QualityPolicy,
+    server::{self, network::{HostStats, ServerNetwork}, Renderer},
+    service::{self, ServiceState},
+    session_log::{SessionEvent, SessionLog},
+};
+use std::ffi::OsString;
+use std::fs::OpenOptions;
+use std::future::Future;
+use std::io::IsTerminal;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::process::Stdio;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{info, warn};
+use tracing_subscriber::{
+    fmt::{format::FmtSpan, writer::BoxMakeWriter},
+    layer::SubscriberExt,
+    util::SubscriberInitExt,
+    EnvFilter, Layer,
+};
+
+// Logged when neither --log-level nor RUST_LOG says otherwise. The QUIC
+// stack is chatty at info.
+const DEFAULT_LOG_FILTER: &str = "info,quinn=warn,rustls=warn";
+
+/// Share screens with PCC: only the pixels that change go over the wire
+#[derive(Debug, Parser)]
+#[command(name = "pcc", version)]
+struct Cli {
+    /// TOML config file [default: pcc.toml, if present]. PCC_<SECTION>_<SETTING>
+    /// environment variables override it, and flags override both.
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    /// What to log: a level, optionally followed by per-module levels, e.g.
+    /// "info,quinn=warn,pcc_net=debug" [default: $RUST_LOG,
+    /// or info]
+    #[arg(long, global = true)]
+    log_level: Option<String>,
+    #[arg(long, global = true, value_enum, default_value_t)]
+    log_format: LogFormat,
+    /// Log each pipeline stage a frame passes through, with its frame_id
+    /// and how long it took: capture, detect, encode and send on the host,
+    /// receive, apply and present on the viewer. Implies debug logging for
+    /// pcc itself.
+    #[arg(long, global = true)]
+    trace_frames: bool,
+    /// Serve Prometheus metrics for `serve` or `connect` at
+    /// http://<ADDR>/metrics, e.g. 127.0.0.1:9100
+    #[arg(long, global = true, value_name = "ADDR")]
+    metrics: Option<SocketAddr>,
+    /// Append session events (connects, disconnects, reconnects, quality
+    /// changes, keyframes and errors) to this file as JSON lines, or write
+    /// them to stdout with -
+    #[arg(long, global = true, value_name = "PATH")]
+    event_log: Option<PathBuf>,
+    /// Push traces, including the per-frame pipeline spans, and metrics to
+    /// an OpenTelemetry collector over OTLP/HTTP, e.g. http://localhost:4318.
+    /// Needs the `otel` feature.
+    #[arg(long, global = true, value_name = "URL")]
+    otlp_endpoint: Option<String>,
+    #[command(flatten)]
+    daemon: DaemonArgs,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+enum LogFormat {
+    /// Multi-line, human readable
+    #[default]
+    Pretty,
+    /// One JSON object per line, for log aggregation
+    Json,
+}
+
+/// Options for running unattended, e.g. on lab machines
+#[derive(Debug, Args)]
+struct DaemonArgs {
+    /// Run `serve` or `connect` in the background, restarting it whenever it
+    /// fails or the session ends
+    #[arg(long, global = true)]
+    daemon: bool,
+    /// Like --daemon but in the foreground, e.g. under systemd or launchd.
+    /// Readiness is reported to $NOTIFY_SOCKET when set.
+    #[arg(long, global = true, conflicts_with = "daemon")]
+    supervise: bool,
+    /// Like --supervise, run by the Windows service manager, e.g. after
+    /// `sc create pcc binPath= "C:\pcc\pcc.exe --service serve"`. Logs go to
+    /// --log-file.
+    #[arg(long, global = true, conflicts_with_all = ["daemon", "supervise"])]
+    service: bool,
+    /// Pidfile written by --daemon and --supervise
+    #[arg(long, global = true, default_value = "pcc.pid")]
+    pidfile: PathBuf,
+    /// Where --daemon, --service and --dashboard write their log
+    #[arg(long, global = true, default_value = "pcc.log")]
+    log_file: PathBuf,
+}
+
+#[derive(Debug, Subcommand)]
+enum CertAction {
+    /// Create a self-signed certificate and key
+    Generate {
+        #[command(flatten)]
+        dir: CertDir,
+        /// DNS names or addresses hosts reach the viewer at
+        #[arg(long = "name", default_value = "localhost")]
+        names: Vec<String>,
+        /// Replace an existing certificate
+        #[arg(long)]
+        force: bool,
+    },
+    /// Print the certificate, where it's kept and its fingerprint
+    Show {
+        #[command(flatten)]
+        dir: CertDir,
+    },
+    /// Print the fingerprint for `connect --pin`
+    Fingerprint {
+        #[command(flatten)]
+        dir: CertDir,
+    },
+    /// Replace the certificate with a new one. Hosts pinning the old
+    /// fingerprint must be given the new one.
+    Rotate {
+        #[command(flatten)]
+        dir: CertDir,
+        #[arg(long = "name", default_value = "localhost")]
+        names: Vec<String>,
+    },
+}
+
+#[derive(Debug, Args)]
+struct CertDir {
+    /// Directory holding cert.pem and key.pem [default: network.identity from
+    /// the config, or pcc-identity]
+    #[arg(long)]
+    dir: Option<PathBuf>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// View screens shared by hosts that connect to this machine
+    Serve {
+        /// UDP port to listen on, or 0 for any free one [default: 5800]
+        #[arg(long)]
+        port: Option<u16>,
+        /// Write the port listened on to this file, e.g. for scripts when
+        /// the port is chosen with --port 0
+        #[arg(long)]
+        port_file: Option<PathBuf>,
+        /// Frames presented per second
+        #[arg(long)]
+        fps: Option<u32>,
+        /// Size of the display surface
+        #[arg(long, default_value_t = 1920)]
+        width: u32,
+        #[arg(long, default_value_t = 1080)]
+        height: u32,
+        /// Name to introduce this viewer to hosts by, e.g. when they ask
+        /// whether to share with it
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Share this machine's screen with the viewer at <addr>
+    Connect {
+        /// Viewer address, e.g. 192.168.1.20:5800
+        addr: SocketAddr,
+        /// Display to share, from `pcc list-displays`
+        #[arg(long)]
+        display: Option<usize>,
+        /// Frames captured per second
+        #[arg(long)]
+        fps: Option<u32>,
+        /// Encoding quality, 0.0-1.0
+        #[arg(long)]
+        quality: Option<f32>,
+        /// How frames are coded on the wire
+        #[arg(long)]
+        codec: Option<VideoCodec>,
+        /// What quality adaptation gives up first when the network or this
+        /// machine can't keep up: prefer-sharpness lowers the frame rate,
+        /// prefer-motion ignores subtle changes, fixed does neither
+        #[arg(long)]
+        policy: Option<QualityPolicy>,
+        /// Only share with a viewer whose certificate has this fingerprint,
+        /// from `pcc cert fingerprint` on the viewer
+        #[arg(long)]
+        pin: Option<Fingerprint>,
+        /// Make viewers not in this file pair by entering a PIN shown here,
+        /// and add them to it once they do
+        #[arg(long, value_name = "FILE")]
+        pair: Option<PathBuf>,
+        /// Ask before sharing with a viewer that isn't paired or in
+        /// network.trusted_viewers. With --dashboard, only those are shared
+        /// with.
+        #[arg(long)]
+        approve: bool,
+        /// Seal frames with a key only the viewer shares, so a relay in
+        /// between can't see the screen
+        #[arg(long)]
+        encrypt: bool,
+        /// Show live stats, with keys to force a keyframe or change quality.
+        /// Needs the `tui` feature.
+        #[arg(long, conflicts_with_all = ["daemon", "supervise"])]
+        dashboard: bool,
+    },
+    /// List the displays `connect --display` can share
+    ListDisplays,
+    /// Show each host connected to a running viewer: traffic, frames
+    /// delivered and dropped, RTT and the quality it's sharing at
+    Hosts {
+        /// The viewer's --metrics address, e.g. 127.0.0.1:9100
+        metrics: SocketAddr,
+    },
+    /// Manage the certificate `serve` presents, so hosts can pin it
+    Cert {
+        #[command(subcommand)]
+        action: CertAction,
+    },
+    /// Capture, detect changes and encode locally for a while, then print
+    /// how long each stage took and the frame rate this machine can reach
+    Benchmark {
+        /// Seconds to run for
+        #[arg(long, default_value_t = 10.0)]
+        duration: f64,
+        /// Display to capture, by its index in list-displays
+        #[arg(long)]
+        display: Option<usize>,
+        /// Generate frames instead of capturing a display
+        #[arg(long)]
+        synthetic: bool,
+        /// Size of synthetic frames
+        #[arg(long, default_value_t = 1920, requires = "synthetic")]
+        width: u32,
+        #[arg(long, default_value_t = 1080, requires = "synthetic")]
+        height: u32,
+    },
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let supervised = cli.daemon.daemon || cli.daemon.supervise || cli.daemon.service;
+    if supervised && !matches!(cli.command, Command::Serve { .. } | Command::Connect { .. }) {
+        bail!("--daemon and --supervise only apply to serve and connect");
+    }
+    if cli.daemon.daemon {
+        return detach(&cli.daemon).await;
+    }
+
+    // The dashboard has the terminal to itself
+    // Services have no console
+    let log_file = (cli.daemon.service || matches!(cli.command, Command::Connect { dashboard: true, .. }))
+        .then_some(&cli.daemon.log_file);
+    let exporter = cli.otlp_endpoint.as_deref().map(OtlpExporter::new).transpose()?;
+    init_logging(cli.log_level.as_deref(), cli.log_format, cli.trace_frames, log_file, exporter.as_ref())?;
+
+    let settings = PccConfig::load(cli.config.as_deref())?;
+    let reporting = Reporting {
+        metrics_addr: cli.metrics,
+        otlp: exporter.as_ref().map(OtlpExporter::metrics),
+        events: match &cli.event_log {
+            Some(path) => SessionLog::open(path)?,
+            None => SessionLog::default(),
+        },
+    };
+    let result = run(cli.command, cli.config, &cli.daemon, supervised, settings, reporting).await;
+    // Whatever is left of the trace goes out before exiting
+    if let Some(exporter) = exporter {
+        if let Err(e) = tokio::task::spawn_blocking(|| exporter.shutdown()).await? {
+            warn!("{:#}", e);
+        }
+    }
+    result
+}
+
+// Where a session reports how it's going, besides its log
+#[derive(Clone)]
+struct Reporting {
+    metrics_addr: Option<SocketAddr>,
+    otlp: Option<OtlpMetrics>,
+    events: SessionLog,
+}
+
+async fn run(
+    command: Command,
+    config: Option<PathBuf>,
+    daemon: &DaemonArgs,
+    supervised: bool,
+    settings: PccConfig,
+    reporting: Reporting,
+) -> Result<()> {
+    match command {
+        Command::Serve { port, port_file, fps, width, height, name } => {
+            let settings = watch_settings(config, settings, move |settings| {
+                settings.network.port = port.or(settings.network.port).or(Some(5800));
+                if name.is_some() {
+                    settings.network.name = name.clone();
+                }
+                settings.quality.target_fps = fps.unwrap_or(settings.quality.target_fps);
+                // Keep the identity from `pcc cert generate` between runs
+                if settings.network.identity.is_none() && Identity::exists(Path::new(DEFAULT_IDENTITY_DIR)) {
+                    settings.network.identity = Some(PathBuf::from(DEFAULT_IDENTITY_DIR));
+                }
+            })?;
+            if supervised {
+                run_supervised(daemon, |stopping| {
+                    serve(settings.clone(), width, height, port_file.as_deref(), reporting.clone(), stopping.wait())
+                })
+                .await
+            } else {
+                serve(settings, width, height, port_file.as_deref(), reporting, stop_signal()).await
+            }
+        }
+        Command::Connect { addr, display, fps, quality, codec, policy, pin, pair, approve, encrypt, dashboard } => {
+            let settings = watch_settings(config, settings, move |settings| {
+                settings.capture.display = display.unwrap_or(settings.capture.display);
+                settings.capture.codec = codec.unwrap_or(settings.capture.codec);
+                settings.network.pinned_fingerprint = pin.or(settings.network.pinned_fingerprint);
+                if pair.is_some() {
+                    settings.network.paired_viewers = pair.clone();
+                }
+                settings.network.approve_viewers |= approve;
+                settings.network.encrypt_payloads |= encrypt;
+                if let Some(fps) = fps {
+                    settings.quality.target_fps = fps;
+                    settings.quality.max_fps = settings.quality.max_fps.max(fps);
+                }
+                if let Some(quality) = quality {
+                    settings.quality.quality = quality.clamp(0.0, 1.0);
+                }
+                settings.quality.policy = policy.unwrap_or(settings.quality.policy);
+            })?;
+            if supervised {
+                // Share again whenever the viewer is back, picking up the
+                // session where the viewer still has it
+                let mut attempt = 0;
+                let resume = SessionResume::default();
+                run_supervised(daemon, |stopping| {
+                    if attempt > 0 {
+                        reporting.events.record(SessionEvent::Reconnect { peer: addr, attempt });
+                    }
+                    attempt += 1;
+                    connect(settings.clone(), addr, reporting.clone(), resume.clone(), stopping.wait())
+                })
+                .await
+            } else if dashboard {
+                connect_with_dashboard(settings, addr, reporting).await
+            } else {
+                connect(settings, addr, reporting, SessionResume::default(), stop_signal()).await
+            }
+        }
+        Command::ListDisplays => list_displays(),
+        Command::Hosts { metrics } => list_hosts(metrics).await,
+        Command::Cert { action } => cert(action, settings.network.identity),
+        Command::Benchmark { duration, display, synthetic, width, height } => {
+            let duration = Duration::try_from_secs_f64(duration).context("Invalid --duration")?;
+            let capture: Box<dyn FrameCapture + Send> = if synthetic {
+                Box::new(SyntheticCapture::new(width, height))
+            } else {
+                Box::new(ScreenCapture::with_display(display.unwrap_or(settings.capture.display))?)
+            };
+            benchmark(capture, settings.quality, duration).await
+        }
+    }
+}
+
+async fn serve(
+    mut settings: watch::Receiver<PccConfig>,
+    width: u32,
+    height: u32,
+    port_file: Option<&Path>,
+    reporting: Reporting,
+    shutdown: impl Future<Output = ()>,
+) -> Result<()> {
+    let current = settings.borrow_and_update().clone();
+    let identity = current.network.identity.clone();
+    let renderer = Renderer::with_options(width, height, current.quality.target_fps, current.renderer).await?;
+    let mut network = ServerNetwork::new(current.network, current.resilience)?
+        .with_event_log(reporting.events)
+        .with_frame_pool(renderer.frame_pool());
+    // Hosts that require pairing show a PIN to type in here
+    if std::io::stdin().is_terminal() {
+        network = network.with_pin_prompt(prompt_pin);
+    }
+    advertise(&network, identity.as_deref(), port_file)?;
+    service::notify(ServiceState::Ready);
+
+    let (network_ref, renderer_ref) = (&network, &renderer);
+    let result = tokio::select! {
+        result = network.start() => result,
+        result = renderer.start() => result,
+        result = server::present_incoming(&network, &renderer) => result,
+        result = serve_metrics(
+            reporting.metrics_addr,
+            || metrics::viewer_metrics(&network, &renderer),
+            || network.health(),
+            || network.host_stats(),
+        ) => result,
+        _ = export_metrics(reporting.otlp, move |mut sink| async move {
+            metrics::write_viewer_metrics(network_ref, renderer_ref, &mut sink).await
+        }) => unreachable!("Metrics are exported until the server stops"),
+        _ = shutdown => Ok(()),
+        _ = async {
+            while settings.changed().await.is_ok() {
+                let resilience = settings.borrow_and_update().resilience.clone();
+                if resilience != network.resilience_config() {
+                    info!("Applying reloaded resilience settings");
+                    network.set_resilience_config(resilience);
+                }
+            }
+            std::future::pending::<()>().await
+        } => unreachable!("Config reloads are applied until the server stops"),
+    };
+    // Hosts hear why rather than waiting to time out, and any recording
+    // gets its trailer
+    network.shutdown(CloseReason::Normal).await;
+    renderer.shutdown().await?;
+    result
+}
+
+// Ask on the terminal for the PIN the host at `host` shows
+fn prompt_pin(host: SocketAddr) -> Option<String> {
+    use std::io::{BufRead, Write};
+
+    // One prompt at a time, should several hosts pair at once
+    let mut stdin = std::io::stdin().lock();
+    eprint!("Host {} asks to pair. Enter the PIN it shows: ", host);
+    let _ = std::io::stderr().flush();
+    let mut pin = String::new();
+    stdin.read_line(&mut pin).ok()?;
+    Some(pin.trim().to_string()).filter(|pin| !pin.is_empty())
+}
+
+// Tell whoever started the viewer how hosts reach it, which matters when
+// the OS picked the port
+fn advertise(network: &ServerNetwork, identity: Option<&Path>, port_file: Option<&Path>) -> Result<()> {
+    let addr = network.advertised_addr()?;
+    if let Some(path) = port_file {
+        std::fs::write(path, format!("{}\n", addr.port()))
+            .with_context(|| format!("Failed to write port file {}", path.display()))?;
+    }
+    let mut command = format!("pcc connect {}", addr);
+    if let Some(dir) = identity {
+        command = format!("{} --pin {}", command, Identity::load(dir)?.fingerprint()?);
+    }
+    println!("Listening on port {}. To share a screen here, run on the host:\n  {}", addr.port(), command);
+    Ok(())
+}
+
+async fn connect(
+    mut settings: watch::Receiver<PccConfig>,
+    viewer: SocketAddr,
+    reporting: Reporting,
+    resume: SessionResume,
+    shutdown: impl Future<Output = ()>,
+) -> Result<()> {
+    let config = ClientConfig {
+        events: reporting.events,
+        approval: terminal_approval(),
+        resume,
+        ..settings.borrow_and_update().client_config(viewer)
+    };
+    let (monitor, remote) = SessionMonitor::new();
+    service::notify(ServiceState::Ready);
+    let reason = tokio::select! {
+        reason = client::run_monitored(config, shutdown, monitor) => reason?,
+        _ = forward_reloads(&mut settings, &remote) => unreachable!("Config reloads are forwarded until the session ends"),
+        _ = dump_on_signal(&remote) => unreachable!("Dump requests are forwarded until the session ends"),
+        _ = pause_on_signal(&remote) => unreachable!("Pause requests are forwarded until the session ends"),
+        Err(e) = serve_metrics(
+            reporting.metrics_addr,
+            || async { metrics::host_metrics(&remote.status()) },
+            || async { remote.health() },
+            // Hosts connect to viewers, not to each other
+            || async { Vec::new() },
+        ) => return Err(e),
+        _ = export_metrics(reporting.otlp, |mut sink| {
+            let status = remote.status();
+            async move { metrics::write_host_metrics(&status, &mut sink) }
+        }) => unreachable!("Metrics are exported until the session ends"),
+    };
+    info!("Session closed: {}", reason);
+    Ok(())
+}
+
+// Ask on the terminal whether to share with a viewer, if there is one to
+// ask on
+fn terminal_approval() -> ViewerApproval {
+    if !std::io::stdin().is_terminal() {
+        return ViewerApproval::default();
+    }
+    ViewerApproval::new(|viewer| {
+        use std::io::{BufRead, Write};
+
+        let mut stdin = std::io::stdin().lock();
+        eprint!("Share this screen with {}? [y/N] ", viewer);
+        let _ = std::io::stderr().flush();
+        let mut answer = String::new();
+        stdin.read_line(&mut answer).is_ok() && matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes")
+    })
+}
+
+// Serve metrics, health and host stats at `addr` if given, otherwise never
+// resolve
+async fn serve_metrics<F, Fut, H, HFut, S, SFut>(addr: Option<SocketAddr>, collect: F, check: H, hosts: S) -> Result<()>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = String>,
+    H: Fn() -> HFut,
+    HFut: Future<Output = Health>,
+    S: Fn() -> SFut,
+    SFut: Future<Output = Vec<HostStats>>,
+{
+    match addr {
+        Some(addr) => metrics::serve(addr, collect, check, hosts).await,
+        None => std::future::pending().await,
+    }
+}
+
+// Write metrics to `sink` every export interval if given, otherwise never
+// resolve
+async fn export_metrics<S, F, Fut>(sink: Option<S>, collect: F)
+where
+    S: MetricsSink + Clone,
+    F: Fn(S) -> Fut,
+    Fut: Future<Output = ()>,
+{
+    let Some(sink) = sink else { return std::future::pending().await };
+    let mut interval = tokio::time::interval(otel::EXPORT_INTERVAL);
+    loop {
+        interval.tick().await;
+        collect(sink.clone()).await;
+    }
+}
+
+// Hand reloaded quality settings to a running session
+async fn forward_reloads(settings: &mut watch::Receiver<PccConfig>, remote: &SessionRemote) {
+    let mut quality = settings.borrow().quality;
+    while settings.changed().await.is_ok() {
+        let reloaded = settings.borrow_and_update().quality;
+        if reloaded != quality {
+            quality = reloaded;
+            remote.send(SessionCommand::Reconfigure(quality));
+        }
+    }
+    std::future::pending().await
+}
+
+// Ask the session to dump its kept frames on SIGUSR1
+#[cfg(unix)]
+async fn dump_on_signal(remote: &SessionRemote) {
+    use tokio::signal::unix::{signal, SignalKind};
+    match signal(SignalKind::user_defined1()) {
+        Ok(mut signal) => {
+            while signal.recv().await.is_some() {
+                remote.send(SessionCommand::DumpFrames);
+            }
+        }
+        Err(e) => warn!("Failed to listen for SIGUSR1: {}", e),
+    }
+    std::future::pending().await
+}
+
+#[cfg(not(unix))]
+async fn dump_on_signal(_remote: &SessionRemote) {
+    std::future::pending().await
+}
+
+// Pause sharing on SIGUSR2, or resume it if paused
+#[cfg(unix)]
+async fn pause_on_signal(remote: &SessionRemote) {
+    use tokio::signal::unix::{signal, SignalKind};
+    match signal(SignalKind::user_defined2()) {
+        Ok(mut signal) => {
+            while signal.recv().await.is_some() {
+                let command = if remote.status().paused { SessionCommand::Resume } else { SessionCommand::Pause };
+                remote.send(command);
+            }
+        }
+        Err(e) => warn!("Failed to listen for SIGUSR2: {}", e),
+    }
+    std::future::pending().await
+}
+
+#[cfg(not(unix))]
+async fn pause_on_signal(_remote: &SessionRemote) {
+    std::future::pending().await
+}
+
+#[cfg(feature = "tui")]
+async fn connect_with_dashboard(
+    mut settings: watch::Receiver<PccConfig>,
+    viewer: SocketAddr,
+    reporting: Reporting,
+) -> Result<()> {
+    use pixel_change_check_client::dashboard::Dashboard;
+
+    // The dashboard has the terminal, so untrusted viewers can't be asked
+    // about and are turned away
+    let config = ClientConfig {
+        events: reporting.events,
+        ..settings.borrow_and_update().client_config(viewer)
+    };
+    let (monitor, remote) = SessionMonitor::new();
+    let (quit, quitting) = tokio::sync::oneshot::channel();
+    let dashboard_remote = remote.clone();
+    let dashboard = tokio::task::spawn_blocking(move || {
+        let result = Dashboard::new(dashboard_remote).run();
+        let _ = quit.send(());
+        result
+    });
+
+    // Quitting the dashboard stops sharing, like Ctrl+C without it
+    let shutdown = async {
+        tokio::select! {
+            _ = quitting => {}
+            _ = shutdown_signal() => {}
+        }
+    };
+    let result = tokio::select! {
+        result = client::run_monitored(config, shutdown, monitor) => result,
+        _ = forward_reloads(&mut settings, &remote) => unreachable!("Config reloads are forwarded until the session ends"),
+        _ = dump_on_signal(&remote) => unreachable!("Dump requests are forwarded until the session ends"),
+        _ = pause_on_signal(&remote) => unreachable!("Pause requests are forwarded until the session ends"),
+        Err(e) = serve_metrics(
+            reporting.metrics_addr,
+            || async { metrics::host_metrics(&remote.status()) },
+            || async { remote.health() },
+            // Hosts connect to viewers, not to each other
+            || async { Vec::new() },
+        ) => Err(e),
+        _ = export_metrics(reporting.otlp, |mut sink| {
+            let status = remote.status();
+            async move { metrics::write_host_metrics(&status, &mut sink) }
+        }) => unreachable!("Metrics are exported until the session ends"),
+    };
+    dashboard.await??;
+    println!("Session closed: {}", result?);
+    Ok(())
+}
+
+#[cfg(not(feature = "tui"))]
+async fn connect_with_dashboard(
+    _settings: watch::Receiver<PccConfig>,
+    _viewer: SocketAddr,
+    _reporting: Reporting,
+) -> Result<()> {
+    bail!("Built without the dashboard (enable the `tui` feature)")
+}
+
+// Keep `settings` up to date with the config file, applying `flags` to
+// them first and to every reload
+fn watch_settings(
+    path: Option<PathBuf>,
+    mut settings: PccConfig,
+    flags: impl Fn(&mut PccConfig) + Send + 'static,
+) -> Result<watch::Receiver<PccConfig>> {
+    let mut watcher = ConfigWatcher::new(path.as_deref())?;
+    flags(&mut settings);
+    let (reloaded, settings) = watch::channel(settings);
+    // Ends once nothing reads the settings
+    tokio::spawn(async move {
+        loop {
+            let changed = tokio::select! {
+                changed = watcher.changed() => changed,
+                _ = reloaded.closed() => break,
+            };
+            match changed {
+                Ok(mut settings) => {
+                    flags(&mut settings);
+                    info!("Reloaded config; quality and resilience settings apply now, others on restart");
+                    reloaded.send_replace(settings);
+                }
+                Err(e) => warn!("Keeping the current config: {:#}", e),
+            }
+        }
+    });
+    Ok(settings)
+}
+
+fn init_logging(
+    filter: Option<&str>,
+    format: LogFormat,
+    trace_frames: bool,
+    file: Option<&PathBuf>,
+    exporter: Option<&OtlpExporter>,
+) -> Result<()> {
+    let mut filter = match filter {
+        Some(filter) => EnvFilter::try_new(filter).with_context(|| format!("Invalid --log-level {:?}", filter))?,
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(DEFAULT_LOG_FILTER)),
+    };
+    // Frame spans are at debug level, and log their timings as they close
+    let span_events = if trace_frames {
+        for target in pixel_change_check_client::LOG_TARGETS {
+            filter = filter.add_directive(format!("{}=debug", target).parse()?);
+        }
+        FmtSpan::CLOSE
+    } else {
+        FmtSpan::NONE
+    };
+    let (writer, ansi) = match file {
+        Some(path) => {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("Failed to open log file {}", path.display()))?;
+            (BoxMakeWriter::new(Mutex::new(file)), false)
+        }
+        None => (BoxMakeWriter::new(std::io::stdout), std::io::stdout().is_terminal()),
+    };
+    let logger = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_span_events(span_events)
+        .with_thread_ids(true)
+        .with_file(true)
+        .with_line_number(true);
+    let logger = match format {
+        LogFormat::Pretty => logger.with_target(false).with_ansi(ansi).pretty().boxed(),
+        // Event fields sit at the top level, where aggregators look for them
+        LogFormat::Json => logger.json().flatten_event(true).boxed(),
+    };
+    // The exporter picks its own spans, so --log-level only affects the log
+    tracing_subscriber::registry()
+        .with(logger.with_filter(filter))
+        .with(exporter.map(|exporter| exporter.layer()))
+        .init();
+    Ok(())
+}
+
+// Run `pipeline` under supervision until Ctrl+C or SIGTERM, or the service
+// manager stops it, holding the pidfile meanwhile
+async fn run_supervised<F, Fut>(args: &DaemonArgs, pipeline: F) -> Result<()>
+where
+    F: FnMut(daemon::Stopping) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let pidfile = PidFile::create(&args.pidfile)?;
+    info!("Running as pid {} ({})", std::process::id(), pidfile.path().display());
+    if args.service {
+        #[cfg(windows)]
+        return service::run_as_windows_service(|stopping| {
+            daemon::supervise(RestartPolicy::default(), stopping.wait(), pipeline)
+        })
+        .await;
+        #[cfg(not(windows))]
+        bail!("--service is only for Windows; use --supervise under systemd or launchd");
+    }
+    daemon::supervise(RestartPolicy::default(), stop_signal(), pipeline).await
+}
+
+// Start this command again in the background under supervision, with its
+// output going to the log file
+async fn detach(args: &DaemonArgs) -> Result<()> {
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&args.log_file)
+        .with_context(|| format!("Failed to open log file {}", args.log_file.display()))?;
+    let child_args = std::env::args_os()
+        .skip(1)
+        .map(|arg| if arg == "--daemon" { OsString::from("--supervise") } else { arg });
+
+    let mut command = std::process::Command::new(std::env::current_exe()?);
+    command
+        .args(child_args)
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log);
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        // Leave the terminal's process group so its Ctrl+C doesn't reach us
+        command.process_group(0);
+    }
+    let mut child = command.spawn().context("Failed to start daemon")?;
+
+    // Report startup failures, like the pidfile being taken, while someone
+    // is still watching
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    if let Some(status) = child.try_wait()? {
+        bail!("Daemon exited at startup ({}); see {}", status, args.log_file.display());
+    }
+    println!("Started pcc as pid {}, logging to {}", child.id(), args.log_file.display());
+    Ok(())
+}
+
+// Ctrl+C or SIGTERM stops serving or sharing, which tells peers why
+fn stop_signal() -> impl Future<Output = ()> {
+    let signal = shutdown_signal();
+    async {
+        signal.await;
+        info!("Stopping...");
+        service::notify(ServiceState::Stopping);
+    }
+}
+
+// Resolves on Ctrl+C, or on SIGTERM where there is one. The handlers are
+// installed straight away rather than when first polled, so a service
+// manager can stop pcc as soon as it reports ready.
+fn shutdown_signal() -> Pin<Box<dyn Future<Output = ()> + Send>> {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match (signal(SignalKind::interrupt()), signal(SignalKind::terminate())) {
+            (Ok(mut interrupt), Ok(mut terminate)) => {
+                return Box::pin(async move {
+                    tokio::select! {
+                        _ = interrupt.recv() => {}
+                        _ = terminate.recv() => {}
+                    }
+                });
+            }
+            (Err(e), _) | (_, Err(e)) => warn!("Not handling SIGTERM: {}", e),
+        }
+    }
+    Box::pin(async {
+        let _ = tokio::signal::ctrl_c().await;
+    })
+}
+
+fn list_displays() -> Result<()> {
+    for display in capture::list_displays()? {
+        println!(
+            "{}: {}x{} (scale {}){}",
+            display.index,
+            display.width,
+            display.height,
+            display.scale_factor,
+            if display.is_primary { ", primary" } else { "" }
+        );
+    }
+    Ok(())
+}
+
+async fn list_hosts(addr: SocketAddr) -> Result<()> {
+    let hosts = metrics::query_hosts(addr).await?;
+    if hosts.is_empty() {
+        println!("No hosts connected");
+        return Ok(());
+    }
+    println!(
+        "{:>7} {:<22} {:>7} {:>10} {:>10} {:>9} {:>7} {:>15}",
+        "SESSION", "HOST", "RTT", "SENT", "RECEIVED", "FRAMES", "DROPPED", "QUALITY"
+    );
+    for host in hosts {
+        let quality = host.quality.map_or("-".to_string(), |quality| {
+            format!("{} fps, {:.0}%", quality.target_fps, quality.quality * 100.0)
+        });
+        println!(
+            "{:>7} {:<22} {:>4} ms {:>10} {:>10} {:>9} {:>7} {:>15}",
+            host.session,
+            host.addr,
+            host.rtt.as_millis(),
+            format_bytes(host.sent_bytes),
+            format_bytes(host.received_bytes),
+            host.frames_delivered(),
+            host.frames_dropped(),
+            quality
+        );
+    }
+    Ok(())
+}
+
+fn format_bytes(bytes: u64) -> String {
+    match bytes {
+        0..=9_999 => format!("{} B", bytes),
+        10_000..=9_999_999 => format!("{:.1} kB", bytes as f64 / 1e3),
+        10_000_000..=9_999_999_999 => format!("{:.1} MB", bytes as f64 / 1e6),
+        _ => format!("{:.1} GB", bytes as f64 / 1e9),
+    }
+}
+
+fn cert(action: CertAction, configured: Option<PathBuf>) -> Result<()> {
+    let dir = |dir: CertDir| dir.dir.or(configured).unwrap_or_else(|| PathBuf::from(DEFAULT_IDENTITY_DIR));
+    match action {
+        CertAction::Generate { dir: cert_dir, names, force } => {
+            let dir = dir(cert_dir);
+            if Identity::exists(&dir) && !force {
+                bail!("{} already holds a certificate; use `pcc cert rotate` to replace it", dir.display());
+            }
+            let identity = Identity::generate(names)?;
+            identity.save(&dir)?;
+            println!("Created {}", Identity::cert_path(&dir).display());
+            println!("Fingerprint: {}", identity.fingerprint()?);
+        }
+        CertAction::Show { dir: cert_dir } => {
+            let dir = dir(cert_dir);
+            let identity = Identity::load(&dir)?;
+            println!("Certificate: {}", Identity::cert_path(&dir).display());
+            println!("Fingerprint: {}", identity.fingerprint()?);
+            print!("{}", identity.cert_pem());
+        }
+        CertAction::Fingerprint { dir: cert_dir } => {
+            println!("{}", Identity::load(&dir(cert_dir))?.fingerprint()?);
+        }
+        CertAction::Rotate { dir: cert_dir, names } => {
+            let dir = dir(cert_dir);
+            let old = Identity::load(&dir)?.fingerprint()?;
+            let identity = Identity::generate(names)?;
+            identity.save(&dir)?;
+            println!("Replaced {}", Identity::cert_path(&dir).display());
+            println!("Old fingerprint: {}", old);
+            println!("New fingerprint: {}", identity.fingerprint()?);
+            println!("Restart `pcc serve` and give hosts the new fingerprint");
+        }
+    }
+    Ok(())
+}
+
+async fn benchmark(capture: Box<dyn FrameCapture + Send>, quality: QualityConfig, duration: Duration) -> Result<()> {
+    let mut detector = PCCDetector::default();
+    detector.configure(quality)?;
+    println!("Running for {:.0?}...", duration);
+    // Capturing blocks, so keep it off the runtime
+    let report = tokio::task::spawn_blocking(move || benchmark::run(capture.as_ref(), detector, duration)).await??;
+    println!("{}", report);
+    Ok(())
+}
@@ -0,0 +1,35 @@
+use std::time::{Instant, SystemTime};
+
+/// The host's presentation clock, shared by video and audio capture so
+/// frame timestamps and audio PTS can be compared directly. It reads like
+/// wall-clock time but runs monotonically from when it was created, so
+/// NTP adjustments mid-session can't tear the streams apart.
+#[derive(Debug, Clone, Copy)]
+pub struct SessionClock {
+    origin: SystemTime,
+    started: Instant,
+}
+
+impl Default for SessionClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SessionClock {
+    pub fn new() -> Self {
+        Self {
+            origin: SystemTime::now(),
+            started: Instant::now(),
+        }
+    }
+
+    pub fn now(&self) -> SystemTime {
+        self.at(Instant::now())
+    }
+
+    /// Session time at `instant`
+    pub fn at(&self, instant: Instant) -> SystemTime {
+        self.origin + instant.saturating_duration_since(self.started)
+    }
+}
@@ -0,0 +1,91 @@
+use crate::types::{Frame, FrameUpdate, PixelChange, Rect};
+use anyhow::Result;
+use bytes::{Bytes, BytesMut};
+
+/// Change shared pixels in place, copying them first only if someone else
+/// still holds them
+pub fn modify<R>(data: &mut Bytes, modify: impl FnOnce(&mut [u8]) -> R) -> R {
+    let mut unique = BytesMut::from(std::mem::take(data));
+    let result = modify(&mut unique);
+    *data = unique.freeze();
+    result
+}
+
+/// Write `changes` into `data`, RGB24 pixels of a `width`x`height` frame.
+/// Nothing is written unless every change fits.
+pub fn apply_changes(data: &mut [u8], width: u32, height: u32, changes: &[PixelChange]) -> Result<()> {
+    for change in changes {
+        let rect = Rect::new(change.x, change.y, change.width, change.height);
+        let expected = change.width as usize * change.height as usize * 3;
+        if !rect.fits_within(width, height) || change.data.len() < expected {
+            anyhow::bail!("Pixel change {:?} does not fit a {}x{} frame", rect, width, height);
+        }
+    }
+    if data.len() < width as usize * height as usize * 3 {
+        anyhow::bail!("Frame is smaller than {}x{}", width, height);
+    }
+
+    for change in changes {
+        let row_bytes = change.width as usize * 3;
+        for y in 0..change.height as usize {
+            let frame_offset = ((change.y as usize + y) * width as usize + change.x as usize) * 3;
+            let change_offset = y * row_bytes;
+            data[frame_offset..frame_offset + row_bytes]
+                .copy_from_slice(&change.data[change_offset..change_offset + row_bytes]);
+        }
+    }
+    Ok(())
+}
+
+/// Copy the block at (`src_x`, `src_y`) of `data`, RGB24 pixels of a
+/// `width`x`height` frame, to `dst_rect` in the same frame, e.g. to scroll
+pub fn copy_rect(data: &mut [u8], width: u32, height: u32, src_x: u32, src_y: u32, dst_rect: Rect) -> Result<()> {
+    let src_rect = Rect::new(src_x, src_y, dst_rect.width, dst_rect.height);
+    if !src_rect.fits_within(width, height) || !dst_rect.fits_within(width, height) {
+        anyhow::bail!("CopyRect out of bounds: {:?} -> {:?}", src_rect, dst_rect);
+    }
+    let stride = width as usize * 3;
+    if data.len() < stride * height as usize {
+        anyhow::bail!("Frame is smaller than {}x{}", width, height);
+    }
+
+    let row_bytes = dst_rect.width as usize * 3;
+    let row_offset = |x: u32, y: u32| y as usize * stride + x as usize * 3;
+    // Walk rows away from the overlap so source rows are read before being
+    // overwritten
+    let rows: Box<dyn Iterator<Item = u32>> = if src_y < dst_rect.y {
+        Box::new((0..dst_rect.height).rev())
+    } else {
+        Box::new(0..dst_rect.height)
+    };
+    for dy in rows {
+        let src = row_offset(src_x, src_y + dy);
+        let dst = row_offset(dst_rect.x, dst_rect.y + dy);
+        data.copy_within(src..src + row_bytes, dst);
+    }
+    Ok(())
+}
+
+impl Frame {
+    /// Change the pixels in place; see `modify`
+    pub fn modify_data<R>(&mut self, change: impl FnOnce(&mut [u8]) -> R) -> R {
+        modify(&mut self.data, change)
+    }
+
+    /// Apply a delta update, making this frame represent `update.frame_id`
+    pub fn apply_update(&mut self, update: &FrameUpdate) -> Result<()> {
+        self.expect_rgb24()?;
+        let (width, height) = (self.width, self.height);
+        self.modify_data(|data| apply_changes(data, width, height, &update.changes))?;
+        self.id = update.frame_id;
+        self.timestamp = update.timestamp;
+        Ok(())
+    }
+
+    /// Copy a block of this frame onto itself; see `copy_rect`
+    pub fn copy_rect(&mut self, src_x: u32, src_y: u32, dst_rect: Rect) -> Result<()> {
+        self.expect_rgb24()?;
+        let (width, height) = (self.width, self.height);
+        self.modify_data(|data| copy_rect(data, width, height, src_x, src_y, dst_rect))
+    }
+}
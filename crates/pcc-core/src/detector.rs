@@ -0,0 +1,245 @@
+use super::format::PixelFormat;
+use super::types::{CopyRect, Frame, PixelChange, PixelChangeDetector, QualityConfig, Rect};
+use anyhow::Result;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+const BYTES_PER_PIXEL: usize = 3; // RGB24
+const MIN_SCROLL_ROWS: usize = 16; // Smallest run of shifted rows worth a CopyRect
+
+pub struct PCCDetector {
+    config: QualityConfig,
+    threshold: u8,
+    block_size: u32,
+}
+
+impl Default for PCCDetector {
+    fn default() -> Self {
+        Self {
+            config: QualityConfig::default(),
+            threshold: QualityConfig::default().threshold,
+            block_size: 32, // Size of blocks to compare
+        }
+    }
+}
+
+impl PCCDetector {
+    /// Create a new PCCDetector with custom configuration
+    pub fn new(config: QualityConfig, threshold: u8, block_size: u32) -> Self {
+        Self {
+            config,
+            threshold,
+            block_size,
+        }
+    }
+
+    /// Compare two blocks of pixels using direct comparison
+    #[inline]
+    fn compare_blocks(&self, prev: &[u8], curr: &[u8]) -> bool {
+        debug_assert_eq!(prev.len(), curr.len(), "Block sizes must match");
+        
+        // Compare bytes directly
+        for (p, c) in prev.iter().zip(curr.iter()) {
+            if (*p as i16 - *c as i16).abs() > self.threshold as i16 {
+                return true;
+            }
+        }
+        
+        false
+    }
+
+    /// Find the bounds of changed region in a block of RGB24 pixels
+    fn find_change_bounds(&self, prev: &[u8], curr: &[u8], width: u32, height: u32) -> Option<(u32, u32, u32, u32)> {
+        let mut min_x = width;
+        let mut min_y = height;
+        let mut max_x = 0;
+        let mut max_y = 0;
+        let mut found_change = false;
+
+        for y in 0..height {
+            for x in 0..width {
+                let idx = (y * width + x) as usize * BYTES_PER_PIXEL;
+                if self.compare_blocks(&prev[idx..idx + BYTES_PER_PIXEL], &curr[idx..idx + BYTES_PER_PIXEL]) {
+                    min_x = min_x.min(x);
+                    min_y = min_y.min(y);
+                    max_x = max_x.max(x);
+                    max_y = max_y.max(y);
+                    found_change = true;
+                }
+            }
+        }
+
+        if found_change {
+            Some((min_x, min_y, max_x + 1, max_y + 1))
+        } else {
+            None
+        }
+    }
+
+    /// Detect a vertical scroll of up to `max_offset` rows between two frames.
+    ///
+    /// Finds the longest run of rows in `current` that exactly match rows of
+    /// `previous` shifted up or down, and returns it as a `CopyRect` so the
+    /// viewer can reproduce the run locally instead of receiving its pixels.
+    pub fn detect_scroll(&self, previous: &Frame, current: &Frame, max_offset: u32) -> Option<CopyRect> {
+        if previous.width != current.width || previous.height != current.height {
+            return None;
+        }
+        if previous.format != PixelFormat::Rgb24 || current.format != PixelFormat::Rgb24 {
+            return None;
+        }
+
+        let row_len = current.width as usize * BYTES_PER_PIXEL;
+        let height = current.height as usize;
+        if row_len == 0 || previous.data.len() < row_len * height || current.data.len() < row_len * height {
+            return None;
+        }
+
+        let prev_rows = Self::row_hashes(&previous.data, row_len, height);
+        let curr_rows = Self::row_hashes(&current.data, row_len, height);
+        let min_run = (height / 4).max(MIN_SCROLL_ROWS);
+        let max_offset = (max_offset as usize).min(height.saturating_sub(1)) as isize;
+
+        // (run length, first destination row, first source row)
+        let mut best: Option<(usize, usize, usize)> = None;
+
+        for shift in (-max_offset..=max_offset).filter(|s| *s != 0) {
+            let mut run_start = 0;
+            let mut run_len = 0;
+            let mut run_moved = false;
+
+            for y in 0..=height {
+                let src = y as isize + shift;
+                let matches = y < height
+                    && (0..height as isize).contains(&src)
+                    && curr_rows[y] == prev_rows[src as usize];
+
+                if matches {
+                    if run_len == 0 {
+                        run_start = y;
+                        run_moved = false;
+                    }
+                    run_len += 1;
+                    // Rows that are identical in both frames don't prove any movement
+                    run_moved |= curr_rows[y] != prev_rows[y];
+                    continue;
+                }
+
+                if run_moved && run_len >= min_run && best.is_none_or(|(len, _, _)| run_len > len) {
+                    best = Some((run_len, run_start, (run_start as isize + shift) as usize));
+                }
+                run_len = 0;
+            }
+        }
+
+        let (run_len, dst_y, src_y) = best?;
+
+        // Guard against hash collisions before committing to the copy
+        let dst = &current.data[dst_y * row_len..(dst_y + run_len) * row_len];
+        let src = &previous.data[src_y * row_len..(src_y + run_len) * row_len];
+        if dst != src {
+            return None;
+        }
+
+        Some(CopyRect {
+            src_x: 0,
+            src_y: src_y as u32,
+            dst_rect: Rect::new(0, dst_y as u32, current.width, run_len as u32),
+        })
+    }
+
+    fn row_hashes(data: &[u8], row_len: usize, height: usize) -> Vec<u64> {
+        data.chunks_exact(row_len)
+            .take(height)
+            .map(|row| {
+                let mut hasher = DefaultHasher::new();
+                row.hash(&mut hasher);
+                hasher.finish()
+            })
+            .collect()
+    }
+}
+
+impl PixelChangeDetector for PCCDetector {
+    fn detect_scroll(&self, previous: &Frame, current: &Frame, max_offset: u32) -> Option<CopyRect> {
+        PCCDetector::detect_scroll(self, previous, current, max_offset)
+    }
+
+    fn detect_changes(&self, previous: &Frame, current: &Frame) -> Result<Vec<PixelChange>> {
+        if previous.width != current.width || previous.height != current.height {
+            anyhow::bail!("Frame dimensions do not match");
+        }
+        previous.expect_rgb24()?;
+        current.expect_rgb24()?;
+
+        let mut changes = Vec::new();
+        let width = previous.width;
+        let height = previous.height;
+
+        let frame_len = width as usize * height as usize * BYTES_PER_PIXEL;
+        if previous.data.len() < frame_len || current.data.len() < frame_len {
+            anyhow::bail!("Frame data is smaller than {}x{} RGB24", width, height);
+        }
+        
+        // Process frame in blocks
+        for y in (0..height).step_by(self.block_size as usize) {
+            for x in (0..width).step_by(self.block_size as usize) {
+                let block_width = std::cmp::min(self.block_size, width - x);
+                let block_height = std::cmp::min(self.block_size, height - y);
+                
+                // Extract blocks from both frames
+                let prev_block: Vec<u8> = (0..block_height)
+                    .flat_map(|dy| {
+                        let start = ((y + dy) * width + x) as usize * BYTES_PER_PIXEL;
+                        let end = start + block_width as usize * BYTES_PER_PIXEL;
+                        previous.data[start..end].iter().copied()
+                    })
+                    .collect();
+
+                let curr_block: Vec<u8> = (0..block_height)
+                    .flat_map(|dy| {
+                        let start = ((y + dy) * width + x) as usize * BYTES_PER_PIXEL;
+                        let end = start + block_width as usize * BYTES_PER_PIXEL;
+                        current.data[start..end].iter().copied()
+                    })
+                    .collect();
+
+                // Compare blocks
+                if self.compare_blocks(&prev_block, &curr_block) {
+                    // Find exact bounds of the change within the block
+                    if let Some((min_x, min_y, max_x, max_y)) = 
+                        self.find_change_bounds(&prev_block, &curr_block, block_width, block_height) {
+                        
+                        let change_width = max_x - min_x;
+                        let change_height = max_y - min_y;
+                        
+                        // Extract changed region
+                        let mut change_data =
+                            Vec::with_capacity((change_width * change_height) as usize * BYTES_PER_PIXEL);
+                        for dy in min_y..max_y {
+                            let start = (dy * block_width + min_x) as usize * BYTES_PER_PIXEL;
+                            let end = start + change_width as usize * BYTES_PER_PIXEL;
+                            change_data.extend_from_slice(&curr_block[start..end]);
+                        }
+
+                        changes.push(PixelChange {
+                            x: x + min_x,
+                            y: y + min_y,
+                            width: change_width,
+                            height: change_height,
+                            data: change_data,
+                        });
+                    }
+                }
+            }
+        }
+
+        Ok(changes)
+    }
+
+    fn configure(&mut self, config: QualityConfig) -> Result<()> {
+        self.config = config;
+        self.threshold = config.threshold;
+        Ok(())
+    }
+} 
\ No newline at end of file
@@ -0,0 +1,263 @@
+use crate::types::Frame;
+use anyhow::Result;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+
+/// How a frame's pixels are laid out in its data. Planes are tightly
+/// packed, one after another, so a frame's size and format give every
+/// plane's place; see `PixelFormat::planes`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PixelFormat {
+    /// 8-bit R, G and B. What detection, deltas, coding and display work
+    /// in, so frames in other formats are converted to it first.
+    #[default]
+    Rgb24,
+    /// 8-bit B, G, R and A, as most OS capture APIs hand back
+    Bgra32,
+    /// A Y plane, then one plane of U and V interleaved at half the size
+    /// each way, as hardware video coders take
+    Nv12,
+    /// A Y plane, then U and V planes at half the size each way
+    I420,
+    /// 16-bit little-endian R, G, B and A, e.g. from HDR capture
+    Rgba64,
+}
+
+/// Where one plane of a frame's pixels lies in its data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Plane {
+    /// Byte offset of the plane's first row
+    pub offset: usize,
+    /// Bytes from the start of one row to the next
+    pub stride: usize,
+    /// Rows in the plane
+    pub rows: usize,
+}
+
+impl Plane {
+    /// Bytes the plane takes
+    pub fn len(&self) -> usize {
+        self.stride * self.rows
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // The plane's bytes in `data`
+    fn of<'a>(&self, data: &'a [u8]) -> &'a [u8] {
+        &data[self.offset..self.offset + self.len()]
+    }
+}
+
+impl PixelFormat {
+    /// Bytes per pixel of a packed format, or None for planar YUV, whose
+    /// chroma is shared between pixels
+    pub fn bytes_per_pixel(self) -> Option<usize> {
+        match self {
+            Self::Rgb24 => Some(3),
+            Self::Bgra32 => Some(4),
+            Self::Rgba64 => Some(8),
+            Self::Nv12 | Self::I420 => None,
+        }
+    }
+
+    /// The planes of a `width`x`height` frame in this format
+    pub fn planes(self, width: u32, height: u32) -> Vec<Plane> {
+        let (width, height) = (width as usize, height as usize);
+        let (chroma_width, chroma_height) = (width.div_ceil(2), height.div_ceil(2));
+        let luma = Plane { offset: 0, stride: width, rows: height };
+        match self {
+            Self::Rgb24 | Self::Bgra32 | Self::Rgba64 => {
+                let bytes = self.bytes_per_pixel().unwrap_or_default();
+                vec![Plane { offset: 0, stride: width * bytes, rows: height }]
+            }
+            Self::Nv12 => vec![luma, Plane { offset: luma.len(), stride: chroma_width * 2, rows: chroma_height }],
+            Self::I420 => {
+                let u = Plane { offset: luma.len(), stride: chroma_width, rows: chroma_height };
+                let v = Plane { offset: u.offset + u.len(), ..u };
+                vec![luma, u, v]
+            }
+        }
+    }
+
+    /// Bytes a `width`x`height` frame in this format takes
+    pub fn frame_len(self, width: u32, height: u32) -> usize {
+        self.planes(width, height).iter().map(Plane::len).sum()
+    }
+}
+
+/// Convert `data`, a `width`x`height` frame in `format`, to RGB24
+pub fn to_rgb24(format: PixelFormat, width: u32, height: u32, data: &[u8]) -> Result<Vec<u8>> {
+    check_len(format, width, height, data)?;
+    let pixels = width as usize * height as usize;
+    let mut rgb = vec![0; pixels * 3];
+    match format {
+        PixelFormat::Rgb24 => rgb.copy_from_slice(&data[..pixels * 3]),
+        PixelFormat::Bgra32 => {
+            for (rgb, bgra) in rgb.chunks_exact_mut(3).zip(data.chunks_exact(4)) {
+                rgb.copy_from_slice(&[bgra[2], bgra[1], bgra[0]]);
+            }
+        }
+        PixelFormat::Rgba64 => {
+            // The high byte of each little-endian channel
+            for (rgb, rgba) in rgb.chunks_exact_mut(3).zip(data.chunks_exact(8)) {
+                rgb.copy_from_slice(&[rgba[1], rgba[3], rgba[5]]);
+            }
+        }
+        PixelFormat::Nv12 | PixelFormat::I420 => {
+            let planes = format.planes(width, height);
+            let luma = planes[0].of(data);
+            for (i, rgb) in rgb.chunks_exact_mut(3).enumerate() {
+                let (x, y) = (i % width as usize, i / width as usize);
+                let (u, v) = chroma_at(format, &planes, data, x / 2, y / 2);
+                rgb.copy_from_slice(&yuv_to_rgb(luma[y * planes[0].stride + x], u, v));
+            }
+        }
+    }
+    Ok(rgb)
+}
+
+/// Convert `rgb`, a `width`x`height` RGB24 frame, to `format`. YUV formats
+/// take the mean chroma of each 2x2 block.
+pub fn from_rgb24(format: PixelFormat, width: u32, height: u32, rgb: &[u8]) -> Result<Vec<u8>> {
+    check_len(PixelFormat::Rgb24, width, height, rgb)?;
+    let len = format.frame_len(width, height);
+    let mut data = vec![0; len];
+    match format {
+        PixelFormat::Rgb24 => data.copy_from_slice(&rgb[..len]),
+        PixelFormat::Bgra32 => {
+            for (bgra, rgb) in data.chunks_exact_mut(4).zip(rgb.chunks_exact(3)) {
+                bgra.copy_from_slice(&[rgb[2], rgb[1], rgb[0], 0xff]);
+            }
+        }
+        PixelFormat::Rgba64 => {
+            for (rgba, rgb) in data.chunks_exact_mut(8).zip(rgb.chunks_exact(3)) {
+                for (channel, value) in rgba.chunks_exact_mut(2).zip([rgb[0], rgb[1], rgb[2], 0xff]) {
+                    channel.copy_from_slice(&(value as u16 * 257).to_le_bytes());
+                }
+            }
+        }
+        PixelFormat::Nv12 | PixelFormat::I420 => {
+            let planes = format.planes(width, height);
+            let (width, height) = (width as usize, height as usize);
+            for (i, rgb) in rgb.chunks_exact(3).take(width * height).enumerate() {
+                data[i] = rgb_to_yuv(rgb).0;
+            }
+            for cy in 0..height.div_ceil(2) {
+                for cx in 0..width.div_ceil(2) {
+                    // Mean of the block's pixels that lie within the frame
+                    let block: Vec<(u8, u8, u8)> = [(0, 0), (1, 0), (0, 1), (1, 1)]
+                        .iter()
+                        .map(|(dx, dy)| (cx * 2 + dx, cy * 2 + dy))
+                        .filter(|&(x, y)| x < width && y < height)
+                        .map(|(x, y)| rgb_to_yuv(&rgb[(y * width + x) * 3..]))
+                        .collect();
+                    let mean = |channel: fn(&(u8, u8, u8)) -> u8| {
+                        (block.iter().map(|yuv| channel(yuv) as usize).sum::<usize>() / block.len()) as u8
+                    };
+                    let (u, v) = (mean(|yuv| yuv.1), mean(|yuv| yuv.2));
+                    match format {
+                        PixelFormat::Nv12 => {
+                            let at = planes[1].offset + cy * planes[1].stride + cx * 2;
+                            data[at] = u;
+                            data[at + 1] = v;
+                        }
+                        _ => {
+                            data[planes[1].offset + cy * planes[1].stride + cx] = u;
+                            data[planes[2].offset + cy * planes[2].stride + cx] = v;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(data)
+}
+
+fn check_len(format: PixelFormat, width: u32, height: u32, data: &[u8]) -> Result<()> {
+    let expected = format.frame_len(width, height);
+    if data.len() < expected {
+        anyhow::bail!("{:?} frame of {}x{} needs {} bytes, not {}", format, width, height, expected, data.len());
+    }
+    Ok(())
+}
+
+// U and V of the 2x2 block at (`cx`, `cy`)
+fn chroma_at(format: PixelFormat, planes: &[Plane], data: &[u8], cx: usize, cy: usize) -> (u8, u8) {
+    match format {
+        PixelFormat::Nv12 => {
+            let at = planes[1].offset + cy * planes[1].stride + cx * 2;
+            (data[at], data[at + 1])
+        }
+        _ => (
+            data[planes[1].offset + cy * planes[1].stride + cx],
+            data[planes[2].offset + cy * planes[2].stride + cx],
+        ),
+    }
+}
+
+// BT.601, limited range, as video coders expect
+fn yuv_to_rgb(y: u8, u: u8, v: u8) -> [u8; 3] {
+    let (c, d, e) = (y as i32 - 16, u as i32 - 128, v as i32 - 128);
+    let clamp = |value: i32| ((value + 128) >> 8).clamp(0, 255) as u8;
+    [clamp(298 * c + 409 * e), clamp(298 * c - 100 * d - 208 * e), clamp(298 * c + 516 * d)]
+}
+
+fn rgb_to_yuv(rgb: &[u8]) -> (u8, u8, u8) {
+    let (r, g, b) = (rgb[0] as i32, rgb[1] as i32, rgb[2] as i32);
+    (
+        (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8,
+        (((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128) as u8,
+        (((112 * r - 94 * g - 18 * b + 128) >> 8) + 128) as u8,
+    )
+}
+
+impl Frame {
+    /// Where each plane of the pixels lies in `data`
+    pub fn planes(&self) -> Vec<Plane> {
+        self.format.planes(self.width, self.height)
+    }
+
+    /// Fail unless the pixels are RGB24, for code that works in nothing else
+    pub fn expect_rgb24(&self) -> Result<()> {
+        if self.format != PixelFormat::Rgb24 {
+            anyhow::bail!("Frame {} is {:?}, not RGB24", self.id, self.format);
+        }
+        Ok(())
+    }
+
+    /// The frame in RGB24, sharing the pixels if it already is
+    pub fn into_rgb24(self) -> Result<Frame> {
+        if self.format == PixelFormat::Rgb24 {
+            return Ok(self);
+        }
+        let data = to_rgb24(self.format, self.width, self.height, &self.data)?;
+        Ok(Frame { format: PixelFormat::Rgb24, data: Bytes::from(data), ..self })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_formats_convert_to_and_from_rgb24() {
+        // Odd sizes leave YUV formats a partial chroma block
+        let (width, height) = (5, 3);
+        let rgb: Vec<u8> = (0..height)
+            .flat_map(|y| (0..width).flat_map(move |x| [40 + x as u8 * 10, 100 + y as u8 * 10, 200 - x as u8 * 10]))
+            .collect();
+        for format in [PixelFormat::Rgb24, PixelFormat::Bgra32, PixelFormat::Nv12, PixelFormat::I420, PixelFormat::Rgba64] {
+            let converted = from_rgb24(format, width, height, &rgb).unwrap();
+            assert_eq!(converted.len(), format.frame_len(width, height));
+            let back = to_rgb24(format, width, height, &converted).unwrap();
+            // YUV shares chroma between neighbours and rounds
+            let tolerance = if format.bytes_per_pixel().is_some() { 0 } else { 12 };
+            for (a, b) in rgb.iter().zip(&back) {
+                assert!(a.abs_diff(*b) <= tolerance, "{:?}: {} became {}", format, a, b);
+            }
+        }
+        assert!(to_rgb24(PixelFormat::I420, width, height, &rgb[..10]).is_err());
+    }
+}
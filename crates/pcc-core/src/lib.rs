@@ -0,0 +1,21 @@
+//! The parts of PixelChangeCheck with no platform dependencies: frames and
+//! the changes between them, the capture and detection traits, the PCC
+//! detector, recycled frame buffers, quality adaptation, and reading frame
+//! messages and applying them. Enough to show what a host shares, e.g. in a
+//! browser, and builds for `wasm32-unknown-unknown`. Also the session clock
+//! and bitmap font that capture, networking and the viewer all use.
+
+pub mod clock;
+pub mod delta;
+pub mod font;
+pub mod format;
+pub mod pool;
+pub mod quality;
+pub mod types;
+pub mod wire;
+
+mod detector;
+pub use clock::SessionClock;
+pub use detector::*;
+pub use format::{PixelFormat, Plane};
+pub use types::*;
@@ -0,0 +1,139 @@
+use bytes::{Bytes, BytesMut};
+use std::sync::{Arc, Mutex};
+
+/// Idle buffers a pool keeps by default: enough for the frame being
+/// captured, the one it's diffed against and one in flight
+pub const DEFAULT_POOL_BUFFERS: usize = 4;
+
+/// Recycles frame-sized pixel buffers, so capture, conversion and decode
+/// reuse the buffers of frames that are done with rather than allocating a
+/// new one for each frame.
+///
+/// Buffers come back through `recycle` once nothing else shares them. The
+/// pool holds buffers of one size, that of the last `take`, and drops the
+/// others when the size changes, e.g. because the resolution did.
+/// Cloning gives another handle to the same pool.
+#[derive(Debug, Clone)]
+pub struct FramePool {
+    inner: Arc<Mutex<PoolState>>,
+}
+
+#[derive(Debug)]
+struct PoolState {
+    buffer_len: usize,
+    capacity: usize,
+    free: Vec<BytesMut>,
+    stats: PoolStats,
+}
+
+/// What a `FramePool` has done so far
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Buffers idle in the pool
+    pub pooled: usize,
+    /// Most buffers idle in the pool at once
+    pub high_water: usize,
+    /// Buffers handed out that had to be allocated
+    pub allocations: u64,
+    /// Buffers handed out that were recycled
+    pub reuses: u64,
+    /// Buffers given back that the pool couldn't keep: still shared, the
+    /// wrong size, or with the pool full
+    pub discarded: u64,
+}
+
+impl Default for FramePool {
+    fn default() -> Self {
+        Self::new(DEFAULT_POOL_BUFFERS)
+    }
+}
+
+impl FramePool {
+    /// A pool keeping up to `capacity` idle buffers
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(PoolState {
+                buffer_len: 0,
+                capacity,
+                free: Vec::with_capacity(capacity),
+                stats: PoolStats::default(),
+            })),
+        }
+    }
+
+    /// A buffer of `len` bytes, recycled if one is idle. A recycled
+    /// buffer still holds an old frame's pixels, so callers overwrite all
+    /// of it.
+    pub fn take(&self, len: usize) -> BytesMut {
+        let mut state = self.inner.lock().unwrap();
+        if state.buffer_len != len {
+            state.buffer_len = len;
+            state.free.clear();
+        }
+        let buffer = state.free.pop();
+        state.stats.pooled = state.free.len();
+        match buffer {
+            Some(buffer) => {
+                state.stats.reuses += 1;
+                buffer
+            }
+            None => {
+                state.stats.allocations += 1;
+                BytesMut::zeroed(len)
+            }
+        }
+    }
+
+    /// Give back a frame's pixels to be reused. Returns whether the pool
+    /// kept them, which it doesn't while anything else still shares them.
+    pub fn recycle(&self, data: Bytes) -> bool {
+        let mut state = self.inner.lock().unwrap();
+        if data.len() != state.buffer_len || state.free.len() >= state.capacity {
+            state.stats.discarded += 1;
+            return false;
+        }
+        match data.try_into_mut() {
+            Ok(buffer) => {
+                state.free.push(buffer);
+                state.stats.pooled = state.free.len();
+                state.stats.high_water = state.stats.high_water.max(state.free.len());
+                true
+            }
+            Err(_) => {
+                state.stats.discarded += 1;
+                false
+            }
+        }
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        self.inner.lock().unwrap().stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pool_reuses_buffers_nobody_shares() {
+        let pool = FramePool::new(2);
+        let mut buffer = pool.take(12);
+        buffer.fill(7);
+        let pixels = buffer.freeze();
+        let shared = pixels.clone();
+        assert!(!pool.recycle(pixels));
+
+        assert!(pool.recycle(shared));
+        let reused = pool.take(12);
+        assert_eq!(&reused[..], &[7; 12]);
+        let stats = pool.stats();
+        assert_eq!((stats.allocations, stats.reuses, stats.discarded), (1, 1, 1));
+        assert_eq!((stats.pooled, stats.high_water), (0, 1));
+
+        // A new size drops the old buffers
+        assert!(pool.recycle(reused.freeze()));
+        assert_eq!(pool.take(6).len(), 6);
+        assert_eq!(pool.stats().allocations, 2);
+    }
+}
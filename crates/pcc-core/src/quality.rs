@@ -0,0 +1,276 @@
+use crate::types::QualityConfig;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+use std::time::Duration;
+
+// Lowest frame rate adaptation drops to
+const MIN_FPS: u32 = 5;
+// Highest change detection threshold adaptation raises to
+const MAX_THRESHOLD: u8 = 30;
+const THRESHOLD_STEP: u8 = 3;
+// Packet loss above which the session counts as congested
+const LOSS_THRESHOLD: f64 = 0.02;
+// Share of a frame interval diffing and coding may take before the host
+// counts as falling behind, and below which it has room to do more
+const BUSY_SHARE: f64 = 0.8;
+const IDLE_SHARE: f64 = 0.5;
+// Share of the bitrate in use below which quality steps back up
+const HEADROOM: f64 = 0.6;
+// Bitrate cut on loss, and the share of the estimate added back otherwise
+const BITRATE_BACKOFF: f64 = 0.85;
+const BITRATE_RECOVERY: f64 = 0.05;
+
+/// What quality adaptation gives up first when the host or network can't
+/// keep up
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum QualityPolicy {
+    /// Drop the frame rate before ignoring subtle changes, keeping text
+    /// crisp
+    #[default]
+    PreferSharpness,
+    /// Ignore subtle changes before dropping the frame rate, keeping
+    /// movement smooth
+    PreferMotion,
+    /// Keep the configured frame rate and threshold
+    Fixed,
+}
+
+impl FromStr for QualityPolicy {
+    type Err = String;
+
+    fn from_str(name: &str) -> std::result::Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "prefer-sharpness" | "sharpness" => Ok(QualityPolicy::PreferSharpness),
+            "prefer-motion" | "motion" => Ok(QualityPolicy::PreferMotion),
+            "fixed" => Ok(QualityPolicy::Fixed),
+            other => Err(format!(
+                "Unknown quality policy {:?} (supported: prefer-sharpness, prefer-motion, fixed)",
+                other
+            )),
+        }
+    }
+}
+
+/// How the session has been doing since the last update
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct QualityStats {
+    /// Frames captured per second
+    pub capture_fps: f32,
+    /// Mean time to diff a frame, code it and hand it to the network
+    pub encode_time: Duration,
+    /// Bits per second sent
+    pub bitrate: u64,
+    /// Bits per second the network is estimated to carry for video, or 0
+    /// before there's an estimate
+    pub available_bitrate: u64,
+    /// Fraction of packets lost
+    pub loss: f64,
+}
+
+/// Steers the frame rate, change detection threshold and video bitrate
+/// from how capture, encoding and the network are keeping up, within the
+/// configured quality settings
+#[derive(Debug, Clone)]
+pub struct QualityController {
+    // The settings adaptation starts from and never goes beyond
+    configured: QualityConfig,
+    current: QualityConfig,
+    bitrate: u64,
+    // Bits per second video never goes above, whatever the estimate
+    bitrate_cap: Option<u64>,
+}
+
+impl QualityController {
+    pub fn new(config: QualityConfig) -> Self {
+        Self {
+            configured: config,
+            current: config,
+            bitrate: 0,
+            bitrate_cap: None,
+        }
+    }
+
+    /// Start over from new settings, e.g. from a reloaded config
+    pub fn reconfigure(&mut self, config: QualityConfig) {
+        self.configured = config;
+        self.current = config;
+    }
+
+    /// Change the JPEG quality, 0.0-1.0, e.g. from a status display
+    pub fn set_quality(&mut self, quality: f32) {
+        self.configured.quality = quality.clamp(0.0, 1.0);
+        self.current.quality = self.configured.quality;
+    }
+
+    /// The settings frames should be captured and diffed with now
+    pub fn config(&self) -> QualityConfig {
+        self.current
+    }
+
+    /// Bits per second video should stay within, or 0 for no limit
+    pub fn bitrate(&self) -> u64 {
+        self.bitrate
+    }
+
+    /// Keep video within `cap` bits per second from now on, e.g. once a
+    /// data quota is used up. Frame rate and detail adapt to fit, unless
+    /// the policy is fixed.
+    pub fn limit_bitrate(&mut self, cap: u64) {
+        self.bitrate_cap = Some(cap);
+    }
+
+    /// Take in the latest stats, returning the new settings if they changed
+    pub fn update(&mut self, stats: &QualityStats) -> Option<QualityConfig> {
+        self.adapt_bitrate(stats);
+        // Nothing to go on until frames have been captured
+        if self.configured.policy == QualityPolicy::Fixed || stats.capture_fps <= 0.0 {
+            return None;
+        }
+
+        let frame_time = 1.0 / self.current.target_fps.max(1) as f64;
+        let encode_share = stats.encode_time.as_secs_f64() / frame_time;
+        let behind =
+            encode_share > BUSY_SHARE || (stats.capture_fps as f64) < self.current.target_fps as f64 * BUSY_SHARE;
+        let over_budget = self.bitrate > 0 && stats.bitrate > self.bitrate;
+        let constrained = behind || over_budget || stats.loss > LOSS_THRESHOLD;
+        let headroom = !constrained
+            && encode_share < IDLE_SHARE
+            && (self.bitrate == 0 || (stats.bitrate as f64) < self.bitrate as f64 * HEADROOM);
+
+        // Steps to take in turn until one changes something
+        type Step = fn(&mut QualityController) -> bool;
+        let (give_up, restore): ([Step; 2], [Step; 2]) = match self.configured.policy {
+            QualityPolicy::PreferSharpness => {
+                ([Self::lower_fps, Self::raise_threshold], [Self::lower_threshold, Self::raise_fps])
+            }
+            QualityPolicy::PreferMotion => {
+                ([Self::raise_threshold, Self::lower_fps], [Self::raise_fps, Self::lower_threshold])
+            }
+            QualityPolicy::Fixed => return None,
+        };
+        let steps = match (constrained, headroom) {
+            (true, _) => give_up,
+            (false, true) => restore,
+            (false, false) => return None,
+        };
+        steps.iter().any(|step| step(self)).then_some(self.current)
+    }
+
+    // Follow the estimate, backing off further while packets are lost
+    fn adapt_bitrate(&mut self, stats: &QualityStats) {
+        let available = match self.bitrate_cap {
+            Some(cap) if stats.available_bitrate == 0 => cap,
+            Some(cap) => stats.available_bitrate.min(cap),
+            None => stats.available_bitrate,
+        };
+        // Start from the first estimate
+        let bitrate = if self.bitrate == 0 { available } else { self.bitrate.min(available) };
+        self.bitrate = if available == 0 || self.configured.policy == QualityPolicy::Fixed {
+            available
+        } else if stats.loss > LOSS_THRESHOLD {
+            (bitrate as f64 * BITRATE_BACKOFF) as u64
+        } else {
+            (bitrate as f64 + available as f64 * BITRATE_RECOVERY).min(available as f64) as u64
+        };
+    }
+
+    // Each step returns whether it changed anything
+
+    fn lower_fps(&mut self) -> bool {
+        let fps = self.current.target_fps;
+        self.current.target_fps = (fps * 4 / 5).max(MIN_FPS.min(self.configured.target_fps));
+        self.current.target_fps != fps
+    }
+
+    fn raise_fps(&mut self) -> bool {
+        let fps = self.current.target_fps;
+        self.current.target_fps = (fps + (fps / 5).max(1)).min(self.configured.target_fps);
+        self.current.target_fps != fps
+    }
+
+    fn raise_threshold(&mut self) -> bool {
+        let threshold = self.current.threshold;
+        self.current.threshold = threshold.saturating_add(THRESHOLD_STEP).min(MAX_THRESHOLD.max(self.configured.threshold));
+        self.current.threshold != threshold
+    }
+
+    fn lower_threshold(&mut self) -> bool {
+        let threshold = self.current.threshold;
+        self.current.threshold = threshold.saturating_sub(THRESHOLD_STEP).max(self.configured.threshold);
+        self.current.threshold != threshold
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn congested() -> QualityStats {
+        QualityStats {
+            capture_fps: 30.0,
+            encode_time: Duration::from_millis(5),
+            bitrate: 8_000_000,
+            available_bitrate: 4_000_000,
+            loss: 0.05,
+        }
+    }
+
+    fn idle() -> QualityStats {
+        QualityStats {
+            capture_fps: 30.0,
+            encode_time: Duration::from_millis(1),
+            bitrate: 100_000,
+            available_bitrate: 4_000_000,
+            loss: 0.0,
+        }
+    }
+
+    fn controller(policy: QualityPolicy) -> QualityController {
+        QualityController::new(QualityConfig { policy, ..QualityConfig::default() })
+    }
+
+    #[test]
+    fn test_policies_give_up_different_things_first() {
+        let mut sharp = controller(QualityPolicy::PreferSharpness);
+        let adapted = sharp.update(&congested()).unwrap();
+        assert!(adapted.target_fps < 30);
+        assert_eq!(adapted.threshold, QualityConfig::default().threshold);
+        assert!(sharp.bitrate() < 4_000_000);
+
+        let mut motion = controller(QualityPolicy::PreferMotion);
+        let adapted = motion.update(&congested()).unwrap();
+        assert_eq!(adapted.target_fps, 30);
+        assert!(adapted.threshold > QualityConfig::default().threshold);
+
+        let mut fixed = controller(QualityPolicy::Fixed);
+        assert_eq!(fixed.update(&congested()), None);
+        assert_eq!(fixed.bitrate(), 4_000_000);
+    }
+
+    #[test]
+    fn test_recovers_to_configured_settings() {
+        let mut controller = controller(QualityPolicy::PreferSharpness);
+        for _ in 0..20 {
+            controller.update(&congested());
+        }
+        let degraded = controller.config();
+        assert_eq!(degraded.target_fps, MIN_FPS);
+        assert!(degraded.threshold > QualityConfig::default().threshold);
+
+        for _ in 0..40 {
+            controller.update(&QualityStats { capture_fps: controller.config().target_fps as f32, ..idle() });
+        }
+        assert_eq!(controller.config(), QualityConfig::default());
+        assert_eq!(controller.bitrate(), 4_000_000);
+    }
+
+    #[test]
+    fn test_bitrate_cap_holds_whatever_the_estimate() {
+        let mut controller = controller(QualityPolicy::PreferSharpness);
+        controller.limit_bitrate(200_000);
+        let adapted = controller.update(&QualityStats { bitrate: 1_000_000, ..idle() }).unwrap();
+        assert_eq!(controller.bitrate(), 200_000);
+        assert!(adapted.target_fps < 30);
+    }
+}
@@ -0,0 +1,234 @@
+use crate::format::PixelFormat;
+use crate::pool::FramePool;
+use crate::quality::QualityPolicy;
+use anyhow::Result;
+use async_trait::async_trait;
+use bytes::Bytes;
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Frame {
+    pub id: u64,
+    pub timestamp: SystemTime,
+    pub width: u32,
+    pub height: u32,
+    /// How `data` is laid out
+    pub format: PixelFormat,
+    /// The pixels, row-major, RGB24 unless `format` says otherwise.
+    /// Shared, so cloning a frame or slicing its pixels never copies them.
+    pub data: Bytes,
+}
+
+impl Frame {
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        Ok(bincode::serialize(self)?)
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        Ok(bincode::deserialize(data)?)
+    }
+
+    /// Like `decode`, with the pixels copied into a buffer from `pool`
+    pub fn decode_pooled(data: &[u8], pool: &FramePool) -> Result<Self> {
+        let encoded: EncodedFrame = bincode::deserialize(data)?;
+        let mut pixels = pool.take(encoded.data.len());
+        pixels.copy_from_slice(encoded.data);
+        Ok(Self {
+            id: encoded.id,
+            timestamp: encoded.timestamp,
+            width: encoded.width,
+            height: encoded.height,
+            format: encoded.format,
+            data: pixels.freeze(),
+        })
+    }
+}
+
+// A `Frame` as encoded, its pixels still in the message
+#[derive(Deserialize)]
+struct EncodedFrame<'a> {
+    id: u64,
+    timestamp: SystemTime,
+    width: u32,
+    height: u32,
+    format: PixelFormat,
+    data: &'a [u8],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PixelChange {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+/// An axis-aligned rectangle in frame pixel coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub fn new(x: u32, y: u32, width: u32, height: u32) -> Self {
+        Self { x, y, width, height }
+    }
+
+    /// Check whether the rectangle lies entirely within a `width`x`height` frame
+    pub fn fits_within(&self, width: u32, height: u32) -> bool {
+        self.x.checked_add(self.width).is_some_and(|right| right <= width)
+            && self.y.checked_add(self.height).is_some_and(|bottom| bottom <= height)
+    }
+}
+
+/// A region of the current frame that can be reproduced by copying pixels
+/// from elsewhere in the previous frame (e.g. after scrolling)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CopyRect {
+    pub src_x: u32,
+    pub src_y: u32,
+    pub dst_rect: Rect,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FrameUpdate {
+    pub frame_id: u64,
+    pub timestamp: SystemTime,
+    pub changes: Vec<PixelChange>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QualityConfig {
+    pub target_fps: u32,
+    pub max_fps: u32,
+    pub quality: f32,          // 0.0-1.0
+    pub compression_level: u8,  // 0-9
+    /// Smallest difference in a color channel counted as a change
+    pub threshold: u8,
+    /// What adaptation gives up first when the session can't keep up
+    pub policy: QualityPolicy,
+}
+
+impl Default for QualityConfig {
+    fn default() -> Self {
+        Self {
+            target_fps: 30,
+            max_fps: 60,
+            quality: 0.8,
+            compression_level: 6,
+            threshold: 5,
+            policy: QualityPolicy::default(),
+        }
+    }
+}
+
+/// Color space of RGB pixel data. Both use the sRGB transfer curve and a
+/// D65 white point; they differ in their primaries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ColorSpace {
+    #[default]
+    Srgb,
+    /// Wide gamut used by most recent Apple displays
+    DisplayP3,
+}
+
+/// Trait for implementing pixel change detection
+pub trait PixelChangeDetector {
+    /// Detect changes between two frames
+    fn detect_changes(&self, previous: &Frame, current: &Frame) -> Result<Vec<PixelChange>>;
+    
+    /// Configure the detector
+    fn configure(&mut self, config: QualityConfig) -> Result<()>;
+
+    /// Find a block of `current` that is `previous` moved by up to
+    /// `max_offset` rows, e.g. scrolled, to send as a copy rather than as
+    /// pixels. Detectors that don't look for movement find none.
+    fn detect_scroll(&self, _previous: &Frame, _current: &Frame, _max_offset: u32) -> Option<CopyRect> {
+        None
+    }
+}
+
+/// Trait for frame capture implementations
+pub trait FrameCapture {
+    /// Capture a new frame
+    fn capture_frame(&self) -> Result<Frame>;
+    
+    /// Get supported capture configurations
+    fn supported_configs(&self) -> Vec<QualityConfig>;
+    
+    /// Configure the capture
+    fn configure(&mut self, config: QualityConfig) -> Result<()>;
+
+    /// Color space of captured frames
+    fn color_space(&self) -> ColorSpace {
+        ColorSpace::Srgb
+    }
+}
+
+/// `PixelChangeDetector` for detectors that wait on other work, e.g. a GPU,
+/// rather than blocking the thread. `BlockingDetector` adapts the
+/// synchronous kind.
+#[async_trait]
+pub trait AsyncPixelChangeDetector: Send + Sync {
+    /// Detect changes between two frames
+    async fn detect_changes(&self, previous: &Frame, current: &Frame) -> Result<Vec<PixelChange>>;
+
+    /// Configure the detector
+    fn configure(&mut self, config: QualityConfig) -> Result<()>;
+}
+
+/// `FrameCapture` for captures that wait on the OS, e.g. for a frame to be
+/// delivered, rather than blocking the thread. `BlockingCapture` adapts the
+/// synchronous kind.
+#[async_trait]
+pub trait AsyncFrameCapture: Send + Sync {
+    /// Capture a new frame
+    async fn capture_frame(&self) -> Result<Frame>;
+
+    /// Get supported capture configurations
+    fn supported_configs(&self) -> Vec<QualityConfig>;
+
+    /// Configure the capture
+    fn configure(&mut self, config: QualityConfig) -> Result<()>;
+
+    /// Color space of captured frames
+    fn color_space(&self) -> ColorSpace {
+        ColorSpace::Srgb
+    }
+}
+
+#[async_trait]
+impl<T: AsyncPixelChangeDetector + ?Sized> AsyncPixelChangeDetector for Box<T> {
+    async fn detect_changes(&self, previous: &Frame, current: &Frame) -> Result<Vec<PixelChange>> {
+        (**self).detect_changes(previous, current).await
+    }
+
+    fn configure(&mut self, config: QualityConfig) -> Result<()> {
+        (**self).configure(config)
+    }
+}
+
+#[async_trait]
+impl<T: AsyncFrameCapture + ?Sized> AsyncFrameCapture for Box<T> {
+    async fn capture_frame(&self) -> Result<Frame> {
+        (**self).capture_frame().await
+    }
+
+    fn supported_configs(&self) -> Vec<QualityConfig> {
+        (**self).supported_configs()
+    }
+
+    fn configure(&mut self, config: QualityConfig) -> Result<()> {
+        (**self).configure(config)
+    }
+
+    fn color_space(&self) -> ColorSpace {
+        (**self).color_space()
+    }
+} 
\ No newline at end of file
@@ -0,0 +1,135 @@
+//! How messages are framed on the wire, and the frame messages a viewer
+//! needs to keep its picture current. The host sends keyframes whole, as
+//! `Frame::encode` codes them, on a stream of their own; everything else
+//! is a framed message.
+
+use crate::types::{ColorSpace, FrameUpdate, Rect};
+use anyhow::Result;
+use serde::Serialize;
+
+// Protocol version for compatibility checking
+pub const PROTOCOL_VERSION: u8 = 2;
+
+// Maximum message sizes
+pub const MAX_FRAME_SIZE: usize = 1024 * 1024 * 64; // 64MB, enough for 4K RGBA
+pub const MAX_MESSAGE_SIZE: usize = 1024 * 64; // 64KB
+
+// Version byte plus little-endian length prefix
+pub const HEADER_SIZE: usize = 5;
+
+/// Where the frame messages come in the full message enum, which bincode
+/// writes ahead of their fields. The full enum lives with the networking,
+/// whose tests check its variants against these.
+pub mod tag {
+    pub const FRAME_UPDATE: u32 = 2;
+    pub const COPY_RECT: u32 = 4;
+    pub const KEEP_ALIVE: u32 = 26;
+    pub const REQUEST_KEYFRAME: u32 = 27;
+    pub const COLOR_SPACE: u32 = 30;
+}
+use tag::*;
+
+/// Frame a message body coded with bincode: a version byte, then its
+/// length, then the body
+pub fn write_message(body: &[u8]) -> Result<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(HEADER_SIZE + body.len());
+    bytes.extend_from_slice(&write_header(body.len())?);
+    bytes.extend_from_slice(body);
+    Ok(bytes)
+}
+
+/// The header `write_message` puts ahead of a body of `len` bytes, for
+/// writing the body separately
+pub fn write_header(len: usize) -> Result<[u8; HEADER_SIZE]> {
+    if len > MAX_MESSAGE_SIZE {
+        anyhow::bail!("Message too large: {} bytes", len);
+    }
+    let mut header = [PROTOCOL_VERSION; HEADER_SIZE];
+    header[1..].copy_from_slice(&(len as u32).to_le_bytes());
+    Ok(header)
+}
+
+/// The body of a framed message, checking its version and length
+pub fn read_message(bytes: &[u8]) -> Result<&[u8]> {
+    if bytes.len() < HEADER_SIZE {
+        anyhow::bail!("Message too short");
+    }
+    let version = bytes[0];
+    if version != PROTOCOL_VERSION {
+        anyhow::bail!("Protocol version mismatch: expected {}, got {}", PROTOCOL_VERSION, version);
+    }
+    let len = u32::from_le_bytes([bytes[1], bytes[2], bytes[3], bytes[4]]) as usize;
+    if len > MAX_MESSAGE_SIZE {
+        anyhow::bail!("Message too large: {} bytes", len);
+    }
+    let body = &bytes[HEADER_SIZE..];
+    if body.len() < len {
+        anyhow::bail!("Message truncated: expected {} bytes, got {}", len, body.len());
+    }
+    Ok(&body[..len])
+}
+
+/// The messages that change what a viewer shows, and the two it sends
+/// back to keep the picture coming, read and written exactly as the full
+/// protocol does. Enough for a viewer without the rest of the protocol,
+/// e.g. in a browser.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FrameMessage {
+    /// Pixel changes since the previous frame, split across `parts`
+    /// messages; present once the last part is applied
+    Update { update: FrameUpdate, part: u32, parts: u32 },
+    /// Copy a block of the current frame to `dst_rect`, reading from the
+    /// same-sized block at (`src_x`, `src_y`)
+    CopyRect { src_x: u32, src_y: u32, dst_rect: Rect },
+    /// Color space of the host's frames; sRGB until announced
+    ColorSpace(ColorSpace),
+    KeepAlive,
+    /// Ask the host to send a full frame instead of deltas
+    RequestKeyframe,
+    /// Any other message, by its place in the full protocol
+    Other(u32),
+}
+
+impl FrameMessage {
+    /// Read a framed message
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        let body = read_message(bytes)?;
+        if body.len() < 4 {
+            anyhow::bail!("Message has no type");
+        }
+        let (tag, fields) = (u32::from_le_bytes([body[0], body[1], body[2], body[3]]), &body[4..]);
+        Ok(match tag {
+            FRAME_UPDATE => {
+                let (update, part, parts) = bincode::deserialize(fields)?;
+                Self::Update { update, part, parts }
+            }
+            COPY_RECT => {
+                let (src_x, src_y, dst_rect) = bincode::deserialize(fields)?;
+                Self::CopyRect { src_x, src_y, dst_rect }
+            }
+            COLOR_SPACE => Self::ColorSpace(bincode::deserialize(fields)?),
+            KEEP_ALIVE => Self::KeepAlive,
+            REQUEST_KEYFRAME => Self::RequestKeyframe,
+            other => Self::Other(other),
+        })
+    }
+
+    /// Write the message framed for sending. `Other` can't be written,
+    /// having only its type.
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        fn body(tag: u32, fields: &impl Serialize) -> Result<Vec<u8>> {
+            let mut body = tag.to_le_bytes().to_vec();
+            bincode::serialize_into(&mut body, fields)?;
+            Ok(body)
+        }
+        let body = match self {
+            Self::Update { update, part, parts } => body(FRAME_UPDATE, &(update, part, parts))?,
+            Self::CopyRect { src_x, src_y, dst_rect } => body(COPY_RECT, &(src_x, src_y, dst_rect))?,
+            Self::ColorSpace(space) => body(COLOR_SPACE, space)?,
+            Self::KeepAlive => body(KEEP_ALIVE, &())?,
+            Self::RequestKeyframe => body(REQUEST_KEYFRAME, &())?,
+            Self::Other(tag) => anyhow::bail!("Message {} can't be written without its fields", tag),
+        };
+        write_message(&body)
+    }
+}
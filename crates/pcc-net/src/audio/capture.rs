@@ -0,0 +1,254 @@
+use super::{AudioFormat, AudioSource, DeviceSelection};
+use crate::network::SessionClock;
+use anyhow::Result;
+use std::task::{Context, Poll};
+use std::time::SystemTime;
+use tokio::sync::mpsc;
+
+// Captured buffers queued for the encoder before the newest are dropped
+#[cfg_attr(not(feature = "audio"), allow(dead_code))]
+const CAPTURE_QUEUE: usize = 64;
+
+/// Samples delivered by a capture device, converted to the capture format
+#[derive(Debug, Clone)]
+pub struct CapturedAudio {
+    /// When the first sample was captured
+    pub pts: SystemTime,
+    /// Interleaved samples
+    pub samples: Vec<f32>,
+}
+
+/// Captures audio on the host: the microphone, or the system loopback
+/// through WASAPI on Windows, a PipeWire or PulseAudio monitor source on
+/// Linux, or a virtual loopback device on macOS. Capture runs for as long
+/// as this is alive.
+pub struct AudioCapture {
+    source: AudioSource,
+    format: AudioFormat,
+    rx: mpsc::Receiver<CapturedAudio>,
+    #[cfg(feature = "audio")]
+    tx: mpsc::Sender<CapturedAudio>,
+    #[cfg(feature = "audio")]
+    clock: SessionClock,
+    #[cfg(feature = "audio")]
+    stream: cpal::Stream,
+}
+
+impl AudioCapture {
+    /// Start capturing everything the host plays from the default loopback
+    /// device, converted to `format` and stamped by `clock`
+    pub fn system(format: AudioFormat, clock: SessionClock) -> Result<Self> {
+        Self::open(AudioSource::System, &DeviceSelection::Default, format, clock)
+    }
+
+    /// Start capturing the default microphone, converted to `format` and
+    /// stamped by `clock`
+    pub fn microphone(format: AudioFormat, clock: SessionClock) -> Result<Self> {
+        Self::open(AudioSource::Microphone, &DeviceSelection::Default, format, clock)
+    }
+
+    /// Start capturing `source` from the selected device
+    #[cfg(feature = "audio")]
+    pub fn open(source: AudioSource, device: &DeviceSelection, format: AudioFormat, clock: SessionClock) -> Result<Self> {
+        let (tx, rx) = mpsc::channel(CAPTURE_QUEUE);
+        let stream = start_stream(source, device, format, clock, tx.clone())?;
+        Ok(Self {
+            source,
+            format,
+            rx,
+            tx,
+            clock,
+            stream,
+        })
+    }
+
+    /// Audio capture needs the `audio` feature
+    #[cfg(not(feature = "audio"))]
+    pub fn open(_source: AudioSource, _device: &DeviceSelection, _format: AudioFormat, _clock: SessionClock) -> Result<Self> {
+        anyhow::bail!("Built without audio capture support (enable the `audio` feature)")
+    }
+
+    /// Move capture to another device mid-session. The stream carries on
+    /// uninterrupted for the viewer; if the new device fails to open, the
+    /// old one keeps capturing.
+    #[cfg(feature = "audio")]
+    pub fn switch_device(&mut self, device: &DeviceSelection) -> Result<()> {
+        self.stream = start_stream(self.source, device, self.format, self.clock, self.tx.clone())?;
+        Ok(())
+    }
+
+    /// Audio capture needs the `audio` feature
+    #[cfg(not(feature = "audio"))]
+    pub fn switch_device(&mut self, _device: &DeviceSelection) -> Result<()> {
+        anyhow::bail!("Built without audio capture support (enable the `audio` feature)")
+    }
+
+    pub fn source(&self) -> AudioSource {
+        self.source
+    }
+
+    /// Format of the captured samples
+    pub fn format(&self) -> AudioFormat {
+        self.format
+    }
+
+    /// Wait for the next captured buffer; `None` once the device is gone
+    pub async fn next(&mut self) -> Option<CapturedAudio> {
+        self.rx.recv().await
+    }
+
+    /// Poll for the next captured buffer, for waiting on several captures
+    /// at once
+    pub fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<CapturedAudio>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+#[cfg(feature = "audio")]
+fn start_stream(
+    source: AudioSource,
+    selection: &DeviceSelection,
+    format: AudioFormat,
+    clock: SessionClock,
+    tx: mpsc::Sender<CapturedAudio>,
+) -> Result<cpal::Stream> {
+    use super::device::{open_device, AudioDeviceKind};
+    use anyhow::Context;
+    use cpal::traits::{DeviceTrait, StreamTrait};
+
+    let kind = match source {
+        AudioSource::System => AudioDeviceKind::Loopback,
+        AudioSource::Microphone => AudioDeviceKind::Input,
+    };
+    let (device, config) = open_device(kind, selection)?;
+    let name = device.name().unwrap_or_else(|_| "unknown device".to_string());
+    let device_format = AudioFormat {
+        sample_rate: config.sample_rate().0,
+        channels: config.channels(),
+    };
+
+    let mut remixer = Remixer::new(device_format, format);
+    let stream = device
+        .build_input_stream(
+            &config.config(),
+            move |data: &[f32], _: &cpal::InputCallbackInfo| {
+                // The buffer ends now, so it started its length ago
+                let frames = data.len() / device_format.channels as usize;
+                let pts = clock.now() - device_format.frame_duration() * frames as u32;
+                let samples = remixer.process(data);
+                if tx.try_send(CapturedAudio { pts, samples }).is_err() {
+                    tracing::debug!("Audio encoder falling behind, dropping captured audio");
+                }
+            },
+            |e| tracing::warn!("Audio capture error: {}", e),
+            None,
+        )
+        .with_context(|| format!("Failed to open {} for capture", name))?;
+    stream.play().context("Failed to start audio capture")?;
+
+    tracing::info!(
+        "Capturing {:?} audio from {}: {} Hz, {} channels",
+        source,
+        name,
+        device_format.sample_rate,
+        device_format.channels
+    );
+    Ok(stream)
+}
+
+/// Converts interleaved audio between channel counts and sample rates,
+/// interpolating linearly across buffer boundaries
+#[cfg_attr(not(feature = "audio"), allow(dead_code))]
+#[derive(Debug)]
+pub(crate) struct Remixer {
+    from: AudioFormat,
+    to: AudioFormat,
+    /// Last input frame of the previous buffer, already remixed
+    previous: Option<Vec<f32>>,
+    /// Position of the next output frame, in input frames after `previous`
+    position: f64,
+}
+
+#[cfg_attr(not(feature = "audio"), allow(dead_code))]
+impl Remixer {
+    pub(crate) fn new(from: AudioFormat, to: AudioFormat) -> Self {
+        Self {
+            from,
+            to,
+            previous: None,
+            position: 0.0,
+        }
+    }
+
+    pub(crate) fn process(&mut self, input: &[f32]) -> Vec<f32> {
+        let channels = self.to.channels as usize;
+        let remixed: Vec<f32> = input
+            .chunks_exact(self.from.channels as usize)
+            .flat_map(|frame| self.remix(frame))
+            .collect();
+        if self.from.sample_rate == self.to.sample_rate {
+            return remixed;
+        }
+
+        // Interpolate over the previous buffer's last frame and this one
+        let frames: Vec<&[f32]> = self
+            .previous
+            .iter()
+            .map(|frame| frame.as_slice())
+            .chain(remixed.chunks_exact(channels))
+            .collect();
+        if frames.len() < 2 {
+            self.previous = frames.first().map(|frame| frame.to_vec());
+            return Vec::new();
+        }
+
+        let step = self.from.sample_rate as f64 / self.to.sample_rate as f64;
+        let mut output = Vec::new();
+        while self.position < (frames.len() - 1) as f64 {
+            let index = self.position as usize;
+            let fraction = (self.position - index as f64) as f32;
+            let (a, b) = (frames[index], frames[index + 1]);
+            output.extend(a.iter().zip(b).map(|(a, b)| a + (b - a) * fraction));
+            self.position += step;
+        }
+
+        self.position -= (frames.len() - 1) as f64;
+        self.previous = frames.last().map(|frame| frame.to_vec());
+        output
+    }
+
+    fn remix(&self, frame: &[f32]) -> Vec<f32> {
+        match (frame.len(), self.to.channels as usize) {
+            (from, to) if from == to => frame.to_vec(),
+            (_, 1) => vec![frame.iter().sum::<f32>() / frame.len() as f32],
+            (1, to) => vec![frame[0]; to],
+            // Keep the front channels, padding with silence
+            (_, to) => (0..to).map(|i| frame.get(i).copied().unwrap_or(0.0)).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remixer_converts_channels_and_rate() {
+        let mono = |sample_rate| AudioFormat { sample_rate, channels: 1 };
+        let stereo = |sample_rate| AudioFormat { sample_rate, channels: 2 };
+
+        let mut downmix = Remixer::new(stereo(48_000), mono(48_000));
+        assert_eq!(downmix.process(&[0.2, 0.4, -1.0, 1.0]), vec![0.3, 0.0]);
+
+        // Doubling the rate interpolates between frames, carrying the last
+        // frame into the next buffer
+        let mut upsample = Remixer::new(mono(24_000), stereo(48_000));
+        assert_eq!(upsample.process(&[0.0, 1.0]), vec![0.0, 0.0, 0.5, 0.5]);
+        assert_eq!(upsample.process(&[0.0]), vec![1.0, 1.0, 0.5, 0.5]);
+
+        // Ten buffers of 441 frames come out as ten buffers' worth at 48kHz
+        let mut resample = Remixer::new(mono(44_100), mono(48_000));
+        let frames: usize = (0..10).map(|_| resample.process(&[0.5; 441]).len()).sum();
+        assert!((4799..=4800).contains(&frames), "{}", frames);
+    }
+}
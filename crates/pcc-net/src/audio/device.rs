@@ -0,0 +1,184 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+// Names of devices that record what the system plays: PipeWire and
+// PulseAudio monitor sources, and the usual virtual loopback drivers
+#[cfg_attr(not(feature = "audio"), allow(dead_code))]
+const LOOPBACK_NAMES: &[&str] = &["monitor", "loopback", "blackhole", "soundflower"];
+
+/// What an audio device can be used for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AudioDeviceKind {
+    /// Records sound, e.g. a microphone
+    Input,
+    /// Plays sound
+    Output,
+    /// Records what the system plays, for capturing system audio
+    Loopback,
+}
+
+/// An audio device found on this machine
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AudioDevice {
+    pub name: String,
+    pub kind: AudioDeviceKind,
+    /// Whether the OS uses this device unless told otherwise
+    pub is_default: bool,
+}
+
+/// Which device to use for capture or playback
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum DeviceSelection {
+    /// Whatever the OS currently uses, or the first loopback device found
+    #[default]
+    Default,
+    /// The device with this name, as listed by `list_devices`. A unique
+    /// case-insensitive part of the name also matches.
+    Named(String),
+}
+
+impl DeviceSelection {
+    /// Pick a device from `names`, given the index of the default
+    #[cfg_attr(not(feature = "audio"), allow(dead_code))]
+    pub(crate) fn choose<'a>(&self, names: &'a [String], default: Option<usize>) -> Option<&'a String> {
+        match self {
+            DeviceSelection::Default => default.and_then(|index| names.get(index)),
+            DeviceSelection::Named(wanted) => names.iter().find(|name| *name == wanted).or_else(|| {
+                let wanted = wanted.to_lowercase();
+                let mut matching = names.iter().filter(|name| name.to_lowercase().contains(&wanted));
+                let first = matching.next();
+                first.filter(|_| matching.next().is_none())
+            }),
+        }
+    }
+}
+
+#[cfg_attr(not(feature = "audio"), allow(dead_code))]
+fn is_loopback_name(name: &str) -> bool {
+    let name = name.to_lowercase();
+    LOOPBACK_NAMES.iter().any(|loopback| name.contains(loopback))
+}
+
+/// Every capture, playback and loopback device on this machine
+#[cfg(feature = "audio")]
+pub fn list_devices() -> Result<Vec<AudioDevice>> {
+    use anyhow::Context;
+    use cpal::traits::{DeviceTrait, HostTrait};
+
+    let host = cpal::default_host();
+    let default_name = |device: Option<cpal::Device>| device.and_then(|device| device.name().ok());
+    let default_input = default_name(host.default_input_device());
+    let default_output = default_name(host.default_output_device());
+
+    let mut devices = Vec::new();
+    for device in host.input_devices().context("Failed to list audio inputs")? {
+        let Ok(name) = device.name() else { continue };
+        let kind = if is_loopback_name(&name) {
+            AudioDeviceKind::Loopback
+        } else {
+            AudioDeviceKind::Input
+        };
+        let is_default = default_input.as_ref() == Some(&name);
+        devices.push(AudioDevice { name, kind, is_default });
+    }
+    for device in host.output_devices().context("Failed to list audio outputs")? {
+        let Ok(name) = device.name() else { continue };
+        let is_default = default_output.as_ref() == Some(&name);
+        // WASAPI can record any output device
+        if cfg!(target_os = "windows") {
+            devices.push(AudioDevice {
+                name: name.clone(),
+                kind: AudioDeviceKind::Loopback,
+                is_default,
+            });
+        }
+        devices.push(AudioDevice {
+            name,
+            kind: AudioDeviceKind::Output,
+            is_default,
+        });
+    }
+    Ok(devices)
+}
+
+/// Listing devices needs the `audio` feature
+#[cfg(not(feature = "audio"))]
+pub fn list_devices() -> Result<Vec<AudioDevice>> {
+    anyhow::bail!("Built without audio device support (enable the `audio` feature)")
+}
+
+/// Open the device of `kind` that `selection` picks
+#[cfg(feature = "audio")]
+pub fn open_device(
+    kind: AudioDeviceKind,
+    selection: &DeviceSelection,
+) -> Result<(cpal::Device, cpal::SupportedStreamConfig)> {
+    use anyhow::Context;
+    use cpal::traits::{DeviceTrait, HostTrait};
+
+    let host = cpal::default_host();
+    // WASAPI records whatever an output device plays when opened for input
+    let records_outputs = kind == AudioDeviceKind::Loopback && cfg!(target_os = "windows");
+    let (devices, default): (Vec<cpal::Device>, Option<cpal::Device>) = if kind == AudioDeviceKind::Output || records_outputs {
+        (
+            host.output_devices().context("Failed to list audio outputs")?.collect(),
+            host.default_output_device(),
+        )
+    } else {
+        let devices: Vec<cpal::Device> = host.input_devices().context("Failed to list audio inputs")?.collect();
+        (devices, host.default_input_device())
+    };
+
+    let names: Vec<String> = devices
+        .iter()
+        .map(|device| device.name().unwrap_or_default())
+        .collect();
+    let default = match kind {
+        // There is no default loopback source outside Windows
+        AudioDeviceKind::Loopback if !records_outputs => names.iter().position(|name| is_loopback_name(name)),
+        _ => {
+            let default_name = default.and_then(|device| device.name().ok());
+            names.iter().position(|name| Some(name) == default_name.as_ref())
+        }
+    };
+
+    let name = selection
+        .choose(&names, default)
+        .with_context(|| format!("No {:?} audio device matches {:?}", kind, selection))?;
+    let device = devices
+        .into_iter()
+        .find(|device| device.name().is_ok_and(|n| &n == name))
+        .context("Audio device went away")?;
+    let config = if kind == AudioDeviceKind::Output || records_outputs {
+        device.default_output_config()
+    } else {
+        device.default_input_config()
+    }
+    .with_context(|| format!("Failed to query the format of {}", name))?;
+    Ok((device, config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_device_selection() {
+        let names: Vec<String> = ["Built-in Microphone", "USB Headset", "Monitor of USB Headset"]
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+        let named = |name: &str| DeviceSelection::Named(name.to_string());
+
+        assert_eq!(DeviceSelection::Default.choose(&names, Some(1)), Some(&names[1]));
+        assert_eq!(DeviceSelection::Default.choose(&names, None), None);
+        assert_eq!(named("USB Headset").choose(&names, None), Some(&names[1]));
+        assert_eq!(named("built-in").choose(&names, None), Some(&names[0]));
+        // Ambiguous or unknown names match nothing
+        assert_eq!(named("headset").choose(&names, None), None);
+        assert_eq!(named("Speakers").choose(&names, None), None);
+
+        assert!(is_loopback_name(&names[2]));
+        assert!(!is_loopback_name(&names[1]));
+    }
+}
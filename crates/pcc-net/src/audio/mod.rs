@@ -0,0 +1,384 @@
+use crate::network::{Message, SessionClock};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{
+    atomic::{AtomicBool, AtomicU32, Ordering},
+    Arc, Mutex,
+};
+use std::time::{Duration, SystemTime};
+
+mod capture;
+mod device;
+#[cfg(feature = "audio")]
+mod opus;
+
+pub use capture::{AudioCapture, CapturedAudio};
+#[cfg(feature = "audio")]
+pub use device::open_device;
+pub use device::{list_devices, AudioDevice, AudioDeviceKind, DeviceSelection};
+
+/// Audio carried by each packet. 20ms is Opus's sweet spot between
+/// packet overhead and latency.
+pub const PACKET_DURATION: Duration = Duration::from_millis(20);
+
+/// Lowest bitrate audio is sent at; speech stays intelligible
+pub const MIN_AUDIO_BITRATE: u32 = 24_000;
+/// Highest bitrate audio is sent at; transparent for stereo music
+pub const MAX_AUDIO_BITRATE: u32 = 128_000;
+/// Bitrate audio starts at before any network feedback
+pub const DEFAULT_AUDIO_BITRATE: u32 = 96_000;
+/// Loudest a source can be turned up, as a multiple of its captured level
+pub const MAX_AUDIO_GAIN: f32 = 4.0;
+
+/// Sample layout of PCM audio, as captured on the host or handed to the
+/// viewer's playback
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AudioFormat {
+    pub sample_rate: u32,
+    pub channels: u16,
+}
+
+impl Default for AudioFormat {
+    fn default() -> Self {
+        Self {
+            sample_rate: 48_000,
+            channels: 2,
+        }
+    }
+}
+
+impl AudioFormat {
+    /// How long one frame (a sample per channel) lasts
+    pub fn frame_duration(&self) -> Duration {
+        Duration::from_secs(1) / self.sample_rate
+    }
+
+    /// Interleaved samples in one packet
+    pub fn packet_samples(&self) -> usize {
+        (self.sample_rate as u64 * PACKET_DURATION.as_millis() as u64 / 1000) as usize * self.channels as usize
+    }
+}
+
+/// Audio devices and format chosen at session start. Devices can still be
+/// switched later with `AudioCapture::switch_device` and
+/// `AudioPlayback::start_output_on`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AudioConfig {
+    /// Format audio is captured, sent and played in
+    pub format: AudioFormat,
+    /// Loopback device to share system audio from; `None` shares none
+    pub system: Option<DeviceSelection>,
+    /// Microphone to narrate with; `None` leaves it off
+    pub microphone: Option<DeviceSelection>,
+    /// Where the viewer plays the host's audio
+    pub output: DeviceSelection,
+}
+
+impl Default for AudioConfig {
+    fn default() -> Self {
+        Self {
+            format: AudioFormat::default(),
+            system: Some(DeviceSelection::Default),
+            microphone: None,
+            output: DeviceSelection::Default,
+        }
+    }
+}
+
+impl AudioConfig {
+    /// Start capturing every source this config enables
+    pub fn start_capture(&self, clock: SessionClock) -> Result<Vec<AudioCapture>> {
+        let sources = [(AudioSource::System, &self.system), (AudioSource::Microphone, &self.microphone)];
+        sources
+            .into_iter()
+            .filter_map(|(source, device)| device.as_ref().map(|device| (source, device)))
+            .map(|(source, device)| AudioCapture::open(source, device, self.format, clock))
+            .collect()
+    }
+}
+
+/// Which of the host's audio a stream carries
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AudioSource {
+    /// Everything the host is playing, captured from a loopback device
+    System,
+    /// The presenter's microphone, for narrating over the shared screen
+    Microphone,
+}
+
+/// Mute and gain of one audio source, kept in step between host and viewer
+/// by `AudioMute` and `AudioGain` messages
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct AudioStreamState {
+    pub muted: bool,
+    /// Multiple of the captured level, from 0.0 to `MAX_AUDIO_GAIN`
+    pub gain: f32,
+}
+
+impl Default for AudioStreamState {
+    fn default() -> Self {
+        Self { muted: false, gain: 1.0 }
+    }
+}
+
+/// Keep a gain, possibly from the peer, within range
+pub fn clamp_gain(gain: f32) -> f32 {
+    if gain.is_finite() {
+        gain.clamp(0.0, MAX_AUDIO_GAIN)
+    } else {
+        1.0
+    }
+}
+
+/// Compresses one packet's worth of interleaved samples
+pub trait AudioEncoder: Send {
+    fn encode(&mut self, samples: &[f32]) -> Result<Vec<u8>>;
+
+    /// Aim for `bits_per_second` from the next packet on. Encoders without
+    /// rate control ignore this.
+    fn set_bitrate(&mut self, _bits_per_second: u32) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Turns packets back into interleaved samples
+pub trait AudioDecoder: Send {
+    fn decode(&mut self, packet: &[u8]) -> Result<Vec<f32>>;
+
+    /// Make up `samples` interleaved samples in place of a lost packet.
+    /// Decoders that can't conceal loss return silence.
+    fn conceal(&mut self, samples: usize) -> Result<Vec<f32>> {
+        Ok(vec![0.0; samples])
+    }
+}
+
+/// The Opus encoder for `format`
+#[cfg(feature = "audio")]
+pub fn opus_encoder(format: AudioFormat) -> Result<Box<dyn AudioEncoder>> {
+    Ok(Box::new(opus::OpusEncoder::new(format)?))
+}
+
+/// Opus needs the `audio` feature
+#[cfg(not(feature = "audio"))]
+pub fn opus_encoder(_format: AudioFormat) -> Result<Box<dyn AudioEncoder>> {
+    anyhow::bail!("Built without Opus support (enable the `audio` feature)")
+}
+
+/// The Opus decoder producing `format`, whatever the stream was encoded at
+#[cfg(feature = "audio")]
+pub fn opus_decoder(format: AudioFormat) -> Result<Box<dyn AudioDecoder>> {
+    Ok(Box::new(opus::OpusDecoder::new(format)?))
+}
+
+/// Opus needs the `audio` feature
+#[cfg(not(feature = "audio"))]
+pub fn opus_decoder(_format: AudioFormat) -> Result<Box<dyn AudioDecoder>> {
+    anyhow::bail!("Built without Opus support (enable the `audio` feature)")
+}
+
+/// Cuts captured audio into fixed-length packets, encodes them and wraps
+/// them in `AudioPacket` messages stamped with their capture time
+pub struct AudioStreamer {
+    source: AudioSource,
+    format: AudioFormat,
+    encoder: Box<dyn AudioEncoder>,
+    /// Captured samples not yet making up a whole packet
+    pending: Vec<f32>,
+    /// Capture time of the first pending sample
+    pending_pts: Option<SystemTime>,
+    sequence: u64,
+    muted: MuteControl,
+    bitrate: BitrateControl,
+    /// Bitrate the encoder was last set to
+    encoder_bitrate: u32,
+}
+
+/// Mutes an `AudioStreamer` from elsewhere, e.g. a UI button while the
+/// streamer is busy in `stream_audio`
+#[derive(Debug, Clone, Default)]
+pub struct MuteControl(Arc<AtomicBool>);
+
+impl MuteControl {
+    pub fn set_muted(&self, muted: bool) {
+        self.0.store(muted, Ordering::Relaxed);
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Sets an `AudioStreamer`'s bitrate from elsewhere, e.g. the bandwidth
+/// estimator. Bitrates are kept between `MIN_AUDIO_BITRATE` and
+/// `MAX_AUDIO_BITRATE`.
+#[derive(Debug, Clone)]
+pub struct BitrateControl(Arc<AtomicU32>);
+
+impl Default for BitrateControl {
+    fn default() -> Self {
+        Self(Arc::new(AtomicU32::new(DEFAULT_AUDIO_BITRATE)))
+    }
+}
+
+impl BitrateControl {
+    pub fn set_bitrate(&self, bits_per_second: u32) {
+        let bitrate = bits_per_second.clamp(MIN_AUDIO_BITRATE, MAX_AUDIO_BITRATE);
+        self.0.store(bitrate, Ordering::Relaxed);
+    }
+
+    pub fn bitrate(&self) -> u32 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// The host's mute and gain state for each audio source. Muting stops a
+/// source's streamer, so nothing is sent; gain is applied by the viewer's
+/// playback and only tracked here.
+#[derive(Debug, Clone, Default)]
+pub struct AudioControls {
+    sources: Arc<Mutex<HashMap<AudioSource, (MuteControl, AudioStreamState)>>>,
+}
+
+impl AudioControls {
+    /// Control `streamer`'s source, applying any state set before it started
+    pub fn add(&self, streamer: &AudioStreamer) {
+        let mut sources = self.lock();
+        let state = sources.get(&streamer.source()).map(|(_, state)| *state).unwrap_or_default();
+        let mute = streamer.mute_control();
+        mute.set_muted(state.muted);
+        sources.insert(streamer.source(), (mute, state));
+    }
+
+    /// Mute or unmute `source` on the host, returning the message telling
+    /// the viewer
+    pub fn set_muted(&self, source: AudioSource, muted: bool) -> Message {
+        let mut sources = self.lock();
+        let (mute, state) = sources.entry(source).or_default();
+        mute.set_muted(muted);
+        state.muted = muted;
+        Message::AudioMute { source, muted }
+    }
+
+    /// Set `source`'s gain, returning the message telling the viewer to
+    /// apply it
+    pub fn set_gain(&self, source: AudioSource, gain: f32) -> Message {
+        let gain = clamp_gain(gain);
+        self.lock().entry(source).or_default().1.gain = gain;
+        Message::AudioGain { source, gain }
+    }
+
+    /// Apply a change the viewer made, returning whether `message` was one
+    pub fn apply(&self, message: &Message) -> bool {
+        match *message {
+            Message::AudioMute { source, muted } => {
+                self.set_muted(source, muted);
+                true
+            }
+            Message::AudioGain { source, gain } => {
+                self.set_gain(source, gain);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn state(&self, source: AudioSource) -> AudioStreamState {
+        self.lock().get(&source).map(|(_, state)| *state).unwrap_or_default()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<AudioSource, (MuteControl, AudioStreamState)>> {
+        self.sources.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl AudioStreamer {
+    pub fn new(source: AudioSource, format: AudioFormat, encoder: Box<dyn AudioEncoder>) -> Self {
+        Self {
+            source,
+            format,
+            encoder,
+            pending: Vec::new(),
+            pending_pts: None,
+            sequence: 0,
+            muted: MuteControl::default(),
+            bitrate: BitrateControl::default(),
+            encoder_bitrate: DEFAULT_AUDIO_BITRATE,
+        }
+    }
+
+    pub fn source(&self) -> AudioSource {
+        self.source
+    }
+
+    /// The message opening this streamer's stream
+    pub fn start_message(&self) -> Message {
+        Message::AudioStreamStart {
+            source: self.source,
+            format: self.format,
+        }
+    }
+
+    /// Stops and resumes sending this source. Nothing is sent while muted,
+    /// so a muted microphone costs no bandwidth.
+    pub fn mute_control(&self) -> MuteControl {
+        self.muted.clone()
+    }
+
+    /// Adapts this source's bitrate to the network while it streams
+    pub fn bitrate_control(&self) -> BitrateControl {
+        self.bitrate.clone()
+    }
+
+    /// Add samples captured starting at `pts`, returning the packets now
+    /// complete
+    pub fn push(&mut self, pts: SystemTime, samples: &[f32]) -> Result<Vec<Message>> {
+        if self.muted.is_muted() {
+            self.pending.clear();
+            return Ok(Vec::new());
+        }
+        if self.pending.is_empty() {
+            self.pending_pts = Some(pts);
+        }
+        self.pending.extend_from_slice(samples);
+
+        let bitrate = self.bitrate.bitrate();
+        if bitrate != self.encoder_bitrate {
+            self.encoder.set_bitrate(bitrate)?;
+            self.encoder_bitrate = bitrate;
+        }
+
+        let packet_samples = self.format.packet_samples();
+        let mut packets = Vec::new();
+        while self.pending.len() >= packet_samples {
+            let data = self.encoder.encode(&self.pending[..packet_samples])?;
+            self.pending.drain(..packet_samples);
+
+            let pts = self.pending_pts.unwrap_or(pts);
+            self.pending_pts = Some(pts + PACKET_DURATION);
+            packets.push(Message::AudioPacket {
+                source: self.source,
+                sequence: self.sequence,
+                pts,
+                data,
+            });
+            self.sequence += 1;
+        }
+        Ok(packets)
+    }
+
+    /// Pad the samples waiting for a full packet with silence and return
+    /// that last packet, e.g. before ending the stream
+    pub fn flush(&mut self) -> Result<Vec<Message>> {
+        if self.pending.is_empty() {
+            return Ok(Vec::new());
+        }
+        let silence = vec![0.0; self.format.packet_samples() - self.pending.len()];
+        self.push(SystemTime::now(), &silence)
+    }
+
+    /// Packets sent so far
+    pub fn packets_sent(&self) -> u64 {
+        self.sequence
+    }
+}
@@ -0,0 +1,103 @@
+use super::{AudioDecoder, AudioEncoder, AudioFormat, DEFAULT_AUDIO_BITRATE};
+use anyhow::{Context, Result};
+use audiopus::{
+    coder::{Decoder, Encoder},
+    packet::Packet,
+    Application, Bitrate, Channels, MutSignals, SampleRate,
+};
+
+// Largest packet Opus recommends allocating for
+const MAX_PACKET_SIZE: usize = 4000;
+// Longest packet Opus can produce, in milliseconds
+const MAX_PACKET_MS: usize = 120;
+
+fn opus_format(format: AudioFormat) -> Result<(SampleRate, Channels)> {
+    let sample_rate = SampleRate::try_from(format.sample_rate as i32)
+        .with_context(|| format!("Opus does not support {} Hz", format.sample_rate))?;
+    let channels = match format.channels {
+        1 => Channels::Mono,
+        2 => Channels::Stereo,
+        n => anyhow::bail!("Opus does not support {} channels", n),
+    };
+    Ok((sample_rate, channels))
+}
+
+pub(super) struct OpusEncoder {
+    encoder: Encoder,
+    output: Vec<u8>,
+}
+
+impl OpusEncoder {
+    pub(super) fn new(format: AudioFormat) -> Result<Self> {
+        let (sample_rate, channels) = opus_format(format)?;
+        let mut encoder =
+            Encoder::new(sample_rate, channels, Application::Audio).context("Failed to create Opus encoder")?;
+        encoder
+            .set_bitrate(Bitrate::BitsPerSecond(DEFAULT_AUDIO_BITRATE as i32))
+            .context("Failed to set Opus bitrate")?;
+
+        Ok(Self {
+            encoder,
+            output: vec![0; MAX_PACKET_SIZE],
+        })
+    }
+}
+
+impl AudioEncoder for OpusEncoder {
+    fn encode(&mut self, samples: &[f32]) -> Result<Vec<u8>> {
+        let len = self
+            .encoder
+            .encode_float(samples, &mut self.output)
+            .context("Opus encoding failed")?;
+        Ok(self.output[..len].to_vec())
+    }
+
+    fn set_bitrate(&mut self, bits_per_second: u32) -> Result<()> {
+        self.encoder
+            .set_bitrate(Bitrate::BitsPerSecond(bits_per_second as i32))
+            .context("Failed to set Opus bitrate")
+    }
+}
+
+pub(super) struct OpusDecoder {
+    decoder: Decoder,
+    channels: usize,
+    output: Vec<f32>,
+}
+
+impl OpusDecoder {
+    pub(super) fn new(format: AudioFormat) -> Result<Self> {
+        let (sample_rate, channels) = opus_format(format)?;
+        let decoder = Decoder::new(sample_rate, channels).context("Failed to create Opus decoder")?;
+        let max_samples = format.sample_rate as usize * MAX_PACKET_MS / 1000 * format.channels as usize;
+
+        Ok(Self {
+            decoder,
+            channels: format.channels as usize,
+            output: vec![0.0; max_samples],
+        })
+    }
+}
+
+impl AudioDecoder for OpusDecoder {
+    fn decode(&mut self, packet: &[u8]) -> Result<Vec<f32>> {
+        let packet = Packet::try_from(packet).context("Invalid Opus packet")?;
+        let signals = MutSignals::try_from(&mut self.output[..]).context("Opus output buffer too large")?;
+        let frames = self
+            .decoder
+            .decode_float(Some(packet), signals, false)
+            .context("Opus decoding failed")?;
+        Ok(self.output[..frames * self.channels].to_vec())
+    }
+
+    // Opus packet loss concealment extrapolates from the audio so far
+    fn conceal(&mut self, samples: usize) -> Result<Vec<f32>> {
+        let len = samples.min(self.output.len());
+        let signals = MutSignals::try_from(&mut self.output[..len]).context("Opus output buffer too large")?;
+        let frames = self
+            .decoder
+            .decode_float(None, signals, false)
+            .context("Opus concealment failed")?;
+        Ok(self.output[..frames * self.channels].to_vec())
+    }
+}
@@ -0,0 +1,321 @@
+use crate::input::{InputEvent, Permission};
+use crate::network::{CloseReason, ViewerInfo};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+// Input events written together as one entry, at most
+const MAX_BATCH_EVENTS: usize = 64;
+// How long input waits to be written with what follows it, at most
+const MAX_BATCH_AGE: Duration = Duration::from_secs(1);
+
+/// Where a host that allows remote control records who connected, what
+/// they were allowed and what input they sent, from the `[audit]` section
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuditConfig {
+    /// File to append to. Without one nothing is audited.
+    pub path: Option<PathBuf>,
+    /// Size the file may reach before it's rotated to `<path>.1`
+    pub max_bytes: u64,
+    /// Rotated files to keep, oldest dropped first
+    pub keep: u32,
+    pub redaction: Redaction,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            max_bytes: 10 * 1024 * 1024,
+            keep: 5,
+            redaction: Redaction::default(),
+        }
+    }
+}
+
+/// How much of the viewer's input the audit log keeps
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Redaction {
+    /// Every event as it arrived
+    None,
+    /// Pointer input in full, but not which keys were pressed or what was
+    /// typed, which could be passwords
+    #[default]
+    Keys,
+    /// Only how many events came, and when
+    All,
+}
+
+/// One input event in the audit log, as redacted
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum AuditedInput {
+    Event(InputEvent),
+    /// A key went down or up
+    Key { pressed: bool },
+    /// Some text was typed
+    Text,
+}
+
+impl AuditedInput {
+    // `event` with what `redaction` hides left out, or nothing if it hides
+    // events altogether
+    fn redact(event: InputEvent, redaction: Redaction) -> Option<Self> {
+        match (redaction, event) {
+            (Redaction::All, _) => None,
+            (Redaction::Keys, InputEvent::Key { pressed, .. }) => Some(AuditedInput::Key { pressed }),
+            (Redaction::Keys, InputEvent::Text(_)) => Some(AuditedInput::Text),
+            (_, event) => Some(AuditedInput::Event(event)),
+        }
+    }
+}
+
+/// Something audited
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum AuditEntry {
+    Connect,
+    /// The viewer was granted `permission`, or had it lowered to it
+    Permission { permission: Permission },
+    /// A batch of input from the viewer, starting at `from_ms`. `events` is
+    /// missing with `Redaction::All`.
+    Input {
+        from_ms: u64,
+        injected: u32,
+        dropped: u32,
+        events: Option<Vec<AuditedInput>>,
+    },
+    Disconnect { reason: CloseReason },
+}
+
+/// One line of the audit log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// Milliseconds since the Unix epoch
+    pub unix_ms: u64,
+    pub viewer: ViewerInfo,
+    #[serde(flatten)]
+    pub entry: AuditEntry,
+}
+
+fn unix_ms() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_millis() as u64)
+}
+
+// The open file, rotated once it's full
+struct AuditWriter {
+    path: PathBuf,
+    file: File,
+    written: u64,
+    max_bytes: u64,
+    keep: u32,
+}
+
+impl AuditWriter {
+    fn open(path: &Path, max_bytes: u64, keep: u32) -> Result<Self> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open audit log {}", path.display()))?;
+        let written = file.metadata()?.len();
+        Ok(Self { path: path.to_path_buf(), file, written, max_bytes, keep })
+    }
+
+    fn rotated(&self, generation: u32) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", generation));
+        path.into()
+    }
+
+    // Shift `<path>.1` to `<path>.2` and so on, dropping the oldest, move
+    // the full file to `<path>.1` and start a new one
+    fn rotate(&mut self) -> Result<()> {
+        let oldest = self.rotated(self.keep.max(1));
+        match std::fs::remove_file(&oldest) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(e).with_context(|| format!("Failed to remove {}", oldest.display()))
+            }
+            _ => {}
+        }
+        for generation in (1..self.keep).rev() {
+            let from = self.rotated(generation);
+            if from.exists() {
+                std::fs::rename(&from, self.rotated(generation + 1))
+                    .with_context(|| format!("Failed to rotate {}", from.display()))?;
+            }
+        }
+        if self.keep > 0 {
+            std::fs::rename(&self.path, self.rotated(1))
+        } else {
+            std::fs::remove_file(&self.path)
+        }
+        .with_context(|| format!("Failed to rotate {}", self.path.display()))?;
+        *self = Self::open(&self.path, self.max_bytes, self.keep)?;
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<()> {
+        let len = line.len() as u64 + 1;
+        if self.written > 0 && self.written + len > self.max_bytes {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.file.flush()?;
+        self.written += len;
+        Ok(())
+    }
+}
+
+/// The audit log a host appends to. Clones share the file; the default
+/// audits nothing.
+#[derive(Clone, Default)]
+pub struct AuditLog {
+    writer: Option<Arc<Mutex<AuditWriter>>>,
+    redaction: Redaction,
+}
+
+impl fmt::Debug for AuditLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuditLog")
+            .field("enabled", &self.writer.is_some())
+            .field("redaction", &self.redaction)
+            .finish()
+    }
+}
+
+impl AuditLog {
+    /// Append to the file `config` names, or audit nothing if it names none
+    pub fn open(config: &AuditConfig) -> Result<Self> {
+        let writer = match &config.path {
+            Some(path) => Some(Arc::new(Mutex::new(AuditWriter::open(path, config.max_bytes, config.keep)?))),
+            None => None,
+        };
+        Ok(Self { writer, redaction: config.redaction })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.writer.is_some()
+    }
+
+    /// Audit a session with `viewer`
+    pub fn session(&self, viewer: ViewerInfo) -> AuditSession {
+        AuditSession {
+            log: self.clone(),
+            viewer,
+            batch: Arc::new(Mutex::new(InputBatch::default())),
+        }
+    }
+
+    // Failures are logged, as the session goes on regardless
+    fn write(&self, viewer: &ViewerInfo, entry: AuditEntry) {
+        let Some(writer) = &self.writer else { return };
+        let record = AuditRecord { unix_ms: unix_ms(), viewer: viewer.clone(), entry };
+        let written = serde_json::to_string(&record)
+            .map_err(anyhow::Error::from)
+            .and_then(|line| writer.lock().unwrap().write_line(&line));
+        if let Err(e) = written {
+            warn!("Failed to write audit record: {:#}", e);
+        }
+    }
+
+    /// Read back a log written by `open`, not including rotated files
+    pub fn read(path: &Path) -> Result<Vec<AuditRecord>> {
+        let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        BufReader::new(file)
+            .lines()
+            .enumerate()
+            .map(|(number, line)| {
+                serde_json::from_str(&line?)
+                    .with_context(|| format!("Invalid record on line {} of {}", number + 1, path.display()))
+            })
+            .collect()
+    }
+}
+
+// Input not yet written
+#[derive(Debug, Default)]
+struct InputBatch {
+    from_ms: u64,
+    started: Option<std::time::Instant>,
+    injected: u32,
+    dropped: u32,
+    events: Vec<AuditedInput>,
+}
+
+/// Audits one viewer's session. Clones share the session, so the host can
+/// record connects while `RemoteInput` records what the viewer does.
+#[derive(Clone)]
+pub struct AuditSession {
+    log: AuditLog,
+    viewer: ViewerInfo,
+    batch: Arc<Mutex<InputBatch>>,
+}
+
+impl fmt::Debug for AuditSession {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuditSession").field("log", &self.log).field("viewer", &self.viewer).finish()
+    }
+}
+
+impl AuditSession {
+    pub fn connected(&self) {
+        self.log.write(&self.viewer, AuditEntry::Connect);
+    }
+
+    pub fn permission(&self, permission: Permission) {
+        self.flush();
+        self.log.write(&self.viewer, AuditEntry::Permission { permission });
+    }
+
+    /// Note an input event from the viewer, and whether it was injected.
+    /// Events are written in batches.
+    pub fn input(&self, event: InputEvent, injected: bool) {
+        let full = {
+            let mut batch = self.batch.lock().unwrap();
+            if batch.started.is_none() {
+                batch.from_ms = unix_ms();
+                batch.started = Some(std::time::Instant::now());
+            }
+            if injected {
+                batch.injected += 1;
+            } else {
+                batch.dropped += 1;
+            }
+            batch.events.extend(AuditedInput::redact(event, self.log.redaction));
+            let count = batch.injected + batch.dropped;
+            count as usize >= MAX_BATCH_EVENTS || batch.started.is_some_and(|started| started.elapsed() >= MAX_BATCH_AGE)
+        };
+        if full {
+            self.flush();
+        }
+    }
+
+    /// Write the input noted so far
+    pub fn flush(&self) {
+        let batch = std::mem::take(&mut *self.batch.lock().unwrap());
+        if batch.started.is_none() {
+            return;
+        }
+        let events = (self.log.redaction != Redaction::All).then_some(batch.events);
+        self.log.write(
+            &self.viewer,
+            AuditEntry::Input { from_ms: batch.from_ms, injected: batch.injected, dropped: batch.dropped, events },
+        );
+    }
+
+    pub fn disconnected(&self, reason: CloseReason) {
+        self.flush();
+        self.log.write(&self.viewer, AuditEntry::Disconnect { reason });
+    }
+}
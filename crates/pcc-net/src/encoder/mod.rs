@@ -0,0 +1,205 @@
+use anyhow::Result;
+use crate::network::SendBudget;
+use pcc_core::QualityConfig;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+use tracing::debug;
+use jpeg_encoder::{Encoder, ColorType};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+// Lowest JPEG quality the encoder drops to when over its bitrate
+const MIN_ADAPTED_QUALITY: u32 = 20;
+// JPEG quality change per frame while adapting to the bitrate
+const QUALITY_STEP: u32 = 5;
+// Share of the bitrate a frame may use before quality creeps back up
+const RAISE_THRESHOLD: f32 = 0.7;
+
+/// How the host codes frames on the wire
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VideoCodec {
+    /// Uncompressed RGB keyframes and pixel-change deltas, which viewers
+    /// apply directly. The only codec viewers can decode so far.
+    #[default]
+    Raw,
+}
+
+impl FromStr for VideoCodec {
+    type Err = String;
+
+    fn from_str(name: &str) -> std::result::Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "raw" => Ok(VideoCodec::Raw),
+            other => Err(format!("Unknown codec {:?} (supported: raw)", other)),
+        }
+    }
+}
+
+pub struct FrameEncoder {
+    // Frame rate the bitrate is shared between
+    target_fps: AtomicU32,
+    width: u32,
+    height: u32,
+    keyframe_requested: AtomicBool,
+    // Bits per second video may use, or 0 for no limit
+    target_bitrate: AtomicU64,
+    // Paces the frames sent to the target bitrate
+    budget: Mutex<SendBudget>,
+    // JPEG quality frames are currently encoded at
+    quality: AtomicU32,
+    // Highest JPEG quality adaptation may return to
+    max_quality: AtomicU32,
+}
+
+impl FrameEncoder {
+    pub fn new(width: u32, height: u32, config: QualityConfig) -> Result<Self> {
+        Ok(Self {
+            target_fps: AtomicU32::new(config.target_fps),
+            width,
+            height,
+            keyframe_requested: AtomicBool::new(false),
+            target_bitrate: AtomicU64::new(0),
+            budget: Mutex::new(SendBudget::new(Instant::now())),
+            quality: AtomicU32::new(Self::config_quality(&config)),
+            max_quality: AtomicU32::new(Self::config_quality(&config)),
+        })
+    }
+    
+    // Encode a frame using optimized JPEG compression
+    pub async fn encode_frame(&self, frame: &[u8]) -> Result<Vec<u8>> {
+        // Log compression start for performance tracking
+        let start = std::time::Instant::now();
+        
+        let mut output = Vec::new();
+        let quality = self.quality.load(Ordering::Relaxed);
+        let encoder = Encoder::new(&mut output, quality as u8);
+        encoder.encode(
+            frame,
+            self.width as u16,
+            self.height as u16,
+            ColorType::Rgb,
+        )?;
+        
+        // Log compression stats
+        let duration = start.elapsed();
+        let compression_ratio = frame.len() as f32 / output.len() as f32;
+        debug!(
+            "Frame encoded: {}x{} in {:?}, ratio: {:.2}:1",
+            self.width, self.height, duration, compression_ratio
+        );
+
+        self.adapt_quality(quality, output.len());
+        Ok(output)
+    }
+    
+    // Request that the next frame is sent in full rather than as deltas
+    pub fn force_keyframe(&self) {
+        self.keyframe_requested.store(true, Ordering::Release);
+    }
+
+    // Check and clear a pending keyframe request
+    pub fn take_keyframe_request(&self) -> bool {
+        self.keyframe_requested.swap(false, Ordering::AcqRel)
+    }
+
+    // Reconfigure encoder with new settings, including while frames are
+    // being encoded
+    pub async fn reconfigure(&self, config: QualityConfig) -> Result<()> {
+        self.quality.store(Self::config_quality(&config), Ordering::Relaxed);
+        self.max_quality.store(Self::config_quality(&config), Ordering::Relaxed);
+        self.target_fps.store(config.target_fps, Ordering::Relaxed);
+        Ok(())
+    }
+
+    // Change the configured quality (0.0-1.0) while frames are being
+    // encoded, e.g. from a status display
+    pub fn set_quality(&self, quality: f32) {
+        let quality = (quality.clamp(0.0, 1.0) * 100.0).round() as u32;
+        self.max_quality.store(quality, Ordering::Relaxed);
+        self.quality.store(quality, Ordering::Relaxed);
+    }
+
+    // Keep video within `bits_per_second`: frames are paced to it where
+    // they're sent, and JPEG encoding lowers its quality, never raising it
+    // past the configured quality. 0 removes the limit.
+    pub fn set_target_bitrate(&self, bits_per_second: u64) {
+        self.target_bitrate.store(bits_per_second, Ordering::Relaxed);
+        self.budget.lock().unwrap().set_bitrate(bits_per_second);
+        if bits_per_second == 0 {
+            self.quality.store(self.max_quality.load(Ordering::Relaxed), Ordering::Relaxed);
+        }
+    }
+
+    // Whether the target bitrate leaves room to send a frame at `now`.
+    // Frames skipped while it doesn't lower the frame rate to fit.
+    pub fn admit_frame(&self, now: Instant) -> bool {
+        self.budget.lock().unwrap().allows(now)
+    }
+
+    // Charge a frame of `bytes` sent at `now` to the target bitrate
+    pub fn frame_sent(&self, bytes: usize, now: Instant) {
+        self.budget.lock().unwrap().spend(bytes, now);
+    }
+
+    // Bits per second frames are paced to, or 0 for no limit
+    pub fn target_bitrate(&self) -> u64 {
+        self.target_bitrate.load(Ordering::Relaxed)
+    }
+
+    // JPEG quality the next frame will be encoded at
+    pub fn current_quality(&self) -> u32 {
+        self.quality.load(Ordering::Relaxed)
+    }
+
+    fn config_quality(config: &QualityConfig) -> u32 {
+        (config.quality * 100.0) as u32
+    }
+
+    // Step quality towards what the bitrate allows, given a frame's size
+    fn adapt_quality(&self, quality: u32, frame_bytes: usize) {
+        let target = self.target_bitrate.load(Ordering::Relaxed);
+        if target == 0 {
+            return;
+        }
+
+        let bitrate = frame_bytes as u64 * 8 * self.target_fps.load(Ordering::Relaxed).max(1) as u64;
+        let max_quality = self.max_quality.load(Ordering::Relaxed);
+        let adapted = if bitrate > target {
+            quality.saturating_sub(QUALITY_STEP).max(MIN_ADAPTED_QUALITY.min(max_quality))
+        } else if (bitrate as f32) < target as f32 * RAISE_THRESHOLD {
+            (quality + QUALITY_STEP).min(max_quality)
+        } else {
+            quality
+        };
+        self.quality.store(adapted, Ordering::Relaxed);
+    }
+}
+
+// Frame compression utilities for small regions
+pub mod compression {
+    use super::*;
+    use lz4_flex::compress_prepend_size;
+    use lz4_flex::decompress_size_prepended;
+    
+    pub fn compress_frame(frame: &[u8], _quality: f32) -> Result<Vec<u8>> {
+        let start = std::time::Instant::now();
+        let compressed = compress_prepend_size(frame);
+        let duration = start.elapsed();
+        
+        debug!(
+            "Region compressed: {} -> {} bytes in {:?}, ratio: {:.2}:1",
+            frame.len(),
+            compressed.len(),
+            duration,
+            frame.len() as f32 / compressed.len() as f32
+        );
+        
+        Ok(compressed)
+    }
+    
+    pub fn decompress_frame(compressed: &[u8]) -> Result<Vec<u8>> {
+        Ok(decompress_size_prepended(compressed)?)
+    }
+}
@@ -0,0 +1,35 @@
+use serde::Serialize;
+
+/// One thing a health check looked at
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    pub ok: bool,
+    /// What was seen, e.g. "29.5 fps"
+    pub detail: String,
+}
+
+/// Whether a host or viewer is doing its job, for orchestrators and
+/// watchdogs
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Health {
+    pub checks: Vec<Check>,
+}
+
+impl Health {
+    /// Add the outcome of one check
+    pub fn check(mut self, name: &'static str, ok: bool, detail: impl Into<String>) -> Self {
+        self.checks.push(Check { name, ok, detail: detail.into() });
+        self
+    }
+
+    /// Whether every check passed
+    pub fn is_healthy(&self) -> bool {
+        self.checks.iter().all(|check| check.ok)
+    }
+
+    /// `{"healthy": .., "checks": [..]}`
+    pub fn to_json(&self) -> String {
+        serde_json::json!({ "healthy": self.is_healthy(), "checks": self.checks }).to_string()
+    }
+}
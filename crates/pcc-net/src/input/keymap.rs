@@ -0,0 +1,175 @@
+use serde::{Deserialize, Serialize};
+
+/// A physical key, identified by its USB HID usage ID (keyboard page), so
+/// viewer and host agree on keys regardless of their OS
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct KeyCode(pub u16);
+
+// Windows scan codes with this bit set are sent as extended (E0-prefixed) keys
+pub(crate) const EXTENDED: u16 = 0xE000;
+
+struct Mapping {
+    hid: u16,
+    evdev: u16,
+    mac: u16,
+    windows: u16,
+}
+
+const fn key(hid: u16, evdev: u16, mac: u16, windows: u16) -> Mapping {
+    Mapping { hid, evdev, mac, windows }
+}
+
+// HID usage, Linux evdev code, macOS virtual key code, Windows scan code
+#[rustfmt::skip]
+const KEYS: &[Mapping] = &[
+    // Letters
+    key(0x04, 30, 0, 0x1E), key(0x05, 48, 11, 0x30), key(0x06, 46, 8, 0x2E),
+    key(0x07, 32, 2, 0x20), key(0x08, 18, 14, 0x12), key(0x09, 33, 3, 0x21),
+    key(0x0A, 34, 5, 0x22), key(0x0B, 35, 4, 0x23), key(0x0C, 23, 34, 0x17),
+    key(0x0D, 36, 38, 0x24), key(0x0E, 37, 40, 0x25), key(0x0F, 38, 37, 0x26),
+    key(0x10, 50, 46, 0x32), key(0x11, 49, 45, 0x31), key(0x12, 24, 31, 0x18),
+    key(0x13, 25, 35, 0x19), key(0x14, 16, 12, 0x10), key(0x15, 19, 15, 0x13),
+    key(0x16, 31, 1, 0x1F), key(0x17, 20, 17, 0x14), key(0x18, 22, 32, 0x16),
+    key(0x19, 47, 9, 0x2F), key(0x1A, 17, 13, 0x11), key(0x1B, 45, 7, 0x2D),
+    key(0x1C, 21, 16, 0x15), key(0x1D, 44, 6, 0x2C),
+    // Digits 1-9, 0
+    key(0x1E, 2, 18, 0x02), key(0x1F, 3, 19, 0x03), key(0x20, 4, 20, 0x04),
+    key(0x21, 5, 21, 0x05), key(0x22, 6, 23, 0x06), key(0x23, 7, 22, 0x07),
+    key(0x24, 8, 26, 0x08), key(0x25, 9, 28, 0x09), key(0x26, 10, 25, 0x0A),
+    key(0x27, 11, 29, 0x0B),
+    // Enter, Escape, Backspace, Tab, Space
+    key(0x28, 28, 36, 0x1C), key(0x29, 1, 53, 0x01), key(0x2A, 14, 51, 0x0E),
+    key(0x2B, 15, 48, 0x0F), key(0x2C, 57, 49, 0x39),
+    // - = [ ] \ ; ' ` , . /
+    key(0x2D, 12, 27, 0x0C), key(0x2E, 13, 24, 0x0D), key(0x2F, 26, 33, 0x1A),
+    key(0x30, 27, 30, 0x1B), key(0x31, 43, 42, 0x2B), key(0x33, 39, 41, 0x27),
+    key(0x34, 40, 39, 0x28), key(0x35, 41, 50, 0x29), key(0x36, 51, 43, 0x33),
+    key(0x37, 52, 47, 0x34), key(0x38, 53, 44, 0x35),
+    // Caps Lock
+    key(0x39, 58, 57, 0x3A),
+    // F1-F12
+    key(0x3A, 59, 122, 0x3B), key(0x3B, 60, 120, 0x3C), key(0x3C, 61, 99, 0x3D),
+    key(0x3D, 62, 118, 0x3E), key(0x3E, 63, 96, 0x3F), key(0x3F, 64, 97, 0x40),
+    key(0x40, 65, 98, 0x41), key(0x41, 66, 100, 0x42), key(0x42, 67, 101, 0x43),
+    key(0x43, 68, 109, 0x44), key(0x44, 87, 103, 0x57), key(0x45, 88, 111, 0x58),
+    // Insert, Home, Page Up, Delete, End, Page Down
+    key(0x49, 110, 114, EXTENDED | 0x52), key(0x4A, 102, 115, EXTENDED | 0x47),
+    key(0x4B, 104, 116, EXTENDED | 0x49), key(0x4C, 111, 117, EXTENDED | 0x53),
+    key(0x4D, 107, 119, EXTENDED | 0x4F), key(0x4E, 109, 121, EXTENDED | 0x51),
+    // Right, Left, Down, Up
+    key(0x4F, 106, 124, EXTENDED | 0x4D), key(0x50, 105, 123, EXTENDED | 0x4B),
+    key(0x51, 108, 125, EXTENDED | 0x50), key(0x52, 103, 126, EXTENDED | 0x48),
+    // Left Ctrl, Shift, Alt, Meta; Right Ctrl, Shift, Alt, Meta
+    key(0xE0, 29, 59, 0x1D), key(0xE1, 42, 56, 0x2A),
+    key(0xE2, 56, 58, 0x38), key(0xE3, 125, 55, EXTENDED | 0x5B),
+    key(0xE4, 97, 62, EXTENDED | 0x1D), key(0xE5, 54, 60, 0x36),
+    key(0xE6, 100, 61, EXTENDED | 0x38), key(0xE7, 126, 54, EXTENDED | 0x5C),
+];
+
+impl KeyCode {
+    pub const BACKSPACE: KeyCode = KeyCode(0x2A);
+    pub const DELETE: KeyCode = KeyCode(0x4C);
+    pub const LEFT_CTRL: KeyCode = KeyCode(0xE0);
+    pub const LEFT_SHIFT: KeyCode = KeyCode(0xE1);
+    pub const LEFT_ALT: KeyCode = KeyCode(0xE2);
+    pub const LEFT_META: KeyCode = KeyCode(0xE3);
+
+    fn mapping(self) -> Option<&'static Mapping> {
+        KEYS.iter().find(|mapping| mapping.hid == self.0)
+    }
+
+    /// Linux input event code
+    pub fn evdev(self) -> Option<u16> {
+        self.mapping().map(|mapping| mapping.evdev)
+    }
+
+    /// macOS virtual key code
+    pub fn mac(self) -> Option<u16> {
+        self.mapping().map(|mapping| mapping.mac)
+    }
+
+    /// Windows set 1 scan code, with `EXTENDED` set for E0-prefixed keys
+    pub fn windows_scan_code(self) -> Option<u16> {
+        self.mapping().map(|mapping| mapping.windows)
+    }
+
+    /// Every key the host can inject
+    pub fn all() -> impl Iterator<Item = KeyCode> {
+        KEYS.iter().map(|mapping| KeyCode(mapping.hid))
+    }
+
+    /// The key that types `c` on a US layout, and whether Shift is needed.
+    /// Used to type text where the OS has no way to inject characters.
+    pub fn for_us_char(c: char) -> Option<(KeyCode, bool)> {
+        match c {
+            'a'..='z' => Some((KeyCode(0x04 + (c as u16 - 'a' as u16)), false)),
+            'A'..='Z' => Some((KeyCode(0x04 + (c as u16 - 'A' as u16)), true)),
+            '1'..='9' => Some((KeyCode(0x1E + (c as u16 - '1' as u16)), false)),
+            '0' => Some((KeyCode(0x27), false)),
+            '\n' => Some((KeyCode(0x28), false)),
+            '\t' => Some((KeyCode(0x2B), false)),
+            ' ' => Some((KeyCode(0x2C), false)),
+            _ => {
+                if let Some(digit) = "!@#$%^&*()".find(c) {
+                    return Some((KeyCode(0x1E + digit as u16), true));
+                }
+                US_PUNCTUATION.iter().find_map(|&(hid, plain, shifted)| {
+                    (c == plain || c == shifted).then_some((KeyCode(hid), c == shifted))
+                })
+            }
+        }
+    }
+
+    /// The left-hand key for a right-hand modifier, so hotkeys match either
+    pub(crate) fn left_hand(self) -> KeyCode {
+        match self.0 {
+            0xE4..=0xE7 => KeyCode(self.0 - 4),
+            _ => self,
+        }
+    }
+
+    /// Whether this is Ctrl, Alt or Meta, which turn keys into shortcuts
+    pub(crate) fn is_shortcut_modifier(self) -> bool {
+        matches!(self.0, 0xE0 | 0xE2 | 0xE3 | 0xE4 | 0xE7)
+    }
+}
+
+// HID usage, unshifted and shifted character on a US layout
+const US_PUNCTUATION: &[(u16, char, char)] = &[
+    (0x2D, '-', '_'),
+    (0x2E, '=', '+'),
+    (0x2F, '[', '{'),
+    (0x30, ']', '}'),
+    (0x31, '\\', '|'),
+    (0x33, ';', ':'),
+    (0x34, '\'', '"'),
+    (0x35, '`', '~'),
+    (0x36, ',', '<'),
+    (0x37, '.', '>'),
+    (0x38, '/', '?'),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    #[test]
+    fn test_us_characters_map_to_keys() {
+        assert_eq!(KeyCode::for_us_char('q'), Some((KeyCode(0x14), false)));
+        assert_eq!(KeyCode::for_us_char('Q'), Some((KeyCode(0x14), true)));
+        assert_eq!(KeyCode::for_us_char('0'), Some((KeyCode(0x27), false)));
+        assert_eq!(KeyCode::for_us_char(')'), Some((KeyCode(0x27), true)));
+        assert_eq!(KeyCode::for_us_char('?'), Some((KeyCode(0x38), true)));
+        assert_eq!(KeyCode::for_us_char('é'), None);
+    }
+
+    #[test]
+    fn test_key_codes_are_unique_per_platform() {
+        let unique = |codes: Vec<u16>| codes.iter().collect::<HashSet<_>>().len() == codes.len();
+        assert!(unique(KEYS.iter().map(|k| k.hid).collect()));
+        assert!(unique(KEYS.iter().map(|k| k.evdev).collect()));
+        assert!(unique(KEYS.iter().map(|k| k.mac).collect()));
+        assert!(unique(KEYS.iter().map(|k| k.windows).collect()));
+    }
+}
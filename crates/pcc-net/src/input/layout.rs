@@ -0,0 +1,62 @@
+use super::{InputEvent, KeyCode};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+/// How the host turns the viewer's key presses into keystrokes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum KeyMode {
+    /// Type the character the viewer's layout produced, so a German viewer
+    /// typing on a US host still gets a 'z' where they pressed one.
+    /// Shortcuts and keys that don't type anything use the physical key.
+    #[default]
+    Text,
+    /// Always press the same physical key, whatever it types on the host.
+    /// Best for games, where key positions matter more than characters.
+    Physical,
+}
+
+/// Decides per key press between the physical key and its text
+#[derive(Debug, Default)]
+pub(crate) struct KeyTranslator {
+    mode: KeyMode,
+    /// Keys whose press was typed as text, so their release is dropped
+    typed: BTreeSet<KeyCode>,
+}
+
+impl KeyTranslator {
+    pub(crate) fn new(mode: KeyMode) -> Self {
+        Self {
+            mode,
+            typed: BTreeSet::new(),
+        }
+    }
+
+    /// Translate `event` given the keys currently held on the host; `None`
+    /// means nothing should be injected
+    pub(crate) fn translate(&mut self, event: InputEvent, held: &BTreeSet<KeyCode>) -> Option<InputEvent> {
+        let InputEvent::Key { key, pressed, text } = event else {
+            return Some(event);
+        };
+
+        if !pressed {
+            return if self.typed.remove(&key) {
+                None
+            } else {
+                Some(InputEvent::Key { key, pressed, text: None })
+            };
+        }
+
+        let shortcut = held.iter().any(|key| key.is_shortcut_modifier());
+        match text {
+            Some(c) if self.mode == KeyMode::Text && !shortcut && !c.is_control() => {
+                self.typed.insert(key);
+                Some(InputEvent::Text(c))
+            }
+            _ => Some(InputEvent::Key { key, pressed, text: None }),
+        }
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.typed.clear();
+    }
+}
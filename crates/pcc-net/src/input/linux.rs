@@ -0,0 +1,222 @@
+use super::{InputBackend, InputEvent, KeyCode, MouseButton};
+use anyhow::{Context, Result};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+
+// Event types and codes from linux/input-event-codes.h
+const EV_SYN: u16 = 0x00;
+const EV_KEY: u16 = 0x01;
+const EV_REL: u16 = 0x02;
+const EV_ABS: u16 = 0x03;
+const SYN_REPORT: u16 = 0;
+const REL_X: u16 = 0x00;
+const REL_Y: u16 = 0x01;
+const REL_HWHEEL: u16 = 0x06;
+const REL_WHEEL: u16 = 0x08;
+const ABS_X: u16 = 0x00;
+const ABS_Y: u16 = 0x01;
+const KEY_LEFTSHIFT: u16 = 42;
+const KEY_RIGHTSHIFT: u16 = 54;
+const BTN_LEFT: u16 = 0x110;
+const BTN_RIGHT: u16 = 0x111;
+const BTN_MIDDLE: u16 = 0x112;
+const BUS_VIRTUAL: u16 = 0x06;
+
+// ioctls from linux/uinput.h
+const UI_DEV_CREATE: libc::c_ulong = 0x5501;
+const UI_DEV_DESTROY: libc::c_ulong = 0x5502;
+const UI_DEV_SETUP: libc::c_ulong = 0x405c_5503;
+const UI_ABS_SETUP: libc::c_ulong = 0x401c_5504;
+const UI_SET_EVBIT: libc::c_ulong = 0x4004_5564;
+const UI_SET_KEYBIT: libc::c_ulong = 0x4004_5565;
+const UI_SET_RELBIT: libc::c_ulong = 0x4004_5566;
+const UI_SET_ABSBIT: libc::c_ulong = 0x4004_5567;
+
+#[repr(C)]
+struct InputId {
+    bustype: u16,
+    vendor: u16,
+    product: u16,
+    version: u16,
+}
+
+#[repr(C)]
+struct UinputSetup {
+    id: InputId,
+    name: [u8; 80],
+    ff_effects_max: u32,
+}
+
+#[repr(C)]
+struct AbsInfo {
+    value: i32,
+    minimum: i32,
+    maximum: i32,
+    fuzz: i32,
+    flat: i32,
+    resolution: i32,
+}
+
+#[repr(C)]
+struct UinputAbsSetup {
+    code: u16,
+    absinfo: AbsInfo,
+}
+
+/// A virtual keyboard and absolute pointer created through /dev/uinput
+pub(super) struct UinputBackend {
+    device: File,
+    // Whether the viewer is holding Shift, which typed text must respect
+    shift_held: bool,
+}
+
+impl UinputBackend {
+    pub(super) fn new(width: u32, height: u32) -> Result<Self> {
+        let device = OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open("/dev/uinput")
+            .context("Failed to open /dev/uinput")?;
+        let fd = device.as_raw_fd();
+
+        let ioctl = |request: libc::c_ulong, value: libc::c_ulong, what: &str| -> Result<()> {
+            // SAFETY: fd is an open uinput device and value is a plain integer argument
+            if unsafe { libc::ioctl(fd, request, value) } < 0 {
+                return Err(std::io::Error::last_os_error()).context(format!("Failed to set up uinput {}", what));
+            }
+            Ok(())
+        };
+
+        for ev in [EV_SYN, EV_KEY, EV_REL, EV_ABS] {
+            ioctl(UI_SET_EVBIT, ev as libc::c_ulong, "event type")?;
+        }
+        let keys = KeyCode::all().filter_map(KeyCode::evdev);
+        for code in keys.chain([BTN_LEFT, BTN_RIGHT, BTN_MIDDLE]) {
+            ioctl(UI_SET_KEYBIT, code as libc::c_ulong, "key")?;
+        }
+        for rel in [REL_X, REL_Y, REL_WHEEL, REL_HWHEEL] {
+            ioctl(UI_SET_RELBIT, rel as libc::c_ulong, "relative axis")?;
+        }
+
+        for (code, size) in [(ABS_X, width), (ABS_Y, height)] {
+            ioctl(UI_SET_ABSBIT, code as libc::c_ulong, "axis")?;
+            let setup = UinputAbsSetup {
+                code,
+                absinfo: AbsInfo {
+                    value: 0,
+                    minimum: 0,
+                    maximum: size.saturating_sub(1) as i32,
+                    fuzz: 0,
+                    flat: 0,
+                    resolution: 0,
+                },
+            };
+            // SAFETY: setup matches struct uinput_abs_setup and outlives the call
+            if unsafe { libc::ioctl(fd, UI_ABS_SETUP, &setup) } < 0 {
+                return Err(std::io::Error::last_os_error()).context("Failed to set up uinput axis range");
+            }
+        }
+
+        let mut setup = UinputSetup {
+            id: InputId {
+                bustype: BUS_VIRTUAL,
+                vendor: 0,
+                product: 0,
+                version: 1,
+            },
+            name: [0; 80],
+            ff_effects_max: 0,
+        };
+        let name = b"PixelChangeCheck remote input";
+        setup.name[..name.len()].copy_from_slice(name);
+        // SAFETY: setup matches struct uinput_setup and outlives the call
+        if unsafe { libc::ioctl(fd, UI_DEV_SETUP, &setup) } < 0 {
+            return Err(std::io::Error::last_os_error()).context("Failed to set up uinput device");
+        }
+        ioctl(UI_DEV_CREATE, 0, "device")?;
+
+        Ok(Self {
+            device,
+            shift_held: false,
+        })
+    }
+
+    fn emit(&mut self, events: &[(u16, u16, i32)]) -> Result<()> {
+        let mut buf = Vec::with_capacity((events.len() + 1) * std::mem::size_of::<libc::input_event>());
+        for &(type_, code, value) in events.iter().chain([(EV_SYN, SYN_REPORT, 0)].iter()) {
+            let event = libc::input_event {
+                time: libc::timeval { tv_sec: 0, tv_usec: 0 },
+                type_,
+                code,
+                value,
+            };
+            // SAFETY: input_event is plain old data
+            let bytes = unsafe {
+                std::slice::from_raw_parts(
+                    &event as *const libc::input_event as *const u8,
+                    std::mem::size_of::<libc::input_event>(),
+                )
+            };
+            buf.extend_from_slice(bytes);
+        }
+        self.device.write_all(&buf).context("Failed to write uinput event")
+    }
+}
+
+impl InputBackend for UinputBackend {
+    fn inject(&mut self, event: &InputEvent) -> Result<()> {
+        match *event {
+            InputEvent::Key { key, pressed, .. } => {
+                let code = key.evdev().with_context(|| format!("No Linux key code for {:?}", key))?;
+                if code == KEY_LEFTSHIFT || code == KEY_RIGHTSHIFT {
+                    self.shift_held = pressed;
+                }
+                self.emit(&[(EV_KEY, code, pressed as i32)])
+            }
+            // uinput can only press keys, so text is typed as the key that
+            // produces it on a US layout, flipping Shift if needed
+            InputEvent::Text(c) => {
+                let (key, shift) = KeyCode::for_us_char(c).with_context(|| format!("Can't type {:?} on Linux", c))?;
+                let code = key.evdev().with_context(|| format!("No Linux key code for {:?}", key))?;
+                let flip = shift != self.shift_held;
+                if flip {
+                    self.emit(&[(EV_KEY, KEY_LEFTSHIFT, shift as i32)])?;
+                }
+                self.emit(&[(EV_KEY, code, 1)])?;
+                self.emit(&[(EV_KEY, code, 0)])?;
+                if flip {
+                    self.emit(&[(EV_KEY, KEY_LEFTSHIFT, self.shift_held as i32)])?;
+                }
+                Ok(())
+            }
+            InputEvent::MouseMove { x, y } => {
+                self.emit(&[(EV_ABS, ABS_X, x as i32), (EV_ABS, ABS_Y, y as i32)])
+            }
+            InputEvent::MouseMotion { dx, dy } => self.emit(&[(EV_REL, REL_X, dx), (EV_REL, REL_Y, dy)]),
+            InputEvent::MouseButton { button, pressed } => {
+                let code = match button {
+                    MouseButton::Left => BTN_LEFT,
+                    MouseButton::Right => BTN_RIGHT,
+                    MouseButton::Middle => BTN_MIDDLE,
+                };
+                self.emit(&[(EV_KEY, code, pressed as i32)])
+            }
+            // Wheel up is positive in evdev, the opposite of our convention
+            InputEvent::Scroll { dx, dy } => self.emit(&[(EV_REL, REL_HWHEEL, dx), (EV_REL, REL_WHEEL, -dy)]),
+            InputEvent::Touch { .. } | InputEvent::Gesture(_) => {
+                anyhow::bail!("{:?} must be mapped to mouse input first", event)
+            }
+        }
+    }
+}
+
+impl Drop for UinputBackend {
+    fn drop(&mut self) {
+        // SAFETY: the device fd is still open
+        unsafe {
+            libc::ioctl(self.device.as_raw_fd(), UI_DEV_DESTROY);
+        }
+    }
+}
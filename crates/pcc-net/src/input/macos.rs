@@ -0,0 +1,183 @@
+use super::{InputBackend, InputEvent, MouseButton};
+use anyhow::{Context, Result};
+use std::ffi::c_void;
+
+type CGEventRef = *mut c_void;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct CGPoint {
+    x: f64,
+    y: f64,
+}
+
+// Values from CoreGraphics' CGEventTypes.h
+const HID_SYSTEM_STATE: i32 = 1;
+const HID_EVENT_TAP: u32 = 0;
+const SCROLL_UNIT_LINE: u32 = 1;
+const LEFT_MOUSE_DOWN: u32 = 1;
+const LEFT_MOUSE_UP: u32 = 2;
+const RIGHT_MOUSE_DOWN: u32 = 3;
+const RIGHT_MOUSE_UP: u32 = 4;
+const MOUSE_MOVED: u32 = 5;
+const LEFT_MOUSE_DRAGGED: u32 = 6;
+const RIGHT_MOUSE_DRAGGED: u32 = 7;
+const OTHER_MOUSE_DOWN: u32 = 25;
+const OTHER_MOUSE_UP: u32 = 26;
+const OTHER_MOUSE_DRAGGED: u32 = 27;
+const MOUSE_DELTA_X: u32 = 4;
+const MOUSE_DELTA_Y: u32 = 5;
+
+#[link(name = "CoreGraphics", kind = "framework")]
+extern "C" {
+    fn CGEventSourceCreate(state: i32) -> *mut c_void;
+    fn CGEventCreateKeyboardEvent(source: *mut c_void, key: u16, down: bool) -> CGEventRef;
+    fn CGEventCreateMouseEvent(source: *mut c_void, kind: u32, position: CGPoint, button: u32) -> CGEventRef;
+    fn CGEventCreateScrollWheelEvent(source: *mut c_void, units: u32, wheels: u32, wheel1: i32, ...) -> CGEventRef;
+    fn CGEventKeyboardSetUnicodeString(event: CGEventRef, length: usize, string: *const u16);
+    fn CGEventSetIntegerValueField(event: CGEventRef, field: u32, value: i64);
+    fn CGEventPost(tap: u32, event: CGEventRef);
+    fn CGMainDisplayID() -> u32;
+    fn CGDisplayPixelsWide(display: u32) -> usize;
+    fn CGDisplayPixelsHigh(display: u32) -> usize;
+}
+
+#[link(name = "CoreFoundation", kind = "framework")]
+extern "C" {
+    fn CFRelease(object: *const c_void);
+}
+
+/// Posts CGEvents to the HID event tap. Needs the Accessibility permission.
+pub(super) struct CGEventBackend {
+    source: *mut c_void,
+    // Frame pixels to display points; captures are at the Retina pixel size
+    scale: (f64, f64),
+    position: CGPoint,
+    held: Option<MouseButton>,
+}
+
+// The event source is only used from the thread that owns the backend
+unsafe impl Send for CGEventBackend {}
+
+impl CGEventBackend {
+    pub(super) fn new(width: u32, height: u32) -> Self {
+        // SAFETY: plain CoreGraphics queries with no preconditions
+        let (source, points) = unsafe {
+            let display = CGMainDisplayID();
+            (
+                CGEventSourceCreate(HID_SYSTEM_STATE),
+                (CGDisplayPixelsWide(display) as f64, CGDisplayPixelsHigh(display) as f64),
+            )
+        };
+        Self {
+            source,
+            scale: (points.0 / width.max(1) as f64, points.1 / height.max(1) as f64),
+            position: CGPoint { x: 0.0, y: 0.0 },
+            held: None,
+        }
+    }
+
+    fn post(&self, event: CGEventRef) -> Result<()> {
+        if event.is_null() {
+            anyhow::bail!("Failed to create CGEvent");
+        }
+        // SAFETY: event is a valid CGEvent we own
+        unsafe {
+            CGEventPost(HID_EVENT_TAP, event);
+            CFRelease(event);
+        }
+        Ok(())
+    }
+
+    fn mouse(&self, kind: u32, button: u32) -> Result<()> {
+        // SAFETY: source is a valid event source or null, both accepted
+        self.post(unsafe { CGEventCreateMouseEvent(self.source, kind, self.position, button) })
+    }
+
+    // Post a move to `position`, carrying the raw deltas games read
+    fn moved(&self, dx: i32, dy: i32) -> Result<()> {
+        // Moves with a button held must be drags or apps miss them
+        let (kind, button) = match self.held {
+            None => (MOUSE_MOVED, 0),
+            Some(MouseButton::Left) => (LEFT_MOUSE_DRAGGED, 0),
+            Some(MouseButton::Right) => (RIGHT_MOUSE_DRAGGED, 1),
+            Some(MouseButton::Middle) => (OTHER_MOUSE_DRAGGED, 2),
+        };
+        // SAFETY: the event is checked for null before its fields are set
+        unsafe {
+            let event = CGEventCreateMouseEvent(self.source, kind, self.position, button);
+            if !event.is_null() {
+                CGEventSetIntegerValueField(event, MOUSE_DELTA_X, dx as i64);
+                CGEventSetIntegerValueField(event, MOUSE_DELTA_Y, dy as i64);
+            }
+            self.post(event)
+        }
+    }
+}
+
+impl InputBackend for CGEventBackend {
+    fn inject(&mut self, event: &InputEvent) -> Result<()> {
+        match *event {
+            InputEvent::Key { key, pressed, .. } => {
+                let code = key.mac().with_context(|| format!("No macOS key code for {:?}", key))?;
+                // SAFETY: source is a valid event source or null, both accepted
+                self.post(unsafe { CGEventCreateKeyboardEvent(self.source, code, pressed) })
+            }
+            // A key event carrying its own string types it whatever the layout
+            InputEvent::Text(c) => {
+                let mut units = [0u16; 2];
+                let units = c.encode_utf16(&mut units);
+                for down in [true, false] {
+                    // SAFETY: the event is checked for null before use and
+                    // units outlives the call that copies it
+                    let event = unsafe { CGEventCreateKeyboardEvent(self.source, 0, down) };
+                    if !event.is_null() {
+                        unsafe { CGEventKeyboardSetUnicodeString(event, units.len(), units.as_ptr()) };
+                    }
+                    self.post(event)?;
+                }
+                Ok(())
+            }
+            InputEvent::MouseMove { x, y } => {
+                self.position = CGPoint {
+                    x: x as f64 * self.scale.0,
+                    y: y as f64 * self.scale.1,
+                };
+                self.moved(0, 0)
+            }
+            InputEvent::MouseMotion { dx, dy } => {
+                self.position.x += dx as f64 * self.scale.0;
+                self.position.y += dy as f64 * self.scale.1;
+                self.moved(dx, dy)
+            }
+            InputEvent::MouseButton { button, pressed } => {
+                self.held = pressed.then_some(button);
+                match (button, pressed) {
+                    (MouseButton::Left, true) => self.mouse(LEFT_MOUSE_DOWN, 0),
+                    (MouseButton::Left, false) => self.mouse(LEFT_MOUSE_UP, 0),
+                    (MouseButton::Right, true) => self.mouse(RIGHT_MOUSE_DOWN, 1),
+                    (MouseButton::Right, false) => self.mouse(RIGHT_MOUSE_UP, 1),
+                    (MouseButton::Middle, true) => self.mouse(OTHER_MOUSE_DOWN, 2),
+                    (MouseButton::Middle, false) => self.mouse(OTHER_MOUSE_UP, 2),
+                }
+            }
+            // Positive wheel values scroll up on macOS
+            InputEvent::Scroll { dx, dy } => {
+                // SAFETY: two wheel values follow, as `wheels` says
+                self.post(unsafe { CGEventCreateScrollWheelEvent(self.source, SCROLL_UNIT_LINE, 2, -dy, -dx) })
+            }
+            InputEvent::Touch { .. } | InputEvent::Gesture(_) => {
+                anyhow::bail!("{:?} must be mapped to mouse input first", event)
+            }
+        }
+    }
+}
+
+impl Drop for CGEventBackend {
+    fn drop(&mut self) {
+        if !self.source.is_null() {
+            // SAFETY: source was created by us and is released once
+            unsafe { CFRelease(self.source) };
+        }
+    }
+}
@@ -0,0 +1,364 @@
+use crate::audit::AuditSession;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::time::Instant;
+use tracing::{debug, warn};
+
+mod keymap;
+mod layout;
+mod mouse;
+mod sanitize;
+#[cfg(target_os = "linux")]
+mod linux;
+#[cfg(target_os = "macos")]
+mod macos;
+mod touch;
+#[cfg(target_os = "windows")]
+mod windows;
+
+pub use keymap::KeyCode;
+pub use layout::KeyMode;
+pub use mouse::MouseMode;
+pub use touch::{Gesture, TouchPhase};
+
+/// A mouse button, as pressed on the viewer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+}
+
+/// Modifier keys held on the viewer when an input event happened
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Modifiers {
+    pub shift: bool,
+    pub ctrl: bool,
+    pub alt: bool,
+    pub meta: bool,
+}
+
+/// Keyboard or mouse input from the viewer, to be replayed on the host.
+/// Pointer positions are in frame pixels, matching what the viewer shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InputEvent {
+    /// A physical key, with the character it typed on the viewer's layout
+    /// if any, for hosts with a different layout
+    Key { key: KeyCode, pressed: bool, text: Option<char> },
+    /// Type a character, whatever the host's layout
+    Text(char),
+    MouseMove { x: u32, y: u32 },
+    /// Move the pointer by this many pixels, for relative mouse mode
+    MouseMotion { dx: i32, dy: i32 },
+    MouseButton { button: MouseButton, pressed: bool },
+    /// Scroll by whole lines; positive `dy` scrolls down, positive `dx` right
+    Scroll { dx: i32, dy: i32 },
+    /// One finger of a touch screen; `id` tells simultaneous fingers apart
+    Touch { id: u32, phase: TouchPhase, x: u32, y: u32 },
+    Gesture(Gesture),
+}
+
+/// What a viewer may do in a session. Ordered, so the session gets the
+/// lesser of what the host offers and what the viewer asks for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default, Serialize, Deserialize)]
+pub enum Permission {
+    /// Watch only; input from the viewer is dropped
+    #[default]
+    ViewOnly,
+    /// Drive the host's keyboard and mouse
+    Control,
+}
+
+impl Permission {
+    /// The permission granted when the host offers `offered` and the viewer
+    /// requests `requested`
+    pub fn negotiate(offered: Permission, requested: Permission) -> Permission {
+        offered.min(requested)
+    }
+}
+
+/// Host-side settings for remote control
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InputConfig {
+    /// Let viewers drive this machine's keyboard and mouse. Off by default:
+    /// a host only shares its screen unless it opts in.
+    pub allow_control: bool,
+    /// Whether typing goes by character or by physical key
+    pub key_mode: KeyMode,
+    /// Most events a viewer may send per second; extra presses and moves
+    /// are dropped, releases never are
+    pub max_events_per_second: u32,
+    /// Key combinations a viewer may never complete. Modifiers match on
+    /// either side of the keyboard.
+    pub blocked_hotkeys: Vec<Vec<KeyCode>>,
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        Self {
+            allow_control: false,
+            key_mode: KeyMode::default(),
+            max_events_per_second: 1000,
+            blocked_hotkeys: vec![
+                // Secure attention / task manager
+                vec![KeyCode::LEFT_CTRL, KeyCode::LEFT_ALT, KeyCode::DELETE],
+                // Kills the X server where zapping is enabled
+                vec![KeyCode::LEFT_CTRL, KeyCode::LEFT_ALT, KeyCode::BACKSPACE],
+            ],
+        }
+    }
+}
+
+/// Injects input events into the host's OS. `RemoteInput` maps touch and
+/// gestures to keyboard and mouse input first, so backends only see those.
+pub trait InputBackend: Send {
+    fn inject(&mut self, event: &InputEvent) -> Result<()>;
+}
+
+/// Open the platform's input injector: uinput on Linux, CGEvent on macOS
+/// and SendInput on Windows. `width` x `height` is the captured frame size.
+pub fn native_backend(width: u32, height: u32) -> Result<Box<dyn InputBackend>> {
+    #[cfg(target_os = "linux")]
+    return Ok(Box::new(linux::UinputBackend::new(width, height)?));
+    #[cfg(target_os = "macos")]
+    return Ok(Box::new(macos::CGEventBackend::new(width, height)));
+    #[cfg(target_os = "windows")]
+    return Ok(Box::new(windows::SendInputBackend::new(width, height)));
+    #[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+    {
+        let _ = (width, height);
+        anyhow::bail!("Input injection is not supported on this platform");
+    }
+}
+
+/// Applies the viewer's input on the host, if the host allows it.
+///
+/// Keys and buttons still held are tracked so they can be released when
+/// the viewer goes away, instead of staying stuck down on the host.
+pub struct RemoteInput {
+    backend: Option<Box<dyn InputBackend>>,
+    permission: Permission,
+    held_keys: BTreeSet<KeyCode>,
+    held_buttons: BTreeSet<MouseButton>,
+    touch: touch::TouchMapper,
+    pointer: mouse::PointerMapper,
+    keys: layout::KeyTranslator,
+    sanitizer: sanitize::Sanitizer,
+    injected: u64,
+    ignored: u64,
+    rejected: u64,
+    audit: Option<AuditSession>,
+}
+
+impl RemoteInput {
+    /// Open the native injector if `config` allows control; otherwise all
+    /// input is ignored. `width` x `height` is the shared frame size.
+    pub fn new(config: &InputConfig, width: u32, height: u32) -> Result<Self> {
+        let backend = if config.allow_control {
+            Some(native_backend(width, height)?)
+        } else {
+            None
+        };
+        let mut input = Self::from_backend(config, backend);
+        input.set_bounds(width, height);
+        Ok(input)
+    }
+
+    /// Inject through `backend` if `config` allows control
+    pub fn with_backend(config: &InputConfig, backend: Box<dyn InputBackend>) -> Self {
+        Self::from_backend(config, config.allow_control.then_some(backend))
+    }
+
+    fn from_backend(config: &InputConfig, backend: Option<Box<dyn InputBackend>>) -> Self {
+        let permission = if backend.is_some() {
+            Permission::Control
+        } else {
+            Permission::ViewOnly
+        };
+        Self {
+            backend,
+            permission,
+            held_keys: BTreeSet::new(),
+            held_buttons: BTreeSet::new(),
+            touch: touch::TouchMapper::default(),
+            pointer: mouse::PointerMapper::default(),
+            keys: layout::KeyTranslator::new(config.key_mode),
+            sanitizer: sanitize::Sanitizer::new(config.max_events_per_second, &config.blocked_hotkeys),
+            injected: 0,
+            ignored: 0,
+            rejected: 0,
+            audit: None,
+        }
+    }
+
+    /// Only accept pointer positions within a `width` x `height` frame
+    pub fn set_bounds(&mut self, width: u32, height: u32) {
+        self.sanitizer.set_bounds(width, height);
+    }
+
+    /// Whether input from the viewer reaches the host
+    pub fn is_enabled(&self) -> bool {
+        self.permission == Permission::Control
+    }
+
+    /// The most this host offers viewers at the handshake
+    pub fn offered_permission(&self) -> Permission {
+        if self.backend.is_some() {
+            Permission::Control
+        } else {
+            Permission::ViewOnly
+        }
+    }
+
+    /// The current viewer's permission
+    pub fn permission(&self) -> Permission {
+        self.permission
+    }
+
+    /// Apply the permission negotiated for a session, or change it mid
+    /// session. Never exceeds what the host offers. Returns the permission
+    /// now in effect.
+    pub fn set_permission(&mut self, permission: Permission) -> Permission {
+        self.permission = permission.min(self.offered_permission());
+        if self.permission == Permission::ViewOnly {
+            self.release_all();
+        }
+        if let Some(audit) = &self.audit {
+            audit.permission(self.permission);
+        }
+        self.permission
+    }
+
+    /// Current mouse mode, as last switched to by the viewer
+    pub fn mouse_mode(&self) -> MouseMode {
+        self.pointer.mode()
+    }
+
+    /// Switch between absolute and relative pointer movement. In relative
+    /// mode absolute positions are ignored; in absolute mode motion moves
+    /// on from the last known position.
+    pub fn set_mouse_mode(&mut self, mode: MouseMode) {
+        debug!("Mouse mode: {:?}", mode);
+        self.pointer.set_mode(mode);
+    }
+
+    /// Take control away from the viewer, releasing anything it held down
+    pub fn revoke_control(&mut self) {
+        self.set_permission(Permission::ViewOnly);
+    }
+
+    /// Inject `event`. Returns false if it was dropped, because the viewer
+    /// may not control the host or the event failed sanitization.
+    pub fn handle(&mut self, event: InputEvent) -> Result<bool> {
+        let result = self.apply(event);
+        if let Some(audit) = &self.audit {
+            audit.input(event, matches!(result, Ok(true)));
+        }
+        result
+    }
+
+    /// Record permission changes and every event handled to `audit`
+    pub fn set_audit(&mut self, audit: AuditSession) {
+        self.audit = Some(audit);
+    }
+
+    fn apply(&mut self, event: InputEvent) -> Result<bool> {
+        let backend = match self.backend.as_mut() {
+            Some(backend) if self.permission == Permission::Control => backend,
+            _ => {
+                debug!("Viewer is view-only, ignoring {:?}", event);
+                self.ignored += 1;
+                return Ok(false);
+            }
+        };
+
+        let Some(event) = self.sanitizer.check(event, Instant::now()) else {
+            self.rejected += 1;
+            return Ok(false);
+        };
+
+        let mut blocked = false;
+        for event in self.touch.map(event) {
+            let translated = self
+                .pointer
+                .map(event)
+                .and_then(|event| self.keys.translate(event, &self.held_keys));
+            let Some(event) = translated else {
+                continue;
+            };
+            if let InputEvent::Key { key, pressed, .. } = event {
+                if self.sanitizer.blocks(key, pressed, &self.held_keys) {
+                    blocked |= pressed;
+                    continue;
+                }
+            }
+            backend.inject(&event)?;
+            match event {
+                InputEvent::Key { key, pressed: true, .. } => {
+                    self.held_keys.insert(key);
+                }
+                InputEvent::Key { key, pressed: false, .. } => {
+                    self.held_keys.remove(&key);
+                }
+                InputEvent::MouseButton { button, pressed: true } => {
+                    self.held_buttons.insert(button);
+                }
+                InputEvent::MouseButton { button, pressed: false } => {
+                    self.held_buttons.remove(&button);
+                }
+                _ => {}
+            }
+        }
+        if blocked {
+            self.rejected += 1;
+            return Ok(false);
+        }
+        self.injected += 1;
+        Ok(true)
+    }
+
+    /// Release every key and button the viewer left pressed
+    pub fn release_all(&mut self) {
+        self.touch.reset();
+        self.keys.reset();
+        self.sanitizer.reset();
+        let keys = std::mem::take(&mut self.held_keys);
+        let buttons = std::mem::take(&mut self.held_buttons);
+        let Some(backend) = self.backend.as_mut() else {
+            return;
+        };
+
+        let releases = keys
+            .into_iter()
+            .map(|key| InputEvent::Key { key, pressed: false, text: None })
+            .chain(buttons.into_iter().map(|button| InputEvent::MouseButton { button, pressed: false }));
+        for event in releases {
+            if let Err(e) = backend.inject(&event) {
+                warn!("Failed to release {:?}: {}", event, e);
+            }
+        }
+    }
+
+    /// Number of events injected on the host
+    pub fn injected(&self) -> u64 {
+        self.injected
+    }
+
+    /// Number of events dropped because the viewer may not control the host
+    pub fn ignored(&self) -> u64 {
+        self.ignored
+    }
+
+    /// Number of events dropped as out of bounds, over the rate limit or
+    /// completing a blocked hotkey
+    pub fn rejected(&self) -> u64 {
+        self.rejected
+    }
+}
+
+impl Drop for RemoteInput {
+    fn drop(&mut self) {
+        self.release_all();
+    }
+}
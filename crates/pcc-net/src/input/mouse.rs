@@ -0,0 +1,51 @@
+use super::InputEvent;
+use serde::{Deserialize, Serialize};
+
+/// How the viewer's mouse drives the host pointer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum MouseMode {
+    /// The host pointer goes where the viewer's pointer is over the frame
+    #[default]
+    Absolute,
+    /// The viewer captures its mouse and sends movement only, for games and
+    /// CAD tools that read raw motion or keep warping the pointer
+    Relative,
+}
+
+/// Applies the mouse mode, keeping track of where the host pointer is so
+/// motion can still be applied in absolute mode
+#[derive(Debug, Default)]
+pub(crate) struct PointerMapper {
+    mode: MouseMode,
+    position: Option<(u32, u32)>,
+}
+
+impl PointerMapper {
+    pub(crate) fn mode(&self) -> MouseMode {
+        self.mode
+    }
+
+    pub(crate) fn set_mode(&mut self, mode: MouseMode) {
+        self.mode = mode;
+    }
+
+    /// `None` means the event doesn't apply in the current mode
+    pub(crate) fn map(&mut self, event: InputEvent) -> Option<InputEvent> {
+        match (self.mode, event) {
+            (MouseMode::Absolute, InputEvent::MouseMove { x, y }) => {
+                self.position = Some((x, y));
+                Some(event)
+            }
+            (MouseMode::Absolute, InputEvent::MouseMotion { dx, dy }) => {
+                let (x, y) = self.position?;
+                let x = x.saturating_add_signed(dx);
+                let y = y.saturating_add_signed(dy);
+                self.position = Some((x, y));
+                Some(InputEvent::MouseMove { x, y })
+            }
+            // A stray absolute position would yank the captured pointer
+            (MouseMode::Relative, InputEvent::MouseMove { .. }) => None,
+            _ => Some(event),
+        }
+    }
+}
@@ -0,0 +1,154 @@
+use super::{Gesture, InputEvent, KeyCode, TouchPhase};
+use std::collections::BTreeSet;
+use std::time::Instant;
+use tracing::debug;
+
+// Largest scroll or zoom a single event may ask for
+const MAX_SCROLL_LINES: i32 = 100;
+
+/// Checks viewer input before it reaches the host: positions must fall
+/// inside the shared frame, events are rate limited, and blocked hotkeys
+/// never complete.
+pub(crate) struct Sanitizer {
+    bounds: Option<(u32, u32)>,
+    max_rate: u32,
+    // Token bucket allowing bursts of up to one second's worth of events
+    tokens: f64,
+    refilled: Instant,
+    blocked: Vec<Vec<KeyCode>>,
+    /// Keys whose press completed a blocked hotkey, so their release is
+    /// swallowed too
+    suppressed: BTreeSet<KeyCode>,
+}
+
+impl Sanitizer {
+    pub(crate) fn new(max_rate: u32, blocked: &[Vec<KeyCode>]) -> Self {
+        Self {
+            bounds: None,
+            max_rate,
+            tokens: max_rate as f64,
+            refilled: Instant::now(),
+            blocked: blocked
+                .iter()
+                .map(|combo| combo.iter().map(|key| key.left_hand()).collect())
+                .collect(),
+            suppressed: BTreeSet::new(),
+        }
+    }
+
+    pub(crate) fn set_bounds(&mut self, width: u32, height: u32) {
+        self.bounds = Some((width, height));
+    }
+
+    /// Validate an event from the viewer, clamping what can be clamped.
+    /// `None` means it was rejected.
+    pub(crate) fn check(&mut self, event: InputEvent, now: Instant) -> Option<InputEvent> {
+        // Releases always go through so nothing is left held down
+        if !is_release(&event) && !self.take_token(now) {
+            debug!("Input rate limit exceeded, dropping {:?}", event);
+            return None;
+        }
+
+        let clamp_lines = |lines: i32| lines.clamp(-MAX_SCROLL_LINES, MAX_SCROLL_LINES);
+        match event {
+            InputEvent::MouseMove { x, y } | InputEvent::Gesture(Gesture::Pinch { x, y, .. })
+                if !self.contains(x, y) =>
+            {
+                debug!("Rejecting {:?} outside the shared region", event);
+                None
+            }
+            InputEvent::Touch { id, phase, x, y } if !self.contains(x, y) => match phase {
+                // Clamp the end of a touch rather than leave it down
+                TouchPhase::Ended | TouchPhase::Cancelled => {
+                    let (x, y) = self.clamp(x, y);
+                    Some(InputEvent::Touch { id, phase, x, y })
+                }
+                _ => None,
+            },
+            InputEvent::MouseMotion { dx, dy } => {
+                let (width, height) = self.bounds.unwrap_or((u32::MAX, u32::MAX));
+                let limit = |delta: i32, size: u32| {
+                    let size = size.min(i32::MAX as u32) as i32;
+                    delta.clamp(-size, size)
+                };
+                Some(InputEvent::MouseMotion {
+                    dx: limit(dx, width),
+                    dy: limit(dy, height),
+                })
+            }
+            InputEvent::Scroll { dx, dy } => Some(InputEvent::Scroll {
+                dx: clamp_lines(dx),
+                dy: clamp_lines(dy),
+            }),
+            InputEvent::Gesture(Gesture::TwoFingerScroll { dx, dy }) => {
+                Some(InputEvent::Gesture(Gesture::TwoFingerScroll {
+                    dx: clamp_lines(dx),
+                    dy: clamp_lines(dy),
+                }))
+            }
+            InputEvent::Gesture(Gesture::Pinch { x, y, steps }) => Some(InputEvent::Gesture(Gesture::Pinch {
+                x,
+                y,
+                steps: clamp_lines(steps),
+            })),
+            other => Some(other),
+        }
+    }
+
+    /// Whether a key press or release must not reach the host because it
+    /// completes a blocked hotkey, given the keys already held
+    pub(crate) fn blocks(&mut self, key: KeyCode, pressed: bool, held: &BTreeSet<KeyCode>) -> bool {
+        if !pressed {
+            return self.suppressed.remove(&key);
+        }
+
+        let down: BTreeSet<KeyCode> = held.iter().chain([&key]).map(|key| key.left_hand()).collect();
+        let blocked = self
+            .blocked
+            .iter()
+            .any(|combo| combo.contains(&key.left_hand()) && combo.iter().all(|key| down.contains(key)));
+        if blocked {
+            debug!("Blocked hotkey completed by {:?}", key);
+            self.suppressed.insert(key);
+        }
+        blocked
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.suppressed.clear();
+    }
+
+    fn take_token(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.refilled).as_secs_f64();
+        self.refilled = now;
+        self.tokens = (self.tokens + elapsed * self.max_rate as f64).min(self.max_rate as f64);
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+
+    fn contains(&self, x: u32, y: u32) -> bool {
+        self.bounds.is_none_or(|(width, height)| x < width && y < height)
+    }
+
+    fn clamp(&self, x: u32, y: u32) -> (u32, u32) {
+        match self.bounds {
+            Some((width, height)) => (x.min(width.saturating_sub(1)), y.min(height.saturating_sub(1))),
+            None => (x, y),
+        }
+    }
+}
+
+fn is_release(event: &InputEvent) -> bool {
+    matches!(
+        event,
+        InputEvent::Key { pressed: false, .. }
+            | InputEvent::MouseButton { pressed: false, .. }
+            | InputEvent::Touch {
+                phase: TouchPhase::Ended | TouchPhase::Cancelled,
+                ..
+            }
+    )
+}
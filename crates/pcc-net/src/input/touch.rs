@@ -0,0 +1,119 @@
+use super::{InputEvent, KeyCode, MouseButton};
+use serde::{Deserialize, Serialize};
+
+// Held while scrolling to zoom
+const CTRL: KeyCode = KeyCode::LEFT_CTRL;
+
+/// Where a touch is in its lifetime
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TouchPhase {
+    Began,
+    Moved,
+    Ended,
+    /// The viewer lost track of the touch, e.g. it turned into a gesture
+    Cancelled,
+}
+
+/// A multi-finger gesture recognized on the viewer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Gesture {
+    /// Pinch around (`x`, `y`) by whole zoom steps; positive zooms in
+    Pinch { x: u32, y: u32, steps: i32 },
+    /// Scroll by whole lines, with the same directions as `InputEvent::Scroll`
+    TwoFingerScroll { dx: i32, dy: i32 },
+}
+
+/// Maps touch and gestures onto the mouse and keyboard input every host
+/// can inject. The first finger down drives the pointer like a left-button
+/// drag; further fingers are left to the viewer's gesture recognizer.
+#[derive(Debug, Default)]
+pub(crate) struct TouchMapper {
+    primary: Option<u32>,
+}
+
+impl TouchMapper {
+    pub(crate) fn map(&mut self, event: InputEvent) -> Vec<InputEvent> {
+        match event {
+            InputEvent::Touch { id, phase, x, y } => self.touch(id, phase, x, y),
+            // Desktop apps zoom on Ctrl+scroll, and scroll up zooms in
+            InputEvent::Gesture(Gesture::Pinch { x, y, steps }) => vec![
+                InputEvent::MouseMove { x, y },
+                InputEvent::Key { key: CTRL, pressed: true, text: None },
+                InputEvent::Scroll { dx: 0, dy: -steps },
+                InputEvent::Key { key: CTRL, pressed: false, text: None },
+            ],
+            InputEvent::Gesture(Gesture::TwoFingerScroll { dx, dy }) => vec![InputEvent::Scroll { dx, dy }],
+            other => vec![other],
+        }
+    }
+
+    fn touch(&mut self, id: u32, phase: TouchPhase, x: u32, y: u32) -> Vec<InputEvent> {
+        let press = |pressed| InputEvent::MouseButton {
+            button: MouseButton::Left,
+            pressed,
+        };
+
+        match phase {
+            TouchPhase::Began if self.primary.is_none() => {
+                self.primary = Some(id);
+                vec![InputEvent::MouseMove { x, y }, press(true)]
+            }
+            TouchPhase::Moved if self.primary == Some(id) => vec![InputEvent::MouseMove { x, y }],
+            TouchPhase::Ended if self.primary == Some(id) => {
+                self.primary = None;
+                vec![InputEvent::MouseMove { x, y }, press(false)]
+            }
+            // Release without moving, so a cancelled touch doesn't drop
+            // whatever it was dragging somewhere new
+            TouchPhase::Cancelled if self.primary == Some(id) => {
+                self.primary = None;
+                vec![press(false)]
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Forget the finger in progress, e.g. when the viewer goes away
+    pub(crate) fn reset(&mut self) {
+        self.primary = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_first_finger_drags_and_others_are_ignored() {
+        let mut mapper = TouchMapper::default();
+        let touch = |id, phase, x| InputEvent::Touch { id, phase, x, y: 5 };
+        let left = |pressed| InputEvent::MouseButton { button: MouseButton::Left, pressed };
+
+        assert_eq!(
+            mapper.map(touch(1, TouchPhase::Began, 10)),
+            vec![InputEvent::MouseMove { x: 10, y: 5 }, left(true)]
+        );
+        assert!(mapper.map(touch(2, TouchPhase::Began, 50)).is_empty());
+        assert_eq!(mapper.map(touch(1, TouchPhase::Moved, 20)), vec![InputEvent::MouseMove { x: 20, y: 5 }]);
+        assert!(mapper.map(touch(2, TouchPhase::Moved, 60)).is_empty());
+        assert_eq!(mapper.map(touch(1, TouchPhase::Cancelled, 30)), vec![left(false)]);
+
+        // The next touch takes over as the pointer
+        assert_eq!(mapper.map(touch(2, TouchPhase::Began, 70)).len(), 2);
+    }
+
+    #[test]
+    fn test_gestures_map_to_scrolling() {
+        let mut mapper = TouchMapper::default();
+        assert_eq!(
+            mapper.map(InputEvent::Gesture(Gesture::TwoFingerScroll { dx: 1, dy: -2 })),
+            vec![InputEvent::Scroll { dx: 1, dy: -2 }]
+        );
+
+        let pinch = mapper.map(InputEvent::Gesture(Gesture::Pinch { x: 3, y: 4, steps: 2 }));
+        assert_eq!(pinch[0], InputEvent::MouseMove { x: 3, y: 4 });
+        assert_eq!(pinch[2], InputEvent::Scroll { dx: 0, dy: -2 });
+        assert_eq!(pinch[1], InputEvent::Key { key: CTRL, pressed: true, text: None });
+        assert_eq!(pinch[3], InputEvent::Key { key: CTRL, pressed: false, text: None });
+    }
+}
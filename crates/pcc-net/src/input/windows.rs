@@ -0,0 +1,179 @@
+use super::keymap::EXTENDED;
+use super::{InputBackend, InputEvent, MouseButton};
+use anyhow::{Context, Result};
+
+// Values from WinUser.h
+const INPUT_MOUSE: u32 = 0;
+const INPUT_KEYBOARD: u32 = 1;
+const KEYEVENTF_EXTENDEDKEY: u32 = 0x0001;
+const KEYEVENTF_KEYUP: u32 = 0x0002;
+const KEYEVENTF_UNICODE: u32 = 0x0004;
+const KEYEVENTF_SCANCODE: u32 = 0x0008;
+const MOUSEEVENTF_MOVE: u32 = 0x0001;
+const MOUSEEVENTF_LEFTDOWN: u32 = 0x0002;
+const MOUSEEVENTF_LEFTUP: u32 = 0x0004;
+const MOUSEEVENTF_RIGHTDOWN: u32 = 0x0008;
+const MOUSEEVENTF_RIGHTUP: u32 = 0x0010;
+const MOUSEEVENTF_MIDDLEDOWN: u32 = 0x0020;
+const MOUSEEVENTF_MIDDLEUP: u32 = 0x0040;
+const MOUSEEVENTF_WHEEL: u32 = 0x0800;
+const MOUSEEVENTF_HWHEEL: u32 = 0x1000;
+const MOUSEEVENTF_ABSOLUTE: u32 = 0x8000;
+const WHEEL_DELTA: i32 = 120;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct MouseInput {
+    dx: i32,
+    dy: i32,
+    mouse_data: u32,
+    flags: u32,
+    time: u32,
+    extra_info: usize,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct KeybdInput {
+    vk: u16,
+    scan: u16,
+    flags: u32,
+    time: u32,
+    extra_info: usize,
+}
+
+#[repr(C)]
+union InputUnion {
+    mi: MouseInput,
+    ki: KeybdInput,
+}
+
+#[repr(C)]
+struct Input {
+    kind: u32,
+    u: InputUnion,
+}
+
+#[link(name = "user32")]
+extern "system" {
+    fn SendInput(count: u32, inputs: *const Input, size: i32) -> u32;
+}
+
+/// Synthesizes input with SendInput. Keys are sent as scan codes so the
+/// host's own layout applies, like a physical keyboard.
+pub(super) struct SendInputBackend {
+    width: u32,
+    height: u32,
+}
+
+impl SendInputBackend {
+    pub(super) fn new(width: u32, height: u32) -> Self {
+        Self { width, height }
+    }
+
+    fn send(&self, inputs: &[Input]) -> Result<()> {
+        // SAFETY: inputs is a valid slice of INPUT structs
+        let sent = unsafe { SendInput(inputs.len() as u32, inputs.as_ptr(), std::mem::size_of::<Input>() as i32) };
+        if sent as usize != inputs.len() {
+            return Err(std::io::Error::last_os_error()).context("SendInput was blocked");
+        }
+        Ok(())
+    }
+
+    fn key(scan: u16, flags: u32) -> Input {
+        Input {
+            kind: INPUT_KEYBOARD,
+            u: InputUnion {
+                ki: KeybdInput {
+                    vk: 0,
+                    scan,
+                    flags,
+                    time: 0,
+                    extra_info: 0,
+                },
+            },
+        }
+    }
+
+    fn mouse(dx: i32, dy: i32, mouse_data: i32, flags: u32) -> Input {
+        Input {
+            kind: INPUT_MOUSE,
+            u: InputUnion {
+                mi: MouseInput {
+                    dx,
+                    dy,
+                    mouse_data: mouse_data as u32,
+                    flags,
+                    time: 0,
+                    extra_info: 0,
+                },
+            },
+        }
+    }
+
+    // Absolute positions are normalized to 0..=65535 across the primary screen
+    fn normalize(value: u32, size: u32) -> i32 {
+        (value as u64 * 65535 / size.saturating_sub(1).max(1) as u64).min(65535) as i32
+    }
+}
+
+impl InputBackend for SendInputBackend {
+    fn inject(&mut self, event: &InputEvent) -> Result<()> {
+        match *event {
+            InputEvent::Key { key, pressed, .. } => {
+                let scan = key
+                    .windows_scan_code()
+                    .with_context(|| format!("No Windows scan code for {:?}", key))?;
+                let mut flags = KEYEVENTF_SCANCODE;
+                if scan & EXTENDED != 0 {
+                    flags |= KEYEVENTF_EXTENDEDKEY;
+                }
+                if !pressed {
+                    flags |= KEYEVENTF_KEYUP;
+                }
+                self.send(&[Self::key(scan & !EXTENDED, flags)])
+            }
+            // Unicode input types the character whatever the host's layout
+            InputEvent::Text(c) => {
+                let mut units = [0u16; 2];
+                let inputs: Vec<Input> = c
+                    .encode_utf16(&mut units)
+                    .iter()
+                    .flat_map(|&unit| {
+                        [
+                            Self::key(unit, KEYEVENTF_UNICODE),
+                            Self::key(unit, KEYEVENTF_UNICODE | KEYEVENTF_KEYUP),
+                        ]
+                    })
+                    .collect();
+                self.send(&inputs)
+            }
+            InputEvent::MouseMove { x, y } => self.send(&[Self::mouse(
+                Self::normalize(x, self.width),
+                Self::normalize(y, self.height),
+                0,
+                MOUSEEVENTF_MOVE | MOUSEEVENTF_ABSOLUTE,
+            )]),
+            InputEvent::MouseMotion { dx, dy } => self.send(&[Self::mouse(dx, dy, 0, MOUSEEVENTF_MOVE)]),
+            InputEvent::MouseButton { button, pressed } => {
+                let flags = match (button, pressed) {
+                    (MouseButton::Left, true) => MOUSEEVENTF_LEFTDOWN,
+                    (MouseButton::Left, false) => MOUSEEVENTF_LEFTUP,
+                    (MouseButton::Right, true) => MOUSEEVENTF_RIGHTDOWN,
+                    (MouseButton::Right, false) => MOUSEEVENTF_RIGHTUP,
+                    (MouseButton::Middle, true) => MOUSEEVENTF_MIDDLEDOWN,
+                    (MouseButton::Middle, false) => MOUSEEVENTF_MIDDLEUP,
+                };
+                self.send(&[Self::mouse(0, 0, 0, flags)])
+            }
+            // Positive wheel data scrolls up (and right for the horizontal wheel)
+            InputEvent::Scroll { dx, dy } => self.send(&[
+                Self::mouse(0, 0, -dy * WHEEL_DELTA, MOUSEEVENTF_WHEEL),
+                Self::mouse(0, 0, dx * WHEEL_DELTA, MOUSEEVENTF_HWHEEL),
+            ]),
+            InputEvent::Touch { .. } | InputEvent::Gesture(_) => {
+                anyhow::bail!("{:?} must be mapped to mouse input first", event)
+            }
+        }
+    }
+}
@@ -0,0 +1,14 @@
+//! A session between host and viewer: the QUIC connection and its protocol,
+//! and what travels over it. That is the frame encoder, audio, and remote
+//! input, along with the audit log, session log, telemetry and health
+//! checks that describe a session. They use one another, so they share a
+//! crate; the viewer builds on it without screen capture.
+
+pub mod audio;
+pub mod audit;
+pub mod encoder;
+pub mod health;
+pub mod input;
+pub mod network;
+pub mod session_log;
+pub mod telemetry;
@@ -0,0 +1,170 @@
+use super::identity::Fingerprint;
+use anyhow::{ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// A range of addresses, written like `192.168.1.0/24`, `fd00::/8` or a
+/// single address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Subnet {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Subnet {
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // IPv4 peers on a dual-stack socket show up as mapped IPv6
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            v4 => v4,
+        };
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX.checked_shl(32 - self.prefix as u32).unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX.checked_shl(128 - self.prefix as u32).unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Subnet {
+    type Err = anyhow::Error;
+
+    fn from_str(text: &str) -> Result<Self> {
+        let (addr, prefix) = match text.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (text, None),
+        };
+        let addr: IpAddr = addr.parse().with_context(|| format!("Invalid address in {:?}", text))?;
+        let bits = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse().with_context(|| format!("Invalid prefix length in {:?}", text))?,
+            None => bits,
+        };
+        ensure!(prefix <= bits, "Prefix length in {:?} is over {}", text, bits);
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl TryFrom<String> for Subnet {
+    type Error = anyhow::Error;
+
+    fn try_from(text: String) -> Result<Self> {
+        text.parse()
+    }
+}
+
+impl From<Subnet> for String {
+    fn from(subnet: Subnet) -> Self {
+        subnet.to_string()
+    }
+}
+
+impl fmt::Display for Subnet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// Whom an access list entry matches: the holder of a certificate, or
+/// anyone connecting from a subnet
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum PeerMatch {
+    Fingerprint(Fingerprint),
+    Subnet(Subnet),
+}
+
+impl PeerMatch {
+    pub fn matches(&self, ip: IpAddr, fingerprint: Option<Fingerprint>) -> bool {
+        match self {
+            PeerMatch::Fingerprint(expected) => fingerprint == Some(*expected),
+            PeerMatch::Subnet(subnet) => subnet.contains(ip),
+        }
+    }
+}
+
+impl FromStr for PeerMatch {
+    type Err = anyhow::Error;
+
+    /// A certificate fingerprint, as `pcc cert fingerprint` prints it, or
+    /// a subnet
+    fn from_str(text: &str) -> Result<Self> {
+        if let Ok(fingerprint) = text.parse() {
+            return Ok(PeerMatch::Fingerprint(fingerprint));
+        }
+        text.parse()
+            .map(PeerMatch::Subnet)
+            .with_context(|| format!("{:?} is neither a certificate fingerprint nor a subnet", text))
+    }
+}
+
+impl TryFrom<String> for PeerMatch {
+    type Error = anyhow::Error;
+
+    fn try_from(text: String) -> Result<Self> {
+        text.parse()
+    }
+}
+
+impl From<PeerMatch> for String {
+    fn from(peer: PeerMatch) -> Self {
+        match peer {
+            PeerMatch::Fingerprint(fingerprint) => fingerprint.to_string(),
+            PeerMatch::Subnet(subnet) => subnet.to_string(),
+        }
+    }
+}
+
+/// Which hosts a viewer accepts connections from, from the
+/// `[network.access]` section. Denials win; with any allow entries, a host
+/// must match one of them.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccessControl {
+    pub allow: Vec<PeerMatch>,
+    pub deny: Vec<PeerMatch>,
+}
+
+impl AccessControl {
+    /// Whether a host connecting from `ip`, with a certificate of
+    /// `fingerprint` if it presented one, may connect
+    pub fn permits(&self, ip: IpAddr, fingerprint: Option<Fingerprint>) -> bool {
+        let matching = |entries: &[PeerMatch]| entries.iter().any(|entry| entry.matches(ip, fingerprint));
+        !matching(&self.deny) && (self.allow.is_empty() || matching(&self.allow))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deny_wins_over_allow() {
+        let fingerprint = Fingerprint::from_str(&"AB".repeat(32)).unwrap();
+        let access = AccessControl {
+            allow: vec!["192.168.1.0/24".parse().unwrap(), PeerMatch::Fingerprint(fingerprint)],
+            deny: vec!["192.168.1.13".parse().unwrap()],
+        };
+        let ip = |text: &str| text.parse::<IpAddr>().unwrap();
+        assert!(access.permits(ip("192.168.1.20"), None));
+        assert!(access.permits(ip("::ffff:192.168.1.20"), None));
+        assert!(!access.permits(ip("192.168.1.13"), Some(fingerprint)));
+        assert!(!access.permits(ip("10.0.0.1"), None));
+        assert!(access.permits(ip("10.0.0.1"), Some(fingerprint)));
+        assert!(AccessControl::default().permits(ip("10.0.0.1"), None));
+
+        assert!("0.0.0.0/0".parse::<Subnet>().unwrap().contains(ip("8.8.8.8")));
+        assert!("fd00::/8".parse::<Subnet>().unwrap().contains(ip("fd12::1")));
+        assert!("10.0.0.0/33".parse::<Subnet>().is_err());
+        assert!("not-a-peer".parse::<PeerMatch>().is_err());
+    }
+}
@@ -0,0 +1,54 @@
+use super::identity::Fingerprint;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// Who a host is about to share with, for deciding whether to
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ViewerInfo {
+    /// Name the viewer introduced itself by, if it gave one
+    pub name: Option<String>,
+    /// Fingerprint of the certificate the viewer presented
+    pub fingerprint: Option<Fingerprint>,
+    pub addr: SocketAddr,
+}
+
+impl fmt::Display for ViewerInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} at {}", self.name.as_deref().unwrap_or("unnamed viewer"), self.addr)?;
+        if let Some(fingerprint) = self.fingerprint {
+            write!(f, " ({})", fingerprint)?;
+        }
+        Ok(())
+    }
+}
+
+// Decides on one viewer, possibly blocking
+type Prompt = dyn Fn(&ViewerInfo) -> bool + Send + Sync;
+
+/// Asks whether a viewer that isn't trusted may see the screen, e.g. by
+/// prompting the host's user. Clones share the prompt; the default has no
+/// one to ask, so approves no one.
+#[derive(Clone, Default)]
+pub struct ViewerApproval {
+    prompt: Option<Arc<Prompt>>,
+}
+
+impl fmt::Debug for ViewerApproval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ViewerApproval").field("prompt", &self.prompt.is_some()).finish()
+    }
+}
+
+impl ViewerApproval {
+    /// Ask `prompt`, which may block, e.g. on terminal input
+    pub fn new(prompt: impl Fn(&ViewerInfo) -> bool + Send + Sync + 'static) -> Self {
+        Self { prompt: Some(Arc::new(prompt)) }
+    }
+
+    /// Whether `viewer` may see the screen
+    pub fn approve(&self, viewer: &ViewerInfo) -> bool {
+        self.prompt.as_ref().is_some_and(|prompt| prompt(viewer))
+    }
+}
@@ -0,0 +1,279 @@
+use crate::audio::{BitrateControl, MAX_AUDIO_BITRATE, MIN_AUDIO_BITRATE};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use tokio::time;
+use tracing::debug;
+
+// How often the estimate is refreshed from the connection's stats
+const ADAPT_INTERVAL: Duration = Duration::from_millis(500);
+// Video bitrate below which audio starts giving up bandwidth too
+const MIN_VIDEO_BITRATE: u64 = 250_000;
+// Packet loss above this means the path is congested beyond what the
+// congestion window shows
+const LOSS_THRESHOLD: f64 = 0.02;
+// Weight of each new sample in the smoothed estimate
+const SMOOTHING: f64 = 0.25;
+// Most of its bitrate video saves up while it has nothing to send, so a
+// burst of changes after a quiet spell can't overshoot it for long
+const MAX_BUDGET_CREDIT: Duration = Duration::from_millis(250);
+
+/// Congestion feedback from the transport, sampled periodically
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct NetworkFeedback {
+    pub rtt: Duration,
+    /// Congestion window in bytes
+    pub cwnd: u64,
+    /// Packets sent since the connection opened
+    pub sent_packets: u64,
+    /// Packets lost since the connection opened
+    pub lost_packets: u64,
+}
+
+/// How the estimated bandwidth is split between the streams
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitrateAllocation {
+    /// Bits per second for video
+    pub video: u64,
+    /// Bits per second for each audio stream
+    pub audio: u32,
+}
+
+/// Estimates the bandwidth available to the session from congestion
+/// feedback, and shares it between video and audio. Video yields first:
+/// audio keeps its full bitrate until video is down to its minimum, since
+/// audio dropouts are more jarring than blurrier frames.
+#[derive(Debug)]
+pub struct BandwidthEstimator {
+    max_bitrate: u64,
+    estimate: Option<f64>,
+    last: Option<NetworkFeedback>,
+}
+
+impl BandwidthEstimator {
+    /// Start estimating, never above `max_bitrate` bits per second
+    pub fn new(max_bitrate: u64) -> Self {
+        Self {
+            max_bitrate,
+            estimate: None,
+            last: None,
+        }
+    }
+
+    /// Fold in new feedback, returning the updated estimate
+    pub fn update(&mut self, feedback: NetworkFeedback) -> u64 {
+        // The congestion window is what the path carries per round trip
+        let mut sample = if feedback.rtt.is_zero() {
+            self.max_bitrate as f64
+        } else {
+            feedback.cwnd as f64 * 8.0 / feedback.rtt.as_secs_f64()
+        };
+
+        if let Some(last) = self.last {
+            let sent = feedback.sent_packets.saturating_sub(last.sent_packets);
+            let lost = feedback.lost_packets.saturating_sub(last.lost_packets);
+            let loss = if sent == 0 { 0.0 } else { (lost as f64 / sent as f64).min(1.0) };
+            if loss > LOSS_THRESHOLD {
+                sample = sample.min(self.estimate.unwrap_or(sample)) * (1.0 - loss);
+            }
+        }
+        self.last = Some(feedback);
+
+        let sample = sample.min(self.max_bitrate as f64);
+        let estimate = match self.estimate {
+            Some(estimate) => estimate + (sample - estimate) * SMOOTHING,
+            None => sample,
+        };
+        self.estimate = Some(estimate);
+        estimate as u64
+    }
+
+    /// Estimated bits per second available, or the maximum before any
+    /// feedback
+    pub fn estimate(&self) -> u64 {
+        self.estimate.map_or(self.max_bitrate, |estimate| estimate as u64)
+    }
+
+    /// Split the estimate between video and `audio_streams` audio streams
+    pub fn allocate(&self, audio_streams: usize) -> BitrateAllocation {
+        let total = self.estimate();
+        if audio_streams == 0 {
+            return BitrateAllocation { video: total, audio: 0 };
+        }
+
+        let streams = audio_streams as u64;
+        let audio_total = total
+            .saturating_sub(MIN_VIDEO_BITRATE)
+            .clamp(MIN_AUDIO_BITRATE as u64 * streams, MAX_AUDIO_BITRATE as u64 * streams);
+        BitrateAllocation {
+            video: total.saturating_sub(audio_total),
+            audio: (audio_total / streams) as u32,
+        }
+    }
+}
+
+/// Paces video to a bitrate where frames are sent. Frames go out while the
+/// budget isn't overdrawn and are charged their bytes once sent, so a
+/// large keyframe holds back the frames after it until it's paid off.
+#[derive(Debug)]
+pub struct SendBudget {
+    // Bits per second, or 0 for no limit
+    bitrate: u64,
+    // Bytes that may be sent now, negative while paying off a frame
+    credit: f64,
+    last: Instant,
+}
+
+impl SendBudget {
+    /// An unlimited budget, until a bitrate is set
+    pub fn new(now: Instant) -> Self {
+        Self { bitrate: 0, credit: 0.0, last: now }
+    }
+
+    /// Pace to `bits_per_second` from now on, or stop pacing with 0
+    pub fn set_bitrate(&mut self, bits_per_second: u64) {
+        if bits_per_second == 0 {
+            self.credit = 0.0;
+        }
+        self.bitrate = bits_per_second;
+    }
+
+    /// Bits per second frames are paced to, or 0 for no limit
+    pub fn bitrate(&self) -> u64 {
+        self.bitrate
+    }
+
+    /// Whether a frame may be sent at `now`
+    pub fn allows(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        self.bitrate == 0 || self.credit >= 0.0
+    }
+
+    /// Charge `bytes` sent at `now` to the budget
+    pub fn spend(&mut self, bytes: usize, now: Instant) {
+        self.refill(now);
+        if self.bitrate > 0 {
+            self.credit -= bytes as f64;
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.last).as_secs_f64();
+        self.last = self.last.max(now);
+        let bytes_per_second = self.bitrate as f64 / 8.0;
+        self.credit = (self.credit + elapsed * bytes_per_second)
+            .min(bytes_per_second * MAX_BUDGET_CREDIT.as_secs_f64());
+    }
+}
+
+/// Samples congestion feedback from a connection, made by
+/// `Connection::bandwidth_monitor` so it outlives `start_frame_processing`
+pub struct BandwidthMonitor {
+    pub(super) connection: quinn::Connection,
+}
+
+impl BandwidthMonitor {
+    /// The connection's congestion feedback so far
+    pub fn feedback(&self) -> NetworkFeedback {
+        let path = self.connection.stats().path;
+        NetworkFeedback {
+            rtt: path.rtt,
+            cwnd: path.cwnd,
+            sent_packets: path.sent_packets,
+            lost_packets: path.lost_packets,
+        }
+    }
+
+    /// Bytes sent on the connection so far, headers included
+    pub fn sent_bytes(&self) -> u64 {
+        self.connection.stats().udp_tx.bytes
+    }
+
+    /// Bytes received on the connection so far, headers included
+    pub fn received_bytes(&self) -> u64 {
+        self.connection.stats().udp_rx.bytes
+    }
+
+    /// Periodically re-estimate the bandwidth and retarget the audio
+    /// streams, until the connection closes. Video's share is left in
+    /// `video` for `QualityController` to steer within.
+    pub async fn adapt_bitrates(&self, estimator: &mut BandwidthEstimator, video: &AtomicU64, audio: &[BitrateControl]) {
+        let mut interval = time::interval(ADAPT_INTERVAL);
+        while self.connection.close_reason().is_none() {
+            interval.tick().await;
+            estimator.update(self.feedback());
+            let allocation = estimator.allocate(audio.len());
+
+            video.store(allocation.video, Ordering::Relaxed);
+            for control in audio {
+                control.set_bitrate(allocation.audio);
+            }
+            debug!(
+                "Bandwidth estimate {} kbps: video {} kbps, audio {} kbps per stream",
+                estimator.estimate() / 1000,
+                allocation.video / 1000,
+                allocation.audio / 1000
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn feedback(cwnd: u64, sent_packets: u64, lost_packets: u64) -> NetworkFeedback {
+        NetworkFeedback {
+            rtt: Duration::from_millis(100),
+            cwnd,
+            sent_packets,
+            lost_packets,
+        }
+    }
+
+    #[test]
+    fn test_video_yields_to_audio_first() {
+        let mut estimator = BandwidthEstimator::new(40_000_000);
+        // 125KB per 100ms round trip is 10 Mbps
+        assert_eq!(estimator.update(feedback(125_000, 100, 0)), 10_000_000);
+        let allocation = estimator.allocate(2);
+        assert_eq!(allocation.audio, MAX_AUDIO_BITRATE);
+        assert_eq!(allocation.video, 10_000_000 - 2 * MAX_AUDIO_BITRATE as u64);
+
+        // Heavy loss cuts the estimate and video gives way before audio
+        estimator.update(feedback(125_000, 200, 50));
+        assert!(estimator.estimate() < 10_000_000);
+        assert_eq!(estimator.allocate(1).audio, MAX_AUDIO_BITRATE);
+
+        // Once video is at its minimum, audio shrinks down to its own
+        let mut estimator = BandwidthEstimator::new(300_000);
+        estimator.update(feedback(125_000, 100, 0));
+        assert_eq!(
+            estimator.allocate(1),
+            BitrateAllocation { video: MIN_VIDEO_BITRATE, audio: 50_000 }
+        );
+        let mut estimator = BandwidthEstimator::new(100_000);
+        estimator.update(feedback(125_000, 100, 0));
+        assert_eq!(estimator.allocate(1).audio, MIN_AUDIO_BITRATE);
+        assert_eq!(estimator.allocate(1).video, 100_000 - MIN_AUDIO_BITRATE as u64);
+    }
+
+    #[test]
+    fn test_send_budget_holds_frames_until_paid_off() {
+        let start = Instant::now();
+        let mut budget = SendBudget::new(start);
+        budget.spend(1_000_000, start);
+        assert!(budget.allows(start), "No bitrate means no pacing");
+
+        // 80 kbps is 10KB a second, so a 5KB frame takes half a second
+        budget.set_bitrate(80_000);
+        assert!(budget.allows(start));
+        budget.spend(5_000, start);
+        assert!(!budget.allows(start + Duration::from_millis(400)));
+        assert!(budget.allows(start + Duration::from_millis(500)));
+
+        // Idle time saves up only a little credit
+        let later = start + Duration::from_secs(60);
+        budget.spend(5_000, later);
+        assert!(!budget.allows(later + Duration::from_millis(200)));
+    }
+}
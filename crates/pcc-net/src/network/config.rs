@@ -0,0 +1,236 @@
+use super::access::AccessControl;
+use super::identity::{Fingerprint, Identity};
+use super::limits::ConnectionLimits;
+use super::queue::NetworkQueues;
+use super::usage::QuotaAction;
+use crate::input::Permission;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use rustls::server::{ClientCertVerified, ClientCertVerifier};
+use rustls::{self, client::ServerCertVerified, client::ServerCertVerifier, DistinguishedName};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkConfig {
+    pub port: Option<u16>,
+    pub max_packet_size: usize,
+    pub target_bandwidth: usize,
+    pub connection_timeout: Duration,
+    pub keepalive_interval: Duration,
+    /// How long a dropped session can be resumed with its token
+    pub session_resume_window: Duration,
+    /// What a viewer asks hosts to let it do; hosts grant at most what
+    /// they offer
+    pub permission: Permission,
+    /// Directory with the certificate and key from `pcc cert generate` that
+    /// a viewer presents. Without one a viewer makes a new certificate every
+    /// run. Hosts present it if set, for viewers' access lists.
+    pub identity: Option<PathBuf>,
+    /// Fingerprint from `pcc cert fingerprint` that a host requires the
+    /// viewer's certificate to have. Without one any viewer is trusted.
+    pub pinned_fingerprint: Option<Fingerprint>,
+    /// Bytes a host may send and receive in one session, e.g. on a metered
+    /// connection. Without one there's no limit.
+    pub data_quota: Option<u64>,
+    /// What a host does once the quota is used up
+    pub quota_action: QuotaAction,
+    /// File of viewers a host has paired with. With one, a viewer not in it
+    /// must send back the PIN the host shows before it sees the screen, and
+    /// is added once it does. Viewers need a saved identity to stay paired.
+    pub paired_viewers: Option<PathBuf>,
+    /// Whether a host asks before sharing with a viewer that isn't trusted
+    /// or paired
+    pub approve_viewers: bool,
+    /// Certificate fingerprints of viewers a host shares with without
+    /// asking
+    pub trusted_viewers: Vec<Fingerprint>,
+    /// Name a viewer introduces itself to hosts by
+    pub name: Option<String>,
+    /// Which hosts a viewer accepts
+    pub access: AccessControl,
+    /// How long a host goes without hearing from the viewer, which sends a
+    /// heartbeat every `keepalive_interval`, before ending the session
+    pub viewer_timeout: Duration,
+    /// How long a host's screen may go without changing before
+    /// `idle_action` is taken. Without one the session never idles.
+    pub idle_timeout: Option<Duration>,
+    pub idle_action: IdleAction,
+    /// Whether a host seals its frame payloads with a key only the viewer
+    /// shares, so a relay carrying the connection can't see the screen
+    pub encrypt_payloads: bool,
+    /// How often a host rotates the session's keys: QUIC's, and the
+    /// payload key when sealing. Without one QUIC's keys change only when
+    /// its own limits call for it, and the payload key never does.
+    pub rekey_interval: Option<Duration>,
+    /// Whether a host refuses to share with viewers that don't say whether
+    /// they're recording the session
+    pub require_recording_state: bool,
+    /// How a viewer guards against connection floods
+    pub limits: ConnectionLimits,
+    /// Capacity and overflow behaviour of the queues received frames,
+    /// messages and events wait in
+    pub queues: NetworkQueues,
+}
+
+/// What a host does once its screen has been idle for `idle_timeout`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IdleAction {
+    /// Capture only now and then, to notice the next change, and keep the
+    /// session open
+    #[default]
+    Suspend,
+    /// End the session
+    Disconnect,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            port: None,
+            max_packet_size: 1400, // Standard MTU size minus headers
+            target_bandwidth: 5_000_000, // 5MB/s
+            connection_timeout: Duration::from_secs(10),
+            keepalive_interval: Duration::from_secs(5),
+            session_resume_window: Duration::from_secs(60),
+            permission: Permission::Control,
+            identity: None,
+            pinned_fingerprint: None,
+            data_quota: None,
+            quota_action: QuotaAction::default(),
+            paired_viewers: None,
+            approve_viewers: false,
+            trusted_viewers: Vec::new(),
+            name: None,
+            access: AccessControl::default(),
+            viewer_timeout: Duration::from_secs(30),
+            idle_timeout: None,
+            idle_action: IdleAction::default(),
+            encrypt_payloads: false,
+            rekey_interval: Some(Duration::from_secs(60 * 60)),
+            require_recording_state: false,
+            limits: ConnectionLimits::default(),
+            queues: NetworkQueues::default(),
+        }
+    }
+}
+
+/// A certificate verifier that accepts any certificate.
+/// WARNING: This skips TLS certificate verification and should ONLY be used
+/// for localhost testing and development. Do not use in production.
+struct SkipServerVerification;
+
+impl ServerCertVerifier for SkipServerVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _server_name: &rustls::ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: std::time::SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+/// Accepts only a certificate whose public key has the pinned fingerprint.
+/// The handshake signatures are still checked against that key.
+struct PinnedServerVerification(Fingerprint);
+
+impl ServerCertVerifier for PinnedServerVerification {
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _server_name: &rustls::ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: std::time::SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let fingerprint = Fingerprint::of_certificate(&end_entity.0)
+            .map_err(|e| rustls::Error::General(e.to_string()))?;
+        if fingerprint != self.0 {
+            return Err(rustls::Error::General(format!(
+                "Viewer certificate fingerprint {} doesn't match the pinned {}",
+                fingerprint, self.0
+            )));
+        }
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+/// Asks hosts for a certificate but takes any, or none. Access lists
+/// decide what a host's certificate allows once connected; the handshake
+/// signatures are still checked against it.
+struct AnyClientCert;
+
+impl ClientCertVerifier for AnyClientCert {
+    fn client_auth_mandatory(&self) -> bool {
+        false
+    }
+
+    fn client_auth_root_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        _end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _now: std::time::SystemTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        Ok(ClientCertVerified::assertion())
+    }
+}
+
+impl NetworkConfig {
+    pub fn client_crypto_config(&self) -> Result<rustls::ClientConfig> {
+        let verifier: Arc<dyn ServerCertVerifier> = match self.pinned_fingerprint {
+            Some(fingerprint) => Arc::new(PinnedServerVerification(fingerprint)),
+            None => Arc::new(SkipServerVerification),
+        };
+        let builder = rustls::ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(verifier);
+        let mut config = match &self.identity {
+            Some(dir) => {
+                let (cert, key) = Identity::load(dir)?.rustls_cert();
+                builder.with_client_auth_cert(vec![cert], key)?
+            }
+            None => builder.with_no_client_auth(),
+        };
+
+        config.alpn_protocols = vec![b"pcc".to_vec()];
+        Ok(config)
+    }
+
+    /// Endpoint settings for a viewer: its certificate, and the limits on
+    /// connections in flight
+    pub fn server_config(&self) -> Result<quinn::ServerConfig> {
+        let mut config = quinn::ServerConfig::with_crypto(Arc::new(self.server_crypto_config()?));
+        config.concurrent_connections(self.limits.max_concurrent);
+        config.use_retry(self.limits.validate_addresses);
+        Ok(config)
+    }
+
+    pub fn server_crypto_config(&self) -> Result<rustls::ServerConfig> {
+        let identity = match &self.identity {
+            Some(dir) => Identity::load(dir)?,
+            // A throwaway certificate, which hosts can't pin
+            None => Identity::generate(vec!["localhost".to_string()])?,
+        };
+        let (cert, key) = identity.rustls_cert();
+
+        let mut server_crypto = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_client_cert_verifier(Arc::new(AnyClientCert))
+            .with_single_cert(vec![cert], key)?;
+
+        server_crypto.alpn_protocols = vec![b"pcc".to_vec()];
+        Ok(server_crypto)
+    }
+} 
\ No newline at end of file
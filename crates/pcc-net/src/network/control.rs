@@ -0,0 +1,59 @@
+//! Control messages each travel on their own unidirectional stream, so they
+//! never queue behind frame data on the frame stream.
+
+use super::protocol::{Message, HEADER_SIZE, MAX_MESSAGE_SIZE};
+use anyhow::{Context, Result};
+
+/// Send a single control message to the peer
+pub async fn send_message(conn: &quinn::Connection, message: &Message) -> Result<()> {
+    send_prioritized(conn, &message.serialize()?, message.priority()).await
+}
+
+/// Send an already serialized control message to the peer
+pub(crate) async fn send_serialized(conn: &quinn::Connection, bytes: &[u8]) -> Result<()> {
+    send_prioritized(conn, bytes, 0).await
+}
+
+async fn send_prioritized(conn: &quinn::Connection, bytes: &[u8], priority: i32) -> Result<()> {
+    let mut send = conn
+        .open_uni()
+        .await
+        .context("Failed to open control stream")?;
+    if priority != 0 {
+        // Only fails if the stream is already gone, which write_all reports
+        let _ = send.set_priority(priority);
+    }
+    send.write_all(bytes)
+        .await
+        .context("Failed to send control message")?;
+    send.finish().await.context("Failed to finish control stream")?;
+    Ok(())
+}
+
+/// Read the first control message carried by `recv`. Most streams carry
+/// just the one; audio streams go on with `read_next`.
+pub async fn read_message(recv: &mut quinn::RecvStream) -> Result<Message> {
+    read_next(recv).await?.context("Empty control stream")
+}
+
+/// Read the next message from a stream carrying several, or `None` once
+/// the peer finished it
+pub async fn read_next(recv: &mut quinn::RecvStream) -> Result<Option<Message>> {
+    let mut header = [0u8; HEADER_SIZE];
+    match recv.read_exact(&mut header).await {
+        Ok(()) => {}
+        Err(quinn::ReadExactError::FinishedEarly) => return Ok(None),
+        Err(e) => return Err(e).context("Failed to read control message"),
+    }
+
+    let len = u32::from_le_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    if len > MAX_MESSAGE_SIZE {
+        anyhow::bail!("Message too large: {} bytes", len);
+    }
+    let mut bytes = vec![0u8; HEADER_SIZE + len];
+    bytes[..HEADER_SIZE].copy_from_slice(&header);
+    recv.read_exact(&mut bytes[HEADER_SIZE..])
+        .await
+        .context("Failed to read control message")?;
+    Message::deserialize(&bytes).map(Some)
+}
@@ -0,0 +1,136 @@
+use super::protocol::Message;
+use quinn::{ConnectionError, VarInt};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// Why a session ended. Each reason maps onto a QUIC application close code
+/// so the peer learns it even if the `Goodbye` message is lost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CloseReason {
+    /// Normal close with no more specific reason
+    Normal,
+    /// The host stopped sharing its screen
+    HostStoppedSharing,
+    /// The host removed this viewer from the session
+    Kicked,
+    /// The session was idle for too long
+    IdleTimeout,
+    /// The peer sent something we could not understand
+    ProtocolError,
+    /// The connection dropped without a close handshake
+    ConnectionLost,
+    /// The host used up its data quota
+    QuotaExceeded,
+    /// The viewer didn't send the PIN the host showed for pairing
+    PairingFailed,
+    /// The host didn't approve the viewer
+    NotApproved,
+    /// The viewer's access list doesn't let the host connect
+    AccessDenied,
+    /// The viewer didn't say whether it records the session, which the
+    /// host requires
+    RecordingUndisclosed,
+}
+
+impl CloseReason {
+    /// QUIC application error code for this reason
+    pub fn code(self) -> VarInt {
+        VarInt::from_u32(match self {
+            CloseReason::Normal => 0,
+            CloseReason::HostStoppedSharing => 1,
+            CloseReason::Kicked => 2,
+            CloseReason::IdleTimeout => 3,
+            CloseReason::ProtocolError => 4,
+            CloseReason::ConnectionLost => 5,
+            CloseReason::QuotaExceeded => 6,
+            CloseReason::PairingFailed => 7,
+            CloseReason::NotApproved => 8,
+            CloseReason::AccessDenied => 9,
+            CloseReason::RecordingUndisclosed => 10,
+        })
+    }
+
+    /// Map a QUIC application error code back to a reason
+    pub fn from_code(code: VarInt) -> Self {
+        match code.into_inner() {
+            0 => CloseReason::Normal,
+            1 => CloseReason::HostStoppedSharing,
+            2 => CloseReason::Kicked,
+            3 => CloseReason::IdleTimeout,
+            4 => CloseReason::ProtocolError,
+            6 => CloseReason::QuotaExceeded,
+            7 => CloseReason::PairingFailed,
+            8 => CloseReason::NotApproved,
+            9 => CloseReason::AccessDenied,
+            10 => CloseReason::RecordingUndisclosed,
+            _ => CloseReason::ConnectionLost,
+        }
+    }
+
+    /// Whether the peer is likely to come back, so the session should be
+    /// kept around rather than torn down
+    pub fn may_reconnect(self) -> bool {
+        matches!(
+            self,
+            CloseReason::IdleTimeout | CloseReason::ProtocolError | CloseReason::ConnectionLost
+        )
+    }
+
+    /// Classify the error a closed QUIC connection reports
+    pub fn from_error(error: &ConnectionError) -> Self {
+        match error {
+            ConnectionError::ApplicationClosed(close) => Self::from_code(close.error_code),
+            ConnectionError::LocallyClosed => CloseReason::Normal,
+            ConnectionError::TimedOut => CloseReason::IdleTimeout,
+            ConnectionError::VersionMismatch | ConnectionError::TransportError(_) => {
+                CloseReason::ProtocolError
+            }
+            _ => CloseReason::ConnectionLost,
+        }
+    }
+}
+
+impl fmt::Display for CloseReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            CloseReason::Normal => "closed",
+            CloseReason::HostStoppedSharing => "host stopped sharing",
+            CloseReason::Kicked => "kicked by host",
+            CloseReason::IdleTimeout => "idle timeout",
+            CloseReason::ProtocolError => "protocol error",
+            CloseReason::ConnectionLost => "connection lost",
+            CloseReason::QuotaExceeded => "data quota used up",
+            CloseReason::PairingFailed => "pairing failed",
+            CloseReason::NotApproved => "not approved by host",
+            CloseReason::AccessDenied => "access denied",
+            CloseReason::RecordingUndisclosed => "viewer doesn't disclose recording",
+        };
+        f.write_str(text)
+    }
+}
+
+/// Connection lifecycle events surfaced to the application
+#[derive(Debug, Clone, PartialEq)]
+pub enum NetworkEvent {
+    /// The peer asked for a full frame (after joining, decode errors or loss)
+    KeyframeRequested,
+    /// Keyboard or mouse input from the viewer, for the host to inject
+    Input(crate::input::InputEvent),
+    /// Any other control message from the peer
+    Message(Message),
+    /// A peer completed the handshake, picking up its old session if `resumed`
+    Connected { resumed: bool },
+    Closed(CloseReason),
+}
+
+impl From<Message> for NetworkEvent {
+    fn from(message: Message) -> Self {
+        match message {
+            Message::RequestKeyframe => NetworkEvent::KeyframeRequested,
+            other => match other.input_event() {
+                Some(event) => NetworkEvent::Input(event),
+                None => NetworkEvent::Message(other),
+            },
+        }
+    }
+}
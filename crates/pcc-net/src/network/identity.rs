@@ -0,0 +1,263 @@
+use anyhow::{bail, ensure, Context, Result};
+use rcgen::generate_simple_self_signed;
+use ring::digest::{digest, SHA256};
+use ring::rand::SystemRandom;
+use ring::signature::{EcdsaKeyPair, UnparsedPublicKey, ECDSA_P256_SHA256_ASN1, ECDSA_P256_SHA256_ASN1_SIGNING};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Directory `pcc cert` keeps the viewer's identity in when none is given
+pub const DEFAULT_IDENTITY_DIR: &str = "pcc-identity";
+const CERT_FILE: &str = "cert.pem";
+const KEY_FILE: &str = "key.pem";
+
+/// A self-signed certificate and its private key, which a viewer presents
+/// to hosts. Saved to disk, it stays the same between runs so hosts can pin
+/// its fingerprint.
+#[derive(Clone)]
+pub struct Identity {
+    cert: Vec<u8>,
+    key: Vec<u8>,
+}
+
+impl Identity {
+    /// A new identity for the given DNS names or addresses
+    pub fn generate(names: Vec<String>) -> Result<Self> {
+        let cert = generate_simple_self_signed(names)?;
+        Ok(Self {
+            cert: cert.serialize_der()?,
+            key: cert.serialize_private_key_der(),
+        })
+    }
+
+    /// Read the identity saved in `dir`
+    pub fn load(dir: &Path) -> Result<Self> {
+        Ok(Self {
+            cert: read_pem(&dir.join(CERT_FILE), "CERTIFICATE")?,
+            key: read_pem(&dir.join(KEY_FILE), "PRIVATE KEY")?,
+        })
+    }
+
+    /// Whether `dir` holds a saved identity
+    pub fn exists(dir: &Path) -> bool {
+        dir.join(CERT_FILE).exists() && dir.join(KEY_FILE).exists()
+    }
+
+    /// Write the certificate and key to `dir`, replacing any there. The key
+    /// is only readable by its owner.
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        write_file(&dir.join(CERT_FILE), self.cert_pem().as_bytes(), 0o644)?;
+        write_file(&dir.join(KEY_FILE), pem::encode(&pem::Pem::new("PRIVATE KEY", self.key.clone())).as_bytes(), 0o600)
+    }
+
+    /// Where `save` puts the certificate in `dir`
+    pub fn cert_path(dir: &Path) -> PathBuf {
+        dir.join(CERT_FILE)
+    }
+
+    pub fn cert_der(&self) -> &[u8] {
+        &self.cert
+    }
+
+    pub fn cert_pem(&self) -> String {
+        pem::encode(&pem::Pem::new("CERTIFICATE", self.cert.clone()))
+    }
+
+    pub fn fingerprint(&self) -> Result<Fingerprint> {
+        Fingerprint::of_certificate(&self.cert)
+    }
+
+    pub(crate) fn rustls_cert(&self) -> (rustls::Certificate, rustls::PrivateKey) {
+        (rustls::Certificate(self.cert.clone()), rustls::PrivateKey(self.key.clone()))
+    }
+
+    /// Sign `message` with the identity's key, for a peer to check with
+    /// `verify_signature`. Only the ECDSA P-256 keys `generate` makes can
+    /// sign.
+    pub(crate) fn sign(&self, message: &[u8]) -> Result<Vec<u8>> {
+        let rng = SystemRandom::new();
+        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_ASN1_SIGNING, &self.key, &rng)
+            .map_err(|_| anyhow::anyhow!("Identity key isn't an ECDSA P-256 key"))?;
+        let signature = key.sign(&rng, message).map_err(|_| anyhow::anyhow!("Failed to sign with identity key"))?;
+        Ok(signature.as_ref().to_vec())
+    }
+}
+
+/// Check that `signature` of `message` was made with the key of DER
+/// certificate `cert`, returning the certificate's fingerprint
+pub(crate) fn verify_signature(cert: &[u8], message: &[u8], signature: &[u8]) -> Result<Fingerprint> {
+    // SubjectPublicKeyInfo: the algorithm, then the key as a bit string
+    let (_, fields, _) = der_element(subject_public_key_info(cert)?)?;
+    let (_, _, rest) = der_element(fields)?;
+    let (tag, key, _) = der_element(rest)?;
+    ensure!(tag == 0x03 && key.first() == Some(&0), "Expected the certificate's key as a bit string");
+    UnparsedPublicKey::new(&ECDSA_P256_SHA256_ASN1, &key[1..])
+        .verify(message, signature)
+        .map_err(|_| anyhow::anyhow!("Signature doesn't match the certificate"))?;
+    Fingerprint::of_certificate(cert)
+}
+
+fn read_pem(path: &Path, tag: &str) -> Result<Vec<u8>> {
+    let text = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let pem = pem::parse(text).with_context(|| format!("Invalid PEM in {}", path.display()))?;
+    ensure!(pem.tag() == tag, "Expected {} in {}, found {}", tag, path.display(), pem.tag());
+    Ok(pem.into_contents())
+}
+
+fn write_file(path: &Path, contents: &[u8], mode: u32) -> Result<()> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(mode);
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(contents))
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// SHA-256 of a certificate's public key (its SubjectPublicKeyInfo), which
+/// hosts pin to know they reached the right viewer. Written as colon
+/// separated hex.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Fingerprint([u8; 32]);
+
+impl Fingerprint {
+    /// Fingerprint of a DER certificate
+    pub fn of_certificate(der: &[u8]) -> Result<Self> {
+        let spki = subject_public_key_info(der).context("Malformed certificate")?;
+        let mut fingerprint = [0; 32];
+        fingerprint.copy_from_slice(digest(&SHA256, spki).as_ref());
+        Ok(Self(fingerprint))
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, byte) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ":")?;
+            }
+            write!(f, "{:02X}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Fingerprint {
+    type Err = anyhow::Error;
+
+    /// Parse hex, with or without colons, in either case
+    fn from_str(text: &str) -> Result<Self> {
+        let hex: Vec<u8> = text.bytes().filter(|&byte| byte != b':').collect();
+        ensure!(hex.len() == 64, "Fingerprint should be 32 bytes of hex, got {:?}", text);
+        let mut fingerprint = [0; 32];
+        for (byte, pair) in fingerprint.iter_mut().zip(hex.chunks(2)) {
+            let pair = std::str::from_utf8(pair)?;
+            *byte = u8::from_str_radix(pair, 16).with_context(|| format!("Invalid hex {:?} in fingerprint", pair))?;
+        }
+        Ok(Self(fingerprint))
+    }
+}
+
+impl TryFrom<String> for Fingerprint {
+    type Error = anyhow::Error;
+
+    fn try_from(text: String) -> Result<Self> {
+        text.parse()
+    }
+}
+
+impl From<Fingerprint> for String {
+    fn from(fingerprint: Fingerprint) -> Self {
+        fingerprint.to_string()
+    }
+}
+
+/// Fingerprint of the certificate the peer on `connection` presented, if
+/// it presented one
+pub fn peer_fingerprint(connection: &quinn::Connection) -> Option<Fingerprint> {
+    let certs = connection.peer_identity()?.downcast::<Vec<rustls::Certificate>>().ok()?;
+    Fingerprint::of_certificate(&certs.first()?.0).ok()
+}
+
+// The SubjectPublicKeyInfo in a DER certificate: the seventh field of the
+// TBSCertificate, or the sixth when the optional version is left out
+fn subject_public_key_info(cert: &[u8]) -> Result<&[u8]> {
+    let (_, certificate, _) = der_element(cert)?;
+    let (_, tbs, _) = der_element(certificate)?;
+    let mut fields = tbs;
+    let (tag, _, rest) = der_element(fields)?;
+    // [0] EXPLICIT version
+    if tag == 0xa0 {
+        fields = rest;
+    }
+    // Serial number, signature algorithm, issuer, validity and subject
+    for _ in 0..5 {
+        fields = der_element(fields)?.2;
+    }
+    let (tag, _, rest) = der_element(fields)?;
+    ensure!(tag == 0x30, "Expected a SubjectPublicKeyInfo sequence");
+    Ok(&fields[..fields.len() - rest.len()])
+}
+
+// Split the first DER element off `input`: its tag, contents and whatever
+// follows it
+fn der_element(input: &[u8]) -> Result<(u8, &[u8], &[u8])> {
+    let [tag, first, rest @ ..] = input else { bail!("Truncated DER") };
+    let (length, rest) = if first & 0x80 == 0 {
+        (*first as usize, rest)
+    } else {
+        let octets = (first & 0x7f) as usize;
+        ensure!((1..=4).contains(&octets) && rest.len() >= octets, "Unsupported DER length");
+        let length = rest[..octets].iter().fold(0, |length, &byte| length << 8 | byte as usize);
+        (length, &rest[octets..])
+    };
+    ensure!(rest.len() >= length, "Truncated DER");
+    Ok((*tag, &rest[..length], &rest[length..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fingerprint_is_of_public_key() {
+        let cert = generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let fingerprint = Fingerprint::of_certificate(&cert.serialize_der().unwrap()).unwrap();
+        let expected = digest(&SHA256, &cert.get_key_pair().public_key_der());
+        assert_eq!(fingerprint.0.as_slice(), expected.as_ref());
+
+        let text = fingerprint.to_string();
+        assert_eq!(text.len(), 32 * 3 - 1);
+        assert_eq!(text.parse::<Fingerprint>().unwrap(), fingerprint);
+        assert_eq!(text.replace(':', "").to_lowercase().parse::<Fingerprint>().unwrap(), fingerprint);
+        assert!("AB:CD".parse::<Fingerprint>().is_err());
+    }
+
+    #[test]
+    fn test_signatures_verify_against_the_certificate() {
+        let identity = Identity::generate(vec!["localhost".to_string()]).unwrap();
+        let signature = identity.sign(b"transcript").unwrap();
+        let fingerprint = verify_signature(identity.cert_der(), b"transcript", &signature).unwrap();
+        assert_eq!(fingerprint, identity.fingerprint().unwrap());
+
+        assert!(verify_signature(identity.cert_der(), b"something else", &signature).is_err());
+        let other = Identity::generate(vec!["localhost".to_string()]).unwrap();
+        assert!(verify_signature(other.cert_der(), b"transcript", &signature).is_err());
+    }
+}
@@ -0,0 +1,199 @@
+use super::access::Subnet;
+use lru::LruCache;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fmt;
+use std::net::IpAddr;
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// Window the per-address connection rate is counted over
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+// Addresses tracked before the least recently seen are forgotten
+const MAX_TRACKED: NonZeroUsize = NonZeroUsize::new(4096).unwrap();
+
+/// How a viewer guards its port against connection floods, from the
+/// `[network.limits]` section
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ConnectionLimits {
+    /// Connections one address may start a minute; more are dropped
+    /// before their handshake completes
+    pub per_address_per_minute: u32,
+    /// Connections being handshaken or open at once, across all addresses
+    pub max_concurrent: u32,
+    /// How long a host has to finish the QUIC handshake
+    pub handshake_timeout: Duration,
+    /// Failed handshakes in a row after which an address is banned
+    pub ban_after_failures: u32,
+    pub ban_duration: Duration,
+    /// Addresses never let in, not even for a handshake
+    pub banned: Vec<Subnet>,
+    /// Make hosts prove they own their address with a retry packet
+    /// before any crypto is done, at the cost of a round trip. Without
+    /// it, the limits count addresses that may be spoofed, and refused
+    /// connections have already started their handshake.
+    pub validate_addresses: bool,
+}
+
+impl Default for ConnectionLimits {
+    fn default() -> Self {
+        Self {
+            per_address_per_minute: 30,
+            max_concurrent: 256,
+            handshake_timeout: Duration::from_secs(10),
+            ban_after_failures: 10,
+            ban_duration: Duration::from_secs(15 * 60),
+            banned: Vec::new(),
+            validate_addresses: true,
+        }
+    }
+}
+
+/// Why a connection was turned away before its handshake completed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Refusal {
+    /// On the ban list, or banned for failing too many handshakes
+    Banned,
+    /// Started too many connections in the last minute
+    RateLimited,
+}
+
+impl fmt::Display for Refusal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Refusal::Banned => "banned",
+            Refusal::RateLimited => "too many connections",
+        })
+    }
+}
+
+// What's known about one address
+#[derive(Debug, Default)]
+struct AddressState {
+    // Connections started within the rate window
+    recent: VecDeque<Instant>,
+    failures: u32,
+    banned_until: Option<Instant>,
+}
+
+/// Decides which addresses may start a connection, counting their rate
+/// and failed handshakes
+#[derive(Debug)]
+pub struct ConnectionLimiter {
+    limits: ConnectionLimits,
+    // A banned address that keeps trying stays recently seen, so only
+    // addresses that went quiet are forgotten
+    addresses: Mutex<LruCache<IpAddr, AddressState>>,
+}
+
+impl ConnectionLimiter {
+    pub fn new(limits: ConnectionLimits) -> Self {
+        Self { limits, addresses: Mutex::new(LruCache::new(MAX_TRACKED)) }
+    }
+
+    pub fn limits(&self) -> &ConnectionLimits {
+        &self.limits
+    }
+
+    /// Whether `ip` may start a connection at `now`, counting it if so
+    pub fn admit(&self, ip: IpAddr, now: Instant) -> Result<(), Refusal> {
+        let ip = ip.to_canonical();
+        if self.limits.banned.iter().any(|subnet| subnet.contains(ip)) {
+            return Err(Refusal::Banned);
+        }
+        let mut addresses = self.addresses.lock().unwrap();
+        let state = addresses.get_or_insert_mut(ip, AddressState::default);
+        if state.banned_until.is_some_and(|until| until > now) {
+            return Err(Refusal::Banned);
+        }
+        while state.recent.front().is_some_and(|&first| now.duration_since(first) >= RATE_WINDOW) {
+            state.recent.pop_front();
+        }
+        if state.recent.len() >= self.limits.per_address_per_minute as usize {
+            return Err(Refusal::RateLimited);
+        }
+        state.recent.push_back(now);
+        Ok(())
+    }
+
+    /// Note a failed handshake from `ip`. Returns true if that got it
+    /// banned.
+    pub fn failed(&self, ip: IpAddr, now: Instant) -> bool {
+        let mut addresses = self.addresses.lock().unwrap();
+        let state = addresses.get_or_insert_mut(ip.to_canonical(), AddressState::default);
+        state.failures += 1;
+        if state.failures < self.limits.ban_after_failures {
+            return false;
+        }
+        state.failures = 0;
+        state.banned_until = Some(now + self.limits.ban_duration);
+        true
+    }
+
+    /// Note a handshake from `ip` that went through
+    pub fn succeeded(&self, ip: IpAddr) {
+        if let Some(state) = self.addresses.lock().unwrap().get_mut(&ip.to_canonical()) {
+            state.failures = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits_rate_and_bans_repeat_failures() {
+        let limits = ConnectionLimits {
+            per_address_per_minute: 2,
+            ban_after_failures: 2,
+            ban_duration: Duration::from_secs(60),
+            banned: vec!["10.9.0.0/16".parse().unwrap()],
+            ..ConnectionLimits::default()
+        };
+        let limiter = ConnectionLimiter::new(limits);
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let ip = |text: &str| text.parse::<IpAddr>().unwrap();
+
+        assert_eq!(limiter.admit(ip("10.9.1.1"), start), Err(Refusal::Banned));
+
+        let host = ip("192.168.1.20");
+        assert_eq!(limiter.admit(host, at(0)), Ok(()));
+        // The same host over a dual-stack socket
+        assert_eq!(limiter.admit(ip("::ffff:192.168.1.20"), at(1)), Ok(()));
+        assert_eq!(limiter.admit(host, at(2)), Err(Refusal::RateLimited));
+        assert_eq!(limiter.admit(ip("192.168.1.21"), at(2)), Ok(()));
+        assert_eq!(limiter.admit(host, at(60)), Ok(()));
+
+        // A success clears earlier failures
+        assert!(!limiter.failed(host, at(60)));
+        limiter.succeeded(host);
+        assert!(!limiter.failed(host, at(61)));
+        assert!(limiter.failed(host, at(62)));
+        assert_eq!(limiter.admit(host, at(100)), Err(Refusal::Banned));
+        assert_eq!(limiter.admit(host, at(122)), Ok(()));
+    }
+
+    #[test]
+    fn test_limiter_forgets_least_recently_seen_addresses() {
+        let limits = ConnectionLimits { per_address_per_minute: 1, ..ConnectionLimits::default() };
+        let limiter = ConnectionLimiter::new(limits);
+        let now = Instant::now();
+        let first: IpAddr = "192.168.1.20".parse().unwrap();
+        let second: IpAddr = "192.168.1.21".parse().unwrap();
+        assert_eq!(limiter.admit(first, now), Ok(()));
+        assert_eq!(limiter.admit(second, now), Ok(()));
+        // Seen again, if refused, so it outlasts the second
+        assert_eq!(limiter.admit(first, now), Err(Refusal::RateLimited));
+
+        for n in 0..MAX_TRACKED.get() as u32 - 1 {
+            assert_eq!(limiter.admit(IpAddr::from((10 << 24 | n).to_be_bytes()), now), Ok(()));
+        }
+        assert_eq!(limiter.addresses.lock().unwrap().len(), MAX_TRACKED.get());
+        assert_eq!(limiter.admit(first, now), Err(Refusal::RateLimited));
+        assert_eq!(limiter.admit(second, now), Ok(()));
+    }
+}
@@ -0,0 +1,698 @@
+use anyhow::{Context, Result};
+use quinn::{ClientConfig, Endpoint};
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::{mpsc, watch};
+use tokio::task::JoinSet;
+use tokio::time;
+use crate::audio::{AudioCapture, AudioControls, AudioStreamer};
+use crate::encoder::FrameEncoder;
+use crate::input::{Permission, RemoteInput};
+use pcc_core::types::{CopyRect, Frame, FrameUpdate};
+use tracing::{debug, debug_span, warn, Instrument};
+
+mod access;
+mod approval;
+mod bandwidth;
+mod config;
+pub mod control;
+mod events;
+mod identity;
+mod limits;
+mod loopback;
+mod pairing;
+mod payload;
+mod queue;
+mod transport;
+pub mod resilience;
+pub(crate) mod protocol;
+mod session;
+mod side_channel;
+mod usage;
+
+pub use access::{AccessControl, PeerMatch, Subnet};
+pub use approval::{ViewerApproval, ViewerInfo};
+pub use bandwidth::{BandwidthEstimator, BandwidthMonitor, BitrateAllocation, NetworkFeedback, SendBudget};
+pub use config::{IdleAction, NetworkConfig};
+pub use events::{CloseReason, NetworkEvent};
+pub use identity::{peer_fingerprint, Fingerprint, Identity, DEFAULT_IDENTITY_DIR};
+pub use limits::{ConnectionLimiter, ConnectionLimits, Refusal};
+pub use loopback::{LoopbackConfig, LoopbackTransport};
+pub use pairing::{pin_proof, PairedViewers, PairingPin, PIN_DIGITS, PIN_LIFETIME};
+pub use payload::{IdentityProof, KeyBinding, PayloadCipher, PayloadKeyExchange};
+pub use queue::{
+    queue, NetworkQueueStats, NetworkQueues, Overflow, QueueClosed, QueueConfig, QueueReceiver, QueueSender, QueueStats,
+};
+pub use transport::{QUICTransport, Transport};
+pub use resilience::{ResilienceConfig, NetworkResilience};
+pub use protocol::*;
+pub use pcc_core::SessionClock;
+pub use session::{ResumeToken, SessionInfo, SessionRegistry, SessionResume};
+pub use side_channel::SideChannel;
+pub use usage::{DataUsage, QuotaAction, UsageMeter, USAGE_WINDOW};
+
+const DEFAULT_PORT: u16 = 5800;
+const EVENT_CHANNEL_CAPACITY: usize = 8;
+// Longest `Connection::close` waits for its tasks to see the close
+const TASK_JOIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+pub struct NetworkManager {
+    endpoint: Endpoint,
+    config: NetworkConfig,
+}
+
+impl NetworkManager {
+    pub async fn new_client(config: NetworkConfig) -> Result<Self> {
+        let client_config = ClientConfig::new(Arc::new(config.client_crypto_config()?));
+        let mut endpoint = Endpoint::client("0.0.0.0:0".parse()?)?;
+        endpoint.set_default_client_config(client_config);
+
+        Ok(Self { endpoint, config })
+    }
+
+    pub async fn new_server(config: NetworkConfig) -> Result<Self> {
+        let endpoint = Endpoint::server(
+            config.server_config()?,
+            format!("0.0.0.0:{}", config.port.unwrap_or(DEFAULT_PORT)).parse()?,
+        )?;
+
+        Ok(Self { endpoint, config })
+    }
+
+    pub async fn connect(&self, addr: SocketAddr) -> Result<Connection> {
+        let connection = self
+            .endpoint
+            .connect(addr, "localhost")?
+            .await
+            .context("Failed to establish connection")?;
+
+        Connection::new(connection, &self.config).await
+    }
+
+    pub async fn accept(&self) -> Result<Connection> {
+        let incoming_conn = self
+            .endpoint
+            .accept()
+            .await
+            .context("No incoming connections")?;
+
+        let connection = incoming_conn
+            .await
+            .context("Failed to establish connection")?;
+
+        Connection::new(connection, &self.config).await
+    }
+
+    /// Get the network configuration this manager was created with
+    pub fn config(&self) -> &NetworkConfig {
+        &self.config
+    }
+
+    /// Wait up to `timeout` for closed connections to finish telling their
+    /// peers, so exiting right after doesn't leave them waiting to time out
+    pub async fn wait_idle(&self, timeout: Duration) {
+        if tokio::time::timeout(timeout, self.endpoint.wait_idle()).await.is_err() {
+            debug!("Gave up waiting for connections to close");
+        }
+    }
+}
+
+/// A frame coded for the wire, as `Connection::send_keyframe` and
+/// `send_update` send them
+#[derive(Debug, Clone)]
+pub enum EncodedFrame {
+    /// A whole frame, sent on a stream of its own
+    Keyframe(Vec<u8>),
+    /// Delta update messages, sent as control messages
+    Update(Vec<Vec<u8>>),
+}
+
+impl EncodedFrame {
+    pub fn keyframe(frame: &Frame) -> Result<Self> {
+        Ok(Self::Keyframe(frame.encode()?))
+    }
+
+    pub fn update(update: &FrameUpdate) -> Result<Self> {
+        Ok(Self::Update(FrameProtocol::encode_update(update)?))
+    }
+
+    /// A copy within the viewer's frame, then the changes on top of it
+    pub fn scroll(copy: CopyRect, update: &FrameUpdate) -> Result<Self> {
+        let mut parts = vec![Message::from(copy).serialize()?];
+        parts.extend(FrameProtocol::encode_update(update)?);
+        Ok(Self::Update(parts))
+    }
+
+    /// Bytes the frame takes on the wire
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Keyframe(data) => data.len(),
+            Self::Update(parts) => parts.iter().map(|part| part.len()).sum(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+pub struct Connection {
+    quinn_conn: quinn::Connection,
+    send_stream: quinn::SendStream,
+    recv_stream: quinn::RecvStream,
+    frame_tx: QueueSender<Frame>,
+    // Taken by `start_frame_processing`
+    frame_rx: std::sync::Mutex<Option<QueueReceiver<Frame>>>,
+    // As the viewer gave it in the handshake
+    viewer_name: std::sync::Mutex<Option<String>>,
+    // When the last control message or heartbeat arrived
+    last_heard: Arc<std::sync::Mutex<Instant>>,
+    // Whether to offer the viewer a key for sealing frame payloads
+    encrypt_payloads: bool,
+    // Set by the handshake if the viewer took it
+    payload: std::sync::OnceLock<PayloadCipher>,
+    // The identity the viewer signed the key exchange with, which a relay
+    // carrying the connection can't stand in for
+    viewer_identity: std::sync::OnceLock<Fingerprint>,
+    // What that identity must be, if pinned
+    pinned_fingerprint: Option<Fingerprint>,
+    // Whether the viewer is recording, as it last said
+    viewer_recording: Arc<watch::Sender<Option<bool>>>,
+    // Reading control messages, watching for the close and moving frames,
+    // joined by `shutdown` and `close` and aborted if the connection is
+    // dropped
+    tasks: std::sync::Mutex<JoinSet<()>>,
+    // Set by `shutdown` to end the tasks
+    stop: watch::Sender<bool>,
+}
+
+impl Connection {
+    async fn new(quinn_conn: quinn::Connection, config: &NetworkConfig) -> Result<Self> {
+        let (send_stream, recv_stream) = quinn_conn
+            .open_bi()
+            .await
+            .context("Failed to open bidirectional stream")?;
+
+        let (frame_tx, frame_rx) = queue(config.queues.frames);
+
+        Ok(Self {
+            quinn_conn,
+            send_stream,
+            recv_stream,
+            frame_tx,
+            frame_rx: std::sync::Mutex::new(Some(frame_rx)),
+            viewer_name: std::sync::Mutex::new(None),
+            last_heard: Arc::new(std::sync::Mutex::new(Instant::now())),
+            encrypt_payloads: config.encrypt_payloads,
+            payload: std::sync::OnceLock::new(),
+            viewer_identity: std::sync::OnceLock::new(),
+            pinned_fingerprint: config.pinned_fingerprint,
+            viewer_recording: Arc::new(watch::Sender::new(None)),
+            tasks: std::sync::Mutex::new(JoinSet::new()),
+            stop: watch::Sender::new(false),
+        })
+    }
+
+    pub async fn send_frame(&mut self, frame: &Frame) -> Result<()> {
+        let encoded: Vec<u8> = frame.encode()?;
+        self.send_stream
+            .write_all(&encoded)
+            .await
+            .context("Failed to send frame")?;
+        Ok(())
+    }
+
+    pub async fn receive_frame(&mut self) -> Result<Frame> {
+        let mut buf = vec![0u8; 8192];
+        let n = self
+            .recv_stream
+            .read(&mut buf)
+            .await
+            .context("Failed to receive frame")?;
+
+        let n = match n {
+            Some(size) => size,
+            None => return Err(anyhow::anyhow!("Connection closed")),
+        };
+
+        buf.truncate(n);
+        Frame::decode(&buf).context("Failed to decode frame")
+    }
+
+    /// Open the session with the viewer, presenting `resume_token` if this is
+    /// a reconnect and offering it at most `permission`. Must complete before
+    /// `start_frame_processing`. Apply the returned session's permission to
+    /// the host's `RemoteInput`.
+    pub async fn handshake(&self, resume_token: Option<ResumeToken>, permission: Permission) -> Result<SessionInfo> {
+        self.open_session(resume_token, permission, None).await
+    }
+
+    /// Like `handshake`, but the viewer must answer with `pin`, which the
+    /// host shows its user. Closes the connection if it doesn't.
+    pub async fn pair(&self, resume_token: Option<ResumeToken>, permission: Permission, pin: &PairingPin) -> Result<SessionInfo> {
+        self.open_session(resume_token, permission, Some(pin)).await
+    }
+
+    async fn open_session(
+        &self,
+        resume_token: Option<ResumeToken>,
+        permission: Permission,
+        pin: Option<&PairingPin>,
+    ) -> Result<SessionInfo> {
+        let pairing = pin.is_some();
+        let exchange = self.encrypt_payloads.then(PayloadKeyExchange::new).transpose()?;
+        let payload_key = exchange.as_ref().map(PayloadKeyExchange::public_key);
+        self.send_message(&Message::Hello { resume_token, permission, pairing, payload_key }).await?;
+
+        let mut recv = self
+            .quinn_conn
+            .accept_uni()
+            .await
+            .context("Connection closed during handshake")?;
+
+        let fail = |reason: CloseReason, error: &str| {
+            self.quinn_conn.close(reason.code(), reason.to_string().as_bytes());
+            anyhow::anyhow!("{}", error)
+        };
+        match control::read_message(&mut recv).await? {
+            Message::Welcome { token, resume_from, permission: taken, pin_proof, name, payload_key, recording, identity } => {
+                let mut binding = None;
+                if let Some(exchange) = exchange {
+                    let Some(viewer_key) = payload_key else {
+                        return Err(fail(CloseReason::ProtocolError, "Viewer didn't take a key for sealing frame payloads"));
+                    };
+                    let mut key_binding = KeyBinding { host_key: exchange.public_key(), viewer_key, viewer: None, pin: None };
+                    if let Some(identity) = identity {
+                        match identity.verify(&key_binding.transcript()) {
+                            Ok(viewer) => key_binding.viewer = Some(viewer),
+                            Err(e) => return Err(fail(CloseReason::ProtocolError, &format!("Viewer's identity proof: {:#}", e))),
+                        }
+                    }
+                    if let Some(pinned) = self.pinned_fingerprint {
+                        if key_binding.viewer != Some(pinned) {
+                            return Err(fail(CloseReason::ProtocolError, "Viewer didn't sign the key exchange as the pinned identity"));
+                        }
+                    }
+                    if key_binding.viewer.is_none() && pin.is_none() {
+                        warn!("Frame payload key isn't bound to the viewer's identity or a PIN; a relay could stand in for it");
+                    }
+                    key_binding.pin = pin.map(PairingPin::code);
+                    let cipher = exchange.agree(&viewer_key, &key_binding)?;
+                    binding = Some(cipher.binding());
+                    if let Some(viewer) = key_binding.viewer {
+                        let _ = self.viewer_identity.set(viewer);
+                    }
+                    // Only one handshake per connection sets it
+                    let _ = self.payload.set(cipher);
+                }
+                if let Some(pin) = pin {
+                    let context = binding.as_ref().map_or(&[][..], |binding| &binding[..]);
+                    if !pin_proof.is_some_and(|proof| pin.verify_proof(&proof, context)) {
+                        return Err(fail(CloseReason::PairingFailed, "Viewer sent a wrong or expired pairing PIN"));
+                    }
+                }
+                *self.viewer_name.lock().unwrap() = name;
+                self.viewer_recording.send_replace(recording);
+                Ok(SessionInfo {
+                    token,
+                    resume_from,
+                    // A viewer cannot take more than it was offered
+                    permission: Permission::negotiate(permission, taken),
+                })
+            }
+            other => anyhow::bail!("Unexpected handshake reply: {:?}", other),
+        }
+    }
+
+    /// Fingerprint of the viewer: the identity it signed the payload key
+    /// exchange with once the handshake is done, otherwise that of the
+    /// certificate it presented, which through a relay is the relay's
+    pub fn viewer_fingerprint(&self) -> Option<Fingerprint> {
+        self.viewer_identity.get().copied().or_else(|| peer_fingerprint(&self.quinn_conn))
+    }
+
+    /// Who the viewer is, with its name once the handshake is done
+    pub fn viewer(&self) -> ViewerInfo {
+        ViewerInfo {
+            name: self.viewer_name.lock().unwrap().clone(),
+            fingerprint: self.viewer_fingerprint(),
+            addr: self.quinn_conn.remote_address(),
+        }
+    }
+
+    /// Whether frame payloads are sealed end to end, which the host asks
+    /// for with `NetworkConfig::encrypt_payloads`
+    pub fn payloads_sealed(&self) -> bool {
+        self.payload.get().is_some()
+    }
+
+    /// Rotate the session's keys: QUIC's, and the payload key if payloads
+    /// are sealed. The viewer follows the payload key from the epoch of
+    /// what's sealed next. QUIC allows one rotation per round trip, so
+    /// this is for rotating now and then, not per frame.
+    pub fn rekey(&self) -> Result<()> {
+        self.quinn_conn.force_key_update();
+        if let Some(payload) = self.payload.get() {
+            let epoch = payload.rekey()?;
+            debug!("Moved to payload key epoch {}", epoch);
+        }
+        Ok(())
+    }
+
+    /// Send a control message on its own stream, ahead of any queued frames
+    pub async fn send_message(&self, message: &Message) -> Result<()> {
+        control::send_message(&self.quinn_conn, message).await
+    }
+
+    /// Send a full frame on a stream of its own, the way the viewer reads
+    /// them. Deltas sent afterwards apply on top of it.
+    pub async fn send_keyframe(&self, frame: &Frame) -> Result<()> {
+        let encoded = debug_span!("encode").in_scope(|| EncodedFrame::keyframe(frame))?;
+        self.send_encoded(&encoded).await
+    }
+
+    /// Send the pixel changes for a frame as delta update messages
+    pub async fn send_update(&self, update: &FrameUpdate) -> Result<()> {
+        let encoded = debug_span!("encode").in_scope(|| EncodedFrame::update(update))?;
+        self.send_encoded(&encoded).await
+    }
+
+    /// Send a keyframe or update coded ahead of time, e.g. to time coding
+    /// and sending separately
+    pub async fn send_encoded(&self, encoded: &EncodedFrame) -> Result<()> {
+        let payload = self.payload.get();
+        async {
+            match encoded {
+                EncodedFrame::Keyframe(data) => {
+                    let sealed;
+                    let data = match payload {
+                        Some(payload) => {
+                            sealed = payload.seal_keyframe(data)?;
+                            &sealed
+                        }
+                        None => data,
+                    };
+                    let (mut send, _recv) = self
+                        .quinn_conn
+                        .open_bi()
+                        .await
+                        .context("Failed to open frame stream")?;
+                    send.write_all(data)
+                        .await
+                        .context("Failed to send frame")?;
+                    send.finish().await.context("Failed to finish frame stream")?;
+                }
+                EncodedFrame::Update(parts) => {
+                    for part in parts {
+                        let sealed;
+                        let part = match payload {
+                            Some(payload) => {
+                                sealed = payload.seal_message(part)?;
+                                &sealed
+                            }
+                            None => part,
+                        };
+                        control::send_serialized(&self.quinn_conn, part).await?;
+                    }
+                }
+            }
+            Ok(())
+        }
+        .instrument(debug_span!("send", bytes = encoded.len()))
+        .await
+    }
+
+    /// Tell the viewer its permission changed, e.g. after
+    /// `RemoteInput::revoke_control`
+    pub async fn send_permission(&self, permission: Permission) -> Result<()> {
+        self.send_message(&Message::Permission(permission)).await
+    }
+
+    /// Open a stream of its own for audio from `streamer`'s source, so
+    /// packets never wait behind frames or control messages
+    pub async fn open_audio_stream(&self, streamer: &AudioStreamer) -> Result<AudioStream> {
+        let start = streamer.start_message();
+        let mut send = self
+            .quinn_conn
+            .open_uni()
+            .await
+            .context("Failed to open audio stream")?;
+        // Only fails if the stream is already gone, which write_all reports
+        let _ = send.set_priority(start.priority());
+        send.write_all(&start.serialize()?)
+            .await
+            .context("Failed to start audio stream")?;
+        Ok(AudioStream { send })
+    }
+
+    /// Watch this connection's congestion feedback to adapt the video and
+    /// audio bitrates, see `BandwidthMonitor::adapt_bitrates`
+    pub fn bandwidth_monitor(&self) -> BandwidthMonitor {
+        BandwidthMonitor {
+            connection: self.quinn_conn.clone(),
+        }
+    }
+
+    /// Ask the host for a full frame, e.g. after joining or a decode error
+    pub async fn request_keyframe(&self) -> Result<()> {
+        self.send_message(&Message::RequestKeyframe).await
+    }
+
+    /// Say goodbye to the peer and close the connection with `reason`
+    pub async fn close(self, reason: CloseReason) -> Result<()> {
+        // Best effort: the close code below carries the reason regardless
+        if let Err(e) = self.send_message(&Message::Goodbye { reason }).await {
+            debug!("Failed to send goodbye: {}", e);
+        }
+
+        self.quinn_conn
+            .close(reason.code(), reason.to_string().as_bytes());
+        self.shutdown().await;
+        Ok(())
+    }
+
+    /// End the tasks started by `control_events` and `start_frame_processing`
+    /// and wait for them, aborting any still running after a second. The
+    /// connection stays open; tasks started afterwards end at once.
+    pub async fn shutdown(&self) {
+        self.stop.send_replace(true);
+        let mut tasks = std::mem::take(&mut *self.tasks.lock().unwrap());
+        let joined = time::timeout(TASK_JOIN_TIMEOUT, async { while tasks.join_next().await.is_some() {} });
+        if joined.await.is_err() {
+            debug!("Connection tasks did not finish in time; aborting them");
+            tasks.shutdown().await;
+        }
+    }
+
+    /// Whether the viewer is recording the session, as it said in the
+    /// handshake and since, by `control_events`. None if it never said.
+    pub fn viewer_recording(&self) -> watch::Receiver<Option<bool>> {
+        self.viewer_recording.subscribe()
+    }
+
+    /// When the peer was last heard from, by `control_events`
+    pub fn last_heard(&self) -> Instant {
+        *self.last_heard.lock().unwrap()
+    }
+
+    /// Wait for the connection to close and report why
+    pub async fn closed(&self) -> CloseReason {
+        CloseReason::from_error(&self.quinn_conn.closed().await)
+    }
+
+    /// Spawn the tasks reading the peer's control messages. The returned
+    /// receiver yields them as events, ending with `NetworkEvent::Closed`.
+    /// Call once, after `handshake`.
+    pub fn control_events(&self) -> mpsc::Receiver<NetworkEvent> {
+        // Spawn control message task
+        let (event_tx, event_rx) = mpsc::channel(EVENT_CHANNEL_CAPACITY);
+        let quinn_conn = self.quinn_conn.clone();
+        let control_tx = event_tx.clone();
+        // Silence is counted from here, not from whatever came before
+        let last_heard = self.last_heard.clone();
+        *last_heard.lock().unwrap() = Instant::now();
+        let recording = self.viewer_recording.clone();
+        let mut stop = self.stop.subscribe();
+        let mut tasks = self.tasks.lock().unwrap();
+        tasks.spawn(async move {
+            loop {
+                let mut recv = tokio::select! {
+                    accepted = quinn_conn.accept_uni() => match accepted {
+                        Ok(recv) => recv,
+                        Err(_) => break,
+                    },
+                    _ = stop.wait_for(|stop| *stop) => break,
+                };
+                let message = control::read_message(&mut recv).await;
+                if message.is_ok() {
+                    *last_heard.lock().unwrap() = Instant::now();
+                }
+                match message {
+                    // The close watcher reports the reason from the close code
+                    Ok(Message::Goodbye { reason }) => debug!("Peer said goodbye: {}", reason),
+                    // Only says the peer is still there
+                    Ok(Message::KeepAlive) => {}
+                    // Repeats of what the viewer said before aren't changes
+                    Ok(Message::Recording(now)) => {
+                        recording.send_if_modified(|said| said.replace(now) != Some(now));
+                    }
+                    Ok(message) => {
+                        if control_tx.send(message.into()).await.is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => warn!("Invalid control message: {}", e),
+                }
+            }
+        });
+
+        // Spawn close watcher
+        let quinn_conn = self.quinn_conn.clone();
+        let mut stop = self.stop.subscribe();
+        tasks.spawn(async move {
+            // A close still gets reported when `close` stops the tasks
+            let closed = tokio::select! {
+                biased;
+                closed = quinn_conn.closed() => closed,
+                _ = stop.wait_for(|stop| *stop) => return,
+            };
+            let reason = CloseReason::from_error(&closed);
+            let _ = event_tx.send(NetworkEvent::Closed(reason)).await;
+        });
+
+        event_rx
+    }
+
+    /// Spawn the frame send/receive tasks, which run until the peer closes
+    /// the connection or `shutdown` ends them. The returned receiver yields
+    /// the peer's control messages as events, ending with
+    /// `NetworkEvent::Closed`. Call once.
+    pub async fn start_frame_processing(&self) -> Result<mpsc::Receiver<NetworkEvent>> {
+        let mut frame_rx = self
+            .frame_rx
+            .lock()
+            .unwrap()
+            .take()
+            .ok_or_else(|| anyhow::anyhow!("Frame processing already started"))?;
+        let (send_stream, recv_stream) = self.quinn_conn.open_bi().await?;
+
+        // Spawn receive task
+        let frame_tx = self.frame_tx.clone();
+        let mut stop = self.stop.subscribe();
+        self.tasks.lock().unwrap().spawn(async move {
+            let mut recv_stream = recv_stream;
+            loop {
+                let mut buf = vec![0u8; 8192];
+                let read = tokio::select! {
+                    read = recv_stream.read(&mut buf) => read,
+                    _ = stop.wait_for(|stop| *stop) => break,
+                };
+                match read {
+                    Ok(Some(n)) if n > 0 => {
+                        buf.truncate(n);
+                        if let Ok(frame) = Frame::decode(&buf) {
+                            if frame_tx.send(frame).await.is_err() {
+                                break;
+                            }
+                        }
+                    }
+                    _ => break,
+                }
+            }
+        });
+
+        let event_rx = self.control_events();
+
+        // Spawn send task
+        let mut send_stream = send_stream;
+        let mut stop = self.stop.subscribe();
+        self.tasks.lock().unwrap().spawn(async move {
+            loop {
+                let frame = tokio::select! {
+                    frame = frame_rx.recv() => match frame {
+                        Some(frame) => frame,
+                        None => break,
+                    },
+                    _ = stop.wait_for(|stop| *stop) => break,
+                };
+                if let Ok(encoded) = frame.encode() {
+                    if send_stream.write_all(&encoded).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+
+        Ok(event_rx)
+    }
+}
+
+/// A host audio stream opened by `Connection::open_audio_stream`
+pub struct AudioStream {
+    send: quinn::SendStream,
+}
+
+impl AudioStream {
+    /// Send packets made by the stream's `AudioStreamer`
+    pub async fn send(&mut self, packets: &[Message]) -> Result<()> {
+        for packet in packets {
+            self.send
+                .write_all(&packet.serialize()?)
+                .await
+                .context("Failed to send audio")?;
+        }
+        Ok(())
+    }
+
+    /// End the stream, e.g. when the host stops sharing audio
+    pub async fn finish(mut self) -> Result<()> {
+        self.send.finish().await.context("Failed to finish audio stream")
+    }
+}
+
+/// Capture, encode and send audio until capture stops or the connection
+/// goes away
+pub async fn stream_audio(capture: &mut AudioCapture, streamer: &mut AudioStreamer, stream: &mut AudioStream) -> Result<()> {
+    while let Some(captured) = capture.next().await {
+        let packets = streamer.push(captured.pts, &captured.samples)?;
+        stream.send(&packets).await?;
+    }
+    debug!("{:?} audio capture stopped", streamer.source());
+    Ok(())
+}
+
+/// Apply host-side reactions to connection events until the connection
+/// closes, returning the reason it closed. Viewer input goes to `input`,
+/// which drops it unless the host allowed remote control, and the viewer's
+/// mute and gain changes go to `audio`.
+pub async fn handle_host_events(
+    events: &mut mpsc::Receiver<NetworkEvent>,
+    encoder: &FrameEncoder,
+    input: &mut RemoteInput,
+    audio: &AudioControls,
+) -> CloseReason {
+    while let Some(event) = events.recv().await {
+        match event {
+            NetworkEvent::KeyframeRequested => encoder.force_keyframe(),
+            NetworkEvent::Input(event) => {
+                if let Err(e) = input.handle(event) {
+                    warn!("Failed to inject {:?}: {}", event, e);
+                }
+            }
+            NetworkEvent::Message(Message::MouseMode(mode)) => input.set_mouse_mode(mode),
+            NetworkEvent::Message(message) if audio.apply(&message) => {}
+            NetworkEvent::Closed(reason) => {
+                input.release_all();
+                return reason;
+            }
+            NetworkEvent::Message(message) => debug!("Unhandled control message: {:?}", message),
+            NetworkEvent::Connected { .. } => {}
+        }
+    }
+
+    input.release_all();
+    CloseReason::ConnectionLost
+}
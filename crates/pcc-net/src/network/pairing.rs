@@ -0,0 +1,151 @@
+use super::identity::Fingerprint;
+use anyhow::{Context, Result};
+use ring::digest::{digest, SHA256};
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Digits in a pairing PIN
+pub const PIN_DIGITS: u32 = 6;
+/// How long a pairing PIN can be entered for
+pub const PIN_LIFETIME: Duration = Duration::from_secs(120);
+
+/// A one-off PIN the host shows, which a viewer must send back to pair
+#[derive(Debug, Clone)]
+pub struct PairingPin {
+    code: String,
+    expires: Instant,
+}
+
+impl PairingPin {
+    /// A new random PIN, valid for `PIN_LIFETIME`
+    pub fn generate() -> Result<Self> {
+        let range = 10u32.pow(PIN_DIGITS);
+        // Draw again above the largest multiple of the range, so every PIN
+        // is equally likely
+        let limit = u32::MAX - u32::MAX % range;
+        let rng = SystemRandom::new();
+        let value = loop {
+            let mut bytes = [0u8; 4];
+            rng.fill(&mut bytes).map_err(|_| anyhow::anyhow!("Failed to generate pairing PIN"))?;
+            let value = u32::from_le_bytes(bytes);
+            if value < limit {
+                break value % range;
+            }
+        };
+        Ok(Self {
+            code: format!("{:0width$}", value, width = PIN_DIGITS as usize),
+            expires: Instant::now() + PIN_LIFETIME,
+        })
+    }
+
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    /// Whether `submitted` is this PIN and it hasn't expired. Spaces and
+    /// dashes, as in "123 456", are ignored.
+    pub fn verify(&self, submitted: &str) -> bool {
+        let submitted = normalize_pin(submitted);
+        // Compared in constant time, so timing doesn't give digits away
+        let matches = ring::constant_time::verify_slices_are_equal(submitted.as_bytes(), self.code.as_bytes()).is_ok();
+        matches && Instant::now() < self.expires
+    }
+
+    /// Whether `proof` is what `pin_proof` makes of this PIN and `context`,
+    /// and the PIN hasn't expired
+    pub fn verify_proof(&self, proof: &[u8], context: &[u8]) -> bool {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, pin_key(&self.code).as_ref());
+        hmac::verify(&key, context, proof).is_ok() && Instant::now() < self.expires
+    }
+}
+
+/// What a viewer sends to show it has the PIN, rather than the PIN: a MAC
+/// of `context` keyed by the PIN. The context should be something only the
+/// two ends know, such as the sealed payload key's `PayloadCipher::binding`,
+/// so whatever carries the connection can't test PINs against it.
+pub fn pin_proof(typed: &str, context: &[u8]) -> [u8; 32] {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, pin_key(&normalize_pin(typed)).as_ref());
+    let mut proof = [0; 32];
+    proof.copy_from_slice(hmac::sign(&key, context).as_ref());
+    proof
+}
+
+// The MAC key for a normalized PIN
+fn pin_key(pin: &str) -> ring::digest::Digest {
+    digest(&SHA256, [b"pcc pairing pin ".as_slice(), pin.as_bytes()].concat().as_slice())
+}
+
+// A PIN as typed, without the spaces and dashes it may be grouped with
+pub(crate) fn normalize_pin(typed: &str) -> String {
+    typed.chars().filter(|c| !matches!(c, ' ' | '-')).collect()
+}
+
+/// Viewers a host has paired with, by certificate fingerprint, which
+/// connect again without a PIN. Kept in a file, one fingerprint per line.
+#[derive(Debug)]
+pub struct PairedViewers {
+    path: PathBuf,
+    viewers: Vec<Fingerprint>,
+}
+
+impl PairedViewers {
+    /// Read the viewers paired so far from `path`, which need not exist yet
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        let viewers = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| line.parse().with_context(|| format!("Invalid fingerprint in {}", path.display())))
+            .collect::<Result<_>>()?;
+        Ok(Self { path: path.to_path_buf(), viewers })
+    }
+
+    pub fn contains(&self, viewer: &Fingerprint) -> bool {
+        self.viewers.contains(viewer)
+    }
+
+    /// Remember `viewer` as paired, saving it to the file
+    pub fn add(&mut self, viewer: Fingerprint) -> Result<()> {
+        if self.contains(&viewer) {
+            return Ok(());
+        }
+        self.viewers.push(viewer);
+        if let Some(dir) = self.path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let text: String = self.viewers.iter().map(|viewer| format!("{}\n", viewer)).collect();
+        std::fs::write(&self.path, text).with_context(|| format!("Failed to write {}", self.path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pin_is_six_digits_and_verifies_once_typed() {
+        let pin = PairingPin::generate().unwrap();
+        assert_eq!(pin.code().len(), PIN_DIGITS as usize);
+        assert!(pin.code().chars().all(|c| c.is_ascii_digit()));
+
+        let typed = format!("{} {}", &pin.code()[..3], &pin.code()[3..]);
+        assert!(pin.verify(&typed));
+        assert!(!pin.verify(&pin.code()[1..]));
+
+        let proof = pin_proof(&typed, b"session");
+        assert!(pin.verify_proof(&proof, b"session"));
+        assert!(!pin.verify_proof(&proof, b"another session"));
+        assert!(!pin.verify_proof(&pin_proof("000000", b"session"), b"session") || pin.code() == "000000");
+
+        let expired = PairingPin { expires: Instant::now(), ..pin };
+        assert!(!expired.verify(expired.code()));
+        assert!(!expired.verify_proof(&proof, b"session"));
+    }
+}
@@ -0,0 +1,462 @@
+use super::identity::{verify_signature, Fingerprint, Identity};
+use super::pairing::normalize_pin;
+use super::protocol::Message;
+use anyhow::{Context, Result};
+use ring::aead::{self, Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305};
+use ring::agreement::{self, EphemeralPrivateKey, UnparsedPublicKey, X25519};
+use ring::digest::{self, SHA256};
+use ring::hkdf::{Prk, Salt, HKDF_SHA256};
+use ring::rand::SystemRandom;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+// Bytes of the key epoch and nonce counter written ahead of a sealed
+// keyframe
+const EPOCH_PREFIX: usize = 4;
+const NONCE_PREFIX: usize = 8;
+
+// Binds derived keys to their use
+const KEY_INFO: &[u8] = b"pcc frame payload key";
+const REKEY_INFO: &[u8] = b"pcc frame payload rekey";
+const BINDING_INFO: &[u8] = b"pcc frame payload binding";
+const TRANSCRIPT_LABEL: &[u8] = b"pcc frame payload exchange";
+
+// Nonces below the highest seen that may still arrive, since sealed
+// messages and keyframes travel on streams of their own and can overtake
+// one another
+const REPLAY_WINDOW: u64 = 128;
+
+// Epochs the viewer steps ahead at once to catch up with the host, at most
+const MAX_EPOCH_SKIP: u32 = 16;
+
+/// One end's half of the key exchange for sealing frame payloads, sent in
+/// the handshake. Both ends agree on the same `PayloadCipher` from the
+/// other's public key and a `KeyBinding`.
+#[derive(Debug)]
+pub struct PayloadKeyExchange {
+    private: EphemeralPrivateKey,
+    public: [u8; 32],
+}
+
+impl PayloadKeyExchange {
+    pub fn new() -> Result<Self> {
+        let rng = SystemRandom::new();
+        let private = EphemeralPrivateKey::generate(&X25519, &rng)
+            .map_err(|_| anyhow::anyhow!("Failed to generate payload key"))?;
+        let public = private
+            .compute_public_key()
+            .map_err(|_| anyhow::anyhow!("Failed to compute payload public key"))?;
+        let public = public.as_ref().try_into().context("Unexpected payload public key length")?;
+        Ok(Self { private, public })
+    }
+
+    pub fn public_key(&self) -> [u8; 32] {
+        self.public
+    }
+
+    /// Derive the key both ends share from the peer's public key, mixed
+    /// with everything in `binding`, which must name both keys
+    pub fn agree(self, peer: &[u8; 32], binding: &KeyBinding) -> Result<PayloadCipher> {
+        anyhow::ensure!(
+            [binding.host_key, binding.viewer_key] == [self.public, *peer]
+                || [binding.host_key, binding.viewer_key] == [*peer, self.public],
+            "Key binding is for another exchange"
+        );
+        let salt = binding.salt();
+        let secret = agreement::agree_ephemeral(self.private, &UnparsedPublicKey::new(&X25519, peer), |shared| {
+            Salt::new(HKDF_SHA256, salt.as_ref()).extract(shared)
+        })
+        .map_err(|_| anyhow::anyhow!("Payload key exchange failed"))?;
+        let mut proof = [0; 32];
+        secret
+            .expand(&[BINDING_INFO], ProofLen)
+            .and_then(|okm| okm.fill(&mut proof))
+            .map_err(|_| anyhow::anyhow!("Failed to derive payload key binding"))?;
+        Ok(PayloadCipher {
+            keys: Mutex::new(PayloadKeys { current: KeyEpoch::new(0, secret)?, previous: None, next_nonce: 0 }),
+            binding: proof,
+        })
+    }
+}
+
+// Length of the value `PayloadCipher::binding` derives
+struct ProofLen;
+
+impl ring::hkdf::KeyType for ProofLen {
+    fn len(&self) -> usize {
+        32
+    }
+}
+
+/// What both ends mix into the payload key besides the exchange itself.
+/// A relay that swaps in keys of its own can't pass the viewer's signature
+/// over them off as its own, and doesn't know the PIN, so it ends up with
+/// keys that neither end shares.
+#[derive(Debug, Clone, Copy)]
+pub struct KeyBinding<'a> {
+    pub host_key: [u8; 32],
+    pub viewer_key: [u8; 32],
+    /// The viewer's identity, from its `IdentityProof`
+    pub viewer: Option<Fingerprint>,
+    /// The pairing PIN, which never goes on the wire
+    pub pin: Option<&'a str>,
+}
+
+impl KeyBinding<'_> {
+    /// Both halves of the exchange, as the viewer signs them
+    pub fn transcript(&self) -> Vec<u8> {
+        [TRANSCRIPT_LABEL, &self.host_key, &self.viewer_key].concat()
+    }
+
+    fn salt(&self) -> digest::Digest {
+        let mut context = digest::Context::new(&SHA256);
+        context.update(&self.transcript());
+        if let Some(viewer) = self.viewer {
+            context.update(b"viewer");
+            context.update(viewer.as_bytes());
+        }
+        if let Some(pin) = self.pin {
+            context.update(b"pin");
+            context.update(normalize_pin(pin).as_bytes());
+        }
+        context.finish()
+    }
+}
+
+/// The viewer's certificate and its signature over the key exchange's
+/// `transcript`, binding the exchange to an identity a host can pin.
+/// Relays can't make one for keys of their own without the viewer's key.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdentityProof {
+    /// DER certificate
+    pub cert: Vec<u8>,
+    pub signature: Vec<u8>,
+}
+
+impl IdentityProof {
+    pub fn sign(identity: &Identity, transcript: &[u8]) -> Result<Self> {
+        Ok(Self { cert: identity.cert_der().to_vec(), signature: identity.sign(transcript)? })
+    }
+
+    /// The fingerprint of the identity that signed `transcript`, failing
+    /// if the signature doesn't match
+    pub fn verify(&self, transcript: &[u8]) -> Result<Fingerprint> {
+        verify_signature(&self.cert, transcript, &self.signature)
+    }
+}
+
+// Nonces opened under one key epoch: the highest, and which of the ones
+// just below it
+#[derive(Debug, Default)]
+struct ReplayWindow {
+    highest: Option<u64>,
+    // Bit i set if `highest - i` was opened
+    seen: u128,
+}
+
+impl ReplayWindow {
+    fn check(&self, nonce: u64) -> Result<()> {
+        let Some(highest) = self.highest else { return Ok(()) };
+        if nonce > highest {
+            return Ok(());
+        }
+        let behind = highest - nonce;
+        anyhow::ensure!(behind < REPLAY_WINDOW, "Payload nonce {} is too old", nonce);
+        anyhow::ensure!(self.seen & (1 << behind) == 0, "Payload nonce {} was already opened", nonce);
+        Ok(())
+    }
+
+    // Only for nonces `check` let through that then authenticated
+    fn record(&mut self, nonce: u64) {
+        match self.highest {
+            Some(highest) if nonce <= highest => self.seen |= 1 << (highest - nonce),
+            Some(highest) => {
+                let ahead = nonce - highest;
+                self.seen = if ahead < REPLAY_WINDOW { self.seen << ahead | 1 } else { 1 };
+                self.highest = Some(nonce);
+            }
+            None => {
+                self.seen = 1;
+                self.highest = Some(nonce);
+            }
+        }
+    }
+}
+
+// One generation of the payload key, and the secret the next is derived
+// from
+#[derive(Debug)]
+struct KeyEpoch {
+    number: u32,
+    secret: Prk,
+    key: LessSafeKey,
+    opened: ReplayWindow,
+}
+
+impl KeyEpoch {
+    fn new(number: u32, secret: Prk) -> Result<Self> {
+        let key = secret
+            .expand(&[KEY_INFO], &CHACHA20_POLY1305)
+            .map(UnboundKey::from)
+            .map_err(|_| anyhow::anyhow!("Failed to derive payload key"))?;
+        Ok(Self { number, secret, key: LessSafeKey::new(key), opened: ReplayWindow::default() })
+    }
+
+    // The next generation. It can't be worked back from, so a key that
+    // leaks doesn't open what was sealed before it.
+    fn next(&self) -> Result<Self> {
+        let number = self.number.checked_add(1).context("Payload key epochs used up")?;
+        let secret = self
+            .secret
+            .expand(&[REKEY_INFO], HKDF_SHA256)
+            .map_err(|_| anyhow::anyhow!("Failed to derive next payload key"))?;
+        Self::new(number, Prk::from(secret))
+    }
+
+    // Open what was sealed with `nonce`, once only
+    fn open(&mut self, nonce: u64, sealed: &[u8]) -> Result<Vec<u8>> {
+        self.opened.check(nonce)?;
+        let mut data = sealed.to_vec();
+        let len = self
+            .key
+            .open_in_place(PayloadCipher::nonce(nonce), Aad::empty(), &mut data)
+            .map_err(|_| anyhow::anyhow!("Payload failed to authenticate"))?
+            .len();
+        data.truncate(len);
+        self.opened.record(nonce);
+        Ok(data)
+    }
+}
+
+#[derive(Debug)]
+struct PayloadKeys {
+    current: KeyEpoch,
+    // Kept by the viewer for what was sealed just before a rekey and
+    // arrives after it
+    previous: Option<KeyEpoch>,
+    next_nonce: u64,
+}
+
+/// Seals the host's frame payloads so only the viewer can read them, even
+/// through a relay that terminates QUIC. Only the host seals, so a counter
+/// keeps nonces unique, and the viewer opens each nonce once, so a relay
+/// can't replay what it saw. The host moves to a new key epoch on `rekey`,
+/// and the viewer follows when it sees one.
+#[derive(Debug)]
+pub struct PayloadCipher {
+    keys: Mutex<PayloadKeys>,
+    binding: [u8; 32],
+}
+
+impl PayloadCipher {
+    fn nonce(counter: u64) -> Nonce {
+        let mut nonce = [0u8; aead::NONCE_LEN];
+        nonce[aead::NONCE_LEN - NONCE_PREFIX..].copy_from_slice(&counter.to_le_bytes());
+        Nonce::assume_unique_for_key(nonce)
+    }
+
+    /// A value derived from the agreed secret, the same at both ends only
+    /// if they agreed on one key: what a pairing PIN proof is made over
+    pub fn binding(&self) -> [u8; 32] {
+        self.binding
+    }
+
+    /// The key epoch payloads are being sealed with
+    pub fn epoch(&self) -> u32 {
+        self.keys.lock().unwrap().current.number
+    }
+
+    /// Move to the next key epoch, forgetting the current key, and return
+    /// the new epoch
+    pub fn rekey(&self) -> Result<u32> {
+        let mut keys = self.keys.lock().unwrap();
+        keys.current = keys.current.next()?;
+        keys.previous = None;
+        // A new key starts its nonces over
+        keys.next_nonce = 0;
+        Ok(keys.current.number)
+    }
+
+    /// Encrypt `data`, returning the key epoch and nonce counter it was
+    /// sealed with
+    pub fn seal(&self, data: &[u8]) -> Result<(u32, u64, Vec<u8>)> {
+        let mut keys = self.keys.lock().unwrap();
+        let counter = keys.next_nonce;
+        keys.next_nonce += 1;
+        let mut sealed = data.to_vec();
+        keys.current
+            .key
+            .seal_in_place_append_tag(Self::nonce(counter), Aad::empty(), &mut sealed)
+            .map_err(|_| anyhow::anyhow!("Failed to seal payload"))?;
+        Ok((keys.current.number, counter, sealed))
+    }
+
+    /// Decrypt what `seal` made, failing if it was tampered with. A later
+    /// epoch is caught up to only once something sealed with it opens, so
+    /// forged epochs can't make the viewer drop its key.
+    pub fn open(&self, epoch: u32, nonce: u64, sealed: &[u8]) -> Result<Vec<u8>> {
+        let mut keys = self.keys.lock().unwrap();
+        if epoch == keys.current.number {
+            return keys.current.open(nonce, sealed);
+        }
+        if epoch < keys.current.number {
+            return match &mut keys.previous {
+                Some(previous) if previous.number == epoch => previous.open(nonce, sealed),
+                _ => anyhow::bail!("Payload sealed with retired key epoch {}", epoch),
+            };
+        }
+        anyhow::ensure!(
+            epoch - keys.current.number <= MAX_EPOCH_SKIP,
+            "Payload key epoch {} is too far ahead of {}",
+            epoch,
+            keys.current.number
+        );
+        let mut skipped = None;
+        let mut next = keys.current.next()?;
+        while next.number < epoch {
+            let after = next.next()?;
+            skipped = Some(std::mem::replace(&mut next, after));
+        }
+        let data = next.open(nonce, sealed)?;
+        let current = std::mem::replace(&mut keys.current, next);
+        keys.previous = Some(skipped.unwrap_or(current));
+        Ok(data)
+    }
+
+    /// Seal a serialized message into a `Message::Sealed`, serialized
+    pub fn seal_message(&self, serialized: &[u8]) -> Result<Vec<u8>> {
+        let (epoch, nonce, data) = self.seal(serialized)?;
+        Message::Sealed { epoch, nonce, data }.serialize()
+    }
+
+    /// The message inside a `Message::Sealed`
+    pub fn open_message(&self, epoch: u32, nonce: u64, data: &[u8]) -> Result<Message> {
+        Message::deserialize(&self.open(epoch, nonce, data)?)
+    }
+
+    /// Seal a coded keyframe for its stream, key epoch and nonce first
+    pub fn seal_keyframe(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let (epoch, nonce, sealed) = self.seal(data)?;
+        let mut framed = Vec::with_capacity(EPOCH_PREFIX + NONCE_PREFIX + sealed.len());
+        framed.extend_from_slice(&epoch.to_le_bytes());
+        framed.extend_from_slice(&nonce.to_le_bytes());
+        framed.extend_from_slice(&sealed);
+        Ok(framed)
+    }
+
+    /// The coded keyframe inside what `seal_keyframe` made
+    pub fn open_keyframe(&self, framed: &[u8]) -> Result<Vec<u8>> {
+        anyhow::ensure!(framed.len() >= EPOCH_PREFIX + NONCE_PREFIX, "Sealed keyframe too short");
+        let (epoch, rest) = framed.split_at(EPOCH_PREFIX);
+        let (nonce, sealed) = rest.split_at(NONCE_PREFIX);
+        let epoch = u32::from_le_bytes(epoch.try_into().expect("Epoch prefix is 4 bytes"));
+        let nonce = u64::from_le_bytes(nonce.try_into().expect("Nonce prefix is 8 bytes"));
+        self.open(epoch, nonce, sealed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Both ends of an exchange, each given its own PIN
+    fn agree(host_pin: Option<&str>, viewer_pin: Option<&str>) -> (PayloadCipher, PayloadCipher) {
+        let (host, viewer) = (PayloadKeyExchange::new().unwrap(), PayloadKeyExchange::new().unwrap());
+        let (host_key, viewer_key) = (host.public_key(), viewer.public_key());
+        let binding = KeyBinding { host_key, viewer_key, viewer: None, pin: host_pin };
+        let host = host.agree(&viewer_key, &binding).unwrap();
+        let viewer = viewer.agree(&host_key, &KeyBinding { pin: viewer_pin, ..binding }).unwrap();
+        (host, viewer)
+    }
+
+    #[test]
+    fn test_both_ends_agree_only_with_the_same_pin() {
+        let (host, viewer) = agree(Some("123456"), Some("123 456"));
+        assert_eq!(host.binding(), viewer.binding());
+
+        let sealed = host.seal_keyframe(b"pixels").unwrap();
+        assert!(!sealed.windows(6).any(|window| window == b"pixels"));
+        assert_eq!(viewer.open_keyframe(&sealed).unwrap(), b"pixels");
+
+        let mut tampered = sealed.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(viewer.open_keyframe(&tampered).is_err());
+
+        let (host, other) = agree(Some("123456"), Some("654321"));
+        assert_ne!(host.binding(), other.binding());
+        assert!(other.open_keyframe(&host.seal_keyframe(b"pixels").unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_a_relay_swapping_keys_ends_up_with_neither_ends_key() {
+        let identity = Identity::generate(vec!["localhost".to_string()]).unwrap();
+        let (host, relay, viewer) =
+            (PayloadKeyExchange::new().unwrap(), PayloadKeyExchange::new().unwrap(), PayloadKeyExchange::new().unwrap());
+        let (host_key, relay_key, viewer_key) = (host.public_key(), relay.public_key(), viewer.public_key());
+
+        // The viewer signs the exchange it saw, with the relay's key in it
+        let seen = KeyBinding { host_key: relay_key, viewer_key, viewer: None, pin: None };
+        let proof = IdentityProof::sign(&identity, &seen.transcript()).unwrap();
+        assert_eq!(proof.verify(&seen.transcript()).unwrap(), identity.fingerprint().unwrap());
+
+        // Passed on to the host, it doesn't cover the exchange the host saw
+        let sent = KeyBinding { host_key, viewer_key: relay_key, viewer: None, pin: None };
+        assert!(proof.verify(&sent.transcript()).is_err());
+        assert!(host.agree(&relay_key, &KeyBinding { host_key, viewer_key, ..sent }).is_err(), "Binding names the keys");
+    }
+
+    #[test]
+    fn test_sealed_payloads_open_once() {
+        let (host, viewer) = agree(None, None);
+        let sealed: Vec<_> = (0..4).map(|i| host.seal_message(&[i]).unwrap()).collect();
+        let open = |message: &[u8]| match Message::deserialize(message).unwrap() {
+            Message::Sealed { epoch, nonce, data } => viewer.open(epoch, nonce, &data),
+            other => panic!("Expected a sealed message, got {:?}", other),
+        };
+
+        // Out of order is fine, but each only once
+        assert!(open(&sealed[2]).is_ok());
+        assert!(open(&sealed[0]).is_ok());
+        assert!(open(&sealed[2]).is_err(), "Replayed");
+        assert!(open(&sealed[0]).is_err(), "Replayed");
+        assert!(open(&sealed[1]).is_ok());
+        assert!(open(&sealed[3]).is_ok());
+
+        // Too far behind the newest to tell whether it was seen
+        let old = host.seal_keyframe(b"old").unwrap();
+        for _ in 0..REPLAY_WINDOW {
+            host.seal_message(b"newer").unwrap();
+        }
+        assert!(viewer.open_keyframe(&host.seal_keyframe(b"newest").unwrap()).is_ok());
+        assert!(viewer.open_keyframe(&old).is_err());
+    }
+
+    #[test]
+    fn test_viewer_follows_rekeys() {
+        let (host, viewer) = agree(None, None);
+
+        let before = host.seal_keyframe(b"epoch 0").unwrap();
+        assert_eq!(host.rekey().unwrap(), 1);
+        let after = host.seal_keyframe(b"epoch 1").unwrap();
+        // The same plaintext and nonce under the new key seal differently
+        assert_eq!(after[..4], 1u32.to_le_bytes());
+        assert_eq!(after[4..12], before[4..12]);
+
+        // Arriving out of order across the rekey
+        assert_eq!(viewer.open_keyframe(&after).unwrap(), b"epoch 1");
+        assert_eq!(viewer.epoch(), 1);
+        assert_eq!(viewer.open_keyframe(&before).unwrap(), b"epoch 0");
+        assert!(viewer.open_keyframe(&before).is_err(), "Replayed");
+
+        // Skipping epochs, with a forged epoch leaving the key be
+        host.rekey().unwrap();
+        host.rekey().unwrap();
+        let skipped = host.seal_keyframe(b"epoch 3").unwrap();
+        let mut forged = skipped.clone();
+        forged[..4].copy_from_slice(&9u32.to_le_bytes());
+        assert!(viewer.open_keyframe(&forged).is_err());
+        assert_eq!(viewer.epoch(), 1);
+        assert_eq!(viewer.open_keyframe(&skipped).unwrap(), b"epoch 3");
+        assert!(viewer.open_keyframe(&before).is_err());
+    }
+}
@@ -0,0 +1,548 @@
+use anyhow::Result;
+use bytes::{Bytes, BytesMut};
+use pcc_core::wire;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::time::SystemTime;
+
+pub(crate) use pcc_core::wire::{HEADER_SIZE, MAX_FRAME_SIZE, MAX_MESSAGE_SIZE};
+
+// Frame payload bytes per FrameData message, leaving room for its header fields
+const FRAME_CHUNK_SIZE: usize = MAX_MESSAGE_SIZE - 256;
+
+// Bytes a PixelChange takes serialized besides its pixels: its rect and
+// the pixels' length
+const CHANGE_OVERHEAD: usize = 4 * 4 + 8;
+
+// Incomplete frames kept while waiting for missing chunks
+const MAX_PENDING_FRAMES: usize = 8;
+
+// Stream priorities for input and audio; everything else uses the default of 0
+const INPUT_PRIORITY: i32 = 16;
+const AUDIO_PRIORITY: i32 = 8;
+
+// Variants are told apart on the wire by their place in this enum, so new
+// ones go at the end. Inserting or reordering variants renumbers the tags
+// after them; that is only allowed together with a bump to
+// `pcc_core::wire::PROTOCOL_VERSION`, so old peers refuse the new layout.
+// `pcc_core::wire::FrameMessage` reads the frame ones by their places, from
+// `pcc_core::wire::tag`, which the tests below check; update both in the
+// same change.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Message {
+    // Frame-related messages
+    FrameData {
+        frame_id: u64,
+        timestamp: SystemTime,
+        width: u32,
+        height: u32,
+        chunk_index: u32,
+        chunk_count: u32,
+        data: Bytes,
+    },
+    FrameAck {
+        frame_id: u64,
+    },
+    /// Pixel changes since the previous frame, split across `parts`
+    /// messages; the viewer presents once the last part is applied
+    FrameUpdate {
+        update: pcc_core::FrameUpdate,
+        part: u32,
+        parts: u32,
+    },
+    /// How long the host took to capture, diff and code a frame it sent
+    FrameTimings(crate::telemetry::FrameTimings),
+    /// Copy a block of the viewer's current frame to `dst_rect`, reading
+    /// from the same-sized block at (`src_x`, `src_y`)
+    CopyRect {
+        src_x: u32,
+        src_y: u32,
+        dst_rect: pcc_core::Rect,
+    },
+
+    // Cursor messages, composited locally by the viewer. `pcc` hosts don't
+    // send them yet, as screen capture can't read the OS cursor; other
+    // hosts speaking the protocol may
+    CursorMoved {
+        x: u32,
+        y: u32,
+    },
+    CursorShape {
+        hotspot: (u32, u32),
+        width: u32,
+        height: u32,
+        rgba: Vec<u8>,
+    },
+    
+    // Viewer input for remote control, stamped with the viewer's clock and
+    // the modifiers it had held. Sent ahead of frame data; see `priority`.
+    KeyEvent {
+        key: crate::input::KeyCode,
+        pressed: bool,
+        /// What the key typed on the viewer's layout
+        text: Option<char>,
+        modifiers: crate::input::Modifiers,
+        timestamp: SystemTime,
+    },
+    /// Switch the host between absolute and relative mouse input, e.g. as
+    /// the viewer captures or releases its mouse
+    MouseMode(crate::input::MouseMode),
+    /// Text entered on the viewer without a key of its own, e.g. from an IME
+    TextInput {
+        text: char,
+        timestamp: SystemTime,
+    },
+    MouseMove {
+        x: u32,
+        y: u32,
+        modifiers: crate::input::Modifiers,
+        timestamp: SystemTime,
+    },
+    MouseMotion {
+        dx: i32,
+        dy: i32,
+        modifiers: crate::input::Modifiers,
+        timestamp: SystemTime,
+    },
+    MouseButton {
+        button: crate::input::MouseButton,
+        pressed: bool,
+        modifiers: crate::input::Modifiers,
+        timestamp: SystemTime,
+    },
+    Scroll {
+        dx: i32,
+        dy: i32,
+        modifiers: crate::input::Modifiers,
+        timestamp: SystemTime,
+    },
+    Touch {
+        id: u32,
+        phase: crate::input::TouchPhase,
+        x: u32,
+        y: u32,
+        timestamp: SystemTime,
+    },
+    Gesture {
+        gesture: crate::input::Gesture,
+        modifiers: crate::input::Modifiers,
+        timestamp: SystemTime,
+    },
+
+    // Host audio. Each source gets its own long-lived stream, opened with
+    // `AudioStreamStart` and followed by its packets; see
+    // `Connection::open_audio_stream`.
+    AudioStreamStart {
+        source: crate::audio::AudioSource,
+        format: crate::audio::AudioFormat,
+    },
+    /// One encoded packet, stamped with the capture time of its first sample
+    AudioPacket {
+        source: crate::audio::AudioSource,
+        sequence: u64,
+        pts: SystemTime,
+        data: Vec<u8>,
+    },
+    /// Mute or unmute a source; either end may send this. The host stops
+    /// sending a muted source and the viewer plays it silent.
+    AudioMute {
+        source: crate::audio::AudioSource,
+        muted: bool,
+    },
+    /// Set a source's playback gain; either end may send this
+    AudioGain {
+        source: crate::audio::AudioSource,
+        gain: f32,
+    },
+
+    // Application-defined side channel data
+    AppData {
+        topic: String,
+        payload: Vec<u8>,
+    },
+
+    // Session handshake: the host opens with `Hello`, presenting its resume
+    // token when reconnecting and the most it lets viewers do, and the
+    // viewer answers with `Welcome`, the permission it takes and its name.
+    // When `pairing`, the viewer must prove it has the PIN the host shows,
+    // with `pairing::pin_proof`; the PIN itself never goes on the wire.
+    // With `payload_key`, each end sends its half of the key exchange for
+    // sealing frame payloads, and a viewer with a saved identity signs the
+    // exchange in `identity`. The viewer says in `recording` whether it's
+    // recording the session; viewers that don't leave it out.
+    Hello {
+        resume_token: Option<super::ResumeToken>,
+        permission: crate::input::Permission,
+        pairing: bool,
+        payload_key: Option<[u8; 32]>,
+    },
+    Welcome {
+        token: super::ResumeToken,
+        resume_from: Option<u64>,
+        permission: crate::input::Permission,
+        pin_proof: Option<[u8; 32]>,
+        name: Option<String>,
+        payload_key: Option<[u8; 32]>,
+        recording: Option<bool>,
+        identity: Option<super::IdentityProof>,
+    },
+    /// A frame payload message sealed with the session's `PayloadCipher`,
+    /// under key `epoch`
+    Sealed {
+        epoch: u32,
+        nonce: u64,
+        data: Vec<u8>,
+    },
+    /// The host changed the viewer's permission mid-session
+    Permission(crate::input::Permission),
+    /// The viewer started or stopped recording the session locally
+    Recording(bool),
+
+    // Control messages
+    KeepAlive,
+    /// Ask the host to send a full frame instead of deltas
+    RequestKeyframe,
+    Goodbye {
+        reason: super::CloseReason,
+    },
+    QualityConfig(pcc_core::QualityConfig),
+    /// Color space of the host's frames; sRGB until announced
+    ColorSpace(pcc_core::ColorSpace),
+    Error(String),
+}
+
+impl Message {
+    // Serialize message to bytes
+    pub fn serialize(&self) -> Result<Vec<u8>> {
+        wire::write_message(&bincode::serialize(self)?)
+    }
+
+    // Deserialize message from bytes
+    pub fn deserialize(bytes: &[u8]) -> Result<Self> {
+        Ok(bincode::deserialize(wire::read_message(bytes)?)?)
+    }
+}
+
+impl Message {
+    /// Wrap viewer input for sending to the host, stamped with the current time
+    pub fn input(event: crate::input::InputEvent, modifiers: crate::input::Modifiers) -> Self {
+        use crate::input::InputEvent;
+
+        let timestamp = SystemTime::now();
+        match event {
+            InputEvent::Key { key, pressed, text } => Message::KeyEvent { key, pressed, text, modifiers, timestamp },
+            InputEvent::Text(text) => Message::TextInput { text, timestamp },
+            InputEvent::MouseMove { x, y } => Message::MouseMove { x, y, modifiers, timestamp },
+            InputEvent::MouseMotion { dx, dy } => Message::MouseMotion { dx, dy, modifiers, timestamp },
+            InputEvent::MouseButton { button, pressed } => {
+                Message::MouseButton { button, pressed, modifiers, timestamp }
+            }
+            InputEvent::Scroll { dx, dy } => Message::Scroll { dx, dy, modifiers, timestamp },
+            // Modifiers don't apply to individual fingers
+            InputEvent::Touch { id, phase, x, y } => Message::Touch { id, phase, x, y, timestamp },
+            InputEvent::Gesture(gesture) => Message::Gesture { gesture, modifiers, timestamp },
+        }
+    }
+
+    /// The input event carried by an input message
+    pub fn input_event(&self) -> Option<crate::input::InputEvent> {
+        use crate::input::InputEvent;
+
+        Some(match *self {
+            Message::KeyEvent { key, pressed, text, .. } => InputEvent::Key { key, pressed, text },
+            Message::TextInput { text, .. } => InputEvent::Text(text),
+            Message::MouseMove { x, y, .. } => InputEvent::MouseMove { x, y },
+            Message::MouseMotion { dx, dy, .. } => InputEvent::MouseMotion { dx, dy },
+            Message::MouseButton { button, pressed, .. } => InputEvent::MouseButton { button, pressed },
+            Message::Scroll { dx, dy, .. } => InputEvent::Scroll { dx, dy },
+            Message::Touch { id, phase, x, y, .. } => InputEvent::Touch { id, phase, x, y },
+            Message::Gesture { gesture, .. } => InputEvent::Gesture(gesture),
+            _ => return None,
+        })
+    }
+
+    /// QUIC stream priority for sending this message. Input goes first so
+    /// it never waits behind frame data or deltas, then audio, which
+    /// stutters audibly where a late frame would go unnoticed.
+    pub fn priority(&self) -> i32 {
+        match self {
+            _ if self.input_event().is_some() => INPUT_PRIORITY,
+            Message::MouseMode(_) => INPUT_PRIORITY,
+            Message::AudioStreamStart { .. }
+            | Message::AudioPacket { .. }
+            | Message::AudioMute { .. }
+            | Message::AudioGain { .. } => AUDIO_PRIORITY,
+            _ => 0,
+        }
+    }
+}
+
+impl From<pcc_core::CopyRect> for Message {
+    fn from(copy: pcc_core::CopyRect) -> Self {
+        Message::CopyRect {
+            src_x: copy.src_x,
+            src_y: copy.src_y,
+            dst_rect: copy.dst_rect,
+        }
+    }
+}
+
+/// One `FrameData` message from `FrameProtocol::encode_frame`: the message
+/// up to its pixels, then the pixels, a slice of the frame's own. Written
+/// one after the other they are the serialized message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameChunk {
+    pub header: Bytes,
+    pub payload: Bytes,
+}
+
+impl FrameChunk {
+    /// Bytes the message takes on the wire
+    pub fn len(&self) -> usize {
+        self.header.len() + self.payload.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The whole message in one buffer, copying the pixels
+    pub fn to_vec(&self) -> Vec<u8> {
+        [&self.header[..], &self.payload[..]].concat()
+    }
+}
+
+// Frame-specific protocol handling
+pub struct FrameProtocol;
+
+impl FrameProtocol {
+    // Encode a frame for transmission, without copying its pixels
+    pub fn encode_frame(frame: &pcc_core::Frame) -> Result<Vec<FrameChunk>> {
+        frame.expect_rgb24()?;
+        if frame.data.len() > MAX_FRAME_SIZE {
+            anyhow::bail!("Frame too large: {} bytes", frame.data.len());
+        }
+
+        // Split large frames into chunks that each fit in one message
+        let chunk_count = frame.data.len().div_ceil(FRAME_CHUNK_SIZE).max(1) as u32;
+        let mut chunks = Vec::with_capacity(chunk_count as usize);
+
+        for chunk_index in 0..chunk_count {
+            let start = chunk_index as usize * FRAME_CHUNK_SIZE;
+            let end = (start + FRAME_CHUNK_SIZE).min(frame.data.len());
+            let payload = frame.data.slice(start..end);
+
+            // The message with no pixels ends in their length, zero, which
+            // becomes the payload's; the payload itself follows unserialized
+            let mut body = bincode::serialize(&Message::FrameData {
+                frame_id: frame.id,
+                timestamp: frame.timestamp,
+                width: frame.width,
+                height: frame.height,
+                chunk_index,
+                chunk_count,
+                data: Bytes::new(),
+            })?;
+            let len_at = body.len() - std::mem::size_of::<u64>();
+            body[len_at..].copy_from_slice(&(payload.len() as u64).to_le_bytes());
+
+            let mut header = BytesMut::with_capacity(HEADER_SIZE + body.len());
+            header.extend_from_slice(&wire::write_header(body.len() + payload.len())?);
+            header.extend_from_slice(&body);
+            chunks.push(FrameChunk { header: header.freeze(), payload });
+        }
+
+        Ok(chunks)
+    }
+
+    // Encode a delta update in as few messages as it fits, each holding as
+    // many changed regions as it can. A region too big for a message of its
+    // own fails, as it would in any message.
+    pub fn encode_update(update: &pcc_core::FrameUpdate) -> Result<Vec<Vec<u8>>> {
+        let message = |changes: &[pcc_core::PixelChange], part: u32, parts: u32| Message::FrameUpdate {
+            update: pcc_core::FrameUpdate {
+                frame_id: update.frame_id,
+                timestamp: update.timestamp,
+                changes: changes.to_vec(),
+            },
+            part,
+            parts,
+        };
+
+        // Room left for changes once the header and update fields are in
+        let room = MAX_MESSAGE_SIZE.saturating_sub(bincode::serialized_size(&message(&[], 0, 0))? as usize);
+        // Where each message's changes start
+        let mut starts = vec![0];
+        // Bytes of changes in the last message; only an empty one has none
+        let mut used = 0;
+        for (i, change) in update.changes.iter().enumerate() {
+            let size = CHANGE_OVERHEAD + change.data.len();
+            if used > 0 && used + size > room {
+                starts.push(i);
+                used = 0;
+            }
+            used += size;
+        }
+
+        let parts = starts.len() as u32;
+        let ends = starts.iter().skip(1).copied().chain([update.changes.len()]);
+        starts
+            .iter()
+            .zip(ends)
+            .enumerate()
+            .map(|(part, (start, end))| message(&update.changes[*start..end], part as u32, parts).serialize())
+            .collect()
+    }
+
+    // Decode a frame from all of its FrameData messages, in any order
+    pub fn decode_frame(messages: Vec<Message>) -> Result<pcc_core::Frame> {
+        let mut assembler = FrameAssembler::new();
+        let mut frame = None;
+
+        for message in messages {
+            if let Some(complete) = assembler.push(message)? {
+                frame = Some(complete);
+            }
+        }
+
+        frame.ok_or_else(|| anyhow::anyhow!("Incomplete frame data"))
+    }
+}
+
+#[derive(Debug)]
+struct PartialFrame {
+    timestamp: SystemTime,
+    width: u32,
+    height: u32,
+    chunk_count: u32,
+    chunks: BTreeMap<u32, Bytes>,
+}
+
+/// Reassembles frames from `FrameData` chunks that may arrive out of order,
+/// interleaved with other frames, or not at all
+#[derive(Debug, Default)]
+pub struct FrameAssembler {
+    pending: HashMap<u64, PartialFrame>,
+}
+
+impl FrameAssembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a received message. Returns the frame once all its chunks are in.
+    /// Non-frame messages are ignored.
+    pub fn push(&mut self, message: Message) -> Result<Option<pcc_core::Frame>> {
+        let Message::FrameData {
+            frame_id,
+            timestamp,
+            width,
+            height,
+            chunk_index,
+            chunk_count,
+            data,
+        } = message
+        else {
+            return Ok(None);
+        };
+
+        if chunk_index >= chunk_count || chunk_count as usize > MAX_FRAME_SIZE.div_ceil(FRAME_CHUNK_SIZE) {
+            anyhow::bail!("Invalid chunk {}/{} for frame {}", chunk_index, chunk_count, frame_id);
+        }
+
+        // Give up on the oldest incomplete frame if too many are in flight
+        if !self.pending.contains_key(&frame_id) && self.pending.len() >= MAX_PENDING_FRAMES {
+            if let Some(oldest) = self.pending.keys().min().copied() {
+                self.pending.remove(&oldest);
+            }
+        }
+
+        let partial = self.pending.entry(frame_id).or_insert_with(|| PartialFrame {
+            timestamp,
+            width,
+            height,
+            chunk_count,
+            chunks: BTreeMap::new(),
+        });
+
+        if partial.chunk_count != chunk_count {
+            anyhow::bail!("Chunk count changed mid-frame for frame {}", frame_id);
+        }
+        partial.chunks.insert(chunk_index, data);
+
+        if partial.chunks.len() < partial.chunk_count as usize {
+            return Ok(None);
+        }
+
+        let partial = self.pending.remove(&frame_id).expect("Frame is pending");
+        let data = match partial.chunk_count {
+            // Nothing to join
+            1 => partial.chunks.into_values().next().unwrap_or_default(),
+            _ => {
+                let mut data = BytesMut::with_capacity(partial.chunks.values().map(Bytes::len).sum());
+                for chunk in partial.chunks.into_values() {
+                    data.extend_from_slice(&chunk);
+                }
+                data.freeze()
+            }
+        };
+
+        Ok(Some(pcc_core::Frame {
+            id: frame_id,
+            timestamp: partial.timestamp,
+            width: partial.width,
+            height: partial.height,
+            format: pcc_core::PixelFormat::Rgb24,
+            data,
+        }))
+    }
+
+    /// Number of frames still waiting for chunks
+    pub fn pending_frames(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pcc_core::{ColorSpace, FrameUpdate, Rect};
+    use pcc_core::wire::{tag, FrameMessage};
+
+    // The frame messages as both enums have them, with the tag pcc-core
+    // reads and writes each under
+    fn frame_messages() -> Vec<(u32, Message, FrameMessage)> {
+        let update = FrameUpdate { frame_id: 3, timestamp: SystemTime::UNIX_EPOCH, changes: Vec::new() };
+        let dst_rect = Rect::new(0, 2, 8, 4);
+        vec![
+            (
+                tag::FRAME_UPDATE,
+                Message::FrameUpdate { update: update.clone(), part: 0, parts: 1 },
+                FrameMessage::Update { update, part: 0, parts: 1 },
+            ),
+            (
+                tag::COPY_RECT,
+                Message::CopyRect { src_x: 0, src_y: 5, dst_rect },
+                FrameMessage::CopyRect { src_x: 0, src_y: 5, dst_rect },
+            ),
+            (tag::KEEP_ALIVE, Message::KeepAlive, FrameMessage::KeepAlive),
+            (tag::REQUEST_KEYFRAME, Message::RequestKeyframe, FrameMessage::RequestKeyframe),
+            (
+                tag::COLOR_SPACE,
+                Message::ColorSpace(ColorSpace::DisplayP3),
+                FrameMessage::ColorSpace(ColorSpace::DisplayP3),
+            ),
+        ]
+    }
+
+    #[test]
+    fn test_frame_message_tags_follow_message() -> Result<()> {
+        for (tag, message, frame_message) in frame_messages() {
+            let bytes = message.serialize()?;
+            assert_eq!(wire::read_message(&bytes)?[..4], tag.to_le_bytes(), "{:?} moved in Message", message);
+            assert_eq!(FrameMessage::parse(&bytes)?, frame_message);
+            assert_eq!(Message::deserialize(&frame_message.serialize()?)?, message);
+        }
+        Ok(())
+    }
+}
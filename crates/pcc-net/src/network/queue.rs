@@ -0,0 +1,270 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+/// What a full queue does with another item
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Overflow {
+    /// Wait for room, slowing whoever sends down to the reader's pace
+    #[default]
+    Block,
+    /// Make room by dropping the item that has waited longest
+    DropOldest,
+    /// Drop the new item
+    DropNewest,
+}
+
+/// How many items one of the network's queues holds, and what happens
+/// once it's full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QueueConfig {
+    pub capacity: usize,
+    pub overflow: Overflow,
+}
+
+impl QueueConfig {
+    pub fn new(capacity: usize, overflow: Overflow) -> Self {
+        Self { capacity, overflow }
+    }
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        Self::new(32, Overflow::Block)
+    }
+}
+
+/// The queues between connections and whoever reads what they receive.
+/// Every item waiting in one is latency, so live viewing may prefer short
+/// queues that drop the oldest frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkQueues {
+    /// Keyframes received, or queued to send
+    pub frames: QueueConfig,
+    /// Control messages received: delta updates, cursor, app data
+    pub messages: QueueConfig,
+    /// Connects and disconnects. Never blocks, since nothing waits to
+    /// report them; a full `Block` queue drops the new event.
+    pub events: QueueConfig,
+}
+
+impl Default for NetworkQueues {
+    fn default() -> Self {
+        Self {
+            frames: QueueConfig::default(),
+            messages: QueueConfig::default(),
+            events: QueueConfig::new(32, Overflow::DropNewest),
+        }
+    }
+}
+
+/// What a queue holds now and has dropped so far
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QueueStats {
+    pub queued: usize,
+    pub dropped: u64,
+}
+
+/// `QueueStats` for each of the `NetworkQueues`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NetworkQueueStats {
+    pub frames: QueueStats,
+    pub messages: QueueStats,
+    pub events: QueueStats,
+}
+
+struct Shared<T> {
+    state: Mutex<State<T>>,
+    config: QueueConfig,
+    dropped: AtomicU64,
+    // Woken when an item is pushed or the last sender goes
+    items: Notify,
+    // Woken when an item is taken or the receiver goes
+    space: Notify,
+}
+
+struct State<T> {
+    items: VecDeque<T>,
+    senders: usize,
+    closed: bool,
+}
+
+/// A bounded queue with the overflow behaviour of `config`. Like tokio's
+/// mpsc channel, but able to drop items rather than wait, and counting what
+/// it drops.
+pub fn queue<T>(config: QueueConfig) -> (QueueSender<T>, QueueReceiver<T>) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State { items: VecDeque::new(), senders: 1, closed: false }),
+        config: QueueConfig { capacity: config.capacity.max(1), ..config },
+        dropped: AtomicU64::new(0),
+        items: Notify::new(),
+        space: Notify::new(),
+    });
+    (QueueSender { shared: shared.clone() }, QueueReceiver { shared })
+}
+
+/// Sends into a `queue`. Clone it for more senders.
+pub struct QueueSender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// The queue's receiver has gone, so the item wasn't sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueClosed;
+
+impl std::fmt::Display for QueueClosed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("queue closed")
+    }
+}
+
+impl std::error::Error for QueueClosed {}
+
+// What became of an item offered to the queue
+enum Push<T> {
+    Sent,
+    Full(T),
+    Closed,
+}
+
+impl<T> Shared<T> {
+    fn push(&self, state: &mut State<T>, item: T) -> Push<T> {
+        if state.closed {
+            return Push::Closed;
+        }
+        if state.items.len() >= self.config.capacity {
+            match self.config.overflow {
+                Overflow::Block => return Push::Full(item),
+                Overflow::DropNewest => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    return Push::Sent;
+                }
+                Overflow::DropOldest => {
+                    state.items.pop_front();
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+        state.items.push_back(item);
+        self.items.notify_one();
+        Push::Sent
+    }
+
+    fn stats(&self) -> QueueStats {
+        QueueStats {
+            queued: self.state.lock().unwrap().items.len(),
+            dropped: self.dropped.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl<T> QueueSender<T> {
+    /// Queue `item`, waiting for room if the queue blocks when full. An
+    /// item dropped by the overflow policy still counts as sent.
+    pub async fn send(&self, mut item: T) -> Result<(), QueueClosed> {
+        loop {
+            let space = self.shared.space.notified();
+            tokio::pin!(space);
+            {
+                let mut state = self.shared.state.lock().unwrap();
+                // Wake on room made once the lock is let go
+                space.as_mut().enable();
+                item = match self.shared.push(&mut state, item) {
+                    Push::Sent => return Ok(()),
+                    Push::Closed => return Err(QueueClosed),
+                    Push::Full(item) => item,
+                };
+            }
+            space.await;
+        }
+    }
+
+    /// Queue `item` without waiting. A full queue that blocks drops it
+    /// instead, counting it with the rest.
+    pub fn try_send(&self, item: T) -> Result<(), QueueClosed> {
+        let mut state = self.shared.state.lock().unwrap();
+        match self.shared.push(&mut state, item) {
+            Push::Sent => Ok(()),
+            Push::Full(_) => {
+                self.shared.dropped.fetch_add(1, Ordering::Relaxed);
+                Ok(())
+            }
+            Push::Closed => Err(QueueClosed),
+        }
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.shared.state.lock().unwrap().closed
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        self.shared.stats()
+    }
+}
+
+impl<T> Clone for QueueSender<T> {
+    fn clone(&self) -> Self {
+        self.shared.state.lock().unwrap().senders += 1;
+        Self { shared: self.shared.clone() }
+    }
+}
+
+impl<T> Drop for QueueSender<T> {
+    fn drop(&mut self) {
+        let mut state = self.shared.state.lock().unwrap();
+        state.senders -= 1;
+        if state.senders == 0 {
+            self.shared.items.notify_one();
+        }
+    }
+}
+
+/// Receives from a `queue`
+pub struct QueueReceiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> QueueReceiver<T> {
+    /// The next item, or None once the queue is empty and every sender has
+    /// gone or it was closed
+    pub async fn recv(&mut self) -> Option<T> {
+        loop {
+            let items = self.shared.items.notified();
+            tokio::pin!(items);
+            {
+                let mut state = self.shared.state.lock().unwrap();
+                items.as_mut().enable();
+                if let Some(item) = state.items.pop_front() {
+                    self.shared.space.notify_one();
+                    return Some(item);
+                }
+                if state.closed || state.senders == 0 {
+                    return None;
+                }
+            }
+            items.await;
+        }
+    }
+
+    /// Turn away further items, waking senders waiting for room. Items
+    /// already queued can still be received.
+    pub fn close(&mut self) {
+        self.shared.state.lock().unwrap().closed = true;
+        self.shared.space.notify_waiters();
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        self.shared.stats()
+    }
+}
+
+impl<T> Drop for QueueReceiver<T> {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
@@ -1,15 +1,15 @@
 use anyhow::Result;
-use std::{sync::Arc, time::Duration};
-use tokio::{sync::Mutex, time};
+use std::{future::Future, sync::Arc, time::Duration};
+use tokio::{sync::Mutex, task::JoinHandle, time};
 use tracing::{error, warn};
 use serde::{Deserialize, Serialize};
 
 // Retry configuration
 const MAX_RETRIES: u32 = 3;
 const BASE_BACKOFF: Duration = Duration::from_millis(100);
-const MAX_BACKOFF: Duration = Duration::from_secs(5);
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ResilienceConfig {
     pub max_retries: u32,
     pub retry_delay: Duration,
@@ -20,8 +20,8 @@ pub struct ResilienceConfig {
 impl Default for ResilienceConfig {
     fn default() -> Self {
         Self {
-            max_retries: 3,
-            retry_delay: Duration::from_millis(100),
+            max_retries: MAX_RETRIES,
+            retry_delay: BASE_BACKOFF,
             jitter_buffer_size: 5,
             error_correction_enabled: true,
         }
@@ -73,8 +73,10 @@ impl NetworkResilience {
         }
     }
 
-    // Monitor connection health
-    pub async fn monitor_connection<F>(&self, health_check: F) -> Result<()>
+    // Monitor connection health every second until the connection is
+    // considered failed or `shutdown` resolves. Await the returned task to
+    // wait for the monitor to stop.
+    pub fn monitor_connection<F>(&self, health_check: F, shutdown: impl Future<Output = ()> + Send + 'static) -> JoinHandle<()>
     where
         F: Fn() -> Result<bool> + Send + Sync + 'static,
     {
@@ -84,8 +86,12 @@ impl NetworkResilience {
 
         tokio::spawn(async move {
             let mut interval = time::interval(Duration::from_secs(1));
+            tokio::pin!(shutdown);
             loop {
-                interval.tick().await;
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = &mut shutdown => break,
+                }
 
                 match health_check() {
                     Ok(true) => {
@@ -108,9 +114,7 @@ impl NetworkResilience {
                     break;
                 }
             }
-        });
-
-        Ok(())
+        })
     }
 
     // Record successful operation
@@ -0,0 +1,135 @@
+use crate::input::Permission;
+use anyhow::Result;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::Mutex;
+
+const RESUME_TOKEN_LEN: usize = 16;
+
+/// Opaque token issued at session start and presented on reconnect
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ResumeToken([u8; RESUME_TOKEN_LEN]);
+
+impl ResumeToken {
+    /// Generate a new random token
+    pub fn generate() -> Result<Self> {
+        let mut bytes = [0u8; RESUME_TOKEN_LEN];
+        SystemRandom::new()
+            .fill(&mut bytes)
+            .map_err(|_| anyhow::anyhow!("Failed to generate resume token"))?;
+        Ok(Self(bytes))
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// The host's side of resumption: the token of the session it last opened
+/// with the viewer. Clones share it, so each reconnect of a supervised host
+/// presents the token the one before was issued.
+#[derive(Debug, Clone, Default)]
+pub struct SessionResume(Arc<std::sync::Mutex<Option<ResumeToken>>>);
+
+impl SessionResume {
+    /// Token to present on the next handshake, if there's a session to resume
+    pub fn token(&self) -> Option<ResumeToken> {
+        *self.0.lock().unwrap()
+    }
+
+    /// Present `session`'s token on the next reconnect
+    pub fn keep(&self, session: &SessionInfo) {
+        *self.0.lock().unwrap() = Some(session.token);
+    }
+
+    /// Start afresh on the next reconnect, e.g. once the host stopped sharing
+    pub fn clear(&self) {
+        *self.0.lock().unwrap() = None;
+    }
+}
+
+/// Outcome of the session handshake, as seen by the host
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionInfo {
+    /// Token to present when reconnecting
+    pub token: ResumeToken,
+    /// Last frame the viewer acknowledged before the reconnect. `None` means
+    /// a new session, and the host should start with a keyframe.
+    pub resume_from: Option<u64>,
+    /// What the viewer may do, as negotiated in the handshake
+    pub permission: Permission,
+}
+
+#[derive(Debug)]
+struct SessionRecord {
+    last_acked_frame: Option<u64>,
+    last_seen: Instant,
+}
+
+/// Server-side record of issued resume tokens and how far each session got
+#[derive(Debug, Clone)]
+pub struct SessionRegistry {
+    sessions: Arc<Mutex<HashMap<ResumeToken, SessionRecord>>>,
+    resume_window: Duration,
+}
+
+impl SessionRegistry {
+    pub fn new(resume_window: Duration) -> Self {
+        Self {
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+            resume_window,
+        }
+    }
+
+    /// Start a session for a connecting client, resuming the session behind
+    /// `presented` if it is known and has not expired
+    pub async fn open(&self, presented: Option<ResumeToken>) -> Result<SessionInfo> {
+        let mut sessions = self.sessions.lock().await;
+        let window = self.resume_window;
+        sessions.retain(|_, record| record.last_seen.elapsed() <= window);
+
+        if let Some(token) = presented {
+            if let Some(record) = sessions.get_mut(&token) {
+                record.last_seen = Instant::now();
+                return Ok(SessionInfo {
+                    token,
+                    resume_from: record.last_acked_frame,
+                    permission: Permission::default(),
+                });
+            }
+        }
+
+        let token = ResumeToken::generate()?;
+        sessions.insert(
+            token,
+            SessionRecord {
+                last_acked_frame: None,
+                last_seen: Instant::now(),
+            },
+        );
+
+        Ok(SessionInfo {
+            token,
+            resume_from: None,
+            permission: Permission::default(),
+        })
+    }
+
+    /// Record that the viewer has received `frame_id`
+    pub async fn acknowledge(&self, token: &ResumeToken, frame_id: u64) {
+        if let Some(record) = self.sessions.lock().await.get_mut(token) {
+            record.last_acked_frame = Some(record.last_acked_frame.map_or(frame_id, |id| id.max(frame_id)));
+            record.last_seen = Instant::now();
+        }
+    }
+
+    /// Forget a session so its token can no longer be resumed
+    pub async fn remove(&self, token: &ResumeToken) {
+        self.sessions.lock().await.remove(token);
+    }
+}
@@ -0,0 +1,72 @@
+use super::protocol::Message;
+use std::{collections::HashMap, sync::Arc};
+use tokio::sync::{mpsc, Mutex};
+use tracing::warn;
+
+// Buffered payloads per subscriber before new ones are dropped
+const SUBSCRIBER_CAPACITY: usize = 32;
+
+type Subscribers = HashMap<String, Vec<mpsc::Sender<Vec<u8>>>>;
+
+/// Routes `Message::AppData` payloads to per-topic subscribers, letting
+/// applications exchange their own control data alongside the stream
+#[derive(Debug, Clone, Default)]
+pub struct SideChannel {
+    subscribers: Arc<Mutex<Subscribers>>,
+}
+
+impl SideChannel {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Subscribe to payloads published on `topic`
+    pub async fn subscribe(&self, topic: impl Into<String>) -> mpsc::Receiver<Vec<u8>> {
+        let (tx, rx) = mpsc::channel(SUBSCRIBER_CAPACITY);
+        self.subscribers
+            .lock()
+            .await
+            .entry(topic.into())
+            .or_default()
+            .push(tx);
+        rx
+    }
+
+    /// Build the message carrying `payload` on `topic` for sending to the peer
+    pub fn message(topic: impl Into<String>, payload: Vec<u8>) -> Message {
+        Message::AppData {
+            topic: topic.into(),
+            payload,
+        }
+    }
+
+    /// Deliver a received `AppData` message to its topic's subscribers.
+    /// Returns the number of subscribers that received the payload.
+    pub async fn dispatch(&self, message: &Message) -> usize {
+        let Message::AppData { topic, payload } = message else {
+            return 0;
+        };
+
+        let mut subscribers = self.subscribers.lock().await;
+        let Some(senders) = subscribers.get_mut(topic) else {
+            return 0;
+        };
+
+        // Forget subscribers that have dropped their receiver
+        senders.retain(|tx| !tx.is_closed());
+
+        let mut delivered = 0;
+        for tx in senders.iter() {
+            match tx.try_send(payload.clone()) {
+                Ok(()) => delivered += 1,
+                Err(_) => warn!("Side channel subscriber for '{}' is full, dropping payload", topic),
+            }
+        }
+
+        if senders.is_empty() {
+            subscribers.remove(topic);
+        }
+
+        delivered
+    }
+}
@@ -1,20 +1,31 @@
-use crate::network::NetworkConfig;
-use crate::pcc::types::Frame;
+use crate::network::{queue, CloseReason, Message, NetworkConfig, QueueReceiver, QueueSender};
+use pcc_core::types::Frame;
 use anyhow::{Context, Result};
 use quinn::{Endpoint, Connection};
-use tokio::sync::mpsc;
+
+/// A bidirectional frame transport between host and viewer
+#[async_trait::async_trait]
+pub trait Transport: Send {
+    /// Send a frame to the peer
+    async fn send_frame(&mut self, frame: &Frame) -> Result<()>;
+
+    /// Receive the next frame from the peer
+    async fn receive_frame(&mut self) -> Result<Frame>;
+}
 
 pub struct QUICTransport {
     endpoint: Endpoint,
     config: NetworkConfig,
     connection: Option<Connection>,
-    frame_tx: mpsc::Sender<Frame>,
-    frame_rx: mpsc::Receiver<Frame>,
+    #[allow(dead_code)] // Reserved for the queued send path
+    frame_tx: QueueSender<Frame>,
+    #[allow(dead_code)]
+    frame_rx: QueueReceiver<Frame>,
 }
 
 impl QUICTransport {
     pub fn new(endpoint: Endpoint, config: NetworkConfig) -> Self {
-        let (frame_tx, frame_rx) = mpsc::channel(32); // Buffer size for frame queue
+        let (frame_tx, frame_rx) = queue(config.queues.frames);
         Self {
             endpoint,
             config,
@@ -47,9 +58,26 @@ impl QUICTransport {
         }
     }
 
+    /// Send `Goodbye` and close the connection with `reason`
+    pub async fn close(&mut self, reason: CloseReason) -> Result<()> {
+        if let Some(conn) = self.connection.take() {
+            if let Ok(mut send) = conn.open_uni().await {
+                let goodbye = Message::Goodbye { reason }.serialize()?;
+                if send.write_all(&goodbye).await.is_ok() {
+                    let _ = send.finish().await;
+                }
+            }
+            conn.close(reason.code(), reason.to_string().as_bytes());
+        }
+        Ok(())
+    }
+
     pub async fn receive_frame(&mut self) -> Result<Frame> {
         if let Some(conn) = &mut self.connection {
-            let (_, mut recv) = conn.accept_bi().await?;
+            let (_, mut recv) = match conn.accept_bi().await {
+                Ok(streams) => streams,
+                Err(e) => anyhow::bail!("Connection closed: {}", CloseReason::from_error(&e)),
+            };
             let mut buf = vec![0u8; self.config.max_packet_size];
             
             let n = recv.read(&mut buf)
@@ -67,4 +95,15 @@ impl QUICTransport {
             Err(anyhow::anyhow!("Not connected"))
         }
     }
-} 
\ No newline at end of file
+}
+
+#[async_trait::async_trait]
+impl Transport for QUICTransport {
+    async fn send_frame(&mut self, frame: &Frame) -> Result<()> {
+        QUICTransport::send_frame(self, frame).await
+    }
+
+    async fn receive_frame(&mut self) -> Result<Frame> {
+        QUICTransport::receive_frame(self).await
+    }
+}
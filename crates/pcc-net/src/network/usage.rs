@@ -0,0 +1,118 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Span the windowed rate is averaged over
+pub const USAGE_WINDOW: Duration = Duration::from_secs(60);
+
+/// What a session does once it has used up its data quota
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum QuotaAction {
+    /// End the session
+    #[default]
+    Stop,
+    /// Keep sharing at a trickle, with the frame rate and detail that allows
+    Degrade,
+}
+
+/// Data a session has used, both directions and headers included
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DataUsage {
+    pub sent_bytes: u64,
+    pub received_bytes: u64,
+    /// Bits per second over the last `USAGE_WINDOW`
+    pub window_bitrate: u64,
+}
+
+impl DataUsage {
+    pub fn total_bytes(&self) -> u64 {
+        self.sent_bytes + self.received_bytes
+    }
+}
+
+/// Accounts for a session's data from periodic samples of its connection's
+/// running totals, against an optional quota
+#[derive(Debug)]
+pub struct UsageMeter {
+    quota: Option<u64>,
+    // Combined totals, back to just before the window
+    samples: VecDeque<(Instant, u64)>,
+    usage: DataUsage,
+}
+
+impl UsageMeter {
+    /// Account against a quota of `quota` bytes, if any
+    pub fn new(quota: Option<u64>) -> Self {
+        Self {
+            quota,
+            samples: VecDeque::new(),
+            usage: DataUsage::default(),
+        }
+    }
+
+    /// Take in the totals sent and received as of `at`
+    pub fn record(&mut self, at: Instant, sent_bytes: u64, received_bytes: u64) -> DataUsage {
+        let total = sent_bytes + received_bytes;
+        self.samples.push_back((at, total));
+        // Keep the last sample from before the window to measure from
+        while self.samples.len() > 2 && at.duration_since(self.samples[1].0) >= USAGE_WINDOW {
+            self.samples.pop_front();
+        }
+
+        let (since, from) = self.samples[0];
+        let elapsed = at.duration_since(since).as_secs_f64();
+        self.usage = DataUsage {
+            sent_bytes,
+            received_bytes,
+            window_bitrate: if elapsed > 0.0 { ((total - from) as f64 * 8.0 / elapsed) as u64 } else { 0 },
+        };
+        self.usage
+    }
+
+    pub fn usage(&self) -> DataUsage {
+        self.usage
+    }
+
+    /// Bytes left of the quota, or None without one
+    pub fn remaining(&self) -> Option<u64> {
+        self.quota.map(|quota| quota.saturating_sub(self.usage.total_bytes()))
+    }
+
+    /// Whether the quota has been used up
+    pub fn exhausted(&self) -> bool {
+        self.remaining() == Some(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_rate_forgets_old_traffic() {
+        let start = Instant::now();
+        let mut meter = UsageMeter::new(None);
+        meter.record(start, 0, 0);
+        // A burst of 6 MB, then a minute of 1 kB/s
+        meter.record(start + Duration::from_secs(1), 6_000_000, 0);
+        for secs in 2..=70 {
+            meter.record(start + Duration::from_secs(secs), 6_000_000 + (secs - 1) * 1000, 0);
+        }
+        let usage = meter.usage();
+        assert_eq!(usage.total_bytes(), 6_069_000);
+        assert_eq!(usage.window_bitrate, 8000);
+        assert_eq!(meter.remaining(), None);
+    }
+
+    #[test]
+    fn test_quota_counts_both_directions() {
+        let start = Instant::now();
+        let mut meter = UsageMeter::new(Some(1_000_000));
+        meter.record(start, 600_000, 300_000);
+        assert_eq!(meter.remaining(), Some(100_000));
+        assert!(!meter.exhausted());
+        meter.record(start + Duration::from_secs(1), 700_000, 350_000);
+        assert!(meter.exhausted());
+    }
+}
@@ -0,0 +1,108 @@
+use crate::network::CloseReason;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::warn;
+
+/// Something that happened in a session, as written to the event log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum SessionEvent {
+    /// A session started; `resumed` when a host picked up where it left off
+    Connect { peer: SocketAddr, resumed: bool },
+    Disconnect { peer: SocketAddr, reason: CloseReason },
+    /// The host is trying the viewer again after a session failed or ended
+    Reconnect { peer: SocketAddr, attempt: u32 },
+    /// The host's encoding quality (1-100) or frame rate changed
+    QualityChange { quality: u32, target_fps: u32 },
+    /// A whole frame was sent or received
+    Keyframe { frame_id: u64 },
+    /// The host paused sharing, showing a placeholder, or resumed it
+    Pause { paused: bool },
+    /// The viewer started or stopped recording the session
+    Recording { recording: bool },
+    Error { message: String },
+}
+
+/// One line of the event log
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LoggedEvent {
+    /// Milliseconds since the Unix epoch
+    pub unix_ms: u64,
+    #[serde(flatten)]
+    pub event: SessionEvent,
+}
+
+/// Where session events go, one JSON object per line. Clones share the
+/// sink; the default logs nothing.
+#[derive(Clone, Default)]
+pub struct SessionLog {
+    sink: Option<Arc<Mutex<Box<dyn Write + Send>>>>,
+}
+
+impl fmt::Debug for SessionLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionLog").field("enabled", &self.sink.is_some()).finish()
+    }
+}
+
+impl SessionLog {
+    /// Append events to the file at `path`, or write them to stdout if it
+    /// is `-`
+    pub fn open(path: &Path) -> Result<Self> {
+        if path == Path::new("-") {
+            return Ok(Self::to_writer(std::io::stdout()));
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Failed to open event log {}", path.display()))?;
+        Ok(Self::to_writer(file))
+    }
+
+    pub fn to_writer(writer: impl Write + Send + 'static) -> Self {
+        Self {
+            sink: Some(Arc::new(Mutex::new(Box::new(writer)))),
+        }
+    }
+
+    /// Write `event`, stamped with the current time. Failures are logged,
+    /// as the session goes on regardless.
+    pub fn record(&self, event: SessionEvent) {
+        let Some(sink) = &self.sink else { return };
+        let unix_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_millis() as u64);
+        let written = serde_json::to_string(&LoggedEvent { unix_ms, event })
+            .map_err(anyhow::Error::from)
+            .and_then(|line| {
+                let mut sink = sink.lock().unwrap();
+                writeln!(sink, "{}", line)?;
+                Ok(sink.flush()?)
+            });
+        if let Err(e) = written {
+            warn!("Failed to write session event: {}", e);
+        }
+    }
+
+    /// Read back a log written by `open`, e.g. to replay a session
+    pub fn read(path: &Path) -> Result<Vec<LoggedEvent>> {
+        let file = std::fs::File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        BufReader::new(file)
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.as_ref().is_ok_and(|line| line.trim().is_empty()))
+            .map(|(number, line)| {
+                serde_json::from_str(&line?)
+                    .with_context(|| format!("Invalid event on line {} of {}", number + 1, path.display()))
+            })
+            .collect()
+    }
+}
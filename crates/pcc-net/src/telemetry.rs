@@ -0,0 +1,210 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime};
+
+/// Samples each stage's percentiles are taken over, about ten seconds of
+/// frames at 30 fps
+pub const WINDOW: usize = 300;
+// Frames the viewer holds on to while waiting for the other half of their
+// timings, as frames and their host timings travel on different streams
+const PENDING_FRAMES: usize = 64;
+
+/// A step every frame passes through on its way to the screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Stage {
+    /// Grabbing the screen on the host
+    Capture,
+    /// Diffing against the previous frame
+    Detect,
+    /// Coding changes or a keyframe for the wire
+    Encode,
+    /// From the host being done with a frame to the viewer receiving it
+    Network,
+    /// Drawing a received frame on the viewer
+    Render,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 5] = [Stage::Capture, Stage::Detect, Stage::Encode, Stage::Network, Stage::Render];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Capture => "capture",
+            Stage::Detect => "detect",
+            Stage::Encode => "encode",
+            Stage::Network => "network",
+            Stage::Render => "render",
+        }
+    }
+}
+
+/// How long the host spent on one frame, sent to the viewer after the frame
+/// so it can show the whole breakdown
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FrameTimings {
+    pub frame_id: u64,
+    pub capture: Duration,
+    pub detect: Duration,
+    pub encode: Duration,
+}
+
+/// The time 50, 95 and 99 percent of recent frames finished a stage within
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Percentiles {
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+}
+
+/// Percentiles for every stage. Stages with no samples yet, like render on
+/// the host, are all zero.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LatencyBreakdown {
+    pub capture: Percentiles,
+    pub detect: Percentiles,
+    pub encode: Percentiles,
+    pub network: Percentiles,
+    pub render: Percentiles,
+}
+
+impl LatencyBreakdown {
+    pub fn stage(&self, stage: Stage) -> Percentiles {
+        match stage {
+            Stage::Capture => self.capture,
+            Stage::Detect => self.detect,
+            Stage::Encode => self.encode,
+            Stage::Network => self.network,
+            Stage::Render => self.render,
+        }
+    }
+}
+
+/// Rolling per-stage latencies. The host records capture, detect and
+/// encode as it sends frames; the viewer records network and render, and
+/// the host's stages from the `FrameTimings` it sends along.
+#[derive(Debug, Clone, Default)]
+pub struct Telemetry {
+    samples: [VecDeque<Duration>; 5],
+    // Transit times of frames whose host timings haven't arrived yet, and
+    // host timings whose frames haven't
+    arrivals: VecDeque<(u64, Duration)>,
+    host_timings: VecDeque<FrameTimings>,
+}
+
+impl Telemetry {
+    /// Record one frame's time in `stage`, forgetting the oldest sample
+    /// once the window is full
+    pub fn record(&mut self, stage: Stage, elapsed: Duration) {
+        let samples = &mut self.samples[stage as usize];
+        if samples.len() == WINDOW {
+            samples.pop_front();
+        }
+        samples.push_back(elapsed);
+    }
+
+    /// Record the stages the host timed for a frame
+    pub fn record_host_timings(&mut self, timings: FrameTimings) {
+        self.record(Stage::Capture, timings.capture);
+        self.record(Stage::Detect, timings.detect);
+        self.record(Stage::Encode, timings.encode);
+        let arrival = self.arrivals.iter().position(|&(id, _)| id == timings.frame_id);
+        match arrival.and_then(|i| self.arrivals.remove(i)) {
+            Some((_, transit)) => self.record_transit(transit, &timings),
+            None => push_bounded(&mut self.host_timings, timings),
+        }
+    }
+
+    /// Record that frame `frame_id`, captured at `captured` by the host's
+    /// clock, just arrived. Its network time is known once its host timings
+    /// are too.
+    pub fn record_arrival(&mut self, frame_id: u64, captured: SystemTime) {
+        let transit = captured.elapsed().unwrap_or_default();
+        let timings = self.host_timings.iter().position(|timings| timings.frame_id == frame_id);
+        match timings.and_then(|i| self.host_timings.remove(i)) {
+            Some(timings) => self.record_transit(transit, &timings),
+            None => push_bounded(&mut self.arrivals, (frame_id, transit)),
+        }
+    }
+
+    // Frames are stamped as they're captured, so the time since then less
+    // the host's work on them is time on the network
+    fn record_transit(&mut self, transit: Duration, timings: &FrameTimings) {
+        self.record(Stage::Network, transit.saturating_sub(timings.detect + timings.encode));
+    }
+
+    pub fn percentiles(&self, stage: Stage) -> Percentiles {
+        let mut sorted: Vec<Duration> = self.samples[stage as usize].iter().copied().collect();
+        sorted.sort_unstable();
+        Percentiles {
+            p50: percentile(&sorted, 50.0),
+            p95: percentile(&sorted, 95.0),
+            p99: percentile(&sorted, 99.0),
+        }
+    }
+
+    pub fn breakdown(&self) -> LatencyBreakdown {
+        LatencyBreakdown {
+            capture: self.percentiles(Stage::Capture),
+            detect: self.percentiles(Stage::Detect),
+            encode: self.percentiles(Stage::Encode),
+            network: self.percentiles(Stage::Network),
+            render: self.percentiles(Stage::Render),
+        }
+    }
+}
+
+fn push_bounded<T>(queue: &mut VecDeque<T>, item: T) {
+    if queue.len() == PENDING_FRAMES {
+        queue.pop_front();
+    }
+    queue.push_back(item);
+}
+
+/// The sample `percent` of `sorted` samples are at or below, by nearest
+/// rank; zero without samples
+pub fn percentile(sorted: &[Duration], percent: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (percent / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_rolls() {
+        let mut telemetry = Telemetry::default();
+        for ms in 0..WINDOW as u64 + 100 {
+            telemetry.record(Stage::Detect, Duration::from_millis(ms));
+        }
+        let detect = telemetry.percentiles(Stage::Detect);
+        assert_eq!(detect.p50, Duration::from_millis(249));
+        assert_eq!(detect.p99, Duration::from_millis(396));
+        assert_eq!(telemetry.percentiles(Stage::Render), Percentiles::default());
+    }
+
+    #[test]
+    fn test_network_time_pairs_arrivals_with_host_timings() {
+        let timings = |frame_id| FrameTimings {
+            frame_id,
+            capture: Duration::from_millis(5),
+            detect: Duration::from_millis(10),
+            encode: Duration::from_millis(20),
+        };
+        let mut telemetry = Telemetry::default();
+
+        // Either half may arrive first
+        telemetry.record_arrival(1, SystemTime::now() - Duration::from_millis(100));
+        telemetry.record_host_timings(timings(1));
+        telemetry.record_host_timings(timings(2));
+        telemetry.record_arrival(2, SystemTime::now() - Duration::from_millis(100));
+
+        let network = telemetry.percentiles(Stage::Network);
+        assert!(network.p50 >= Duration::from_millis(70) && network.p99 < Duration::from_millis(90));
+        assert_eq!(telemetry.breakdown().encode.p95, Duration::from_millis(20));
+        assert!(telemetry.arrivals.is_empty() && telemetry.host_timings.is_empty());
+    }
+}
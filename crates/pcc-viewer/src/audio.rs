@@ -0,0 +1,579 @@
+pub use pcc_net::audio::AudioFormat;
+
+#[cfg(feature = "audio")]
+use pcc_net::audio::AudioDeviceKind;
+use pcc_net::audio::{clamp_gain, AudioDecoder, AudioStreamState, DeviceSelection, PACKET_DURATION};
+use crate::renderer::jitter::JitterEstimator;
+use crate::renderer::JitterConfig;
+use anyhow::Result;
+use std::{
+    collections::{BTreeMap, VecDeque},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+use tracing::debug;
+
+/// How far audio and video may drift apart either way before the stream
+/// that is ahead is held back
+pub const SYNC_TOLERANCE: Duration = Duration::from_millis(40);
+// Audio further behind than this skips ahead, rather than holding the
+// video back that long
+const MAX_VIDEO_HOLD: Duration = Duration::from_millis(150);
+// Drift smaller than this is inaudible and left alone
+const SOFT_SYNC_THRESHOLD: Duration = Duration::from_millis(10);
+// While easing, drop or repeat one sample frame in this many (~1% speed change)
+const CORRECTION_INTERVAL: u64 = 100;
+// Most audio held before the oldest is discarded
+const MAX_QUEUED: Duration = Duration::from_secs(2);
+
+#[derive(Debug)]
+struct PlaybackState {
+    format: AudioFormat,
+    /// Interleaved samples waiting to be played
+    queue: VecDeque<f32>,
+    /// Capture time of the first queued sample frame
+    head_pts: Option<SystemTime>,
+    /// Capture time of the last presented video frame, and when it was shown
+    video: Option<(SystemTime, Instant)>,
+    frames_played: u64,
+    corrections: u64,
+    /// Keep consuming audio in sync, but output silence
+    muted: bool,
+    gain: f32,
+}
+
+impl PlaybackState {
+    fn queued_frames(&self) -> usize {
+        self.queue.len() / self.format.channels as usize
+    }
+
+    fn drop_frames(&mut self, frames: usize) {
+        let frames = frames.min(self.queued_frames());
+        self.queue.drain(..frames * self.format.channels as usize);
+        self.advance(frames);
+    }
+
+    fn advance(&mut self, frames: usize) {
+        if let Some(pts) = self.head_pts.as_mut() {
+            *pts += self.format.frame_duration() * frames as u32;
+        }
+    }
+
+    // How far the audio playhead is ahead of the video (negative: behind)
+    fn drift(&self, now: Instant) -> Option<f64> {
+        let head = self.head_pts?;
+        let (video_pts, shown_at) = self.video?;
+        let video_now = video_pts + now.duration_since(shown_at);
+        Some(match head.duration_since(video_now) {
+            Ok(ahead) => ahead.as_secs_f64(),
+            Err(behind) => -behind.duration().as_secs_f64(),
+        })
+    }
+}
+
+/// Plays decoded audio in step with the presented video. Small drift is
+/// eased out by dropping or repeating the odd sample. Beyond
+/// `SYNC_TOLERANCE`, whichever stream is ahead waits: audio ahead plays
+/// silence, and video ahead is held by the renderer until `playhead`
+/// catches up. Audio far behind skips ahead instead.
+///
+/// The state is shared with the output device's realtime callback thread,
+/// so it sits behind a std mutex rather than an async one.
+#[derive(Debug, Clone)]
+pub struct AudioPlayback {
+    state: Arc<Mutex<PlaybackState>>,
+}
+
+impl AudioPlayback {
+    pub fn new(format: AudioFormat) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(PlaybackState {
+                format,
+                queue: VecDeque::new(),
+                head_pts: None,
+                video: None,
+                frames_played: 0,
+                corrections: 0,
+                muted: false,
+                gain: 1.0,
+            })),
+        }
+    }
+
+    pub fn format(&self) -> AudioFormat {
+        self.lock().format
+    }
+
+    /// Queue interleaved samples captured starting at `pts`
+    pub fn push(&self, pts: SystemTime, samples: &[f32]) {
+        let mut state = self.lock();
+        if state.queue.is_empty() {
+            state.head_pts = Some(pts);
+        }
+        state.queue.extend(samples);
+
+        let max_frames = (MAX_QUEUED.as_secs_f64() * state.format.sample_rate as f64) as usize;
+        let excess = state.queued_frames().saturating_sub(max_frames);
+        if excess > 0 {
+            state.drop_frames(excess);
+        }
+    }
+
+    /// Tell the playback which video frame just went on screen
+    pub fn video_presented(&self, pts: SystemTime) {
+        self.lock().video = Some((pts, Instant::now()));
+    }
+
+    /// Fill an output buffer of interleaved samples; called by the device
+    pub fn fill(&self, output: &mut [f32]) {
+        self.fill_at(output, Instant::now());
+    }
+
+    fn fill_at(&self, output: &mut [f32], now: Instant) {
+        let mut state = self.lock();
+        let channels = state.format.channels as usize;
+        let frame_duration = state.format.frame_duration().as_secs_f64();
+
+        let mut correction = 0i64;
+        if let Some(drift) = state.drift(now) {
+            if drift >= SYNC_TOLERANCE.as_secs_f64() {
+                // Ahead of the video: hold back with silence
+                output.fill(0.0);
+                return;
+            } else if -drift >= MAX_VIDEO_HOLD.as_secs_f64() {
+                // Too far behind for the video to wait: skip ahead
+                let frames = (-drift / frame_duration) as usize;
+                state.drop_frames(frames);
+                debug!("Audio resynchronized after {:.0}ms drift", drift * 1000.0);
+            } else if drift.abs() >= SOFT_SYNC_THRESHOLD.as_secs_f64() {
+                correction = if drift < 0.0 { 1 } else { -1 };
+            }
+        }
+
+        for frame in output.chunks_mut(channels) {
+            state.frames_played += 1;
+            let ease = correction != 0 && state.frames_played.is_multiple_of(CORRECTION_INTERVAL);
+
+            if ease && correction > 0 {
+                // Behind: drop a frame
+                state.drop_frames(1);
+                state.corrections += 1;
+            }
+
+            if state.queued_frames() == 0 {
+                frame.fill(0.0);
+                continue;
+            }
+
+            for (out, sample) in frame.iter_mut().zip(state.queue.iter()) {
+                *out = *sample;
+            }
+
+            if ease && correction < 0 {
+                // Ahead: play this frame again next time
+                state.corrections += 1;
+            } else {
+                state.drop_frames(1);
+            }
+        }
+
+        if state.muted {
+            output.fill(0.0);
+        } else if state.gain != 1.0 {
+            output.iter_mut().for_each(|sample| *sample *= state.gain);
+        }
+    }
+
+    /// Silence this playback without losing sync, so unmuting picks up
+    /// where the stream is now
+    pub fn set_muted(&self, muted: bool) {
+        self.lock().muted = muted;
+    }
+
+    pub fn is_muted(&self) -> bool {
+        self.lock().muted
+    }
+
+    /// Scale this playback's volume, from 0.0 to `MAX_AUDIO_GAIN`
+    pub fn set_gain(&self, gain: f32) {
+        self.lock().gain = clamp_gain(gain);
+    }
+
+    pub fn gain(&self) -> f32 {
+        self.lock().gain
+    }
+
+    /// Mute and gain as the session shares them
+    pub fn stream_state(&self) -> AudioStreamState {
+        let state = self.lock();
+        AudioStreamState {
+            muted: state.muted,
+            gain: state.gain,
+        }
+    }
+
+    /// Drop everything queued, e.g. to resynchronize after a long gap
+    pub fn clear(&self) {
+        let mut state = self.lock();
+        state.queue.clear();
+        state.head_pts = None;
+    }
+
+    /// Capture time of the audio about to play, if any is queued
+    pub fn playhead(&self) -> Option<SystemTime> {
+        let state = self.lock();
+        state.head_pts.filter(|_| !state.queue.is_empty())
+    }
+
+    /// Audio queued for playback
+    pub fn buffered(&self) -> Duration {
+        let state = self.lock();
+        state.format.frame_duration() * state.queued_frames() as u32
+    }
+
+    /// Current audio-minus-video offset in seconds, if both are playing
+    pub fn drift(&self) -> Option<f64> {
+        self.lock().drift(Instant::now())
+    }
+
+    /// Sample frames dropped or repeated to stay in sync
+    pub fn corrections(&self) -> u64 {
+        self.lock().corrections
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, PlaybackState> {
+        // A panic in the audio callback must not silence the session
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Start playing through the default output device. The returned stream
+    /// must be kept alive for as long as audio should play.
+    #[cfg(feature = "audio")]
+    pub fn start_output(&self) -> Result<cpal::Stream> {
+        self.start_output_on(&DeviceSelection::Default)
+    }
+
+    /// Start playing through the selected output device. To switch devices
+    /// mid-session, start the new one and drop the old stream; queued audio
+    /// and sync carry over.
+    #[cfg(feature = "audio")]
+    pub fn start_output_on(&self, device: &DeviceSelection) -> Result<cpal::Stream> {
+        use anyhow::Context;
+        use cpal::traits::{DeviceTrait, StreamTrait};
+
+        let (device, _) = pcc_net::audio::open_device(AudioDeviceKind::Output, device)?;
+        let name = device.name().unwrap_or_else(|_| "unknown device".to_string());
+        let format = self.format();
+        let config = cpal::StreamConfig {
+            channels: format.channels,
+            sample_rate: cpal::SampleRate(format.sample_rate),
+            buffer_size: cpal::BufferSize::Default,
+        };
+
+        let playback = self.clone();
+        let stream = device
+            .build_output_stream(
+                &config,
+                move |output: &mut [f32], _| playback.fill(output),
+                |e| tracing::warn!("Audio output error: {}", e),
+                None,
+            )
+            .with_context(|| format!("Failed to open {} for playback", name))?;
+        stream.play().context("Failed to start audio output")?;
+
+        tracing::info!(
+            "Audio output started on {}: {} Hz, {} channels",
+            name,
+            format.sample_rate,
+            format.channels
+        );
+        Ok(stream)
+    }
+
+    /// Audio output needs the `audio` feature
+    #[cfg(not(feature = "audio"))]
+    pub fn start_output(&self) -> Result<()> {
+        self.start_output_on(&DeviceSelection::Default)
+    }
+
+    /// Audio output needs the `audio` feature
+    #[cfg(not(feature = "audio"))]
+    pub fn start_output_on(&self, _device: &DeviceSelection) -> Result<()> {
+        anyhow::bail!("Built without audio output support (enable the `audio` feature)")
+    }
+}
+
+/// Default playout scheduling for received audio: enough delay to ride out
+/// typical jitter, growing with it up to a fifth of a second
+pub const AUDIO_JITTER: JitterConfig = JitterConfig {
+    target_latency: Duration::from_millis(40),
+    adaptive: true,
+    max_latency: Duration::from_millis(200),
+};
+
+// Lost packets filled in by concealment; longer gaps are filled with
+// silence, as extrapolating further just sounds wrong
+const MAX_CONCEALED_PACKETS: u64 = 5;
+// Beyond this many missing packets the stream is resynchronized instead
+const MAX_GAP_PACKETS: u64 = 50;
+
+#[derive(Debug)]
+struct PendingPacket {
+    pts: SystemTime,
+    due: Instant,
+    data: Vec<u8>,
+}
+
+/// Receive statistics for one audio stream
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AudioReceiveStats {
+    pub received: u64,
+    /// Packets that never arrived in time
+    pub lost: u64,
+    /// Lost packets filled in by the decoder's concealment
+    pub concealed: u64,
+    /// Packets that arrived after their slot was already played or concealed
+    pub late: u64,
+}
+
+/// Decodes one host audio stream into a playback through an adaptive
+/// jitter buffer. Packets are held for a playout delay that follows the
+/// measured jitter, so they can be played in order; packets still missing
+/// when their successor is due, or when the playback is about to run dry,
+/// are concealed by the decoder rather than left as a gap.
+pub struct AudioReceiver {
+    decoder: Box<dyn AudioDecoder>,
+    playback: AudioPlayback,
+    jitter: JitterEstimator,
+    pending: BTreeMap<u64, PendingPacket>,
+    next_sequence: Option<u64>,
+    /// Capture time of the packet at `next_sequence`
+    next_pts: Option<SystemTime>,
+    stats: AudioReceiveStats,
+}
+
+impl AudioReceiver {
+    /// `decoder` must produce the playback's format
+    pub fn new(decoder: Box<dyn AudioDecoder>, playback: AudioPlayback) -> Self {
+        Self::with_jitter(decoder, playback, AUDIO_JITTER)
+    }
+
+    pub fn with_jitter(decoder: Box<dyn AudioDecoder>, playback: AudioPlayback, jitter: JitterConfig) -> Self {
+        Self {
+            decoder,
+            playback,
+            jitter: JitterEstimator::new(jitter),
+            pending: BTreeMap::new(),
+            next_sequence: None,
+            next_pts: None,
+            stats: AudioReceiveStats::default(),
+        }
+    }
+
+    /// Buffer a received packet, and play whatever is due
+    pub fn receive(&mut self, sequence: u64, pts: SystemTime, packet: &[u8]) -> Result<()> {
+        self.receive_at(sequence, pts, packet, Instant::now(), SystemTime::now())
+    }
+
+    fn receive_at(
+        &mut self,
+        sequence: u64,
+        pts: SystemTime,
+        packet: &[u8],
+        now: Instant,
+        wall_now: SystemTime,
+    ) -> Result<()> {
+        self.stats.received += 1;
+        if self.next_sequence.is_some_and(|next| sequence < next) || self.pending.contains_key(&sequence) {
+            debug!("Dropping late audio packet {}", sequence);
+            self.stats.late += 1;
+            return Ok(());
+        }
+
+        let due = self.jitter.schedule_at(pts, now, wall_now);
+        self.pending.insert(sequence, PendingPacket { pts, due, data: packet.to_vec() });
+        self.release(now)
+    }
+
+    /// Play packets that have become due, concealing any lost before them.
+    /// Call regularly, as packets may come due between arrivals.
+    pub fn poll(&mut self) -> Result<()> {
+        self.release(Instant::now())
+    }
+
+    fn release(&mut self, now: Instant) -> Result<()> {
+        let packet_samples = self.playback.format().packet_samples();
+
+        while let Some((&sequence, first)) = self.pending.first_key_value() {
+            let next = *self.next_sequence.get_or_insert(sequence);
+            let running_dry = self.playback.buffered() < PACKET_DURATION;
+
+            if sequence == next {
+                if first.due > now {
+                    break;
+                }
+                let packet = self.pending.remove(&sequence).expect("Packet is pending");
+                let samples = self.decoder.decode(&packet.data)?;
+                self.playback.push(packet.pts, &samples);
+                self.next_sequence = Some(sequence + 1);
+                self.next_pts = Some(packet.pts + PACKET_DURATION);
+                continue;
+            }
+
+            // Packets next..sequence are missing. Wait for them until the
+            // one after the gap is due, unless playback would stall first.
+            if first.due > now && !running_dry {
+                break;
+            }
+
+            let missing = sequence - next;
+            self.stats.lost += missing;
+            if missing > MAX_GAP_PACKETS {
+                debug!("Lost {} audio packets, resynchronizing", missing);
+                self.playback.clear();
+            } else {
+                let mut pts = self.next_pts.unwrap_or(first.pts);
+                for lost in 0..missing {
+                    let samples = if lost < MAX_CONCEALED_PACKETS {
+                        self.stats.concealed += 1;
+                        self.decoder.conceal(packet_samples)?
+                    } else {
+                        vec![0.0; packet_samples]
+                    };
+                    self.playback.push(pts, &samples);
+                    pts += PACKET_DURATION;
+                }
+            }
+            self.next_sequence = Some(sequence);
+        }
+        Ok(())
+    }
+
+    /// Current playout delay
+    pub fn playout_delay(&self) -> Duration {
+        self.jitter.playout_delay()
+    }
+
+    pub fn stats(&self) -> AudioReceiveStats {
+        self.stats
+    }
+
+    /// Packets that never arrived in time
+    pub fn packets_lost(&self) -> u64 {
+        self.stats.lost
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MONO: AudioFormat = AudioFormat {
+        sample_rate: 1000,
+        channels: 1,
+    };
+
+    #[test]
+    fn test_jitter_buffer_reorders_and_conceals() -> Result<()> {
+        // Each packet decodes to 20 copies of its first byte; lost ones
+        // are concealed as -1
+        struct Marker;
+        impl AudioDecoder for Marker {
+            fn decode(&mut self, packet: &[u8]) -> Result<Vec<f32>> {
+                Ok(vec![packet[0] as f32; 20])
+            }
+            fn conceal(&mut self, samples: usize) -> Result<Vec<f32>> {
+                Ok(vec![-1.0; samples])
+            }
+        }
+
+        let playback = AudioPlayback::new(MONO);
+        let jitter = JitterConfig {
+            target_latency: Duration::from_millis(40),
+            adaptive: false,
+            ..Default::default()
+        };
+        let mut receiver = AudioReceiver::with_jitter(Box::new(Marker), playback.clone(), jitter);
+
+        let (start, wall) = (Instant::now(), SystemTime::now());
+        let ms = |ms: u64| Duration::from_millis(ms);
+        let pts = |sequence: u64| wall + ms(20 * sequence);
+        let arrive = |receiver: &mut AudioReceiver, sequence: u64, at: u64| {
+            receiver.receive_at(sequence, pts(sequence), &[sequence as u8], start + ms(at), wall + ms(at))
+        };
+
+        // Held for the playout delay
+        arrive(&mut receiver, 0, 0)?;
+        assert_eq!(playback.buffered(), Duration::ZERO);
+
+        // Packet 1 arrives after 2, but still in time to play in order
+        arrive(&mut receiver, 2, 40)?;
+        arrive(&mut receiver, 1, 45)?;
+        assert_eq!(playback.buffered(), ms(20));
+
+        // Packet 3 never arrives and is concealed once 4 is due; it is
+        // too late when it finally turns up
+        arrive(&mut receiver, 4, 80)?;
+        receiver.release(start + ms(120))?;
+        arrive(&mut receiver, 3, 130)?;
+
+        let stats = receiver.stats();
+        assert_eq!((stats.received, stats.lost, stats.concealed, stats.late), (5, 1, 1, 1));
+
+        let mut output = [0.0; 100];
+        playback.fill(&mut output);
+        let firsts: Vec<f32> = output.chunks(20).map(|packet| packet[0]).collect();
+        assert_eq!(firsts, [0.0, 1.0, 2.0, -1.0, 4.0]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_plays_freely_without_video() {
+        let playback = AudioPlayback::new(MONO);
+        playback.push(SystemTime::now(), &[0.1, 0.2, 0.3]);
+
+        let mut output = [1.0; 5];
+        playback.fill(&mut output);
+        assert_eq!(output, [0.1, 0.2, 0.3, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn test_large_drift_jumps() {
+        let now = Instant::now();
+        let video_pts = SystemTime::now();
+        let samples: Vec<f32> = (0..1000).map(|i| i as f32).collect();
+
+        // Audio 200ms behind the video: the first 200 samples are skipped
+        let playback = AudioPlayback::new(MONO);
+        playback.push(video_pts - Duration::from_millis(200), &samples);
+        playback.lock().video = Some((video_pts, now));
+        let mut output = [0.0; 4];
+        playback.fill_at(&mut output, now);
+        assert_eq!(output[0], 200.0);
+
+        // Audio 200ms ahead: silence until the video catches up
+        let playback = AudioPlayback::new(MONO);
+        playback.push(video_pts + Duration::from_millis(200), &samples);
+        playback.lock().video = Some((video_pts, now));
+        playback.fill_at(&mut output, now);
+        assert_eq!(output, [0.0; 4]);
+        assert_eq!(playback.buffered(), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_small_drift_eases() {
+        let now = Instant::now();
+        let video_pts = SystemTime::now();
+        let playback = AudioPlayback::new(MONO);
+        playback.push(video_pts - Duration::from_millis(30), &vec![0.5; 1000]);
+        playback.lock().video = Some((video_pts, now));
+
+        let mut output = vec![0.0; 300];
+        playback.fill_at(&mut output, now);
+
+        // Behind by 30ms: a few frames dropped, not 30
+        let corrections = playback.corrections();
+        assert!((1..=3).contains(&corrections), "{}", corrections);
+        assert_eq!(playback.buffered(), Duration::from_millis(700 - corrections));
+    }
+}
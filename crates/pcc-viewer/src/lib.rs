@@ -0,0 +1,60 @@
+//! The viewer: accepting hosts, playing out and rendering what they share,
+//! their audio, and recording. It depends on `pcc-net` and `pcc-core` but
+//! not on screen capture.
+
+pub mod audio;
+pub mod renderer;
+pub mod network;
+pub mod recorder;
+pub mod sink;
+
+// Re-export commonly used types
+pub use renderer::Renderer;
+pub use sink::{FrameSink, NullSink};
+
+use pcc_net::network::NetworkEvent;
+use anyhow::Result;
+use tracing::warn;
+
+/// Feed everything received from connected hosts into the renderer: full
+/// frames go through the frame buffer, delta updates and other display
+/// messages are applied to the current frame and presented. When a host
+/// drops out, its last frame stays up until it reconnects. Hosts are told
+/// whenever the renderer starts or stops recording.
+pub async fn present_incoming(network: &network::ServerNetwork, renderer: &Renderer) -> Result<()> {
+    let mut recording = renderer.watch_recording();
+    let now = *recording.borrow_and_update();
+    network.set_recording(now).await?;
+    loop {
+        tokio::select! {
+            Ok(()) = recording.changed() => {
+                let now = *recording.borrow_and_update();
+                if let Err(e) = network.set_recording(now).await {
+                    warn!("Failed to tell hosts about recording: {}", e);
+                }
+            }
+            frame = network.next_frame() => match frame {
+                Some(frame) => {
+                    renderer.record_arrival(frame.id, frame.timestamp).await;
+                    renderer.buffer.push_frame(frame).await?
+                }
+                None => return Ok(()),
+            },
+            message = network.next_message() => match message {
+                Some(message) => {
+                    if let Err(e) = renderer.handle_message(message).await {
+                        warn!("Failed to apply message: {}", e);
+                    }
+                }
+                None => return Ok(()),
+            },
+            Some(event) = network.next_event() => match event {
+                NetworkEvent::Connected { .. } => renderer.set_reconnecting(false).await,
+                NetworkEvent::Closed(reason) if reason.may_reconnect() => {
+                    renderer.set_reconnecting(true).await
+                }
+                _ => {}
+            },
+        }
+    }
+}
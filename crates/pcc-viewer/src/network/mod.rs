@@ -0,0 +1,770 @@
+use pcc_core::wire::MAX_FRAME_SIZE;
+use pcc_net::network::{
+    control, peer_fingerprint, pin_proof, queue, CloseReason, ConnectionLimiter, Identity,
+    IdentityProof, KeyBinding, Message, NetworkConfig, NetworkEvent, NetworkQueueStats, PayloadCipher, PayloadKeyExchange,
+    QueueReceiver, QueueSender, ResilienceConfig, SessionInfo, SessionRegistry,
+};
+use pcc_net::health::Health;
+use pcc_net::input::{InputEvent, Modifiers, MouseMode, Permission};
+use pcc_core::types::Frame;
+use pcc_core::{pool::FramePool, QualityConfig};
+use pcc_net::session_log::{SessionEvent, SessionLog};
+use anyhow::{Context, Result};
+use quinn::Endpoint;
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::Mutex;
+use tokio::task::JoinSet;
+use tokio::time;
+use tracing::{debug, debug_span, field, info, warn, Instrument};
+
+mod sequence;
+mod sessions;
+
+use sequence::FrameSequence;
+pub use sequence::SequenceCounts;
+use sessions::SessionManager;
+pub use sessions::SessionId;
+
+pub struct ServerNetwork {
+    endpoint: Endpoint,
+    config: NetworkConfig,
+    // Replaced when the config is reloaded
+    resilience: std::sync::Mutex<ResilienceConfig>,
+    routes: Routes,
+    frame_rx: Mutex<QueueReceiver<(SessionId, Frame)>>,
+    message_rx: Mutex<QueueReceiver<(SessionId, Message)>>,
+    event_rx: Mutex<QueueReceiver<NetworkEvent>>,
+    /// One per connection, joined on shutdown
+    tasks: Mutex<JoinSet<Result<()>>>,
+}
+
+// The address this machine reaches other networks from. Connecting a UDP
+// socket only picks a route; nothing is sent to the documentation address.
+fn outward_ip() -> Option<IpAddr> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+    socket.connect((Ipv4Addr::new(192, 0, 2, 1), 9)).ok()?;
+    Some(socket.local_addr().ok()?.ip()).filter(|ip| !ip.is_unspecified())
+}
+
+// How long shutdown waits for goodbyes to be acknowledged and connection
+// tasks to finish before giving up on them
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Where a connection's received frames and messages are delivered, tagged
+/// with the session they came from
+#[derive(Clone)]
+struct Routes {
+    frame_tx: QueueSender<(SessionId, Frame)>,
+    message_tx: QueueSender<(SessionId, Message)>,
+    /// Connects and disconnects; dropped if nobody is listening
+    event_tx: QueueSender<NetworkEvent>,
+    sessions: SessionRegistry,
+    /// Hosts past the handshake, for sending input back to them
+    hosts: SessionManager,
+    events: SessionLog,
+    /// Asks this viewer's user for the PIN a host shows to pair
+    pin_prompt: Option<PinPrompt>,
+    /// Turns away addresses that connect too often or keep failing
+    limiter: Arc<ConnectionLimiter>,
+    /// Whether this viewer is recording, told to hosts as they connect
+    recording: Arc<AtomicBool>,
+    /// Where received keyframes are decoded into
+    pool: FramePool,
+    /// The saved identity this viewer signs payload key exchanges with
+    identity: Option<Arc<Identity>>,
+}
+
+/// Asks for the PIN the host at the given address shows, returning None if
+/// none was given. Called off the async runtime, so it may block on input.
+pub type PinPrompt = Arc<dyn Fn(SocketAddr) -> Option<String> + Send + Sync>;
+
+// Frames received from one host
+#[derive(Debug, Default)]
+struct HostCounters {
+    keyframes: AtomicU64,
+    updates: AtomicU64,
+    sequence: std::sync::Mutex<FrameSequence>,
+    // As last announced by the host
+    quality: std::sync::Mutex<Option<QualityConfig>>,
+}
+
+/// Traffic from one connected host, from `ServerNetwork::host_stats`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HostStats {
+    pub session: SessionId,
+    pub addr: SocketAddr,
+    /// Whether the host seals its frame payloads end to end
+    pub encrypted: bool,
+    /// What this viewer may do, as negotiated and since changed by the host
+    pub permission: Permission,
+    /// Whether the host resumed an earlier session when it connected
+    pub resumed: bool,
+    pub connected_for: Duration,
+    pub rtt: Duration,
+    /// Bytes sent to the host, mostly acknowledgements and input
+    pub sent_bytes: u64,
+    pub received_bytes: u64,
+    pub lost_packets: u64,
+    pub keyframes: u64,
+    /// Delta updates, counted once all their parts have arrived
+    pub updates: u64,
+    pub sequence: SequenceCounts,
+    /// Frame rate, threshold and quality the host is sharing at, once it
+    /// has said
+    pub quality: Option<QualityConfig>,
+}
+
+impl HostStats {
+    /// Keyframes and updates that arrived whole
+    pub fn frames_delivered(&self) -> u64 {
+        self.keyframes + self.updates
+    }
+
+    /// Frames the host sent that never arrived
+    pub fn frames_dropped(&self) -> u64 {
+        self.sequence.missing
+    }
+}
+
+impl Routes {
+    fn notify(&self, event: NetworkEvent) {
+        let _ = self.event_tx.try_send(event);
+    }
+
+    // Count a failed handshake against `peer`, banning it if it keeps failing
+    fn handshake_failed(&self, peer: SocketAddr) {
+        if self.limiter.failed(peer.ip(), Instant::now()) {
+            let ban = self.limiter.limits().ban_duration;
+            warn!("Banned {} for {}s after repeated failed handshakes", peer.ip(), ban.as_secs());
+            self.events.record(SessionEvent::Error {
+                message: format!("Banned {} after repeated failed handshakes", peer.ip()),
+            });
+        }
+    }
+}
+
+impl ServerNetwork {
+    pub fn new(config: NetworkConfig, resilience: ResilienceConfig) -> Result<Self> {
+        let addr = SocketAddr::from((Ipv4Addr::UNSPECIFIED, config.port.unwrap_or(5800)));
+        Self::bind(addr, config, resilience)
+    }
+
+    /// Like `new`, listening on `addr` rather than every interface at the
+    /// configured port
+    pub fn bind(addr: SocketAddr, config: NetworkConfig, resilience: ResilienceConfig) -> Result<Self> {
+        let endpoint = Endpoint::server(config.server_config()?, addr)?;
+
+        let (frame_tx, frame_rx) = queue(config.queues.frames);
+        let (message_tx, message_rx) = queue(config.queues.messages);
+        let (event_tx, event_rx) = queue(config.queues.events);
+
+        Ok(Self {
+            endpoint,
+            routes: Routes {
+                frame_tx,
+                message_tx,
+                event_tx,
+                sessions: SessionRegistry::new(config.session_resume_window),
+                hosts: SessionManager::default(),
+                events: SessionLog::default(),
+                pin_prompt: None,
+                limiter: Arc::new(ConnectionLimiter::new(config.limits.clone())),
+                recording: Arc::new(AtomicBool::new(false)),
+                pool: FramePool::default(),
+                identity: config.identity.as_deref().map(Identity::load).transpose()?.map(Arc::new),
+            },
+            config,
+            resilience: std::sync::Mutex::new(resilience),
+            frame_rx: Mutex::new(frame_rx),
+            message_rx: Mutex::new(message_rx),
+            event_rx: Mutex::new(event_rx),
+            tasks: Mutex::new(JoinSet::new()),
+        })
+    }
+
+    /// Log hosts' connects, disconnects and keyframes, and connection
+    /// errors, to `events`
+    pub fn with_event_log(mut self, events: SessionLog) -> Self {
+        self.routes.events = events;
+        self
+    }
+
+    /// Decode received keyframes into buffers from `pool`, e.g. the
+    /// renderer's `frame_pool`, which recycles them once presented
+    pub fn with_frame_pool(mut self, pool: FramePool) -> Self {
+        self.routes.pool = pool;
+        self
+    }
+
+    /// Ask `prompt` for the PIN when a host wants to pair. Without a
+    /// prompt, hosts that require pairing can't connect.
+    pub fn with_pin_prompt(mut self, prompt: impl Fn(SocketAddr) -> Option<String> + Send + Sync + 'static) -> Self {
+        self.routes.pin_prompt = Some(Arc::new(prompt));
+        self
+    }
+
+    pub async fn start(&self) -> Result<()> {
+        info!("Server listening on port {}", self.local_addr()?.port());
+        
+        while let Some(conn) = self.endpoint.accept().await {
+            let remote = conn.remote_address();
+            // Dropping the attempt closes it, before the handshake finishes.
+            // Not logged above debug, as a flood would flood the log too.
+            if let Err(refusal) = self.routes.limiter.admit(remote.ip(), Instant::now()) {
+                debug!("Refused connection from {}: {}", remote, refusal);
+                continue;
+            }
+            let routes = self.routes.clone();
+            let config = self.config.clone();
+            let mut tasks = self.tasks.lock().await;
+            // Forget connections that already ended
+            while tasks.try_join_next().is_some() {}
+            // Each connection completes its handshakes in its own task, so
+            // a slow or failed one doesn't hold up the hosts behind it
+            tasks.spawn(async move {
+                let connection = match time::timeout(config.limits.handshake_timeout, conn).await {
+                    Ok(Ok(connection)) => connection,
+                    Ok(Err(e)) => {
+                        routes.handshake_failed(remote);
+                        return Err(e).with_context(|| format!("Connection from {} failed", remote));
+                    }
+                    Err(_) => {
+                        routes.handshake_failed(remote);
+                        anyhow::bail!("Connection from {} didn't finish its handshake in time", remote);
+                    }
+                };
+                info!("Client connected from {}", remote);
+                if !config.access.permits(remote.ip(), peer_fingerprint(&connection)) {
+                    warn!("Refused {}, which the access list doesn't allow", remote);
+                    routes.handshake_failed(remote);
+                    routes.events.record(SessionEvent::Error {
+                        message: format!("Refused {} by access list", remote),
+                    });
+                    let reason = CloseReason::AccessDenied;
+                    connection.close(reason.code(), reason.to_string().as_bytes());
+                    return Ok(());
+                }
+                let (session, payload) =
+                    match Self::handshake(&connection, &routes, &config).await {
+                        Ok(handshake) => handshake,
+                        Err(e) => {
+                            warn!("Handshake with {} failed: {}", remote, e);
+                            routes.handshake_failed(remote);
+                            routes.events.record(SessionEvent::Error {
+                                message: format!("Handshake with {} failed: {:#}", remote, e),
+                            });
+                            connection.close(
+                                CloseReason::ProtocolError.code(),
+                                CloseReason::ProtocolError.to_string().as_bytes(),
+                            );
+                            return Err(e);
+                        }
+                    };
+                routes.limiter.succeeded(remote.ip());
+                let resumed = session.resume_from.is_some();
+                let (id, counters) = routes.hosts.register(connection.clone(), session, payload.is_some()).await;
+                info!("Host {} has session {}", remote, id);
+                if payload.is_some() {
+                    info!("Host {} seals its frames end to end", remote);
+                }
+                // Recording may have started or stopped since the welcome,
+                // before the host was there to be told
+                let recording = Message::Recording(routes.recording.load(Ordering::Relaxed));
+                if let Err(e) = control::send_message(&connection, &recording).await {
+                    debug!("Failed to send recording state: {}", e);
+                }
+                routes.notify(NetworkEvent::Connected { resumed });
+                routes.events.record(SessionEvent::Connect { peer: remote, resumed });
+                let keepalive = config.keepalive_interval;
+                let result =
+                    Self::handle_connection(connection, routes.clone(), id, session, counters, payload, keepalive).await;
+                routes.hosts.remove(id).await;
+                if let Err(e) = &result {
+                    routes.events.record(SessionEvent::Error {
+                        message: format!("Connection from {} failed: {:#}", remote, e),
+                    });
+                }
+                result
+            });
+        }
+        
+        Ok(())
+    }
+
+    /// Say goodbye to every host with `reason`, close the endpoint and wait
+    /// for the connection tasks to finish. Frames and messages not yet
+    /// received are dropped, so call this once done receiving.
+    pub async fn shutdown(&self, reason: CloseReason) {
+        let hosts = self.routes.hosts.connections(Permission::ViewOnly).await;
+        let goodbye = Message::Goodbye { reason };
+        for connection in hosts {
+            // Best effort: the close code carries the reason regardless
+            match time::timeout(SHUTDOWN_TIMEOUT, control::send_message(&connection, &goodbye)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => debug!("Failed to send goodbye: {}", e),
+                Err(_) => debug!("Goodbye to {} timed out", connection.remote_address()),
+            }
+            connection.close(reason.code(), reason.to_string().as_bytes());
+        }
+        self.endpoint.close(reason.code(), reason.to_string().as_bytes());
+
+        // Nothing reads what is still arriving, so tasks must not wait to
+        // deliver it
+        self.frame_rx.lock().await.close();
+        self.message_rx.lock().await.close();
+        self.event_rx.lock().await.close();
+
+        let mut tasks = self.tasks.lock().await;
+        let joined = time::timeout(SHUTDOWN_TIMEOUT, async {
+            while let Some(result) = tasks.join_next().await {
+                if let Ok(Err(e)) = result {
+                    debug!("Connection ended with: {}", e);
+                }
+            }
+        });
+        if joined.await.is_err() {
+            warn!("Connection tasks did not finish in time; aborting them");
+            tasks.shutdown().await;
+        }
+        if time::timeout(SHUTDOWN_TIMEOUT, self.endpoint.wait_idle()).await.is_err() {
+            debug!("Gave up waiting for connections to close");
+        }
+        info!("Server shut down");
+    }
+
+    /// Address the server is listening on, with the port the OS chose if
+    /// configured with port 0
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.endpoint.local_addr()?)
+    }
+
+    /// Address for hosts to connect to: the port listened on, at this
+    /// machine's address on its default route
+    pub fn advertised_addr(&self) -> Result<SocketAddr> {
+        let mut addr = self.local_addr()?;
+        if addr.ip().is_unspecified() {
+            addr.set_ip(outward_ip().unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST)));
+        }
+        Ok(addr)
+    }
+
+    /// Get the resilience configuration for this server
+    pub fn resilience_config(&self) -> ResilienceConfig {
+        self.resilience.lock().unwrap().clone()
+    }
+
+    /// Replace the resilience configuration while serving
+    pub fn set_resilience_config(&self, resilience: ResilienceConfig) {
+        *self.resilience.lock().unwrap() = resilience;
+    }
+
+    /// Receive the next frame decoded from any connected client
+    pub async fn next_frame(&self) -> Option<Frame> {
+        self.next_session_frame().await.map(|(_, frame)| frame)
+    }
+
+    /// Receive the next control message (delta updates, cursor, app data)
+    /// from any connected client
+    pub async fn next_message(&self) -> Option<Message> {
+        self.next_session_message().await.map(|(_, message)| message)
+    }
+
+    /// Receive the next frame, with the session of the host that sent it
+    pub async fn next_session_frame(&self) -> Option<(SessionId, Frame)> {
+        self.frame_rx.lock().await.recv().await
+    }
+
+    /// Receive the next control message, with the session of the host that
+    /// sent it
+    pub async fn next_session_message(&self) -> Option<(SessionId, Message)> {
+        self.message_rx.lock().await.recv().await
+    }
+
+    /// Receive the next connect or disconnect of a host
+    pub async fn next_event(&self) -> Option<NetworkEvent> {
+        self.event_rx.lock().await.recv().await
+    }
+
+    /// Send a control message to every connected host
+    pub async fn send_message(&self, message: &Message) -> Result<()> {
+        for host in self.host_connections(Permission::ViewOnly).await {
+            control::send_message(&host, message).await?;
+        }
+        Ok(())
+    }
+
+    /// Tell every host whether this viewer is recording the session, and
+    /// hosts that connect later as they do
+    pub async fn set_recording(&self, recording: bool) -> Result<()> {
+        if self.routes.recording.swap(recording, Ordering::Relaxed) == recording {
+            return Ok(());
+        }
+        info!("Telling hosts recording {}", if recording { "started" } else { "stopped" });
+        self.send_message(&Message::Recording(recording)).await
+    }
+
+    /// Send local keyboard or mouse input to the hosts this viewer controls.
+    /// Returns the number of hosts it was sent to.
+    pub async fn send_input(&self, event: InputEvent, modifiers: Modifiers) -> Result<usize> {
+        let message = Message::input(event, modifiers);
+        let hosts = self.host_connections(Permission::Control).await;
+        for host in &hosts {
+            control::send_message(host, &message).await?;
+        }
+        Ok(hosts.len())
+    }
+
+    /// Switch the hosts this viewer controls to absolute or relative mouse
+    /// input, e.g. when capturing the local mouse for a game
+    pub async fn set_mouse_mode(&self, mode: MouseMode) -> Result<()> {
+        for host in self.host_connections(Permission::Control).await {
+            control::send_message(&host, &Message::MouseMode(mode)).await?;
+        }
+        Ok(())
+    }
+
+    /// Whether any connected host lets this viewer control it
+    pub async fn has_control(&self) -> bool {
+        !self.host_connections(Permission::Control).await.is_empty()
+    }
+
+    /// Say goodbye to one host with `reason` and close its connection.
+    /// Returns whether the session was still connected.
+    pub async fn disconnect(&self, session: SessionId, reason: CloseReason) -> bool {
+        let Some(connection) = self.routes.hosts.connection(session).await else {
+            return false;
+        };
+        if let Err(e) = control::send_message(&connection, &Message::Goodbye { reason }).await {
+            debug!("Failed to send goodbye: {}", e);
+        }
+        connection.close(reason.code(), reason.to_string().as_bytes());
+        true
+    }
+
+    /// Whether any hosts are connected
+    pub async fn health(&self) -> Health {
+        let hosts = self.routes.hosts.count().await;
+        Health::default().check("hosts", hosts > 0, format!("{} connected", hosts))
+    }
+
+    /// Traffic from each connected host, in the order they connected
+    pub async fn host_stats(&self) -> Vec<HostStats> {
+        self.routes.hosts.stats().await
+    }
+
+    /// What's waiting in the queues received frames, messages and events
+    /// go through, and what their overflow policies have dropped
+    pub fn queue_stats(&self) -> NetworkQueueStats {
+        NetworkQueueStats {
+            frames: self.routes.frame_tx.stats(),
+            messages: self.routes.message_tx.stats(),
+            events: self.routes.event_tx.stats(),
+        }
+    }
+
+    async fn host_connections(&self, at_least: Permission) -> Vec<quinn::Connection> {
+        self.routes.hosts.connections(at_least).await
+    }
+
+    /// Answer the host's `Hello`, resuming its previous session if possible,
+    /// sending the PIN if the host asks to pair and taking its key if it
+    /// seals frame payloads
+    async fn handshake(
+        connection: &quinn::Connection,
+        routes: &Routes,
+        config: &NetworkConfig,
+    ) -> Result<(SessionInfo, Option<Arc<PayloadCipher>>)> {
+        let hello = tokio::time::timeout(config.connection_timeout, async {
+            let mut recv = connection.accept_uni().await?;
+            control::read_message(&mut recv).await
+        })
+        .await
+        .context("Timed out waiting for hello")??;
+
+        let Message::Hello { resume_token, permission: offered, pairing, payload_key: host_key } = hello else {
+            anyhow::bail!("Expected hello, got {:?}", hello);
+        };
+        let pin = match (pairing, routes.pin_prompt.clone()) {
+            (false, _) => None,
+            (true, Some(prompt)) => {
+                let remote = connection.remote_address();
+                let pin = tokio::task::spawn_blocking(move || prompt(remote)).await?;
+                Some(pin.context("No pairing PIN entered")?)
+            }
+            (true, None) => anyhow::bail!("Host asked to pair, but there's no way to enter its PIN here"),
+        };
+
+        // Sign the exchange so the host can tell it reached this viewer,
+        // and mix in the PIN, which a relay never sees
+        let mut identity = None;
+        let payload = match host_key {
+            Some(host_key) => {
+                let exchange = PayloadKeyExchange::new()?;
+                let viewer_key = exchange.public_key();
+                let mut binding = KeyBinding { host_key, viewer_key, viewer: None, pin: pin.as_deref() };
+                if let Some(saved) = &routes.identity {
+                    identity = Some(IdentityProof::sign(saved, &binding.transcript())?);
+                    binding.viewer = Some(saved.fingerprint()?);
+                }
+                Some((viewer_key, exchange.agree(&host_key, &binding)?))
+            }
+            None => None,
+        };
+        let pin_proof = pin.as_deref().map(|pin| {
+            let binding = payload.as_ref().map(|(_, payload)| payload.binding());
+            pin_proof(pin, binding.as_ref().map_or(&[][..], |binding| &binding[..]))
+        });
+
+        let mut session = routes.sessions.open(resume_token).await?;
+        session.permission = Permission::negotiate(offered, config.permission);
+        control::send_message(
+            connection,
+            &Message::Welcome {
+                token: session.token,
+                resume_from: session.resume_from,
+                permission: session.permission,
+                pin_proof,
+                name: config.name.clone(),
+                payload_key: payload.as_ref().map(|(viewer_key, _)| *viewer_key),
+                recording: Some(routes.recording.load(Ordering::Relaxed)),
+                identity,
+            },
+        )
+        .await?;
+        let payload = payload.map(|(_, payload)| Arc::new(payload));
+        info!("Session permission: {:?}", session.permission);
+
+        if let Some(frame_id) = session.resume_from {
+            info!("Resumed session after frame {}", frame_id);
+        }
+        // Start from a full frame rather than waiting for the host's next one;
+        // deltas sent while disconnected are lost even on resume
+        Self::request_keyframe(connection).await;
+
+        Ok((session, payload))
+    }
+
+    async fn handle_connection(
+        connection: quinn::Connection,
+        routes: Routes,
+        id: SessionId,
+        session: SessionInfo,
+        counters: Arc<HostCounters>,
+        payload: Option<Arc<PayloadCipher>>,
+        keepalive: Duration,
+    ) -> Result<()> {
+        // Heartbeats tell the host the viewer is still there when it has
+        // nothing else to say
+        let heartbeat_conn = connection.clone();
+        let heartbeats = tokio::spawn(async move {
+            let mut interval = time::interval_at(time::Instant::now() + keepalive, keepalive);
+            loop {
+                interval.tick().await;
+                if let Err(e) = control::send_message(&heartbeat_conn, &Message::KeepAlive).await {
+                    debug!("Stopped sending heartbeats: {}", e);
+                    break;
+                }
+            }
+        });
+
+        // Control messages arrive on their own unidirectional streams
+        let control_conn = connection.clone();
+        let control_routes = routes.clone();
+        let control_counters = counters.clone();
+        let control_payload = payload.clone();
+        let control = tokio::spawn(async move {
+            // Audio streams end with the connection, and are joined with it
+            let mut audio = JoinSet::new();
+            while let Ok(mut recv) = control_conn.accept_uni().await {
+                let message = match control::read_message(&mut recv).await {
+                    Ok(message) => message,
+                    Err(e) => {
+                        warn!("Invalid control message: {}", e);
+                        continue;
+                    }
+                };
+                let message = match message {
+                    Message::Sealed { epoch, nonce, data } => match &control_payload {
+                        Some(payload) => match payload.open_message(epoch, nonce, &data) {
+                            Ok(message) => message,
+                            Err(e) => {
+                                warn!("Failed to open sealed message: {}", e);
+                                continue;
+                            }
+                        },
+                        None => {
+                            warn!("Sealed message from a host that didn't exchange keys");
+                            continue;
+                        }
+                    },
+                    // Frame payloads of a sealed session only count sealed, so
+                    // a relay can't slip its own in
+                    Message::FrameUpdate { .. } | Message::CopyRect { .. } if control_payload.is_some() => {
+                        warn!("Dropped an unsealed frame update");
+                        continue;
+                    }
+                    message => message,
+                };
+
+                if let Message::AudioStreamStart { source, .. } = message {
+                    // The rest of the stream is audio packets, read as they come
+                    audio.spawn(Self::receive_audio(recv, control_routes.clone(), id));
+                    info!("Host started streaming {:?} audio", source);
+                }
+                if let Message::FrameUpdate { update, part, parts } = &message {
+                    if part + 1 >= *parts {
+                        control_counters.updates.fetch_add(1, Ordering::Relaxed);
+                        control_counters.sequence.lock().unwrap().received(update.frame_id);
+                        control_routes.sessions.acknowledge(&session.token, update.frame_id).await;
+                    }
+                }
+                if let Message::FrameTimings(timings) = &message {
+                    control_counters.sequence.lock().unwrap().sent(timings.frame_id);
+                }
+                if let Message::QualityConfig(config) = &message {
+                    *control_counters.quality.lock().unwrap() = Some(*config);
+                }
+                if let Message::Permission(permission) = message {
+                    // The host may restore control, but never beyond what was negotiated
+                    control_routes.hosts.set_permission(id, permission).await;
+                    info!("Host changed permission to {:?}", permission);
+                }
+
+                if control_routes.message_tx.send((id, message)).await.is_err() {
+                    break;
+                }
+            }
+            while audio.join_next().await.is_some() {}
+        });
+
+        let result = Self::receive_frames(&connection, &routes, id, session, &counters, &payload).await;
+        heartbeats.abort();
+        if result.is_err() {
+            control.abort();
+        }
+        let _ = control.await;
+        result
+    }
+
+    // Receive whole frames, each on its own stream, until the host
+    // disconnects. Streams are read side by side, as streams the host opened
+    // but never sent on, like its `Connection`'s own, are accepted in order
+    // and would otherwise hold up every frame after them.
+    async fn receive_frames(
+        connection: &quinn::Connection,
+        routes: &Routes,
+        id: SessionId,
+        session: SessionInfo,
+        counters: &Arc<HostCounters>,
+        payload: &Option<Arc<PayloadCipher>>,
+    ) -> Result<()> {
+        let mut frames = JoinSet::new();
+        loop {
+            let accepted = tokio::select! {
+                accepted = connection.accept_bi() => accepted,
+                Some(received) = frames.join_next() => {
+                    received??;
+                    continue;
+                }
+            };
+            let recv = match accepted {
+                Ok((_send, recv)) => recv,
+                Err(e) => {
+                    let reason = CloseReason::from_error(&e);
+                    info!("Client {} disconnected: {}", connection.remote_address(), reason);
+                    routes.notify(NetworkEvent::Closed(reason));
+                    routes.events.record(SessionEvent::Disconnect { peer: connection.remote_address(), reason });
+                    break;
+                }
+            };
+            frames.spawn(Self::receive_frame(
+                recv,
+                connection.clone(),
+                routes.clone(),
+                id,
+                session,
+                counters.clone(),
+                payload.clone(),
+            ));
+        }
+        Ok(())
+    }
+
+    // Read one whole frame off its stream and pass it on
+    async fn receive_frame(
+        mut recv: quinn::RecvStream,
+        connection: quinn::Connection,
+        routes: Routes,
+        id: SessionId,
+        session: SessionInfo,
+        counters: Arc<HostCounters>,
+        payload: Option<Arc<PayloadCipher>>,
+    ) -> Result<()> {
+        let span = debug_span!("receive", frame_id = field::Empty);
+        let buf = match recv.read_to_end(MAX_FRAME_SIZE + 1024).instrument(span.clone()).await {
+            Ok(buf) => buf,
+            Err(quinn::ReadToEndError::TooLong) => {
+                warn!("Frame larger than {} bytes", MAX_FRAME_SIZE);
+                Self::request_keyframe(&connection).await;
+                return Ok(());
+            }
+            // Streams end early when the host disconnects, which the accept
+            // loop reports
+            Err(e) => {
+                debug!("Frame stream ended: {}", e);
+                return Ok(());
+            }
+        };
+
+        let decoded = span.in_scope(|| match &payload {
+            Some(payload) => Frame::decode_pooled(&payload.open_keyframe(&buf)?, &routes.pool),
+            None => Frame::decode_pooled(&buf, &routes.pool),
+        });
+        match decoded {
+            Ok(frame) => {
+                span.record("frame_id", frame.id);
+                counters.keyframes.fetch_add(1, Ordering::Relaxed);
+                counters.sequence.lock().unwrap().received(frame.id);
+                routes.events.record(SessionEvent::Keyframe { frame_id: frame.id });
+                routes.sessions.acknowledge(&session.token, frame.id).await;
+                routes.frame_tx.send((id, frame)).instrument(span).await?;
+            }
+            Err(e) => {
+                warn!("Failed to decode frame: {}", e);
+                Self::request_keyframe(&connection).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// Forward the packets of a host audio stream until the host ends it
+    async fn receive_audio(mut recv: quinn::RecvStream, routes: Routes, id: SessionId) {
+        loop {
+            match control::read_next(&mut recv).await {
+                Ok(Some(message @ Message::AudioPacket { .. })) => {
+                    if routes.message_tx.send((id, message)).await.is_err() {
+                        break;
+                    }
+                }
+                Ok(Some(other)) => warn!("Unexpected message on audio stream: {:?}", other),
+                Ok(None) => break,
+                Err(e) => {
+                    debug!("Audio stream ended: {}", e);
+                    break;
+                }
+            }
+        }
+    }
+
+    async fn request_keyframe(connection: &quinn::Connection) {
+        if let Err(e) = control::send_message(connection, &Message::RequestKeyframe).await {
+            warn!("Failed to request keyframe: {}", e);
+        }
+    }
+} 
\ No newline at end of file
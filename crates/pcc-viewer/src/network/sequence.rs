@@ -0,0 +1,118 @@
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use tracing::{debug, warn};
+
+// Received ids remembered for spotting duplicates and reorders
+const HISTORY: usize = 256;
+// Later frames that may arrive before a frame is given up on. Keyframes and
+// updates travel on different streams, so some reordering is normal.
+const REORDER_WINDOW: usize = 8;
+
+/// Lost, reordered and duplicated frames from one host
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SequenceCounts {
+    /// Frames the host sent that never arrived
+    pub missing: u64,
+    /// Frames that arrived after a later one
+    pub reordered: u64,
+    /// Frames that arrived more than once
+    pub duplicates: u64,
+}
+
+/// Frame ids received from one host. Hosts skip unchanged frames, so gaps
+/// in ids are only losses for frames the host says it sent, through their
+/// `FrameTimings`.
+#[derive(Debug, Default)]
+pub struct FrameSequence {
+    highest: Option<u64>,
+    received: VecDeque<u64>,
+    // Sent by the host and not received yet
+    outstanding: Vec<u64>,
+    counts: SequenceCounts,
+}
+
+impl FrameSequence {
+    /// Note that frame `frame_id` arrived
+    pub fn received(&mut self, frame_id: u64) {
+        if self.received.contains(&frame_id) {
+            self.counts.duplicates += 1;
+            warn!("Frame {} received twice", frame_id);
+            return;
+        }
+        match self.highest {
+            Some(highest) if frame_id < highest => {
+                self.counts.reordered += 1;
+                debug!("Frame {} arrived after frame {}", frame_id, highest);
+            }
+            _ => self.highest = Some(frame_id),
+        }
+        if self.received.len() == HISTORY {
+            self.received.pop_front();
+        }
+        self.received.push_back(frame_id);
+        self.outstanding.retain(|&id| id != frame_id);
+        self.give_up();
+    }
+
+    /// Note that the host sent frame `frame_id`
+    pub fn sent(&mut self, frame_id: u64) {
+        if !self.received.contains(&frame_id) && !self.outstanding.contains(&frame_id) {
+            self.outstanding.push(frame_id);
+            self.give_up();
+        }
+    }
+
+    pub fn counts(&self) -> SequenceCounts {
+        self.counts
+    }
+
+    // Count frames as missing once enough later ones have arrived
+    fn give_up(&mut self) {
+        let received = &self.received;
+        let counts = &mut self.counts;
+        self.outstanding.retain(|&id| {
+            let later = received.iter().filter(|&&received| received > id).count();
+            if later < REORDER_WINDOW {
+                return true;
+            }
+            counts.missing += 1;
+            warn!("Frame {} was sent but never arrived", id);
+            false
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_skipped_ids_are_not_missing() {
+        let mut sequence = FrameSequence::default();
+        // The host skipped unchanged frames 1 and 3 and never sent them
+        for id in [0, 2, 4, 5, 6, 7, 8, 9, 10, 11, 12] {
+            sequence.sent(id);
+            sequence.received(id);
+        }
+        assert_eq!(sequence.counts(), SequenceCounts::default());
+    }
+
+    #[test]
+    fn test_counts_missing_reordered_and_duplicate_frames() {
+        let mut sequence = FrameSequence::default();
+        for id in 0..5 {
+            sequence.sent(id);
+        }
+        for id in [0, 2, 1, 2, 4] {
+            sequence.received(id);
+        }
+        // Frame 3 could still be on its way
+        assert_eq!(sequence.counts(), SequenceCounts { missing: 0, reordered: 1, duplicates: 1 });
+
+        for id in 5..5 + REORDER_WINDOW as u64 {
+            sequence.sent(id);
+            sequence.received(id);
+        }
+        assert_eq!(sequence.counts().missing, 1);
+    }
+}
@@ -0,0 +1,128 @@
+use super::{HostCounters, HostStats};
+use pcc_net::input::Permission;
+use pcc_net::network::SessionInfo;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::sync::Mutex;
+
+/// Identifies a host's session with this viewer while it stays connected.
+/// A host that reconnects, even resuming, gets a new one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SessionId(u64);
+
+impl fmt::Display for SessionId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+// One host past the handshake
+struct Session {
+    connection: quinn::Connection,
+    // As negotiated in the handshake
+    info: SessionInfo,
+    // What the viewer may do now; the host may lower it below the
+    // negotiated permission and restore it again
+    permission: Permission,
+    // Whether the host seals its frame payloads
+    encrypted: bool,
+    counters: Arc<HostCounters>,
+    connected_at: Instant,
+}
+
+/// Every connected host's session, by id, for routing what arrives from
+/// them and sending to them
+#[derive(Clone, Default)]
+pub(super) struct SessionManager {
+    next_id: Arc<AtomicU64>,
+    sessions: Arc<Mutex<BTreeMap<SessionId, Session>>>,
+}
+
+impl SessionManager {
+    /// Take on a host that finished the handshake
+    pub async fn register(
+        &self,
+        connection: quinn::Connection,
+        info: SessionInfo,
+        encrypted: bool,
+    ) -> (SessionId, Arc<HostCounters>) {
+        let id = SessionId(self.next_id.fetch_add(1, Ordering::Relaxed) + 1);
+        let counters = Arc::new(HostCounters::default());
+        self.sessions.lock().await.insert(
+            id,
+            Session {
+                connection,
+                info,
+                permission: info.permission,
+                encrypted,
+                counters: counters.clone(),
+                connected_at: Instant::now(),
+            },
+        );
+        (id, counters)
+    }
+
+    pub async fn remove(&self, id: SessionId) {
+        self.sessions.lock().await.remove(&id);
+    }
+
+    /// Apply a permission change from the host, never beyond what was
+    /// negotiated
+    pub async fn set_permission(&self, id: SessionId, permission: Permission) {
+        if let Some(session) = self.sessions.lock().await.get_mut(&id) {
+            session.permission = permission.min(session.info.permission);
+        }
+    }
+
+    pub async fn count(&self) -> usize {
+        self.sessions.lock().await.len()
+    }
+
+    pub async fn connection(&self, id: SessionId) -> Option<quinn::Connection> {
+        self.sessions.lock().await.get(&id).map(|session| session.connection.clone())
+    }
+
+    /// Connections of the hosts that let this viewer do at least `at_least`
+    pub async fn connections(&self, at_least: Permission) -> Vec<quinn::Connection> {
+        self.sessions
+            .lock()
+            .await
+            .values()
+            .filter(|session| session.permission >= at_least)
+            .map(|session| session.connection.clone())
+            .collect()
+    }
+
+    pub async fn stats(&self) -> Vec<HostStats> {
+        self.sessions
+            .lock()
+            .await
+            .iter()
+            .map(|(&id, session)| {
+                let stats = session.connection.stats();
+                let counters = &session.counters;
+                HostStats {
+                    session: id,
+                    addr: session.connection.remote_address(),
+                    encrypted: session.encrypted,
+                    permission: session.permission,
+                    resumed: session.info.resume_from.is_some(),
+                    connected_for: session.connected_at.elapsed(),
+                    rtt: stats.path.rtt,
+                    sent_bytes: stats.udp_tx.bytes,
+                    received_bytes: stats.udp_rx.bytes,
+                    lost_packets: stats.path.lost_packets,
+                    keyframes: counters.keyframes.load(Ordering::Relaxed),
+                    updates: counters.updates.load(Ordering::Relaxed),
+                    sequence: counters.sequence.lock().unwrap().counts(),
+                    quality: *counters.quality.lock().unwrap(),
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,396 @@
+use pcc_net::encoder::FrameEncoder;
+use pcc_core::{Frame, QualityConfig};
+use crate::sink::FrameSink;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    io::{BufWriter, Seek, SeekFrom, Write},
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+// Matroska element IDs
+const EBML: u32 = 0x1A45_DFA3;
+const EBML_VERSION: u32 = 0x4286;
+const EBML_READ_VERSION: u32 = 0x42F7;
+const EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+const EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+const DOC_TYPE: u32 = 0x4282;
+const DOC_TYPE_VERSION: u32 = 0x4287;
+const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+const SEGMENT: u32 = 0x1853_8067;
+const INFO: u32 = 0x1549_A966;
+const TIMECODE_SCALE: u32 = 0x2A_D7B1;
+const DURATION: u32 = 0x4489;
+const MUXING_APP: u32 = 0x4D80;
+const WRITING_APP: u32 = 0x5741;
+const TRACKS: u32 = 0x1654_AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_UID: u32 = 0x73C5;
+const TRACK_TYPE: u32 = 0x83;
+const CODEC_ID: u32 = 0x86;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const CLUSTER: u32 = 0x1F43_B675;
+const TIMECODE: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+const CUES: u32 = 0x1C53_BB6B;
+const CUE_POINT: u32 = 0xBB;
+const CUE_TIME: u32 = 0xB3;
+const CUE_TRACK_POSITIONS: u32 = 0xB7;
+const CUE_TRACK: u32 = 0xF7;
+const CUE_CLUSTER_POSITION: u32 = 0xF1;
+
+// Size marker for elements written before their length is known (live muxing)
+const UNKNOWN_SIZE: [u8; 8] = [0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+// Start a new cluster at least this often; block offsets are 16-bit milliseconds
+const CLUSTER_DURATION: Duration = Duration::from_secs(5);
+const APP_NAME: &str = "pixel-change-check";
+
+/// Recording settings and disk guard rails
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordingConfig {
+    /// Output file; written as Matroska (.mkv) with MJPEG video
+    pub path: PathBuf,
+    /// Encoding quality of recorded frames
+    pub quality: QualityConfig,
+    /// Stop recording if the file would grow beyond this many bytes
+    pub max_file_size: Option<u64>,
+    /// Stop recording if free space on the target disk drops below this
+    pub min_free_space: u64,
+}
+
+impl RecordingConfig {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            quality: QualityConfig::default(),
+            max_file_size: None,
+            min_free_space: 512 * 1024 * 1024,
+        }
+    }
+}
+
+/// What was written once a recording stops
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordingSummary {
+    pub path: PathBuf,
+    pub frames: u64,
+    pub bytes: u64,
+    pub duration: Duration,
+}
+
+/// Writes presented frames to a Matroska file with their capture timing
+pub struct SessionRecorder {
+    config: RecordingConfig,
+    encoder: FrameEncoder,
+    writer: BufWriter<File>,
+    width: u32,
+    height: u32,
+    start: Option<SystemTime>,
+    cluster_start: Option<Duration>,
+    last_timecode: Duration,
+    frames: u64,
+    bytes: u64,
+    // File offsets patched by the trailer once the recording stops
+    segment_size_offset: u64,
+    duration_offset: u64,
+    // Start time and segment-relative offset of each cluster
+    cues: Vec<(Duration, u64)>,
+}
+
+impl SessionRecorder {
+    /// Create the output file and write the stream headers
+    pub fn start(config: RecordingConfig, width: u32, height: u32) -> Result<Self> {
+        check_free_space(&config)?;
+
+        let file = File::create(&config.path)
+            .with_context(|| format!("Failed to create recording {}", config.path.display()))?;
+        let encoder = FrameEncoder::new(width, height, config.quality)?;
+
+        let mut recorder = Self {
+            config,
+            encoder,
+            writer: BufWriter::new(file),
+            width,
+            height,
+            start: None,
+            cluster_start: None,
+            last_timecode: Duration::ZERO,
+            frames: 0,
+            bytes: 0,
+            segment_size_offset: 0,
+            duration_offset: 0,
+            cues: Vec::new(),
+        };
+        recorder.write_headers()?;
+
+        info!("Recording started: {}", recorder.config.path.display());
+        Ok(recorder)
+    }
+
+    /// Encode and append a frame captured at `timestamp`
+    pub async fn write_frame(&mut self, data: &[u8], timestamp: SystemTime) -> Result<()> {
+        let jpeg = self.encoder.encode_frame(data).await?;
+
+        if let Some(max) = self.config.max_file_size {
+            if self.bytes + jpeg.len() as u64 > max {
+                anyhow::bail!("Recording reached its {} byte size limit", max);
+            }
+        }
+        // Checking free space on every frame would be wasteful
+        if self.frames.is_multiple_of(30) {
+            check_free_space(&self.config)?;
+        }
+
+        // Timecodes follow the host's capture clock, never going backwards
+        let start = *self.start.get_or_insert(timestamp);
+        let timecode = timestamp
+            .duration_since(start)
+            .unwrap_or_default()
+            .max(self.last_timecode);
+        self.last_timecode = timecode;
+
+        let cluster_start = match self.cluster_start {
+            Some(cluster) if timecode - cluster < CLUSTER_DURATION => cluster,
+            _ => {
+                let mut cluster = Vec::new();
+                write_id(&mut cluster, CLUSTER);
+                cluster.extend_from_slice(&UNKNOWN_SIZE);
+                write_uint(&mut cluster, TIMECODE, timecode.as_millis() as u64);
+                self.cues.push((timecode, self.bytes - self.segment_start()));
+                self.write(&cluster)?;
+                self.cluster_start = Some(timecode);
+                timecode
+            }
+        };
+
+        let relative = (timecode - cluster_start).as_millis() as i16;
+        let mut block = Vec::with_capacity(jpeg.len() + 4);
+        block.push(0x81); // Track 1
+        block.extend_from_slice(&relative.to_be_bytes());
+        block.push(0x80); // Keyframe
+        block.extend_from_slice(&jpeg);
+
+        let mut element = Vec::with_capacity(block.len() + 12);
+        write_element(&mut element, SIMPLE_BLOCK, &block);
+        self.write(&element)?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Frame size the recording was started with
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// Write the trailer, flush the file and finish the recording
+    pub fn stop(mut self) -> Result<RecordingSummary> {
+        self.write_trailer()?;
+        self.writer.flush().context("Failed to flush recording")?;
+        let summary = RecordingSummary {
+            path: self.config.path.clone(),
+            frames: self.frames,
+            bytes: self.bytes,
+            duration: self.last_timecode,
+        };
+        info!(
+            "Recording stopped: {} ({} frames, {} bytes)",
+            summary.path.display(),
+            summary.frames,
+            summary.bytes
+        );
+        Ok(summary)
+    }
+
+    fn write_headers(&mut self) -> Result<()> {
+        let mut header = Vec::new();
+        write_uint(&mut header, EBML_VERSION, 1);
+        write_uint(&mut header, EBML_READ_VERSION, 1);
+        write_uint(&mut header, EBML_MAX_ID_LENGTH, 4);
+        write_uint(&mut header, EBML_MAX_SIZE_LENGTH, 8);
+        write_element(&mut header, DOC_TYPE, b"matroska");
+        write_uint(&mut header, DOC_TYPE_VERSION, 4);
+        write_uint(&mut header, DOC_TYPE_READ_VERSION, 2);
+
+        let mut info = Vec::new();
+        write_uint(&mut info, TIMECODE_SCALE, 1_000_000); // Millisecond timecodes
+        write_element(&mut info, MUXING_APP, APP_NAME.as_bytes());
+        write_element(&mut info, WRITING_APP, APP_NAME.as_bytes());
+        // Filled in by the trailer
+        write_element(&mut info, DURATION, &0f64.to_be_bytes());
+
+        let mut video = Vec::new();
+        write_uint(&mut video, PIXEL_WIDTH, self.width as u64);
+        write_uint(&mut video, PIXEL_HEIGHT, self.height as u64);
+
+        let mut track = Vec::new();
+        write_uint(&mut track, TRACK_NUMBER, 1);
+        write_uint(&mut track, TRACK_UID, 1);
+        write_uint(&mut track, TRACK_TYPE, 1); // Video
+        write_element(&mut track, CODEC_ID, b"V_MJPEG");
+        write_element(&mut track, VIDEO, &video);
+
+        let mut tracks = Vec::new();
+        write_element(&mut tracks, TRACK_ENTRY, &track);
+
+        let mut out = Vec::new();
+        write_element(&mut out, EBML, &header);
+        write_id(&mut out, SEGMENT);
+        self.segment_size_offset = out.len() as u64;
+        out.extend_from_slice(&UNKNOWN_SIZE);
+        write_element(&mut out, INFO, &info);
+        // Duration is the last element of Info
+        self.duration_offset = out.len() as u64 - 8;
+        write_element(&mut out, TRACKS, &tracks);
+        self.write(&out)
+    }
+
+    // Index the clusters so players can seek, then fill in the sizes and
+    // duration left open while recording live
+    fn write_trailer(&mut self) -> Result<()> {
+        let mut cues = Vec::new();
+        for (timecode, position) in &self.cues {
+            let mut positions = Vec::new();
+            write_uint(&mut positions, CUE_TRACK, 1);
+            write_uint(&mut positions, CUE_CLUSTER_POSITION, *position);
+            let mut point = Vec::new();
+            write_uint(&mut point, CUE_TIME, timecode.as_millis() as u64);
+            write_element(&mut point, CUE_TRACK_POSITIONS, &positions);
+            write_element(&mut cues, CUE_POINT, &point);
+        }
+        let mut element = Vec::new();
+        write_element(&mut element, CUES, &cues);
+        self.write(&element)?;
+
+        let segment_size = self.bytes - self.segment_start();
+        let duration = self.last_timecode.as_secs_f64() * 1000.0;
+        let patch = |writer: &mut BufWriter<File>, offset: u64, bytes: &[u8]| -> std::io::Result<()> {
+            writer.seek(SeekFrom::Start(offset))?;
+            writer.write_all(bytes)
+        };
+        // An 8 byte size in place of the unknown size marker
+        patch(&mut self.writer, self.segment_size_offset, &(segment_size | 1 << 56).to_be_bytes())
+            .and_then(|()| patch(&mut self.writer, self.duration_offset, &duration.to_be_bytes()))
+            .and_then(|()| self.writer.seek(SeekFrom::End(0)).map(|_| ()))
+            .context("Failed to write recording trailer")
+    }
+
+    // Offset of the segment's contents, which cue positions count from
+    fn segment_start(&self) -> u64 {
+        self.segment_size_offset + UNKNOWN_SIZE.len() as u64
+    }
+
+    fn write(&mut self, bytes: &[u8]) -> Result<()> {
+        self.writer
+            .write_all(bytes)
+            .context("Failed to write recording")?;
+        self.bytes += bytes.len() as u64;
+        Ok(())
+    }
+}
+
+/// Records frames handed to it as a `FrameSink`. The recording starts at
+/// the first frame's size and ends on shutdown.
+pub struct RecordingSink {
+    config: RecordingConfig,
+    recorder: Mutex<Option<SessionRecorder>>,
+    finished: Mutex<Option<RecordingSummary>>,
+}
+
+impl RecordingSink {
+    pub fn new(config: RecordingConfig) -> Self {
+        Self {
+            config,
+            recorder: Mutex::new(None),
+            finished: Mutex::new(None),
+        }
+    }
+
+    /// What was written, once the sink has been shut down
+    pub async fn summary(&self) -> Option<RecordingSummary> {
+        self.finished.lock().await.clone()
+    }
+}
+
+#[async_trait]
+impl FrameSink for RecordingSink {
+    async fn present(&self, frame: Frame) -> Result<()> {
+        if self.finished.lock().await.is_some() {
+            anyhow::bail!("Recording already finished");
+        }
+
+        let mut recorder = self.recorder.lock().await;
+        if recorder.is_none() {
+            *recorder = Some(SessionRecorder::start(self.config.clone(), frame.width, frame.height)?);
+        }
+        let recorder = recorder.as_mut().expect("Recorder was started");
+
+        if recorder.dimensions() != (frame.width, frame.height) {
+            anyhow::bail!("Frame size changed to {}x{} mid-recording", frame.width, frame.height);
+        }
+        recorder.write_frame(&frame.data, frame.timestamp).await
+    }
+
+    async fn resize(&self, _width: u32, _height: u32) -> Result<()> {
+        // Recordings keep the source resolution whatever the window does
+        Ok(())
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        if let Some(recorder) = self.recorder.lock().await.take() {
+            *self.finished.lock().await = Some(recorder.stop()?);
+        }
+        Ok(())
+    }
+}
+
+fn check_free_space(config: &RecordingConfig) -> Result<()> {
+    let dir = config
+        .path
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or_else(|| std::path::Path::new("."));
+
+    match fs2::available_space(dir) {
+        Ok(free) if free < config.min_free_space => {
+            anyhow::bail!("Only {} bytes free for recording, need {}", free, config.min_free_space)
+        }
+        Ok(_) => Ok(()),
+        Err(e) => {
+            warn!("Could not check free space for {}: {}", dir.display(), e);
+            Ok(())
+        }
+    }
+}
+
+fn write_id(out: &mut Vec<u8>, id: u32) {
+    let bytes = id.to_be_bytes();
+    let skip = bytes.iter().take_while(|b| **b == 0).count();
+    out.extend_from_slice(&bytes[skip..]);
+}
+
+// EBML variable-length size, using the shortest encoding
+fn write_size(out: &mut Vec<u8>, size: u64) {
+    let len = (1..=8).find(|len| size < (1u64 << (7 * len)) - 1).unwrap_or(8);
+    let marked = size | (1u64 << (7 * len));
+    out.extend_from_slice(&marked.to_be_bytes()[8 - len..]);
+}
+
+fn write_element(out: &mut Vec<u8>, id: u32, data: &[u8]) {
+    write_id(out, id);
+    write_size(out, data.len() as u64);
+    out.extend_from_slice(data);
+}
+
+fn write_uint(out: &mut Vec<u8>, id: u32, value: u64) {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().take_while(|b| **b == 0).count().min(7);
+    write_element(out, id, &bytes[skip..]);
+}
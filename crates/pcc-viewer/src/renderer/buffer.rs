@@ -0,0 +1,570 @@
+use super::jitter::{JitterConfig, JitterEstimator};
+use anyhow::{Context, Result};
+use arc_swap::ArcSwapOption;
+use bytes::Bytes;
+use pcc_core::delta;
+use pcc_core::pool::FramePool;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+use tracing::{debug, warn};
+
+const DEFAULT_CAPACITY: usize = 3; // Frames kept waiting for playout
+const DEFAULT_FRAME_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What to do with a new frame when the buffer is already full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum EvictionPolicy {
+    /// Make room by dropping the oldest queued frame
+    #[default]
+    DropOldest,
+    /// Keep the queue as is and discard the new frame
+    RejectNew,
+}
+
+/// Frames the buffer has thrown away, by reason
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct EvictionStats {
+    /// Queued frames dropped to make room for newer ones
+    pub dropped_oldest: u64,
+    /// New frames discarded because the buffer was full
+    pub rejected: u64,
+    /// Frames that waited longer than the frame timeout
+    pub expired: u64,
+    /// Frames that arrived after a newer frame had already been played
+    pub late: u64,
+    /// Frames received twice
+    pub duplicates: u64,
+}
+
+/// What to do when the viewer falls behind the incoming stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CatchUpPolicy {
+    /// Present every frame in order, however far behind that leaves us
+    PlayAll,
+    /// When more than `max_backlog` frames are due, jump to the newest one
+    /// and drop the rest
+    SkipToNewest { max_backlog: usize },
+}
+
+impl Default for CatchUpPolicy {
+    fn default() -> Self {
+        CatchUpPolicy::SkipToNewest { max_backlog: 1 }
+    }
+}
+
+/// Frame buffer counters, for the stats overlay and metrics export
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+pub struct BufferStats {
+    /// Frames currently waiting for playout
+    pub depth: usize,
+    /// Frames offered to the buffer
+    pub pushed: u64,
+    /// Frames handed out for presentation
+    pub played: u64,
+    /// Frames that arrived too late or expired while queued
+    pub dropped_late: u64,
+    /// Frames dropped or rejected because the buffer was full
+    pub dropped_full: u64,
+    /// Frames skipped by the catch-up policy
+    pub skipped: u64,
+    /// Mean time played frames spent queued
+    pub average_wait: Duration,
+}
+
+/// Frame buffer behaviour
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct FrameBufferConfig {
+    pub jitter: JitterConfig,
+    pub catch_up: CatchUpPolicy,
+    /// Most frames queued for playout
+    pub capacity: usize,
+    /// Frames captured longer ago than this are dropped unplayed
+    pub frame_timeout: Duration,
+    pub eviction: EvictionPolicy,
+    /// How long to hold a frame that follows a gap in frame ids, waiting
+    /// for the missing frame to arrive out of order. Zero plays straight on.
+    pub reorder_window: Duration,
+}
+
+impl Default for FrameBufferConfig {
+    fn default() -> Self {
+        Self {
+            jitter: JitterConfig::default(),
+            catch_up: CatchUpPolicy::default(),
+            capacity: DEFAULT_CAPACITY,
+            frame_timeout: DEFAULT_FRAME_TIMEOUT,
+            eviction: EvictionPolicy::default(),
+            reorder_window: Duration::ZERO,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ScheduledFrame {
+    frame: BufferedFrame,
+    due: Instant,
+    arrived: Instant,
+}
+
+#[derive(Debug)]
+struct Queue {
+    frames: VecDeque<ScheduledFrame>,
+    jitter: JitterEstimator,
+    catch_up: CatchUpPolicy,
+    capacity: usize,
+    frame_timeout: Duration,
+    eviction: EvictionPolicy,
+    reorder_window: Duration,
+    /// Id and capture time of the last frame handed out
+    last_played: Option<(u64, SystemTime)>,
+    skipped: u64,
+    evictions: EvictionStats,
+    pushed: u64,
+    played: u64,
+    total_wait: Duration,
+}
+
+/// Frames waiting for playout, and the frame on screen.
+///
+/// Reading the current frame never waits: it is swapped in whole, so the
+/// renderer, screenshots and the like load whichever frame was last
+/// published while updates build the next one. Only the playout queue,
+/// which reorders, evicts and schedules frames, is behind a lock, and that
+/// is never held across an await.
+#[derive(Debug)]
+pub struct FrameBuffer {
+    queue: Arc<Mutex<Queue>>,
+    current_frame: Arc<ArcSwapOption<BufferedFrame>>,
+    /// Deltas are ignored until the next full frame is played
+    awaiting_keyframe: Arc<AtomicBool>,
+    /// Where played frames go once the next one replaces them
+    pool: FramePool,
+    width: u32,
+    height: u32,
+}
+
+/// A frame held by the `FrameBuffer`, as played out for presentation.
+/// Its pixels are always RGB24; frames in other formats are converted on
+/// the way in.
+///
+/// The pixels are shared, so cloning a frame (the buffer keeps the current
+/// frame while handing it to the renderer) never copies them. Updates
+/// copy them once, into a pooled buffer, leaving frames already handed
+/// out as they were.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BufferedFrame {
+    pub id: u64,
+    /// Capture time on the host
+    pub timestamp: SystemTime,
+    /// RGB24 pixels, row-major
+    pub data: Bytes,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl BufferedFrame {
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    /// How long ago the frame was captured, by the local clock
+    pub fn age(&self) -> Duration {
+        self.timestamp.elapsed().unwrap_or_default()
+    }
+
+    /// The RGB24 pixel at (`x`, `y`), if it lies within the frame
+    pub fn pixel(&self, x: u32, y: u32) -> Option<[u8; 3]> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let i = (y as usize * self.width as usize + x as usize) * 3;
+        self.data.get(i..i + 3).map(|p| [p[0], p[1], p[2]])
+    }
+
+    /// Convert into a `pcc::Frame`, sharing the pixels
+    pub fn into_frame(self) -> pcc_core::Frame {
+        self.into()
+    }
+
+    /// A `pcc::Frame` sharing the pixels
+    pub fn to_frame(&self) -> pcc_core::Frame {
+        self.clone().into()
+    }
+}
+
+// Bytes in a `width`x`height` RGB24 frame, or None if that overflows
+pub(crate) fn rgb24_len(width: u32, height: u32) -> Option<usize> {
+    (width as usize).checked_mul(height as usize)?.checked_mul(3)
+}
+
+impl TryFrom<pcc_core::Frame> for BufferedFrame {
+    type Error = anyhow::Error;
+
+    fn try_from(frame: pcc_core::Frame) -> Result<Self> {
+        let frame = frame.into_rgb24()?;
+        let expected = rgb24_len(frame.width, frame.height)
+            .ok_or_else(|| anyhow::anyhow!("Frame of {}x{} is too large", frame.width, frame.height))?;
+        if frame.data.len() != expected {
+            anyhow::bail!(
+                "Frame {} is {} bytes, expected {} for {}x{} RGB24",
+                frame.id,
+                frame.data.len(),
+                expected,
+                frame.width,
+                frame.height
+            );
+        }
+        Ok(Self {
+            id: frame.id,
+            timestamp: frame.timestamp,
+            data: frame.data,
+            width: frame.width,
+            height: frame.height,
+        })
+    }
+}
+
+impl From<BufferedFrame> for pcc_core::Frame {
+    fn from(frame: BufferedFrame) -> Self {
+        Self {
+            id: frame.id,
+            timestamp: frame.timestamp,
+            width: frame.width,
+            height: frame.height,
+            format: pcc_core::PixelFormat::Rgb24,
+            data: frame.data,
+        }
+    }
+}
+
+impl FrameBuffer {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self::with_config(width, height, FrameBufferConfig::default())
+    }
+
+    pub fn with_config(width: u32, height: u32, config: FrameBufferConfig) -> Self {
+        Self {
+            queue: Arc::new(Mutex::new(Queue {
+                frames: VecDeque::with_capacity(config.capacity),
+                jitter: JitterEstimator::new(config.jitter),
+                catch_up: config.catch_up,
+                capacity: config.capacity.max(1),
+                frame_timeout: config.frame_timeout,
+                eviction: config.eviction,
+                reorder_window: config.reorder_window,
+                last_played: None,
+                skipped: 0,
+                evictions: EvictionStats::default(),
+                pushed: 0,
+                played: 0,
+                total_wait: Duration::ZERO,
+            })),
+            current_frame: Arc::new(ArcSwapOption::empty()),
+            awaiting_keyframe: Arc::new(AtomicBool::new(false)),
+            pool: FramePool::default(),
+            width,
+            height,
+        }
+    }
+
+    /// Give played frames back to `pool` once replaced, e.g. the one
+    /// received frames are decoded into
+    pub fn with_pool(mut self, pool: FramePool) -> Self {
+        self.pool = pool;
+        self
+    }
+
+    pub fn pool(&self) -> &FramePool {
+        &self.pool
+    }
+
+    // Add a new frame to the buffer, in frame id order, converted to RGB24
+    pub async fn push_frame(&self, frame: pcc_core::Frame) -> Result<()> {
+        let frame = BufferedFrame::try_from(frame)?;
+        let mut queue = self.queue.lock().unwrap();
+        queue.pushed += 1;
+
+        // Too late to show, or already queued. A low id captured after the
+        // last played frame means the host restarted its count.
+        if let Some((last_id, last_timestamp)) = queue.last_played {
+            if frame.id <= last_id {
+                if frame.timestamp <= last_timestamp {
+                    queue.evictions.late += 1;
+                    debug!("Dropped late frame {}", frame.id);
+                    return Ok(());
+                }
+                debug!("Frame ids restarted at {}", frame.id);
+                queue.frames.clear();
+                queue.last_played = None;
+            }
+        }
+        if queue.frames.iter().any(|scheduled| scheduled.frame.id == frame.id) {
+            queue.evictions.duplicates += 1;
+            return Ok(());
+        }
+        
+        // Make room, or turn the frame away, if the buffer is full
+        if queue.frames.len() >= queue.capacity {
+            match queue.eviction {
+                EvictionPolicy::DropOldest => {
+                    queue.evictions.dropped_oldest += 1;
+                    // The new frame may itself be the oldest
+                    if queue.frames.front().is_some_and(|s| s.frame.id > frame.id) {
+                        return Ok(());
+                    }
+                    queue.frames.pop_front();
+                }
+                EvictionPolicy::RejectNew => {
+                    queue.evictions.rejected += 1;
+                    debug!("Frame buffer full: rejected frame {}", frame.id);
+                    return Ok(());
+                }
+            }
+        }
+        
+        // Add new frame, scheduled for presentation after the playout delay
+        let due = queue.jitter.schedule(frame.timestamp);
+        let position = queue
+            .frames
+            .iter()
+            .position(|scheduled| scheduled.frame.id > frame.id)
+            .unwrap_or(queue.frames.len());
+        queue.frames.insert(
+            position,
+            ScheduledFrame {
+                frame,
+                due,
+                arrived: Instant::now(),
+            },
+        );
+        
+        Ok(())
+    }
+
+    // Apply a delta update received from the host, making the current frame
+    // represent `update.frame_id`
+    pub async fn apply_frame_update(&self, update: pcc_core::FrameUpdate) -> Result<()> {
+        if self.awaiting_keyframe.load(Ordering::Acquire) {
+            debug!("Ignoring update {} until the next keyframe", update.frame_id);
+            return Ok(());
+        }
+        let updated = self.update_current(|frame, data| {
+            delta::apply_changes(data, frame.width, frame.height, &update.changes)
+                .with_context(|| format!("Update {} doesn't apply to frame {}", update.frame_id, frame.id))?;
+            frame.id = update.frame_id;
+            frame.timestamp = update.timestamp;
+            Ok(())
+        })?;
+        if !updated {
+            anyhow::bail!("No keyframe to apply update {} to", update.frame_id);
+        }
+        Ok(())
+    }
+
+    // Apply frame updates to the current frame
+    pub async fn apply_updates(&self, updates: Vec<pcc_core::PixelChange>) -> Result<()> {
+        let updated = self.update_current(|frame, data| delta::apply_changes(data, frame.width, frame.height, &updates))?;
+        if !updated {
+            warn!("No current frame to update");
+        }
+        Ok(())
+    }
+
+    // Copy a block of the current frame onto itself (CopyRect / scroll)
+    pub async fn copy_rect(&self, src_x: u32, src_y: u32, dst_rect: pcc_core::Rect) -> Result<()> {
+        if self.awaiting_keyframe.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        let updated =
+            self.update_current(|frame, data| delta::copy_rect(data, frame.width, frame.height, src_x, src_y, dst_rect))?;
+        if !updated {
+            warn!("No current frame to copy within");
+        }
+        Ok(())
+    }
+
+    // Publish a changed copy of the current frame, returning false if there
+    // is none. Readers keep whichever frame they loaded; a writer that races
+    // another (a keyframe played meanwhile) starts over from the winner.
+    fn update_current(&self, change: impl Fn(&mut BufferedFrame, &mut [u8]) -> Result<()>) -> Result<bool> {
+        loop {
+            let Some(current) = self.current_frame.load_full() else {
+                return Ok(false);
+            };
+            let mut data = self.pool.take(current.data.len());
+            data.copy_from_slice(&current.data);
+            let mut next = BufferedFrame { data: Bytes::new(), ..(*current).clone() };
+            if let Err(e) = change(&mut next, &mut data) {
+                self.pool.recycle(data.freeze());
+                return Err(e);
+            }
+            next.data = data.freeze();
+
+            let next = Arc::new(next);
+            let previous = self.current_frame.compare_and_swap(&current, Some(next.clone()));
+            let published = previous.as_ref().is_some_and(|previous| Arc::ptr_eq(previous, &current));
+            drop(previous);
+            if published {
+                self.recycle(current);
+                return Ok(true);
+            }
+            self.recycle(next);
+        }
+    }
+
+    // Return a frame's pixels to the pool, unless a reader still has it
+    fn recycle(&self, frame: Arc<BufferedFrame>) {
+        if let Ok(frame) = Arc::try_unwrap(frame) {
+            self.pool.recycle(frame.data);
+        }
+    }
+
+    // Get the next frame for rendering
+    pub async fn next_frame(&self) -> Result<Option<BufferedFrame>> {
+        self.next_frame_until(None).await
+    }
+
+    /// Get the next frame for rendering, holding back frames captured after
+    /// `limit`, e.g. to wait for audio that is running behind
+    pub async fn next_frame_until(&self, limit: Option<SystemTime>) -> Result<Option<BufferedFrame>> {
+        let mut queue = self.queue.lock().unwrap();
+        
+        // Remove expired frames. Timestamps come from the host's clock, so
+        // one in the future is treated as fresh rather than an error.
+        let frame_timeout = queue.frame_timeout;
+        while let Some(scheduled) = queue.frames.front() {
+            if scheduled.frame.timestamp.elapsed().is_ok_and(|age| age > frame_timeout) {
+                queue.frames.pop_front();
+                queue.evictions.expired += 1;
+            } else {
+                break;
+            }
+        }
+        
+        // Hold the next frame until its playout time
+        let now = Instant::now();
+        if queue.frames.front().is_some_and(|scheduled| scheduled.due > now) {
+            return Ok(None);
+        }
+
+        let held = |scheduled: &ScheduledFrame| limit.is_some_and(|limit| scheduled.frame.timestamp > limit);
+        if queue.frames.front().is_some_and(held) {
+            return Ok(None);
+        }
+
+        // Give a missing earlier frame a moment to arrive before playing past it
+        if let (Some((last, _)), Some(front)) = (queue.last_played, queue.frames.front()) {
+            if front.frame.id > last + 1 && front.arrived + queue.reorder_window > now {
+                return Ok(None);
+            }
+        }
+
+        // If we've fallen behind, jump to the newest due frame
+        if let CatchUpPolicy::SkipToNewest { max_backlog } = queue.catch_up {
+            let due = queue.frames.iter().take_while(|s| s.due <= now && !held(s)).count();
+            if due > max_backlog.max(1) {
+                queue.frames.drain(..due - 1);
+                queue.skipped += (due - 1) as u64;
+                debug!("Viewer behind: skipped {} frames to catch up", due - 1);
+            }
+        }
+
+        // Get next frame
+        if let Some(ScheduledFrame { frame, arrived, .. }) = queue.frames.pop_front() {
+            queue.last_played = Some((frame.id, frame.timestamp));
+            queue.played += 1;
+            queue.total_wait += now.duration_since(arrived);
+            if let Some(replaced) = self.current_frame.swap(Some(Arc::new(frame.clone()))) {
+                self.recycle(replaced);
+            }
+            self.awaiting_keyframe.store(false, Ordering::Release);
+            Ok(Some(frame))
+        } else {
+            Ok(None)
+        }
+    }
+
+    // Number of frames waiting to be presented
+    pub async fn depth(&self) -> usize {
+        self.queue.lock().unwrap().frames.len()
+    }
+
+    // Frames dropped by the catch-up policy so far
+    pub async fn skipped_frames(&self) -> u64 {
+        self.queue.lock().unwrap().skipped
+    }
+
+    // Keep the current frame but ignore deltas until the next full frame is
+    // played, e.g. after updates were lost to a dropped connection
+    pub async fn await_keyframe(&self) {
+        self.awaiting_keyframe.store(true, Ordering::Release);
+    }
+
+    pub async fn is_awaiting_keyframe(&self) -> bool {
+        self.awaiting_keyframe.load(Ordering::Acquire)
+    }
+
+    // Snapshot of the buffer's counters
+    pub async fn stats(&self) -> BufferStats {
+        let queue = self.queue.lock().unwrap();
+        let evictions = queue.evictions;
+        BufferStats {
+            depth: queue.frames.len(),
+            pushed: queue.pushed,
+            played: queue.played,
+            dropped_late: evictions.late + evictions.expired,
+            dropped_full: evictions.dropped_oldest + evictions.rejected,
+            skipped: queue.skipped,
+            average_wait: match queue.played {
+                0 => Duration::ZERO,
+                played => queue.total_wait / played as u32,
+            },
+        }
+    }
+
+    // Frames dropped because the buffer was full or they expired
+    pub async fn evictions(&self) -> EvictionStats {
+        self.queue.lock().unwrap().evictions
+    }
+
+    // Current playout delay applied by the jitter buffer
+    pub async fn playout_delay(&self) -> Duration {
+        self.queue.lock().unwrap().jitter.playout_delay()
+    }
+
+    // Smoothed interarrival jitter measured from incoming frames
+    pub async fn jitter(&self) -> Duration {
+        self.queue.lock().unwrap().jitter.jitter()
+    }
+
+    // Get the current frame without advancing. Never waits, even while an
+    // update is being applied.
+    pub async fn current_frame(&self) -> Option<BufferedFrame> {
+        self.current_frame.load_full().map(|frame| (*frame).clone())
+    }
+
+    // Size of the current frame, or the size the buffer was created with
+    // until one plays. Frames may change size mid-stream.
+    pub fn dimensions(&self) -> (u32, u32) {
+        self.current_frame
+            .load()
+            .as_ref()
+            .map_or((self.width, self.height), |frame| frame.dimensions())
+    }
+
+    // Clear the buffer
+    pub async fn clear(&self) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.frames.clear();
+        queue.jitter.reset();
+        queue.last_played = None;
+        self.current_frame.store(None);
+    }
+} 
\ No newline at end of file
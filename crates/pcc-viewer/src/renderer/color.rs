@@ -0,0 +1,122 @@
+use pcc_core::ColorSpace;
+
+// Linear-light conversions between the two gamuts (D65, no adaptation needed)
+const P3_TO_SRGB: [[f32; 3]; 3] = [
+    [1.224_940_1, -0.224_940_4, 0.0],
+    [-0.042_056_9, 1.042_057_1, 0.0],
+    [-0.019_637_6, -0.078_636_1, 1.098_273_5],
+];
+const SRGB_TO_P3: [[f32; 3]; 3] = [
+    [0.822_462_1, 0.177_538, 0.0],
+    [0.033_194_1, 0.966_805_8, 0.0],
+    [0.017_082_7, 0.072_397_4, 0.910_519_9],
+];
+
+// Resolution of the linear-to-encoded lookup table
+const ENCODE_STEPS: usize = 16384;
+
+/// Converts RGB24 pixels between color spaces sharing the sRGB transfer curve
+pub(crate) struct ColorConverter {
+    matrix: [[f32; 3]; 3],
+    decode: [f32; 256],
+    encode: Vec<u8>,
+}
+
+impl ColorConverter {
+    /// Build a converter, or `None` if the spaces already match
+    pub fn new(source: ColorSpace, display: ColorSpace) -> Option<Self> {
+        let matrix = match (source, display) {
+            (ColorSpace::DisplayP3, ColorSpace::Srgb) => P3_TO_SRGB,
+            (ColorSpace::Srgb, ColorSpace::DisplayP3) => SRGB_TO_P3,
+            _ => return None,
+        };
+
+        let mut decode = [0.0; 256];
+        for (value, linear) in decode.iter_mut().enumerate() {
+            *linear = srgb_to_linear(value as f32 / 255.0);
+        }
+        let encode = (0..=ENCODE_STEPS)
+            .map(|step| (linear_to_srgb(step as f32 / ENCODE_STEPS as f32) * 255.0).round() as u8)
+            .collect();
+
+        Some(Self { matrix, decode, encode })
+    }
+
+    /// Convert pixels in place, clipping colors outside the display gamut
+    pub fn convert(&self, pixels: &mut [u8]) {
+        for pixel in pixels.chunks_exact_mut(3) {
+            let linear = [
+                self.decode[pixel[0] as usize],
+                self.decode[pixel[1] as usize],
+                self.decode[pixel[2] as usize],
+            ];
+            for (out, row) in pixel.iter_mut().zip(&self.matrix) {
+                let value = row[0] * linear[0] + row[1] * linear[1] + row[2] * linear[2];
+                *out = self.encode[(value.clamp(0.0, 1.0) * ENCODE_STEPS as f32).round() as usize];
+            }
+        }
+    }
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.040_45 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matching_spaces_need_no_conversion() {
+        assert!(ColorConverter::new(ColorSpace::Srgb, ColorSpace::Srgb).is_none());
+        assert!(ColorConverter::new(ColorSpace::DisplayP3, ColorSpace::DisplayP3).is_none());
+    }
+
+    #[test]
+    fn test_p3_to_srgb() {
+        let converter = ColorConverter::new(ColorSpace::DisplayP3, ColorSpace::Srgb).unwrap();
+
+        // Neutrals share the white point and pass through
+        let mut grays = vec![0, 0, 0, 128, 128, 128, 255, 255, 255];
+        converter.convert(&mut grays);
+        assert_eq!(grays, vec![0, 0, 0, 128, 128, 128, 255, 255, 255]);
+
+        // P3 red is outside sRGB and clips to its most saturated red
+        let mut red = vec![255, 0, 0];
+        converter.convert(&mut red);
+        assert_eq!(red, vec![255, 0, 0]);
+
+        // In-gamut colors come out more saturated in sRGB terms
+        let mut muted = vec![180, 90, 90];
+        converter.convert(&mut muted);
+        assert!(muted[0] > 180 && muted[1] < 90, "{:?}", muted);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let to_p3 = ColorConverter::new(ColorSpace::Srgb, ColorSpace::DisplayP3).unwrap();
+        let to_srgb = ColorConverter::new(ColorSpace::DisplayP3, ColorSpace::Srgb).unwrap();
+
+        let original = vec![200, 40, 90, 60, 160, 220, 90, 90, 30];
+        let mut pixels = original.clone();
+        to_p3.convert(&mut pixels);
+        assert_ne!(pixels, original);
+        to_srgb.convert(&mut pixels);
+
+        for (a, b) in pixels.iter().zip(&original) {
+            assert!(a.abs_diff(*b) <= 1, "{:?} vs {:?}", pixels, original);
+        }
+    }
+}
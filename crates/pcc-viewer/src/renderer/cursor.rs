@@ -0,0 +1,90 @@
+use super::viewport::Viewport;
+use anyhow::Result;
+
+/// Cursor image sent by the host, composited locally by the viewer
+#[derive(Debug, Clone)]
+pub struct CursorImage {
+    pub hotspot: (u32, u32),
+    pub width: u32,
+    pub height: u32,
+    pub rgba: Vec<u8>,
+}
+
+impl CursorImage {
+    pub fn new(hotspot: (u32, u32), width: u32, height: u32, rgba: Vec<u8>) -> Result<Self> {
+        let expected = (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|pixels| pixels.checked_mul(4))
+            .ok_or_else(|| anyhow::anyhow!("Cursor of {}x{} is too large", width, height))?;
+        if rgba.len() != expected {
+            anyhow::bail!(
+                "Cursor data is {} bytes, expected {} for {}x{} RGBA",
+                rgba.len(),
+                expected,
+                width,
+                height
+            );
+        }
+
+        Ok(Self {
+            hotspot,
+            width,
+            height,
+            rgba,
+        })
+    }
+}
+
+/// Local cursor state. Kept separate from frame data so pointer motion is
+/// presented immediately without waiting for the next video frame.
+#[derive(Debug, Clone, Default)]
+pub struct CursorState {
+    pub position: Option<(u32, u32)>,
+    pub image: Option<CursorImage>,
+}
+
+impl CursorState {
+    /// Alpha-blend the cursor into an RGB24 output surface, positioned
+    /// where the frame coordinate it points at lands in `viewport`
+    pub fn composite(&self, output: &mut [u8], width: u32, height: u32, viewport: &Viewport) {
+        let (Some((x, y)), Some(image)) = (self.position, &self.image) else {
+            return;
+        };
+
+        let (x, y) = viewport.frame_to_surface(x, y);
+        let origin_x = x - image.hotspot.0 as i64;
+        let origin_y = y - image.hotspot.1 as i64;
+
+        for cy in 0..image.height {
+            let py = origin_y + cy as i64;
+            if py < 0 || py >= height as i64 {
+                continue;
+            }
+
+            for cx in 0..image.width {
+                let px = origin_x + cx as i64;
+                if px < 0 || px >= width as i64 {
+                    continue;
+                }
+
+                let src = ((cy * image.width + cx) * 4) as usize;
+                let alpha = image.rgba[src + 3] as u32;
+                if alpha == 0 {
+                    continue;
+                }
+
+                let dst = ((py as u32 * width + px as u32) * 3) as usize;
+                if dst + 3 > output.len() {
+                    return;
+                }
+
+                for c in 0..3 {
+                    let blended = (image.rgba[src + c] as u32 * alpha
+                        + output[dst + c] as u32 * (255 - alpha))
+                        / 255;
+                    output[dst + c] = blended as u8;
+                }
+            }
+        }
+    }
+}
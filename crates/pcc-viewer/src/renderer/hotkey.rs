@@ -0,0 +1,130 @@
+use pcc_net::input::{KeyCode, Modifiers};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+/// Viewer actions bound to keys by the windowing backend
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Hotkey {
+    ToggleFullscreen,
+    ToggleStats,
+    /// Freeze or unfreeze presentation
+    TogglePause,
+    /// Leave single view and show every stream tiled
+    ShowMosaic,
+    /// Save the current frame as a PNG in the screenshot directory
+    Screenshot,
+    /// Give the local mouse back after capturing it for relative mode
+    ReleaseMouseCapture,
+}
+
+impl Hotkey {
+    /// Default bindings, by key name as reported by the windowing backend
+    pub fn from_key(name: &str) -> Option<Self> {
+        match name {
+            "F11" => Some(Self::ToggleFullscreen),
+            "F3" => Some(Self::ToggleStats),
+            "Pause" => Some(Self::TogglePause),
+            "Escape" => Some(Self::ShowMosaic),
+            "F12" | "PrintScreen" => Some(Self::Screenshot),
+            _ => None,
+        }
+    }
+}
+
+/// A key pressed with an exact set of modifiers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeyChord {
+    pub key: KeyCode,
+    pub modifiers: Modifiers,
+}
+
+impl KeyChord {
+    /// `key` with Ctrl and Alt held, the prefix the default bindings use
+    pub fn ctrl_alt(key: KeyCode) -> Self {
+        Self {
+            key,
+            modifiers: Modifiers {
+                ctrl: true,
+                alt: true,
+                ..Default::default()
+            },
+        }
+    }
+}
+
+/// Which key chords the viewer keeps for itself while controlling a host.
+/// Everything else is forwarded, so the defaults avoid keys the host's own
+/// apps are likely to want (plain F-keys, Escape).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HotkeyBindings {
+    pub bindings: Vec<(KeyChord, Hotkey)>,
+}
+
+impl Default for HotkeyBindings {
+    fn default() -> Self {
+        let chord = |hid| KeyChord::ctrl_alt(KeyCode(hid));
+        Self {
+            bindings: vec![
+                (chord(0x28), Hotkey::ToggleFullscreen),    // Enter
+                (chord(0x29), Hotkey::ReleaseMouseCapture), // Escape
+                (chord(0x0C), Hotkey::ToggleStats),         // I
+                (chord(0x13), Hotkey::TogglePause),         // P
+                (chord(0x10), Hotkey::ShowMosaic),          // M
+                (chord(0x16), Hotkey::Screenshot),          // S
+            ],
+        }
+    }
+}
+
+impl HotkeyBindings {
+    /// No local hotkeys; every key goes to the host
+    pub fn none() -> Self {
+        Self { bindings: Vec::new() }
+    }
+
+    pub fn lookup(&self, chord: KeyChord) -> Option<Hotkey> {
+        self.bindings
+            .iter()
+            .find(|(bound, _)| *bound == chord)
+            .map(|(_, hotkey)| *hotkey)
+    }
+}
+
+/// Where a key event from the windowing backend should go
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyRoute {
+    /// Send it to the host
+    Forward,
+    /// Run this viewer hotkey instead
+    Local(Hotkey),
+    /// Drop it: the release of a key that ran a hotkey
+    Consumed,
+}
+
+/// Routes key events between viewer hotkeys and the host
+#[derive(Debug, Default)]
+pub(crate) struct HotkeyRouter {
+    pub(crate) bindings: HotkeyBindings,
+    /// Keys pressed as hotkeys, so their release isn't forwarded either
+    local: BTreeSet<KeyCode>,
+}
+
+impl HotkeyRouter {
+    pub(crate) fn route(&mut self, key: KeyCode, pressed: bool, modifiers: Modifiers) -> KeyRoute {
+        if !pressed {
+            return if self.local.remove(&key) {
+                KeyRoute::Consumed
+            } else {
+                KeyRoute::Forward
+            };
+        }
+
+        match self.bindings.lookup(KeyChord { key, modifiers }) {
+            Some(hotkey) => {
+                self.local.insert(key);
+                KeyRoute::Local(hotkey)
+            }
+            None => KeyRoute::Forward,
+        }
+    }
+}
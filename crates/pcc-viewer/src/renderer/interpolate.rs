@@ -0,0 +1,167 @@
+use super::buffer::BufferedFrame;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+// Longest gap between frames that is still blended across; beyond this the
+// stream has stalled and fading would only smear
+const MAX_BLEND_SPAN: Duration = Duration::from_millis(250);
+
+/// How the renderer fills the gaps between frames of a low-fps stream
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Interpolation {
+    /// Present each frame as it arrives
+    #[default]
+    Off,
+    /// Cross-fade from the previous frame to the newest over the interval
+    /// between their capture times. Adds one frame of latency.
+    Blend,
+}
+
+/// Blends between the two most recent frames at render ticks
+#[derive(Debug, Default)]
+pub(crate) struct Interpolator {
+    from: Option<BufferedFrame>,
+    to: Option<BufferedFrame>,
+    started: Option<Instant>,
+    span: Duration,
+    /// Whether the newest frame has been handed out unblended
+    settled_shown: bool,
+}
+
+impl Interpolator {
+    /// Start blending towards a newly played-out frame
+    pub fn push(&mut self, frame: BufferedFrame, now: Instant) {
+        // Continue from wherever the previous blend had reached
+        if let Some(to) = self.to.take() {
+            self.from = Some(to);
+        }
+        self.span = self
+            .from
+            .as_ref()
+            .and_then(|from| frame.timestamp.duration_since(from.timestamp).ok())
+            .unwrap_or_default()
+            .min(MAX_BLEND_SPAN);
+        self.to = Some(frame);
+        self.started = Some(now);
+        self.settled_shown = false;
+    }
+
+    /// The frame to present at this tick, or `None` once the newest frame
+    /// has been shown and nothing is left to blend
+    pub fn next(&mut self, now: Instant) -> Option<BufferedFrame> {
+        if self.settled_shown {
+            return None;
+        }
+        self.settled_shown = self.is_settled(now);
+        self.sample(now)
+    }
+
+    /// Whether the blend has reached the newest frame
+    pub fn is_settled(&self, now: Instant) -> bool {
+        self.progress(now) >= 1.0
+    }
+
+    /// The frame to present at `now`
+    pub fn sample(&self, now: Instant) -> Option<BufferedFrame> {
+        let to = self.to.as_ref()?;
+        let progress = self.progress(now);
+        let from = match &self.from {
+            Some(from) if progress < 1.0 && from.data.len() == to.data.len() => from,
+            _ => return Some(to.clone()),
+        };
+
+        let weight = (progress * 256.0) as u16;
+        let data: Vec<u8> = from
+            .data
+            .iter()
+            .zip(to.data.iter())
+            .map(|(&a, &b)| ((a as u16 * (256 - weight) + b as u16 * weight) >> 8) as u8)
+            .collect();
+
+        // Blends count as the older frame until the newest is fully shown
+        Some(BufferedFrame {
+            id: from.id,
+            timestamp: from.timestamp,
+            data: data.into(),
+            width: to.width,
+            height: to.height,
+        })
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    fn progress(&self, now: Instant) -> f32 {
+        match self.started {
+            Some(started) if !self.span.is_zero() => {
+                (now.duration_since(started).as_secs_f32() / self.span.as_secs_f32()).min(1.0)
+            }
+            _ => 1.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+
+    fn frame(id: u64, timestamp: SystemTime, value: u8) -> BufferedFrame {
+        BufferedFrame {
+            id,
+            timestamp,
+            data: vec![value; 12].into(),
+            width: 2,
+            height: 2,
+        }
+    }
+
+    #[test]
+    fn test_blend_between_frames() {
+        let start = Instant::now();
+        let captured = SystemTime::now();
+        let mut interpolator = Interpolator::default();
+
+        // The first frame has nothing to blend from
+        interpolator.push(frame(1, captured, 0), start);
+        assert!(interpolator.is_settled(start));
+        assert_eq!(interpolator.sample(start).unwrap().data[..], [0; 12]);
+
+        // A 15fps stream: halfway through the interval shows a 50% mix
+        interpolator.push(frame(2, captured + Duration::from_millis(66), 200), start);
+        let halfway = interpolator.sample(start + Duration::from_millis(33)).unwrap();
+        assert_eq!(halfway.id, 1);
+        assert!(halfway.data.iter().all(|&v| (95..=105).contains(&v)), "{:?}", halfway.data);
+
+        let end = start + Duration::from_millis(70);
+        assert!(interpolator.is_settled(end));
+        let settled = interpolator.sample(end).unwrap();
+        assert_eq!(settled.id, 2);
+        assert_eq!(settled.data[..], [200; 12]);
+    }
+
+    #[test]
+    fn test_stalled_stream_is_not_faded() {
+        let start = Instant::now();
+        let captured = SystemTime::now();
+        let mut interpolator = Interpolator::default();
+
+        interpolator.push(frame(1, captured, 0), start);
+        interpolator.push(frame(2, captured + Duration::from_secs(3), 200), start);
+        assert!(interpolator.is_settled(start + MAX_BLEND_SPAN));
+    }
+
+    #[test]
+    fn test_next_stops_once_settled() {
+        let start = Instant::now();
+        let captured = SystemTime::now();
+        let mut interpolator = Interpolator::default();
+
+        interpolator.push(frame(1, captured, 0), start);
+        interpolator.push(frame(2, captured + Duration::from_millis(100), 200), start);
+        assert_eq!(interpolator.next(start).unwrap().id, 1);
+        assert_eq!(interpolator.next(start + Duration::from_millis(100)).unwrap().id, 2);
+        assert!(interpolator.next(start + Duration::from_millis(120)).is_none());
+    }
+}
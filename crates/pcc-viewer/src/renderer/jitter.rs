@@ -0,0 +1,114 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant, SystemTime},
+};
+
+// Jitter multiple the adaptive playout delay covers
+const JITTER_MULTIPLIER: f64 = 3.0;
+// RFC 3550 smoothing factor for the interarrival jitter estimate
+const JITTER_GAIN: f64 = 1.0 / 16.0;
+// Frames over which the fastest transit time is tracked
+const TRANSIT_WINDOW: usize = 120;
+
+/// Playout scheduling for the frame buffer
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct JitterConfig {
+    /// Delay added on top of the fastest observed transit before a frame is
+    /// presented. Zero presents frames as soon as they arrive; ~50ms absorbs
+    /// typical network jitter.
+    pub target_latency: Duration,
+    /// Grow the delay beyond `target_latency` when measured jitter needs it
+    pub adaptive: bool,
+    /// Upper bound on the adaptive delay
+    pub max_latency: Duration,
+}
+
+impl Default for JitterConfig {
+    fn default() -> Self {
+        Self {
+            target_latency: Duration::ZERO,
+            adaptive: false,
+            max_latency: Duration::from_millis(500),
+        }
+    }
+}
+
+/// Estimates network jitter from frame capture timestamps versus arrival
+/// times, and schedules each frame's presentation time from it
+#[derive(Debug)]
+pub(crate) struct JitterEstimator {
+    config: JitterConfig,
+    jitter_secs: f64,
+    last_transit: Option<f64>,
+    transits: VecDeque<f64>,
+}
+
+impl JitterEstimator {
+    pub fn new(config: JitterConfig) -> Self {
+        Self {
+            config,
+            jitter_secs: 0.0,
+            last_transit: None,
+            transits: VecDeque::with_capacity(TRANSIT_WINDOW),
+        }
+    }
+
+    /// Record a frame captured at `timestamp` arriving now, and return when
+    /// it should be presented
+    pub fn schedule(&mut self, timestamp: SystemTime) -> Instant {
+        self.schedule_at(timestamp, Instant::now(), SystemTime::now())
+    }
+
+    /// `schedule` for something that arrived at `arrived`, when the local
+    /// wall clock read `received`
+    pub fn schedule_at(&mut self, timestamp: SystemTime, arrived: Instant, received: SystemTime) -> Instant {
+        // Host and viewer clocks may differ; only transit differences matter
+        let transit = match received.duration_since(timestamp) {
+            Ok(d) => d.as_secs_f64(),
+            Err(e) => -e.duration().as_secs_f64(),
+        };
+
+        if let Some(last) = self.last_transit {
+            self.jitter_secs += ((transit - last).abs() - self.jitter_secs) * JITTER_GAIN;
+        }
+        self.last_transit = Some(transit);
+
+        if self.transits.len() == TRANSIT_WINDOW {
+            self.transits.pop_front();
+        }
+        self.transits.push_back(transit);
+        let fastest = self.transits.iter().copied().fold(f64::INFINITY, f64::min);
+
+        // A frame that was slower than the fastest recent one has already
+        // used up that much of its playout delay
+        let lateness = transit - fastest;
+        let remaining = self.playout_delay().as_secs_f64() - lateness;
+        if remaining > 0.0 {
+            arrived + Duration::from_secs_f64(remaining)
+        } else {
+            arrived
+        }
+    }
+
+    /// Current delay between the fastest possible arrival and presentation
+    pub fn playout_delay(&self) -> Duration {
+        if !self.config.adaptive {
+            return self.config.target_latency;
+        }
+
+        let needed = Duration::from_secs_f64(self.jitter_secs * JITTER_MULTIPLIER);
+        needed
+            .max(self.config.target_latency)
+            .min(self.config.max_latency.max(self.config.target_latency))
+    }
+
+    /// Smoothed interarrival jitter
+    pub fn jitter(&self) -> Duration {
+        Duration::from_secs_f64(self.jitter_secs)
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new(self.config);
+    }
+}
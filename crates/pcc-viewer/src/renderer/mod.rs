@@ -0,0 +1,1222 @@
+mod buffer;
+mod color;
+mod cursor;
+mod hotkey;
+mod interpolate;
+pub(crate) mod jitter;
+mod mosaic;
+mod overlay;
+mod scale;
+mod viewport;
+pub use buffer::{BufferStats, BufferedFrame, CatchUpPolicy, EvictionPolicy, EvictionStats, FrameBuffer, FrameBufferConfig};
+pub use jitter::JitterConfig;
+pub use mosaic::{StreamId, Tile, PRIMARY_STREAM};
+pub use cursor::{CursorImage, CursorState};
+pub use hotkey::{Hotkey, HotkeyBindings, KeyChord, KeyRoute};
+pub use interpolate::Interpolation;
+pub use overlay::OverlayStats;
+pub use scale::ScaleFilter;
+pub use viewport::{RendererOptions, ScaleMode, Viewport, Zoom, MAX_ZOOM};
+
+use pcc_net::audio::AudioSource;
+use pcc_net::input::{KeyCode, Modifiers};
+use pcc_net::network::Message;
+use crate::audio::{AudioPlayback, AudioReceiver, SYNC_TOLERANCE};
+use crate::sink::FrameSink;
+use pcc_core::{pool::FramePool, ColorSpace};
+use crate::recorder::{RecordingConfig, RecordingSummary, SessionRecorder};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::{
+    sync::{watch, Mutex},
+    time,
+};
+use tracing::{debug, debug_span, error, info, instrument, warn, Instrument};
+
+/// The viewer's display surface
+#[derive(Debug)]
+struct Surface {
+    width: u32,
+    height: u32,
+    options: RendererOptions,
+    /// Color space of incoming frames, as announced by the host
+    source_color_space: ColorSpace,
+    zoom: Zoom,
+    /// The current rendered output (RGB24)
+    pixels: Vec<u8>,
+}
+
+/// What happens to incoming updates while the viewer is paused
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum PauseMode {
+    /// Keep applying updates to the back buffer, so resuming is instant
+    #[default]
+    ApplyUpdates,
+    /// Discard delta updates; resuming then needs a keyframe from the host
+    DropUpdates,
+}
+
+#[derive(Debug)]
+struct Pause {
+    mode: PauseMode,
+    /// The frame left on screen
+    frozen: Option<buffer::BufferedFrame>,
+    dropped_updates: bool,
+}
+
+impl Surface {
+    // Unzoomed layout of a `frame` sized image under the current scale mode
+    fn base_viewport(&self, frame: (u32, u32)) -> Viewport {
+        Viewport::compute(self.options.scale_mode, (self.width, self.height), frame)
+    }
+
+    fn viewport(&self, frame: (u32, u32)) -> Viewport {
+        self.base_viewport(frame).zoomed(self.zoom, (self.width, self.height))
+    }
+}
+
+/// Called with `true` when the host connection drops and `false` once it's back
+pub type ReconnectCallback = Box<dyn Fn(bool) + Send + Sync>;
+
+pub struct Renderer {
+    pub buffer: Arc<FrameBuffer>,
+    fps: u32,
+    frame_interval: Duration,
+    surface: Arc<Mutex<Surface>>,
+    /// Locally composited cursor, drawn over every presented frame
+    cursor: Arc<Mutex<CursorState>>,
+    overlay: Arc<Mutex<overlay::StatsOverlay>>,
+    interpolator: Arc<Mutex<interpolate::Interpolator>>,
+    pause: Arc<Mutex<Option<Pause>>>,
+    reconnecting: Arc<Mutex<bool>>,
+    on_reconnecting: Arc<Mutex<Option<ReconnectCallback>>>,
+    /// Audio kept in sync with the presented frames, per host audio source
+    audio: Arc<Mutex<HashMap<AudioSource, AudioPlayback>>>,
+    /// Decode the host's audio streams into `audio`
+    audio_receivers: Arc<Mutex<HashMap<AudioSource, AudioReceiver>>>,
+    /// Additional sessions shown alongside `buffer`
+    mosaic: Arc<Mutex<mosaic::Mosaic>>,
+    recording: Arc<Mutex<Option<Recording>>>,
+    /// Whether `recording` is active, for telling hosts
+    recording_state: Arc<watch::Sender<bool>>,
+    /// Where hotkey screenshots are saved
+    screenshot_dir: Arc<Mutex<PathBuf>>,
+    hotkeys: Arc<Mutex<hotkey::HotkeyRouter>>,
+    /// Whether the windowing backend has the local mouse captured
+    mouse_captured: Arc<Mutex<bool>>,
+    /// Set by `shutdown` to end the render loop
+    stop: watch::Sender<bool>,
+}
+
+/// An active recording and the id of the last frame written to it
+struct Recording {
+    recorder: SessionRecorder,
+    last_frame: Option<u64>,
+}
+
+impl Renderer {
+    pub async fn new(width: u32, height: u32, fps: u32) -> Result<Self> {
+        Self::with_options(width, height, fps, RendererOptions::default()).await
+    }
+
+    pub async fn with_options(width: u32, height: u32, fps: u32, options: RendererOptions) -> Result<Self> {
+        let buffer = Arc::new(FrameBuffer::new(width, height));
+        let frame_size = (width * height * 3) as usize;
+
+        info!(
+            "Renderer initialized: {}x{} @ {}fps",
+            width, height, fps
+        );
+
+        Ok(Self {
+            interpolator: Arc::new(Mutex::new(interpolate::Interpolator::default())),
+            pause: Arc::new(Mutex::new(None)),
+            reconnecting: Arc::new(Mutex::new(false)),
+            on_reconnecting: Arc::new(Mutex::new(None)),
+            audio: Arc::new(Mutex::new(HashMap::new())),
+            audio_receivers: Arc::new(Mutex::new(HashMap::new())),
+            mosaic: Arc::new(Mutex::new(mosaic::Mosaic::new(buffer.clone()))),
+            buffer,
+            fps,
+            frame_interval: Duration::from_secs(1) / fps,
+            surface: Arc::new(Mutex::new(Surface {
+                width,
+                height,
+                options,
+                source_color_space: ColorSpace::default(),
+                zoom: Zoom::default(),
+                pixels: vec![0u8; frame_size],
+            })),
+            cursor: Arc::new(Mutex::new(CursorState::default())),
+            overlay: Arc::new(Mutex::new(overlay::StatsOverlay::default())),
+            recording: Arc::new(Mutex::new(None)),
+            recording_state: Arc::new(watch::Sender::new(false)),
+            screenshot_dir: Arc::new(Mutex::new(PathBuf::from("."))),
+            hotkeys: Arc::new(Mutex::new(hotkey::HotkeyRouter::default())),
+            mouse_captured: Arc::new(Mutex::new(false)),
+            stop: watch::Sender::new(false),
+        })
+    }
+
+    /// Start the render loop. Continuously pulls frames from the buffer
+    /// and updates the current output, until `shutdown`.
+    pub async fn start(&self) -> Result<()> {
+        info!("Starting renderer at {} fps", self.fps);
+
+        let mut interval = time::interval(self.frame_interval);
+        let mut stop = self.stop.subscribe();
+
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = stop.wait_for(|stop| *stop) => {
+                    debug!("Render loop stopped");
+                    return Ok(());
+                }
+            }
+
+            let (tiled, single, buffers) = {
+                let mosaic = self.mosaic.lock().await;
+                (mosaic.is_tiled(), mosaic.single(), mosaic.buffers())
+            };
+
+            // Every stream keeps playing out, whichever is on screen. The
+            // primary stream's video waits for its audio if that is behind.
+            self.poll_audio().await;
+            let audio_limit = self.audio_limit().await;
+            let mut tiled_changed = false;
+            let mut single_frame = None;
+            for buffer in buffers {
+                let limit = if Arc::ptr_eq(&buffer, &self.buffer) { audio_limit } else { None };
+                let Some(frame) = buffer.next_frame_until(limit).await? else {
+                    continue;
+                };
+                if tiled {
+                    tiled_changed = true;
+                } else if Arc::ptr_eq(&buffer, &single) {
+                    single_frame = Some(frame);
+                }
+            }
+
+            // Paused: frames above still advance the back buffer, unseen
+            if self.is_paused().await {
+                continue;
+            }
+
+            if tiled_changed {
+                if let Err(e) = self.render_mosaic().await {
+                    error!("Failed to render mosaic: {}", e);
+                }
+            } else if !tiled {
+                if let Err(e) = self.present_single(single_frame).await {
+                    error!("Failed to render frame: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Present a newly played-out frame in single view, blending towards it
+    /// over the following ticks when interpolation is on
+    async fn present_single(&self, frame: Option<buffer::BufferedFrame>) -> Result<()> {
+        let interpolation = self.surface.lock().await.options.interpolation;
+        let frame = match interpolation {
+            Interpolation::Off => frame,
+            Interpolation::Blend => {
+                let now = Instant::now();
+                let mut interpolator = self.interpolator.lock().await;
+                if let Some(frame) = frame {
+                    interpolator.push(frame, now);
+                }
+                interpolator.next(now)
+            }
+        };
+
+        match frame {
+            Some(frame) => self.render_frame(&frame).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Draw every stream's current frame into its tile
+    async fn render_mosaic(&self) -> Result<()> {
+        let (tiles, buffers) = {
+            let surface = self.surface.lock().await;
+            let mosaic = self.mosaic.lock().await;
+            let tiles = mosaic.layout(surface.width, surface.height);
+            let buffers: Vec<_> = tiles.iter().filter_map(|tile| mosaic.get(tile.stream)).collect();
+            (tiles, buffers)
+        };
+
+        let mut frames = Vec::with_capacity(buffers.len());
+        for buffer in &buffers {
+            frames.push(buffer.current_frame().await);
+        }
+
+        let mut surface = self.surface.lock().await;
+        let Surface { width, height, options, source_color_space, pixels, .. } = &mut *surface;
+        pixels.fill(0);
+
+        for (tile, frame) in tiles.iter().zip(frames) {
+            let Some(frame) = frame else { continue };
+            // Host-declared sizes; skip a tile whose size overflows
+            match buffer::rgb24_len(frame.width, frame.height) {
+                Some(len) if frame.data.len() >= len => {}
+                _ => continue,
+            }
+            let mut viewport = Viewport::compute(
+                ScaleMode::Fit,
+                (tile.width, tile.height),
+                (frame.width, frame.height),
+            );
+            viewport.x += tile.x as i64;
+            viewport.y += tile.y as i64;
+            scale::draw(&frame.data, pixels, *width, *height, &viewport, options.scale_filter);
+        }
+        if let Some(converter) = color::ColorConverter::new(*source_color_space, options.display_color_space) {
+            converter.convert(pixels);
+        }
+
+        debug!("Rendered mosaic of {} streams", tiles.len());
+        Ok(())
+    }
+
+    /// Add another incoming session to the mosaic, returning its frame buffer
+    pub async fn add_stream(&self, id: StreamId, width: u32, height: u32) -> Result<Arc<FrameBuffer>> {
+        let buffer = Arc::new(FrameBuffer::new(width, height).with_pool(self.frame_pool()));
+        self.mosaic.lock().await.add(id, buffer.clone())?;
+        self.present_current().await?;
+        Ok(buffer)
+    }
+
+    /// Drop a session from the mosaic
+    pub async fn remove_stream(&self, id: StreamId) -> Result<()> {
+        self.mosaic.lock().await.remove(id)?;
+        self.present_current().await
+    }
+
+    /// Get the frame buffer of a stream
+    pub async fn stream(&self, id: StreamId) -> Option<Arc<FrameBuffer>> {
+        self.mosaic.lock().await.get(id)
+    }
+
+    /// Show one stream on its own, or `None` to return to the tiled view
+    pub async fn focus_stream(&self, id: Option<StreamId>) -> Result<()> {
+        self.mosaic.lock().await.set_focus(id)?;
+        // Never blend across streams
+        self.interpolator.lock().await.reset();
+        self.present_current().await
+    }
+
+    /// The stream shown in single view, if one was focused
+    pub async fn focused_stream(&self) -> Option<StreamId> {
+        self.mosaic.lock().await.focus()
+    }
+
+    /// Tiles of the mosaic on the current surface
+    pub async fn mosaic_layout(&self) -> Vec<Tile> {
+        let (width, height) = self.surface_size().await;
+        self.mosaic.lock().await.layout(width, height)
+    }
+
+    /// Handle a click on the surface: in the tiled view, focus the stream
+    /// under the pointer. Returns the newly focused stream.
+    pub async fn click(&self, x: u32, y: u32) -> Result<Option<StreamId>> {
+        if !self.mosaic.lock().await.is_tiled() {
+            return Ok(None);
+        }
+        let Some(tile) = self.mosaic_layout().await.into_iter().find(|tile| tile.contains(x, y)) else {
+            return Ok(None);
+        };
+        self.focus_stream(Some(tile.stream)).await?;
+        Ok(Some(tile.stream))
+    }
+
+    /// Render a buffered frame into the current output.
+    ///
+    /// Frames are presented as raw RGB24 straight into the display surface;
+    /// nothing on the display path re-encodes them.
+    #[instrument(level = "debug", name = "present", skip_all, fields(frame_id = frame.id))]
+    async fn render_frame(&self, frame: &buffer::BufferedFrame) -> Result<()> {
+        let start = Instant::now();
+        let mut surface = self.surface.lock().await;
+        let surface_size = (surface.width, surface.height);
+        let viewport = surface.viewport((frame.width, frame.height));
+
+        let Surface { width, height, options, source_color_space, pixels, .. } = &mut *surface;
+        let expected_size = (frame.width * frame.height * 3) as usize;
+        if viewport.is_identity(surface_size) {
+            // Same size as the surface: copy straight through
+            let copy_len = frame.data.len().min(pixels.len());
+            pixels[..copy_len].copy_from_slice(&frame.data[..copy_len]);
+        } else if frame.data.len() >= expected_size {
+            scale::blit(&frame.data, pixels, *width, *height, &viewport, options.scale_filter);
+        } else {
+            anyhow::bail!(
+                "Frame {} has {} bytes, expected {} for {}x{}",
+                frame.id,
+                frame.data.len(),
+                expected_size,
+                frame.width,
+                frame.height
+            );
+        }
+
+        if let Some(converter) = color::ColorConverter::new(*source_color_space, options.display_color_space) {
+            converter.convert(pixels);
+        }
+
+        // The local cursor belongs to the primary session
+        if self.mosaic.lock().await.focus().is_none_or(|id| id == PRIMARY_STREAM) {
+            self.cursor
+                .lock()
+                .await
+                .composite(pixels, *width, *height, &viewport);
+        }
+
+        let mut overlay = self.overlay.lock().await;
+        overlay.record_present(frame.id, frame.timestamp, start.elapsed(), self.buffer.stats().await);
+        if options.show_stats {
+            overlay.draw(pixels, *width, *height);
+        }
+
+        drop(overlay);
+        drop(surface);
+        for audio in self.audio.lock().await.values() {
+            audio.video_presented(frame.timestamp);
+        }
+        self.record_frame(frame).await;
+
+        debug!("Rendered frame {}: {}x{}", frame.id, frame.width, frame.height);
+        Ok(())
+    }
+
+    // Play out audio packets that came due since they arrived
+    async fn poll_audio(&self) {
+        for (source, receiver) in self.audio_receivers.lock().await.iter_mut() {
+            if let Err(e) = receiver.poll() {
+                warn!("Failed to play {:?} audio: {}", source, e);
+            }
+        }
+    }
+
+    // Latest capture time the video may show without running ahead of the
+    // audio: the furthest-behind playing source, plus the tolerance
+    async fn audio_limit(&self) -> Option<SystemTime> {
+        let audio = self.audio.lock().await;
+        let playhead = audio.values().filter_map(|playback| playback.playhead()).min()?;
+        Some(playhead + SYNC_TOLERANCE)
+    }
+
+    /// Slave an audio playback to the presented video and play the host's
+    /// system audio through it
+    pub async fn attach_audio(&self, playback: AudioPlayback) {
+        self.attach_audio_source(AudioSource::System, playback).await
+    }
+
+    /// Play one of the host's audio sources, e.g. its microphone, through
+    /// `playback`. Each source gets its own playback, so they can be muted
+    /// separately and the output device mixes them.
+    pub async fn attach_audio_source(&self, source: AudioSource, playback: AudioPlayback) {
+        self.overlay.lock().await.record_audio(source, playback.stream_state());
+        self.audio.lock().await.insert(source, playback);
+    }
+
+    /// Mute or unmute one of the host's audio sources on this viewer. Send
+    /// the host `Message::AudioMute` too to stop it sending the source.
+    pub async fn set_audio_muted(&self, source: AudioSource, muted: bool) {
+        if let Some(playback) = self.audio.lock().await.get(&source) {
+            playback.set_muted(muted);
+            self.overlay.lock().await.record_audio(source, playback.stream_state());
+        }
+    }
+
+    /// Set the playback gain of one of the host's audio sources. Send the
+    /// host `Message::AudioGain` too so its controls stay in step.
+    pub async fn set_audio_gain(&self, source: AudioSource, gain: f32) {
+        if let Some(playback) = self.audio.lock().await.get(&source) {
+            playback.set_gain(gain);
+            self.overlay.lock().await.record_audio(source, playback.stream_state());
+        }
+    }
+
+    // Decode the host's new audio stream into its playback, if one is attached
+    async fn start_audio_stream(&self, source: AudioSource) -> Result<()> {
+        let Some(playback) = self.audio.lock().await.get(&source).cloned() else {
+            return Ok(());
+        };
+        let decoder = pcc_net::audio::opus_decoder(playback.format())?;
+        self.audio_receivers
+            .lock()
+            .await
+            .insert(source, AudioReceiver::new(decoder, playback));
+        Ok(())
+    }
+
+    /// Start recording presented frames at their source resolution
+    pub async fn start_recording(&self, config: RecordingConfig) -> Result<()> {
+        let mut recording = self.recording.lock().await;
+        if recording.is_some() {
+            anyhow::bail!("Already recording");
+        }
+
+        let (width, height) = match self.buffer.current_frame().await {
+            Some(frame) => (frame.width, frame.height),
+            None => self.surface_size().await,
+        };
+        *recording = Some(Recording {
+            recorder: SessionRecorder::start(config, width, height)?,
+            last_frame: None,
+        });
+        self.recording_state.send_replace(true);
+        Ok(())
+    }
+
+    /// Stop the active recording, if any
+    pub async fn stop_recording(&self) -> Result<Option<RecordingSummary>> {
+        match self.recording.lock().await.take() {
+            Some(recording) => {
+                self.recording_state.send_replace(false);
+                recording.recorder.stop().map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub async fn is_recording(&self) -> bool {
+        self.recording.lock().await.is_some()
+    }
+
+    /// Whether a recording is active, changing as recordings start and stop
+    pub fn watch_recording(&self) -> watch::Receiver<bool> {
+        self.recording_state.subscribe()
+    }
+
+    // Append a newly presented frame to the recording. Re-presents of the
+    // same frame (cursor moves, overlay redraws) are not recorded again.
+    async fn record_frame(&self, frame: &buffer::BufferedFrame) {
+        let mut recording = self.recording.lock().await;
+        let Some(Recording { recorder, last_frame }) = recording.as_mut() else {
+            return;
+        };
+        if *last_frame == Some(frame.id) {
+            return;
+        }
+
+        let result = if recorder.dimensions() != (frame.width, frame.height) {
+            Err(anyhow::anyhow!(
+                "Frame size changed to {}x{}",
+                frame.width,
+                frame.height
+            ))
+        } else {
+            recorder.write_frame(&frame.data, frame.timestamp).await
+        };
+
+        match result {
+            Ok(()) => *last_frame = Some(frame.id),
+            Err(e) => {
+                // Guard rails tripped or the disk failed: keep what was written
+                warn!("Recording stopped: {}", e);
+                if let Some(recording) = recording.take() {
+                    self.recording_state.send_replace(false);
+                    if let Err(e) = recording.recorder.stop() {
+                        error!("Failed to finish recording: {}", e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Apply a display-side protocol message to the viewer state
+    pub async fn handle_message(&self, message: Message) -> Result<()> {
+        self.handle_stream_message(PRIMARY_STREAM, message).await
+    }
+
+    /// Apply a protocol message from one of the mosaic's sessions. Cursor
+    /// messages only apply to the primary stream.
+    pub async fn handle_stream_message(&self, stream: StreamId, message: Message) -> Result<()> {
+        let buffer = self
+            .stream(stream)
+            .await
+            .ok_or_else(|| anyhow::anyhow!("Unknown stream {}", stream))?;
+
+        match message {
+            Message::FrameUpdate { .. } | Message::CopyRect { .. } if self.drop_while_paused().await => Ok(()),
+            Message::FrameUpdate { update, part, parts } => {
+                if stream == PRIMARY_STREAM && part + 1 >= parts {
+                    self.record_arrival(update.frame_id, update.timestamp).await;
+                }
+                let span = debug_span!("apply", frame_id = update.frame_id, part);
+                buffer.apply_frame_update(update).instrument(span).await?;
+                // Present once per frame rather than once per part
+                if part + 1 >= parts {
+                    self.present_current().await?;
+                }
+                Ok(())
+            }
+            Message::CopyRect { src_x, src_y, dst_rect } => {
+                buffer.copy_rect(src_x, src_y, dst_rect).await?;
+                self.present_current().await
+            }
+            _ if stream != PRIMARY_STREAM => Ok(()),
+            Message::ColorSpace(color_space) => self.set_source_color_space(color_space).await,
+            Message::FrameTimings(timings) => {
+                self.overlay.lock().await.record_host_timings(timings);
+                Ok(())
+            }
+            Message::AudioStreamStart { source, .. } => self.start_audio_stream(source).await,
+            Message::AudioPacket { source, sequence, pts, data } => {
+                match self.audio_receivers.lock().await.get_mut(&source) {
+                    Some(receiver) => receiver.receive(sequence, pts, &data),
+                    None => Ok(()),
+                }
+            }
+            // The host changed a source's mute or gain
+            Message::AudioMute { source, muted } => {
+                self.set_audio_muted(source, muted).await;
+                Ok(())
+            }
+            Message::AudioGain { source, gain } => {
+                self.set_audio_gain(source, gain).await;
+                Ok(())
+            }
+            Message::CursorMoved { x, y } => self.set_cursor_position(x, y).await,
+            Message::CursorShape { hotspot, width, height, rgba } => {
+                let image = CursorImage::new(hotspot, width, height, rgba)?;
+                self.set_cursor_image(image).await
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Move the local cursor and re-present the current frame under it
+    pub async fn set_cursor_position(&self, x: u32, y: u32) -> Result<()> {
+        self.cursor.lock().await.position = Some((x, y));
+        self.present_current().await
+    }
+
+    /// Replace the local cursor image
+    pub async fn set_cursor_image(&self, image: CursorImage) -> Result<()> {
+        self.cursor.lock().await.image = Some(image);
+        self.present_current().await
+    }
+
+    /// Freeze presentation on the current frame. Incoming frames keep
+    /// advancing the back buffer; delta updates are applied or discarded
+    /// according to `mode`.
+    pub async fn pause(&self, mode: PauseMode) {
+        let (tiled, single) = {
+            let mosaic = self.mosaic.lock().await;
+            (mosaic.is_tiled(), mosaic.single())
+        };
+        let frozen = if tiled { None } else { single.current_frame().await };
+
+        let mut pause = self.pause.lock().await;
+        match pause.as_mut() {
+            Some(pause) => pause.mode = mode,
+            None => {
+                *pause = Some(Pause {
+                    mode,
+                    frozen,
+                    dropped_updates: false,
+                });
+                info!("Viewer paused");
+            }
+        }
+    }
+
+    /// Resume presenting, snapping straight to the live frame. Returns
+    /// whether updates were dropped while paused, in which case the caller
+    /// should ask the host for a keyframe.
+    pub async fn resume(&self) -> Result<bool> {
+        let Some(pause) = self.pause.lock().await.take() else {
+            return Ok(false);
+        };
+        info!("Viewer resumed");
+
+        // Blending from the frozen frame would only delay going live
+        self.interpolator.lock().await.reset();
+        self.present_current().await?;
+        Ok(pause.dropped_updates)
+    }
+
+    pub async fn is_paused(&self) -> bool {
+        self.pause.lock().await.is_some()
+    }
+
+    // Whether a delta update should be discarded, noting it if so
+    async fn drop_while_paused(&self) -> bool {
+        match self.pause.lock().await.as_mut() {
+            Some(pause) if pause.mode == PauseMode::DropUpdates => {
+                pause.dropped_updates = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Register the "reconnecting" indicator, replacing any previous one
+    pub async fn on_reconnecting(&self, callback: impl Fn(bool) + Send + Sync + 'static) {
+        *self.on_reconnecting.lock().await = Some(Box::new(callback));
+    }
+
+    /// Note that the host connection dropped or came back. The last frame
+    /// stays on screen meanwhile; once reconnected, deltas are only applied
+    /// again from the next keyframe.
+    pub async fn set_reconnecting(&self, reconnecting: bool) {
+        {
+            let mut state = self.reconnecting.lock().await;
+            if *state == reconnecting {
+                return;
+            }
+            *state = reconnecting;
+        }
+
+        if reconnecting {
+            info!("Host connection lost, holding the last frame");
+            self.buffer.await_keyframe().await;
+        }
+        if let Some(callback) = self.on_reconnecting.lock().await.as_ref() {
+            callback(reconnecting);
+        }
+    }
+
+    pub async fn is_reconnecting(&self) -> bool {
+        *self.reconnecting.lock().await
+    }
+
+    /// Set the color space incoming frames are in, re-presenting the current
+    /// frame converted for the display
+    pub async fn set_source_color_space(&self, color_space: ColorSpace) -> Result<()> {
+        self.surface.lock().await.source_color_space = color_space;
+        self.present_current().await
+    }
+
+    /// Re-render the buffer's current frame, e.g. after a cursor or in-place update
+    async fn present_current(&self) -> Result<()> {
+        if let Some(pause) = self.pause.lock().await.as_ref() {
+            // Keep showing the frozen frame (a tiled mosaic simply stays put)
+            return match pause.frozen.clone() {
+                Some(frozen) => self.render_frame(&frozen).await,
+                None => Ok(()),
+            };
+        }
+
+        let (tiled, single) = {
+            let mosaic = self.mosaic.lock().await;
+            (mosaic.is_tiled(), mosaic.single())
+        };
+        if tiled {
+            return self.render_mosaic().await;
+        }
+
+        match single.current_frame().await {
+            Some(frame) => self.render_frame(&frame).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Get the size of the display surface
+    pub async fn surface_size(&self) -> (u32, u32) {
+        let surface = self.surface.lock().await;
+        (surface.width, surface.height)
+    }
+
+    /// Resize the display surface (e.g. after the window was resized) and
+    /// re-present the current frame into it
+    pub async fn resize(&self, width: u32, height: u32) -> Result<()> {
+        {
+            let mut surface = self.surface.lock().await;
+            surface.width = width;
+            surface.height = height;
+            surface.pixels = vec![0u8; (width * height * 3) as usize];
+        }
+        self.present_current().await
+    }
+
+    /// Get the current display options
+    pub async fn options(&self) -> RendererOptions {
+        self.surface.lock().await.options
+    }
+
+    /// Replace the display options and re-present the current frame
+    pub async fn set_options(&self, options: RendererOptions) -> Result<()> {
+        self.surface.lock().await.options = options;
+        self.present_current().await
+    }
+
+    /// Toggle the fullscreen flag, returning the new state.
+    ///
+    /// This only records the request in the options and the surface keeps
+    /// its size. An embedder's window reads the flag and calls `resize`
+    pub async fn toggle_fullscreen(&self) -> bool {
+        let mut surface = self.surface.lock().await;
+        surface.options.fullscreen = !surface.options.fullscreen;
+        surface.options.fullscreen
+    }
+
+    /// Toggle the stats overlay, returning whether it is now shown
+    pub async fn toggle_stats_overlay(&self) -> Result<bool> {
+        let shown = {
+            let mut surface = self.surface.lock().await;
+            surface.options.show_stats = !surface.options.show_stats;
+            surface.options.show_stats
+        };
+        self.present_current().await?;
+        Ok(shown)
+    }
+
+    /// Feed network figures for the stats overlay
+    pub async fn record_network_stats(&self, bitrate_bps: u64, loss: f32) {
+        self.overlay.lock().await.record_network(bitrate_bps, loss);
+    }
+
+    /// Note that frame `frame_id`, captured at `timestamp`, arrived from the
+    /// primary host, for the latency breakdown
+    pub async fn record_arrival(&self, frame_id: u64, timestamp: SystemTime) {
+        self.overlay.lock().await.record_arrival(frame_id, timestamp);
+    }
+
+    /// Recycles frame buffers once presented; decode received frames into
+    /// it, e.g. with `ServerNetwork::with_frame_pool`
+    pub fn frame_pool(&self) -> FramePool {
+        self.buffer.pool().clone()
+    }
+
+    /// Get the statistics shown on the stats overlay
+    pub async fn overlay_stats(&self) -> OverlayStats {
+        self.overlay.lock().await.stats()
+    }
+
+    /// Where the current frame sits on the display surface
+    pub async fn viewport(&self) -> Option<Viewport> {
+        let frame = self.buffer.current_frame().await?;
+        Some(self.surface.lock().await.viewport((frame.width, frame.height)))
+    }
+
+    /// Map a surface point (e.g. a mouse position) to the remote frame
+    /// pixel under it, accounting for scaling, zoom and pan
+    pub async fn surface_to_frame(&self, x: i64, y: i64) -> Option<(u32, u32)> {
+        self.viewport().await?.surface_to_frame(x, y)
+    }
+
+    pub async fn zoom(&self) -> Zoom {
+        self.surface.lock().await.zoom
+    }
+
+    /// Set zoom and pan directly; the pan is clamped to keep the frame visible
+    pub async fn set_zoom(&self, zoom: Zoom) -> Result<()> {
+        self.update_zoom(|_, _| zoom).await
+    }
+
+    /// Zoom by `scale` about surface point (`x`, `y`), keeping the remote
+    /// pixel under it in place
+    pub async fn zoom_at(&self, x: i64, y: i64, scale: f64) -> Result<()> {
+        self.update_zoom(|zoom, (base, surface)| {
+            zoom.about(base, surface, x, y, zoom.factor * scale)
+        })
+        .await
+    }
+
+    /// Scroll-wheel zoom: each notch zooms in (positive) or out by 25%
+    pub async fn scroll(&self, x: i64, y: i64, notches: f64) -> Result<()> {
+        self.zoom_at(x, y, 1.25f64.powf(notches)).await
+    }
+
+    /// Drag-to-pan by a pointer movement in surface pixels
+    pub async fn pan_by(&self, dx: i64, dy: i64) -> Result<()> {
+        self.update_zoom(|zoom, _| Zoom {
+            pan_x: zoom.pan_x + dx,
+            pan_y: zoom.pan_y + dy,
+            ..zoom
+        })
+        .await
+    }
+
+    /// Back to the scale mode's own layout
+    pub async fn reset_zoom(&self) -> Result<()> {
+        self.set_zoom(Zoom::default()).await
+    }
+
+    async fn update_zoom(&self, update: impl FnOnce(Zoom, (&Viewport, (u32, u32))) -> Zoom) -> Result<()> {
+        let frame = self.buffer.current_frame().await;
+        {
+            let mut surface = self.surface.lock().await;
+            let surface_size = (surface.width, surface.height);
+            let frame_size = frame.map_or(surface_size, |frame| (frame.width, frame.height));
+            let base = surface.base_viewport(frame_size);
+            surface.zoom = update(surface.zoom, (&base, surface_size)).clamped(&base, surface_size);
+        }
+        self.present_current().await
+    }
+
+    /// Save the current frame as a PNG at its source resolution, without the
+    /// cursor or overlay. Reads the frame buffer, so it works while paused.
+    /// In the mosaic, this captures the focused (or primary) stream.
+    pub async fn screenshot(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let single = self.mosaic.lock().await.single();
+        let frame = single
+            .current_frame()
+            .await
+            .ok_or_else(|| anyhow::anyhow!("No frame to capture"))?;
+        // image panics on a buffer of the wrong size
+        if buffer::rgb24_len(frame.width, frame.height) != Some(frame.data.len()) {
+            anyhow::bail!("Frame {} doesn't match its {}x{} size", frame.id, frame.width, frame.height);
+        }
+
+        image::save_buffer_with_format(
+            path,
+            &frame.data,
+            frame.width,
+            frame.height,
+            image::ColorType::Rgb8,
+            image::ImageFormat::Png,
+        )
+        .with_context(|| format!("Failed to save screenshot {}", path.display()))?;
+
+        info!("Saved screenshot of frame {} to {}", frame.id, path.display());
+        Ok(())
+    }
+
+    /// Set the directory hotkey screenshots are saved to
+    pub async fn set_screenshot_dir(&self, dir: impl Into<PathBuf>) {
+        *self.screenshot_dir.lock().await = dir.into();
+    }
+
+    /// Replace the key chords kept as viewer hotkeys while controlling a host
+    pub async fn set_hotkey_bindings(&self, bindings: HotkeyBindings) {
+        self.hotkeys.lock().await.bindings = bindings;
+    }
+
+    /// Decide where a key event goes while controlling a host, running the
+    /// hotkey if it's one of ours. Returns true if it should be forwarded.
+    pub async fn route_key(&self, key: KeyCode, pressed: bool, modifiers: Modifiers) -> Result<bool> {
+        let route = self.hotkeys.lock().await.route(key, pressed, modifiers);
+        match route {
+            KeyRoute::Forward => Ok(true),
+            KeyRoute::Local(hotkey) => {
+                self.handle_hotkey(hotkey).await?;
+                Ok(false)
+            }
+            KeyRoute::Consumed => Ok(false),
+        }
+    }
+
+    /// Record whether the windowing backend captured the local mouse for
+    /// relative mouse mode
+    pub async fn set_mouse_captured(&self, captured: bool) {
+        *self.mouse_captured.lock().await = captured;
+    }
+
+    pub async fn is_mouse_captured(&self) -> bool {
+        *self.mouse_captured.lock().await
+    }
+
+    /// Run the action bound to a hotkey
+    pub async fn handle_hotkey(&self, hotkey: Hotkey) -> Result<()> {
+        match hotkey {
+            Hotkey::ToggleFullscreen => {
+                self.toggle_fullscreen().await;
+            }
+            Hotkey::ToggleStats => {
+                self.toggle_stats_overlay().await?;
+            }
+            Hotkey::TogglePause => {
+                if self.is_paused().await {
+                    if self.resume().await? {
+                        warn!("Updates were dropped while paused; a keyframe is needed");
+                    }
+                } else {
+                    self.pause(PauseMode::default()).await;
+                }
+            }
+            Hotkey::ShowMosaic => {
+                self.focus_stream(None).await?;
+            }
+            Hotkey::Screenshot => {
+                let millis = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis();
+                let path = self
+                    .screenshot_dir
+                    .lock()
+                    .await
+                    .join(format!("screenshot-{}.png", millis));
+                self.screenshot(path).await?;
+            }
+            Hotkey::ReleaseMouseCapture => {
+                // The windowing backend lets go of the mouse on seeing this,
+                // and switches hosts back with `ServerNetwork::set_mouse_mode`
+                self.set_mouse_captured(false).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// Get a copy of the current rendered frame
+    pub async fn get_current_frame(&self) -> Vec<u8> {
+        self.surface.lock().await.pixels.clone()
+    }
+
+    /// End the render loop, stop any recording and drop buffered frames
+    pub async fn shutdown(&self) -> Result<()> {
+        info!("Shutting down renderer");
+        self.stop.send_replace(true);
+        self.stop_recording().await?;
+        self.buffer.clear().await;
+        Ok(())
+    }
+}
+
+// Presents into the in-memory surface; whatever shows it on screen reads
+// it back with `get_current_frame`
+#[async_trait]
+impl FrameSink for Renderer {
+    async fn present(&self, frame: pcc_core::Frame) -> Result<()> {
+        if self.is_paused().await {
+            return Ok(());
+        }
+        self.render_frame(&frame.try_into()?).await
+    }
+
+    async fn resize(&self, width: u32, height: u32) -> Result<()> {
+        Renderer::resize(self, width, height).await
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        Renderer::shutdown(self).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_renderer_creation() {
+        let renderer = Renderer::new(1920, 1080, 30).await;
+        assert!(renderer.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_frame_rendering() {
+        let renderer = Renderer::new(1920, 1080, 30).await.unwrap();
+        let frame = pcc_core::Frame {
+            id: 1,
+            timestamp: std::time::SystemTime::now(),
+            width: 1920,
+            height: 1080,
+            format: pcc_core::PixelFormat::Rgb24,
+            data: vec![128; 1920 * 1080 * 3].into(), // Gray frame
+        };
+
+        renderer.buffer.push_frame(frame).await.unwrap();
+        if let Some(buffered_frame) = renderer.buffer.next_frame().await.unwrap() {
+            assert!(renderer.render_frame(&buffered_frame).await.is_ok());
+
+            // Verify the rendered output
+            let output = renderer.get_current_frame().await;
+            assert_eq!(output.len(), 1920 * 1080 * 3);
+            assert_eq!(output[0], 128); // Check first pixel
+        }
+    }
+
+    #[tokio::test]
+    async fn test_display_path_is_lossless() {
+        let renderer = Renderer::new(32, 16, 30).await.unwrap();
+        let data: Vec<u8> = (0..32 * 16 * 3).map(|i| (i * 7 % 256) as u8).collect();
+        let frame = pcc_core::Frame {
+            id: 1,
+            timestamp: std::time::SystemTime::now(),
+            width: 32,
+            height: 16,
+            format: pcc_core::PixelFormat::Rgb24,
+            data: data.clone().into(),
+        };
+
+        renderer.buffer.push_frame(frame).await.unwrap();
+        let buffered = renderer.buffer.next_frame().await.unwrap().unwrap();
+        renderer.render_frame(&buffered).await.unwrap();
+
+        // Any lossy encode on the way to the screen would perturb this noise
+        assert_eq!(renderer.get_current_frame().await, data);
+    }
+
+    #[tokio::test]
+    async fn test_letterboxed_resize() {
+        let renderer = Renderer::new(4, 2, 30).await.unwrap();
+        let frame = pcc_core::Frame {
+            id: 1,
+            timestamp: std::time::SystemTime::now(),
+            width: 4,
+            height: 2,
+            format: pcc_core::PixelFormat::Rgb24,
+            data: vec![200; 4 * 2 * 3].into(),
+        };
+        renderer.buffer.push_frame(frame).await.unwrap();
+        renderer.buffer.next_frame().await.unwrap();
+
+        // A square window fits the 2:1 frame with bars above and below
+        renderer.resize(8, 8).await.unwrap();
+        let viewport = renderer.viewport().await.unwrap();
+        assert_eq!((viewport.x, viewport.y, viewport.width, viewport.height), (0, 2, 8, 4));
+
+        let output = renderer.get_current_frame().await;
+        let at = |x: usize, y: usize| output[(y * 8 + x) * 3];
+        assert_eq!(at(0, 0), 0, "Letterbox should be black");
+        assert_eq!(at(0, 2), 200);
+        assert_eq!(at(7, 5), 200);
+        assert_eq!(at(7, 6), 0);
+
+        // Stretch fills the whole surface
+        renderer
+            .set_options(RendererOptions {
+                scale_mode: ScaleMode::Stretch,
+                ..RendererOptions::default()
+            })
+            .await
+            .unwrap();
+        assert!(renderer.get_current_frame().await.iter().all(|&b| b == 200));
+
+        // 1:1 mode centers the frame without scaling
+        renderer
+            .set_options(RendererOptions {
+                scale_mode: ScaleMode::Actual,
+                ..RendererOptions::default()
+            })
+            .await
+            .unwrap();
+        let viewport = renderer.viewport().await.unwrap();
+        assert_eq!((viewport.x, viewport.y, viewport.width, viewport.height), (2, 3, 4, 2));
+        assert_eq!(viewport.surface_to_frame(5, 4), Some((3, 1)));
+        assert_eq!(viewport.surface_to_frame(0, 0), None);
+
+        // Fullscreen is only a flag for the embedder's window
+        let size = renderer.surface_size().await;
+        assert!(renderer.toggle_fullscreen().await);
+        assert!(renderer.options().await.fullscreen);
+        assert_eq!(renderer.surface_size().await, size);
+    }
+
+    #[tokio::test]
+    async fn test_scale_filters() {
+        // Alternating black and white columns, downscaled 2:1
+        let data: Vec<u8> = (0..8 * 2)
+            .flat_map(|i| if i % 2 == 0 { [0u8; 3] } else { [255u8; 3] })
+            .collect();
+        let frame = pcc_core::Frame {
+            id: 1,
+            timestamp: std::time::SystemTime::now(),
+            width: 8,
+            height: 2,
+            format: pcc_core::PixelFormat::Rgb24,
+            data: data.into(),
+        };
+
+        let render = |filter| {
+            let frame = frame.clone();
+            async move {
+                let options = RendererOptions {
+                    scale_filter: filter,
+                    ..RendererOptions::default()
+                };
+                let renderer = Renderer::with_options(4, 1, 30, options).await.unwrap();
+                renderer.buffer.push_frame(frame).await.unwrap();
+                let buffered = renderer.buffer.next_frame().await.unwrap().unwrap();
+                renderer.render_frame(&buffered).await.unwrap();
+                renderer.get_current_frame().await
+            }
+        };
+
+        // Nearest picks source pixels; the smoothing filters average them
+        let nearest = render(ScaleFilter::Nearest).await;
+        assert!(nearest.iter().all(|&b| b == 0 || b == 255));
+        for filter in [ScaleFilter::Bilinear, ScaleFilter::Lanczos] {
+            let smoothed = render(filter).await;
+            assert!(smoothed.iter().any(|&b| b > 32 && b < 224), "{:?} should blend", filter);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stats_overlay() {
+        let renderer = Renderer::new(128, 64, 30).await.unwrap();
+        let frame = pcc_core::Frame {
+            id: 1,
+            timestamp: std::time::SystemTime::now(),
+            width: 128,
+            height: 64,
+            format: pcc_core::PixelFormat::Rgb24,
+            data: vec![128; 128 * 64 * 3].into(),
+        };
+        renderer.buffer.push_frame(frame).await.unwrap();
+        let buffered = renderer.buffer.next_frame().await.unwrap().unwrap();
+        renderer.render_frame(&buffered).await.unwrap();
+        renderer.record_network_stats(2_000_000, 0.05).await;
+
+        let stats = renderer.overlay_stats().await;
+        assert_eq!(stats.fps, 1.0);
+        assert_eq!(stats.bitrate_kbps, 2000.0);
+        assert!((stats.loss_percent - 5.0).abs() < 1e-3);
+
+        // Hidden by default, drawn once toggled on
+        assert!(renderer.get_current_frame().await.iter().all(|&b| b == 128));
+        assert!(renderer.toggle_stats_overlay().await.unwrap());
+        let output = renderer.get_current_frame().await;
+        assert!(output.contains(&255) && output.contains(&32));
+        assert_eq!(output[output.len() - 1], 128, "HUD stays in the corner");
+
+        // Re-presenting the same frame doesn't count towards fps
+        assert_eq!(renderer.overlay_stats().await.fps, 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_cursor_compositing() {
+        let renderer = Renderer::new(64, 64, 30).await.unwrap();
+        let frame = pcc_core::Frame {
+            id: 1,
+            timestamp: std::time::SystemTime::now(),
+            width: 64,
+            height: 64,
+            format: pcc_core::PixelFormat::Rgb24,
+            data: vec![0; 64 * 64 * 3].into(),
+        };
+        renderer.buffer.push_frame(frame).await.unwrap();
+        renderer.buffer.next_frame().await.unwrap();
+
+        // Opaque white 2x2 cursor with its hotspot at the top-left
+        let shape = Message::CursorShape {
+            hotspot: (0, 0),
+            width: 2,
+            height: 2,
+            rgba: vec![255; 2 * 2 * 4],
+        };
+        renderer.handle_message(shape).await.unwrap();
+        renderer
+            .handle_message(Message::CursorMoved { x: 10, y: 5 })
+            .await
+            .unwrap();
+
+        let output = renderer.get_current_frame().await;
+        let at = |x: usize, y: usize| output[(y * 64 + x) * 3];
+        assert_eq!(at(10, 5), 255);
+        assert_eq!(at(11, 6), 255);
+        assert_eq!(at(12, 5), 0);
+
+        // The underlying frame is untouched by the cursor
+        let current = renderer.buffer.current_frame().await.unwrap();
+        assert_eq!(current.data[(5 * 64 + 10) * 3], 0);
+
+        // A size whose byte count overflows is refused, not wrapped around
+        let huge = Message::CursorShape { hotspot: (0, 0), width: u32::MAX, height: u32::MAX, rgba: Vec::new() };
+        assert!(renderer.handle_message(huge).await.is_err());
+    }
+}
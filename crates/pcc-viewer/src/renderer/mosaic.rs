@@ -0,0 +1,124 @@
+use super::buffer::FrameBuffer;
+use std::sync::Arc;
+
+/// Identifies one incoming session shown by the renderer
+pub type StreamId = u64;
+
+/// The renderer's own `buffer`, always present
+pub const PRIMARY_STREAM: StreamId = 0;
+
+/// Where one stream is drawn on the surface in the tiled view
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tile {
+    pub stream: StreamId,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Tile {
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        x >= self.x && y >= self.y && x - self.x < self.width && y - self.y < self.height
+    }
+}
+
+/// The streams a renderer shows and which one, if any, has focus
+#[derive(Debug)]
+pub(crate) struct Mosaic {
+    streams: Vec<(StreamId, Arc<FrameBuffer>)>,
+    focus: Option<StreamId>,
+}
+
+impl Mosaic {
+    pub fn new(primary: Arc<FrameBuffer>) -> Self {
+        Self {
+            streams: vec![(PRIMARY_STREAM, primary)],
+            focus: None,
+        }
+    }
+
+    pub fn add(&mut self, id: StreamId, buffer: Arc<FrameBuffer>) -> anyhow::Result<()> {
+        if self.get(id).is_some() {
+            anyhow::bail!("Stream {} already exists", id);
+        }
+        self.streams.push((id, buffer));
+        Ok(())
+    }
+
+    pub fn remove(&mut self, id: StreamId) -> anyhow::Result<()> {
+        if id == PRIMARY_STREAM {
+            anyhow::bail!("The primary stream cannot be removed");
+        }
+        let before = self.streams.len();
+        self.streams.retain(|(stream, _)| *stream != id);
+        if self.streams.len() == before {
+            anyhow::bail!("Unknown stream {}", id);
+        }
+        if self.focus == Some(id) {
+            self.focus = None;
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, id: StreamId) -> Option<Arc<FrameBuffer>> {
+        self.streams
+            .iter()
+            .find(|(stream, _)| *stream == id)
+            .map(|(_, buffer)| buffer.clone())
+    }
+
+    pub fn buffers(&self) -> Vec<Arc<FrameBuffer>> {
+        self.streams.iter().map(|(_, buffer)| buffer.clone()).collect()
+    }
+
+    pub fn focus(&self) -> Option<StreamId> {
+        self.focus
+    }
+
+    pub fn set_focus(&mut self, id: Option<StreamId>) -> anyhow::Result<()> {
+        if let Some(id) = id {
+            if self.get(id).is_none() {
+                anyhow::bail!("Unknown stream {}", id);
+            }
+        }
+        self.focus = id;
+        Ok(())
+    }
+
+    /// Whether several streams are shown side by side
+    pub fn is_tiled(&self) -> bool {
+        self.focus.is_none() && self.streams.len() > 1
+    }
+
+    /// The stream shown in single view
+    pub fn single(&self) -> Arc<FrameBuffer> {
+        self.focus
+            .and_then(|id| self.get(id))
+            .unwrap_or_else(|| self.streams[0].1.clone())
+    }
+
+    /// Lay the streams out in a near-square grid filling the surface
+    pub fn layout(&self, width: u32, height: u32) -> Vec<Tile> {
+        let count = self.streams.len() as u32;
+        let columns = (count as f64).sqrt().ceil() as u32;
+        let rows = count.div_ceil(columns);
+
+        self.streams
+            .iter()
+            .enumerate()
+            .map(|(i, (stream, _))| {
+                let (column, row) = (i as u32 % columns, i as u32 / columns);
+                let x = column * width / columns;
+                let y = row * height / rows;
+                Tile {
+                    stream: *stream,
+                    x,
+                    y,
+                    width: (column + 1) * width / columns - x,
+                    height: (row + 1) * height / rows - y,
+                }
+            })
+            .collect()
+    }
+}
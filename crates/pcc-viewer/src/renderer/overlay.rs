@@ -0,0 +1,194 @@
+use super::buffer::BufferStats;
+use pcc_net::audio::{AudioSource, AudioStreamState};
+use pcc_net::telemetry::{FrameTimings, LatencyBreakdown, Stage, Telemetry};
+use pcc_core::font::{glyph, GLYPH_HEIGHT, GLYPH_WIDTH};
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant, SystemTime},
+};
+
+const GLYPH_SCALE: u32 = 2;
+const MARGIN: u32 = 4;
+const FPS_WINDOW: Duration = Duration::from_secs(1);
+
+/// Snapshot of the numbers shown on the stats overlay
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct OverlayStats {
+    /// Frames presented over the last second
+    pub fps: f32,
+    /// Capture-to-present latency of the last frame, in milliseconds
+    pub latency_ms: f32,
+    /// Receive bitrate reported by the network layer
+    pub bitrate_kbps: f32,
+    /// Packet loss reported by the network layer, 0.0-100.0
+    pub loss_percent: f32,
+    /// Frames waiting in the frame buffer
+    pub buffer_depth: usize,
+    /// Mean time frames spend in the frame buffer, in milliseconds
+    pub queue_wait_ms: f32,
+    /// Frames the frame buffer dropped, for any reason
+    pub dropped_frames: u64,
+    /// Mute and gain of the host's system audio, if it is played
+    pub system_audio: Option<AudioStreamState>,
+    /// Mute and gain of the host's microphone, if it is played
+    pub microphone: Option<AudioStreamState>,
+    /// Recent percentiles for each stage from capture to render
+    pub latency: LatencyBreakdown,
+}
+
+/// Tracks presentation statistics and draws them as a HUD
+#[derive(Debug, Default)]
+pub(crate) struct StatsOverlay {
+    stats: OverlayStats,
+    presented: VecDeque<Instant>,
+    last_frame_id: Option<u64>,
+    telemetry: Telemetry,
+}
+
+impl StatsOverlay {
+    /// Record that frame `id`, captured at `timestamp`, was presented after
+    /// taking `rendered` to draw
+    pub fn record_present(&mut self, id: u64, timestamp: SystemTime, rendered: Duration, buffer: BufferStats) {
+        self.stats.buffer_depth = buffer.depth;
+        self.stats.queue_wait_ms = buffer.average_wait.as_secs_f32() * 1000.0;
+        self.stats.dropped_frames = buffer.dropped_late + buffer.dropped_full + buffer.skipped;
+
+        // Re-presenting the same frame (cursor moves, resizes) isn't a new frame
+        if self.last_frame_id == Some(id) {
+            return;
+        }
+        self.last_frame_id = Some(id);
+        self.telemetry.record(Stage::Render, rendered);
+
+        let now = Instant::now();
+        self.presented.push_back(now);
+        while self
+            .presented
+            .front()
+            .is_some_and(|t| now.duration_since(*t) > FPS_WINDOW)
+        {
+            self.presented.pop_front();
+        }
+        self.stats.fps = self.presented.len() as f32 / FPS_WINDOW.as_secs_f32();
+
+        if let Ok(latency) = timestamp.elapsed() {
+            self.stats.latency_ms = latency.as_secs_f32() * 1000.0;
+        }
+    }
+
+    /// Record network figures, which the renderer cannot observe itself
+    pub fn record_network(&mut self, bitrate_bps: u64, loss: f32) {
+        self.stats.bitrate_kbps = bitrate_bps as f32 / 1000.0;
+        self.stats.loss_percent = loss * 100.0;
+    }
+
+    /// Record that frame `id`, captured at `timestamp`, arrived from the host
+    pub fn record_arrival(&mut self, id: u64, timestamp: SystemTime) {
+        self.telemetry.record_arrival(id, timestamp);
+    }
+
+    /// Record the host's capture, detect and encode times for a frame
+    pub fn record_host_timings(&mut self, timings: FrameTimings) {
+        self.telemetry.record_host_timings(timings);
+    }
+
+    /// Record a change to one of the host's audio sources
+    pub fn record_audio(&mut self, source: AudioSource, state: AudioStreamState) {
+        match source {
+            AudioSource::System => self.stats.system_audio = Some(state),
+            AudioSource::Microphone => self.stats.microphone = Some(state),
+        }
+    }
+
+    pub fn stats(&self) -> OverlayStats {
+        OverlayStats {
+            latency: self.telemetry.breakdown(),
+            ..self.stats
+        }
+    }
+
+    /// Draw the HUD into the top-left corner of an RGB24 surface
+    pub fn draw(&self, surface: &mut [u8], width: u32, height: u32) {
+        let audio = |label: &str, state: Option<AudioStreamState>| {
+            state.map(|state| match state.muted {
+                true => format!("{} MUTE", label),
+                false => format!("{} {:.0}%", label, state.gain * 100.0),
+            })
+        };
+        let ms = |duration: Duration| duration.as_secs_f32() * 1000.0;
+        let latency = self.telemetry.breakdown();
+        let stages = Stage::ALL.map(|stage| {
+            let label = stage.name()[..3].to_uppercase();
+            let percentiles = latency.stage(stage);
+            format!(
+                "{} {:.0}/{:.0}/{:.0}",
+                label,
+                ms(percentiles.p50),
+                ms(percentiles.p95),
+                ms(percentiles.p99)
+            )
+        });
+        let lines = [
+            Some(format!("FPS {:.1}", self.stats.fps)),
+            Some(format!("LAT {:.0}MS", self.stats.latency_ms)),
+            Some(format!("KBPS {:.0}", self.stats.bitrate_kbps)),
+            Some(format!("LOSS {:.1}%", self.stats.loss_percent)),
+            Some(format!("BUF {}", self.stats.buffer_depth)),
+            Some(format!("WAIT {:.0}MS", self.stats.queue_wait_ms)),
+            Some(format!("DROP {}", self.stats.dropped_frames)),
+            audio("SYS", self.stats.system_audio),
+            audio("MIC", self.stats.microphone),
+        ];
+        // Per-stage p50/p95/p99 in milliseconds
+        let lines: Vec<String> = lines
+            .into_iter()
+            .flatten()
+            .chain(Some("MS P50/95/99".to_string()))
+            .chain(stages)
+            .collect();
+
+        let advance = (GLYPH_WIDTH + 1) * GLYPH_SCALE;
+        let line_height = (GLYPH_HEIGHT + 2) * GLYPH_SCALE;
+        let longest = lines.iter().map(|l| l.len()).max().unwrap_or(0) as u32;
+        let box_w = longest * advance + MARGIN * 2;
+        let box_h = lines.len() as u32 * line_height + MARGIN * 2;
+
+        // Darken the background so the text reads over any content
+        for y in 0..box_h.min(height) {
+            for x in 0..box_w.min(width) {
+                let i = ((y * width + x) * 3) as usize;
+                for c in &mut surface[i..i + 3] {
+                    *c /= 4;
+                }
+            }
+        }
+
+        for (row, line) in lines.iter().enumerate() {
+            let y = MARGIN + row as u32 * line_height;
+            for (col, ch) in line.chars().enumerate() {
+                let x = MARGIN + col as u32 * advance;
+                draw_glyph(surface, width, height, x, y, glyph(ch));
+            }
+        }
+    }
+}
+
+fn draw_glyph(surface: &mut [u8], width: u32, height: u32, x: u32, y: u32, rows: [u8; 5]) {
+    for (gy, bits) in rows.iter().enumerate() {
+        for gx in 0..GLYPH_WIDTH {
+            if bits & (0b100 >> gx) == 0 {
+                continue;
+            }
+            for sy in 0..GLYPH_SCALE {
+                for sx in 0..GLYPH_SCALE {
+                    let px = x + gx * GLYPH_SCALE + sx;
+                    let py = y + gy as u32 * GLYPH_SCALE + sy;
+                    if px < width && py < height {
+                        let i = ((py * width + px) * 3) as usize;
+                        surface[i..i + 3].fill(255);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use super::viewport::Viewport;
+use image::{imageops, ImageBuffer, Rgb};
+use serde::{Deserialize, Serialize};
+
+/// Resampling filter used when the remote frame is scaled to the surface
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ScaleFilter {
+    /// Crisp pixels; best for text at integer scale factors
+    Nearest,
+    /// Smooth and cheap; a good general default
+    #[default]
+    Bilinear,
+    /// Sharpest downscaling (e.g. 4K onto a laptop screen), most expensive
+    Lanczos,
+}
+
+/// Draw an RGB24 frame into an RGB24 surface at `viewport`, clearing the
+/// letterbox area around it
+pub(crate) fn blit(
+    frame: &[u8],
+    surface: &mut [u8],
+    surface_width: u32,
+    surface_height: u32,
+    viewport: &Viewport,
+    filter: ScaleFilter,
+) {
+    surface.fill(0);
+    draw(frame, surface, surface_width, surface_height, viewport, filter);
+}
+
+/// Draw an RGB24 frame into an RGB24 surface at `viewport`, leaving the
+/// rest of the surface untouched
+pub(crate) fn draw(
+    frame: &[u8],
+    surface: &mut [u8],
+    surface_width: u32,
+    surface_height: u32,
+    viewport: &Viewport,
+    filter: ScaleFilter,
+) {
+    if viewport.width == 0 || viewport.height == 0 {
+        return;
+    }
+
+    let unscaled = (viewport.width, viewport.height) == (viewport.frame_width, viewport.frame_height);
+    let filter_type = match filter {
+        _ if unscaled => None,
+        ScaleFilter::Nearest => None,
+        ScaleFilter::Bilinear => Some(imageops::FilterType::Triangle),
+        ScaleFilter::Lanczos => Some(imageops::FilterType::Lanczos3),
+    };
+
+    match filter_type {
+        Some(filter_type) => blit_resampled(frame, surface, surface_width, surface_height, viewport, filter_type),
+        None => blit_nearest(frame, surface, surface_width, surface_height, viewport),
+    }
+}
+
+// Visible span of the viewport on the surface: (x0, y0, x1, y1)
+fn visible_span(surface_width: u32, surface_height: u32, viewport: &Viewport) -> (u32, u32, u32, u32) {
+    (
+        viewport.x.max(0) as u32,
+        viewport.y.max(0) as u32,
+        (viewport.x + viewport.width as i64).min(surface_width as i64).max(0) as u32,
+        (viewport.y + viewport.height as i64).min(surface_height as i64).max(0) as u32,
+    )
+}
+
+fn blit_resampled(
+    frame: &[u8],
+    surface: &mut [u8],
+    surface_width: u32,
+    surface_height: u32,
+    viewport: &Viewport,
+    filter_type: imageops::FilterType,
+) {
+    let frame_len = viewport.frame_width as usize * viewport.frame_height as usize * 3;
+    let Some(source) = ImageBuffer::<Rgb<u8>, _>::from_raw(
+        viewport.frame_width,
+        viewport.frame_height,
+        &frame[..frame_len],
+    ) else {
+        return;
+    };
+
+    let scaled = imageops::resize(&source, viewport.width, viewport.height, filter_type).into_raw();
+    let (x0, y0, x1, y1) = visible_span(surface_width, surface_height, viewport);
+    let scaled_stride = viewport.width as usize * 3;
+    let src_x = (x0 as i64 - viewport.x) as usize * 3;
+    let row_bytes = (x1 - x0) as usize * 3;
+
+    for sy in y0..y1 {
+        let src_start = (sy as i64 - viewport.y) as usize * scaled_stride + src_x;
+        let dst_start = (sy as usize * surface_width as usize + x0 as usize) * 3;
+        surface[dst_start..dst_start + row_bytes].copy_from_slice(&scaled[src_start..src_start + row_bytes]);
+    }
+}
+
+fn blit_nearest(frame: &[u8], surface: &mut [u8], surface_width: u32, surface_height: u32, viewport: &Viewport) {
+    let (x0, y0, x1, y1) = visible_span(surface_width, surface_height, viewport);
+
+    // Nearest-neighbour source column for each visible surface column
+    let src_columns: Vec<usize> = (x0..x1)
+        .map(|sx| {
+            let dx = (sx as i64 - viewport.x) as u64;
+            (dx * viewport.frame_width as u64 / viewport.width as u64) as usize
+        })
+        .collect();
+
+    let frame_stride = viewport.frame_width as usize * 3;
+    for sy in y0..y1 {
+        let dy = (sy as i64 - viewport.y) as u64;
+        let fy = (dy * viewport.frame_height as u64 / viewport.height as u64) as usize;
+        let src_row = &frame[fy * frame_stride..(fy + 1) * frame_stride];
+        let dst_row_start = (sy as usize * surface_width as usize + x0 as usize) * 3;
+        let dst_row = &mut surface[dst_row_start..dst_row_start + src_columns.len() * 3];
+
+        for (dst, &fx) in dst_row.chunks_exact_mut(3).zip(&src_columns) {
+            dst.copy_from_slice(&src_row[fx * 3..fx * 3 + 3]);
+        }
+    }
+}
@@ -0,0 +1,197 @@
+use super::interpolate::Interpolation;
+use super::scale::ScaleFilter;
+use pcc_core::ColorSpace;
+use serde::{Deserialize, Serialize};
+
+/// How remote frames are fitted into the viewer's display surface
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ScaleMode {
+    /// Scale to fit, preserving aspect ratio with letterboxing
+    #[default]
+    Fit,
+    /// Scale to fill the surface, ignoring aspect ratio
+    Stretch,
+    /// Show remote pixels 1:1, centered and cropped to the surface
+    Actual,
+}
+
+/// Display options for the viewer
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RendererOptions {
+    pub scale_mode: ScaleMode,
+    pub scale_filter: ScaleFilter,
+    /// Whether a window showing the surface should go fullscreen. pcc opens
+    /// no window, so only an embedder's window reads this
+    pub fullscreen: bool,
+    /// Draw the stats overlay HUD over presented frames
+    pub show_stats: bool,
+    /// Color space of the display; incoming frames are converted into it
+    pub display_color_space: ColorSpace,
+    /// Smooth out low-fps streams between frames
+    pub interpolation: Interpolation,
+}
+
+// Furthest the viewer can zoom in, relative to the scale mode's own size
+pub const MAX_ZOOM: f64 = 16.0;
+
+/// Magnification and panning on top of the scale mode
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Zoom {
+    /// 1.0 shows the frame as the scale mode lays it out
+    pub factor: f64,
+    /// Offset of the zoomed frame from centered, in surface pixels
+    pub pan_x: i64,
+    pub pan_y: i64,
+}
+
+impl Default for Zoom {
+    fn default() -> Self {
+        Self {
+            factor: 1.0,
+            pan_x: 0,
+            pan_y: 0,
+        }
+    }
+}
+
+impl Zoom {
+    /// Change the factor while keeping the frame pixel under surface point
+    /// (`x`, `y`) in place, as when zooming with the scroll wheel
+    pub fn about(self, base: &Viewport, surface: (u32, u32), x: i64, y: i64, factor: f64) -> Self {
+        let current = base.zoomed(self, surface);
+        let factor = factor.clamp(1.0, MAX_ZOOM);
+        let zoomed = base.zoomed(Self { factor, pan_x: 0, pan_y: 0 }, surface);
+
+        // Fraction of the frame under the point, held fixed across the zoom
+        let fraction = |point: i64, origin: i64, size: u32| {
+            if size == 0 { 0.5 } else { (point - origin) as f64 / size as f64 }
+        };
+        let fx = fraction(x, current.x, current.width);
+        let fy = fraction(y, current.y, current.height);
+        let centered_x = base.x + base.width as i64 / 2 - zoomed.width as i64 / 2;
+        let centered_y = base.y + base.height as i64 / 2 - zoomed.height as i64 / 2;
+
+        let zoom = Self {
+            factor,
+            pan_x: (x as f64 - fx * zoomed.width as f64).round() as i64 - centered_x,
+            pan_y: (y as f64 - fy * zoomed.height as f64).round() as i64 - centered_y,
+        };
+        zoom.clamped(base, surface)
+    }
+
+    /// Pull the pan back so the frame doesn't leave the surface
+    pub fn clamped(self, base: &Viewport, surface: (u32, u32)) -> Self {
+        let factor = self.factor.clamp(1.0, MAX_ZOOM);
+        let unpanned = base.zoomed(Self { factor, pan_x: 0, pan_y: 0 }, surface);
+        let panned = base.zoomed(self, surface);
+        Self {
+            factor,
+            pan_x: panned.x - unpanned.x,
+            pan_y: panned.y - unpanned.y,
+        }
+    }
+}
+
+/// Where a remote frame lands on the display surface. The origin may be
+/// negative when the frame is larger than the surface (1:1 mode).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Viewport {
+    pub x: i64,
+    pub y: i64,
+    pub width: u32,
+    pub height: u32,
+    pub frame_width: u32,
+    pub frame_height: u32,
+}
+
+impl Viewport {
+    /// Lay out a `frame` sized image on a `surface` sized display
+    pub fn compute(mode: ScaleMode, surface: (u32, u32), frame: (u32, u32)) -> Self {
+        let (surface_w, surface_h) = surface;
+        let (frame_w, frame_h) = frame;
+
+        let (width, height) = match mode {
+            ScaleMode::Stretch => (surface_w, surface_h),
+            ScaleMode::Actual => (frame_w, frame_h),
+            ScaleMode::Fit if frame_w == 0 || frame_h == 0 => (0, 0),
+            ScaleMode::Fit => {
+                let scale = (surface_w as f64 / frame_w as f64).min(surface_h as f64 / frame_h as f64);
+                (
+                    ((frame_w as f64 * scale).round() as u32).min(surface_w),
+                    ((frame_h as f64 * scale).round() as u32).min(surface_h),
+                )
+            }
+        };
+
+        Self {
+            x: (surface_w as i64 - width as i64) / 2,
+            y: (surface_h as i64 - height as i64) / 2,
+            width,
+            height,
+            frame_width: frame_w,
+            frame_height: frame_h,
+        }
+    }
+
+    /// Magnify about the viewport's center and apply the pan. A frame
+    /// larger than the surface is kept covering it; a smaller one stays
+    /// centered.
+    pub fn zoomed(&self, zoom: Zoom, surface: (u32, u32)) -> Self {
+        let factor = zoom.factor.clamp(1.0, MAX_ZOOM);
+        let width = (self.width as f64 * factor).round() as u32;
+        let height = (self.height as f64 * factor).round() as u32;
+        let x = self.x + self.width as i64 / 2 - width as i64 / 2 + zoom.pan_x;
+        let y = self.y + self.height as i64 / 2 - height as i64 / 2 + zoom.pan_y;
+
+        Self {
+            x: clamp_axis(x, width, surface.0),
+            y: clamp_axis(y, height, surface.1),
+            width,
+            height,
+            ..*self
+        }
+    }
+
+    /// Whether the frame maps exactly onto a `surface` sized display
+    pub fn is_identity(&self, surface: (u32, u32)) -> bool {
+        self.x == 0
+            && self.y == 0
+            && (self.width, self.height) == surface
+            && (self.frame_width, self.frame_height) == surface
+    }
+
+    /// Map a frame pixel coordinate onto the surface
+    pub fn frame_to_surface(&self, x: u32, y: u32) -> (i64, i64) {
+        if self.frame_width == 0 || self.frame_height == 0 {
+            return (self.x, self.y);
+        }
+        (
+            self.x + x as i64 * self.width as i64 / self.frame_width as i64,
+            self.y + y as i64 * self.height as i64 / self.frame_height as i64,
+        )
+    }
+
+    /// Map a surface coordinate back to the frame pixel under it, if any
+    pub fn surface_to_frame(&self, x: i64, y: i64) -> Option<(u32, u32)> {
+        let dx = x - self.x;
+        let dy = y - self.y;
+        if dx < 0 || dy < 0 || dx >= self.width as i64 || dy >= self.height as i64 {
+            return None;
+        }
+        Some((
+            (dx * self.frame_width as i64 / self.width as i64) as u32,
+            (dy * self.frame_height as i64 / self.height as i64) as u32,
+        ))
+    }
+}
+
+// Keep one axis of a zoomed frame on the surface
+fn clamp_axis(origin: i64, size: u32, surface: u32) -> i64 {
+    let (size, surface) = (size as i64, surface as i64);
+    if size <= surface {
+        (surface - size) / 2
+    } else {
+        origin.clamp(surface - size, 0)
+    }
+}
@@ -0,0 +1,78 @@
+use pcc_core::Frame;
+use crate::renderer::FrameBuffer;
+use anyhow::Result;
+use async_trait::async_trait;
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+use tokio::time;
+use tracing::{error, info};
+
+/// Somewhere played-out frames go: the renderer's surface, a recording,
+/// or nowhere at all.
+///
+/// This tree has no window of its own, so there is no SDL2, FFmpeg or wgpu
+/// sink. An embedder that opens one implements this trait for it and reads
+/// pixels from `Renderer::get_current_frame` or its own frames.
+#[async_trait]
+pub trait FrameSink: Send + Sync {
+    /// Show (or store) a frame
+    async fn present(&self, frame: Frame) -> Result<()>;
+
+    /// The output size changed, e.g. the window was resized
+    async fn resize(&self, width: u32, height: u32) -> Result<()>;
+
+    /// Release the output; nothing is presented afterwards
+    async fn shutdown(&self) -> Result<()>;
+}
+
+/// Discards frames; for headless receivers and benchmarks
+#[derive(Debug, Default)]
+pub struct NullSink {
+    presented: AtomicU64,
+}
+
+impl NullSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Frames presented so far
+    pub fn presented(&self) -> u64 {
+        self.presented.load(Ordering::Relaxed)
+    }
+}
+
+#[async_trait]
+impl FrameSink for NullSink {
+    async fn present(&self, _frame: Frame) -> Result<()> {
+        self.presented.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    async fn resize(&self, _width: u32, _height: u32) -> Result<()> {
+        Ok(())
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Play frames out of `buffer` into `sink` at `fps`. Sink errors are logged
+/// and playout continues; only a buffer error ends it.
+pub async fn play_out(buffer: &FrameBuffer, sink: &dyn FrameSink, fps: u32) -> Result<()> {
+    info!("Playing out to sink at {} fps", fps);
+
+    let mut interval = time::interval(Duration::from_secs(1) / fps.max(1));
+    loop {
+        interval.tick().await;
+
+        if let Some(frame) = buffer.next_frame().await? {
+            if let Err(e) = sink.present(frame.into()).await {
+                error!("Failed to present frame: {}", e);
+            }
+        }
+    }
+}
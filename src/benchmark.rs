@@ -0,0 +1,181 @@
+use crate::client::{FrameOutput, FramePipeline};
+use crate::network::EncodedFrame;
+use crate::pcc::{capture_stream, Frame, FrameCapture, FrameStream, PixelChangeDetector, PixelFormat, QualityConfig};
+use crate::telemetry;
+use anyhow::Result;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+
+/// Frames for benchmarking without a display: a bar a tenth of the screen
+/// tall scrolls down a frame at a time, like a scrolling document
+pub struct SyntheticCapture {
+    width: u32,
+    height: u32,
+    frame_counter: AtomicU64,
+}
+
+impl SyntheticCapture {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            width,
+            height,
+            frame_counter: AtomicU64::new(0),
+        }
+    }
+
+    /// Frames as a `Stream`; see `capture_stream`
+    pub fn into_stream(self) -> FrameStream {
+        capture_stream(self)
+    }
+}
+
+impl FrameCapture for SyntheticCapture {
+    fn capture_frame(&self) -> Result<Frame> {
+        let id = self.frame_counter.fetch_add(1, Ordering::Relaxed);
+        let row_bytes = self.width as usize * 3;
+        let bar = (self.height as usize / 10).max(1);
+        let top = (id as usize * 8) % self.height.max(1) as usize;
+
+        let mut data = vec![32; row_bytes * self.height as usize];
+        for row in (top..top + bar).map(|row| row % self.height as usize) {
+            data[row * row_bytes..(row + 1) * row_bytes].fill(224);
+        }
+        Ok(Frame {
+            id,
+            timestamp: SystemTime::now(),
+            width: self.width,
+            height: self.height,
+            format: PixelFormat::Rgb24,
+            data: data.into(),
+        })
+    }
+
+    fn supported_configs(&self) -> Vec<QualityConfig> {
+        vec![QualityConfig::default()]
+    }
+
+    fn configure(&mut self, _config: QualityConfig) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// How long each frame spent in one stage
+#[derive(Debug, Clone, Default)]
+pub struct StageTimings {
+    samples: Vec<Duration>,
+}
+
+impl StageTimings {
+    fn record(&mut self, elapsed: Duration) {
+        self.samples.push(elapsed);
+    }
+
+    /// The time `percent` of frames finished the stage within
+    pub fn percentile(&self, percent: f64) -> Duration {
+        let mut sorted = self.samples.clone();
+        sorted.sort_unstable();
+        telemetry::percentile(&sorted, percent)
+    }
+
+    pub fn mean(&self) -> Duration {
+        let total: Duration = self.samples.iter().sum();
+        total / self.samples.len().max(1) as u32
+    }
+}
+
+/// Per-stage timings from `run`
+#[derive(Debug, Clone, Default)]
+pub struct BenchmarkReport {
+    pub width: u32,
+    pub height: u32,
+    pub frames: u64,
+    pub capture: StageTimings,
+    pub detect: StageTimings,
+    pub encode: StageTimings,
+    /// Bytes the encoded frames would take on the wire
+    pub bytes: u64,
+}
+
+impl BenchmarkReport {
+    /// Frames per second one core could push through all three stages
+    pub fn achievable_fps(&self) -> f64 {
+        let per_frame = self.capture.mean() + self.detect.mean() + self.encode.mean();
+        if per_frame.is_zero() {
+            return 0.0;
+        }
+        1.0 / per_frame.as_secs_f64()
+    }
+}
+
+impl fmt::Display for BenchmarkReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ms = |duration: Duration| duration.as_secs_f64() * 1000.0;
+        writeln!(f, "{}x{}, {} frames", self.width, self.height, self.frames)?;
+        writeln!(f, "{:<8} {:>8} {:>8} {:>8} {:>8}  (ms)", "stage", "p50", "p95", "p99", "max")?;
+        for (name, timings) in [("capture", &self.capture), ("detect", &self.detect), ("encode", &self.encode)] {
+            writeln!(
+                f,
+                "{:<8} {:>8.2} {:>8.2} {:>8.2} {:>8.2}",
+                name,
+                ms(timings.percentile(50.0)),
+                ms(timings.percentile(95.0)),
+                ms(timings.percentile(99.0)),
+                ms(timings.percentile(100.0)),
+            )?;
+        }
+        let per_frame = self.bytes / self.frames.max(1);
+        writeln!(f, "Average {} bytes per frame on the wire", per_frame)?;
+        write!(f, "Achievable: {:.1} fps", self.achievable_fps())
+    }
+}
+
+/// Capture, detect changes in and encode frames from `capture` for
+/// `duration`, as screen sharing would but without a network, timing each
+/// stage
+pub fn run<D: PixelChangeDetector>(capture: &dyn FrameCapture, detector: D, duration: Duration) -> Result<BenchmarkReport> {
+    let mut pipeline = FramePipeline::new(detector);
+    let mut report = BenchmarkReport::default();
+    let deadline = Instant::now() + duration;
+
+    while report.frames == 0 || Instant::now() < deadline {
+        let start = Instant::now();
+        let frame = capture.capture_frame()?;
+        report.width = frame.width;
+        report.height = frame.height;
+        report.capture.record(start.elapsed());
+
+        let start = Instant::now();
+        let output = pipeline.process(frame, report.frames == 0)?;
+        report.detect.record(start.elapsed());
+
+        let start = Instant::now();
+        report.bytes += match output {
+            FrameOutput::Keyframe(frame) => EncodedFrame::keyframe(&frame)?.len() as u64,
+            FrameOutput::Update(update) => EncodedFrame::update(&update)?.len() as u64,
+            FrameOutput::Scroll { copy, update } => EncodedFrame::scroll(copy, &update)?.len() as u64,
+            FrameOutput::Unchanged => 0,
+        };
+        report.encode.record(start.elapsed());
+        report.frames += 1;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentiles() {
+        let mut timings = StageTimings::default();
+        for ms in 1..=100 {
+            timings.record(Duration::from_millis(ms));
+        }
+        assert_eq!(timings.percentile(50.0), Duration::from_millis(50));
+        assert_eq!(timings.percentile(99.0), Duration::from_millis(99));
+        assert_eq!(timings.percentile(100.0), Duration::from_millis(100));
+        assert_eq!(timings.percentile(0.0), Duration::from_millis(1));
+        assert_eq!(StageTimings::default().percentile(50.0), Duration::ZERO);
+    }
+}
@@ -0,0 +1,1193 @@
+use crate::audio::{opus_encoder, AudioCapture, AudioConfig, AudioControls, AudioStreamer, BitrateControl};
+use crate::capture::ScreenCapture;
+use crate::config::PccConfig;
+use crate::encoder::{FrameEncoder, VideoCodec};
+use crate::frame_dump::{DumpConfig, FrameRing};
+use crate::health::Health;
+use crate::input::{InputConfig, Permission, RemoteInput};
+use crate::network::{
+    handle_host_events, AudioStream, BandwidthEstimator, BandwidthMonitor, CloseReason, Connection, DataUsage,
+    EncodedFrame, IdleAction, Message, NetworkConfig, NetworkFeedback, NetworkManager, PairedViewers, PairingPin, QuotaAction,
+    ResumeToken, SessionClock, SessionInfo, SessionResume, UsageMeter, ViewerApproval, ViewerInfo, PIN_LIFETIME,
+};
+use crate::pcc::{
+    AsyncPixelChangeDetector, CopyRect, Frame, FrameCapture, FramePool, FrameUpdate, PCCDetector, PixelChange, PixelChangeDetector,
+    PoolStats, QualityConfig,
+};
+use crate::quality::{QualityController, QualityStats};
+use crate::session_log::{SessionEvent, SessionLog};
+use crate::telemetry::{FrameTimings, LatencyBreakdown, Stage, Telemetry};
+use crate::watchdog::{PipelineStage, Recovery, StageContext, StageError, Watchdog, WatchdogConfig};
+use crate::watermark::{Watermark, WatermarkConfig};
+use crate::audit::{AuditConfig, AuditLog};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::future::{poll_fn, Future};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::task::Poll;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc, watch, Notify};
+use tokio::time::{self, MissedTickBehavior};
+use tracing::{debug, debug_span, field, info, warn, Instrument, Span};
+
+// How long to wait for the viewer to acknowledge the close on shutdown
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+// How often a session's status is refreshed
+const STATUS_INTERVAL: Duration = Duration::from_millis(500);
+// Video bitrate once the data quota is used up, with `QuotaAction::Degrade`
+const DEGRADED_BITRATE: u64 = 200_000;
+// How often a suspended session captures, to notice the screen changing
+const SUSPENDED_INTERVAL: Duration = Duration::from_secs(2);
+// Share of a frame that has to change before a scroll is looked for
+const SCROLL_CHANGED_SHARE: f64 = 0.25;
+// Furthest a scroll is looked for, in rows
+const MAX_SCROLL_OFFSET: u32 = 256;
+// Shortest time between key rotations, leaving QUIC time to confirm one
+// before the next
+const MIN_REKEY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Everything the host needs to share its screen with a viewer. Settings
+/// missing from a config file keep their defaults.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClientConfig {
+    /// Viewer to share with
+    pub viewer: SocketAddr,
+    /// Display to share, by its index in `capture::list_displays`
+    pub display: usize,
+    pub codec: VideoCodec,
+    pub network: NetworkConfig,
+    pub quality: QualityConfig,
+    pub input: InputConfig,
+    /// Audio to share alongside the screen; `None` shares none
+    pub audio: Option<AudioConfig>,
+    /// Recent frames to keep for dumping
+    pub debug: DumpConfig,
+    /// When stalled pipeline stages are restarted
+    pub watchdog: WatchdogConfig,
+    /// Whether frames are marked with the viewer they're shared with
+    pub watermark: WatermarkConfig,
+    /// Where connects, permissions and input are audited, when the host
+    /// allows control
+    pub audit: AuditConfig,
+    /// Where to log connects, keyframes, quality changes and errors
+    #[serde(skip)]
+    pub events: SessionLog,
+    /// Asked about viewers that aren't trusted, with
+    /// `network.approve_viewers`
+    #[serde(skip)]
+    pub approval: ViewerApproval,
+    /// The session to pick up on reconnecting, shared between runs
+    #[serde(skip)]
+    pub resume: SessionResume,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        Self {
+            viewer: SocketAddr::from(([127, 0, 0, 1], 5800)),
+            display: 0,
+            codec: VideoCodec::default(),
+            network: NetworkConfig::default(),
+            quality: QualityConfig::default(),
+            input: InputConfig::default(),
+            audio: Some(AudioConfig::default()),
+            debug: DumpConfig::default(),
+            watchdog: WatchdogConfig::default(),
+            watermark: WatermarkConfig::default(),
+            audit: AuditConfig::default(),
+            events: SessionLog::default(),
+            approval: ViewerApproval::default(),
+            resume: SessionResume::default(),
+        }
+    }
+}
+
+/// What the host sends for one captured frame
+#[derive(Debug, Clone)]
+pub enum FrameOutput {
+    /// The whole frame, for a new viewer, a keyframe request or a resize
+    Keyframe(Frame),
+    /// Only the regions that changed since the previous frame
+    Update(FrameUpdate),
+    /// A block of the previous frame moved, e.g. scrolled, which the viewer
+    /// copies within its frame before applying the changes left
+    Scroll { copy: CopyRect, update: FrameUpdate },
+    /// Nothing changed, so nothing is sent
+    Unchanged,
+}
+
+/// Decides frame by frame whether the viewer needs the whole frame or only
+/// what changed
+pub struct FramePipeline<D> {
+    detector: D,
+    previous: Option<Frame>,
+    ring: FrameRing,
+    watermark: Option<Watermark>,
+    pool: FramePool,
+}
+
+impl<D> FramePipeline<D> {
+    pub fn new(detector: D) -> Self {
+        Self { detector, previous: None, ring: FrameRing::new(0), watermark: None, pool: FramePool::default() }
+    }
+
+    /// Give frames back to `pool` once they've been diffed against, for
+    /// capture to reuse
+    pub fn with_pool(mut self, pool: FramePool) -> Self {
+        self.pool = pool;
+        self
+    }
+
+    /// Where frames go back once they've been diffed against
+    pub fn pool(&self) -> &FramePool {
+        &self.pool
+    }
+
+    /// Keep the last `frames` frames and their changes for `dump_frames`
+    pub fn with_frame_ring(mut self, frames: usize) -> Self {
+        self.ring = FrameRing::new(frames);
+        self
+    }
+
+    /// Mark every frame with `watermark` before working out what changed
+    pub fn with_watermark(mut self, watermark: Watermark) -> Self {
+        self.watermark = Some(watermark);
+        self
+    }
+
+    /// Forget the previous frame, so the next one goes whole
+    pub fn reset(&mut self) {
+        if let Some(previous) = self.previous.take() {
+            self.pool.recycle(previous.data);
+        }
+    }
+
+    /// Write the kept frames under `dir`, returning where, or None if no
+    /// frames are kept
+    pub fn dump_frames(&self, dir: &Path) -> Result<Option<PathBuf>> {
+        if self.ring.is_empty() {
+            return Ok(None);
+        }
+        self.ring.dump(dir).map(Some)
+    }
+
+    // The previous frame, when `frame` can be sent as changes to it
+    fn diff_base(&self, frame: &Frame, keyframe: bool) -> Option<&Frame> {
+        self.previous
+            .as_ref()
+            .filter(|previous| !keyframe && previous.width == frame.width && previous.height == frame.height)
+    }
+
+    // What to send given the copy and changes found, None meaning `frame`
+    // goes whole
+    fn finish(&mut self, frame: Frame, copy: Option<CopyRect>, changes: Option<Vec<PixelChange>>) -> FrameOutput {
+        let output = match changes {
+            Some(changes) if changes.is_empty() && copy.is_none() => FrameOutput::Unchanged,
+            Some(changes) => {
+                let update = FrameUpdate { frame_id: frame.id, timestamp: frame.timestamp, changes };
+                match copy {
+                    Some(copy) => FrameOutput::Scroll { copy, update },
+                    None => FrameOutput::Update(update),
+                }
+            }
+            None => FrameOutput::Keyframe(frame.clone()),
+        };
+        self.ring.record(&frame, &output);
+        if let Some(previous) = self.previous.replace(frame) {
+            self.pool.recycle(previous.data);
+        }
+        output
+    }
+
+    fn mark(&self, frame: &mut Frame) {
+        if let Some(watermark) = &self.watermark {
+            watermark.apply(frame);
+        }
+    }
+}
+
+impl<D: PixelChangeDetector> FramePipeline<D> {
+    /// Apply new quality settings to the detector
+    pub fn configure(&mut self, config: QualityConfig) -> Result<()> {
+        self.detector.configure(config)
+    }
+
+    /// Work out what to send for `frame`, sending it whole if `keyframe`.
+    /// Frames in other pixel formats are converted to RGB24 first.
+    pub fn process(&mut self, frame: Frame, keyframe: bool) -> Result<FrameOutput> {
+        let mut frame = frame.into_rgb24()?;
+        self.mark(&mut frame);
+        let (copy, changes) = match self.diff_base(&frame, keyframe) {
+            Some(previous) => {
+                let changes = self.detector.detect_changes(previous, &frame)?;
+                match self.scroll(previous, &frame, &changes)? {
+                    Some((copy, scrolled)) => (Some(copy), Some(scrolled)),
+                    None => (None, Some(changes)),
+                }
+            }
+            None => (None, None),
+        };
+        Ok(self.finish(frame, copy, changes))
+    }
+
+    // A scroll from `previous` that leaves less to send than `changes`, and
+    // the changes left once the viewer has copied the moved block. Only
+    // looked for when much of the frame changed, as scrolling changes most
+    // of it.
+    fn scroll(
+        &self,
+        previous: &Frame,
+        frame: &Frame,
+        changes: &[PixelChange],
+    ) -> Result<Option<(CopyRect, Vec<PixelChange>)>> {
+        let changed: u64 = changes.iter().map(|change| change.width as u64 * change.height as u64).sum();
+        let pixels = frame.width as u64 * frame.height as u64;
+        if (changed as f64) < pixels as f64 * SCROLL_CHANGED_SHARE {
+            return Ok(None);
+        }
+        let Some(copy) = self.detector.detect_scroll(previous, frame, MAX_SCROLL_OFFSET) else {
+            return Ok(None);
+        };
+        let mut moved = previous.clone();
+        moved.copy_rect(copy.src_x, copy.src_y, copy.dst_rect)?;
+        let scrolled = self.detector.detect_changes(&moved, frame)?;
+        self.pool.recycle(moved.data);
+        let bytes = |changes: &[PixelChange]| changes.iter().map(|change| change.data.len()).sum::<usize>();
+        Ok((bytes(&scrolled) < bytes(changes)).then_some((copy, scrolled)))
+    }
+}
+
+impl<D: AsyncPixelChangeDetector> FramePipeline<D> {
+    /// `process` for async detectors
+    pub async fn process_async(&mut self, frame: Frame, keyframe: bool) -> Result<FrameOutput> {
+        let mut frame = frame.into_rgb24()?;
+        self.mark(&mut frame);
+        let changes = match self.diff_base(&frame, keyframe) {
+            Some(previous) => Some(self.detector.detect_changes(previous, &frame).await?),
+            None => None,
+        };
+        Ok(self.finish(frame, None, changes))
+    }
+}
+
+/// Live numbers for a sharing session, for status displays
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SessionStatus {
+    /// Viewer being shared with, once connected
+    pub viewer: Option<SocketAddr>,
+    /// Frames captured per second
+    pub fps: f32,
+    /// Bits per second sent, audio and overhead included
+    pub bitrate: u64,
+    pub rtt: Duration,
+    /// Fraction of packets lost since the last update
+    pub loss: f64,
+    /// JPEG quality frames are encoded at, 1-100
+    pub quality: u32,
+    /// Bits per second frames are paced to, or 0 for no limit
+    pub video_budget: u64,
+    /// Frame rate and change threshold quality adaptation has settled on
+    pub target_fps: u32,
+    pub threshold: u8,
+    pub frames_captured: u64,
+    /// Fraction of captured pixels that changed
+    pub change_ratio: f64,
+    /// Mean time to diff a frame, code it and hand it to the network
+    pub encode_time: Duration,
+    /// Recent capture, detect and encode percentiles
+    pub latency: LatencyBreakdown,
+    /// Data used so far this session
+    pub usage: DataUsage,
+    /// PIN to enter on the viewer, while pairing with it
+    pub pairing_pin: Option<String>,
+    /// Whether viewers are being shown the paused placeholder
+    pub paused: bool,
+    /// Whether the viewer says it's recording the session
+    pub viewer_recording: bool,
+    /// Frame buffers capture reuses rather than allocating
+    pub frame_pool: PoolStats,
+}
+
+impl SessionStatus {
+    /// Whether capture is producing frames, diffing and coding them keeps
+    /// up with the frame rate, and a viewer is connected
+    pub fn health(&self) -> Health {
+        let frame_time = Duration::from_secs(1) / self.target_fps.max(1);
+        Health::default()
+            .check(
+                "capture",
+                self.paused || self.fps > 0.0,
+                if self.paused { "paused".to_string() } else { format!("{:.1} fps", self.fps) },
+            )
+            .check(
+                "encoder",
+                self.encode_time <= frame_time,
+                format!("{} ms per frame of {} ms", self.encode_time.as_millis(), frame_time.as_millis()),
+            )
+            .check(
+                "viewer",
+                self.viewer.is_some(),
+                self.viewer.map_or("not connected".to_string(), |viewer| viewer.to_string()),
+            )
+    }
+}
+
+// Running totals kept by `share_screen`, turned into rates for the status
+#[derive(Debug, Default)]
+struct FrameCounters {
+    captured: AtomicU64,
+    // Pixels captured, and how many of them changed
+    pixels: AtomicU64,
+    changed_pixels: AtomicU64,
+    // Frames diffed and sent, and the time that took
+    processed: AtomicU64,
+    encode_nanos: AtomicU64,
+    telemetry: std::sync::Mutex<Telemetry>,
+}
+
+// `FrameCounters` and the network's figures at one moment
+#[derive(Debug, Clone, Copy)]
+struct CounterSnapshot {
+    at: Instant,
+    captured: u64,
+    pixels: u64,
+    changed_pixels: u64,
+    processed: u64,
+    encode_nanos: u64,
+    sent_bytes: u64,
+    received_bytes: u64,
+    feedback: NetworkFeedback,
+}
+
+impl FrameCounters {
+    fn snapshot(&self, bandwidth: &BandwidthMonitor) -> CounterSnapshot {
+        CounterSnapshot {
+            at: Instant::now(),
+            captured: self.captured.load(Ordering::Relaxed),
+            pixels: self.pixels.load(Ordering::Relaxed),
+            changed_pixels: self.changed_pixels.load(Ordering::Relaxed),
+            processed: self.processed.load(Ordering::Relaxed),
+            encode_nanos: self.encode_nanos.load(Ordering::Relaxed),
+            sent_bytes: bandwidth.sent_bytes(),
+            received_bytes: bandwidth.received_bytes(),
+            feedback: bandwidth.feedback(),
+        }
+    }
+}
+
+/// What a status display can ask of a running session
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SessionCommand {
+    /// Send the next frame whole
+    ForceKeyframe,
+    /// Change the encoding quality, 0.0-1.0
+    SetQuality(f32),
+    /// Apply new quality settings, e.g. from a reloaded config, to capture,
+    /// change detection and encoding
+    Reconfigure(QualityConfig),
+    /// Write the recently kept frames to disk, if frames are being kept
+    DumpFrames,
+    /// Show viewers a placeholder instead of the screen, keeping the
+    /// session open
+    Pause,
+    /// Share the screen again after `Pause`, starting from a keyframe
+    Resume,
+}
+
+/// The session's end of a status display link, passed to `run_monitored`
+pub struct SessionMonitor {
+    status: watch::Sender<SessionStatus>,
+    commands: mpsc::Receiver<SessionCommand>,
+    // The settings being shared with, for `SessionRemote::config_snapshot`
+    config: watch::Sender<PccConfig>,
+}
+
+/// The display's end of a status display link
+#[derive(Clone)]
+pub struct SessionRemote {
+    status: watch::Receiver<SessionStatus>,
+    commands: mpsc::Sender<SessionCommand>,
+    config: watch::Receiver<PccConfig>,
+}
+
+impl SessionMonitor {
+    /// Link a session to a status display
+    pub fn new() -> (Self, SessionRemote) {
+        let (status_tx, status_rx) = watch::channel(SessionStatus::default());
+        let (commands_tx, commands_rx) = mpsc::channel(16);
+        let (config_tx, config_rx) = watch::channel(PccConfig::default());
+        (
+            Self { status: status_tx, commands: commands_rx, config: config_tx },
+            SessionRemote { status: status_rx, commands: commands_tx, config: config_rx },
+        )
+    }
+
+    // Refresh the status, adapt quality to it and carry out commands until
+    // the session ends, the viewer goes quiet, or the data quota is used up
+    // or the screen idles when that stops it. `video_bitrate` is video's
+    // share of the bandwidth.
+    async fn run(
+        &mut self,
+        viewer: SocketAddr,
+        bandwidth: &BandwidthMonitor,
+        sharing: &Sharing<'_>,
+        quality: &watch::Sender<QualityConfig>,
+        video_bitrate: &AtomicU64,
+        network: &NetworkConfig,
+    ) -> CloseReason {
+        let Sharing { encoder, connection, counters, events, .. } = *sharing;
+        let mut controller = QualityController::new(*quality.borrow());
+        let mut usage = UsageMeter::new(network.data_quota);
+        let mut degraded = false;
+        let mut last_change = Instant::now();
+        let mut last_rekey = Instant::now();
+        let rekey_interval = network.rekey_interval.map(|interval| interval.max(MIN_REKEY_INTERVAL));
+        let mut interval = time::interval(STATUS_INTERVAL);
+        let mut last: Option<CounterSnapshot> = None;
+        let mut logged_quality = (encoder.current_quality(), quality.borrow().target_fps);
+        let mut recording = connection.viewer_recording();
+        if *recording.borrow_and_update() == Some(true) {
+            note_recording(events, true);
+        }
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {
+                    // Quality changes however it was changed: adaptation,
+                    // commands or a reloaded config
+                    let current = (encoder.current_quality(), quality.borrow().target_fps);
+                    if current != logged_quality {
+                        events.record(SessionEvent::QualityChange { quality: current.0, target_fps: current.1 });
+                        logged_quality = current;
+                    }
+                    let now = counters.snapshot(bandwidth);
+                    let silent = now.at.saturating_duration_since(connection.last_heard());
+                    if silent >= network.viewer_timeout {
+                        warn!("Heard nothing from the viewer for {:.0?}; ending the session", silent);
+                        return CloseReason::IdleTimeout;
+                    }
+                    // A paused screen isn't idle, it's hidden
+                    if *sharing.paused.borrow() || last.is_none_or(|last| now.changed_pixels > last.changed_pixels) {
+                        last_change = now.at;
+                    }
+                    if network.idle_action == IdleAction::Disconnect
+                        && network.idle_timeout.is_some_and(|timeout| now.at.duration_since(last_change) >= timeout)
+                    {
+                        info!("Screen unchanged for {:.0?}; ending the session", now.at.duration_since(last_change));
+                        return CloseReason::IdleTimeout;
+                    }
+                    if rekey_interval.is_some_and(|interval| now.at.duration_since(last_rekey) >= interval) {
+                        match connection.rekey() {
+                            Ok(()) => info!("Rotated session keys"),
+                            Err(e) => warn!("Failed to rotate session keys: {:#}", e),
+                        }
+                        last_rekey = now.at;
+                    }
+                    usage.record(now.at, now.sent_bytes, now.received_bytes);
+                    if usage.exhausted() && !degraded {
+                        let used = usage.usage().total_bytes();
+                        match network.quota_action {
+                            QuotaAction::Stop => {
+                                warn!("Used {} bytes of data, the session's quota; stopping", used);
+                                return CloseReason::QuotaExceeded;
+                            }
+                            QuotaAction::Degrade => {
+                                warn!("Used {} bytes of data, the session's quota; degrading quality", used);
+                                controller.limit_bitrate(DEGRADED_BITRATE);
+                                degraded = true;
+                            }
+                        }
+                    }
+                    let mut status = SessionStatus {
+                        viewer: Some(viewer),
+                        rtt: now.feedback.rtt,
+                        quality: encoder.current_quality(),
+                        video_budget: encoder.target_bitrate(),
+                        target_fps: controller.config().target_fps,
+                        threshold: controller.config().threshold,
+                        frames_captured: now.captured,
+                        latency: counters.telemetry.lock().unwrap().breakdown(),
+                        usage: usage.usage(),
+                        paused: *sharing.paused.borrow(),
+                        viewer_recording: *recording.borrow() == Some(true),
+                        frame_pool: sharing.pool.stats(),
+                        ..SessionStatus::default()
+                    };
+                    if let Some(last) = last {
+                        let elapsed = now.at.duration_since(last.at).as_secs_f64().max(f64::EPSILON);
+                        status.fps = ((now.captured - last.captured) as f64 / elapsed) as f32;
+                        status.bitrate = ((now.sent_bytes - last.sent_bytes) as f64 * 8.0 / elapsed) as u64;
+                        let packets = now.feedback.sent_packets.saturating_sub(last.feedback.sent_packets);
+                        let lost = now.feedback.lost_packets.saturating_sub(last.feedback.lost_packets);
+                        status.loss = if packets == 0 { 0.0 } else { (lost as f64 / packets as f64).min(1.0) };
+                        let pixels = now.pixels - last.pixels;
+                        if pixels > 0 {
+                            status.change_ratio = (now.changed_pixels - last.changed_pixels) as f64 / pixels as f64;
+                        }
+                        let encode_nanos = now.encode_nanos - last.encode_nanos;
+                        if let Some(mean) = encode_nanos.checked_div(now.processed - last.processed) {
+                            status.encode_time = Duration::from_nanos(mean);
+                        }
+
+                        let stats = QualityStats {
+                            capture_fps: status.fps,
+                            encode_time: status.encode_time,
+                            bitrate: status.bitrate,
+                            available_bitrate: video_bitrate.load(Ordering::Relaxed),
+                            loss: status.loss,
+                        };
+                        if let Some(adapted) = controller.update(&stats) {
+                            debug!("Adapted to {} fps with change threshold {}", adapted.target_fps, adapted.threshold);
+                            quality.send_replace(adapted);
+                        }
+                        encoder.set_target_bitrate(controller.bitrate());
+                    }
+                    self.status.send_replace(status);
+                    last = Some(now);
+                }
+                Ok(()) = recording.changed() => {
+                    let now = *recording.borrow_and_update() == Some(true);
+                    note_recording(events, now);
+                    self.status.send_modify(|status| status.viewer_recording = now);
+                }
+                Some(command) = self.commands.recv() => match command {
+                    SessionCommand::ForceKeyframe => encoder.force_keyframe(),
+                    SessionCommand::SetQuality(level) => {
+                        encoder.set_quality(level);
+                        // Reconfiguring for adaptation would reset it otherwise
+                        controller.set_quality(level);
+                        quality.send_replace(controller.config());
+                    }
+                    SessionCommand::Reconfigure(config) => {
+                        controller.reconfigure(config);
+                        quality.send_replace(config);
+                        self.config.send_modify(|snapshot| snapshot.quality = config);
+                    }
+                    SessionCommand::DumpFrames => sharing.dump_requests.notify_one(),
+                    SessionCommand::Pause => {
+                        if !sharing.paused.send_replace(true) {
+                            events.record(SessionEvent::Pause { paused: true });
+                        }
+                        // Shown at once, not at the next refresh
+                        self.status.send_modify(|status| status.paused = true);
+                    }
+                    SessionCommand::Resume => {
+                        if sharing.paused.send_replace(false) {
+                            events.record(SessionEvent::Pause { paused: false });
+                        }
+                        self.status.send_modify(|status| status.paused = false);
+                    }
+                },
+            }
+        }
+    }
+}
+
+// Log the viewer starting or stopping recording, loudly if starting
+fn note_recording(events: &SessionLog, recording: bool) {
+    if recording {
+        warn!("The viewer is recording the session");
+    } else {
+        info!("The viewer stopped recording the session");
+    }
+    events.record(SessionEvent::Recording { recording });
+}
+
+impl SessionRemote {
+    /// The session's latest status
+    pub fn status(&self) -> SessionStatus {
+        self.status.borrow().clone()
+    }
+
+    /// The session's health, from its latest status
+    pub fn health(&self) -> Health {
+        self.status.borrow().health()
+    }
+
+    /// Whether the session has ended
+    pub fn is_closed(&self) -> bool {
+        self.status.has_changed().is_err()
+    }
+
+    /// Wait until a viewer is connected and approved. Returns false if the
+    /// session ended first.
+    pub async fn connected(&mut self) -> bool {
+        self.status.wait_for(|status| status.viewer.is_some()).await.is_ok()
+    }
+
+    /// Ask the session to do something. Returns false once it has ended.
+    pub fn send(&self, command: SessionCommand) -> bool {
+        self.commands.try_send(command).is_ok()
+    }
+
+    /// The settings the session is sharing with: those it started with,
+    /// and quality settings since reconfigured. What quality adaptation
+    /// has settled on is in the status instead.
+    pub fn config_snapshot(&self) -> PccConfig {
+        self.config.borrow().clone()
+    }
+}
+
+// One audio source being shared
+struct SharedAudio {
+    capture: AudioCapture,
+    streamer: AudioStreamer,
+    stream: AudioStream,
+}
+
+/// Share this machine's screen, and its audio if configured, with the
+/// viewer until the connection closes or `shutdown` completes. Returns why
+/// the session ended.
+///
+/// On shutdown the frame and audio being sent are finished, the viewer is
+/// told goodbye, and the connection is closed and its tasks joined before
+/// returning, so the viewer never waits for a timeout.
+pub async fn run(config: ClientConfig, shutdown: impl Future<Output = ()>) -> Result<CloseReason> {
+    let (monitor, _remote) = SessionMonitor::new();
+    run_monitored(config, shutdown, monitor).await
+}
+
+/// Like `run`, publishing the session's status to `monitor` and taking its
+/// commands
+pub async fn run_monitored(
+    config: ClientConfig,
+    shutdown: impl Future<Output = ()>,
+    mut monitor: SessionMonitor,
+) -> Result<CloseReason> {
+    monitor.config.send_replace(PccConfig::from_client_config(&config));
+    let clock = SessionClock::new();
+    // Frames go back to the pool once diffed against and capture converts
+    // into them, so steady sharing allocates no new frame buffers
+    let pool = FramePool::default();
+    let mut capture = ScreenCapture::with_display(config.display)?;
+    capture.set_clock(clock);
+    capture.set_pool(pool.clone());
+    capture.configure(config.quality)?;
+    let (width, height) = (capture.width(), capture.height());
+
+    let mut detector = PCCDetector::default();
+    detector.configure(config.quality)?;
+    let mut pipeline = FramePipeline::new(detector).with_frame_ring(config.debug.dump_frames).with_pool(pool.clone());
+    let encoder = FrameEncoder::new(width, height, config.quality)?;
+    let mut input = RemoteInput::new(&config.input, width, height)?;
+
+    let manager = NetworkManager::new_client(config.network.clone()).await?;
+    let connection = manager.connect(config.viewer).await?;
+    let session = open_session(&connection, &config, input.offered_permission(), &monitor).await?;
+    // Nothing is sent until the viewer is approved
+    let viewer = connection.viewer();
+    if config.network.approve_viewers && !approve_viewer(&config, viewer.clone()).await? {
+        info!("Not sharing with {}", viewer);
+        connection.close(CloseReason::NotApproved).await?;
+        manager.wait_idle(CLOSE_TIMEOUT).await;
+        return Ok(CloseReason::NotApproved);
+    }
+    if config.network.require_recording_state && connection.viewer_recording().borrow().is_none() {
+        warn!("{} doesn't say whether it records; not sharing", viewer);
+        connection.close(CloseReason::RecordingUndisclosed).await?;
+        manager.wait_idle(CLOSE_TIMEOUT).await;
+        return Ok(CloseReason::RecordingUndisclosed);
+    }
+    if config.watermark.enabled {
+        let watermark = Watermark::for_viewer(&viewer, &config.watermark);
+        info!("Marking frames with {:?}", watermark.text());
+        pipeline = pipeline.with_watermark(watermark);
+    }
+    // Only input is worth auditing, so view-only hosts don't
+    let audit = if config.input.allow_control { AuditLog::open(&config.audit)? } else { AuditLog::default() };
+    let audit = audit.session(viewer.clone());
+    audit.connected();
+    input.set_audit(audit.clone());
+    input.set_permission(session.permission);
+    config.events.record(SessionEvent::Connect { peer: config.viewer, resumed: session.resume_from.is_some() });
+    info!("Sharing {}x{} screen with {} as {:?}", width, height, config.viewer, config.codec);
+
+    connection.send_message(&Message::ColorSpace(capture.color_space())).await?;
+    let mut events = connection.control_events();
+
+    let controls = AudioControls::default();
+    let mut audio = match &config.audio {
+        Some(audio) => start_audio(audio, clock, &connection, &controls).await,
+        None => Vec::new(),
+    };
+    let bitrates: Vec<BitrateControl> = audio.iter().map(|shared| shared.streamer.bitrate_control()).collect();
+    let bandwidth = connection.bandwidth_monitor();
+    let mut estimator = BandwidthEstimator::new(config.network.target_bandwidth as u64 * 8);
+    let video_bitrate = AtomicU64::new(0);
+
+    // Shutdown lets the frame and audio in flight finish sending, rather
+    // than cutting them off mid-stream
+    let (stop, stopping) = watch::channel(false);
+    let counters = FrameCounters::default();
+    let (quality, reconfigured) = watch::channel(config.quality);
+    let dump_requests = Notify::new();
+    let (paused, _) = watch::channel(false);
+    let sharing = Sharing {
+        encoder: &encoder,
+        connection: &connection,
+        counters: &counters,
+        events: &config.events,
+        dump_dir: &config.debug.dump_dir,
+        dump_requests: &dump_requests,
+        paused: &paused,
+        pool: &pool,
+        watchdog: config.watchdog,
+        suspend_after: config.network.idle_timeout.filter(|_| config.network.idle_action == IdleAction::Suspend),
+    };
+    let streams = async {
+        tokio::try_join!(
+            share_screen(&mut capture, &mut pipeline, &sharing, reconfigured, stopping.clone()),
+            stream_all_audio(&mut audio, stopping.clone()),
+        )
+    };
+    let result = tokio::select! {
+        reason = handle_host_events(&mut events, &encoder, &mut input, &controls) => Ok(reason),
+        result = streams => result.map(|_| CloseReason::HostStoppedSharing),
+        _ = bandwidth.adapt_bitrates(&mut estimator, &video_bitrate, &bitrates) => Ok(CloseReason::ConnectionLost),
+        reason = monitor.run(config.viewer, &bandwidth, &sharing, &quality, &video_bitrate, &config.network) => Ok(reason),
+        _ = async {
+            shutdown.await;
+            info!("Stopping screen sharing");
+            let _ = stop.send(true);
+            std::future::pending::<()>().await
+        } => unreachable!("Shutdown waits for sharing to stop"),
+    };
+
+    monitor.status.send_modify(|status| status.viewer = None);
+    input.release_all();
+    for shared in audio {
+        if let Err(e) = shared.stream.finish().await {
+            debug!("Failed to finish audio stream: {}", e);
+        }
+    }
+    let reason = match &result {
+        Ok(reason) => *reason,
+        Err(e) => {
+            config.events.record(SessionEvent::Error { message: format!("{:#}", e) });
+            CloseReason::ProtocolError
+        }
+    };
+    config.events.record(SessionEvent::Disconnect { peer: config.viewer, reason });
+    // A viewer that won't be back has no session to resume
+    if !reason.may_reconnect() {
+        config.resume.clear();
+    }
+    audit.disconnected(reason);
+    connection.close(reason).await?;
+    // The event tasks end once the connection is closed
+    let _ = time::timeout(CLOSE_TIMEOUT, async { while events.recv().await.is_some() {} }).await;
+    manager.wait_idle(CLOSE_TIMEOUT).await;
+    info!("Screen sharing ended: {}", reason);
+    result
+}
+
+/// Open the session with the viewer on `connection`, offering it at most
+/// `permission`. The session in `config.resume` is picked up if the viewer
+/// still has it, and the one opened is kept there for the next reconnect.
+/// With `network.paired_viewers`, pairs with the viewer first if it isn't
+/// paired, showing the PIN in `monitor`'s status.
+pub async fn open_session(
+    connection: &Connection,
+    config: &ClientConfig,
+    permission: Permission,
+    monitor: &SessionMonitor,
+) -> Result<SessionInfo> {
+    let resume_token = config.resume.token();
+    let session = match &config.network.paired_viewers {
+        Some(paired) => open_paired_session(connection, paired, resume_token, permission, &monitor.status).await?,
+        None => connection.handshake(resume_token, permission).await?,
+    };
+    if let Some(frame_id) = session.resume_from {
+        info!("Resumed the session after frame {}", frame_id);
+    }
+    config.resume.keep(&session);
+    Ok(session)
+}
+
+// Open the session with a viewer paired before, or pair with it by a PIN
+// shown here and in the status, and remember it
+async fn open_paired_session(
+    connection: &Connection,
+    paired: &Path,
+    resume_token: Option<ResumeToken>,
+    permission: Permission,
+    status: &watch::Sender<SessionStatus>,
+) -> Result<SessionInfo> {
+    let mut viewers = PairedViewers::load(paired)?;
+    let viewer = connection.viewer_fingerprint().context("Viewer presented no certificate to pair with")?;
+    if viewers.contains(&viewer) {
+        debug!("Viewer {} is paired", viewer);
+        let session = connection.handshake(resume_token, permission).await?;
+        // A viewer that signed the payload key exchange must have signed
+        // as the one paired
+        if connection.viewer_fingerprint() != Some(viewer) {
+            anyhow::bail!("Viewer signed the key exchange as someone other than paired viewer {}", viewer);
+        }
+        return Ok(session);
+    }
+
+    let pin = PairingPin::generate()?;
+    info!(
+        "Pairing PIN: {} (enter it on the viewer within {} seconds)",
+        pin.code(),
+        PIN_LIFETIME.as_secs()
+    );
+    status.send_modify(|status| status.pairing_pin = Some(pin.code().to_string()));
+    let session = connection.pair(resume_token, permission, &pin).await;
+    status.send_modify(|status| status.pairing_pin = None);
+    let session = session?;
+    // Through a relay, the identity the viewer signed with rather than the
+    // relay's certificate
+    let viewer = connection.viewer_fingerprint().unwrap_or(viewer);
+    viewers.add(viewer)?;
+    info!("Paired with viewer {}", viewer);
+    Ok(session)
+}
+
+// Whether `viewer` may see the screen: trusted and paired viewers may,
+// others if the approval prompt says so
+async fn approve_viewer(config: &ClientConfig, viewer: ViewerInfo) -> Result<bool> {
+    if let Some(fingerprint) = viewer.fingerprint {
+        let paired = match &config.network.paired_viewers {
+            Some(paired) => PairedViewers::load(paired)?.contains(&fingerprint),
+            None => false,
+        };
+        if paired || config.network.trusted_viewers.contains(&fingerprint) {
+            debug!("Viewer {} is trusted", viewer);
+            return Ok(true);
+        }
+    }
+    info!("Asking whether to share with {}", viewer);
+    let approval = config.approval.clone();
+    Ok(tokio::task::spawn_blocking(move || approval.approve(&viewer)).await?)
+}
+
+fn frame_interval(fps: u32) -> time::Interval {
+    let mut interval = time::interval(Duration::from_secs(1) / fps.max(1));
+    interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+    interval
+}
+
+// Resolves once the stop signal is sent, or its sender is gone
+async fn stopped(stopping: &mut watch::Receiver<bool>) {
+    let _ = stopping.wait_for(|stop| *stop).await;
+}
+
+// What sharing frames uses of the session
+struct Sharing<'a> {
+    encoder: &'a FrameEncoder,
+    connection: &'a Connection,
+    counters: &'a FrameCounters,
+    events: &'a SessionLog,
+    // Where kept frames are dumped, on request or when sharing fails
+    dump_dir: &'a Path,
+    dump_requests: &'a Notify,
+    // Whether viewers are shown the paused placeholder instead
+    paused: &'a watch::Sender<bool>,
+    // Shared by capture and the frame pipeline
+    pool: &'a FramePool,
+    watchdog: WatchdogConfig,
+    // How long the screen may be unchanged before capture is suspended
+    suspend_after: Option<Duration>,
+}
+
+// Capture, diff and send frames at the target frame rate until stopped or
+// sending fails, applying quality settings as they're reconfigured
+async fn share_screen(
+    capture: &mut ScreenCapture,
+    pipeline: &mut FramePipeline<PCCDetector>,
+    sharing: &Sharing<'_>,
+    mut quality: watch::Receiver<QualityConfig>,
+    mut stopping: watch::Receiver<bool>,
+) -> Result<()> {
+    let config = *quality.borrow_and_update();
+    let mut interval = frame_interval(config.target_fps);
+    let mut watchdog = Watchdog::new(sharing.watchdog);
+    let mut last_change = Instant::now();
+    let mut suspended = false;
+    let mut paused = sharing.paused.subscribe();
+    // Lets the viewer show what each host is sharing at
+    sharing.connection.send_message(&Message::QualityConfig(config)).await?;
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = stopped(&mut stopping) => return Ok(()),
+            Ok(()) = quality.changed() => {
+                let config = *quality.borrow_and_update();
+                capture.configure(config)?;
+                pipeline.configure(config)?;
+                sharing.encoder.reconfigure(config).await?;
+                if !suspended {
+                    interval = frame_interval(config.target_fps);
+                }
+                sharing.connection.send_message(&Message::QualityConfig(config)).await?;
+                info!("Reconfigured to {} fps at quality {:.2}", config.target_fps, config.quality);
+                continue;
+            }
+            _ = sharing.dump_requests.notified() => {
+                dump_frames(pipeline, sharing.dump_dir);
+                continue;
+            }
+            Ok(()) = paused.changed() => {
+                if *paused.borrow_and_update() {
+                    info!("Sharing paused; showing the placeholder");
+                    send_placeholder(capture, pipeline, sharing).await?;
+                } else {
+                    info!("Sharing resumed");
+                    sharing.encoder.force_keyframe();
+                    last_change = Instant::now();
+                }
+                continue;
+            }
+        }
+        // Nothing is captured while paused, so nothing of the screen leaks
+        if *paused.borrow() {
+            continue;
+        }
+        // Over video's share of the bandwidth, frames are skipped until the
+        // ones sent are paid off
+        if !sharing.encoder.admit_frame(Instant::now()) {
+            continue;
+        }
+        let span = debug_span!("frame", frame_id = field::Empty);
+        let changed = sharing.counters.changed_pixels.load(Ordering::Relaxed);
+        let result = share_frame(capture, pipeline, sharing, &mut watchdog).instrument(span).await;
+        if sharing.counters.changed_pixels.load(Ordering::Relaxed) > changed {
+            last_change = Instant::now();
+            if suspended {
+                info!("Screen changed; resuming capture");
+                interval = frame_interval(quality.borrow().target_fps);
+                suspended = false;
+            }
+        } else if !suspended && sharing.suspend_after.is_some_and(|after| last_change.elapsed() >= after) {
+            info!("Screen unchanged for {:.0?}; suspending capture until it changes", last_change.elapsed());
+            interval = time::interval(SUSPENDED_INTERVAL);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+            suspended = true;
+        }
+        let Err(e) = result else {
+            continue;
+        };
+        let now = Instant::now();
+        match watchdog.failed(e.stage, now) {
+            Recovery::Retry => debug!("{}; retrying with the next frame", e),
+            Recovery::Restart => {
+                warn!("{}; restarting {} ({})", e, e.stage, watchdog.diagnostics(now));
+                if let Err(e) = restart_stage(e.stage, capture, pipeline, *quality.borrow()) {
+                    warn!("Failed to restart: {:#}", e);
+                }
+            }
+            Recovery::GiveUp => {
+                warn!("{}; giving up ({})", e, watchdog.diagnostics(now));
+                dump_frames(pipeline, sharing.dump_dir);
+                return Err(e.into());
+            }
+        }
+    }
+}
+
+// Recreate the component behind a stalled stage
+fn restart_stage(
+    stage: PipelineStage,
+    capture: &mut ScreenCapture,
+    pipeline: &mut FramePipeline<PCCDetector>,
+    config: QualityConfig,
+) -> Result<()> {
+    match stage {
+        PipelineStage::Capture => capture.reopen(),
+        // Starting over from a keyframe drops whatever state went bad
+        PipelineStage::Encode => {
+            pipeline.reset();
+            pipeline.configure(config)
+        }
+        PipelineStage::Send => anyhow::bail!("Sending can't be restarted"),
+    }
+}
+
+// Dump the kept frames, if any, logging rather than failing
+fn dump_frames(pipeline: &FramePipeline<PCCDetector>, dir: &Path) {
+    match pipeline.dump_frames(dir) {
+        Ok(Some(_)) => {}
+        Ok(None) => info!("No frames kept to dump; set dump_frames in [debug]"),
+        Err(e) => warn!("Failed to dump frames: {:#}", e),
+    }
+}
+
+// Send the paused placeholder whole, in place of the screen
+async fn send_placeholder(
+    capture: &ScreenCapture,
+    pipeline: &mut FramePipeline<PCCDetector>,
+    sharing: &Sharing<'_>,
+) -> Result<()> {
+    let FrameOutput::Keyframe(frame) = pipeline.process(capture.placeholder_frame(), true)? else {
+        unreachable!("Frames asked for whole are keyframes");
+    };
+    sharing.events.record(SessionEvent::Keyframe { frame_id: frame.id });
+    sharing.connection.send_encoded(&EncodedFrame::keyframe(&frame)?).await
+}
+
+// Capture, diff and send one frame, in a span per stage under the frame's
+// span so traces show where a slow frame spent its time
+async fn share_frame(
+    capture: &ScreenCapture,
+    pipeline: &mut FramePipeline<PCCDetector>,
+    sharing: &Sharing<'_>,
+    watchdog: &mut Watchdog,
+) -> Result<(), StageError> {
+    let Sharing { encoder, connection, counters, events, .. } = *sharing;
+    let start = Instant::now();
+    let frame = debug_span!("capture").in_scope(|| capture.capture_frame()).at_stage(PipelineStage::Capture)?;
+    watchdog.progress(PipelineStage::Capture, Instant::now());
+    let frame_id = frame.id;
+    Span::current().record("frame_id", frame_id);
+    let capture_time = start.elapsed();
+    let pixels = frame.width as u64 * frame.height as u64;
+    counters.captured.fetch_add(1, Ordering::Relaxed);
+    counters.pixels.fetch_add(pixels, Ordering::Relaxed);
+
+    let start = Instant::now();
+    let output = debug_span!("detect")
+        .in_scope(|| pipeline.process(frame, encoder.take_keyframe_request()))
+        .at_stage(PipelineStage::Encode)?;
+    let detect_time = start.elapsed();
+
+    let encoded = debug_span!("encode")
+        .in_scope(|| match &output {
+            FrameOutput::Keyframe(frame) => EncodedFrame::keyframe(frame).map(Some),
+            FrameOutput::Update(update) => EncodedFrame::update(update).map(Some),
+            FrameOutput::Scroll { copy, update } => EncodedFrame::scroll(*copy, update).map(Some),
+            FrameOutput::Unchanged => Ok(None),
+        })
+        .at_stage(PipelineStage::Encode)?;
+    watchdog.progress(PipelineStage::Encode, Instant::now());
+    let timings = FrameTimings {
+        frame_id,
+        capture: capture_time,
+        detect: detect_time,
+        encode: start.elapsed() - detect_time,
+    };
+    {
+        let mut telemetry = counters.telemetry.lock().unwrap();
+        telemetry.record(Stage::Capture, timings.capture);
+        telemetry.record(Stage::Detect, timings.detect);
+        if encoded.is_some() {
+            telemetry.record(Stage::Encode, timings.encode);
+        }
+    }
+
+    if let Some(encoded) = encoded {
+        if matches!(output, FrameOutput::Keyframe(_)) {
+            debug!("Sending keyframe {}", frame_id);
+            events.record(SessionEvent::Keyframe { frame_id });
+        }
+        let send = async {
+            connection.send_encoded(&encoded).await?;
+            // Lets the viewer break its latency down by stage
+            connection.send_message(&Message::FrameTimings(timings)).await
+        };
+        let stall_timeout = watchdog.config().stall_timeout;
+        time::timeout(stall_timeout, send)
+            .await
+            .with_context(|| format!("Sending frame {} stalled for {:?}", frame_id, stall_timeout))
+            .and_then(|sent| sent)
+            .at_stage(PipelineStage::Send)?;
+        let sent = Instant::now();
+        encoder.frame_sent(encoded.len(), sent);
+        watchdog.progress(PipelineStage::Send, sent);
+    }
+    let changed = match output {
+        FrameOutput::Keyframe(_) => pixels,
+        FrameOutput::Update(update) => {
+            update.changes.iter().map(|change| change.width as u64 * change.height as u64).sum()
+        }
+        FrameOutput::Scroll { copy, update } => {
+            let copied = copy.dst_rect.width as u64 * copy.dst_rect.height as u64;
+            copied + update.changes.iter().map(|change| change.width as u64 * change.height as u64).sum::<u64>()
+        }
+        FrameOutput::Unchanged => 0,
+    };
+    counters.changed_pixels.fetch_add(changed, Ordering::Relaxed);
+    counters.processed.fetch_add(1, Ordering::Relaxed);
+    counters.encode_nanos.fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    Ok(())
+}
+
+// Open a stream for each audio source `config` enables. Audio is optional,
+// so sources that fail to start are logged and left out.
+async fn start_audio(
+    config: &AudioConfig,
+    clock: SessionClock,
+    connection: &Connection,
+    controls: &AudioControls,
+) -> Vec<SharedAudio> {
+    let captures = match config.start_capture(clock) {
+        Ok(captures) => captures,
+        Err(e) => {
+            warn!("Not sharing audio: {}", e);
+            return Vec::new();
+        }
+    };
+
+    let mut shared = Vec::new();
+    for capture in captures {
+        let source = capture.source();
+        let started = async {
+            let streamer = AudioStreamer::new(source, capture.format(), opus_encoder(capture.format())?);
+            let stream = connection.open_audio_stream(&streamer).await?;
+            Ok::<_, anyhow::Error>((streamer, stream))
+        };
+        match started.await {
+            Ok((streamer, stream)) => {
+                controls.add(&streamer);
+                shared.push(SharedAudio { capture, streamer, stream });
+            }
+            Err(e) => warn!("Not sharing {:?} audio: {}", source, e),
+        }
+    }
+    shared
+}
+
+// Send every source's audio as it is captured, until stopped. Captures hold
+// their device streams, which must stay on this thread, so they are polled
+// together here rather than spawned.
+async fn stream_all_audio(audio: &mut Vec<SharedAudio>, mut stopping: watch::Receiver<bool>) -> Result<()> {
+    loop {
+        let next = poll_fn(|cx| {
+            for (index, shared) in audio.iter_mut().enumerate() {
+                if let Poll::Ready(captured) = shared.capture.poll_next(cx) {
+                    return Poll::Ready((index, captured));
+                }
+            }
+            Poll::Pending
+        });
+        let (index, captured) = tokio::select! {
+            next = next => next,
+            _ = stopped(&mut stopping) => break,
+        };
+
+        let Some(captured) = captured else {
+            let stopped = audio.remove(index);
+            info!("{:?} audio capture stopped", stopped.streamer.source());
+            continue;
+        };
+        let shared = &mut audio[index];
+        let packets = shared.streamer.push(captured.pts, &captured.samples)?;
+        shared
+            .stream
+            .send(&packets)
+            .await
+            .with_context(|| format!("Failed to send {:?} audio", shared.streamer.source()))?;
+    }
+
+    // Send what is left of each source's last packet
+    for shared in audio.iter_mut() {
+        let packets = shared.streamer.flush()?;
+        if let Err(e) = shared.stream.send(&packets).await {
+            debug!("Failed to flush {:?} audio: {}", shared.streamer.source(), e);
+        }
+    }
+    Ok(())
+}
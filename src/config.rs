@@ -0,0 +1,233 @@
+use crate::audit::AuditConfig;
+use crate::client::ClientConfig;
+use crate::encoder::VideoCodec;
+use crate::frame_dump::DumpConfig;
+use crate::network::{NetworkConfig, ResilienceConfig};
+use crate::pcc::QualityConfig;
+use crate::server::renderer::RendererOptions;
+use crate::watchdog::WatchdogConfig;
+use crate::watermark::WatermarkConfig;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::time;
+use toml::Value;
+use tracing::info;
+
+/// Config file read from the working directory when none is given
+pub const DEFAULT_CONFIG_FILE: &str = "pcc.toml";
+/// Prefix of environment variables that override the config file, e.g.
+/// `PCC_QUALITY_TARGET_FPS=60` or `PCC_NETWORK_PORT=5900`
+pub const ENV_PREFIX: &str = "PCC_";
+// How often a watched config file is checked for changes
+const RELOAD_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What to capture, from the `[capture]` section
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CaptureConfig {
+    /// Display to share, by its index in `capture::list_displays`
+    pub display: usize,
+    pub codec: VideoCodec,
+}
+
+/// Settings for a deployment, from `pcc.toml`. Every section and setting is
+/// optional and falls back to its default.
+///
+/// A running session's `config_snapshot()` gives the settings it is using
+/// as one of these; `to_toml` writes it out to attach to a bug report, and
+/// loading that file sets the same session up again.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PccConfig {
+    pub capture: CaptureConfig,
+    pub quality: QualityConfig,
+    pub network: NetworkConfig,
+    pub resilience: ResilienceConfig,
+    /// How a viewer presents what it's shown
+    pub renderer: RendererOptions,
+    pub debug: DumpConfig,
+    pub watchdog: WatchdogConfig,
+    pub watermark: WatermarkConfig,
+    pub audit: AuditConfig,
+}
+
+impl PccConfig {
+    /// Read `path`, or `pcc.toml` if it exists when no path is given, then
+    /// apply overrides from `PCC_*` environment variables
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let default_path = Path::new(DEFAULT_CONFIG_FILE);
+        let path = match path {
+            Some(path) => Some(path),
+            None => Some(default_path).filter(|path| path.exists()),
+        };
+        let config = match path {
+            Some(path) => {
+                let text = std::fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read {}", path.display()))?;
+                Self::from_toml(&text).with_context(|| format!("Invalid config in {}", path.display()))?
+            }
+            None => Self::default(),
+        };
+        config.with_overrides(std::env::vars())
+    }
+
+    /// Parse a config from TOML text
+    pub fn from_toml(text: &str) -> Result<Self> {
+        Ok(toml::from_str(text)?)
+    }
+
+    /// Every setting as TOML, defaults included, which `from_toml` and
+    /// `load` read back
+    pub fn to_toml(&self) -> Result<String> {
+        toml::to_string(self).context("Failed to write config as TOML")
+    }
+
+    /// The settings a host shares with, as given to it. Sections a host
+    /// doesn't use keep their defaults.
+    pub fn from_client_config(config: &ClientConfig) -> Self {
+        Self {
+            capture: CaptureConfig { display: config.display, codec: config.codec },
+            quality: config.quality,
+            network: config.network.clone(),
+            debug: config.debug.clone(),
+            watchdog: config.watchdog,
+            watermark: config.watermark,
+            audit: config.audit.clone(),
+            ..Self::default()
+        }
+    }
+
+    /// Override settings from `PCC_<SECTION>_<SETTING>` variables in `vars`.
+    /// Durations are given in seconds. Other variables are ignored.
+    pub fn with_overrides(self, vars: impl IntoIterator<Item = (String, String)>) -> Result<Self> {
+        let mut value = Value::try_from(&self)?;
+        let Value::Table(sections) = &mut value else {
+            bail!("Config is not a table");
+        };
+
+        for (name, text) in vars {
+            let Some(setting) = name.strip_prefix(ENV_PREFIX) else { continue };
+            let setting = setting.to_lowercase();
+            let Some((section, key)) = setting.split_once('_') else { continue };
+            let Some(Value::Table(section)) = sections.get_mut(section) else { continue };
+
+            let parsed = parse_override(section.get(key), &text)
+                .with_context(|| format!("Invalid value {:?} for {}", text, name))?;
+            section.insert(key.to_string(), parsed);
+        }
+
+        value.try_into().context("Invalid config from environment")
+    }
+
+    /// Settings for sharing this machine's screen with `viewer`
+    pub fn client_config(&self, viewer: SocketAddr) -> ClientConfig {
+        ClientConfig {
+            viewer,
+            display: self.capture.display,
+            codec: self.capture.codec,
+            network: self.network.clone(),
+            quality: self.quality,
+            debug: self.debug.clone(),
+            watchdog: self.watchdog,
+            watermark: self.watermark,
+            audit: self.audit.clone(),
+            ..ClientConfig::default()
+        }
+    }
+}
+
+/// Notices when settings should be reloaded: when the config file is
+/// changed, created or removed, or on SIGHUP on unix
+pub struct ConfigWatcher {
+    // As given to `PccConfig::load`
+    path: Option<PathBuf>,
+    file: PathBuf,
+    modified: Option<SystemTime>,
+    #[cfg(unix)]
+    hangup: tokio::signal::unix::Signal,
+}
+
+impl ConfigWatcher {
+    /// Watch `path`, or `pcc.toml` when none is given, as `PccConfig::load`
+    /// reads them. Must be called within a Tokio runtime.
+    pub fn new(path: Option<&Path>) -> Result<Self> {
+        let file = path.unwrap_or(Path::new(DEFAULT_CONFIG_FILE)).to_path_buf();
+        Ok(Self {
+            path: path.map(Path::to_path_buf),
+            modified: modified(&file),
+            file,
+            #[cfg(unix)]
+            hangup: tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?,
+        })
+    }
+
+    /// Wait until the settings should be reloaded, then load them again
+    pub async fn changed(&mut self) -> Result<PccConfig> {
+        let mut interval = time::interval(RELOAD_POLL_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = self.hangup() => {
+                    info!("Reloading config on SIGHUP");
+                    break;
+                }
+            }
+            let modified = modified(&self.file);
+            if modified != self.modified {
+                self.modified = modified;
+                info!("Reloading changed {}", self.file.display());
+                break;
+            }
+        }
+        PccConfig::load(self.path.as_deref())
+    }
+
+    #[cfg(unix)]
+    async fn hangup(&mut self) {
+        self.hangup.recv().await;
+    }
+
+    #[cfg(not(unix))]
+    async fn hangup(&mut self) {
+        std::future::pending().await
+    }
+}
+
+fn modified(file: &Path) -> Option<SystemTime> {
+    std::fs::metadata(file).and_then(|metadata| metadata.modified()).ok()
+}
+
+// Parse `text` as the same kind of value as `current`. Settings that are
+// unset, like a port left to the OS, take whichever kind `text` looks like.
+fn parse_override(current: Option<&Value>, text: &str) -> Result<Value> {
+    let text = text.trim();
+    Ok(match current {
+        Some(Value::Integer(_)) => Value::Integer(text.parse()?),
+        Some(Value::Float(_)) => Value::Float(text.parse()?),
+        Some(Value::Boolean(_)) => Value::Boolean(text.parse()?),
+        Some(Value::String(_)) => Value::String(text.to_string()),
+        // Durations are tables of whole seconds and nanoseconds
+        Some(Value::Table(table)) if table.contains_key("secs") && table.contains_key("nanos") => {
+            let duration = std::time::Duration::try_from_secs_f64(text.parse()?)?;
+            let mut table = toml::map::Map::new();
+            table.insert("secs".to_string(), Value::Integer(duration.as_secs() as i64));
+            table.insert("nanos".to_string(), Value::Integer(duration.subsec_nanos() as i64));
+            Value::Table(table)
+        }
+        Some(other) => bail!("Can't override a {} from the environment", other.type_str()),
+        None => {
+            if let Ok(integer) = text.parse() {
+                Value::Integer(integer)
+            } else if let Ok(float) = text.parse() {
+                Value::Float(float)
+            } else if let Ok(boolean) = text.parse() {
+                Value::Boolean(boolean)
+            } else {
+                Value::String(text.to_string())
+            }
+        }
+    })
+}
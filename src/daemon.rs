@@ -0,0 +1,130 @@
+use anyhow::{Context, Result};
+use fs2::FileExt;
+use std::fs::{File, OpenOptions};
+use std::future::Future;
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::pin::pin;
+use std::time::{Duration, Instant};
+use tokio::sync::watch;
+use tokio::time;
+use tracing::{info, warn};
+
+/// How a supervised pipeline is restarted after it stops
+#[derive(Debug, Clone)]
+pub struct RestartPolicy {
+    /// Wait before the first restart
+    pub initial_delay: Duration,
+    /// Longest wait, reached by doubling after each quick failure
+    pub max_delay: Duration,
+    /// A run lasting at least this long starts the backoff over
+    pub reset_after: Duration,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            reset_after: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Resolves once the supervisor is shutting down. Each run of a pipeline
+/// gets one, so it can stop cleanly rather than being cut off.
+#[derive(Debug, Clone)]
+pub struct Stopping(watch::Receiver<bool>);
+
+impl Stopping {
+    pub(crate) fn new(stop: watch::Receiver<bool>) -> Self {
+        Self(stop)
+    }
+
+    pub async fn wait(mut self) {
+        let _ = self.0.wait_for(|stop| *stop).await;
+    }
+}
+
+/// Run `pipeline` until `shutdown` completes, starting it again with
+/// backoff whenever it fails or ends on its own. On shutdown the current
+/// run is told to stop and awaited.
+pub async fn supervise<F, Fut>(policy: RestartPolicy, shutdown: impl Future<Output = ()>, mut pipeline: F) -> Result<()>
+where
+    F: FnMut(Stopping) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let (stop, stopping) = watch::channel(false);
+    let mut shutdown = pin!(shutdown);
+    let mut delay = policy.initial_delay;
+
+    loop {
+        let started = Instant::now();
+        let mut run = pin!(pipeline(Stopping::new(stopping.clone())));
+        let result = tokio::select! {
+            result = &mut run => result,
+            _ = &mut shutdown => {
+                let _ = stop.send(true);
+                return run.await;
+            }
+        };
+
+        if started.elapsed() >= policy.reset_after {
+            delay = policy.initial_delay;
+        }
+        match result {
+            Ok(()) => info!("Pipeline stopped; restarting in {:?}", delay),
+            Err(e) => warn!("Pipeline failed: {:#}; restarting in {:?}", e, delay),
+        }
+        tokio::select! {
+            _ = time::sleep(delay) => {}
+            _ = &mut shutdown => return Ok(()),
+        }
+        delay = (delay * 2).min(policy.max_delay);
+    }
+}
+
+/// A pidfile holding this process's id. It stays locked while held, so a
+/// second daemon given the same file refuses to start, and is removed when
+/// dropped.
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+    _file: File,
+}
+
+impl PidFile {
+    pub fn create(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .with_context(|| format!("Failed to open pidfile {}", path.display()))?;
+
+        if file.try_lock_exclusive().is_err() {
+            let mut running = String::new();
+            let _ = file.read_to_string(&mut running);
+            anyhow::bail!("Already running as pid {} ({})", running.trim(), path.display());
+        }
+        file.set_len(0)?;
+        file.rewind()?;
+        writeln!(file, "{}", std::process::id())
+            .and_then(|()| file.flush())
+            .with_context(|| format!("Failed to write pidfile {}", path.display()))?;
+
+        Ok(Self { path, _file: file })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
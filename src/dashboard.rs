@@ -0,0 +1,220 @@
+use crate::client::{SessionCommand, SessionRemote, SessionStatus};
+use anyhow::Result;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Paragraph, Sparkline};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+// Bitrate samples kept for the graph
+const HISTORY: usize = 120;
+const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+// How much + and - change the quality
+const QUALITY_STEP: f32 = 0.05;
+const MIN_QUALITY: f32 = 0.05;
+
+/// Live status of a sharing session in the terminal, with keys to force a
+/// keyframe, change the quality or pause sharing
+pub struct Dashboard {
+    remote: SessionRemote,
+    status: SessionStatus,
+    bitrates: VecDeque<u64>,
+    // Feedback on the last key press
+    notice: Option<String>,
+}
+
+impl Dashboard {
+    pub fn new(remote: SessionRemote) -> Self {
+        Self {
+            remote,
+            status: SessionStatus::default(),
+            bitrates: VecDeque::with_capacity(HISTORY),
+            notice: None,
+        }
+    }
+
+    /// Take over the terminal until q, Esc or Ctrl+C is pressed or the
+    /// session ends. This blocks, so run it off the async runtime.
+    pub fn run(mut self) -> Result<()> {
+        let mut terminal = ratatui::init();
+        let result = self.event_loop(&mut terminal);
+        ratatui::restore();
+        result
+    }
+
+    fn event_loop(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        while !self.remote.is_closed() {
+            self.refresh();
+            terminal.draw(|frame| self.draw(frame))?;
+            if event::poll(REDRAW_INTERVAL)? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press && !self.handle_key(key) {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Pick up the session's latest status
+    pub fn refresh(&mut self) {
+        let status = self.remote.status();
+        if status != self.status {
+            if self.bitrates.len() == HISTORY {
+                self.bitrates.pop_front();
+            }
+            self.bitrates.push_back(status.bitrate);
+            self.status = status;
+        }
+    }
+
+    /// Act on a key press, returning false to quit
+    pub fn handle_key(&mut self, key: KeyEvent) -> bool {
+        match key.code {
+            KeyCode::Char('q') | KeyCode::Esc => return false,
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return false,
+            KeyCode::Char('k') => self.send(SessionCommand::ForceKeyframe, "Keyframe requested".to_string()),
+            KeyCode::Char('d') => self.send(SessionCommand::DumpFrames, "Dumping kept frames".to_string()),
+            KeyCode::Char('p') if self.status.paused => self.send(SessionCommand::Resume, "Sharing resumed".to_string()),
+            KeyCode::Char('p') => self.send(SessionCommand::Pause, "Sharing paused".to_string()),
+            KeyCode::Char('+') | KeyCode::Char('=') => self.change_quality(QUALITY_STEP),
+            KeyCode::Char('-') => self.change_quality(-QUALITY_STEP),
+            _ => {}
+        }
+        true
+    }
+
+    fn change_quality(&mut self, step: f32) {
+        let quality = (self.status.quality as f32 / 100.0 + step).clamp(MIN_QUALITY, 1.0);
+        self.send(
+            SessionCommand::SetQuality(quality),
+            format!("Quality set to {:.0}", quality * 100.0),
+        );
+    }
+
+    fn send(&mut self, command: SessionCommand, notice: String) {
+        self.notice = Some(if self.remote.send(command) {
+            notice
+        } else {
+            "Session has ended".to_string()
+        });
+    }
+
+    /// Draw the dashboard into `frame`
+    pub fn draw(&self, frame: &mut Frame) {
+        let status = &self.status;
+        let [stats, graph, help] = Layout::vertical([
+            Constraint::Length(9),
+            Constraint::Min(3),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+
+        let (viewer, viewer_style) = match (status.viewer, &status.pairing_pin) {
+            (Some(viewer), _) if status.viewer_recording => {
+                let paused = if status.paused { ", paused" } else { "" };
+                (format!("{}, recording{}", viewer, paused), Style::new().fg(Color::Red))
+            }
+            (Some(viewer), _) if status.paused => (format!("{}, paused", viewer), Style::new().fg(Color::Yellow)),
+            (Some(viewer), _) => (viewer.to_string(), Style::new().fg(Color::Green)),
+            (None, Some(pin)) => (format!("pairing, enter PIN {} on the viewer", pin), Style::new().fg(Color::Yellow)),
+            (None, None) => ("not connected".to_string(), Style::new().fg(Color::Yellow)),
+        };
+        let loss_style = if status.loss > 0.02 {
+            Style::new().fg(Color::Red)
+        } else {
+            Style::new()
+        };
+        let lines = vec![
+            Line::from(vec!["Viewer   ".bold(), Span::styled(viewer, viewer_style)]),
+            Line::from(vec![
+                "FPS      ".bold(),
+                format!("{:.1} of {}", status.fps, status.target_fps).into(),
+            ]),
+            Line::from(vec![
+                "Bitrate  ".bold(),
+                match status.video_budget {
+                    0 => format_bitrate(status.bitrate),
+                    budget => format!("{}, video paced to {}", format_bitrate(status.bitrate), format_bitrate(budget)),
+                }
+                .into(),
+            ]),
+            Line::from(vec!["RTT      ".bold(), format!("{} ms", status.rtt.as_millis()).into()]),
+            Line::from(vec![
+                "Loss     ".bold(),
+                Span::styled(format!("{:.1}%", status.loss * 100.0), loss_style),
+            ]),
+            Line::from(vec!["Quality  ".bold(), status.quality.to_string().into()]),
+            Line::from(vec![
+                "Data     ".bold(),
+                format!(
+                    "{:.1} MB, {} over the last minute",
+                    status.usage.total_bytes() as f64 / 1e6,
+                    format_bitrate(status.usage.window_bitrate)
+                )
+                .into(),
+            ]),
+        ];
+        frame.render_widget(Paragraph::new(lines).block(Block::bordered().title(" PCC ")), stats);
+
+        let history: Vec<u64> = self.bitrates.iter().copied().collect();
+        frame.render_widget(
+            Sparkline::default()
+                .block(Block::bordered().title(" Bitrate "))
+                .data(&history)
+                .style(Style::new().fg(Color::Cyan)),
+            graph,
+        );
+
+        let mut keys = "k keyframe  +/- quality  p pause  d dump  q quit".to_string();
+        if let Some(notice) = &self.notice {
+            keys = format!("{}  | {}", keys, notice);
+        }
+        frame.render_widget(Paragraph::new(keys).dim(), help);
+    }
+}
+
+fn format_bitrate(bits_per_second: u64) -> String {
+    if bits_per_second >= 1_000_000 {
+        format!("{:.1} Mbps", bits_per_second as f64 / 1_000_000.0)
+    } else {
+        format!("{} kbps", bits_per_second / 1000)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::SessionMonitor;
+    use ratatui::backend::TestBackend;
+    use ratatui::Terminal;
+
+    #[test]
+    fn test_dashboard_renders_and_quits() {
+        let (_monitor, remote) = SessionMonitor::new();
+        let mut dashboard = Dashboard::new(remote);
+        dashboard.status = SessionStatus {
+            fps: 29.5,
+            bitrate: 2_500_000,
+            rtt: Duration::from_millis(12),
+            quality: 80,
+            ..SessionStatus::default()
+        };
+
+        let mut terminal = Terminal::new(TestBackend::new(60, 16)).unwrap();
+        terminal.draw(|frame| dashboard.draw(frame)).unwrap();
+        let screen: String = terminal.backend().buffer().content().iter().map(|cell| cell.symbol()).collect();
+        for text in ["not connected", "29.5", "2.5 Mbps", "12 ms", "80"] {
+            assert!(screen.contains(text), "Missing {:?}", text);
+        }
+
+        assert!(dashboard.handle_key(KeyEvent::from(KeyCode::Char('+'))));
+        assert_eq!(dashboard.notice.as_deref(), Some("Quality set to 85"));
+        assert!(!dashboard.handle_key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL)));
+        assert!(!dashboard.handle_key(KeyEvent::from(KeyCode::Char('q'))));
+    }
+}
@@ -0,0 +1,217 @@
+use crate::client::{self, ClientConfig, SessionCommand, SessionMonitor, SessionRemote, SessionStatus};
+use crate::config::PccConfig;
+use crate::network::CloseReason;
+use crate::server::{self, network::{HostStats, ServerNetwork}, renderer::{PauseMode, Renderer}};
+use anyhow::{Context, Result};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::runtime::Handle;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+use tracing::debug;
+
+// Display surface size, as `pcc serve` defaults to
+const VIEWER_WIDTH: u32 = 1920;
+const VIEWER_HEIGHT: u32 = 1080;
+
+/// Shares this machine's screen with a viewer, as `pcc connect` does.
+///
+/// ```no_run
+/// # async fn share() -> anyhow::Result<()> {
+/// use pixel_change_check_client::{config::PccConfig, Host};
+///
+/// let config = PccConfig::load(None)?.client_config("192.168.1.20:5800".parse()?);
+/// let host = Host::share(config).await?;
+/// tokio::signal::ctrl_c().await?;
+/// println!("Captured {} frames", host.stats().frames_captured);
+/// host.stop().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Host;
+
+impl Host {
+    /// Connect to `config.viewer` and start sharing, returning once the
+    /// viewer has accepted. Fails if capture can't start or the viewer
+    /// can't be reached or turns the host away.
+    pub async fn share(config: ClientConfig) -> Result<HostHandle> {
+        let (monitor, remote) = SessionMonitor::new();
+        let (stop, stopped) = oneshot::channel::<()>();
+        // Audio capture keeps the session on one thread, so it gets a
+        // thread of its own
+        let runtime = Handle::current();
+        let session = tokio::task::spawn_blocking(move || {
+            let shutdown = async {
+                let _ = stopped.await;
+            };
+            runtime.block_on(client::run_monitored(config, shutdown, monitor))
+        });
+
+        let mut connecting = remote.clone();
+        if !connecting.connected().await {
+            // Ended before sharing anything; the session says why
+            let reason = session.await.context("Session task failed")??;
+            anyhow::bail!("Session ended before sharing: {}", reason);
+        }
+        Ok(HostHandle { remote, stop, session })
+    }
+}
+
+/// A running `Host::share` session
+pub struct HostHandle {
+    remote: SessionRemote,
+    stop: oneshot::Sender<()>,
+    session: JoinHandle<Result<CloseReason>>,
+}
+
+impl HostHandle {
+    /// Frame rate, bitrate, quality and the like, as of the last update
+    pub fn stats(&self) -> SessionStatus {
+        self.remote.status()
+    }
+
+    /// Show the viewer a placeholder instead of the screen. Returns false
+    /// once the session has ended.
+    pub fn pause(&self) -> bool {
+        self.remote.send(SessionCommand::Pause)
+    }
+
+    /// Share the screen again after `pause`
+    pub fn resume(&self) -> bool {
+        self.remote.send(SessionCommand::Resume)
+    }
+
+    /// The settings being shared with, to save with `PccConfig::to_toml`
+    /// and share again from
+    pub fn config_snapshot(&self) -> PccConfig {
+        self.remote.config_snapshot()
+    }
+
+    /// For commands beyond pausing, and for status displays
+    pub fn remote(&self) -> SessionRemote {
+        self.remote.clone()
+    }
+
+    /// Whether the session has ended, e.g. because the viewer left
+    pub fn is_finished(&self) -> bool {
+        self.session.is_finished()
+    }
+
+    /// Say goodbye to the viewer and wait for the session to close.
+    /// Returns why it ended, which is `Normal` unless it had already ended.
+    pub async fn stop(self) -> Result<CloseReason> {
+        let _ = self.stop.send(());
+        self.session.await.context("Session task failed")?
+    }
+}
+
+/// Shows the screens hosts share, as `pcc serve` does. The viewer listens
+/// and hosts connect to it, so `connect` opens it to hosts rather than
+/// reaching out to one.
+///
+/// ```no_run
+/// # async fn view() -> anyhow::Result<()> {
+/// use pixel_change_check_client::{config::PccConfig, Viewer};
+///
+/// let viewer = Viewer::connect("0.0.0.0:5800".parse()?, PccConfig::load(None)?).await?;
+/// tokio::signal::ctrl_c().await?;
+/// for host in viewer.stats().await {
+///     println!("{}: {} frames", host.addr, host.frames_delivered());
+/// }
+/// viewer.stop().await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct Viewer;
+
+impl Viewer {
+    /// Listen for hosts on `addr` and present what they share, using the
+    /// network, resilience, renderer and frame rate settings in `config`
+    pub async fn connect(addr: SocketAddr, config: PccConfig) -> Result<ViewerHandle> {
+        let renderer = Arc::new(
+            Renderer::with_options(VIEWER_WIDTH, VIEWER_HEIGHT, config.quality.target_fps, config.renderer).await?,
+        );
+        let network = Arc::new(
+            ServerNetwork::bind(addr, config.network.clone(), config.resilience.clone())?
+                .with_frame_pool(renderer.frame_pool()),
+        );
+        let (stop, stopped) = oneshot::channel::<()>();
+        let session = tokio::spawn({
+            let (network, renderer) = (network.clone(), renderer.clone());
+            async move {
+                let result = tokio::select! {
+                    result = network.start() => result,
+                    result = renderer.start() => result,
+                    result = server::present_incoming(&network, &renderer) => result,
+                    _ = stopped => Ok(()),
+                };
+                // Hosts hear why rather than waiting to time out, and any
+                // recording gets its trailer
+                network.shutdown(CloseReason::Normal).await;
+                renderer.shutdown().await?;
+                result
+            }
+        });
+        Ok(ViewerHandle { network, renderer, config, stop, session })
+    }
+}
+
+/// A running `Viewer::connect` viewer
+pub struct ViewerHandle {
+    network: Arc<ServerNetwork>,
+    renderer: Arc<Renderer>,
+    // As given to `connect`
+    config: PccConfig,
+    stop: oneshot::Sender<()>,
+    session: JoinHandle<Result<()>>,
+}
+
+impl ViewerHandle {
+    /// Address hosts can connect to
+    pub fn addr(&self) -> Result<SocketAddr> {
+        self.network.advertised_addr()
+    }
+
+    /// Traffic from each connected host, in the order they connected
+    pub async fn stats(&self) -> Vec<HostStats> {
+        self.network.host_stats().await
+    }
+
+    /// Freeze the display on the current frame. Frames keep arriving, so
+    /// resuming goes straight to the live one.
+    pub async fn pause(&self) {
+        self.renderer.pause(PauseMode::ApplyUpdates).await;
+    }
+
+    /// Present frames again after `pause`
+    pub async fn resume(&self) -> Result<()> {
+        self.renderer.resume().await?;
+        Ok(())
+    }
+
+    /// The settings the viewer is using: those it was started with, with
+    /// resilience and display options as they are now
+    pub async fn config_snapshot(&self) -> PccConfig {
+        PccConfig {
+            resilience: self.network.resilience_config(),
+            renderer: self.renderer.options().await,
+            ..self.config.clone()
+        }
+    }
+
+    /// For recording, screenshots and the like
+    pub fn renderer(&self) -> &Arc<Renderer> {
+        &self.renderer
+    }
+
+    pub fn network(&self) -> &Arc<ServerNetwork> {
+        &self.network
+    }
+
+    /// Say goodbye to every host and stop presenting
+    pub async fn stop(self) -> Result<()> {
+        let _ = self.stop.send(());
+        debug!("Viewer stopping");
+        self.session.await.context("Viewer task failed")?
+    }
+}
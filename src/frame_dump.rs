@@ -0,0 +1,146 @@
+use crate::client::FrameOutput;
+use crate::pcc::{Frame, Rect};
+use anyhow::{Context, Result};
+use image::RgbImage;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+/// Keeping recent frames for debugging, from the `[debug]` section
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DumpConfig {
+    /// Frames to keep, or 0 to keep none. Each is kept whole, so a 1080p
+    /// frame takes about 6 MB.
+    pub dump_frames: usize,
+    /// Directory dumps are written under, one subdirectory per dump
+    pub dump_dir: PathBuf,
+}
+
+impl Default for DumpConfig {
+    fn default() -> Self {
+        Self {
+            dump_frames: 0,
+            dump_dir: PathBuf::from("pcc-dumps"),
+        }
+    }
+}
+
+/// What change detection made of one kept frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DumpedOutput {
+    Keyframe,
+    Update,
+    /// A block copied within the frame, then changes
+    Scroll,
+    Unchanged,
+}
+
+// One kept frame and the regions found changed in it
+struct Entry {
+    frame: Frame,
+    output: DumpedOutput,
+    changes: Vec<Rect>,
+}
+
+// A kept frame's entry in `frames.json`
+#[derive(Serialize)]
+struct EntryInfo<'a> {
+    frame_id: u64,
+    unix_ms: u64,
+    width: u32,
+    height: u32,
+    output: DumpedOutput,
+    changes: &'a [Rect],
+    image: String,
+}
+
+/// The last few captured frames and their detected changes, for dumping to
+/// disk when the viewer shows something it shouldn't
+pub struct FrameRing {
+    capacity: usize,
+    entries: VecDeque<Entry>,
+}
+
+impl FrameRing {
+    /// Keep the last `capacity` frames
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Keep `frame` and what was sent for it, forgetting the oldest frame
+    /// if full
+    pub fn record(&mut self, frame: &Frame, output: &FrameOutput) {
+        if self.capacity == 0 {
+            return;
+        }
+        let (output, changes) = match output {
+            FrameOutput::Keyframe(_) => (DumpedOutput::Keyframe, vec![Rect::new(0, 0, frame.width, frame.height)]),
+            FrameOutput::Update(update) => (
+                DumpedOutput::Update,
+                update
+                    .changes
+                    .iter()
+                    .map(|change| Rect::new(change.x, change.y, change.width, change.height))
+                    .collect(),
+            ),
+            // The copied block first, then what changed on top of it
+            FrameOutput::Scroll { copy, update } => (
+                DumpedOutput::Scroll,
+                std::iter::once(copy.dst_rect)
+                    .chain(update.changes.iter().map(|change| Rect::new(change.x, change.y, change.width, change.height)))
+                    .collect(),
+            ),
+            FrameOutput::Unchanged => (DumpedOutput::Unchanged, Vec::new()),
+        };
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(Entry { frame: frame.clone(), output, changes });
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Write each kept frame as a PNG, with `frames.json` listing them and
+    /// their changes, into a new directory under `dir`. Returns the new
+    /// directory.
+    pub fn dump(&self, dir: &Path) -> Result<PathBuf> {
+        let unix_ms = |time: SystemTime| time.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_millis() as u64);
+        let dir = dir.join(format!("frames-{}", unix_ms(SystemTime::now())));
+        std::fs::create_dir_all(&dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+
+        let mut index = Vec::with_capacity(self.entries.len());
+        for Entry { frame, output, changes } in &self.entries {
+            let image = format!("frame-{}.png", frame.id);
+            RgbImage::from_raw(frame.width, frame.height, frame.data.to_vec())
+                .with_context(|| format!("Frame {} is not {}x{} RGB", frame.id, frame.width, frame.height))?
+                .save(dir.join(&image))
+                .with_context(|| format!("Failed to write frame {}", frame.id))?;
+            index.push(EntryInfo {
+                frame_id: frame.id,
+                unix_ms: unix_ms(frame.timestamp),
+                width: frame.width,
+                height: frame.height,
+                output: *output,
+                changes,
+                image,
+            });
+        }
+        std::fs::write(dir.join("frames.json"), serde_json::to_string_pretty(&index)?)
+            .with_context(|| format!("Failed to write {}", dir.join("frames.json").display()))?;
+        info!("Dumped {} frames to {}", index.len(), dir.display());
+        Ok(dir)
+    }
+}
@@ -1,12 +1,37 @@
-pub mod capture;
-pub mod encoder;
-pub mod network;
+pub mod benchmark;
+pub mod client;
+pub mod config;
+#[cfg(feature = "tui")]
+pub mod dashboard;
+pub mod daemon;
+pub mod facade;
+pub mod frame_dump;
+pub mod metrics;
+pub mod otel;
 pub mod pcc;
-pub mod server;
+pub mod pipeline;
+pub mod prelude;
+pub mod quality;
+pub mod service;
+pub mod testing;
+pub mod watchdog;
+pub mod watermark;
 
-// Re-export commonly used types
+pub use pcc_capture as capture;
+pub use pcc_capture::privacy;
+pub use pcc_net::{audio, audit, encoder, health, input, network, session_log, telemetry};
+pub use pcc_viewer as server;
+
+/// Tracing targets of pcc's crates, for filters that pick out its own spans
+pub const LOG_TARGETS: &[&str] = &["pixel_change_check_client", "pcc_core", "pcc_capture", "pcc_net", "pcc_viewer"];
+
+// Re-export commonly used types; `prelude` has the traits as well
 pub use capture::ScreenCapture;
+pub use client::{ClientConfig, FramePipeline};
 pub use encoder::FrameEncoder;
-pub use network::{NetworkConfig, QUICTransport, ResilienceConfig, NetworkResilience};
-pub use pcc::{PCCDetector, QualityConfig};
-pub use server::renderer::Renderer; 
\ No newline at end of file
+pub use facade::{Host, Viewer};
+pub use network::{NetworkConfig, NetworkResilience, QUICTransport, ResilienceConfig};
+pub use pcc::{Frame, PCCDetector, PixelFormat, QualityConfig};
+pub use pipeline::Pipeline;
+pub use server::{FrameSink, Renderer};
+ 
\ No newline at end of file
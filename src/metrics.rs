@@ -0,0 +1,356 @@
+use crate::client::SessionStatus;
+use crate::health::Health;
+use crate::pcc::PoolStats;
+use crate::server::network::{HostStats, ServerNetwork};
+use crate::server::Renderer;
+use anyhow::{Context, Result};
+use std::fmt::Write as _;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time;
+use tracing::{debug, info};
+
+// Longest a scraper may take to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_REQUEST_SIZE: usize = 8192;
+const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// A sample's labels, e.g. `[("host", "10.0.0.2:5800")]`
+pub type Labels<'a> = Vec<(&'a str, String)>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricKind {
+    /// A value that goes up and down
+    Gauge,
+    /// A running total
+    Counter,
+}
+
+impl MetricKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            MetricKind::Gauge => "gauge",
+            MetricKind::Counter => "counter",
+        }
+    }
+}
+
+/// Where metrics are written: Prometheus text, or an OpenTelemetry meter
+pub trait MetricsSink {
+    /// A metric with one sample per set of labels
+    fn family<'a>(
+        &mut self,
+        name: &str,
+        help: &str,
+        kind: MetricKind,
+        samples: impl IntoIterator<Item = (Labels<'a>, f64)>,
+    );
+
+    fn gauge(&mut self, name: &str, help: &str, value: f64) {
+        self.family(name, help, MetricKind::Gauge, [(Vec::new(), value)]);
+    }
+
+    fn counter(&mut self, name: &str, help: &str, value: f64) {
+        self.family(name, help, MetricKind::Counter, [(Vec::new(), value)]);
+    }
+}
+
+/// Metrics in the Prometheus text format, written one family at a time
+#[derive(Debug, Default)]
+pub struct MetricsWriter {
+    text: String,
+}
+
+impl MetricsWriter {
+    pub fn finish(self) -> String {
+        self.text
+    }
+}
+
+impl MetricsSink for MetricsWriter {
+    fn family<'a>(
+        &mut self,
+        name: &str,
+        help: &str,
+        kind: MetricKind,
+        samples: impl IntoIterator<Item = (Labels<'a>, f64)>,
+    ) {
+        let _ = writeln!(self.text, "# HELP {} {}", name, help);
+        let _ = writeln!(self.text, "# TYPE {} {}", name, kind.name());
+        for (labels, value) in samples {
+            self.text.push_str(name);
+            if !labels.is_empty() {
+                let labels: Vec<String> = labels
+                    .iter()
+                    .map(|(label, value)| format!("{}=\"{}\"", label, escape_label(value)))
+                    .collect();
+                let _ = write!(self.text, "{{{}}}", labels.join(","));
+            }
+            let _ = writeln!(self.text, " {}", value);
+        }
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Metrics for a host sharing its screen
+pub fn host_metrics(status: &SessionStatus) -> String {
+    let mut metrics = MetricsWriter::default();
+    write_host_metrics(status, &mut metrics);
+    metrics.finish()
+}
+
+pub fn write_host_metrics(status: &SessionStatus, metrics: &mut impl MetricsSink) {
+    metrics.gauge("pcc_connected", "Whether a viewer is connected", status.viewer.is_some() as u8 as f64);
+    metrics.gauge("pcc_capture_fps", "Frames captured per second", status.fps as f64);
+    metrics.counter("pcc_frames_captured_total", "Frames captured", status.frames_captured as f64);
+    metrics.gauge("pcc_change_ratio", "Fraction of captured pixels that changed", status.change_ratio);
+    metrics.gauge(
+        "pcc_encode_seconds",
+        "Mean time to diff, code and send a frame",
+        status.encode_time.as_secs_f64(),
+    );
+    metrics.gauge("pcc_bitrate_bits_per_second", "Bits sent per second", status.bitrate as f64);
+    metrics.gauge(
+        "pcc_video_budget_bits_per_second",
+        "Bits per second frames are paced to, or 0 for no limit",
+        status.video_budget as f64,
+    );
+    metrics.gauge("pcc_rtt_seconds", "Round trip time to the viewer", status.rtt.as_secs_f64());
+    metrics.gauge("pcc_packet_loss_ratio", "Fraction of packets lost", status.loss);
+    metrics.gauge("pcc_quality", "JPEG quality frames are encoded at, 1-100", status.quality as f64);
+    metrics.gauge("pcc_target_fps", "Frame rate quality adaptation aims for", status.target_fps as f64);
+    metrics.gauge(
+        "pcc_change_threshold",
+        "Smallest color difference counted as a change",
+        status.threshold as f64,
+    );
+    metrics.family(
+        "pcc_session_bytes_total",
+        "Bytes used this session, headers included",
+        MetricKind::Counter,
+        [
+            (vec![("direction", "sent".to_string())], status.usage.sent_bytes as f64),
+            (vec![("direction", "received".to_string())], status.usage.received_bytes as f64),
+        ],
+    );
+    metrics.gauge(
+        "pcc_window_bitrate_bits_per_second",
+        "Bits per second both ways over the last minute",
+        status.usage.window_bitrate as f64,
+    );
+    write_pool_metrics(&status.frame_pool, metrics);
+}
+
+fn write_pool_metrics(pool: &PoolStats, metrics: &mut impl MetricsSink) {
+    metrics.gauge("pcc_frame_pool_buffers", "Frame buffers idle in the pool", pool.pooled as f64);
+    metrics.gauge("pcc_frame_pool_high_water", "Most frame buffers idle in the pool at once", pool.high_water as f64);
+    metrics.counter(
+        "pcc_frame_pool_allocations_total",
+        "Frame buffers allocated because none were idle",
+        pool.allocations as f64,
+    );
+    metrics.counter("pcc_frame_pool_reuses_total", "Frame buffers reused", pool.reuses as f64);
+}
+
+/// Metrics for a viewer, with counters for each connected host
+pub async fn viewer_metrics(network: &ServerNetwork, renderer: &Renderer) -> String {
+    let mut metrics = MetricsWriter::default();
+    write_viewer_metrics(network, renderer, &mut metrics).await;
+    metrics.finish()
+}
+
+pub async fn write_viewer_metrics(network: &ServerNetwork, renderer: &Renderer, metrics: &mut impl MetricsSink) {
+    let stats = renderer.overlay_stats().await;
+    let hosts = network.host_stats().await;
+
+    metrics.gauge("pcc_hosts_connected", "Hosts connected", hosts.len() as f64);
+    metrics.gauge("pcc_present_fps", "Frames presented per second", stats.fps as f64);
+    metrics.gauge(
+        "pcc_latency_seconds",
+        "Capture to present latency of the last frame",
+        stats.latency_ms as f64 / 1000.0,
+    );
+    metrics.gauge("pcc_buffer_depth", "Frames waiting in the frame buffer", stats.buffer_depth as f64);
+    metrics.gauge(
+        "pcc_queue_wait_seconds",
+        "Mean time frames wait in the frame buffer",
+        stats.queue_wait_ms as f64 / 1000.0,
+    );
+    metrics.counter("pcc_dropped_frames_total", "Frames the frame buffer dropped", stats.dropped_frames as f64);
+    metrics.gauge(
+        "pcc_bitrate_bits_per_second",
+        "Bits received per second",
+        stats.bitrate_kbps as f64 * 1000.0,
+    );
+    metrics.gauge("pcc_packet_loss_ratio", "Fraction of packets lost", stats.loss_percent as f64 / 100.0);
+    write_pool_metrics(&renderer.frame_pool().stats(), metrics);
+
+    let queues = network.queue_stats();
+    let per_queue = |value: fn(&crate::network::QueueStats) -> f64| {
+        [("frames", queues.frames), ("messages", queues.messages), ("events", queues.events)]
+            .map(|(queue, stats)| (vec![("queue", queue.to_string())], value(&stats)))
+    };
+    metrics.family(
+        "pcc_network_queue_depth",
+        "Items waiting in the network's receive queue",
+        MetricKind::Gauge,
+        per_queue(|stats| stats.queued as f64),
+    );
+    metrics.family(
+        "pcc_network_queue_dropped_total",
+        "Items the receive queue's overflow policy dropped",
+        MetricKind::Counter,
+        per_queue(|stats| stats.dropped as f64),
+    );
+
+    let per_host = |value: fn(&crate::server::network::HostStats) -> f64| {
+        hosts
+            .iter()
+            .map(move |host| (vec![("host", host.addr.to_string())], value(host)))
+            .collect::<Vec<_>>()
+    };
+    metrics.family("pcc_host_rtt_seconds", "Round trip time to the host", MetricKind::Gauge, per_host(|host| host.rtt.as_secs_f64()));
+    metrics.family(
+        "pcc_host_received_bytes_total",
+        "Bytes received from the host",
+        MetricKind::Counter,
+        per_host(|host| host.received_bytes as f64),
+    );
+    metrics.family(
+        "pcc_host_lost_packets_total",
+        "Packets lost to or from the host",
+        MetricKind::Counter,
+        per_host(|host| host.lost_packets as f64),
+    );
+    metrics.family(
+        "pcc_host_keyframes_total",
+        "Whole frames received from the host",
+        MetricKind::Counter,
+        per_host(|host| host.keyframes as f64),
+    );
+    metrics.family(
+        "pcc_host_updates_total",
+        "Delta updates received from the host",
+        MetricKind::Counter,
+        per_host(|host| host.updates as f64),
+    );
+    metrics.family(
+        "pcc_host_missing_frames_total",
+        "Frames the host sent that never arrived",
+        MetricKind::Counter,
+        per_host(|host| host.sequence.missing as f64),
+    );
+    metrics.family(
+        "pcc_host_reordered_frames_total",
+        "Frames from the host that arrived after a later one",
+        MetricKind::Counter,
+        per_host(|host| host.sequence.reordered as f64),
+    );
+    metrics.family(
+        "pcc_host_duplicate_frames_total",
+        "Frames from the host that arrived more than once",
+        MetricKind::Counter,
+        per_host(|host| host.sequence.duplicates as f64),
+    );
+}
+
+/// Answer `GET /metrics` on `addr` with what `collect` returns, `GET
+/// /health` with what `check` finds and `GET /hosts` with the connected
+/// hosts' stats as JSON, freshly for each request, until dropped. Requests
+/// are answered one at a time.
+pub async fn serve<F, Fut, H, HFut, S, SFut>(addr: SocketAddr, collect: F, check: H, hosts: S) -> Result<()>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = String>,
+    H: Fn() -> HFut,
+    HFut: Future<Output = Health>,
+    S: Fn() -> SFut,
+    SFut: Future<Output = Vec<HostStats>>,
+{
+    let listener = TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to listen for metrics scrapes on {}", addr))?;
+    info!("Serving metrics at http://{}/metrics", listener.local_addr()?);
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        if let Err(e) = respond(stream, &collect, &check, &hosts).await {
+            debug!("Metrics scrape from {} failed: {}", peer, e);
+        }
+    }
+}
+
+async fn respond<F, Fut, H, HFut, S, SFut>(mut stream: TcpStream, collect: &F, check: &H, hosts: &S) -> Result<()>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = String>,
+    H: Fn() -> HFut,
+    HFut: Future<Output = Health>,
+    S: Fn() -> SFut,
+    SFut: Future<Output = Vec<HostStats>>,
+{
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    time::timeout(REQUEST_TIMEOUT, async {
+        while !request.windows(4).any(|end| end == b"\r\n\r\n") {
+            anyhow::ensure!(request.len() < MAX_REQUEST_SIZE, "Request too large");
+            let read = stream.read(&mut buf).await?;
+            anyhow::ensure!(read > 0, "Connection closed mid-request");
+            request.extend_from_slice(&buf[..read]);
+        }
+        Ok(())
+    })
+    .await
+    .context("Timed out reading request")??;
+
+    let request_line = request.split(|&byte| byte == b'\r').next().unwrap_or_default();
+    let mut parts = request_line.split(|&byte| byte == b' ');
+    let (status, content_type, body) = match (parts.next(), parts.next()) {
+        (Some(b"GET"), Some(b"/metrics")) => ("200 OK", METRICS_CONTENT_TYPE, collect().await),
+        (Some(b"GET"), Some(b"/health")) => {
+            let health = check().await;
+            // Load balancers and watchdogs go by the status code
+            let status = if health.is_healthy() { "200 OK" } else { "503 Service Unavailable" };
+            (status, "application/json", health.to_json())
+        }
+        (Some(b"GET"), Some(b"/hosts")) => ("200 OK", "application/json", serde_json::to_string(&hosts().await)?),
+        _ => (
+            "404 Not Found",
+            "text/plain",
+            "Metrics are at /metrics, health at /health and host stats at /hosts\n".to_string(),
+        ),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+/// Ask the viewer serving metrics at `addr` for its connected hosts' stats
+pub async fn query_hosts(addr: SocketAddr) -> Result<Vec<HostStats>> {
+    let mut stream = time::timeout(REQUEST_TIMEOUT, TcpStream::connect(addr))
+        .await
+        .context("Timed out connecting")?
+        .with_context(|| format!("Failed to connect to {}", addr))?;
+    stream.write_all(format!("GET /hosts HTTP/1.1\r\nHost: {}\r\n\r\n", addr).as_bytes()).await?;
+    let mut response = String::new();
+    time::timeout(REQUEST_TIMEOUT, stream.read_to_string(&mut response))
+        .await
+        .context("Timed out reading response")??;
+
+    let (head, body) = response.split_once("\r\n\r\n").context("Malformed response")?;
+    let status = head.lines().next().unwrap_or_default();
+    anyhow::ensure!(status.contains(" 200 "), "{} answered {}", addr, status);
+    serde_json::from_str(body).context("Malformed host stats")
+}
@@ -0,0 +1,196 @@
+use crate::metrics::{Labels, MetricKind, MetricsSink};
+use anyhow::Result;
+use std::time::Duration;
+use tracing::Subscriber;
+use tracing_subscriber::{registry::LookupSpan, Layer};
+
+#[cfg(feature = "otel")]
+pub use self::otlp::{OtlpExporter, OtlpMetrics};
+#[cfg(not(feature = "otel"))]
+pub use self::disabled::{OtlpExporter, OtlpMetrics};
+
+/// How often metrics are collected and pushed to the collector
+pub const EXPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+#[cfg(feature = "otel")]
+mod otlp {
+    use super::*;
+    use anyhow::Context;
+    use opentelemetry::metrics::{Counter, Gauge, Meter, MeterProvider};
+    use opentelemetry::trace::TracerProvider;
+    use opentelemetry::KeyValue;
+    use opentelemetry_otlp::{MetricExporter, SpanExporter, WithExportConfig};
+    use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+    use opentelemetry_sdk::trace::SdkTracerProvider;
+    use opentelemetry_sdk::Resource;
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use tracing::Level;
+    use tracing_subscriber::filter::Targets;
+
+    const SERVICE_NAME: &str = "pcc";
+
+    /// Pushes traces and metrics to an OpenTelemetry collector over
+    /// OTLP/HTTP until shut down
+    #[derive(Debug)]
+    pub struct OtlpExporter {
+        tracer_provider: SdkTracerProvider,
+        meter_provider: SdkMeterProvider,
+    }
+
+    impl OtlpExporter {
+        /// Export to the collector at `endpoint`, e.g. http://localhost:4318,
+        /// under its /v1/traces and /v1/metrics paths
+        pub fn new(endpoint: &str) -> Result<Self> {
+            let endpoint = endpoint.trim_end_matches('/');
+            let resource = Resource::builder().with_service_name(SERVICE_NAME).build();
+
+            let spans = SpanExporter::builder()
+                .with_http()
+                .with_endpoint(format!("{}/v1/traces", endpoint))
+                .build()
+                .context("Failed to create the OTLP span exporter")?;
+            let tracer_provider = SdkTracerProvider::builder()
+                .with_batch_exporter(spans)
+                .with_resource(resource.clone())
+                .build();
+
+            let metrics = MetricExporter::builder()
+                .with_http()
+                .with_endpoint(format!("{}/v1/metrics", endpoint))
+                .build()
+                .context("Failed to create the OTLP metric exporter")?;
+            let meter_provider = SdkMeterProvider::builder()
+                .with_reader(PeriodicReader::builder(metrics).with_interval(EXPORT_INTERVAL).build())
+                .with_resource(resource)
+                .build();
+
+            Ok(Self { tracer_provider, meter_provider })
+        }
+
+        /// A layer exporting pcc's spans, including the per-frame pipeline
+        /// spans, whatever the log level
+        pub fn layer<S>(&self) -> impl Layer<S>
+        where
+            S: Subscriber + for<'a> LookupSpan<'a>,
+        {
+            let tracer = self.tracer_provider.tracer(SERVICE_NAME);
+            tracing_opentelemetry::layer()
+                .with_tracer(tracer)
+                .with_filter(Targets::new().with_targets(crate::LOG_TARGETS.iter().map(|target| (*target, Level::DEBUG))))
+        }
+
+        pub fn metrics(&self) -> OtlpMetrics {
+            OtlpMetrics {
+                meter: self.meter_provider.meter(SERVICE_NAME),
+                instruments: Arc::default(),
+            }
+        }
+
+        /// Export whatever is still buffered and stop. Blocks until the
+        /// collector answers or times out.
+        pub fn shutdown(self) -> Result<()> {
+            let traces = self.tracer_provider.shutdown().context("Failed to flush traces");
+            let metrics = self.meter_provider.shutdown().context("Failed to flush metrics");
+            traces.and(metrics)
+        }
+    }
+
+    /// Records metrics written to it on an OpenTelemetry meter. Clones share
+    /// instruments.
+    #[derive(Debug, Clone)]
+    pub struct OtlpMetrics {
+        meter: Meter,
+        instruments: Arc<Mutex<Instruments>>,
+    }
+
+    #[derive(Debug, Default)]
+    struct Instruments {
+        gauges: HashMap<String, Gauge<f64>>,
+        counters: HashMap<String, Counter<f64>>,
+        // Last total written for each counter and set of labels. OTLP
+        // counters take increments, where pcc keeps running totals.
+        totals: HashMap<(String, Vec<(String, String)>), f64>,
+    }
+
+    impl MetricsSink for OtlpMetrics {
+        fn family<'a>(
+            &mut self,
+            name: &str,
+            help: &str,
+            kind: MetricKind,
+            samples: impl IntoIterator<Item = (Labels<'a>, f64)>,
+        ) {
+            let mut instruments = self.instruments.lock().unwrap();
+            let Instruments { gauges, counters, totals } = &mut *instruments;
+            for (labels, value) in samples {
+                let labels: Vec<(String, String)> =
+                    labels.into_iter().map(|(label, value)| (label.to_string(), value)).collect();
+                let attributes: Vec<KeyValue> =
+                    labels.iter().map(|(label, value)| KeyValue::new(label.clone(), value.clone())).collect();
+                match kind {
+                    MetricKind::Gauge => gauges
+                        .entry(name.to_string())
+                        .or_insert_with(|| self.meter.f64_gauge(name.to_string()).with_description(help.to_string()).build())
+                        .record(value, &attributes),
+                    MetricKind::Counter => {
+                        let counter = counters.entry(name.to_string()).or_insert_with(|| {
+                            self.meter.f64_counter(name.to_string()).with_description(help.to_string()).build()
+                        });
+                        let last = totals.insert((name.to_string(), labels), value).unwrap_or(0.0);
+                        // Totals start over with each session
+                        let increment = if value >= last { value - last } else { value };
+                        counter.add(increment, &attributes);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(not(feature = "otel"))]
+mod disabled {
+    use super::*;
+    use anyhow::bail;
+    use std::convert::Infallible;
+
+    /// Stands in for the exporter in builds without the `otel` feature
+    #[derive(Debug)]
+    pub struct OtlpExporter(Infallible);
+
+    impl OtlpExporter {
+        pub fn new(_endpoint: &str) -> Result<Self> {
+            bail!("Built without OpenTelemetry export (enable the `otel` feature)")
+        }
+
+        pub fn layer<S>(&self) -> impl Layer<S>
+        where
+            S: Subscriber + for<'a> LookupSpan<'a>,
+        {
+            tracing_subscriber::layer::Identity::new()
+        }
+
+        pub fn metrics(&self) -> OtlpMetrics {
+            match self.0 {}
+        }
+
+        pub fn shutdown(self) -> Result<()> {
+            match self.0 {}
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct OtlpMetrics(Infallible);
+
+    impl MetricsSink for OtlpMetrics {
+        fn family<'a>(
+            &mut self,
+            _name: &str,
+            _help: &str,
+            _kind: MetricKind,
+            _samples: impl IntoIterator<Item = (Labels<'a>, f64)>,
+        ) {
+            match self.0 {}
+        }
+    }
+}
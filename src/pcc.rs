@@ -0,0 +1,2 @@
+pub use pcc_core::{delta, format, format::{PixelFormat, Plane}, pool, pool::*, types, types::*, wire, PCCDetector};
+pub use pcc_capture::{async_capture_stream, capture_stream, BlockingCapture, BlockingDetector, FrameStream};
@@ -1,5 +0,0 @@
-pub mod types;
-pub use types::*;
-
-mod detector;
-pub use detector::*; 
\ No newline at end of file
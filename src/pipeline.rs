@@ -0,0 +1,383 @@
+use crate::client::{FrameOutput, FramePipeline};
+use crate::encoder::FrameEncoder;
+use crate::network::{Connection, EncodedFrame, Message};
+use crate::pcc::{
+    AsyncFrameCapture, AsyncPixelChangeDetector, BlockingCapture, BlockingDetector, FrameCapture, FramePool,
+    PCCDetector, PixelChangeDetector, QualityConfig,
+};
+use crate::server::renderer::Renderer;
+use crate::pcc::FrameUpdate;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use futures::future::BoxFuture;
+use futures::{FutureExt, Sink};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{ready, Context as TaskContext, Poll};
+use std::time::Duration;
+use tokio::sync::{mpsc, watch};
+use tokio::time::{self, MissedTickBehavior};
+use tracing::{debug, info};
+
+// Captured frames waiting to be diffed and sent, by default. More only adds
+// latency; fewer leaves capture idle while a frame is sent.
+const DEFAULT_QUEUE: usize = 2;
+
+/// Where a `Pipeline` sends what changed: a connected viewer, or a local
+/// renderer
+#[async_trait]
+pub trait FrameTransport: Send + Sync {
+    /// Send a whole frame or the changes since the last one
+    async fn send(&self, output: &FrameOutput) -> Result<()>;
+}
+
+#[async_trait]
+impl FrameTransport for Connection {
+    async fn send(&self, output: &FrameOutput) -> Result<()> {
+        match output {
+            FrameOutput::Keyframe(frame) => self.send_keyframe(frame).await,
+            FrameOutput::Update(update) => self.send_update(update).await,
+            FrameOutput::Scroll { copy, update } => self.send_encoded(&EncodedFrame::scroll(*copy, update)?).await,
+            FrameOutput::Unchanged => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl FrameTransport for Renderer {
+    async fn send(&self, output: &FrameOutput) -> Result<()> {
+        match output {
+            FrameOutput::Keyframe(frame) => self.buffer.push_frame(frame.clone()).await,
+            FrameOutput::Update(update) => {
+                self.handle_message(Message::FrameUpdate { update: update.clone(), part: 0, parts: 1 }).await
+            }
+            FrameOutput::Scroll { copy, update } => {
+                self.handle_message(Message::from(*copy)).await?;
+                self.handle_message(Message::FrameUpdate { update: update.clone(), part: 0, parts: 1 }).await
+            }
+            FrameOutput::Unchanged => Ok(()),
+        }
+    }
+}
+
+#[async_trait]
+impl<T: FrameTransport + ?Sized> FrameTransport for Arc<T> {
+    async fn send(&self, output: &FrameOutput) -> Result<()> {
+        (**self).send(output).await
+    }
+}
+
+/// A `FrameTransport` as a `futures::Sink`, so a stream of frame outputs
+/// can be forwarded to a viewer. It takes one frame at a time and isn't
+/// ready for the next until the last is sent, so a faster stream waits
+/// rather than queueing frames. Dropping it mid-send abandons that frame;
+/// the viewer discards the part it got.
+///
+/// ```no_run
+/// # async fn share(connection: pixel_change_check_client::network::Connection) -> anyhow::Result<()> {
+/// use futures::StreamExt;
+/// use pixel_change_check_client::{capture::ScreenCapture, client::FramePipeline, pcc::*, pipeline::TransportSink};
+///
+/// let mut pipeline = FramePipeline::new(PCCDetector::default());
+/// ScreenCapture::new()?
+///     .into_stream()
+///     .map(|frame| pipeline.process(frame?, false))
+///     .forward(TransportSink::new(connection))
+///     .await?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct TransportSink {
+    transport: Arc<dyn FrameTransport>,
+    sending: Option<BoxFuture<'static, Result<()>>>,
+}
+
+impl TransportSink {
+    pub fn new(transport: impl FrameTransport + 'static) -> Self {
+        Self { transport: Arc::new(transport), sending: None }
+    }
+
+    // Finish the send in flight, if any
+    fn poll_sent(&mut self, cx: &mut TaskContext<'_>) -> Poll<Result<()>> {
+        if let Some(sending) = &mut self.sending {
+            let result = ready!(sending.as_mut().poll(cx));
+            self.sending = None;
+            result?;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Sink<FrameOutput> for TransportSink {
+    type Error = anyhow::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Result<()>> {
+        self.get_mut().poll_sent(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, output: FrameOutput) -> Result<()> {
+        let transport = self.transport.clone();
+        self.get_mut().sending = Some(async move { transport.send(&output).await }.boxed());
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Result<()>> {
+        self.get_mut().poll_sent(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Result<()>> {
+        self.get_mut().poll_sent(cx)
+    }
+}
+
+impl Sink<FrameUpdate> for TransportSink {
+    type Error = anyhow::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Result<()>> {
+        self.get_mut().poll_sent(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, update: FrameUpdate) -> Result<()> {
+        Sink::<FrameOutput>::start_send(self, FrameOutput::Update(update))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Result<()>> {
+        self.get_mut().poll_sent(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Result<()>> {
+        self.get_mut().poll_sent(cx)
+    }
+}
+
+/// What a `Pipeline` did before it stopped
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PipelineStats {
+    pub captured: u64,
+    /// Captures skipped because the frames before them hadn't been sent yet
+    pub skipped: u64,
+    pub keyframes: u64,
+    pub updates: u64,
+    /// Frames with nothing changed, so nothing sent
+    pub unchanged: u64,
+}
+
+#[derive(Debug, Default)]
+struct PipelineCounters {
+    captured: AtomicU64,
+    skipped: AtomicU64,
+}
+
+/// Sets up a `Pipeline`; see `Pipeline::builder`
+pub struct PipelineBuilder {
+    capture: Option<Box<dyn AsyncFrameCapture>>,
+    detector: Box<dyn AsyncPixelChangeDetector>,
+    encoder: Option<Arc<FrameEncoder>>,
+    transport: Option<Arc<dyn FrameTransport>>,
+    quality: QualityConfig,
+    queue: usize,
+    pool: FramePool,
+}
+
+impl PipelineBuilder {
+    /// Where frames come from, e.g. `ScreenCapture::with_display(1)?`.
+    /// Each capture runs on a blocking thread.
+    pub fn capture(self, capture: impl FrameCapture + Send + 'static) -> Self {
+        self.async_capture(BlockingCapture::new(capture))
+    }
+
+    /// Where frames come from, for captures that wait on the OS
+    /// asynchronously
+    pub fn async_capture(mut self, capture: impl AsyncFrameCapture + 'static) -> Self {
+        self.capture = Some(Box::new(capture));
+        self
+    }
+
+    /// How changes are found between frames; `PCCDetector` by default
+    pub fn detector(self, detector: impl PixelChangeDetector + Send + Sync + 'static) -> Self {
+        self.async_detector(BlockingDetector::new(detector))
+    }
+
+    /// How changes are found between frames, for detectors that wait on
+    /// other work, e.g. a GPU
+    pub fn async_detector(mut self, detector: impl AsyncPixelChangeDetector + 'static) -> Self {
+        self.detector = Box::new(detector);
+        self
+    }
+
+    /// Takes keyframe requests, e.g. from `Pipeline::encoder`
+    pub fn encoder(mut self, encoder: FrameEncoder) -> Self {
+        self.encoder = Some(Arc::new(encoder));
+        self
+    }
+
+    /// Where frames go, e.g. a `Connection` after its handshake
+    pub fn transport(mut self, transport: impl FrameTransport + 'static) -> Self {
+        self.transport = Some(Arc::new(transport));
+        self
+    }
+
+    /// Frame rate and change threshold; the defaults otherwise
+    pub fn quality(mut self, quality: QualityConfig) -> Self {
+        self.quality = quality;
+        self
+    }
+
+    /// How many captured frames may wait to be sent before capture skips
+    /// frames; 2 by default
+    pub fn queue(mut self, frames: usize) -> Self {
+        self.queue = frames.max(1);
+        self
+    }
+
+    /// Where frames go back once diffed against; give the capture the same
+    /// pool to reuse them
+    pub fn pool(mut self, pool: FramePool) -> Self {
+        self.pool = pool;
+        self
+    }
+
+    /// Configure the capture and detector, failing if the capture, encoder
+    /// or transport is missing
+    pub fn build(self) -> Result<Pipeline> {
+        let mut capture = self.capture.context("Pipeline needs a capture")?;
+        let encoder = self.encoder.context("Pipeline needs an encoder")?;
+        let transport = self.transport.context("Pipeline needs a transport")?;
+        let mut detector = self.detector;
+        capture.configure(self.quality)?;
+        detector.configure(self.quality)?;
+        Ok(Pipeline {
+            capture,
+            frames: FramePipeline::new(detector).with_pool(self.pool),
+            encoder,
+            transport,
+            quality: self.quality,
+            queue: self.queue,
+        })
+    }
+}
+
+/// Capture, diff and send frames in the common case, without assembling
+/// the pieces by hand. Capture runs at the target frame rate and skips
+/// frames while earlier ones wait to be sent, so a slow transport lowers
+/// the frame rate rather than adding latency.
+///
+/// ```no_run
+/// # async fn share(connection: pixel_change_check_client::network::Connection) -> anyhow::Result<()> {
+/// use pixel_change_check_client::{capture::ScreenCapture, encoder::FrameEncoder, pcc::*, pipeline::Pipeline};
+///
+/// let capture = ScreenCapture::with_display(1)?;
+/// let encoder = FrameEncoder::new(capture.width(), capture.height(), QualityConfig::default())?;
+/// let pipeline = Pipeline::builder()
+///     .capture(capture)
+///     .detector(PCCDetector::default())
+///     .encoder(encoder)
+///     .transport(connection)
+///     .build()?;
+/// let stats = pipeline.run(tokio::signal::ctrl_c()).await?;
+/// println!("Sent {} keyframes and {} updates", stats.keyframes, stats.updates);
+/// # Ok(())
+/// # }
+/// ```
+pub struct Pipeline {
+    capture: Box<dyn AsyncFrameCapture>,
+    frames: FramePipeline<Box<dyn AsyncPixelChangeDetector>>,
+    encoder: Arc<FrameEncoder>,
+    transport: Arc<dyn FrameTransport>,
+    quality: QualityConfig,
+    queue: usize,
+}
+
+impl Pipeline {
+    pub fn builder() -> PipelineBuilder {
+        PipelineBuilder {
+            capture: None,
+            detector: Box::new(BlockingDetector::new(PCCDetector::default())),
+            encoder: None,
+            transport: None,
+            quality: QualityConfig::default(),
+            queue: DEFAULT_QUEUE,
+            pool: FramePool::default(),
+        }
+    }
+
+    /// The encoder, for forcing keyframes while running
+    pub fn encoder(&self) -> Arc<FrameEncoder> {
+        self.encoder.clone()
+    }
+
+    /// Share frames until `shutdown` resolves or a stage fails. Frames
+    /// already captured are sent before it returns.
+    pub async fn run(self, shutdown: impl Future) -> Result<PipelineStats> {
+        let Self { capture, mut frames, encoder, transport, quality, queue } = self;
+        let (frame_tx, mut frame_rx) = mpsc::channel(queue);
+        let (stop, stopping) = watch::channel(false);
+        let counters = Arc::new(PipelineCounters::default());
+        let interval = Duration::from_secs(1) / quality.target_fps.max(1);
+        info!("Pipeline running at {} fps", quality.target_fps);
+
+        let capturing = tokio::spawn({
+            let counters = counters.clone();
+            let mut stopping = stopping;
+            async move {
+                let mut ticks = time::interval(interval);
+                // Behind; start counting again from now
+                ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                while !*stopping.borrow() {
+                    tokio::select! {
+                        _ = ticks.tick() => {}
+                        _ = stopping.changed() => break,
+                    }
+                    // Backpressure: no capture while the queue is full
+                    if frame_tx.capacity() == 0 {
+                        counters.skipped.fetch_add(1, Ordering::Relaxed);
+                        continue;
+                    }
+                    let frame = capture.capture_frame().await?;
+                    counters.captured.fetch_add(1, Ordering::Relaxed);
+                    if frame_tx.try_send(frame).is_err() {
+                        // Sending stopped
+                        break;
+                    }
+                }
+                Ok::<_, anyhow::Error>(())
+            }
+        });
+
+        let mut stats = PipelineStats::default();
+        let result = {
+            let sending = async {
+                while let Some(frame) = frame_rx.recv().await {
+                    let output = frames.process_async(frame, encoder.take_keyframe_request()).await?;
+                    match &output {
+                        FrameOutput::Keyframe(_) => stats.keyframes += 1,
+                        FrameOutput::Update(_) | FrameOutput::Scroll { .. } => stats.updates += 1,
+                        FrameOutput::Unchanged => stats.unchanged += 1,
+                    }
+                    transport.send(&output).await?;
+                }
+                Ok::<_, anyhow::Error>(())
+            };
+            tokio::pin!(sending);
+            tokio::select! {
+                result = &mut sending => result,
+                _ = shutdown => {
+                    debug!("Pipeline stopping");
+                    // Capture stops, and sending ends once its queue is empty
+                    let _ = stop.send(true);
+                    sending.await
+                }
+            }
+        };
+        let _ = stop.send(true);
+        // Capture fails first when it's the cause, so its error wins
+        capturing.await??;
+        result?;
+
+        stats.captured = counters.captured.load(Ordering::Relaxed);
+        stats.skipped = counters.skipped.load(Ordering::Relaxed);
+        Ok(stats)
+    }
+}
@@ -0,0 +1,28 @@
+//! The traits, core types and configs most users of the library need, for
+//! a single glob import:
+//!
+//! ```
+//! use pixel_change_check_client::prelude::*;
+//!
+//! let frame = Frame {
+//!     id: 1,
+//!     timestamp: std::time::SystemTime::now(),
+//!     width: 2,
+//!     height: 1,
+//!     format: PixelFormat::Rgb24,
+//!     data: vec![0; 6].into(),
+//! };
+//! assert!(PCCDetector::default().detect_changes(&frame, &frame).unwrap().is_empty());
+//! ```
+
+pub use crate::capture::ScreenCapture;
+pub use crate::client::{ClientConfig, FrameOutput, FramePipeline};
+pub use crate::encoder::FrameEncoder;
+pub use crate::facade::{Host, Viewer};
+pub use crate::network::{NetworkConfig, NetworkResilience, ResilienceConfig, Transport};
+pub use crate::pcc::{
+    AsyncFrameCapture, AsyncPixelChangeDetector, Frame, FrameCapture, FrameStream, FrameUpdate, PCCDetector,
+    PixelChange, PixelChangeDetector, PixelFormat, QualityConfig, Rect,
+};
+pub use crate::pipeline::{FrameTransport, Pipeline, TransportSink};
+pub use crate::server::{FrameSink, Renderer};
@@ -0,0 +1 @@
+pub use pcc_core::quality::*;
@@ -1,5 +0,0 @@
-pub mod renderer;
-pub mod network;
-
-// Re-export commonly used types
-pub use renderer::Renderer; 
\ No newline at end of file
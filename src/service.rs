@@ -0,0 +1,170 @@
+use std::ffi::OsStr;
+use tracing::debug;
+
+#[cfg(windows)]
+pub use self::windows::run_as_windows_service;
+
+/// A change in the service's state to report to the service manager
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ServiceState {
+    /// Started up, e.g. listening for hosts
+    Ready,
+    /// Shutting down
+    Stopping,
+}
+
+impl ServiceState {
+    fn message(self) -> &'static str {
+        match self {
+            ServiceState::Ready => "READY=1",
+            ServiceState::Stopping => "STOPPING=1",
+        }
+    }
+}
+
+/// Report `state` to systemd, or another service manager speaking its
+/// notify protocol, through the socket in `$NOTIFY_SOCKET`. Does nothing
+/// without one, as under launchd, which only needs pcc kept in the
+/// foreground.
+pub fn notify(state: ServiceState) {
+    let Some(socket) = std::env::var_os("NOTIFY_SOCKET") else { return };
+    if let Err(e) = send_notification(&socket, state.message()) {
+        debug!("Failed to notify the service manager: {}", e);
+    }
+}
+
+#[cfg(unix)]
+fn send_notification(socket: &OsStr, message: &str) -> std::io::Result<()> {
+    use std::os::unix::net::UnixDatagram;
+
+    let sender = UnixDatagram::unbound()?;
+    // Names starting with @ are in the abstract namespace
+    #[cfg(target_os = "linux")]
+    if let Some(name) = socket.as_encoded_bytes().strip_prefix(b"@") {
+        use std::os::linux::net::SocketAddrExt;
+        let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+        return sender.send_to_addr(message.as_bytes(), &addr).map(drop);
+    }
+    sender.send_to(message.as_bytes(), socket).map(drop)
+}
+
+#[cfg(not(unix))]
+fn send_notification(_socket: &OsStr, _message: &str) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(windows)]
+mod windows {
+    use crate::daemon::Stopping;
+    use anyhow::{bail, Context, Result};
+    use std::ffi::OsString;
+    use std::future::Future;
+    use std::sync::{mpsc, Mutex};
+    use std::time::Duration;
+    use tokio::sync::{oneshot, watch};
+    use tracing::error;
+    use windows_service::service::{
+        ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus, ServiceType,
+    };
+    use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+    use windows_service::{define_windows_service, service_dispatcher};
+
+    // Services run in their own process, so the name is only used in logs
+    const SERVICE_NAME: &str = "pcc";
+
+    // Links the service manager's thread to the pipeline on the runtime
+    struct Link {
+        started: oneshot::Sender<()>,
+        stop: watch::Sender<bool>,
+        // Whether the pipeline succeeded, once it has finished
+        finished: mpsc::Receiver<bool>,
+    }
+
+    static LINK: Mutex<Option<Link>> = Mutex::new(None);
+
+    define_windows_service!(ffi_service_main, service_main);
+
+    /// Run `pipeline` as a Windows service, stopping it when the service
+    /// manager stops the service or the machine shuts down. Fails when pcc
+    /// wasn't started by the service manager, e.g. from a console.
+    pub async fn run_as_windows_service<F, Fut>(pipeline: F) -> Result<()>
+    where
+        F: FnOnce(Stopping) -> Fut,
+        Fut: Future<Output = Result<()>>,
+    {
+        let (started_tx, started) = oneshot::channel();
+        let (stop, stopping) = watch::channel(false);
+        let (finished_tx, finished) = mpsc::channel();
+        *LINK.lock().unwrap() = Some(Link { started: started_tx, stop, finished });
+
+        // Blocks until the service has stopped
+        let mut dispatcher = tokio::task::spawn_blocking(|| service_dispatcher::start(SERVICE_NAME, ffi_service_main));
+        tokio::select! {
+            started = started => started.context("Service failed to start")?,
+            result = &mut dispatcher => {
+                result?.context("Not started by the Windows service manager")?;
+                bail!("Service manager returned before starting the service");
+            }
+        }
+
+        let result = pipeline(Stopping::new(stopping)).await;
+        let _ = finished_tx.send(result.is_ok());
+        dispatcher.await??;
+        result
+    }
+
+    fn service_main(_arguments: Vec<OsString>) {
+        if let Err(e) = run_service() {
+            error!("Service failed: {:#}", e);
+        }
+    }
+
+    fn run_service() -> Result<()> {
+        let Link { started, stop, finished } = LINK.lock().unwrap().take().context("Service started twice")?;
+        let status = service_control_handler::register(SERVICE_NAME, move |control| match control {
+            ServiceControl::Stop | ServiceControl::Shutdown => {
+                let _ = stop.send(true);
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        })?;
+        let report = |state, controls_accepted, code| {
+            status.set_service_status(ServiceStatus {
+                service_type: ServiceType::OWN_PROCESS,
+                current_state: state,
+                controls_accepted,
+                exit_code: ServiceExitCode::Win32(code),
+                checkpoint: 0,
+                wait_hint: Duration::ZERO,
+                process_id: None,
+            })
+        };
+
+        report(ServiceState::Running, ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN, 0)?;
+        let _ = started.send(());
+        let succeeded = finished.recv().unwrap_or(false);
+        report(ServiceState::Stopped, ServiceControlAccept::empty(), if succeeded { 0 } else { 1 })?;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixDatagram;
+
+    #[test]
+    fn test_notify_socket_receives_state() {
+        let path = std::env::temp_dir().join(format!("pcc-notify-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let receiver = UnixDatagram::bind(&path).unwrap();
+
+        send_notification(path.as_os_str(), ServiceState::Ready.message()).unwrap();
+        let mut buf = [0; 64];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
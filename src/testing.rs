@@ -0,0 +1,243 @@
+//! Helpers for testing frame handling end to end without a display:
+//! deterministic frames, hashes to compare them by, and a host-to-viewer
+//! round trip that checks the viewer ends up with what the host captured.
+
+use crate::client::{FrameOutput, FramePipeline};
+use crate::network::EncodedFrame;
+use crate::pcc::{wire::FrameMessage, Frame, FrameCapture, PixelChangeDetector, PixelFormat, QualityConfig};
+use anyhow::{bail, Result};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+
+// Time between generated frames' timestamps
+const FRAME_INTERVAL: Duration = Duration::from_millis(33);
+// Side of the grid a perceptual hash averages the frame down to
+const HASH_GRID: u32 = 8;
+
+/// What a `FrameGenerator` draws. Every pattern changes from one frame to
+/// the next, each in its own way.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pattern {
+    /// A diagonal color gradient sliding a pixel right each frame, so
+    /// every pixel changes a little
+    Gradient,
+    /// Squares of `cell` pixels, shifting a cell each frame
+    Checkerboard { cell: u32 },
+    /// A box of `size` pixels moving diagonally over a still background,
+    /// so only a small region changes
+    MovingBox { size: u32 },
+    /// Pseudo-random pixels from `seed`, all new each frame
+    Noise { seed: u64 },
+}
+
+/// Makes the same frames every run: frame `n` of a pattern is always the
+/// same pixels, id and timestamp. Implements `FrameCapture`, capturing
+/// frames 0, 1, 2 and so on.
+pub struct FrameGenerator {
+    width: u32,
+    height: u32,
+    pattern: Pattern,
+    next: AtomicU64,
+}
+
+impl FrameGenerator {
+    pub fn new(width: u32, height: u32, pattern: Pattern) -> Self {
+        Self { width, height, pattern, next: AtomicU64::new(0) }
+    }
+
+    /// Frame `n` of the pattern, timestamped `n` frame intervals after the
+    /// Unix epoch
+    pub fn frame(&self, n: u64) -> Frame {
+        let (width, height) = (self.width as u64, self.height as u64);
+        let mut data = Vec::with_capacity((width * height * 3) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                data.extend_from_slice(&self.pixel(n, x, y));
+            }
+        }
+        Frame {
+            id: n,
+            timestamp: SystemTime::UNIX_EPOCH + FRAME_INTERVAL * n as u32,
+            width: self.width,
+            height: self.height,
+            format: PixelFormat::Rgb24,
+            data: data.into(),
+        }
+    }
+
+    fn pixel(&self, n: u64, x: u64, y: u64) -> [u8; 3] {
+        let (width, height) = (self.width.max(1) as u64, self.height.max(1) as u64);
+        match self.pattern {
+            Pattern::Gradient => {
+                let shifted = (x + n) % width;
+                [(shifted * 255 / width) as u8, (y * 255 / height) as u8, ((shifted + y) * 127 / (width + height)) as u8]
+            }
+            Pattern::Checkerboard { cell } => {
+                let cell = cell.max(1) as u64;
+                if (x / cell + y / cell + n).is_multiple_of(2) {
+                    [230, 230, 230]
+                } else {
+                    [25, 25, 25]
+                }
+            }
+            Pattern::MovingBox { size } => {
+                let size = size as u64;
+                let (left, top) = ((n * 4) % width, (n * 4) % height);
+                if (left..left + size).contains(&x) && (top..top + size).contains(&y) {
+                    [240, 200, 40]
+                } else {
+                    [40, 60, 90]
+                }
+            }
+            Pattern::Noise { seed } => {
+                let bits = mix(seed ^ mix(n) ^ mix(y * width + x));
+                [bits as u8, (bits >> 8) as u8, (bits >> 16) as u8]
+            }
+        }
+    }
+}
+
+// SplitMix64's finalizer: well spread bits from any input
+fn mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+impl FrameCapture for FrameGenerator {
+    fn capture_frame(&self) -> Result<Frame> {
+        Ok(self.frame(self.next.fetch_add(1, Ordering::Relaxed)))
+    }
+
+    fn supported_configs(&self) -> Vec<QualityConfig> {
+        vec![QualityConfig::default()]
+    }
+
+    fn configure(&mut self, _config: QualityConfig) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// A hash of the frame's size, format and every byte of its pixels, the
+/// same on every platform and run. Equal only for identical frames.
+pub fn exact_hash(frame: &Frame) -> u64 {
+    // FNV-1a
+    let header = [frame.width.to_le_bytes(), frame.height.to_le_bytes(), (frame.format as u32).to_le_bytes()];
+    header.iter().flatten().chain(frame.data.iter()).fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// A 64-bit average hash: the frame's brightness averaged over an 8x8 grid,
+/// one bit per cell that is brighter than the mean. Frames that look alike
+/// have hashes a few bits apart; see `hash_distance`.
+pub fn perceptual_hash(frame: &Frame) -> Result<u64> {
+    let frame = frame.clone().into_rgb24()?;
+    let (width, height) = (frame.width.max(1), frame.height.max(1));
+    let mut sums = [0u64; (HASH_GRID * HASH_GRID) as usize];
+    let mut counts = [0u64; (HASH_GRID * HASH_GRID) as usize];
+    for (i, rgb) in frame.data.chunks_exact(3).enumerate() {
+        let (x, y) = (i as u32 % width, i as u32 / width);
+        let cell = (y * HASH_GRID / height * HASH_GRID + x * HASH_GRID / width) as usize;
+        // Rec. 601 luma, in integers
+        sums[cell] += (299 * rgb[0] as u64 + 587 * rgb[1] as u64 + 114 * rgb[2] as u64) / 1000;
+        counts[cell] += 1;
+    }
+    let cells: Vec<u64> = sums.iter().zip(&counts).map(|(sum, count)| sum / (*count).max(1)).collect();
+    let mean = cells.iter().sum::<u64>() / cells.len() as u64;
+    Ok(cells.iter().enumerate().fold(0, |hash, (i, cell)| hash | ((*cell > mean) as u64) << i))
+}
+
+/// Bits two perceptual hashes differ in: 0 for frames that look the same,
+/// up to 64
+pub fn hash_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// The largest difference between two frames in any color channel, after
+/// converting both to RGB24. Fails if their sizes differ.
+pub fn max_difference(a: &Frame, b: &Frame) -> Result<u8> {
+    if (a.width, a.height) != (b.width, b.height) {
+        bail!("Frames differ in size: {}x{} and {}x{}", a.width, a.height, b.width, b.height);
+    }
+    let (a, b) = (a.clone().into_rgb24()?, b.clone().into_rgb24()?);
+    Ok(a.data.iter().zip(b.data.iter()).map(|(a, b)| a.abs_diff(*b)).max().unwrap_or(0))
+}
+
+/// Panic unless `actual` is `expected` give or take `tolerance` in every
+/// color channel, naming the first pixel that's further off
+#[track_caller]
+pub fn assert_frames_match(expected: &Frame, actual: &Frame, tolerance: u8) {
+    assert_eq!((actual.width, actual.height), (expected.width, expected.height), "Frame sizes differ");
+    let (expected, actual) = (expected.clone().into_rgb24().unwrap(), actual.clone().into_rgb24().unwrap());
+    let width = expected.width.max(1) as usize;
+    let pixels = expected.data.chunks_exact(3).zip(actual.data.chunks_exact(3));
+    for (i, (want, got)) in pixels.enumerate() {
+        let off = want.iter().zip(got).map(|(a, b)| a.abs_diff(*b)).max().unwrap_or(0);
+        assert!(
+            off <= tolerance,
+            "Frame {} pixel ({}, {}) is {:?}, expected {:?} within {}",
+            actual.id,
+            i % width,
+            i / width,
+            got,
+            want,
+            tolerance
+        );
+    }
+}
+
+/// A host's pipeline and a viewer's copy of the screen, joined by the wire
+/// encoding but no network. Each frame sent goes through change detection,
+/// is encoded as a keyframe or update, decoded and applied as a viewer
+/// would, giving what the viewer then shows.
+pub struct RoundTrip<D> {
+    pipeline: FramePipeline<D>,
+    viewer: Option<Frame>,
+}
+
+impl<D: PixelChangeDetector> RoundTrip<D> {
+    pub fn new(detector: D) -> Self {
+        Self { pipeline: FramePipeline::new(detector), viewer: None }
+    }
+
+    /// Send `frame` to the viewer, returning what it shows afterwards
+    pub fn send(&mut self, frame: Frame) -> Result<Frame> {
+        let encoded = match self.pipeline.process(frame, false)? {
+            FrameOutput::Keyframe(frame) => EncodedFrame::keyframe(&frame)?,
+            FrameOutput::Update(update) => EncodedFrame::update(&update)?,
+            FrameOutput::Scroll { copy, update } => EncodedFrame::scroll(copy, &update)?,
+            FrameOutput::Unchanged => return self.viewer.clone().ok_or_else(|| anyhow::anyhow!("Nothing sent yet")),
+        };
+        match encoded {
+            EncodedFrame::Keyframe(data) => self.viewer = Some(Frame::decode(&data)?),
+            EncodedFrame::Update(parts) => {
+                let Some(viewer) = self.viewer.as_mut() else {
+                    bail!("Update sent before a keyframe");
+                };
+                for part in parts {
+                    match FrameMessage::parse(&part)? {
+                        FrameMessage::Update { update, .. } => viewer.apply_update(&update)?,
+                        FrameMessage::CopyRect { src_x, src_y, dst_rect } => viewer.copy_rect(src_x, src_y, dst_rect)?,
+                        other => bail!("Expected an update, got {:?}", other),
+                    }
+                }
+            }
+        }
+        Ok(self.viewer.clone().expect("A frame was received"))
+    }
+}
+
+/// Send `frames` through a `RoundTrip` with `detector`, panicking unless
+/// the viewer shows each one within `tolerance`. The tolerance has to
+/// cover the detector's threshold, since changes smaller than that aren't
+/// sent, and they can add up over a run of frames.
+#[track_caller]
+pub fn assert_round_trip(detector: impl PixelChangeDetector, frames: impl IntoIterator<Item = Frame>, tolerance: u8) {
+    let mut round_trip = RoundTrip::new(detector);
+    for frame in frames {
+        let shown = round_trip.send(frame.clone()).unwrap();
+        assert_frames_match(&frame, &shown, tolerance);
+    }
+}
@@ -0,0 +1,202 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// When the host gives up on a failing pipeline stage, from the
+/// `[watchdog]` section
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WatchdogConfig {
+    /// How long a stage may go without getting a frame through before it's
+    /// restarted
+    pub stall_timeout: Duration,
+    /// Restarts of one stage in a row before sharing gives up
+    pub max_restarts: u32,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            stall_timeout: Duration::from_secs(5),
+            max_restarts: 3,
+        }
+    }
+}
+
+/// A stage of the host's frame pipeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PipelineStage {
+    /// Grabbing the screen
+    Capture,
+    /// Diffing against the previous frame and coding the result
+    Encode,
+    /// Handing the result to the network
+    Send,
+}
+
+impl PipelineStage {
+    const ALL: [PipelineStage; 3] = [PipelineStage::Capture, PipelineStage::Encode, PipelineStage::Send];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl fmt::Display for PipelineStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            PipelineStage::Capture => "capture",
+            PipelineStage::Encode => "encode",
+            PipelineStage::Send => "send",
+        })
+    }
+}
+
+/// An error from one stage of the host's frame pipeline
+#[derive(Debug)]
+pub struct StageError {
+    pub stage: PipelineStage,
+    pub error: anyhow::Error,
+}
+
+impl fmt::Display for StageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} failed: {:#}", self.stage, self.error)
+    }
+}
+
+impl std::error::Error for StageError {}
+
+/// Tag an error with the pipeline stage it came from
+pub trait StageContext<T> {
+    fn at_stage(self, stage: PipelineStage) -> Result<T, StageError>;
+}
+
+impl<T, E: Into<anyhow::Error>> StageContext<T> for Result<T, E> {
+    fn at_stage(self, stage: PipelineStage) -> Result<T, StageError> {
+        self.map_err(|error| StageError { stage, error: error.into() })
+    }
+}
+
+/// What to do about a failed stage
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Recovery {
+    /// Carry on with the next frame; the stage hasn't been failing long
+    Retry,
+    /// Recreate the stage's component, e.g. the capture context
+    Restart,
+    /// Stop sharing; restarting hasn't helped, or can't
+    GiveUp,
+}
+
+/// Notices pipeline stages that have stopped getting frames through, and
+/// decides when to restart them
+#[derive(Debug)]
+pub struct Watchdog {
+    config: WatchdogConfig,
+    // When each stage last got a frame through, or was restarted
+    progress: [Instant; 3],
+    restarts: [u32; 3],
+}
+
+impl Watchdog {
+    pub fn new(config: WatchdogConfig) -> Self {
+        let now = Instant::now();
+        Self {
+            config,
+            progress: [now; 3],
+            restarts: [0; 3],
+        }
+    }
+
+    pub fn config(&self) -> WatchdogConfig {
+        self.config
+    }
+
+    /// Note that `stage` got a frame through
+    pub fn progress(&mut self, stage: PipelineStage, at: Instant) {
+        self.progress[stage.index()] = at;
+        self.restarts[stage.index()] = 0;
+    }
+
+    /// How long `stage` has gone without getting a frame through
+    pub fn stalled_for(&self, stage: PipelineStage, at: Instant) -> Duration {
+        at.saturating_duration_since(self.progress[stage.index()])
+    }
+
+    /// Restarts of `stage` since it last got a frame through
+    pub fn restarts(&self, stage: PipelineStage) -> u32 {
+        self.restarts[stage.index()]
+    }
+
+    /// Decide what to do about `stage` failing at `at`. Sending can't be
+    /// restarted here: a failed or stalled send means the connection is in
+    /// trouble, which ends the session.
+    pub fn failed(&mut self, stage: PipelineStage, at: Instant) -> Recovery {
+        if stage == PipelineStage::Send {
+            return Recovery::GiveUp;
+        }
+        if self.stalled_for(stage, at) < self.config.stall_timeout {
+            return Recovery::Retry;
+        }
+        if self.restarts[stage.index()] >= self.config.max_restarts {
+            return Recovery::GiveUp;
+        }
+        self.restarts[stage.index()] += 1;
+        // Give the restarted stage the full timeout to recover
+        self.progress[stage.index()] = at;
+        Recovery::Restart
+    }
+
+    /// How long since each stage got a frame through, for logging when one
+    /// stalls
+    pub fn diagnostics(&self, at: Instant) -> String {
+        PipelineStage::ALL
+            .iter()
+            .map(|&stage| {
+                format!(
+                    "{}: {:.1}s since progress, {} restarts",
+                    stage,
+                    self.stalled_for(stage, at).as_secs_f64(),
+                    self.restarts(stage)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("; ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restarts_after_stall_then_gives_up() {
+        let config = WatchdogConfig { stall_timeout: Duration::from_secs(5), max_restarts: 2 };
+        let start = Instant::now();
+        let mut watchdog = Watchdog::new(config);
+        watchdog.progress(PipelineStage::Capture, start);
+
+        let at = |secs| start + Duration::from_secs(secs);
+        assert_eq!(watchdog.failed(PipelineStage::Capture, at(1)), Recovery::Retry);
+        assert_eq!(watchdog.failed(PipelineStage::Capture, at(5)), Recovery::Restart);
+        // The restart gets the full timeout
+        assert_eq!(watchdog.failed(PipelineStage::Capture, at(7)), Recovery::Retry);
+        assert_eq!(watchdog.failed(PipelineStage::Capture, at(10)), Recovery::Restart);
+        assert_eq!(watchdog.failed(PipelineStage::Capture, at(15)), Recovery::GiveUp);
+
+        // Getting a frame through clears the restarts
+        watchdog.progress(PipelineStage::Capture, at(16));
+        assert_eq!(watchdog.restarts(PipelineStage::Capture), 0);
+        assert_eq!(watchdog.failed(PipelineStage::Capture, at(21)), Recovery::Restart);
+    }
+
+    #[test]
+    fn test_failed_sends_are_not_retried() {
+        let start = Instant::now();
+        let mut watchdog = Watchdog::new(WatchdogConfig::default());
+        watchdog.progress(PipelineStage::Send, start);
+        assert_eq!(watchdog.failed(PipelineStage::Send, start), Recovery::GiveUp);
+        assert!(watchdog.diagnostics(start).starts_with("capture: 0.0s since progress"));
+    }
+}
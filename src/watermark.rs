@@ -0,0 +1,123 @@
+use crate::network::ViewerInfo;
+use crate::pcc::Frame;
+use pcc_core::font::{glyph, GLYPH_HEIGHT, GLYPH_WIDTH};
+use serde::{Deserialize, Serialize};
+
+// Blank glyphs between repeats of the text along a row
+const TILE_GAP: u32 = 6;
+// Glyph heights from one row of repeats to the next
+const ROW_PITCH: u32 = 6;
+
+/// Whether the host marks the frames it shares with who it shares them
+/// with, from the `[watermark]` section
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WatermarkConfig {
+    pub enabled: bool,
+    /// How strongly the text shows through, 0.0-1.0
+    pub opacity: f32,
+    /// Screen pixels per glyph pixel
+    pub scale: u32,
+}
+
+impl Default for WatermarkConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            opacity: 0.15,
+            scale: 3,
+        }
+    }
+}
+
+/// Text tiled faintly across every frame, naming the viewer it was shared
+/// with, so a leaked screenshot or recording can be traced to it. It's
+/// drawn before frames are diffed and coded, so the viewer can't leave it
+/// out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watermark {
+    text: String,
+    opacity: f32,
+    scale: u32,
+}
+
+impl Watermark {
+    pub fn new(text: &str, config: &WatermarkConfig) -> Self {
+        Self {
+            // The glyphs are capitals only
+            text: text.to_uppercase(),
+            opacity: config.opacity.clamp(0.0, 1.0),
+            scale: config.scale.max(1),
+        }
+    }
+
+    /// A watermark of the viewer's name, the start of its certificate
+    /// fingerprint and its address
+    pub fn for_viewer(viewer: &ViewerInfo, config: &WatermarkConfig) -> Self {
+        let mut parts = Vec::new();
+        parts.extend(viewer.name.clone());
+        // Four bytes are plenty to tell viewers apart
+        parts.extend(viewer.fingerprint.map(|fingerprint| fingerprint.to_string()[..11].to_string()));
+        parts.push(viewer.addr.ip().to_string());
+        Self::new(&parts.join(" "), config)
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Draw the text over an RGB24 frame, repeated so any crop of it still
+    /// carries the mark. Lit pixels are pushed towards black on light
+    /// content and white on dark, so it reads over both.
+    pub fn apply(&self, frame: &mut Frame) {
+        let (width, height) = (frame.width, frame.height);
+        if self.text.is_empty() || frame.data.len() < (width * height * 3) as usize {
+            return;
+        }
+        let advance = (GLYPH_WIDTH + 1) * self.scale;
+        let text_width = self.text.chars().count() as u32 * advance;
+        let tile_width = text_width + TILE_GAP * advance;
+        let row_height = GLYPH_HEIGHT * ROW_PITCH * self.scale;
+        let glyphs: Vec<[u8; 5]> = self.text.chars().map(glyph).collect();
+
+        frame.modify_data(|data| {
+            for (row, y) in (0..height).step_by(row_height as usize).enumerate() {
+                // Alternate rows are staggered by half a tile
+                let offset = (row as u32 % 2) * tile_width / 2;
+                let mut x = -(offset as i64);
+                while x < width as i64 {
+                    for (i, rows) in glyphs.iter().enumerate() {
+                        self.draw_glyph(data, (width, height), x + (i as u32 * advance) as i64, y, rows);
+                    }
+                    x += tile_width as i64;
+                }
+            }
+        });
+    }
+
+    // `data` is RGB24 pixels of a frame `size` big
+    fn draw_glyph(&self, data: &mut [u8], size: (u32, u32), x: i64, y: u32, rows: &[u8; 5]) {
+        let (width, height) = size;
+        for (gy, bits) in rows.iter().enumerate() {
+            for gx in 0..GLYPH_WIDTH {
+                if bits & (0b100 >> gx) == 0 {
+                    continue;
+                }
+                for sy in 0..self.scale {
+                    for sx in 0..self.scale {
+                        let px = x + (gx * self.scale + sx) as i64;
+                        let py = y + gy as u32 * self.scale + sy;
+                        if px < 0 || px >= width as i64 || py >= height {
+                            continue;
+                        }
+                        let i = ((py as u64 * width as u64 + px as u64) * 3) as usize;
+                        for c in &mut data[i..i + 3] {
+                            let target = if *c < 128 { 255.0 } else { 0.0 };
+                            *c = (*c as f32 + (target - *c as f32) * self.opacity).round() as u8;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
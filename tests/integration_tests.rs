@@ -1,12 +1,11 @@
 use anyhow::Result;
 use pixel_change_check_client::{
     encoder::FrameEncoder,
-    network::{NetworkConfig, ResilienceConfig, NetworkResilience},
-    pcc::{PCCDetector, QualityConfig, Frame, PixelChangeDetector},
+    network::{ResilienceConfig, NetworkResilience},
+    pcc::{PCCDetector, QualityConfig, Frame, PixelChangeDetector, PixelFormat},
     server::renderer::FrameBuffer,
 };
 use std::time::Duration;
-use tokio::time;
 
 // Test configurations
 const TEST_WIDTH: u32 = 1920;
@@ -19,7 +18,8 @@ fn create_test_frame(id: u64) -> Frame {
         timestamp: std::time::SystemTime::now(),
         width: TEST_WIDTH,
         height: TEST_HEIGHT,
-        data: vec![0; (TEST_WIDTH * TEST_HEIGHT * 3) as usize],
+        format: PixelFormat::Rgb24,
+        data: vec![0; (TEST_WIDTH * TEST_HEIGHT * 3) as usize].into(),
     }
 }
 
@@ -32,9 +32,7 @@ async fn test_pcc_detection_pipeline() -> Result<()> {
     let frame1 = create_test_frame(1);
     let mut frame2 = create_test_frame(2);
     // Modify some pixels in frame2
-    for i in 0..300 {
-        frame2.data[i] = 255;
-    }
+    frame2.modify_data(|data| data[..300].fill(255));
 
     // Detect changes
     let changes = detector.detect_changes(&frame1, &frame2)?;
@@ -102,7 +100,7 @@ async fn test_network_resilience() -> Result<()> {
 
 #[tokio::test]
 async fn test_quality_adaptation() -> Result<()> {
-    let mut encoder = FrameEncoder::new(TEST_WIDTH, TEST_HEIGHT, QualityConfig::default())?;
+    let encoder = FrameEncoder::new(TEST_WIDTH, TEST_HEIGHT, QualityConfig::default())?;
 
     // Test quality adjustment
     let configs = [
@@ -111,12 +109,14 @@ async fn test_quality_adaptation() -> Result<()> {
             max_fps: 60,
             quality: 0.8,
             compression_level: 6,
+            ..QualityConfig::default()
         },
         QualityConfig {
             target_fps: 15,
             max_fps: 30,
             quality: 0.5,
             compression_level: 8,
+            ..QualityConfig::default()
         },
     ];
 
@@ -213,7 +213,7 @@ async fn test_renderer_creation() -> Result<()> {
 
     // Push a frame with known data and verify rendering
     let mut frame = create_test_frame(1);
-    frame.data = vec![42; (TEST_WIDTH * TEST_HEIGHT * 3) as usize];
+    frame.data = vec![42; (TEST_WIDTH * TEST_HEIGHT * 3) as usize].into();
     renderer.buffer.push_frame(frame).await?;
 
     if let Some(buffered) = renderer.buffer.next_frame().await? {
@@ -229,4 +229,4110 @@ async fn test_renderer_creation() -> Result<()> {
 
     renderer.shutdown().await?;
     Ok(())
-} 
\ No newline at end of file
+} 
+#[tokio::test]
+async fn test_scroll_detection_and_copy_rect() -> Result<()> {
+    let detector = PCCDetector::default();
+    let row_len = (TEST_WIDTH * 3) as usize;
+
+    // Give every row distinct content so shifts are unambiguous
+    let mut frame1 = create_test_frame(1);
+    frame1.modify_data(|data| {
+        for (y, row) in data.chunks_exact_mut(row_len).enumerate() {
+            row.fill((y % 251) as u8);
+        }
+    });
+
+    // Scroll the content up by 40 rows
+    let mut frame2 = create_test_frame(2);
+    let shift = 40 * row_len;
+    frame2.modify_data(|data| data[..frame1.data.len() - shift].copy_from_slice(&frame1.data[shift..]));
+
+    let copy = detector
+        .detect_scroll(&frame1, &frame2, 64)
+        .expect("Should detect the scroll");
+    assert_eq!(copy.src_y, copy.dst_rect.y + 40);
+    assert_eq!(copy.dst_rect.width, TEST_WIDTH);
+
+    // Replaying the CopyRect on the viewer reproduces the scrolled rows
+    let buffer = FrameBuffer::new(TEST_WIDTH, TEST_HEIGHT);
+    buffer.push_frame(frame1).await?;
+    buffer.next_frame().await?;
+    buffer.copy_rect(copy.src_x, copy.src_y, copy.dst_rect).await?;
+
+    let current = buffer.current_frame().await.unwrap();
+    let start = copy.dst_rect.y as usize * row_len;
+    let end = start + copy.dst_rect.height as usize * row_len;
+    assert_eq!(current.data[start..end], frame2.data[start..end]);
+
+    // Out-of-bounds copies are rejected
+    let bad = pixel_change_check_client::pcc::Rect::new(0, TEST_HEIGHT - 1, TEST_WIDTH, 2);
+    assert!(buffer.copy_rect(0, 0, bad).await.is_err());
+
+    // After a resolution change, copies use the new frame's stride
+    let (width, height) = (8, 2);
+    let mut resized = Frame {
+        id: 3,
+        timestamp: std::time::SystemTime::now(),
+        width,
+        height,
+        format: PixelFormat::Rgb24,
+        data: (0..width * height * 3).map(|i| i as u8).collect::<Vec<u8>>().into(),
+    };
+    buffer.push_frame(resized.clone()).await?;
+    buffer.next_frame().await?;
+    assert_eq!(buffer.dimensions(), (width, height));
+    let copy = pixel_change_check_client::pcc::Rect::new(0, 0, 6, 2);
+    buffer.copy_rect(2, 0, copy).await?;
+    resized.copy_rect(2, 0, copy)?;
+    assert_eq!(buffer.current_frame().await.unwrap().data, resized.data);
+
+    Ok(())
+}
+
+#[test]
+fn test_host_sends_scrolls_as_copies() -> Result<()> {
+    use pixel_change_check_client::client::{FrameOutput, FramePipeline};
+    use pixel_change_check_client::network::EncodedFrame;
+    use pixel_change_check_client::testing::{assert_frames_match, FrameGenerator, Pattern, RoundTrip};
+
+    // A page of noise scrolled up 30 rows, with new rows coming in below
+    let (width, height) = (320, 240);
+    let row_len = width as usize * 3;
+    let page = FrameGenerator::new(width, height, Pattern::Noise { seed: 3 }).frame(0);
+    let incoming = FrameGenerator::new(width, height, Pattern::Noise { seed: 4 }).frame(1);
+    let mut scrolled = Frame { id: 1, ..page.clone() };
+    scrolled.modify_data(|data| {
+        let shift = 30 * row_len;
+        data.copy_within(shift.., 0);
+        let kept = data.len() - shift;
+        data[kept..].copy_from_slice(&incoming.data[kept..]);
+    });
+
+    let detector = || PCCDetector::new(QualityConfig::default(), 0, 16);
+    let mut pipeline = FramePipeline::new(detector());
+    assert!(matches!(pipeline.process(page.clone(), false)?, FrameOutput::Keyframe(_)));
+    let FrameOutput::Scroll { copy, update } = pipeline.process(scrolled.clone(), false)? else {
+        panic!("Expected the scroll to be sent as a copy");
+    };
+    assert_eq!(copy.src_y, copy.dst_rect.y + 30);
+    // Only the rows scrolled in are sent as pixels
+    let sent = EncodedFrame::scroll(copy, &update)?.len();
+    assert!(sent < scrolled.data.len() / 4, "{} bytes sent for the scroll", sent);
+
+    // The viewer ends up showing the scrolled page
+    let mut round_trip = RoundTrip::new(detector());
+    round_trip.send(page)?;
+    assert_frames_match(&scrolled, &round_trip.send(scrolled.clone())?, 0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_side_channel_topics() -> Result<()> {
+    use pixel_change_check_client::network::{Message, SideChannel};
+
+    let channel = SideChannel::new();
+    let mut chat = channel.subscribe("chat").await;
+    let mut annotations = channel.subscribe("annotations").await;
+
+    // Round-trip through the wire format like a real peer message
+    let sent = SideChannel::message("chat", b"hello".to_vec()).serialize()?;
+    let received = Message::deserialize(&sent)?;
+
+    assert_eq!(channel.dispatch(&received).await, 1);
+    assert_eq!(chat.recv().await.unwrap(), b"hello");
+    assert!(annotations.try_recv().is_err(), "Other topics should not receive it");
+
+    // Dropped subscribers are pruned
+    drop(chat);
+    assert_eq!(channel.dispatch(&received).await, 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_close_reason_codes() -> Result<()> {
+    use pixel_change_check_client::network::{CloseReason, Message};
+
+    let reasons = [
+        CloseReason::Normal,
+        CloseReason::HostStoppedSharing,
+        CloseReason::Kicked,
+        CloseReason::IdleTimeout,
+        CloseReason::ProtocolError,
+        CloseReason::ConnectionLost,
+        CloseReason::QuotaExceeded,
+        CloseReason::PairingFailed,
+        CloseReason::NotApproved,
+        CloseReason::AccessDenied,
+        CloseReason::RecordingUndisclosed,
+    ];
+    for reason in reasons {
+        assert_eq!(CloseReason::from_code(reason.code()), reason);
+
+        let bytes = Message::Goodbye { reason }.serialize()?;
+        match Message::deserialize(&bytes)? {
+            Message::Goodbye { reason: decoded } => assert_eq!(decoded, reason),
+            other => panic!("Unexpected message: {:?}", other),
+        }
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_keyframe_request_reaches_encoder() -> Result<()> {
+    use pixel_change_check_client::input::{InputConfig, RemoteInput};
+    use pixel_change_check_client::audio::AudioControls;
+    use pixel_change_check_client::network::{handle_host_events, CloseReason, Message, NetworkEvent};
+
+    let mut input = RemoteInput::new(&InputConfig::default(), TEST_WIDTH, TEST_HEIGHT)?;
+    let encoder = FrameEncoder::new(TEST_WIDTH, TEST_HEIGHT, QualityConfig::default())?;
+    assert!(!encoder.take_keyframe_request());
+
+    let (tx, mut rx) = tokio::sync::mpsc::channel(4);
+    tx.send(NetworkEvent::from(Message::RequestKeyframe)).await?;
+    tx.send(NetworkEvent::Closed(CloseReason::Kicked)).await?;
+
+    let reason = handle_host_events(&mut rx, &encoder, &mut input, &AudioControls::default()).await;
+    assert_eq!(reason, CloseReason::Kicked);
+    assert!(encoder.take_keyframe_request(), "Keyframe should have been forced");
+    assert!(!encoder.take_keyframe_request(), "Request is consumed once taken");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_session_resumption() -> Result<()> {
+    use pixel_change_check_client::network::{ResumeToken, SessionRegistry};
+
+    let sessions = SessionRegistry::new(Duration::from_secs(60));
+
+    // A fresh session starts from a keyframe
+    let first = sessions.open(None).await?;
+    assert_eq!(first.resume_from, None);
+
+    sessions.acknowledge(&first.token, 10).await;
+    sessions.acknowledge(&first.token, 12).await;
+    sessions.acknowledge(&first.token, 11).await;
+
+    // Reconnecting with the token continues after the last acknowledged frame
+    let resumed = sessions.open(Some(first.token)).await?;
+    assert_eq!(resumed.token, first.token);
+    assert_eq!(resumed.resume_from, Some(12));
+
+    // Unknown tokens get a brand-new session
+    let unknown = sessions.open(Some(ResumeToken::generate()?)).await?;
+    assert_ne!(unknown.token, first.token);
+    assert_eq!(unknown.resume_from, None);
+
+    // Expired sessions cannot be resumed
+    let short = SessionRegistry::new(Duration::from_millis(10));
+    let session = short.open(None).await?;
+    tokio::time::sleep(Duration::from_millis(30)).await;
+    let after = short.open(Some(session.token)).await?;
+    assert_ne!(after.token, session.token);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_loopback_transport_reassembly() -> Result<()> {
+    use pixel_change_check_client::network::{LoopbackConfig, LoopbackTransport, Transport};
+
+    let config = LoopbackConfig {
+        latency: Duration::from_millis(5),
+        reorder: 0.5,
+        ..LoopbackConfig::default()
+    };
+    let (mut host, mut viewer) = LoopbackTransport::pair(config);
+
+    // Full-size frames span many protocol chunks
+    for id in 0..3 {
+        let mut frame = create_test_frame(id);
+        frame.modify_data(|data| {
+            for (i, byte) in data.iter_mut().enumerate() {
+                *byte = (i as u64 * 31 + id) as u8;
+            }
+        });
+        host.send_frame(&frame).await?;
+
+        let received = viewer.receive_frame().await?;
+        assert_eq!(received.id, id);
+        assert_eq!(received.width, TEST_WIDTH);
+        assert_eq!(received.height, TEST_HEIGHT);
+        assert!(received.data == frame.data, "Reordered chunks should reassemble exactly");
+    }
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_loopback_transport_loss() -> Result<()> {
+    use pixel_change_check_client::network::{LoopbackConfig, LoopbackTransport, Transport};
+
+    let (mut host, mut viewer) = LoopbackTransport::pair(LoopbackConfig {
+        loss: 0.5,
+        ..LoopbackConfig::default()
+    });
+
+    // Small single-chunk frames: each one either arrives whole or not at all
+    let mut sent = Vec::new();
+    for id in 0..20 {
+        let frame = Frame {
+            id,
+            timestamp: std::time::SystemTime::now(),
+            width: 4,
+            height: 4,
+            format: PixelFormat::Rgb24,
+            data: vec![id as u8; 4 * 4 * 3].into(),
+        };
+        host.send_frame(&frame).await?;
+        sent.push(frame);
+    }
+    drop(host);
+
+    let mut received = Vec::new();
+    while let Ok(frame) = viewer.receive_frame().await {
+        assert_eq!(frame.data, sent[frame.id as usize].data);
+        received.push(frame.id);
+    }
+    assert!(!received.is_empty() && received.len() < sent.len());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_jitter_buffer_playout() -> Result<()> {
+    use pixel_change_check_client::server::renderer::{FrameBufferConfig, JitterConfig};
+
+    let config = FrameBufferConfig {
+        jitter: JitterConfig {
+            target_latency: Duration::from_millis(50),
+            ..JitterConfig::default()
+        },
+        ..FrameBufferConfig::default()
+    };
+    let buffer = FrameBuffer::with_config(TEST_WIDTH, TEST_HEIGHT, config);
+
+    buffer.push_frame(create_test_frame(1)).await?;
+    assert!(buffer.next_frame().await?.is_none(), "Frame is held for the target latency");
+    assert_eq!(buffer.depth().await, 1);
+
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    let frame = buffer.next_frame().await?;
+    assert_eq!(frame.map(|f| f.id), Some(1));
+
+    // A frame that spent longer in transit has less playout delay left
+    let mut late = create_test_frame(2);
+    late.timestamp -= Duration::from_millis(40);
+    buffer.push_frame(late).await?;
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert_eq!(buffer.next_frame().await?.map(|f| f.id), Some(2));
+    assert!(buffer.jitter().await > Duration::ZERO);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_frame_buffer_catch_up() -> Result<()> {
+    use pixel_change_check_client::server::renderer::{CatchUpPolicy, FrameBufferConfig};
+
+    // By default a backlog is skipped so the viewer snaps back to live
+    let buffer = FrameBuffer::new(TEST_WIDTH, TEST_HEIGHT);
+    for id in 1..=3 {
+        buffer.push_frame(create_test_frame(id)).await?;
+    }
+    assert_eq!(buffer.next_frame().await?.map(|f| f.id), Some(3));
+    assert_eq!(buffer.skipped_frames().await, 2);
+    assert!(buffer.next_frame().await?.is_none());
+
+    // PlayAll presents every frame in order
+    let buffer = FrameBuffer::with_config(
+        TEST_WIDTH,
+        TEST_HEIGHT,
+        FrameBufferConfig {
+            catch_up: CatchUpPolicy::PlayAll,
+            ..FrameBufferConfig::default()
+        },
+    );
+    for id in 1..=3 {
+        buffer.push_frame(create_test_frame(id)).await?;
+    }
+    for id in 1..=3 {
+        assert_eq!(buffer.next_frame().await?.map(|f| f.id), Some(id));
+    }
+    assert_eq!(buffer.skipped_frames().await, 0);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_delta_updates_reach_the_screen() -> Result<()> {
+    use pixel_change_check_client::{
+        network::{FrameProtocol, Message},
+        pcc::FrameUpdate,
+        server::renderer::Renderer,
+    };
+
+    let width = 256;
+    let height = 128;
+    let frame = |id, fill: u8| Frame {
+        id,
+        timestamp: std::time::SystemTime::now(),
+        width,
+        height,
+        format: PixelFormat::Rgb24,
+        data: vec![fill; (width * height * 3) as usize].into(),
+    };
+
+    let keyframe = frame(1, 0);
+    let mut next = frame(2, 0);
+    // Change a rectangle spanning several detector blocks
+    next.modify_data(|data| {
+        for y in 20..90 {
+            for x in 30..200 {
+                let i = ((y * width + x) * 3) as usize;
+                data[i..i + 3].copy_from_slice(&[10, 200, 30]);
+            }
+        }
+    });
+
+    let renderer = Renderer::new(width, height, 30).await?;
+    renderer.buffer.push_frame(keyframe.clone()).await?;
+    renderer.buffer.next_frame().await?;
+
+    let detector = PCCDetector::default();
+    let update = FrameUpdate {
+        frame_id: next.id,
+        timestamp: next.timestamp,
+        changes: detector.detect_changes(&keyframe, &next)?,
+    };
+    assert!(!update.changes.is_empty());
+
+    // Over the wire and into the renderer, part by part
+    for bytes in FrameProtocol::encode_update(&update)? {
+        renderer.handle_message(Message::deserialize(&bytes)?).await?;
+    }
+
+    let current = renderer.buffer.current_frame().await.unwrap();
+    assert_eq!(current.id, 2);
+    assert!(*current.data == next.data, "Deltas should reproduce the new frame");
+    assert!(renderer.get_current_frame().await == next.data, "And it should be presented");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_session_recording() -> Result<()> {
+    use pixel_change_check_client::{
+        network::{FrameProtocol, Message},
+        pcc::FrameUpdate,
+        server::{recorder::RecordingConfig, renderer::Renderer},
+    };
+
+    let (width, height) = (64, 48);
+    let start = std::time::SystemTime::now();
+    let renderer = Renderer::new(width, height, 30).await?;
+    renderer
+        .buffer
+        .push_frame(Frame {
+            id: 1,
+            timestamp: start,
+            width,
+            height,
+            format: PixelFormat::Rgb24,
+            data: vec![90; (width * height * 3) as usize].into(),
+        })
+        .await?;
+    renderer.buffer.next_frame().await?;
+
+    let path = std::env::temp_dir().join(format!("pcc-recording-{}.mkv", std::process::id()));
+    let mut config = RecordingConfig::new(&path);
+    config.min_free_space = 0;
+    renderer.start_recording(config.clone()).await?;
+    assert!(renderer.start_recording(config).await.is_err(), "Only one recording at a time");
+
+    // Cursor moves re-present frame 1 but must not record it twice
+    renderer.set_cursor_position(5, 5).await?;
+    renderer.set_cursor_position(6, 6).await?;
+    for id in 2..=3 {
+        let update = FrameUpdate {
+            frame_id: id,
+            timestamp: start + Duration::from_millis(100 * id),
+            changes: Vec::new(),
+        };
+        for bytes in FrameProtocol::encode_update(&update)? {
+            renderer.handle_message(Message::deserialize(&bytes)?).await?;
+        }
+    }
+
+    let summary = renderer.stop_recording().await?.expect("Recording was active");
+    assert_eq!(summary.frames, 3);
+    assert_eq!(summary.duration, Duration::from_millis(300));
+
+    let bytes = std::fs::read(&path)?;
+    std::fs::remove_file(&path)?;
+    assert_eq!(bytes.len() as u64, summary.bytes);
+    assert_eq!(&bytes[..4], &[0x1A, 0x45, 0xDF, 0xA3], "EBML header");
+    assert!(bytes.windows(7).any(|w| w == b"V_MJPEG"));
+    assert!(!renderer.is_recording().await);
+
+    // The trailer indexes the clusters and fills in the segment size
+    assert!(bytes.windows(4).any(|w| w == [0x1C, 0x53, 0xBB, 0x6B]), "Cues");
+    let segment = bytes
+        .windows(4)
+        .position(|w| w == [0x18, 0x53, 0x80, 0x67])
+        .expect("Segment");
+    let size = u64::from_be_bytes(bytes[segment + 4..segment + 12].try_into()?) & !(1 << 56);
+    assert_eq!(segment as u64 + 12 + size, summary.bytes);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_recording_size_limit() -> Result<()> {
+    use pixel_change_check_client::server::{recorder::RecordingConfig, renderer::Renderer};
+
+    let (width, height) = (32, 32);
+    let renderer = Renderer::new(width, height, 30).await?;
+    renderer
+        .buffer
+        .push_frame(Frame {
+            id: 1,
+            timestamp: std::time::SystemTime::now(),
+            width,
+            height,
+            format: PixelFormat::Rgb24,
+            data: vec![200; (width * height * 3) as usize].into(),
+        })
+        .await?;
+    renderer.buffer.next_frame().await?;
+
+    let path = std::env::temp_dir().join(format!("pcc-limit-{}.mkv", std::process::id()));
+    let mut config = RecordingConfig::new(&path);
+    config.min_free_space = 0;
+    config.max_file_size = Some(16);
+    renderer.start_recording(config).await?;
+
+    // The first frame exceeds the limit, so recording stops on its own
+    renderer.set_cursor_position(1, 1).await?;
+    assert!(!renderer.is_recording().await);
+    std::fs::remove_file(&path)?;
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_screenshot_saves_current_frame() -> Result<()> {
+    use pixel_change_check_client::server::renderer::{Hotkey, Renderer};
+
+    let (width, height) = (16, 8);
+    let renderer = Renderer::new(width, height, 30).await?;
+    let path = std::env::temp_dir().join(format!("pcc-screenshot-{}.png", std::process::id()));
+    assert!(renderer.screenshot(&path).await.is_err(), "Nothing presented yet");
+
+    let data: Vec<u8> = (0..width * height * 3).map(|i| (i * 5 % 256) as u8).collect();
+    renderer
+        .buffer
+        .push_frame(Frame {
+            id: 1,
+            timestamp: std::time::SystemTime::now(),
+            width,
+            height,
+            format: PixelFormat::Rgb24,
+            data: data.clone().into(),
+        })
+        .await?;
+    renderer.buffer.next_frame().await?;
+
+    renderer.screenshot(&path).await?;
+    let saved = image::open(&path)?.into_rgb8();
+    std::fs::remove_file(&path)?;
+    assert_eq!(saved.dimensions(), (width, height));
+    assert_eq!(saved.into_raw(), data);
+
+    // A frame whose data doesn't match its size never reaches a screenshot
+    let short = Frame {
+        id: 2,
+        timestamp: std::time::SystemTime::now(),
+        width,
+        height,
+        format: PixelFormat::Rgb24,
+        data: data[..data.len() - 3].to_vec().into(),
+    };
+    assert!(renderer.buffer.push_frame(short.clone()).await.is_err());
+    let huge = Frame { id: 3, width: u32::MAX, height: u32::MAX, ..short };
+    assert!(renderer.buffer.push_frame(huge).await.is_err());
+
+    // The hotkey saves into the configured directory
+    let dir = std::env::temp_dir().join(format!("pcc-screenshots-{}", std::process::id()));
+    std::fs::create_dir_all(&dir)?;
+    renderer.set_screenshot_dir(&dir).await;
+    assert_eq!(Hotkey::from_key("F12"), Some(Hotkey::Screenshot));
+    renderer.handle_hotkey(Hotkey::Screenshot).await?;
+    let saved = std::fs::read_dir(&dir)?.count();
+    std::fs::remove_dir_all(&dir)?;
+    assert_eq!(saved, 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_mosaic_click_to_focus() -> Result<()> {
+    use pixel_change_check_client::server::renderer::{Hotkey, Renderer, PRIMARY_STREAM};
+
+    let frame = |id, rgb: [u8; 3]| Frame {
+        id,
+        timestamp: std::time::SystemTime::now(),
+        width: 4,
+        height: 4,
+        format: PixelFormat::Rgb24,
+        data: rgb.repeat(16).into(),
+    };
+    let pixel = |output: &[u8], x: usize, y: usize| {
+        let i = (y * 8 + x) * 3;
+        [output[i], output[i + 1], output[i + 2]]
+    };
+
+    let renderer = Renderer::new(8, 4, 30).await?;
+    renderer.buffer.push_frame(frame(1, [255, 0, 0])).await?;
+    renderer.buffer.next_frame().await?;
+
+    let second = renderer.add_stream(1, 4, 4).await?;
+    assert!(renderer.add_stream(1, 4, 4).await.is_err());
+    second.push_frame(frame(1, [0, 0, 255])).await?;
+    second.next_frame().await?;
+    renderer.focus_stream(None).await?;
+
+    // Two streams side by side
+    let tiles = renderer.mosaic_layout().await;
+    assert_eq!(tiles.len(), 2);
+    let output = renderer.get_current_frame().await;
+    assert_eq!(pixel(&output, 1, 1), [255, 0, 0]);
+    assert_eq!(pixel(&output, 6, 1), [0, 0, 255]);
+
+    // Clicking a tile switches to single view, letterboxed on the surface
+    assert_eq!(renderer.click(6, 1).await?, Some(1));
+    assert_eq!(renderer.focused_stream().await, Some(1));
+    let output = renderer.get_current_frame().await;
+    assert_eq!(pixel(&output, 0, 0), [0, 0, 0]);
+    assert_eq!(pixel(&output, 4, 2), [0, 0, 255]);
+    assert_eq!(renderer.click(1, 1).await?, None, "Clicks in single view don't switch");
+
+    renderer.handle_hotkey(Hotkey::ShowMosaic).await?;
+    assert_eq!(renderer.focused_stream().await, None);
+
+    renderer.remove_stream(1).await?;
+    assert!(renderer.remove_stream(PRIMARY_STREAM).await.is_err());
+    let output = renderer.get_current_frame().await;
+    assert_eq!(pixel(&output, 4, 2), [255, 0, 0], "Back to the primary stream alone");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_announced_color_space_is_converted_for_display() -> Result<()> {
+    use pixel_change_check_client::{
+        network::Message,
+        pcc::ColorSpace,
+        server::renderer::{Renderer, RendererOptions},
+    };
+
+    let data = [180, 90, 90, 128, 128, 128].repeat(8);
+    let renderer = Renderer::new(4, 4, 30).await?;
+    renderer
+        .buffer
+        .push_frame(Frame {
+            id: 1,
+            timestamp: std::time::SystemTime::now(),
+            width: 4,
+            height: 4,
+            format: PixelFormat::Rgb24,
+            data: data.clone().into(),
+        })
+        .await?;
+    renderer.buffer.next_frame().await?;
+    renderer.set_cursor_position(0, 0).await?;
+    assert_eq!(renderer.get_current_frame().await, data, "sRGB on sRGB is untouched");
+
+    // A P3 host on an sRGB display: colors change, neutrals don't
+    let p3 = Message::deserialize(&Message::ColorSpace(ColorSpace::DisplayP3).serialize()?)?;
+    renderer.handle_message(p3).await?;
+    let output = renderer.get_current_frame().await;
+    assert!(output[0] > 180 && output[1] < 90);
+    assert_eq!(&output[3..6], &[128, 128, 128]);
+
+    // Once the display is P3 as well, pixels pass straight through
+    renderer
+        .set_options(RendererOptions {
+            display_color_space: ColorSpace::DisplayP3,
+            ..renderer.options().await
+        })
+        .await?;
+    assert_eq!(renderer.get_current_frame().await, data);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_frame_sinks() -> Result<()> {
+    use pixel_change_check_client::server::{
+        recorder::{RecordingConfig, RecordingSink},
+        renderer::Renderer,
+        sink::play_out,
+        FrameSink, NullSink,
+    };
+    use std::sync::Arc;
+
+    let frame = |id: u64| Frame {
+        id,
+        timestamp: std::time::SystemTime::now(),
+        width: 16,
+        height: 16,
+        format: PixelFormat::Rgb24,
+        data: vec![id as u8 * 40; 16 * 16 * 3].into(),
+    };
+
+    // The same calls drive every kind of sink
+    let path = std::env::temp_dir().join(format!("pcc-sink-{}.mkv", std::process::id()));
+    let mut config = RecordingConfig::new(&path);
+    config.min_free_space = 0;
+    let null = Arc::new(NullSink::new());
+    let recording = Arc::new(RecordingSink::new(config));
+    let renderer = Arc::new(Renderer::new(16, 16, 30).await?);
+    let sinks: Vec<Arc<dyn FrameSink>> = vec![null.clone(), recording.clone(), renderer.clone()];
+
+    for sink in &sinks {
+        sink.present(frame(1)).await?;
+        sink.resize(32, 32).await?;
+        sink.present(frame(2)).await?;
+        sink.shutdown().await?;
+    }
+
+    assert_eq!(null.presented(), 2);
+    assert_eq!(renderer.surface_size().await, (32, 32));
+    assert_eq!(recording.summary().await.map(|s| s.frames), Some(2));
+    assert!(recording.present(frame(3)).await.is_err(), "Finished recordings stay finished");
+    std::fs::remove_file(&path)?;
+
+    // The playout loop feeds any sink from a frame buffer
+    let buffer = FrameBuffer::new(16, 16);
+    buffer.push_frame(frame(1)).await?;
+    let null = NullSink::new();
+    let _ = tokio::time::timeout(Duration::from_millis(200), play_out(&buffer, &null, 30)).await;
+    assert_eq!(null.presented(), 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_pause_and_resume() -> Result<()> {
+    use pixel_change_check_client::{
+        network::{FrameProtocol, Message},
+        pcc::{FrameUpdate, PixelChange},
+        server::renderer::{PauseMode, Renderer},
+    };
+
+    let renderer = Renderer::new(4, 4, 30).await?;
+    renderer
+        .buffer
+        .push_frame(Frame {
+            id: 1,
+            timestamp: std::time::SystemTime::now(),
+            width: 4,
+            height: 4,
+            format: PixelFormat::Rgb24,
+            data: vec![0; 4 * 4 * 3].into(),
+        })
+        .await?;
+    renderer.buffer.next_frame().await?;
+    renderer.set_cursor_position(100, 100).await?;
+
+    let update = |frame_id, value| -> Result<Message> {
+        let update = FrameUpdate {
+            frame_id,
+            timestamp: std::time::SystemTime::now(),
+            changes: vec![PixelChange {
+                x: 0,
+                y: 0,
+                width: 4,
+                height: 4,
+                data: vec![value; 4 * 4 * 3],
+            }],
+        };
+        Message::deserialize(&FrameProtocol::encode_update(&update)?[0])
+    };
+
+    // Updates land in the back buffer while the screen stays frozen
+    renderer.pause(PauseMode::ApplyUpdates).await;
+    renderer.handle_message(update(2, 50)?).await?;
+    assert_eq!(renderer.get_current_frame().await, vec![0; 4 * 4 * 3]);
+    assert_eq!(renderer.buffer.current_frame().await.unwrap().id, 2);
+
+    // Resuming snaps to live without needing a keyframe
+    assert!(!renderer.resume().await?);
+    assert_eq!(renderer.get_current_frame().await, vec![50; 4 * 4 * 3]);
+
+    // Dropped updates leave the back buffer stale until a keyframe arrives
+    renderer.pause(PauseMode::DropUpdates).await;
+    renderer.handle_message(update(3, 90)?).await?;
+    assert_eq!(renderer.buffer.current_frame().await.unwrap().id, 2);
+    assert!(renderer.resume().await?);
+    assert_eq!(renderer.get_current_frame().await, vec![50; 4 * 4 * 3]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_zoom_and_pan() -> Result<()> {
+    use pixel_change_check_client::server::renderer::{Renderer, RendererOptions, ScaleFilter, Zoom};
+
+    // Top-left quadrant red, the rest black
+    let mut data = vec![0; 8 * 8 * 3];
+    for y in 0..4 {
+        for x in 0..4 {
+            data[(y * 8 + x) * 3] = 255;
+        }
+    }
+    let renderer = Renderer::new(8, 8, 30).await?;
+    renderer
+        .set_options(RendererOptions {
+            scale_filter: ScaleFilter::Nearest,
+            ..RendererOptions::default()
+        })
+        .await?;
+    renderer
+        .buffer
+        .push_frame(Frame {
+            id: 1,
+            timestamp: std::time::SystemTime::now(),
+            width: 8,
+            height: 8,
+            format: PixelFormat::Rgb24,
+            data: data.into(),
+        })
+        .await?;
+    renderer.buffer.next_frame().await?;
+
+    // Zooming 2x about the top-left corner fills the screen with red
+    renderer.zoom_at(0, 0, 2.0).await?;
+    assert_eq!(renderer.zoom().await.factor, 2.0);
+    assert!(renderer.get_current_frame().await.chunks(3).all(|p| p == [255, 0, 0]));
+    assert_eq!(renderer.surface_to_frame(7, 7).await, Some((3, 3)));
+
+    // Dragging can't pull the frame off the surface
+    renderer.pan_by(-100, -100).await?;
+    assert_eq!(renderer.surface_to_frame(0, 0).await, Some((4, 4)));
+    assert!(renderer.get_current_frame().await.chunks(3).all(|p| p == [0, 0, 0]));
+
+    // Scrolling out past 1x stops at the scale mode's own layout
+    renderer.scroll(4, 4, -10.0).await?;
+    assert_eq!(renderer.zoom().await, Zoom::default());
+    assert_eq!(renderer.surface_to_frame(7, 7).await, Some((7, 7)));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_frame_buffer_eviction_policies() -> Result<()> {
+    use pixel_change_check_client::server::renderer::{CatchUpPolicy, EvictionPolicy, FrameBufferConfig};
+
+    let frame = |id| Frame {
+        id,
+        timestamp: std::time::SystemTime::now(),
+        width: 2,
+        height: 2,
+        format: PixelFormat::Rgb24,
+        data: vec![0; 12].into(),
+    };
+    let config = |eviction| FrameBufferConfig {
+        capacity: 2,
+        eviction,
+        catch_up: CatchUpPolicy::PlayAll,
+        ..FrameBufferConfig::default()
+    };
+
+    // Dropping the oldest keeps the newest frames
+    let buffer = FrameBuffer::with_config(2, 2, config(EvictionPolicy::DropOldest));
+    for id in 1..=4 {
+        buffer.push_frame(frame(id)).await?;
+    }
+    assert_eq!(buffer.evictions().await.dropped_oldest, 2);
+    assert_eq!(buffer.next_frame().await?.map(|f| f.id), Some(3));
+
+    // Rejecting keeps the queue as it was
+    let buffer = FrameBuffer::with_config(2, 2, config(EvictionPolicy::RejectNew));
+    for id in 1..=4 {
+        buffer.push_frame(frame(id)).await?;
+    }
+    assert_eq!(buffer.evictions().await.rejected, 2);
+    assert_eq!(buffer.next_frame().await?.map(|f| f.id), Some(1));
+
+    // Stale frames expire after the configured timeout
+    let buffer = FrameBuffer::with_config(
+        2,
+        2,
+        FrameBufferConfig {
+            frame_timeout: Duration::from_millis(10),
+            ..FrameBufferConfig::default()
+        },
+    );
+    buffer.push_frame(frame(1)).await?;
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert!(buffer.next_frame().await?.is_none());
+    assert_eq!(buffer.evictions().await.expired, 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_frame_buffer_reordering() -> Result<()> {
+    use pixel_change_check_client::server::renderer::{CatchUpPolicy, FrameBufferConfig};
+
+    let start = std::time::SystemTime::now();
+    let frame = |id: u64| Frame {
+        id,
+        timestamp: start + Duration::from_millis(id),
+        width: 2,
+        height: 2,
+        format: PixelFormat::Rgb24,
+        data: vec![0; 12].into(),
+    };
+    let buffer = FrameBuffer::with_config(
+        2,
+        2,
+        FrameBufferConfig {
+            capacity: 8,
+            catch_up: CatchUpPolicy::PlayAll,
+            reorder_window: Duration::from_millis(50),
+            ..FrameBufferConfig::default()
+        },
+    );
+
+    // Out of order arrivals are played in id order, duplicates once
+    for id in [2, 1, 3, 2] {
+        buffer.push_frame(frame(id)).await?;
+    }
+    assert_eq!(buffer.evictions().await.duplicates, 1);
+    for id in 1..=3 {
+        assert_eq!(buffer.next_frame().await?.map(|f| f.id), Some(id));
+    }
+
+    // A frame after a gap waits for the reorder window...
+    buffer.push_frame(frame(5)).await?;
+    assert!(buffer.next_frame().await?.is_none());
+    // ...and the missing one slots in ahead of it
+    buffer.push_frame(frame(4)).await?;
+    assert_eq!(buffer.next_frame().await?.map(|f| f.id), Some(4));
+    assert_eq!(buffer.next_frame().await?.map(|f| f.id), Some(5));
+
+    // Once past a frame, stragglers are dropped
+    buffer.push_frame(frame(3)).await?;
+    assert_eq!(buffer.evictions().await.late, 1);
+
+    // Gaps that never fill are played past after the window
+    buffer.push_frame(frame(7)).await?;
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    assert_eq!(buffer.next_frame().await?.map(|f| f.id), Some(7));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_frame_buffer_stats() -> Result<()> {
+    use pixel_change_check_client::server::renderer::{
+        BufferStats, CatchUpPolicy, EvictionPolicy, FrameBufferConfig, Renderer,
+    };
+
+    let start = std::time::SystemTime::now();
+    let frame = |id: u64| Frame {
+        id,
+        timestamp: start + Duration::from_millis(id),
+        width: 2,
+        height: 2,
+        format: PixelFormat::Rgb24,
+        data: vec![0; 12].into(),
+    };
+    let buffer = FrameBuffer::with_config(
+        2,
+        2,
+        FrameBufferConfig {
+            capacity: 2,
+            eviction: EvictionPolicy::RejectNew,
+            catch_up: CatchUpPolicy::PlayAll,
+            ..FrameBufferConfig::default()
+        },
+    );
+    assert_eq!(buffer.stats().await, BufferStats::default());
+
+    for id in [2, 3, 4] {
+        buffer.push_frame(frame(id)).await?;
+    }
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    buffer.next_frame().await?;
+    buffer.push_frame(frame(1)).await?;
+
+    let stats = buffer.stats().await;
+    assert_eq!(stats.depth, 1);
+    assert_eq!(stats.pushed, 4);
+    assert_eq!(stats.played, 1);
+    assert_eq!(stats.dropped_full, 1);
+    assert_eq!(stats.dropped_late, 1);
+    assert!(stats.average_wait >= Duration::from_millis(10));
+
+    // The overlay picks the figures up on present
+    let renderer = Renderer::new(2, 2, 30).await?;
+    renderer.buffer.push_frame(frame(2)).await?;
+    renderer.buffer.next_frame().await?;
+    renderer.buffer.push_frame(frame(1)).await?;
+    renderer.set_cursor_position(0, 0).await?;
+    let overlay = renderer.overlay_stats().await;
+    assert_eq!(overlay.buffer_depth, 0);
+    assert_eq!(overlay.dropped_frames, 1);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_last_frame_survives_reconnect() -> Result<()> {
+    use pixel_change_check_client::{
+        network::{CloseReason, FrameProtocol, Message},
+        pcc::{FrameUpdate, PixelChange},
+        server::renderer::Renderer,
+    };
+    use std::sync::{Arc, Mutex};
+
+    let frame = |id, value| Frame {
+        id,
+        timestamp: std::time::SystemTime::now(),
+        width: 2,
+        height: 2,
+        format: PixelFormat::Rgb24,
+        data: vec![value; 12].into(),
+    };
+    let delta = |frame_id, value| -> Result<Message> {
+        let update = FrameUpdate {
+            frame_id,
+            timestamp: std::time::SystemTime::now(),
+            changes: vec![PixelChange { x: 0, y: 0, width: 1, height: 1, data: vec![value; 3] }],
+        };
+        Message::deserialize(&FrameProtocol::encode_update(&update)?[0])
+    };
+
+    let renderer = Renderer::new(2, 2, 30).await?;
+    let indicator = Arc::new(Mutex::new(Vec::new()));
+    let seen = indicator.clone();
+    renderer.on_reconnecting(move |reconnecting| seen.lock().unwrap().push(reconnecting)).await;
+
+    renderer.buffer.push_frame(frame(1, 10)).await?;
+    renderer.buffer.next_frame().await?;
+    renderer.handle_message(delta(2, 20)?).await?;
+
+    // The connection drops: the frame stays, deltas wait for a keyframe
+    assert!(CloseReason::ConnectionLost.may_reconnect());
+    assert!(!CloseReason::HostStoppedSharing.may_reconnect());
+    renderer.set_reconnecting(true).await;
+    renderer.set_reconnecting(true).await;
+    renderer.handle_message(delta(3, 30)?).await?;
+    let current = renderer.buffer.current_frame().await.unwrap();
+    assert_eq!((current.id, current.data[0]), (2, 20));
+
+    // Back again: the fresh keyframe lets deltas apply once more
+    renderer.set_reconnecting(false).await;
+    renderer.buffer.push_frame(frame(4, 40)).await?;
+    renderer.buffer.next_frame().await?;
+    assert!(!renderer.buffer.is_awaiting_keyframe().await);
+    renderer.handle_message(delta(5, 50)?).await?;
+    let current = renderer.buffer.current_frame().await.unwrap();
+    assert_eq!((current.id, current.data[0], current.data[3]), (5, 50, 40));
+
+    assert_eq!(*indicator.lock().unwrap(), vec![true, false]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_buffered_frame_is_usable() -> Result<()> {
+    use pixel_change_check_client::server::renderer::BufferedFrame;
+
+    let buffer = FrameBuffer::new(2, 1);
+    let frame = Frame {
+        id: 7,
+        timestamp: std::time::SystemTime::now(),
+        width: 2,
+        height: 1,
+        format: PixelFormat::Rgb24,
+        data: vec![1, 2, 3, 4, 5, 6].into(),
+    };
+    buffer.push_frame(frame.clone()).await?;
+
+    let played: BufferedFrame = buffer.next_frame().await?.expect("Frame is due");
+    assert_eq!(played.dimensions(), (2, 1));
+    assert_eq!(played.pixel(1, 0), Some([4, 5, 6]));
+    assert_eq!(played.pixel(2, 0), None);
+    assert!(played.age() < Duration::from_secs(5));
+    assert_eq!(BufferedFrame::try_from(played.to_frame())?, played);
+
+    let back = played.into_frame();
+    assert_eq!((back.id, back.data), (frame.id, frame.data));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_played_frames_share_pixels() -> Result<()> {
+    use pixel_change_check_client::pcc::{FrameUpdate, PixelChange};
+
+    let buffer = FrameBuffer::new(2, 1);
+    buffer
+        .push_frame(Frame {
+            id: 1,
+            timestamp: std::time::SystemTime::now(),
+            width: 2,
+            height: 1,
+            format: PixelFormat::Rgb24,
+            data: vec![0; 6].into(),
+        })
+        .await?;
+
+    let played = buffer.next_frame().await?.expect("Frame is due");
+    let current = buffer.current_frame().await.expect("Frame was played");
+    assert_eq!(played.data.as_ptr(), current.data.as_ptr(), "Playout should not copy pixels");
+
+    // A delta must not change a frame the renderer is still holding
+    buffer
+        .apply_frame_update(FrameUpdate {
+            frame_id: 2,
+            timestamp: std::time::SystemTime::now(),
+            changes: vec![PixelChange {
+                x: 0,
+                y: 0,
+                width: 1,
+                height: 1,
+                data: vec![9, 9, 9],
+            }],
+        })
+        .await?;
+    assert_eq!(*played.data, vec![0; 6]);
+    let updated = buffer.current_frame().await.expect("Frame was updated");
+    assert_eq!(&updated.data[..3], &[9, 9, 9]);
+
+    Ok(())
+}
+
+#[test]
+fn test_remote_input_requires_opt_in() -> Result<()> {
+    use pixel_change_check_client::input::{
+        InputBackend, InputConfig, InputEvent, KeyCode, MouseButton, RemoteInput,
+    };
+    use std::sync::{Arc, Mutex};
+
+    struct Recorder(Arc<Mutex<Vec<InputEvent>>>);
+    impl InputBackend for Recorder {
+        fn inject(&mut self, event: &InputEvent) -> Result<()> {
+            self.0.lock().unwrap().push(*event);
+            Ok(())
+        }
+    }
+
+    let injected = Arc::new(Mutex::new(Vec::new()));
+    let key = KeyCode(0x04);
+    let press = InputEvent::Key { key, pressed: true, text: None };
+
+    // Hosts share their screen only unless they opt in
+    let mut view_only = RemoteInput::with_backend(&InputConfig::default(), Box::new(Recorder(injected.clone())));
+    assert!(!view_only.is_enabled());
+    assert!(!view_only.handle(press)?);
+    assert_eq!(view_only.ignored(), 1);
+    assert!(injected.lock().unwrap().is_empty());
+
+    let config = InputConfig { allow_control: true, ..Default::default() };
+    let mut control = RemoteInput::with_backend(&config, Box::new(Recorder(injected.clone())));
+    assert!(control.handle(press)?);
+    assert!(control.handle(InputEvent::MouseButton { button: MouseButton::Left, pressed: true })?);
+    assert!(control.handle(InputEvent::MouseMove { x: 10, y: 20 })?);
+    assert_eq!(control.injected(), 3);
+
+    // Whatever the viewer left held down is released when it goes away
+    drop(control);
+    let events = injected.lock().unwrap();
+    assert_eq!(
+        &events[3..],
+        &[
+            InputEvent::Key { key, pressed: false, text: None },
+            InputEvent::MouseButton { button: MouseButton::Left, pressed: false },
+        ]
+    );
+    assert_eq!(key.evdev(), Some(30));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_input_messages_reach_the_injector() -> Result<()> {
+    use pixel_change_check_client::input::{
+        InputBackend, InputConfig, InputEvent, KeyCode, Modifiers, MouseButton, RemoteInput,
+    };
+    use pixel_change_check_client::audio::AudioControls;
+    use pixel_change_check_client::network::{handle_host_events, CloseReason, Message, NetworkEvent};
+    use std::sync::{Arc, Mutex};
+
+    struct Recorder(Arc<Mutex<Vec<InputEvent>>>);
+    impl InputBackend for Recorder {
+        fn inject(&mut self, event: &InputEvent) -> Result<()> {
+            self.0.lock().unwrap().push(*event);
+            Ok(())
+        }
+    }
+
+    let shift = Modifiers { shift: true, ..Default::default() };
+    let events = [
+        InputEvent::Key { key: KeyCode(0x04), pressed: true, text: None },
+        InputEvent::MouseMove { x: 40, y: 30 },
+        InputEvent::MouseButton { button: MouseButton::Right, pressed: true },
+        InputEvent::Scroll { dx: 0, dy: 3 },
+    ];
+
+    let (tx, mut rx) = tokio::sync::mpsc::channel(8);
+    for event in events {
+        let message = Message::input(event, shift);
+        assert!(message.priority() > Message::RequestKeyframe.priority());
+
+        let decoded = Message::deserialize(&message.serialize()?)?;
+        assert_eq!(decoded, message);
+        match &decoded {
+            Message::KeyEvent { modifiers, .. }
+            | Message::MouseMove { modifiers, .. }
+            | Message::MouseButton { modifiers, .. }
+            | Message::Scroll { modifiers, .. } => assert_eq!(*modifiers, shift),
+            other => panic!("Unexpected message: {:?}", other),
+        }
+        tx.send(NetworkEvent::from(decoded)).await?;
+    }
+    tx.send(NetworkEvent::Closed(CloseReason::Normal)).await?;
+
+    let injected = Arc::new(Mutex::new(Vec::new()));
+    let config = InputConfig { allow_control: true, ..Default::default() };
+    let mut input = RemoteInput::with_backend(&config, Box::new(Recorder(injected.clone())));
+    let encoder = FrameEncoder::new(TEST_WIDTH, TEST_HEIGHT, QualityConfig::default())?;
+    handle_host_events(&mut rx, &encoder, &mut input, &AudioControls::default()).await;
+
+    // Held keys and buttons are released once the viewer is gone
+    let injected = injected.lock().unwrap();
+    assert_eq!(&injected[..4], &events);
+    assert_eq!(injected.len(), 6);
+
+    Ok(())
+}
+
+#[test]
+fn test_view_only_and_control_permissions() -> Result<()> {
+    use pixel_change_check_client::input::{
+        InputBackend, InputConfig, InputEvent, KeyCode, Permission, RemoteInput,
+    };
+    use std::sync::{Arc, Mutex};
+
+    struct Recorder(Arc<Mutex<Vec<InputEvent>>>);
+    impl InputBackend for Recorder {
+        fn inject(&mut self, event: &InputEvent) -> Result<()> {
+            self.0.lock().unwrap().push(*event);
+            Ok(())
+        }
+    }
+
+    assert_eq!(Permission::negotiate(Permission::Control, Permission::Control), Permission::Control);
+    assert_eq!(Permission::negotiate(Permission::Control, Permission::ViewOnly), Permission::ViewOnly);
+    assert_eq!(Permission::negotiate(Permission::ViewOnly, Permission::Control), Permission::ViewOnly);
+
+    // Without the host's opt-in, control can't be granted at all
+    let mut closed = RemoteInput::new(&InputConfig::default(), TEST_WIDTH, TEST_HEIGHT)?;
+    assert_eq!(closed.offered_permission(), Permission::ViewOnly);
+    assert_eq!(closed.set_permission(Permission::Control), Permission::ViewOnly);
+
+    let injected = Arc::new(Mutex::new(Vec::new()));
+    let config = InputConfig { allow_control: true, ..Default::default() };
+    let mut input = RemoteInput::with_backend(&config, Box::new(Recorder(injected.clone())));
+    assert_eq!(input.offered_permission(), Permission::Control);
+
+    // A view-only session drops input
+    input.set_permission(Permission::ViewOnly);
+    let press = InputEvent::Key { key: KeyCode(0x04), pressed: true, text: None };
+    assert!(!input.handle(press)?);
+
+    // Revoking control mid-session releases what the viewer held
+    input.set_permission(Permission::Control);
+    assert!(input.handle(press)?);
+    input.revoke_control();
+    assert!(!input.is_enabled());
+    assert!(!input.handle(press)?);
+    assert_eq!(
+        *injected.lock().unwrap(),
+        vec![press, InputEvent::Key { key: KeyCode(0x04), pressed: false, text: None }]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_typing_follows_the_viewer_layout() -> Result<()> {
+    use pixel_change_check_client::input::{
+        InputBackend, InputConfig, InputEvent, KeyCode, KeyMode, RemoteInput,
+    };
+    use std::sync::{Arc, Mutex};
+
+    struct Recorder(Arc<Mutex<Vec<InputEvent>>>);
+    impl InputBackend for Recorder {
+        fn inject(&mut self, event: &InputEvent) -> Result<()> {
+            self.0.lock().unwrap().push(*event);
+            Ok(())
+        }
+    }
+
+    // The key labelled Y on a US keyboard types 'z' on a German one
+    let y_key = KeyCode(0x1C);
+    let ctrl = KeyCode(0xE0);
+    let key = |key, pressed, text| InputEvent::Key { key, pressed, text };
+
+    let injected = Arc::new(Mutex::new(Vec::new()));
+    let config = InputConfig { allow_control: true, ..Default::default() };
+    let mut input = RemoteInput::with_backend(&config, Box::new(Recorder(injected.clone())));
+    input.handle(key(y_key, true, Some('z')))?;
+    input.handle(key(y_key, false, None))?;
+    // Shortcuts keep the physical key so they work on any layout
+    input.handle(key(ctrl, true, None))?;
+    input.handle(key(y_key, true, Some('\u{1a}')))?;
+    input.handle(key(y_key, false, None))?;
+    input.handle(key(ctrl, false, None))?;
+    assert_eq!(
+        *injected.lock().unwrap(),
+        vec![
+            InputEvent::Text('z'),
+            key(ctrl, true, None),
+            key(y_key, true, None),
+            key(y_key, false, None),
+            key(ctrl, false, None),
+        ]
+    );
+
+    let injected = Arc::new(Mutex::new(Vec::new()));
+    let config = InputConfig { allow_control: true, key_mode: KeyMode::Physical, ..Default::default() };
+    let mut input = RemoteInput::with_backend(&config, Box::new(Recorder(injected.clone())));
+    input.handle(key(y_key, true, Some('z')))?;
+    input.handle(key(y_key, false, None))?;
+    assert_eq!(*injected.lock().unwrap(), vec![key(y_key, true, None), key(y_key, false, None)]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_relative_and_absolute_mouse_modes() -> Result<()> {
+    use pixel_change_check_client::input::{
+        InputBackend, InputConfig, InputEvent, MouseMode, RemoteInput,
+    };
+    use pixel_change_check_client::audio::AudioControls;
+    use pixel_change_check_client::network::{handle_host_events, CloseReason, Message, NetworkEvent};
+    use std::sync::{Arc, Mutex};
+
+    struct Recorder(Arc<Mutex<Vec<InputEvent>>>);
+    impl InputBackend for Recorder {
+        fn inject(&mut self, event: &InputEvent) -> Result<()> {
+            self.0.lock().unwrap().push(*event);
+            Ok(())
+        }
+    }
+
+    let injected = Arc::new(Mutex::new(Vec::new()));
+    let config = InputConfig { allow_control: true, ..Default::default() };
+    let mut input = RemoteInput::with_backend(&config, Box::new(Recorder(injected.clone())));
+    assert_eq!(input.mouse_mode(), MouseMode::Absolute);
+
+    let modifiers = Default::default();
+    let (tx, mut rx) = tokio::sync::mpsc::channel(8);
+    for message in [
+        Message::input(InputEvent::MouseMove { x: 100, y: 50 }, modifiers),
+        // Motion in absolute mode moves on from the last position
+        Message::input(InputEvent::MouseMotion { dx: -5, dy: 5 }, modifiers),
+        Message::MouseMode(MouseMode::Relative),
+        // Absolute positions are ignored while the mouse is captured
+        Message::input(InputEvent::MouseMove { x: 0, y: 0 }, modifiers),
+        Message::input(InputEvent::MouseMotion { dx: 3, dy: -2 }, modifiers),
+    ] {
+        tx.send(NetworkEvent::from(Message::deserialize(&message.serialize()?)?)).await?;
+    }
+    tx.send(NetworkEvent::Closed(CloseReason::Normal)).await?;
+
+    let encoder = FrameEncoder::new(TEST_WIDTH, TEST_HEIGHT, QualityConfig::default())?;
+    handle_host_events(&mut rx, &encoder, &mut input, &AudioControls::default()).await;
+
+    assert_eq!(input.mouse_mode(), MouseMode::Relative);
+    assert_eq!(
+        *injected.lock().unwrap(),
+        vec![
+            InputEvent::MouseMove { x: 100, y: 50 },
+            InputEvent::MouseMove { x: 95, y: 55 },
+            InputEvent::MouseMotion { dx: 3, dy: -2 },
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_viewer_input_is_sanitized() -> Result<()> {
+    use pixel_change_check_client::input::{
+        InputBackend, InputConfig, InputEvent, KeyCode, MouseButton, RemoteInput,
+    };
+    use std::sync::{Arc, Mutex};
+
+    struct Recorder(Arc<Mutex<Vec<InputEvent>>>);
+    impl InputBackend for Recorder {
+        fn inject(&mut self, event: &InputEvent) -> Result<()> {
+            self.0.lock().unwrap().push(*event);
+            Ok(())
+        }
+    }
+
+    let key = |key, pressed| InputEvent::Key { key, pressed, text: None };
+    let injected = Arc::new(Mutex::new(Vec::new()));
+    let config = InputConfig { allow_control: true, max_events_per_second: 5, ..Default::default() };
+    let mut input = RemoteInput::with_backend(&config, Box::new(Recorder(injected.clone())));
+    input.set_bounds(TEST_WIDTH, TEST_HEIGHT);
+
+    // Positions outside the shared frame never reach the host
+    assert!(!input.handle(InputEvent::MouseMove { x: TEST_WIDTH, y: 0 })?);
+    assert!(input.handle(InputEvent::MouseMove { x: TEST_WIDTH - 1, y: 0 })?);
+    assert_eq!(input.rejected(), 1);
+
+    // Ctrl+Alt+Delete is blocked, even with the right-hand Ctrl
+    let right_ctrl = KeyCode(0xE4);
+    assert!(input.handle(key(right_ctrl, true))?);
+    assert!(input.handle(key(KeyCode::LEFT_ALT, true))?);
+    assert!(!input.handle(key(KeyCode::DELETE, true))?);
+    assert!(!injected.lock().unwrap().contains(&key(KeyCode::DELETE, true)));
+
+    // The bucket is now empty, but releases still get through
+    assert!(!input.handle(InputEvent::MouseButton { button: MouseButton::Left, pressed: true })?);
+    for released in [KeyCode::DELETE, KeyCode::LEFT_ALT, right_ctrl] {
+        assert!(input.handle(key(released, false))?);
+    }
+    assert_eq!(input.rejected(), 3);
+
+    let injected = injected.lock().unwrap();
+    assert_eq!(
+        &injected[1..],
+        &[
+            key(right_ctrl, true),
+            key(KeyCode::LEFT_ALT, true),
+            key(KeyCode::LEFT_ALT, false),
+            key(right_ctrl, false),
+        ]
+    );
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_hotkeys_stay_local_and_other_keys_are_forwarded() -> Result<()> {
+    use pixel_change_check_client::input::{KeyCode, Modifiers};
+    use pixel_change_check_client::server::renderer::{Hotkey, HotkeyBindings, KeyChord, Renderer};
+
+    let renderer = Renderer::new(TEST_WIDTH, TEST_HEIGHT, 30).await?;
+    let ctrl_alt = Modifiers { ctrl: true, alt: true, ..Default::default() };
+    let escape = KeyCode(0x29);
+
+    // Plain Escape belongs to the host
+    assert!(renderer.route_key(escape, true, Modifiers::default()).await?);
+    assert!(renderer.route_key(escape, false, Modifiers::default()).await?);
+
+    // Ctrl+Alt+Escape releases the captured mouse, and its release stays local too
+    renderer.set_mouse_captured(true).await;
+    assert!(!renderer.route_key(escape, true, ctrl_alt).await?);
+    assert!(!renderer.is_mouse_captured().await);
+    assert!(!renderer.route_key(escape, false, ctrl_alt).await?);
+
+    // Rebinding hands the default chords back to the host
+    let f9 = KeyCode(0x42);
+    renderer
+        .set_hotkey_bindings(HotkeyBindings {
+            bindings: vec![(KeyChord { key: f9, modifiers: Modifiers::default() }, Hotkey::TogglePause)],
+        })
+        .await;
+    assert!(renderer.route_key(escape, true, ctrl_alt).await?);
+    assert!(!renderer.route_key(f9, true, Modifiers::default()).await?);
+    assert!(renderer.is_paused().await);
+
+    Ok(())
+}
+
+#[test]
+fn test_system_audio_is_packetized_and_played() -> Result<()> {
+    use pixel_change_check_client::audio::{AudioDecoder, AudioEncoder, AudioFormat, AudioSource, AudioStreamer};
+    use pixel_change_check_client::network::Message;
+    use pixel_change_check_client::server::audio::{AudioPlayback, AudioReceiver};
+    use pixel_change_check_client::server::renderer::JitterConfig;
+    use std::time::SystemTime;
+
+    // Stands in for Opus: raw little-endian samples
+    struct Raw;
+    impl AudioEncoder for Raw {
+        fn encode(&mut self, samples: &[f32]) -> Result<Vec<u8>> {
+            Ok(samples.iter().flat_map(|s| s.to_le_bytes()).collect())
+        }
+    }
+    impl AudioDecoder for Raw {
+        fn decode(&mut self, packet: &[u8]) -> Result<Vec<f32>> {
+            Ok(packet.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect())
+        }
+    }
+
+    let format = AudioFormat { sample_rate: 1000, channels: 1 };
+    let mut streamer = AudioStreamer::new(AudioSource::System, format, Box::new(Raw));
+    assert!(streamer.start_message().priority() > Message::RequestKeyframe.priority());
+
+    // 20ms packets of 20 samples; the remainder waits for more audio
+    let start = SystemTime::now();
+    let samples: Vec<f32> = (0..50).map(|i| i as f32).collect();
+    let packets = streamer.push(start, &samples)?;
+    assert_eq!(packets.len(), 2);
+    let packets = [packets, streamer.push(start + Duration::from_millis(50), &samples[..10])?].concat();
+    assert_eq!(packets.len(), 3);
+    assert_eq!(streamer.packets_sent(), 3);
+
+    // No playout delay, so packets play as soon as they arrive
+    let playback = AudioPlayback::new(format);
+    let jitter = JitterConfig { target_latency: Duration::ZERO, adaptive: false, ..Default::default() };
+    let mut receiver = AudioReceiver::with_jitter(Box::new(Raw), playback.clone(), jitter);
+    for (i, packet) in packets.iter().enumerate() {
+        let Message::AudioPacket { source, sequence, pts, data } = Message::deserialize(&packet.serialize()?)? else {
+            panic!("Expected an audio packet, got {:?}", packet);
+        };
+        assert_eq!(source, AudioSource::System);
+        assert_eq!(sequence, i as u64);
+        assert_eq!(pts, start + Duration::from_millis(20) * i as u32);
+        // Lose the middle packet
+        if i != 1 {
+            receiver.receive(sequence, pts, &data)?;
+        }
+    }
+    // The lost packet is concealed, with silence from this decoder
+    assert_eq!(receiver.packets_lost(), 1);
+    assert_eq!(playback.buffered(), Duration::from_millis(60));
+
+    let mut output = [1.0; 45];
+    playback.fill(&mut output);
+    assert_eq!(output[..20], samples[..20]);
+    assert_eq!(output[20..40], [0.0; 20]);
+    assert_eq!(output[40..], samples[40..45]);
+
+    Ok(())
+}
+
+#[test]
+fn test_microphone_streams_and_mutes_independently() -> Result<()> {
+    use pixel_change_check_client::audio::{AudioEncoder, AudioFormat, AudioSource, AudioStreamer};
+    use pixel_change_check_client::network::Message;
+    use pixel_change_check_client::server::audio::AudioPlayback;
+    use std::time::SystemTime;
+
+    struct Raw;
+    impl AudioEncoder for Raw {
+        fn encode(&mut self, samples: &[f32]) -> Result<Vec<u8>> {
+            Ok(samples.iter().flat_map(|s| s.to_le_bytes()).collect())
+        }
+    }
+
+    let format = AudioFormat { sample_rate: 1000, channels: 1 };
+    let mut system = AudioStreamer::new(AudioSource::System, format, Box::new(Raw));
+    let mut microphone = AudioStreamer::new(AudioSource::Microphone, format, Box::new(Raw));
+    assert_eq!(
+        microphone.start_message(),
+        Message::AudioStreamStart { source: AudioSource::Microphone, format }
+    );
+
+    // Muting the host's microphone stops its packets but not system audio
+    let now = SystemTime::now();
+    let mic_mute = microphone.mute_control();
+    mic_mute.set_muted(true);
+    assert!(microphone.push(now, &[0.5; 30])?.is_empty());
+    assert_eq!(system.push(now, &[0.5; 30])?.len(), 1);
+
+    // Samples captured while muted are never sent
+    mic_mute.set_muted(false);
+    let packets = microphone.push(now, &[0.25; 10])?;
+    assert!(packets.is_empty());
+    let packets = microphone.push(now, &[0.25; 10])?;
+    match &packets[..] {
+        [Message::AudioPacket { source: AudioSource::Microphone, sequence: 0, data, .. }] => {
+            assert_eq!(data.len(), 20 * 4)
+        }
+        other => panic!("Unexpected packets: {:?}", other),
+    }
+
+    // A viewer muting a source keeps it in step but plays silence
+    let playback = AudioPlayback::new(format);
+    playback.push(now, &[0.5; 20]);
+    playback.set_muted(true);
+    let mut output = [1.0; 10];
+    playback.fill(&mut output);
+    assert_eq!(output, [0.0; 10]);
+    assert_eq!(playback.buffered(), Duration::from_millis(10));
+
+    playback.set_muted(false);
+    playback.fill(&mut output);
+    assert_eq!(output, [0.5; 10]);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_video_and_audio_wait_for_each_other() -> Result<()> {
+    use pixel_change_check_client::audio::AudioFormat;
+    use pixel_change_check_client::network::SessionClock;
+    use pixel_change_check_client::server::audio::{AudioPlayback, SYNC_TOLERANCE};
+    use std::time::Instant;
+
+    // Video and audio capture stamp from the same monotonic clock
+    let clock = SessionClock::new();
+    let start = Instant::now();
+    assert_eq!(
+        clock.at(start + Duration::from_millis(250)).duration_since(clock.at(start))?,
+        Duration::from_millis(250)
+    );
+
+    // Audio runs 100ms behind: frames past its playhead plus the
+    // tolerance are held back
+    let now = clock.now();
+    let format = AudioFormat { sample_rate: 1000, channels: 1 };
+    let audio = AudioPlayback::new(format);
+    audio.push(now - Duration::from_millis(100), &[0.5; 500]);
+    let limit = audio.playhead().map(|playhead| playhead + SYNC_TOLERANCE);
+
+    let buffer = FrameBuffer::new(TEST_WIDTH, TEST_HEIGHT);
+    for (id, age) in [(0, 80), (1, 20)] {
+        let mut frame = create_test_frame(id);
+        frame.timestamp = now - Duration::from_millis(age);
+        buffer.push_frame(frame).await?;
+    }
+    assert_eq!(buffer.next_frame_until(limit).await?.map(|frame| frame.id), Some(0));
+    assert!(buffer.next_frame_until(limit).await?.is_none());
+    assert_eq!(buffer.next_frame_until(None).await?.map(|frame| frame.id), Some(1));
+
+    // Audio ahead of the presented video by more than the tolerance waits
+    let audio = AudioPlayback::new(format);
+    audio.push(now + Duration::from_millis(60), &[0.5; 100]);
+    audio.video_presented(now);
+    let mut output = [1.0; 10];
+    audio.fill(&mut output);
+    assert_eq!(output, [0.0; 10]);
+    assert_eq!(audio.buffered(), Duration::from_millis(100));
+
+    Ok(())
+}
+
+#[test]
+fn test_audio_device_config() -> Result<()> {
+    use pixel_change_check_client::audio::{AudioConfig, DeviceSelection};
+
+    // System audio is shared by default, the microphone is opt-in
+    let config = AudioConfig::default();
+    assert_eq!(config.system, Some(DeviceSelection::Default));
+    assert_eq!(config.microphone, None);
+
+    let config = AudioConfig {
+        microphone: Some(DeviceSelection::Named("USB Headset".to_string())),
+        output: DeviceSelection::Named("Speakers".to_string()),
+        ..AudioConfig::default()
+    };
+    let json = serde_json::to_string(&config)?;
+    assert_eq!(serde_json::from_str::<AudioConfig>(&json)?, config);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_audio_and_video_follow_the_bandwidth_estimate() -> Result<()> {
+    use pixel_change_check_client::audio::{
+        AudioEncoder, AudioFormat, AudioSource, AudioStreamer, MAX_AUDIO_BITRATE, MIN_AUDIO_BITRATE,
+    };
+    use pixel_change_check_client::network::{BandwidthEstimator, NetworkFeedback};
+    use std::sync::{Arc, Mutex};
+    use std::time::SystemTime;
+
+    // Records the bitrates it is set to
+    struct Recording(Arc<Mutex<Vec<u32>>>);
+    impl AudioEncoder for Recording {
+        fn encode(&mut self, _samples: &[f32]) -> Result<Vec<u8>> {
+            Ok(Vec::new())
+        }
+        fn set_bitrate(&mut self, bits_per_second: u32) -> Result<()> {
+            self.0.lock().unwrap().push(bits_per_second);
+            Ok(())
+        }
+    }
+
+    let format = AudioFormat { sample_rate: 1000, channels: 1 };
+    let bitrates = Arc::new(Mutex::new(Vec::new()));
+    let mut streamer = AudioStreamer::new(AudioSource::System, format, Box::new(Recording(bitrates.clone())));
+    let control = streamer.bitrate_control();
+
+    // A starved link: audio drops to its floor, never below
+    let mut estimator = BandwidthEstimator::new(10_000_000);
+    let rtt = Duration::from_millis(100);
+    estimator.update(NetworkFeedback { rtt, cwnd: 1_000, ..Default::default() });
+    let allocation = estimator.allocate(1);
+    control.set_bitrate(allocation.audio);
+    assert_eq!(control.bitrate(), MIN_AUDIO_BITRATE);
+    streamer.push(SystemTime::now(), &[0.0; 20])?;
+    assert_eq!(*bitrates.lock().unwrap(), vec![MIN_AUDIO_BITRATE]);
+
+    // Unchanged bitrates aren't reapplied, and the ceiling holds
+    streamer.push(SystemTime::now(), &[0.0; 20])?;
+    control.set_bitrate(1_000_000);
+    streamer.push(SystemTime::now(), &[0.0; 20])?;
+    assert_eq!(*bitrates.lock().unwrap(), vec![MIN_AUDIO_BITRATE, MAX_AUDIO_BITRATE]);
+
+    // Video over its share steps quality down, and back up with room
+    let encoder = FrameEncoder::new(TEST_WIDTH, TEST_HEIGHT, QualityConfig::default())?;
+    let frame = create_test_frame(0);
+    assert_eq!(encoder.current_quality(), 80);
+    encoder.set_target_bitrate(1_000);
+    encoder.encode_frame(&frame.data).await?;
+    assert_eq!(encoder.current_quality(), 75);
+    encoder.set_target_bitrate(1_000_000_000);
+    encoder.encode_frame(&frame.data).await?;
+    assert_eq!(encoder.current_quality(), 80);
+
+    Ok(())
+}
+
+#[test]
+fn test_video_sends_less_when_audio_takes_bandwidth() -> Result<()> {
+    use pixel_change_check_client::network::{BandwidthEstimator, EncodedFrame, NetworkFeedback};
+    use pixel_change_check_client::quality::{QualityController, QualityStats};
+    use pixel_change_check_client::testing::{FrameGenerator, Pattern};
+    use std::time::Instant;
+
+    // Bytes of new frames sent over ten seconds at 30 fps, paced the way
+    // the host paces them to video's share of a 2 Mbps link
+    let generator = FrameGenerator::new(160, 120, Pattern::Noise { seed: 7 });
+    let sent_with = |audio_streams: usize| -> Result<(u64, u64)> {
+        let mut estimator = BandwidthEstimator::new(10_000_000);
+        // 25KB per 100ms round trip is 2 Mbps
+        estimator.update(NetworkFeedback { rtt: Duration::from_millis(100), cwnd: 25_000, ..Default::default() });
+        let mut controller = QualityController::new(QualityConfig::default());
+        controller.update(&QualityStats {
+            available_bitrate: estimator.allocate(audio_streams).video,
+            ..Default::default()
+        });
+        let encoder = FrameEncoder::new(160, 120, QualityConfig::default())?;
+        encoder.set_target_bitrate(controller.bitrate());
+
+        let start = Instant::now();
+        let mut sent = 0;
+        for n in 0..300 {
+            let now = start + Duration::from_secs(1) / 30 * n;
+            if !encoder.admit_frame(now) {
+                continue;
+            }
+            let encoded = EncodedFrame::keyframe(&generator.frame(n as u64))?;
+            encoder.frame_sent(encoded.len(), now);
+            sent += encoded.len() as u64;
+        }
+        Ok((sent, encoder.target_bitrate()))
+    };
+
+    let (alone, budget) = sent_with(0)?;
+    assert_eq!(budget, 2_000_000);
+    let (beside_audio, budget) = sent_with(2)?;
+    assert!(budget < 2_000_000);
+    // Video yields what audio takes, give or take the frame in flight
+    assert!(beside_audio < alone, "{} bytes beside audio, {} alone", beside_audio, alone);
+    let frame = EncodedFrame::keyframe(&generator.frame(0))?.len() as u64;
+    assert!(alone <= 2_000_000 / 8 * 10 + frame);
+    assert!(beside_audio <= budget / 8 * 10 + frame);
+    assert!(beside_audio >= budget / 8 * 9);
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_audio_mute_and_gain_from_either_end() -> Result<()> {
+    use pixel_change_check_client::audio::{
+        AudioControls, AudioEncoder, AudioFormat, AudioSource, AudioStreamState, AudioStreamer, MAX_AUDIO_GAIN,
+    };
+    use pixel_change_check_client::input::{InputBackend, InputConfig, InputEvent, RemoteInput};
+    use pixel_change_check_client::network::{handle_host_events, CloseReason, Message, NetworkEvent};
+    use pixel_change_check_client::server::audio::AudioPlayback;
+    use pixel_change_check_client::server::renderer::Renderer;
+    use std::time::SystemTime;
+
+    struct NoInput;
+    impl InputBackend for NoInput {
+        fn inject(&mut self, _event: &InputEvent) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    struct Raw;
+    impl AudioEncoder for Raw {
+        fn encode(&mut self, samples: &[f32]) -> Result<Vec<u8>> {
+            Ok(samples.iter().flat_map(|s| s.to_le_bytes()).collect())
+        }
+    }
+
+    // The host mutes its microphone before it starts streaming
+    let format = AudioFormat { sample_rate: 1000, channels: 1 };
+    let controls = AudioControls::default();
+    let to_viewer = controls.set_muted(AudioSource::Microphone, true);
+    assert_eq!(to_viewer, Message::AudioMute { source: AudioSource::Microphone, muted: true });
+    let mut microphone = AudioStreamer::new(AudioSource::Microphone, format, Box::new(Raw));
+    let mut system = AudioStreamer::new(AudioSource::System, format, Box::new(Raw));
+    controls.add(&microphone);
+    controls.add(&system);
+    assert!(microphone.push(SystemTime::now(), &[0.5; 20])?.is_empty());
+
+    // The viewer reflects it, and turns system audio down
+    let renderer = Renderer::new(4, 4, 30).await?;
+    renderer.attach_audio_source(AudioSource::Microphone, AudioPlayback::new(format)).await;
+    let playback = AudioPlayback::new(format);
+    renderer.attach_audio(playback.clone()).await;
+    renderer.handle_message(Message::deserialize(&to_viewer.serialize()?)?).await?;
+    renderer.set_audio_gain(AudioSource::System, 0.5).await;
+    let stats = renderer.overlay_stats().await;
+    assert_eq!(stats.microphone, Some(AudioStreamState { muted: true, gain: 1.0 }));
+    assert_eq!(stats.system_audio, Some(AudioStreamState { muted: false, gain: 0.5 }));
+
+    playback.push(SystemTime::now(), &[0.5; 10]);
+    let mut output = [0.0; 10];
+    playback.fill(&mut output);
+    assert_eq!(output, [0.25; 10]);
+
+    // The viewer's changes reach the host: gain is tracked, mute stops
+    // the stream there too
+    let (tx, mut rx) = tokio::sync::mpsc::channel(8);
+    for message in [
+        Message::AudioGain { source: AudioSource::System, gain: 0.5 },
+        Message::AudioMute { source: AudioSource::System, muted: true },
+        Message::AudioMute { source: AudioSource::Microphone, muted: false },
+    ] {
+        assert_eq!(message.priority(), to_viewer.priority());
+        tx.send(NetworkEvent::from(Message::deserialize(&message.serialize()?)?)).await?;
+    }
+    tx.send(NetworkEvent::Closed(CloseReason::Normal)).await?;
+    let encoder = FrameEncoder::new(TEST_WIDTH, TEST_HEIGHT, QualityConfig::default())?;
+    let mut input = RemoteInput::with_backend(&InputConfig::default(), Box::new(NoInput));
+    handle_host_events(&mut rx, &encoder, &mut input, &controls).await;
+
+    assert_eq!(controls.state(AudioSource::System), AudioStreamState { muted: true, gain: 0.5 });
+    assert!(system.push(SystemTime::now(), &[0.5; 20])?.is_empty());
+    assert_eq!(microphone.push(SystemTime::now(), &[0.5; 20])?.len(), 1);
+
+    // Gains from the peer are kept in range
+    controls.apply(&Message::AudioGain { source: AudioSource::Microphone, gain: f32::NAN });
+    assert_eq!(controls.state(AudioSource::Microphone).gain, 1.0);
+    assert_eq!(controls.set_gain(AudioSource::Microphone, 10.0), Message::AudioGain {
+        source: AudioSource::Microphone,
+        gain: MAX_AUDIO_GAIN
+    });
+
+    Ok(())
+}
+
+#[test]
+fn test_client_pipeline_sends_keyframes_then_changes() -> Result<()> {
+    use pixel_change_check_client::client::{ClientConfig, FrameOutput, FramePipeline};
+
+    let frame = |id, value| Frame {
+        id,
+        timestamp: std::time::SystemTime::now(),
+        width: 64,
+        height: 64,
+        format: PixelFormat::Rgb24,
+        data: vec![value; 64 * 64 * 3].into(),
+    };
+    let mut pipeline = FramePipeline::new(PCCDetector::default());
+
+    // The first frame goes whole, then only what changes
+    assert!(matches!(pipeline.process(frame(0, 0), false)?, FrameOutput::Keyframe(f) if f.id == 0));
+    assert!(matches!(pipeline.process(frame(1, 0), false)?, FrameOutput::Unchanged));
+    let mut changed = frame(2, 0);
+    changed.modify_data(|data| data[..3].copy_from_slice(&[255, 255, 255]));
+    match pipeline.process(changed, false)? {
+        FrameOutput::Update(update) => {
+            assert_eq!(update.frame_id, 2);
+            assert!(!update.changes.is_empty());
+        }
+        other => panic!("Expected an update, got {:?}", other),
+    }
+
+    // Keyframe requests and resizes send the whole frame again
+    assert!(matches!(pipeline.process(frame(3, 0), true)?, FrameOutput::Keyframe(_)));
+    let resized = Frame { width: 32, height: 32, data: vec![0; 32 * 32 * 3].into(), ..frame(4, 0) };
+    assert!(matches!(pipeline.process(resized, false)?, FrameOutput::Keyframe(_)));
+    // As does a restart after encoding stalls
+    let same = Frame { width: 32, height: 32, data: vec![0; 32 * 32 * 3].into(), ..frame(5, 0) };
+    pipeline.reset();
+    assert!(matches!(pipeline.process(same, false)?, FrameOutput::Keyframe(_)));
+
+    // Configs round-trip through JSON
+    let config = ClientConfig::default();
+    let parsed: ClientConfig = serde_json::from_str(&serde_json::to_string(&config)?)?;
+    assert_eq!(parsed.viewer, config.viewer);
+    assert_eq!(parsed.audio, config.audio);
+
+    Ok(())
+}
+
+#[test]
+fn test_frame_ring_dumps_recent_frames() -> Result<()> {
+    use pixel_change_check_client::client::FramePipeline;
+
+    let frame = |id, value| Frame { width: 16, height: 8, data: vec![value; 16 * 8 * 3].into(), ..create_test_frame(id) };
+    let dir = std::env::temp_dir().join(format!("pcc-dump-{}", std::process::id()));
+    let mut pipeline = FramePipeline::new(PCCDetector::default());
+    pipeline.process(frame(0, 0), false)?;
+    assert_eq!(pipeline.dump_frames(&dir)?, None, "Nothing is kept by default");
+
+    let mut pipeline = FramePipeline::new(PCCDetector::default()).with_frame_ring(2);
+    for id in 0..3 {
+        pipeline.process(frame(id, 0), false)?;
+    }
+    let mut changed = frame(3, 0);
+    changed.modify_data(|data| data[..3].copy_from_slice(&[255, 255, 255]));
+    pipeline.process(changed, false)?;
+
+    // Only the last two frames are kept
+    let dumped = pipeline.dump_frames(&dir)?.expect("Frames are kept");
+    let index: serde_json::Value = serde_json::from_str(&std::fs::read_to_string(dumped.join("frames.json"))?)?;
+    let frames = index.as_array().unwrap();
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0]["frame_id"], 2);
+    assert_eq!(frames[0]["output"], "unchanged");
+    assert_eq!(frames[1]["output"], "update");
+    assert_eq!(frames[1]["changes"][0]["x"], 0);
+    let image = image::open(dumped.join("frame-3.png"))?.to_rgb8();
+    assert_eq!(image.dimensions(), (16, 8));
+    assert_eq!(image.get_pixel(0, 0).0, [255, 255, 255]);
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn test_cli_config_defaults_and_codecs() -> Result<()> {
+    use pixel_change_check_client::client::ClientConfig;
+    use pixel_change_check_client::encoder::VideoCodec;
+
+    // Settings missing from a config file keep their defaults
+    let config: ClientConfig = serde_json::from_str(r#"{"viewer": "10.0.0.2:5900", "display": 1}"#)?;
+    assert_eq!(config.viewer.port(), 5900);
+    assert_eq!(config.display, 1);
+    assert_eq!(config.codec, VideoCodec::Raw);
+    assert_eq!(config.quality.target_fps, QualityConfig::default().target_fps);
+
+    assert_eq!("raw".parse::<VideoCodec>(), Ok(VideoCodec::Raw));
+    assert_eq!("RAW".parse::<VideoCodec>(), Ok(VideoCodec::Raw));
+    assert!("h264".parse::<VideoCodec>().unwrap_err().contains("supported: raw"));
+
+    Ok(())
+}
+
+#[test]
+fn test_toml_config_with_env_overrides() -> Result<()> {
+    use pixel_change_check_client::config::PccConfig;
+    use std::net::SocketAddr;
+
+    let config = PccConfig::from_toml(
+        r#"
+        [capture]
+        display = 1
+
+        [quality]
+        target_fps = 15
+
+        [network]
+        port = 5900
+        connection_timeout = { secs = 3, nanos = 0 }
+        "#,
+    )?;
+    assert_eq!(config.capture.display, 1);
+    assert_eq!(config.quality.target_fps, 15);
+    // Settings left out keep their defaults
+    assert_eq!(config.quality.quality, QualityConfig::default().quality);
+    assert_eq!(config.network.port, Some(5900));
+    assert_eq!(config.network.connection_timeout, Duration::from_secs(3));
+    assert!(config.resilience.error_correction_enabled);
+
+    let vars = [
+        ("PCC_QUALITY_TARGET_FPS", "60"),
+        ("PCC_NETWORK_CONNECTION_TIMEOUT", "2.5"),
+        ("PCC_RESILIENCE_ERROR_CORRECTION_ENABLED", "false"),
+        ("PCC_CAPTURE_CODEC", "raw"),
+        ("PCC_QUALITY_POLICY", "prefer-motion"),
+        ("PCC_NETWORK_DATA_QUOTA", "2000000000"),
+        ("PCC_NETWORK_QUOTA_ACTION", "degrade"),
+        ("PCC_WATCHDOG_STALL_TIMEOUT", "2"),
+        ("HOME", "/root"),
+    ];
+    let vars = vars.iter().map(|(name, value)| (name.to_string(), value.to_string()));
+    let config = config.with_overrides(vars)?;
+    assert_eq!(config.quality.target_fps, 60);
+    assert_eq!(config.quality.policy, pixel_change_check_client::quality::QualityPolicy::PreferMotion);
+    assert_eq!(config.network.connection_timeout, Duration::from_millis(2500));
+    assert_eq!(config.network.data_quota, Some(2_000_000_000));
+    assert_eq!(config.watchdog.stall_timeout, Duration::from_secs(2));
+    assert_eq!(config.network.quota_action, pixel_change_check_client::network::QuotaAction::Degrade);
+    assert!(!config.resilience.error_correction_enabled);
+    assert_eq!(config.capture.display, 1);
+
+    // Unset settings can be overridden too, and bad values are rejected
+    let config = PccConfig::default().with_overrides([("PCC_NETWORK_PORT".to_string(), "6000".to_string())])?;
+    assert_eq!(config.network.port, Some(6000));
+    let invalid = PccConfig::default().with_overrides([("PCC_QUALITY_TARGET_FPS".to_string(), "fast".to_string())]);
+    assert!(invalid.is_err());
+
+    let viewer: SocketAddr = "10.0.0.2:5800".parse()?;
+    let client = config.client_config(viewer);
+    assert_eq!(client.viewer, viewer);
+    assert_eq!(client.network.port, Some(6000));
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_graceful_shutdown() -> Result<()> {
+    use pixel_change_check_client::{
+        audio::{AudioEncoder, AudioFormat, AudioSource, AudioStreamer},
+        input::Permission,
+        network::{CloseReason, Message, NetworkConfig, NetworkEvent, NetworkManager},
+        server::network::ServerNetwork,
+    };
+    use std::{net::SocketAddr, sync::Arc};
+
+    // The viewer says goodbye to connected hosts instead of leaving them to
+    // time out
+    let config = NetworkConfig { port: Some(0), ..NetworkConfig::default() };
+    let network = Arc::new(ServerNetwork::new(config.clone(), ResilienceConfig::default())?);
+    let addr = SocketAddr::from(([127, 0, 0, 1], network.local_addr()?.port()));
+    let accepting = tokio::spawn({
+        let network = network.clone();
+        async move { network.start().await }
+    });
+
+    let manager = NetworkManager::new_client(config).await?;
+    let connection = manager.connect(addr).await?;
+    connection.handshake(None, Permission::ViewOnly).await?;
+    let connected = tokio::time::timeout(Duration::from_secs(2), network.next_event()).await?;
+    assert!(matches!(connected, Some(NetworkEvent::Connected { .. })));
+
+    accepting.abort();
+    tokio::time::timeout(Duration::from_secs(3), network.shutdown(CloseReason::Normal)).await?;
+    let reason = tokio::time::timeout(Duration::from_millis(500), connection.closed()).await?;
+    assert_eq!(reason, CloseReason::Normal);
+    assert!(network.next_frame().await.is_none(), "Nothing is received after shutdown");
+
+    // Hosts flush the partial audio packet left when sharing stops
+    struct Passthrough;
+    impl AudioEncoder for Passthrough {
+        fn encode(&mut self, samples: &[f32]) -> Result<Vec<u8>> {
+            Ok(vec![0; samples.len()])
+        }
+    }
+    // 20 samples per 20ms packet
+    let format = AudioFormat { sample_rate: 1000, channels: 1 };
+    let mut streamer = AudioStreamer::new(AudioSource::System, format, Box::new(Passthrough));
+    let half = vec![0.5; 10];
+    assert!(streamer.push(std::time::SystemTime::now(), &half)?.is_empty());
+    match streamer.flush()?.as_slice() {
+        [Message::AudioPacket { data, .. }] => assert_eq!(data.len(), 20),
+        other => panic!("Expected one final packet, got {:?}", other),
+    }
+    assert!(streamer.flush()?.is_empty());
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_daemon_restarts_pipeline_and_holds_pidfile() -> Result<()> {
+    use pixel_change_check_client::daemon::{self, PidFile, RestartPolicy};
+    use std::sync::{
+        atomic::{AtomicU32, Ordering},
+        Arc,
+    };
+
+    let policy = RestartPolicy {
+        initial_delay: Duration::from_millis(5),
+        max_delay: Duration::from_millis(20),
+        reset_after: Duration::from_secs(10),
+    };
+    let runs = Arc::new(AtomicU32::new(0));
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let mut stop = Some(stop);
+    let stopped_cleanly = Arc::new(AtomicU32::new(0));
+
+    // Two failed runs are restarted; the third is asked to stop on shutdown
+    // and allowed to finish
+    let result = daemon::supervise(policy, async { let _ = stopped.await; }, |stopping| {
+        let run = runs.fetch_add(1, Ordering::SeqCst);
+        if run == 2 {
+            let _ = stop.take().map(|stop| stop.send(()));
+        }
+        let stopped_cleanly = stopped_cleanly.clone();
+        async move {
+            if run < 2 {
+                anyhow::bail!("Capture failed");
+            }
+            stopping.wait().await;
+            stopped_cleanly.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    });
+    tokio::time::timeout(Duration::from_secs(2), result).await??;
+    assert_eq!(runs.load(Ordering::SeqCst), 3);
+    assert_eq!(stopped_cleanly.load(Ordering::SeqCst), 1);
+
+    let path = std::env::temp_dir().join(format!("pcc-{}.pid", std::process::id()));
+    let pidfile = PidFile::create(&path)?;
+    assert_eq!(std::fs::read_to_string(&path)?.trim(), std::process::id().to_string());
+    let second = PidFile::create(&path).unwrap_err().to_string();
+    assert!(second.contains("Already running"), "{}", second);
+    drop(pidfile);
+    assert!(!path.exists(), "Pidfile is removed on exit");
+
+    Ok(())
+}
+
+#[test]
+fn test_session_remote_steers_quality() -> Result<()> {
+    use pixel_change_check_client::client::{SessionCommand, SessionMonitor};
+
+    let encoder = FrameEncoder::new(64, 64, QualityConfig::default())?;
+    assert_eq!(encoder.current_quality(), 80);
+    encoder.set_quality(0.55);
+    assert_eq!(encoder.current_quality(), 55);
+    // Adaptation never goes back above a quality set on the fly
+    encoder.set_target_bitrate(0);
+    assert_eq!(encoder.current_quality(), 55);
+    encoder.set_quality(2.0);
+    assert_eq!(encoder.current_quality(), 100);
+
+    let (monitor, remote) = SessionMonitor::new();
+    assert!(!remote.is_closed());
+    assert_eq!(remote.status().viewer, None);
+    assert!(remote.send(SessionCommand::ForceKeyframe));
+    drop(monitor);
+    assert!(remote.is_closed());
+    assert!(!remote.send(SessionCommand::SetQuality(0.5)), "Commands fail once the session ends");
+
+    Ok(())
+}
+
+#[test]
+fn test_benchmark_reports_every_stage() -> Result<()> {
+    use pixel_change_check_client::benchmark::{self, SyntheticCapture};
+
+    let capture = SyntheticCapture::new(320, 240);
+    let report = benchmark::run(&capture, PCCDetector::default(), Duration::from_millis(200))?;
+
+    assert_eq!((report.width, report.height), (320, 240));
+    assert!(report.frames > 1);
+    // The first frame is a keyframe and the scrolling bar changes every one after
+    assert!(report.bytes >= 320 * 240 * 3);
+    for stage in [&report.capture, &report.detect, &report.encode] {
+        assert!(stage.percentile(50.0) <= stage.percentile(99.0));
+        assert!(stage.percentile(100.0) > Duration::ZERO);
+    }
+    assert!(report.achievable_fps() > 0.0);
+
+    let printed = report.to_string();
+    assert!(printed.contains("320x240"));
+    assert!(printed.contains("Achievable:"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_config_watcher_reloads_changed_file() -> Result<()> {
+    use pixel_change_check_client::config::ConfigWatcher;
+    use std::time::SystemTime;
+
+    let path = std::env::temp_dir().join(format!("pcc-reload-{}.toml", std::process::id()));
+    std::fs::write(&path, "[quality]\ntarget_fps = 30\n")?;
+    let mut watcher = ConfigWatcher::new(Some(&path))?;
+
+    std::fs::write(&path, "[quality]\ntarget_fps = 15\n\n[resilience]\nmax_retries = 7\n")?;
+    // Filesystems with coarse timestamps might not see the write otherwise
+    std::fs::File::options()
+        .write(true)
+        .open(&path)?
+        .set_modified(SystemTime::now() + Duration::from_secs(10))?;
+    let reloaded = tokio::time::timeout(Duration::from_secs(5), watcher.changed()).await??;
+    assert_eq!(reloaded.quality.target_fps, 15);
+    assert_eq!(reloaded.resilience.max_retries, 7);
+
+    // An invalid edit is reported, leaving the caller's settings alone
+    std::fs::write(&path, "[quality]\ntarget_fps = \"fast\"\n")?;
+    std::fs::File::options()
+        .write(true)
+        .open(&path)?
+        .set_modified(SystemTime::now() + Duration::from_secs(20))?;
+    assert!(tokio::time::timeout(Duration::from_secs(5), watcher.changed()).await?.is_err());
+
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_hosts_pin_viewer_certificate() -> Result<()> {
+    use pixel_change_check_client::{
+        network::{Fingerprint, Identity, NetworkConfig, NetworkManager},
+        server::network::ServerNetwork,
+    };
+    use std::{net::SocketAddr, sync::Arc};
+
+    let dir = std::env::temp_dir().join(format!("pcc-identity-{}", std::process::id()));
+    let identity = Identity::generate(vec!["localhost".to_string()])?;
+    identity.save(&dir)?;
+    let fingerprint = Identity::load(&dir)?.fingerprint()?;
+    assert_eq!(fingerprint, identity.fingerprint()?);
+
+    let config = NetworkConfig { port: Some(0), identity: Some(dir.clone()), ..NetworkConfig::default() };
+    let network = Arc::new(ServerNetwork::new(config, ResilienceConfig::default())?);
+    let addr = SocketAddr::from(([127, 0, 0, 1], network.local_addr()?.port()));
+    let accepting = tokio::spawn({
+        let network = network.clone();
+        async move { network.start().await }
+    });
+
+    // The saved certificate is the one presented
+    let pinned = NetworkConfig { pinned_fingerprint: Some(fingerprint), ..NetworkConfig::default() };
+    let manager = NetworkManager::new_client(pinned).await?;
+    assert!(manager.connect(addr).await.is_ok());
+
+    let other = Identity::generate(vec!["localhost".to_string()])?.fingerprint()?;
+    let wrong = NetworkConfig { pinned_fingerprint: Some(other), ..NetworkConfig::default() };
+    let manager = NetworkManager::new_client(wrong).await?;
+    assert!(manager.connect(addr).await.is_err());
+
+    // Pins in config files are colon separated hex
+    let parsed: NetworkConfig = toml::from_str(&format!("pinned_fingerprint = \"{}\"", fingerprint))?;
+    assert_eq!(parsed.pinned_fingerprint, Some(fingerprint));
+    assert!("not-a-fingerprint".parse::<Fingerprint>().is_err());
+
+    accepting.abort();
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_viewer_pairs_with_host_pin() -> Result<()> {
+    use pixel_change_check_client::{
+        input::Permission,
+        network::{CloseReason, Identity, NetworkConfig, NetworkManager, PairedViewers, PairingPin},
+        server::network::ServerNetwork,
+    };
+    use std::{net::SocketAddr, sync::Arc};
+
+    let dir = std::env::temp_dir().join(format!("pcc-pairing-{}", std::process::id()));
+    let identity = Identity::generate(vec!["localhost".to_string()])?;
+    identity.save(&dir)?;
+
+    let pin = PairingPin::generate()?;
+    let typed = pin.code().to_string();
+    let config = NetworkConfig { port: Some(0), identity: Some(dir.clone()), ..NetworkConfig::default() };
+    let network = Arc::new(
+        ServerNetwork::new(config, ResilienceConfig::default())?.with_pin_prompt(move |_| Some(typed.clone())),
+    );
+    let addr = SocketAddr::from(([127, 0, 0, 1], network.local_addr()?.port()));
+    let accepting = tokio::spawn({
+        let network = network.clone();
+        async move { network.start().await }
+    });
+
+    let manager = NetworkManager::new_client(NetworkConfig::default()).await?;
+    let connection = manager.connect(addr).await?;
+    let viewer = connection.viewer_fingerprint().expect("Viewer presents its certificate");
+    assert_eq!(viewer, identity.fingerprint()?);
+    let session = tokio::time::timeout(Duration::from_secs(2), connection.pair(None, Permission::ViewOnly, &pin)).await??;
+    assert_eq!(session.permission, Permission::ViewOnly);
+
+    // Paired viewers are remembered by their certificate
+    let paired = dir.join("paired.txt");
+    PairedViewers::load(&paired)?.add(viewer)?;
+    assert!(PairedViewers::load(&paired)?.contains(&viewer));
+
+    // A viewer sending some other PIN is turned away
+    let other = PairingPin::generate()?;
+    if other.code() != pin.code() {
+        let connection = manager.connect(addr).await?;
+        let paired = tokio::time::timeout(Duration::from_secs(2), connection.pair(None, Permission::ViewOnly, &other)).await?;
+        assert!(paired.is_err());
+        assert_eq!(connection.closed().await, CloseReason::Normal, "The host closed it");
+    }
+
+    accepting.abort();
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_host_sees_who_it_approves() -> Result<()> {
+    use pixel_change_check_client::{
+        input::Permission,
+        network::{NetworkConfig, NetworkManager, ViewerApproval},
+        server::network::ServerNetwork,
+    };
+    use std::{net::SocketAddr, sync::Arc};
+
+    let config = NetworkConfig { port: Some(0), name: Some("lab-viewer".to_string()), ..NetworkConfig::default() };
+    let network = Arc::new(ServerNetwork::new(config, ResilienceConfig::default())?);
+    let addr = SocketAddr::from(([127, 0, 0, 1], network.local_addr()?.port()));
+    let accepting = tokio::spawn({
+        let network = network.clone();
+        async move { network.start().await }
+    });
+
+    let manager = NetworkManager::new_client(NetworkConfig::default()).await?;
+    let connection = manager.connect(addr).await?;
+    connection.handshake(None, Permission::ViewOnly).await?;
+    let viewer = connection.viewer();
+    assert_eq!(viewer.name.as_deref(), Some("lab-viewer"));
+    assert_eq!(viewer.addr, addr);
+    assert!(viewer.fingerprint.is_some());
+    assert!(viewer.to_string().starts_with("lab-viewer at 127.0.0.1:"));
+
+    // Without a prompt nobody approves
+    assert!(!ViewerApproval::default().approve(&viewer));
+    let approval = ViewerApproval::new(|viewer| viewer.name.as_deref() == Some("lab-viewer"));
+    assert!(approval.clone().approve(&viewer));
+
+    let parsed: NetworkConfig = toml::from_str(&format!(
+        "approve_viewers = true\ntrusted_viewers = [\"{}\"]",
+        viewer.fingerprint.unwrap()
+    ))?;
+    assert!(parsed.approve_viewers);
+    assert_eq!(parsed.trusted_viewers, vec![viewer.fingerprint.unwrap()]);
+
+    accepting.abort();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_viewer_access_list_refuses_unlisted_hosts() -> Result<()> {
+    use pixel_change_check_client::{
+        input::Permission,
+        network::{AccessControl, CloseReason, Identity, NetworkConfig, NetworkManager, PeerMatch},
+        server::network::ServerNetwork,
+    };
+    use std::{net::SocketAddr, sync::Arc};
+
+    let dir = std::env::temp_dir().join(format!("pcc-access-{}", std::process::id()));
+    let host = Identity::generate(vec!["host".to_string()])?;
+    host.save(&dir)?;
+
+    let access = AccessControl { allow: vec![PeerMatch::Fingerprint(host.fingerprint()?)], deny: Vec::new() };
+    let config = NetworkConfig { port: Some(0), access, ..NetworkConfig::default() };
+    let network = Arc::new(ServerNetwork::new(config, ResilienceConfig::default())?);
+    let addr = SocketAddr::from(([127, 0, 0, 1], network.local_addr()?.port()));
+    let accepting = tokio::spawn({
+        let network = network.clone();
+        async move { network.start().await }
+    });
+
+    // Hosts present their identity's certificate when they have one
+    let listed = NetworkManager::new_client(NetworkConfig { identity: Some(dir.clone()), ..NetworkConfig::default() }).await?;
+    let connection = listed.connect(addr).await?;
+    tokio::time::timeout(Duration::from_secs(2), connection.handshake(None, Permission::ViewOnly)).await??;
+
+    let unlisted = NetworkManager::new_client(NetworkConfig::default()).await?;
+    let connection = unlisted.connect(addr).await?;
+    let reason = tokio::time::timeout(Duration::from_secs(2), connection.closed()).await?;
+    assert_eq!(reason, CloseReason::AccessDenied);
+
+    // Entries in config files are fingerprints or subnets
+    let parsed: NetworkConfig = toml::from_str("[access]\nallow = [\"10.0.0.0/8\"]\ndeny = [\"10.0.0.13\"]")?;
+    assert!(parsed.access.permits("10.1.2.3".parse()?, None));
+    assert!(!parsed.access.permits("10.0.0.13".parse()?, None));
+
+    accepting.abort();
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_viewer_heartbeats_keep_host_hearing() -> Result<()> {
+    use pixel_change_check_client::{
+        input::Permission,
+        network::{IdleAction, NetworkConfig, NetworkEvent, NetworkManager},
+        server::network::ServerNetwork,
+    };
+    use std::{net::SocketAddr, sync::Arc};
+
+    let keepalive = Duration::from_millis(50);
+    let config = NetworkConfig { port: Some(0), keepalive_interval: keepalive, ..NetworkConfig::default() };
+    let network = Arc::new(ServerNetwork::new(config, ResilienceConfig::default())?);
+    let addr = SocketAddr::from(([127, 0, 0, 1], network.local_addr()?.port()));
+    let accepting = tokio::spawn({
+        let network = network.clone();
+        async move { network.start().await }
+    });
+
+    let manager = NetworkManager::new_client(NetworkConfig::default()).await?;
+    let connection = manager.connect(addr).await?;
+    connection.handshake(None, Permission::ViewOnly).await?;
+    let mut events = connection.control_events();
+    let started = connection.last_heard();
+
+    tokio::time::timeout(Duration::from_secs(2), async {
+        while connection.last_heard() <= started + keepalive {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await?;
+    // Heartbeats aren't passed on as events
+    while let Ok(event) = events.try_recv() {
+        assert_eq!(event, NetworkEvent::KeyframeRequested);
+    }
+
+    let parsed: NetworkConfig = toml::from_str("idle_action = \"disconnect\"\n[idle_timeout]\nsecs = 600\nnanos = 0")?;
+    assert_eq!(parsed.idle_action, IdleAction::Disconnect);
+    assert_eq!(parsed.idle_timeout, Some(Duration::from_secs(600)));
+    assert_eq!(NetworkConfig::default().idle_action, IdleAction::Suspend);
+
+    accepting.abort();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_port_zero_advertises_chosen_port() -> Result<()> {
+    use pixel_change_check_client::{
+        input::Permission,
+        network::{NetworkConfig, NetworkManager},
+        server::network::ServerNetwork,
+    };
+    use std::sync::Arc;
+
+    let config = NetworkConfig { port: Some(0), ..NetworkConfig::default() };
+    let network = Arc::new(ServerNetwork::new(config.clone(), ResilienceConfig::default())?);
+    let addr = network.advertised_addr()?;
+    assert_ne!(addr.port(), 0);
+    assert_eq!(addr.port(), network.local_addr()?.port());
+    assert!(!addr.ip().is_unspecified());
+
+    // Hosts can reach the viewer at the advertised address
+    let accepting = tokio::spawn({
+        let network = network.clone();
+        async move { network.start().await }
+    });
+    let manager = NetworkManager::new_client(config).await?;
+    let connection = tokio::time::timeout(Duration::from_secs(2), manager.connect(addr)).await??;
+    connection.handshake(None, Permission::ViewOnly).await?;
+
+    accepting.abort();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_metrics_endpoint_serves_host_status() -> Result<()> {
+    use pixel_change_check_client::{client::SessionStatus, metrics, network::DataUsage};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    let status = SessionStatus {
+        fps: 30.0,
+        frames_captured: 900,
+        change_ratio: 0.25,
+        encode_time: Duration::from_millis(4),
+        usage: DataUsage { sent_bytes: 5000, received_bytes: 300, window_bitrate: 42_400 },
+        ..SessionStatus::default()
+    };
+    let text = metrics::host_metrics(&status);
+    assert!(text.contains("pcc_session_bytes_total{direction=\"sent\"} 5000\n"));
+    assert!(text.contains("pcc_session_bytes_total{direction=\"received\"} 300\n"));
+    assert!(text.contains("# TYPE pcc_capture_fps gauge\npcc_capture_fps 30\n"));
+    assert!(text.contains("# TYPE pcc_frames_captured_total counter\npcc_frames_captured_total 900\n"));
+    assert!(text.contains("pcc_change_ratio 0.25\n"));
+    assert!(text.contains("pcc_encode_seconds 0.004\n"));
+
+    let addr = TcpListener::bind("127.0.0.1:0").await?.local_addr()?;
+    let health = status.health();
+    let serving = tokio::spawn(metrics::serve(
+        addr,
+        move || {
+            let status = status.clone();
+            async move { metrics::host_metrics(&status) }
+        },
+        move || {
+            let health = health.clone();
+            async move { health }
+        },
+        || async { Vec::new() },
+    ));
+    let scrape = |path: &'static str| async move {
+        let mut stream = loop {
+            match TcpStream::connect(addr).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        };
+        stream.write_all(format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).as_bytes()).await?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await?;
+        anyhow::Ok(response)
+    };
+
+    let response = tokio::time::timeout(Duration::from_secs(2), scrape("/metrics")).await??;
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with(&text));
+    // No viewer has connected
+    let response = scrape("/health").await?;
+    assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+    assert!(response.contains("Content-Type: application/json\r\n"));
+    let body: serde_json::Value = serde_json::from_str(response.split("\r\n\r\n").nth(1).unwrap())?;
+    assert_eq!(body["healthy"], false);
+    assert_eq!(body["checks"][2]["name"], "viewer");
+    assert_eq!(body["checks"][2]["ok"], false);
+    let response = scrape("/").await?;
+    assert!(response.starts_with("HTTP/1.1 404"));
+
+    serving.abort();
+    Ok(())
+}
+
+#[cfg(not(feature = "otel"))]
+#[test]
+fn test_otlp_export_needs_feature() {
+    use pixel_change_check_client::otel::OtlpExporter;
+
+    let error = OtlpExporter::new("http://localhost:4318").unwrap_err();
+    assert!(error.to_string().contains("`otel` feature"));
+}
+
+#[cfg(feature = "otel")]
+#[test]
+fn test_otlp_exports_spans_and_metrics() -> Result<()> {
+    use pixel_change_check_client::{client::SessionStatus, metrics, otel::OtlpExporter};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    // A collector that accepts every export, passing on its path and body
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let endpoint = format!("http://{}", listener.local_addr()?);
+    let (exported, exports) = mpsc::channel();
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let exported = exported.clone();
+            std::thread::spawn(move || -> std::io::Result<()> {
+                let mut reader = BufReader::new(stream.try_clone()?);
+                let mut stream = stream;
+                loop {
+                    let mut request_line = String::new();
+                    if reader.read_line(&mut request_line)? == 0 {
+                        return Ok(());
+                    }
+                    let mut length = 0;
+                    loop {
+                        let mut header = String::new();
+                        reader.read_line(&mut header)?;
+                        if header.trim().is_empty() {
+                            break;
+                        }
+                        if let Some((name, value)) = header.split_once(':') {
+                            if name.eq_ignore_ascii_case("content-length") {
+                                length = value.trim().parse().unwrap_or(0);
+                            }
+                        }
+                    }
+                    let mut body = vec![0; length];
+                    reader.read_exact(&mut body)?;
+                    let path = request_line.split(' ').nth(1).unwrap_or_default().to_string();
+                    let _ = exported.send((path, body));
+                    stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")?;
+                }
+            });
+        }
+    });
+
+    let exporter = OtlpExporter::new(&endpoint)?;
+    let subscriber = tracing_subscriber::registry().with(exporter.layer());
+    tracing::subscriber::with_default(subscriber, || {
+        let _frame = tracing::debug_span!(target: "pixel_change_check_client::client", "frame", frame_id = 7).entered();
+    });
+    let mut sink = exporter.metrics();
+    for frames_captured in [300, 900] {
+        metrics::write_host_metrics(&SessionStatus { frames_captured, ..SessionStatus::default() }, &mut sink);
+    }
+    exporter.shutdown()?;
+
+    let exports: Vec<(String, Vec<u8>)> = exports.try_iter().collect();
+    let body = |path: &str| {
+        exports
+            .iter()
+            .find(|(exported, _)| exported == path)
+            .map(|(_, body)| body.clone())
+            .unwrap_or_else(|| panic!("Nothing exported to {}", path))
+    };
+    let contains = |body: &[u8], text: &str| body.windows(text.len()).any(|window| window == text.as_bytes());
+    assert!(contains(&body("/v1/traces"), "frame"));
+    assert!(contains(&body("/v1/metrics"), "pcc_frames_captured_total"));
+    assert!(contains(&body("/v1/metrics"), "pcc_capture_fps"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_keyframes_reach_viewer() -> Result<()> {
+    use pixel_change_check_client::{
+        input::Permission,
+        network::{NetworkConfig, NetworkManager},
+        server::network::ServerNetwork,
+    };
+    use std::sync::Arc;
+
+    let config = NetworkConfig { port: Some(0), ..NetworkConfig::default() };
+    let network = Arc::new(ServerNetwork::new(config.clone(), ResilienceConfig::default())?);
+    let addr = network.advertised_addr()?;
+    let accepting = tokio::spawn({
+        let network = network.clone();
+        async move { network.start().await }
+    });
+    let manager = NetworkManager::new_client(config).await?;
+    let connection = tokio::time::timeout(Duration::from_secs(2), manager.connect(addr)).await??;
+    connection.handshake(None, Permission::ViewOnly).await?;
+
+    for id in [1, 2] {
+        let frame = Frame { width: 64, height: 48, data: vec![id as u8; 64 * 48 * 3].into(), ..create_test_frame(id) };
+        connection.send_keyframe(&frame).await?;
+        let received = tokio::time::timeout(Duration::from_secs(2), network.next_frame()).await?;
+        assert_eq!(received.map(|frame| frame.id), Some(id));
+    }
+
+    accepting.abort();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_viewer_counts_missing_and_duplicate_frames() -> Result<()> {
+    use pixel_change_check_client::{
+        input::Permission,
+        network::{Message, NetworkConfig, NetworkManager},
+        server::network::{SequenceCounts, ServerNetwork},
+        telemetry::FrameTimings,
+    };
+    use std::sync::Arc;
+
+    let config = NetworkConfig { port: Some(0), ..NetworkConfig::default() };
+    let network = Arc::new(ServerNetwork::new(config.clone(), ResilienceConfig::default())?);
+    let addr = network.advertised_addr()?;
+    let accepting = tokio::spawn({
+        let network = network.clone();
+        async move { network.start().await }
+    });
+    let manager = NetworkManager::new_client(config).await?;
+    let connection = tokio::time::timeout(Duration::from_secs(2), manager.connect(addr)).await??;
+    connection.handshake(None, Permission::ViewOnly).await?;
+
+    // Frame 3 is sent, going by its timings, but lost; frame 5 is unchanged
+    // and never sent; frame 2 arrives twice
+    for id in (1..=14).filter(|&id| id != 5) {
+        let timings = FrameTimings { frame_id: id, capture: Duration::ZERO, detect: Duration::ZERO, encode: Duration::ZERO };
+        connection.send_message(&Message::FrameTimings(timings)).await?;
+        let copies = match id {
+            2 => 2,
+            3 => 0,
+            _ => 1,
+        };
+        for _ in 0..copies {
+            let frame = Frame { width: 64, height: 48, data: vec![0; 64 * 48 * 3].into(), ..create_test_frame(id) };
+            connection.send_keyframe(&frame).await?;
+            tokio::time::timeout(Duration::from_secs(2), network.next_frame()).await?;
+        }
+    }
+
+    let expected = SequenceCounts { missing: 1, reordered: 0, duplicates: 1 };
+    let counts = tokio::time::timeout(Duration::from_secs(2), async {
+        loop {
+            let counts = network.host_stats().await.first().map(|host| host.sequence);
+            if counts == Some(expected) {
+                break counts;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await;
+    assert_eq!(counts, Ok(Some(expected)));
+
+    accepting.abort();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_viewer_reports_per_host_stats() -> Result<()> {
+    use pixel_change_check_client::{
+        input::Permission,
+        metrics,
+        network::{Message, NetworkConfig, NetworkManager},
+        server::network::ServerNetwork,
+    };
+    use std::sync::Arc;
+
+    let config = NetworkConfig { port: Some(0), ..NetworkConfig::default() };
+    let network = Arc::new(ServerNetwork::new(config.clone(), ResilienceConfig::default())?);
+    let addr = network.advertised_addr()?;
+    let accepting = tokio::spawn({
+        let network = network.clone();
+        async move { network.start().await }
+    });
+    let manager = NetworkManager::new_client(config).await?;
+    let connection = tokio::time::timeout(Duration::from_secs(2), manager.connect(addr)).await??;
+    connection.handshake(None, Permission::ViewOnly).await?;
+
+    let quality = QualityConfig { target_fps: 15, ..QualityConfig::default() };
+    connection.send_message(&Message::QualityConfig(quality)).await?;
+    for id in 1..=3 {
+        let frame = Frame { width: 64, height: 48, data: vec![0; 64 * 48 * 3].into(), ..create_test_frame(id) };
+        connection.send_keyframe(&frame).await?;
+        tokio::time::timeout(Duration::from_secs(2), network.next_frame()).await?;
+    }
+
+    let metrics_addr = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?;
+    let serving = tokio::spawn({
+        let network = network.clone();
+        metrics::serve(
+            metrics_addr,
+            || async { String::new() },
+            || async { Default::default() },
+            move || {
+                let network = network.clone();
+                async move { network.host_stats().await }
+            },
+        )
+    });
+    let hosts = tokio::time::timeout(Duration::from_secs(2), async {
+        loop {
+            match metrics::query_hosts(metrics_addr).await {
+                Ok(hosts) if hosts.first().is_some_and(|host| host.quality.is_some()) => break hosts,
+                _ => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        }
+    })
+    .await?;
+
+    assert_eq!(hosts.len(), 1);
+    let host = &hosts[0];
+    assert_eq!(host.quality, Some(quality));
+    assert_eq!(host.frames_delivered(), 3);
+    assert_eq!(host.frames_dropped(), 0);
+    assert!(host.received_bytes > 64 * 48 * 3);
+    assert!(host.sent_bytes > 0);
+
+    serving.abort();
+    accepting.abort();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_viewer_routes_concurrent_host_sessions() -> Result<()> {
+    use pixel_change_check_client::{
+        input::Permission,
+        network::{CloseReason, NetworkConfig, NetworkManager},
+        server::network::ServerNetwork,
+    };
+    use std::sync::Arc;
+
+    let config = NetworkConfig { port: Some(0), ..NetworkConfig::default() };
+    let network = Arc::new(ServerNetwork::new(config.clone(), ResilienceConfig::default())?);
+    let addr = network.advertised_addr()?;
+    let accepting = tokio::spawn({
+        let network = network.clone();
+        async move { network.start().await }
+    });
+
+    // A host stuck before its handshake doesn't hold up the others
+    let stalled = NetworkManager::new_client(config.clone()).await?;
+    let _stalled = tokio::time::timeout(Duration::from_secs(2), stalled.connect(addr)).await??;
+
+    let mut hosts = Vec::new();
+    for _ in 0..2 {
+        let manager = NetworkManager::new_client(config.clone()).await?;
+        let connection = tokio::time::timeout(Duration::from_secs(2), manager.connect(addr)).await??;
+        tokio::time::timeout(Duration::from_secs(2), connection.handshake(None, Permission::ViewOnly)).await??;
+        hosts.push((manager, connection));
+    }
+
+    let mut senders = Vec::new();
+    for (id, (_, connection)) in hosts.iter().enumerate() {
+        connection.send_keyframe(&create_test_frame(id as u64 + 1)).await?;
+        let (session, frame) = tokio::time::timeout(Duration::from_secs(2), network.next_session_frame())
+            .await?
+            .expect("Frame is received");
+        assert_eq!(frame.id, id as u64 + 1);
+        senders.push(session);
+    }
+    assert_ne!(senders[0], senders[1], "Each host has its own session");
+
+    let stats = network.host_stats().await;
+    assert_eq!(stats.iter().map(|host| host.session).collect::<Vec<_>>(), senders);
+    assert!(stats.iter().all(|host| host.permission == Permission::ViewOnly && !host.resumed));
+
+    assert!(network.disconnect(senders[0], CloseReason::Normal).await);
+    let remaining = tokio::time::timeout(Duration::from_secs(2), async {
+        loop {
+            let stats = network.host_stats().await;
+            if stats.len() == 1 {
+                break stats;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await?;
+    assert_eq!(remaining[0].session, senders[1]);
+    assert!(!network.disconnect(senders[0], CloseReason::Normal).await);
+
+    accepting.abort();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_host_resumes_its_session_on_reconnect() -> Result<()> {
+    use pixel_change_check_client::{
+        client::{self, ClientConfig, SessionMonitor},
+        input::Permission,
+        network::{CloseReason, NetworkConfig, NetworkManager},
+        server::network::ServerNetwork,
+    };
+    use std::sync::Arc;
+
+    let network_config = NetworkConfig { port: Some(0), ..NetworkConfig::default() };
+    let network = Arc::new(ServerNetwork::new(network_config.clone(), ResilienceConfig::default())?);
+    let accepting = tokio::spawn({
+        let network = network.clone();
+        async move { network.start().await }
+    });
+    let config = ClientConfig {
+        viewer: network.advertised_addr()?,
+        network: network_config,
+        ..ClientConfig::default()
+    };
+    let (monitor, _remote) = SessionMonitor::new();
+    let connect = || async {
+        let manager = NetworkManager::new_client(config.network.clone()).await?;
+        let connection = tokio::time::timeout(Duration::from_secs(2), manager.connect(config.viewer)).await??;
+        let session = tokio::time::timeout(
+            Duration::from_secs(2),
+            client::open_session(&connection, &config, Permission::ViewOnly, &monitor),
+        )
+        .await??;
+        Ok::<_, anyhow::Error>((manager, connection, session))
+    };
+
+    let (_manager, connection, first) = connect().await?;
+    assert_eq!(first.resume_from, None);
+    assert_eq!(config.resume.token(), Some(first.token));
+    connection.send_keyframe(&create_test_frame(7)).await?;
+    let (_, frame) = tokio::time::timeout(Duration::from_secs(2), network.next_session_frame())
+        .await?
+        .expect("Frame is received");
+    assert_eq!(frame.id, 7);
+    // Dropped, as if the link went down
+    connection.close(CloseReason::ConnectionLost).await?;
+
+    // The next connection presents the kept token and picks up after the
+    // frame the viewer got
+    let (_manager, connection, resumed) = connect().await?;
+    assert_eq!(resumed.token, first.token);
+    assert_eq!(resumed.resume_from, Some(7));
+    // The viewer sees it as resumed too
+    tokio::time::timeout(Duration::from_secs(2), async {
+        while !network.host_stats().await.iter().any(|host| host.resumed) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await?;
+    connection.close(CloseReason::HostStoppedSharing).await?;
+
+    // Without a kept token the viewer starts a new session
+    config.resume.clear();
+    let (_manager, _connection, fresh) = connect().await?;
+    assert_ne!(fresh.token, first.token);
+    assert_eq!(fresh.resume_from, None);
+
+    accepting.abort();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_frame_spans_carry_frame_id() -> Result<()> {
+    use pixel_change_check_client::{
+        input::Permission,
+        network::{NetworkConfig, NetworkManager},
+        server::{network::ServerNetwork, FrameSink, Renderer},
+    };
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing_subscriber::{layer::Context, prelude::*, registry::LookupSpan, Layer};
+
+    // Each span's name and frame_id, by span id
+    type SpanFields = HashMap<u64, (&'static str, Option<u64>)>;
+    #[derive(Clone, Default)]
+    struct Spans(Arc<Mutex<SpanFields>>);
+
+    struct FrameId<'a>(&'a mut Option<u64>);
+
+    impl Visit for FrameId<'_> {
+        fn record_u64(&mut self, field: &Field, value: u64) {
+            if field.name() == "frame_id" {
+                *self.0 = Some(value);
+            }
+        }
+
+        fn record_debug(&mut self, _field: &Field, _value: &dyn std::fmt::Debug) {}
+    }
+
+    impl<S: tracing::Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Spans {
+        fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, _ctx: Context<'_, S>) {
+            let mut frame_id = None;
+            attrs.record(&mut FrameId(&mut frame_id));
+            self.0.lock().unwrap().insert(id.into_u64(), (attrs.metadata().name(), frame_id));
+        }
+
+        fn on_record(&self, id: &Id, values: &Record<'_>, _ctx: Context<'_, S>) {
+            if let Some((_, frame_id)) = self.0.lock().unwrap().get_mut(&id.into_u64()) {
+                values.record(&mut FrameId(frame_id));
+            }
+        }
+    }
+
+    let spans = Spans::default();
+    let _default = tracing::subscriber::set_default(tracing_subscriber::registry().with(spans.clone()));
+
+    let config = NetworkConfig { port: Some(0), ..NetworkConfig::default() };
+    let network = Arc::new(ServerNetwork::new(config.clone(), ResilienceConfig::default())?);
+    let addr = network.advertised_addr()?;
+    let accepting = tokio::spawn({
+        let network = network.clone();
+        async move { network.start().await }
+    });
+    let manager = NetworkManager::new_client(config).await?;
+    let connection = tokio::time::timeout(Duration::from_secs(2), manager.connect(addr)).await??;
+    connection.handshake(None, Permission::ViewOnly).await?;
+
+    let frame = Frame { width: 64, height: 48, data: vec![7; 64 * 48 * 3].into(), ..create_test_frame(42) };
+    connection.send_keyframe(&frame).await?;
+    let received = tokio::time::timeout(Duration::from_secs(2), network.next_frame())
+        .await?
+        .expect("Frame should arrive");
+    let renderer = Renderer::new(64, 48, 30).await?;
+    renderer.present(received).await?;
+
+    let names = |frame_id| -> Vec<&'static str> {
+        let spans = spans.0.lock().unwrap();
+        let mut names: Vec<_> = spans.values().filter(|span| span.1 == frame_id).map(|span| span.0).collect();
+        names.sort_unstable();
+        names
+    };
+    assert_eq!(names(Some(42)), ["present", "receive"]);
+    let unlabelled = names(None);
+    assert!(unlabelled.contains(&"encode") && unlabelled.contains(&"send"), "{:?}", unlabelled);
+
+    accepting.abort();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_overlay_breaks_latency_down_by_stage() -> Result<()> {
+    use pixel_change_check_client::{
+        network::Message,
+        server::{FrameSink, Renderer},
+        telemetry::{FrameTimings, Stage},
+    };
+
+    let renderer = Renderer::new(64, 48, 30).await?;
+    let mut frame = Frame { width: 64, height: 48, data: vec![0; 64 * 48 * 3].into(), ..create_test_frame(7) };
+    frame.timestamp -= Duration::from_millis(50);
+    renderer.record_arrival(frame.id, frame.timestamp).await;
+    renderer
+        .handle_message(Message::FrameTimings(FrameTimings {
+            frame_id: 7,
+            capture: Duration::from_millis(4),
+            detect: Duration::from_millis(6),
+            encode: Duration::from_millis(10),
+        }))
+        .await?;
+    renderer.present(frame).await?;
+
+    let latency = renderer.overlay_stats().await.latency;
+    assert_eq!(latency.stage(Stage::Capture).p99, Duration::from_millis(4));
+    assert_eq!(latency.detect.p50, Duration::from_millis(6));
+    assert_eq!(latency.encode.p95, Duration::from_millis(10));
+    // 50ms since capture, less the host's 16ms of detect and encode
+    assert!(latency.network.p50 >= Duration::from_millis(34), "{:?}", latency.network);
+    assert!(latency.network.p50 < Duration::from_millis(100), "{:?}", latency.network);
+    assert!(latency.render.p50 > Duration::ZERO);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_session_log_records_viewer_events() -> Result<()> {
+    use pixel_change_check_client::{
+        input::Permission,
+        network::{CloseReason, NetworkConfig, NetworkManager},
+        server::network::ServerNetwork,
+        session_log::{SessionEvent, SessionLog},
+    };
+    use std::sync::Arc;
+
+    let path = std::env::temp_dir().join(format!("pcc-events-{}.jsonl", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let events = SessionLog::open(&path)?;
+
+    let config = NetworkConfig { port: Some(0), ..NetworkConfig::default() };
+    let network = Arc::new(ServerNetwork::new(config.clone(), ResilienceConfig::default())?.with_event_log(events));
+    let addr = network.advertised_addr()?;
+    let accepting = tokio::spawn({
+        let network = network.clone();
+        async move { network.start().await }
+    });
+    let manager = NetworkManager::new_client(config).await?;
+    let connection = tokio::time::timeout(Duration::from_secs(2), manager.connect(addr)).await??;
+    connection.handshake(None, Permission::ViewOnly).await?;
+    let frame = Frame { width: 64, height: 48, data: vec![0; 64 * 48 * 3].into(), ..create_test_frame(3) };
+    connection.send_keyframe(&frame).await?;
+    tokio::time::timeout(Duration::from_secs(2), network.next_frame()).await?;
+    connection.close(CloseReason::HostStoppedSharing).await?;
+
+    let logged = tokio::time::timeout(Duration::from_secs(2), async {
+        loop {
+            let logged = SessionLog::read(&path)?;
+            if logged.len() >= 3 {
+                break anyhow::Ok(logged);
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await??;
+    let events: Vec<_> = logged.iter().map(|logged| logged.event.clone()).collect();
+    assert!(matches!(events[0], SessionEvent::Connect { resumed: false, .. }), "{:?}", events);
+    assert_eq!(events[1], SessionEvent::Keyframe { frame_id: 3 });
+    assert!(
+        matches!(events[2], SessionEvent::Disconnect { reason: CloseReason::HostStoppedSharing, .. }),
+        "{:?}",
+        events
+    );
+    assert!(logged.windows(2).all(|pair| pair[0].unix_ms <= pair[1].unix_ms));
+
+    // Each line is a flat JSON object tagged with its event
+    let text = std::fs::read_to_string(&path)?;
+    let first: serde_json::Value = serde_json::from_str(text.lines().next().unwrap())?;
+    assert_eq!(first["event"], "connect");
+    assert!(first["unix_ms"].is_u64());
+
+    accepting.abort();
+    std::fs::remove_file(&path)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_sealed_frames_reach_paired_viewer() -> Result<()> {
+    use pixel_change_check_client::{
+        input::Permission,
+        network::{Identity, Message, NetworkConfig, NetworkManager, PairingPin},
+        pcc::{FrameUpdate, PixelChange},
+        server::network::ServerNetwork,
+    };
+    use std::{net::SocketAddr, sync::Arc};
+
+    let dir = std::env::temp_dir().join(format!("pcc-sealed-{}", std::process::id()));
+    let identity = Identity::generate(vec!["localhost".to_string()])?;
+    identity.save(&dir)?;
+
+    let pin = PairingPin::generate()?;
+    let typed = pin.code().to_string();
+    let config = NetworkConfig { port: Some(0), identity: Some(dir.clone()), ..NetworkConfig::default() };
+    let network = Arc::new(
+        ServerNetwork::new(config, ResilienceConfig::default())?.with_pin_prompt(move |_| Some(typed.clone())),
+    );
+    let addr = SocketAddr::from(([127, 0, 0, 1], network.local_addr()?.port()));
+    let accepting = tokio::spawn({
+        let network = network.clone();
+        async move { network.start().await }
+    });
+
+    // The viewer signs the key exchange as the pinned identity
+    let host_config =
+        NetworkConfig { encrypt_payloads: true, pinned_fingerprint: Some(identity.fingerprint()?), ..NetworkConfig::default() };
+    let manager = NetworkManager::new_client(host_config).await?;
+    let connection = manager.connect(addr).await?;
+    tokio::time::timeout(Duration::from_secs(2), connection.pair(None, Permission::ViewOnly, &pin)).await??;
+    assert!(connection.payloads_sealed());
+    assert_eq!(connection.viewer_fingerprint(), Some(identity.fingerprint()?));
+
+    let frame = Frame { width: 64, height: 48, data: vec![7; 64 * 48 * 3].into(), ..create_test_frame(1) };
+    connection.send_keyframe(&frame).await?;
+    let received = tokio::time::timeout(Duration::from_secs(2), network.next_frame()).await?;
+    assert_eq!(received.map(|frame| frame.data), Some(frame.data));
+
+    let update = |frame_id| FrameUpdate {
+        frame_id,
+        timestamp: std::time::SystemTime::now(),
+        changes: vec![PixelChange { x: 0, y: 0, width: 2, height: 2, data: vec![9; 2 * 2 * 3] }],
+    };
+    // An unsealed update, as a relay might inject, is dropped
+    connection
+        .send_message(&Message::FrameUpdate { update: update(2), part: 0, parts: 1 })
+        .await?;
+    connection.send_update(&update(3)).await?;
+    let message = tokio::time::timeout(Duration::from_secs(2), network.next_message()).await?;
+    assert!(matches!(message, Some(Message::FrameUpdate { update, .. }) if update.frame_id == 3));
+
+    assert!(network.host_stats().await[0].encrypted);
+    accepting.abort();
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[test]
+fn test_watermark_names_viewer_in_every_frame() -> Result<()> {
+    use pixel_change_check_client::{
+        client::{FrameOutput, FramePipeline},
+        config::PccConfig,
+        network::ViewerInfo,
+        watermark::{Watermark, WatermarkConfig},
+    };
+
+    let config = PccConfig::from_toml("[watermark]\nenabled = true\nopacity = 0.5\nscale = 2\n")?;
+    assert_eq!(config.watermark, WatermarkConfig { enabled: true, opacity: 0.5, scale: 2 });
+
+    let viewer = ViewerInfo { name: Some("lab-viewer".to_string()), fingerprint: None, addr: "10.0.0.7:5800".parse()? };
+    let watermark = Watermark::for_viewer(&viewer, &config.watermark);
+    assert_eq!(watermark.text(), "LAB-VIEWER 10.0.0.7");
+
+    // Lightens dark content and darkens light content, leaving most of it be
+    let frame = |id, value| Frame { width: 320, height: 240, data: vec![value; 320 * 240 * 3].into(), ..create_test_frame(id) };
+    for value in [0, 255] {
+        let mut marked = frame(0, value);
+        watermark.apply(&mut marked);
+        let changed = marked.data.iter().filter(|&&c| c != value).count();
+        assert!(changed > 0 && changed < marked.data.len() / 4, "{} of {} changed", changed, marked.data.len());
+        assert!(marked.data.iter().all(|&c| c == value || c.abs_diff(value) == 128 || c.abs_diff(value) == 127));
+    }
+
+    // The mark is the same every frame, so it doesn't count as a change
+    let mut pipeline = FramePipeline::new(PCCDetector::default()).with_watermark(watermark.clone());
+    match pipeline.process(frame(1, 0), false)? {
+        FrameOutput::Keyframe(keyframe) => assert_ne!(keyframe.data, frame(1, 0).data),
+        other => panic!("Expected a keyframe, got {:?}", other),
+    }
+    assert!(matches!(pipeline.process(frame(2, 0), false)?, FrameOutput::Unchanged));
+    Ok(())
+}
+
+#[test]
+fn test_audit_log_records_control_sessions() -> Result<()> {
+    use pixel_change_check_client::{
+        audit::{AuditConfig, AuditEntry, AuditLog, AuditedInput, Redaction},
+        input::{InputBackend, InputConfig, InputEvent, KeyCode, Permission, RemoteInput},
+        network::{CloseReason, ViewerInfo},
+    };
+
+    struct Discard;
+    impl InputBackend for Discard {
+        fn inject(&mut self, _event: &InputEvent) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    let dir = std::env::temp_dir().join(format!("pcc-audit-{}", std::process::id()));
+    let path = dir.join("audit.log");
+    let viewer = ViewerInfo { name: Some("lab-viewer".to_string()), fingerprint: None, addr: "10.0.0.7:5800".parse()? };
+    let config = AuditConfig { path: Some(path.clone()), ..AuditConfig::default() };
+    let audit = AuditLog::open(&config)?.session(viewer.clone());
+
+    let mut input = RemoteInput::with_backend(&InputConfig { allow_control: true, ..Default::default() }, Box::new(Discard));
+    audit.connected();
+    input.set_audit(audit.clone());
+    input.set_permission(Permission::Control);
+    input.handle(InputEvent::Key { key: KeyCode(0x13), pressed: true, text: Some('p') })?;
+    input.handle(InputEvent::MouseMove { x: 10, y: 20 })?;
+    input.revoke_control();
+    input.handle(InputEvent::MouseMove { x: 11, y: 20 })?;
+    audit.disconnected(CloseReason::Normal);
+
+    let records = AuditLog::read(&path)?;
+    assert!(records.iter().all(|record| record.viewer == viewer && record.unix_ms > 0));
+    let entries: Vec<AuditEntry> = records.into_iter().map(|record| record.entry).collect();
+    assert_eq!(entries[..2], [AuditEntry::Connect, AuditEntry::Permission { permission: Permission::Control }]);
+    // Keys are redacted by default, but pointer input is kept
+    let AuditEntry::Input { injected: 2, dropped: 0, events: Some(events), .. } = &entries[2] else {
+        panic!("Expected the first input batch, got {:?}", entries[2]);
+    };
+    assert_eq!(events, &[AuditedInput::Key { pressed: true }, AuditedInput::Event(InputEvent::MouseMove { x: 10, y: 20 })]);
+    assert_eq!(entries[3], AuditEntry::Permission { permission: Permission::ViewOnly });
+    assert!(matches!(&entries[4], AuditEntry::Input { injected: 0, dropped: 1, .. }));
+    assert_eq!(entries[5], AuditEntry::Disconnect { reason: CloseReason::Normal });
+
+    // Full files are rotated, keeping as many as asked
+    let config = AuditConfig { max_bytes: 1, keep: 1, redaction: Redaction::All, ..config };
+    let audit = AuditLog::open(&config)?.session(viewer);
+    audit.input(InputEvent::Text('x'), true);
+    audit.disconnected(CloseReason::Normal);
+    assert!(matches!(
+        AuditLog::read(&dir.join("audit.log.1"))?[0].entry,
+        AuditEntry::Input { injected: 1, events: None, .. }
+    ));
+    assert_eq!(AuditLog::read(&path)?.len(), 1);
+    assert!(!dir.join("audit.log.2").exists());
+
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_viewer_turns_away_connection_floods() -> Result<()> {
+    use pixel_change_check_client::{
+        input::Permission,
+        network::{ConnectionLimits, NetworkConfig, NetworkManager},
+        server::network::ServerNetwork,
+    };
+    use std::{net::SocketAddr, sync::Arc};
+
+    let serve = |limits: ConnectionLimits| -> Result<(Arc<ServerNetwork>, SocketAddr)> {
+        let config = NetworkConfig { port: Some(0), limits, ..NetworkConfig::default() };
+        let network = Arc::new(ServerNetwork::new(config, ResilienceConfig::default())?);
+        let addr = SocketAddr::from(([127, 0, 0, 1], network.local_addr()?.port()));
+        Ok((network, addr))
+    };
+    let manager = NetworkManager::new_client(NetworkConfig::default()).await?;
+    // QUIC may finish connecting before the viewer drops the attempt, so
+    // what counts is whether a session follows
+    let open_session = |addr| {
+        let manager = &manager;
+        async move {
+            let connection = manager.connect(addr).await?;
+            connection.handshake(None, Permission::ViewOnly).await?;
+            Ok::<_, anyhow::Error>(connection)
+        }
+    };
+
+    // One connection a minute from this address: the second is dropped
+    let (network, addr) = serve(ConnectionLimits { per_address_per_minute: 1, ..ConnectionLimits::default() })?;
+    let accepting = tokio::spawn({
+        let network = network.clone();
+        async move { network.start().await }
+    });
+    let connection = tokio::time::timeout(Duration::from_secs(2), manager.connect(addr)).await??;
+    connection.handshake(None, Permission::ViewOnly).await?;
+    let flooding = tokio::time::timeout(Duration::from_secs(2), open_session(addr)).await;
+    assert!(!matches!(flooding, Ok(Ok(_))), "A second connection within the minute got through");
+    accepting.abort();
+
+    // Banned addresses never get a session
+    let banned = ConnectionLimits { banned: vec!["127.0.0.0/8".parse()?], ..ConnectionLimits::default() };
+    let (network, addr) = serve(banned)?;
+    let accepting = tokio::spawn({
+        let network = network.clone();
+        async move { network.start().await }
+    });
+    let refused = tokio::time::timeout(Duration::from_secs(2), open_session(addr)).await;
+    assert!(!matches!(refused, Ok(Ok(_))), "A banned address connected");
+    assert!(network.host_stats().await.is_empty());
+    accepting.abort();
+    Ok(())
+}
+
+#[test]
+fn test_paused_placeholder_hides_the_screen() -> Result<()> {
+    use pixel_change_check_client::{
+        client::{FrameOutput, FramePipeline, SessionStatus},
+        privacy,
+        session_log::{LoggedEvent, SessionEvent},
+    };
+
+    let screen = Frame { data: vec![200; (TEST_WIDTH * TEST_HEIGHT * 3) as usize].into(), ..create_test_frame(0) };
+    let placeholder = privacy::placeholder(1, screen.timestamp, TEST_WIDTH, TEST_HEIGHT);
+    assert_eq!((placeholder.id, placeholder.width, placeholder.height), (1, TEST_WIDTH, TEST_HEIGHT));
+    assert_eq!(placeholder.data.len(), screen.data.len());
+    // Mostly dark, with the caption lit
+    let lit = placeholder.data.chunks_exact(3).filter(|pixel| pixel[0] > 0x80).count();
+    assert!(lit > 0 && lit < (TEST_WIDTH * TEST_HEIGHT / 10) as usize, "{} pixels lit", lit);
+
+    // The placeholder goes whole, and so does the screen after it
+    let mut pipeline = FramePipeline::new(PCCDetector::default());
+    assert!(matches!(pipeline.process(screen.clone(), false)?, FrameOutput::Keyframe(_)));
+    match pipeline.process(placeholder.clone(), true)? {
+        FrameOutput::Keyframe(frame) => assert_eq!(frame.data, placeholder.data),
+        other => panic!("Expected a keyframe, got {:?}", other),
+    }
+    let resumed = Frame { id: 2, ..screen.clone() };
+    assert!(matches!(pipeline.process(resumed, true)?, FrameOutput::Keyframe(frame) if frame.data == screen.data));
+
+    // A paused host captures nothing but is still healthy
+    let status = SessionStatus { viewer: Some("127.0.0.1:5800".parse()?), target_fps: 30, paused: true, ..SessionStatus::default() };
+    assert!(status.health().is_healthy(), "{:?}", status.health());
+
+    let line = r#"{"unix_ms":1,"event":"pause","paused":true}"#;
+    let logged: LoggedEvent = serde_json::from_str(line)?;
+    assert_eq!(logged.event, SessionEvent::Pause { paused: true });
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_sealed_session_survives_rekeys() -> Result<()> {
+    use pixel_change_check_client::{
+        config::PccConfig,
+        input::Permission,
+        network::{NetworkConfig, NetworkManager},
+        server::network::ServerNetwork,
+    };
+    use std::{net::SocketAddr, sync::Arc};
+
+    let config = PccConfig::from_toml("[network.rekey_interval]\nsecs = 600\nnanos = 0\n")?;
+    assert_eq!(config.network.rekey_interval, Some(Duration::from_secs(600)));
+    assert_eq!(NetworkConfig::default().rekey_interval, Some(Duration::from_secs(3600)));
+
+    let network = Arc::new(ServerNetwork::new(
+        NetworkConfig { port: Some(0), ..NetworkConfig::default() },
+        ResilienceConfig::default(),
+    )?);
+    let addr = SocketAddr::from(([127, 0, 0, 1], network.local_addr()?.port()));
+    let accepting = tokio::spawn({
+        let network = network.clone();
+        async move { network.start().await }
+    });
+
+    let host_config = NetworkConfig { encrypt_payloads: true, ..NetworkConfig::default() };
+    let manager = NetworkManager::new_client(host_config).await?;
+    let connection = manager.connect(addr).await?;
+    tokio::time::timeout(Duration::from_secs(2), connection.handshake(None, Permission::ViewOnly)).await??;
+    assert!(connection.payloads_sealed());
+
+    // Each frame goes out under new QUIC and payload keys
+    for id in 1..=3u8 {
+        let frame = Frame { width: 64, height: 48, data: vec![id; 64 * 48 * 3].into(), ..create_test_frame(id as u64) };
+        connection.send_keyframe(&frame).await?;
+        let received = tokio::time::timeout(Duration::from_secs(2), network.next_frame()).await?;
+        assert_eq!(received.map(|frame| frame.data), Some(frame.data));
+        connection.rekey()?;
+    }
+    accepting.abort();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_hosts_hear_when_viewer_records() -> Result<()> {
+    use pixel_change_check_client::{
+        input::Permission,
+        network::{NetworkConfig, NetworkManager},
+        server::{self, network::ServerNetwork, recorder::RecordingConfig, renderer::Renderer},
+        session_log::{LoggedEvent, SessionEvent},
+    };
+    use std::{net::SocketAddr, sync::Arc};
+
+    assert!(!NetworkConfig::default().require_recording_state);
+    let network = Arc::new(ServerNetwork::new(
+        NetworkConfig { port: Some(0), ..NetworkConfig::default() },
+        ResilienceConfig::default(),
+    )?);
+    let renderer = Arc::new(Renderer::new(64, 48, 30).await?);
+    let addr = SocketAddr::from(([127, 0, 0, 1], network.local_addr()?.port()));
+    let viewer = tokio::spawn({
+        let (network, renderer) = (network.clone(), renderer.clone());
+        async move {
+            tokio::select! {
+                result = network.start() => result,
+                result = server::present_incoming(&network, &renderer) => result,
+            }
+        }
+    });
+
+    let manager = NetworkManager::new_client(NetworkConfig::default()).await?;
+    let connection = manager.connect(addr).await?;
+    tokio::time::timeout(Duration::from_secs(2), connection.handshake(None, Permission::ViewOnly)).await??;
+    let mut recording = connection.viewer_recording();
+    assert_eq!(*recording.borrow_and_update(), Some(false), "Said in the handshake");
+    let _events = connection.control_events();
+
+    let path = std::env::temp_dir().join(format!("pcc-consent-{}.mkv", std::process::id()));
+    let mut config = RecordingConfig::new(&path);
+    config.min_free_space = 0;
+    renderer.start_recording(config).await?;
+    tokio::time::timeout(Duration::from_secs(2), recording.wait_for(|now| *now == Some(true))).await??;
+    renderer.stop_recording().await?;
+    tokio::time::timeout(Duration::from_secs(2), recording.wait_for(|now| *now == Some(false))).await??;
+    let _ = std::fs::remove_file(&path);
+
+    let logged: LoggedEvent = serde_json::from_str(r#"{"unix_ms":1,"event":"recording","recording":true}"#)?;
+    assert_eq!(logged.event, SessionEvent::Recording { recording: true });
+    viewer.abort();
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_pipeline_builder_wires_capture_to_transport() -> Result<()> {
+    use async_trait::async_trait;
+    use pixel_change_check_client::{
+        benchmark::SyntheticCapture,
+        client::FrameOutput,
+        pipeline::{FrameTransport, Pipeline},
+    };
+    use std::sync::{Arc, Mutex};
+
+    // Keeps what was sent, taking a while over each
+    #[derive(Default)]
+    struct Slow {
+        sent: Mutex<Vec<&'static str>>,
+    }
+    #[async_trait]
+    impl FrameTransport for Slow {
+        async fn send(&self, output: &FrameOutput) -> Result<()> {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            self.sent.lock().unwrap().push(match output {
+                FrameOutput::Keyframe(_) => "keyframe",
+                FrameOutput::Update(_) => "update",
+                FrameOutput::Scroll { .. } => "scroll",
+                FrameOutput::Unchanged => "unchanged",
+            });
+            Ok(())
+        }
+    }
+
+    let missing = Pipeline::builder().capture(SyntheticCapture::new(64, 48)).build();
+    assert!(missing.is_err(), "No encoder or transport");
+
+    let transport = Arc::new(Slow::default());
+    let quality = QualityConfig { target_fps: 200, max_fps: 200, ..QualityConfig::default() };
+    let pipeline = Pipeline::builder()
+        .capture(SyntheticCapture::new(64, 48))
+        .detector(PCCDetector::default())
+        .encoder(FrameEncoder::new(64, 48, quality)?)
+        .transport(transport.clone())
+        .quality(quality)
+        .build()?;
+    pipeline.encoder().force_keyframe();
+    let stats = pipeline.run(tokio::time::sleep(Duration::from_millis(300))).await?;
+
+    let sent = transport.sent.lock().unwrap().clone();
+    assert_eq!(sent[0], "keyframe");
+    assert!(sent[1..].contains(&"update"), "{:?}", sent);
+    // Everything captured was sent, and the slow transport held capture back
+    assert_eq!(stats.captured, sent.len() as u64);
+    assert_eq!(stats.keyframes + stats.updates + stats.unchanged, stats.captured);
+    assert!(stats.skipped > 0, "{:?}", stats);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_viewer_facade_presents_and_stops() -> Result<()> {
+    use pixel_change_check_client::{
+        config::PccConfig,
+        input::Permission,
+        network::{NetworkConfig, NetworkManager},
+        Viewer,
+    };
+    use std::net::SocketAddr;
+
+    let viewer = Viewer::connect(SocketAddr::from(([127, 0, 0, 1], 0)), PccConfig::default()).await?;
+    let addr = viewer.addr()?;
+    assert!(addr.ip().is_loopback(), "Bound where asked");
+
+    let manager = NetworkManager::new_client(NetworkConfig::default()).await?;
+    let connection = manager.connect(addr).await?;
+    tokio::time::timeout(Duration::from_secs(2), connection.handshake(None, Permission::ViewOnly)).await??;
+    let frame = Frame { width: 64, height: 48, data: vec![9; 64 * 48 * 3].into(), ..create_test_frame(1) };
+    connection.send_keyframe(&frame).await?;
+    tokio::time::timeout(Duration::from_secs(2), async {
+        while viewer.stats().await.first().is_none_or(|host| host.keyframes == 0) {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    })
+    .await?;
+
+    viewer.pause().await;
+    assert!(viewer.renderer().is_paused().await);
+    viewer.resume().await?;
+    assert!(!viewer.renderer().is_paused().await);
+
+    // The host is told goodbye rather than timing out
+    let closed = connection.closed();
+    tokio::time::timeout(Duration::from_secs(5), viewer.stop()).await??;
+    tokio::time::timeout(Duration::from_secs(2), closed).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_host_facade_fails_without_a_viewer() -> Result<()> {
+    use pixel_change_check_client::{client::ClientConfig, network::NetworkConfig, Host};
+    use std::net::SocketAddr;
+
+    // Nothing listens here; capture may fail first on a headless machine
+    let config = ClientConfig {
+        viewer: SocketAddr::from(([127, 0, 0, 1], 9)),
+        network: NetworkConfig { connection_timeout: Duration::from_secs(1), ..NetworkConfig::default() },
+        ..ClientConfig::default()
+    };
+    let shared = tokio::time::timeout(Duration::from_secs(10), Host::share(config)).await?;
+    assert!(shared.is_err());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_pipeline_runs_async_capture_and_detection() -> Result<()> {
+    use async_trait::async_trait;
+    use pixel_change_check_client::{
+        benchmark::SyntheticCapture,
+        client::FrameOutput,
+        pcc::{AsyncFrameCapture, AsyncPixelChangeDetector, BlockingCapture, FrameCapture, PixelChange},
+        pipeline::{FrameTransport, Pipeline},
+    };
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    // Frames arrive from the "OS" after a wait, without a thread blocked on them
+    struct Delivered(SyntheticCapture);
+    #[async_trait]
+    impl AsyncFrameCapture for Delivered {
+        async fn capture_frame(&self) -> Result<Frame> {
+            tokio::time::sleep(Duration::from_millis(2)).await;
+            self.0.capture_frame()
+        }
+        fn supported_configs(&self) -> Vec<QualityConfig> {
+            self.0.supported_configs()
+        }
+        fn configure(&mut self, config: QualityConfig) -> Result<()> {
+            self.0.configure(config)
+        }
+    }
+
+    // Hands detection off and waits for the answer, as a GPU detector would
+    struct Offloaded(Arc<PCCDetector>, Arc<AtomicU64>);
+    #[async_trait]
+    impl AsyncPixelChangeDetector for Offloaded {
+        async fn detect_changes(&self, previous: &Frame, current: &Frame) -> Result<Vec<PixelChange>> {
+            self.1.fetch_add(1, Ordering::Relaxed);
+            let (detector, previous, current) = (self.0.clone(), previous.clone(), current.clone());
+            tokio::task::spawn_blocking(move || detector.detect_changes(&previous, &current)).await?
+        }
+        fn configure(&mut self, _config: QualityConfig) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    struct Counting(AtomicU64);
+    #[async_trait]
+    impl FrameTransport for Counting {
+        async fn send(&self, output: &FrameOutput) -> Result<()> {
+            if !matches!(output, FrameOutput::Unchanged) {
+                self.0.fetch_add(1, Ordering::Relaxed);
+            }
+            Ok(())
+        }
+    }
+
+    // The blocking adapter captures off the async threads
+    let blocking = BlockingCapture::new(SyntheticCapture::new(64, 48));
+    assert_eq!(blocking.capture_frame().await?.data.len(), 64 * 48 * 3);
+
+    let detections = Arc::new(AtomicU64::new(0));
+    let transport = Arc::new(Counting(AtomicU64::new(0)));
+    let quality = QualityConfig { target_fps: 100, max_fps: 100, ..QualityConfig::default() };
+    let pipeline = Pipeline::builder()
+        .async_capture(Delivered(SyntheticCapture::new(64, 48)))
+        .async_detector(Offloaded(Arc::new(PCCDetector::default()), detections.clone()))
+        .encoder(FrameEncoder::new(64, 48, quality)?)
+        .transport(transport.clone())
+        .quality(quality)
+        .build()?;
+    let stats = pipeline.run(tokio::time::sleep(Duration::from_millis(200))).await?;
+
+    assert!(stats.captured > 1, "{:?}", stats);
+    assert_eq!(stats.keyframes, 1, "Only the first frame goes whole");
+    assert_eq!(detections.load(Ordering::Relaxed), stats.captured - 1);
+    assert_eq!(transport.0.load(Ordering::Relaxed), stats.keyframes + stats.updates);
+    Ok(())
+}
+
+#[test]
+fn test_core_reads_frame_messages_as_the_host_sends_them() -> Result<()> {
+    use pixel_change_check_client::{
+        network::{CloseReason, EncodedFrame, Message},
+        pcc::{wire::FrameMessage, ColorSpace, FrameUpdate, PixelChange, Rect},
+    };
+
+    let frame = Frame { width: 8, height: 4, data: vec![10; 8 * 4 * 3].into(), ..create_test_frame(1) };
+    let update = FrameUpdate {
+        frame_id: 2,
+        timestamp: frame.timestamp,
+        changes: vec![PixelChange { x: 2, y: 1, width: 2, height: 2, data: vec![200; 2 * 2 * 3] }],
+    };
+    let copy = Rect::new(0, 0, 2, 1);
+
+    // Written and read the same way by the full protocol and the core's subset
+    let pairs = [
+        (Message::FrameUpdate { update: update.clone(), part: 0, parts: 1 }, FrameMessage::Update { update: update.clone(), part: 0, parts: 1 }),
+        (Message::CopyRect { src_x: 2, src_y: 1, dst_rect: copy }, FrameMessage::CopyRect { src_x: 2, src_y: 1, dst_rect: copy }),
+        (Message::ColorSpace(ColorSpace::DisplayP3), FrameMessage::ColorSpace(ColorSpace::DisplayP3)),
+        (Message::KeepAlive, FrameMessage::KeepAlive),
+        (Message::RequestKeyframe, FrameMessage::RequestKeyframe),
+    ];
+    for (full, core) in &pairs {
+        assert_eq!(full.serialize()?, core.serialize()?, "{:?}", core);
+        assert_eq!(&FrameMessage::parse(&full.serialize()?)?, core);
+        assert_eq!(&Message::deserialize(&core.serialize()?)?, full);
+    }
+    let goodbye = Message::Goodbye { reason: CloseReason::Normal }.serialize()?;
+    assert!(matches!(FrameMessage::parse(&goodbye)?, FrameMessage::Other(_)));
+    assert!(FrameMessage::parse(&goodbye[..3]).is_err());
+
+    // A viewer with only the core follows along from a keyframe
+    let EncodedFrame::Keyframe(keyframe) = EncodedFrame::keyframe(&frame)? else { unreachable!() };
+    let EncodedFrame::Update(parts) = EncodedFrame::update(&update)? else { unreachable!() };
+    let mut shown = Frame::decode(&keyframe)?;
+    for part in &parts {
+        let FrameMessage::Update { update, .. } = FrameMessage::parse(part)? else { unreachable!() };
+        shown.apply_update(&update)?;
+    }
+    shown.copy_rect(2, 1, copy)?;
+    assert_eq!(shown.id, 2);
+    let pixel = |frame: &Frame, x: usize, y: usize| frame.data[(y * 8 + x) * 3];
+    assert_eq!((pixel(&shown, 0, 0), pixel(&shown, 3, 2), pixel(&shown, 5, 3)), (200, 200, 10));
+
+    let outside = FrameUpdate { changes: vec![PixelChange { x: 7, ..update.changes[0].clone() }], ..update };
+    let before = shown.data.clone();
+    assert!(shown.apply_update(&outside).is_err());
+    assert_eq!(shown.data, before, "Nothing applied");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_frames_share_pixels_from_pipeline_to_buffer() -> Result<()> {
+    use pixel_change_check_client::client::{FrameOutput, FramePipeline};
+
+    let frame = Frame { width: 16, height: 8, data: vec![7; 16 * 8 * 3].into(), ..create_test_frame(1) };
+    let pixels = frame.data.as_ptr();
+
+    // The pipeline keeps the frame to diff against and sends it too
+    let mut pipeline = FramePipeline::new(PCCDetector::default());
+    let FrameOutput::Keyframe(sent) = pipeline.process(frame.clone(), false)? else {
+        panic!("The first frame goes whole");
+    };
+    assert_eq!(sent.data.as_ptr(), pixels);
+
+    let buffer = FrameBuffer::new(16, 8);
+    buffer.push_frame(sent).await?;
+    let played = buffer.next_frame().await?.expect("Frame is due");
+    assert_eq!(played.data.as_ptr(), pixels, "Buffering shouldn't copy pixels");
+
+    // Changing a shared frame copies it, leaving the others as they were
+    let mut changed = played.into_frame();
+    changed.modify_data(|data| data[0] = 9);
+    assert_ne!(changed.data.as_ptr(), pixels);
+    assert_eq!(frame.data[0], 7);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_frame_pools_reuse_buffers_once_frames_are_done() -> Result<()> {
+    use pixel_change_check_client::client::{FrameOutput, FramePipeline};
+    use pixel_change_check_client::pcc::FramePool;
+
+    // The host captures into its pool and the pipeline gives frames back
+    // once diffed against; the viewer decodes into its pool and the buffer
+    // gives frames back once the next is played
+    let host_pool = FramePool::default();
+    let mut pipeline = FramePipeline::new(PCCDetector::default()).with_pool(host_pool.clone());
+    let viewer_pool = FramePool::default();
+    let buffer = FrameBuffer::new(16, 8).with_pool(viewer_pool.clone());
+
+    for id in 0..20u64 {
+        let mut data = host_pool.take(16 * 8 * 3);
+        data.fill(id as u8);
+        let frame = Frame { id, timestamp: std::time::SystemTime::now(), width: 16, height: 8, format: PixelFormat::Rgb24, data: data.freeze() };
+        let FrameOutput::Keyframe(sent) = pipeline.process(frame, true)? else {
+            panic!("Keyframes go whole");
+        };
+        let received = Frame::decode_pooled(&sent.encode()?, &viewer_pool)?;
+        drop(sent);
+        buffer.push_frame(received).await?;
+        let played = buffer.next_frame().await?.expect("Frame is due");
+        assert_eq!(played.data[0], id as u8);
+    }
+
+    // One buffer for the newest frame and one for the frame before it
+    for pool in [host_pool, viewer_pool] {
+        let stats = pool.stats();
+        assert_eq!((stats.allocations, stats.reuses), (2, 18), "{:?}", stats);
+        assert_eq!(stats.high_water, 1);
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_captures_compose_as_streams() -> Result<()> {
+    use futures::{StreamExt, TryStreamExt};
+    use pixel_change_check_client::benchmark::SyntheticCapture;
+
+    let frames: Vec<Frame> = SyntheticCapture::new(32, 20).into_stream().take(6).try_collect().await?;
+    assert_eq!(frames.iter().map(|frame| frame.id).collect::<Vec<_>>(), (0..6).collect::<Vec<_>>());
+    assert_ne!(frames[0].data, frames[1].data, "The bar scrolls");
+
+    // Combinators end the stream, which otherwise goes on
+    let stream = SyntheticCapture::new(32, 20).into_stream();
+    let kept = stream
+        .take_while(|frame| std::future::ready(frame.as_ref().is_ok_and(|frame| frame.id < 3)))
+        .count()
+        .await;
+    assert_eq!(kept, 3);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_frame_streams_forward_into_a_transport_one_at_a_time() -> Result<()> {
+    use futures::{SinkExt, StreamExt};
+    use pixel_change_check_client::{
+        benchmark::SyntheticCapture,
+        client::{FrameOutput, FramePipeline},
+        pcc::FrameUpdate,
+        pipeline::{FrameTransport, TransportSink},
+    };
+    use std::sync::{Arc, Mutex};
+
+    // Takes its time over each send, noting how many overlap
+    #[derive(Default)]
+    struct SlowTransport {
+        sent: Mutex<Vec<&'static str>>,
+        in_flight: Mutex<(usize, usize)>,
+    }
+
+    #[async_trait::async_trait]
+    impl FrameTransport for SlowTransport {
+        async fn send(&self, output: &FrameOutput) -> Result<()> {
+            {
+                let mut in_flight = self.in_flight.lock().unwrap();
+                in_flight.0 += 1;
+                in_flight.1 = in_flight.1.max(in_flight.0);
+            }
+            tokio::time::sleep(Duration::from_millis(5)).await;
+            self.in_flight.lock().unwrap().0 -= 1;
+            self.sent.lock().unwrap().push(match output {
+                FrameOutput::Keyframe(_) => "keyframe",
+                FrameOutput::Update(_) => "update",
+                FrameOutput::Scroll { .. } => "scroll",
+                FrameOutput::Unchanged => "unchanged",
+            });
+            Ok(())
+        }
+    }
+
+    let transport = Arc::new(SlowTransport::default());
+    let mut pipeline = FramePipeline::new(PCCDetector::default());
+    let mut sink = TransportSink::new(transport.clone());
+    SyntheticCapture::new(32, 20)
+        .into_stream()
+        .take(4)
+        .map(|frame| pipeline.process(frame?, false))
+        .forward(&mut sink)
+        .await?;
+
+    // Updates on their own go too
+    let update = FrameUpdate { frame_id: 9, timestamp: std::time::SystemTime::now(), changes: Vec::new() };
+    sink.send(update).await?;
+
+    assert_eq!(*transport.sent.lock().unwrap(), ["keyframe", "update", "update", "update", "update"]);
+    assert_eq!(transport.in_flight.lock().unwrap().1, 1, "One send at a time");
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_shutdown_stops_long_running_loops() -> Result<()> {
+    use pixel_change_check_client::server::Renderer;
+    use std::sync::Arc;
+
+    let renderer = Arc::new(Renderer::new(16, 8, 30).await?);
+    let rendering = tokio::spawn({
+        let renderer = renderer.clone();
+        async move { renderer.start().await }
+    });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    renderer.shutdown().await?;
+    tokio::time::timeout(Duration::from_secs(2), rendering).await???;
+    // Already shut down, so it doesn't start again
+    tokio::time::timeout(Duration::from_secs(2), renderer.start()).await??;
+
+    let resilience = NetworkResilience::new(ResilienceConfig::default());
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let monitor = resilience.monitor_connection(|| Ok(true), async {
+        let _ = stopped.await;
+    });
+    let _ = stop.send(());
+    tokio::time::timeout(Duration::from_secs(2), monitor).await??;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_frames_in_other_pixel_formats_are_converted_to_rgb24() -> Result<()> {
+    use pixel_change_check_client::client::{FrameOutput, FramePipeline};
+    use pixel_change_check_client::pcc::format;
+
+    let (width, height) = (4, 2);
+    let rgb: Vec<u8> = (0..width * height).flat_map(|i| [60, 90 + i as u8 * 10, 150]).collect();
+    let frame = |id, format| -> Result<Frame> {
+        Ok(Frame {
+            id,
+            timestamp: std::time::SystemTime::now(),
+            width,
+            height,
+            format,
+            data: format::from_rgb24(format, width, height, &rgb)?.into(),
+        })
+    };
+
+    // The detector only diffs RGB24
+    let bgra = frame(1, PixelFormat::Bgra32)?;
+    assert_eq!(bgra.planes()[0].stride, 16);
+    assert!(PCCDetector::default().detect_changes(&bgra, &bgra).is_err());
+    assert_eq!(bgra.clone().into_rgb24()?.data, rgb);
+
+    // The host's pipeline and the viewer's buffer convert on the way in
+    let mut pipeline = FramePipeline::new(PCCDetector::default());
+    let FrameOutput::Keyframe(sent) = pipeline.process(bgra, false)? else {
+        panic!("The first frame goes whole");
+    };
+    assert_eq!((sent.format, &sent.data[..]), (PixelFormat::Rgb24, &rgb[..]));
+    assert!(matches!(pipeline.process(frame(2, PixelFormat::Rgba64)?, false)?, FrameOutput::Unchanged));
+
+    let buffer = FrameBuffer::new(width, height);
+    buffer.push_frame(frame(3, PixelFormat::Nv12)?).await?;
+    let played = buffer.next_frame().await?.expect("Frame is due");
+    assert_eq!(played.data.len(), rgb.len());
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_network_queues_overflow_as_configured() -> Result<()> {
+    use pixel_change_check_client::network::{queue, NetworkConfig, Overflow, QueueConfig, QueueStats};
+
+    let (tx, mut rx) = queue(QueueConfig::new(2, Overflow::DropOldest));
+    for item in 1..=4 {
+        tx.send(item).await?;
+    }
+    assert_eq!(tx.stats(), QueueStats { queued: 2, dropped: 2 });
+    assert_eq!((rx.recv().await, rx.recv().await), (Some(3), Some(4)));
+
+    let (tx, mut rx) = queue(QueueConfig::new(2, Overflow::DropNewest));
+    for item in 1..=4 {
+        tx.send(item).await?;
+    }
+    assert_eq!((rx.recv().await, rx.recv().await), (Some(1), Some(2)));
+    assert_eq!(rx.stats().dropped, 2);
+
+    // A blocking queue holds the sender until there's room, and ends once
+    // the senders have gone and it's drained
+    let (tx, mut rx) = queue(QueueConfig::new(1, Overflow::Block));
+    tx.send(1).await?;
+    let blocked = tokio::spawn(async move { tx.send(2).await });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    assert!(!blocked.is_finished());
+    assert_eq!(rx.recv().await, Some(1));
+    tokio::time::timeout(Duration::from_secs(1), blocked).await???;
+    assert_eq!((rx.recv().await, rx.recv().await), (Some(2), None));
+
+    // Closing turns senders away, waking any waiting for room
+    let (tx, mut rx) = queue(QueueConfig::new(1, Overflow::Block));
+    tx.send(1).await?;
+    let blocked = tokio::spawn(async move { tx.send(2).await });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    rx.close();
+    assert!(tokio::time::timeout(Duration::from_secs(1), blocked).await??.is_err());
+    assert_eq!(rx.recv().await, Some(1));
+
+    let parsed: NetworkConfig =
+        toml::from_str("[queues]\nframes = { capacity = 4, overflow = \"drop-oldest\" }")?;
+    assert_eq!(parsed.queues.frames, QueueConfig::new(4, Overflow::DropOldest));
+    assert_eq!(parsed.queues.messages, QueueConfig::new(32, Overflow::Block));
+    assert_eq!(parsed.queues.events.overflow, Overflow::DropNewest);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_prelude_covers_a_capture_to_sink_loop() -> Result<()> {
+    use pixel_change_check_client::benchmark::SyntheticCapture;
+    use pixel_change_check_client::prelude::*;
+
+    let capture = SyntheticCapture::new(16, 8);
+    let renderer = Renderer::new(16, 8, 30).await?;
+    let mut pipeline = FramePipeline::new(PCCDetector::new(QualityConfig::default(), 0, 8));
+    for _ in 0..3 {
+        let frame = capture.capture_frame()?;
+        if let FrameOutput::Keyframe(frame) = pipeline.process(frame, false)? {
+            renderer.present(frame).await?;
+        }
+    }
+    assert_eq!(renderer.overlay_stats().await.fps, 1.0);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_config_snapshots_round_trip_through_toml() -> Result<()> {
+    use pixel_change_check_client::config::PccConfig;
+    use pixel_change_check_client::server::renderer::{RendererOptions, ScaleMode};
+    use pixel_change_check_client::Viewer;
+
+    let mut config = PccConfig::from_toml("[quality]\ntarget_fps = 12\n[renderer]\nshow_stats = true")?;
+    config.network.port = Some(0);
+    let viewer = Viewer::connect("127.0.0.1:0".parse()?, config).await?;
+    assert!(viewer.renderer().options().await.show_stats);
+
+    // Settings changed while running are in the snapshot
+    let options = RendererOptions { scale_mode: ScaleMode::Actual, ..viewer.renderer().options().await };
+    viewer.renderer().set_options(options).await?;
+    let resilience = ResilienceConfig { max_retries: 9, ..viewer.network().resilience_config() };
+    viewer.network().set_resilience_config(resilience);
+    let snapshot = viewer.config_snapshot().await;
+    viewer.stop().await?;
+
+    let text = snapshot.to_toml()?;
+    let reloaded = PccConfig::from_toml(&text)?;
+    assert_eq!(reloaded.to_toml()?, text);
+    assert_eq!(reloaded.quality.target_fps, 12);
+    assert_eq!(reloaded.renderer, options);
+    assert_eq!(reloaded.resilience.max_retries, 9);
+    assert_eq!(reloaded.network.port, Some(0));
+
+    // A host's settings come from what it was given
+    let client = reloaded.client_config("127.0.0.1:5800".parse()?);
+    assert_eq!(PccConfig::from_client_config(&client).quality.target_fps, 12);
+    Ok(())
+}
+
+#[test]
+fn test_generated_frames_round_trip_from_host_to_viewer() -> Result<()> {
+    use pixel_change_check_client::testing::{
+        assert_round_trip, exact_hash, hash_distance, max_difference, perceptual_hash, FrameGenerator, Pattern,
+        RoundTrip,
+    };
+
+    // Deterministic: two generators draw the same frames
+    let pattern = Pattern::Noise { seed: 7 };
+    let (a, b) = (FrameGenerator::new(48, 32, pattern), FrameGenerator::new(48, 32, pattern));
+    assert_eq!(exact_hash(&a.frame(3)), exact_hash(&b.frame(3)));
+    assert_ne!(exact_hash(&a.frame(3)), exact_hash(&a.frame(4)));
+    assert_eq!(a.frame(3).timestamp, b.frame(3).timestamp);
+
+    // A slight change keeps the perceptual hash close; a new picture doesn't
+    let gradient = FrameGenerator::new(64, 64, Pattern::Gradient);
+    let mut brighter = gradient.frame(0);
+    brighter.modify_data(|data| data.iter_mut().for_each(|value| *value = value.saturating_add(2)));
+    assert!(hash_distance(perceptual_hash(&gradient.frame(0))?, perceptual_hash(&brighter)?) <= 2);
+    assert!(hash_distance(perceptual_hash(&a.frame(0))?, perceptual_hash(&gradient.frame(0))?) > 8);
+    assert_eq!(max_difference(&gradient.frame(0), &brighter)?, 2);
+
+    // Every pattern arrives exactly with no threshold
+    let patterns =
+        [Pattern::Gradient, Pattern::Checkerboard { cell: 8 }, Pattern::MovingBox { size: 10 }, Pattern::Noise { seed: 1 }];
+    for pattern in patterns {
+        let generator = FrameGenerator::new(64, 48, pattern);
+        assert_round_trip(PCCDetector::new(QualityConfig::default(), 0, 16), (0..6).map(|n| generator.frame(n)), 0);
+    }
+    // and within the default threshold when every change is bigger
+    let threshold = QualityConfig::default().threshold;
+    for pattern in &patterns[1..] {
+        let generator = FrameGenerator::new(64, 48, *pattern);
+        assert_round_trip(PCCDetector::default(), (0..6).map(|n| generator.frame(n)), threshold);
+    }
+
+    // Changes under the threshold aren't sent, so a slowly sliding
+    // gradient leaves the viewer further behind than that
+    let mut round_trip = RoundTrip::new(PCCDetector::default());
+    let shown = round_trip.send(gradient.frame(0))?;
+    assert_eq!(exact_hash(&round_trip.send(brighter)?), exact_hash(&shown));
+    let mut round_trip = RoundTrip::new(PCCDetector::default());
+    let generator = FrameGenerator::new(64, 48, Pattern::Gradient);
+    for n in 0..3 {
+        round_trip.send(generator.frame(n))?;
+    }
+    let shown = round_trip.send(generator.frame(3))?;
+    assert!(max_difference(&generator.frame(3), &shown)? > threshold);
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+async fn test_frame_buffer_reads_never_wait_on_updates() -> Result<()> {
+    use pixel_change_check_client::pcc::{FramePool, FrameUpdate, PixelChange};
+    use std::sync::Arc;
+
+    let pool = FramePool::default();
+    let buffer = Arc::new(FrameBuffer::new(32, 16).with_pool(pool.clone()));
+    buffer.push_frame(Frame { width: 32, height: 16, data: vec![0; 32 * 16 * 3].into(), ..create_test_frame(0) }).await?;
+    buffer.next_frame().await?.expect("Frame is due");
+
+    // A frame held while presenting doesn't hold up updates, and a reader
+    // only ever sees whole frames, never one half updated
+    let held = buffer.current_frame().await.expect("Frame was played");
+    let writer = tokio::spawn({
+        let buffer = buffer.clone();
+        async move {
+            for id in 1..=200u64 {
+                let changes = vec![PixelChange { x: 0, y: 0, width: 32, height: 16, data: vec![id as u8; 32 * 16 * 3] }];
+                buffer.apply_frame_update(FrameUpdate { frame_id: id, timestamp: std::time::SystemTime::now(), changes }).await?;
+            }
+            anyhow::Ok(())
+        }
+    });
+    let mut last = 0;
+    while last < 200 {
+        let frame = buffer.current_frame().await.expect("A frame is always current");
+        assert!(frame.data.iter().all(|byte| *byte == frame.id as u8), "Frame {} is torn", frame.id);
+        assert!(frame.id >= last, "Frames go backwards");
+        last = frame.id;
+        tokio::task::yield_now().await;
+    }
+    writer.await??;
+    assert!(held.data.iter().all(|byte| *byte == 0), "Held frames don't change");
+
+    // Pixels nobody holds any more go back to the pool for the next update
+    drop(held);
+    let before = pool.stats().reuses;
+    for id in 201..=203u64 {
+        let changes = vec![PixelChange { x: 0, y: 0, width: 1, height: 1, data: vec![1, 2, 3] }];
+        buffer.apply_frame_update(FrameUpdate { frame_id: id, timestamp: std::time::SystemTime::now(), changes }).await?;
+    }
+    assert!(pool.stats().reuses > before, "{:?}", pool.stats());
+    Ok(())
+}
+
+#[test]
+fn test_frame_chunks_share_the_frames_pixels() -> Result<()> {
+    use pixel_change_check_client::network::{FrameProtocol, Message};
+
+    let frame = Frame {
+        width: 200,
+        height: 150,
+        data: (0..200 * 150 * 3).map(|i| i as u8).collect::<Vec<u8>>().into(),
+        ..create_test_frame(4)
+    };
+    let chunks = FrameProtocol::encode_frame(&frame)?;
+    assert!(chunks.len() > 1);
+
+    // Each chunk's payload is a slice of the frame, not a copy, and header
+    // then payload is the message as serialized whole
+    let mut offset = 0;
+    for (index, chunk) in chunks.iter().enumerate() {
+        assert_eq!(chunk.payload.as_ptr(), frame.data[offset..].as_ptr(), "Chunk {} copied its pixels", index);
+        let message = Message::FrameData {
+            frame_id: frame.id,
+            timestamp: frame.timestamp,
+            width: frame.width,
+            height: frame.height,
+            chunk_index: index as u32,
+            chunk_count: chunks.len() as u32,
+            data: chunk.payload.clone(),
+        };
+        assert_eq!(chunk.to_vec(), message.serialize()?);
+        assert_eq!(chunk.len(), chunk.to_vec().len());
+        offset += chunk.payload.len();
+    }
+    assert_eq!(offset, frame.data.len());
+
+    let messages = chunks.iter().map(|chunk| Message::deserialize(&chunk.to_vec())).collect::<Result<Vec<_>>>()?;
+    let decoded = FrameProtocol::decode_frame(messages)?;
+    assert_eq!((decoded.id, decoded.data), (frame.id, frame.data));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_updates_batch_changes_into_few_messages() -> Result<()> {
+    use pixel_change_check_client::{
+        network::{FrameProtocol, Message},
+        pcc::{FrameUpdate, PixelChange},
+        server::renderer::Renderer,
+    };
+
+    // Hundreds of small tiles go in a single message
+    let (width, height) = (320, 240);
+    let tile = |x: u32, y: u32, size: u32, value: u8| PixelChange { x, y, width: size, height: size, data: vec![value; (size * size * 3) as usize] };
+    let tiles: Vec<PixelChange> = (0..300).map(|i| tile(i % 20 * 16, i / 20 * 16, 2, i as u8)).collect();
+    let update = FrameUpdate { frame_id: 2, timestamp: std::time::SystemTime::now(), changes: tiles };
+    let messages = FrameProtocol::encode_update(&update)?;
+    assert_eq!(messages.len(), 1);
+    let Message::FrameUpdate { update: sent, part, parts } = Message::deserialize(&messages[0])? else {
+        panic!("Expected an update");
+    };
+    assert_eq!((sent.changes.len(), part, parts), (300, 0, 1));
+
+    // Larger ones fill as few messages as they fit, in order, and the
+    // viewer presents once the last is applied
+    let big: Vec<PixelChange> = (0..40).map(|i| tile(i % 10 * 32, i / 10 * 32, 32, 100 + i as u8)).collect();
+    let update = FrameUpdate { frame_id: 3, changes: big.clone(), ..update };
+    let messages = FrameProtocol::encode_update(&update)?;
+    assert!(messages.len() > 1 && messages.len() < big.len(), "{} messages", messages.len());
+    let renderer = Renderer::new(width, height, 30).await?;
+    renderer
+        .buffer
+        .push_frame(Frame { width, height, data: vec![0; (width * height * 3) as usize].into(), ..create_test_frame(1) })
+        .await?;
+    renderer.buffer.next_frame().await?;
+    let mut received = Vec::new();
+    for (index, bytes) in messages.iter().enumerate() {
+        let message = Message::deserialize(bytes)?;
+        let Message::FrameUpdate { update, part, parts } = &message else { panic!("Expected an update") };
+        assert_eq!((*part as usize, *parts as usize), (index, messages.len()));
+        received.extend(update.changes.iter().cloned());
+        renderer.handle_message(message).await?;
+    }
+    assert_eq!(received, big);
+    let current = renderer.buffer.current_frame().await.expect("Frame was updated");
+    assert_eq!((current.id, current.pixel(32, 32)), (3, Some([111; 3])));
+
+    // Nothing changed is still one message, so the viewer moves on a frame
+    let empty = FrameProtocol::encode_update(&FrameUpdate { changes: Vec::new(), ..update })?;
+    assert_eq!(empty.len(), 1);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_connection_shutdown_ends_its_tasks() -> Result<()> {
+    use pixel_change_check_client::{
+        input::Permission,
+        network::{CloseReason, NetworkConfig, NetworkManager},
+        server::{self, network::ServerNetwork, renderer::Renderer},
+    };
+    use std::{net::SocketAddr, sync::Arc, time::Instant};
+
+    let network = Arc::new(ServerNetwork::new(
+        NetworkConfig { port: Some(0), ..NetworkConfig::default() },
+        ResilienceConfig::default(),
+    )?);
+    let renderer = Arc::new(Renderer::new(64, 48, 30).await?);
+    let addr = SocketAddr::from(([127, 0, 0, 1], network.local_addr()?.port()));
+    let viewer = tokio::spawn({
+        let (network, renderer) = (network.clone(), renderer.clone());
+        async move {
+            tokio::select! {
+                result = network.start() => result,
+                result = server::present_incoming(&network, &renderer) => result,
+            }
+        }
+    });
+
+    let manager = NetworkManager::new_client(NetworkConfig::default()).await?;
+    let connection = manager.connect(addr).await?;
+    tokio::time::timeout(Duration::from_secs(2), connection.handshake(None, Permission::ViewOnly)).await??;
+    let mut events = connection.start_frame_processing().await?;
+    assert!(connection.start_frame_processing().await.is_err(), "Only once");
+
+    // The frame loops and control readers end well before shutdown would
+    // abort them, and the peer is still there
+    let start = Instant::now();
+    connection.shutdown().await;
+    assert!(start.elapsed() < Duration::from_millis(500), "Took {:?}", start.elapsed());
+    assert!(tokio::time::timeout(Duration::from_secs(1), events.recv()).await?.is_none());
+    assert!(tokio::time::timeout(Duration::from_millis(100), connection.closed()).await.is_err());
+
+    connection.close(CloseReason::HostStoppedSharing).await?;
+    viewer.abort();
+    Ok(())
+}